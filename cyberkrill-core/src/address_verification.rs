@@ -0,0 +1,277 @@
+//! Show an address on a hardware wallet's screen for visual confirmation
+//!
+//! An address printed by the host can be swapped by malware before it is copied
+//! anywhere. Asking the device to derive and display the same address lets the
+//! user compare it on a screen the host does not control.
+
+use anyhow::{Context, Result};
+use bitcoin::Network;
+use bitcoin::bip32::{ChildNumber, DerivationPath};
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::descriptor::derive_single_key_address;
+use crate::error::{CoreResult, bail, ensure};
+use crate::message_signing::MessageSigningDevice;
+
+/// Outcome of showing an address on a device
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressVerification {
+    pub device: String,
+    pub network: String,
+    pub derivation_path: String,
+    /// Address derived and displayed by the device; `None` when the user rejected it
+    pub device_address: Option<String>,
+    /// Address computed on the host, from the descriptor or given by the user
+    pub expected_address: Option<String>,
+    /// Whether the device address equals the expected one
+    pub matches: Option<bool>,
+    /// Whether the user confirmed the address on the device; `None` when the
+    /// device does not report it (Coldcard)
+    pub approved: Option<bool>,
+}
+
+impl AddressVerification {
+    fn new(
+        device: MessageSigningDevice,
+        network: Network,
+        derivation_path: &DerivationPath,
+        outcome: DisplayOutcome,
+        expected_address: Option<String>,
+    ) -> Self {
+        let (device_address, approved) = match outcome {
+            DisplayOutcome::Approved(address) => (Some(address), Some(true)),
+            DisplayOutcome::Rejected => (None, Some(false)),
+            DisplayOutcome::Shown(address) => (Some(address), None),
+        };
+        let matches = expected_address
+            .as_ref()
+            .zip(device_address.as_ref())
+            .map(|(expected, shown)| expected == shown);
+        Self {
+            device: device.to_string(),
+            network: network.to_string(),
+            derivation_path: format!("m/{derivation_path}"),
+            device_address,
+            expected_address,
+            matches,
+            approved,
+        }
+    }
+
+    /// The device showed the address, the user did not reject it and it
+    /// matches the expected address when there is one
+    pub fn is_verified(&self) -> bool {
+        self.device_address.is_some() && self.approved != Some(false) && self.matches != Some(false)
+    }
+}
+
+/// What the device did with the address
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    not(any(
+        feature = "trezor",
+        feature = "jade",
+        feature = "coldcard",
+        feature = "bitbox"
+    )),
+    allow(dead_code)
+)]
+enum DisplayOutcome {
+    /// The user confirmed the address on the device
+    Approved(String),
+    /// The user rejected the address on the device
+    Rejected,
+    /// The device showed the address without reporting the user's answer
+    Shown(String),
+}
+
+/// Check that `device` derives the script type implied by the path purpose.
+/// Coldcard is only asked for native segwit addresses.
+fn ensure_supported_path(device: MessageSigningDevice, path: &DerivationPath) -> Result<()> {
+    let purpose = path.into_iter().next();
+    match device {
+        MessageSigningDevice::Coldcard => ensure!(
+            purpose == Some(&ChildNumber::Hardened { index: 84 }),
+            "Coldcard addresses can only be verified for m/84'/... (p2wpkh) paths"
+        ),
+        MessageSigningDevice::Bitbox => ensure!(
+            matches!(
+                purpose,
+                Some(ChildNumber::Hardened {
+                    index: 49 | 84 | 86
+                })
+            ),
+            "BitBox02 addresses can only be verified for m/49'/, m/84'/ and m/86'/ paths"
+        ),
+        MessageSigningDevice::Trezor | MessageSigningDevice::Jade => ensure!(
+            matches!(
+                purpose,
+                Some(ChildNumber::Hardened {
+                    index: 44 | 49 | 84 | 86
+                })
+            ),
+            "Address verification supports m/44'/, m/49'/, m/84'/ and m/86'/ paths"
+        ),
+    }
+    Ok(())
+}
+
+/// Ask `device` to display the address at `path`, optionally comparing it with
+/// `expected_address`
+pub async fn verify_address_on_device(
+    device: MessageSigningDevice,
+    path: &str,
+    network: Network,
+    expected_address: Option<&str>,
+) -> CoreResult<AddressVerification> {
+    let derivation_path = DerivationPath::from_str(path)
+        .with_context(|| format!("Invalid derivation path: {path}"))?;
+    ensure_supported_path(device, &derivation_path)?;
+    let outcome = display_address(device, &derivation_path, network).await?;
+    Ok(AddressVerification::new(
+        device,
+        network,
+        &derivation_path,
+        outcome,
+        expected_address.map(str::to_string),
+    ))
+}
+
+/// Derive the address at `index` on `branch` of a single-key descriptor and ask
+/// `device` to display it at the key's derivation path
+pub async fn verify_descriptor_address_on_device(
+    device: MessageSigningDevice,
+    descriptor: &str,
+    branch: usize,
+    index: u32,
+    network: Network,
+) -> CoreResult<AddressVerification> {
+    let derived = derive_single_key_address(descriptor, network, branch, index)?;
+    let derivation_path = DerivationPath::from_str(&derived.derivation_path)
+        .context("Invalid derivation path in descriptor")?;
+    ensure_supported_path(device, &derivation_path)?;
+    let outcome = display_address(device, &derivation_path, network).await?;
+    Ok(AddressVerification::new(
+        device,
+        network,
+        &derivation_path,
+        outcome,
+        Some(derived.address),
+    ))
+}
+
+async fn display_address(
+    device: MessageSigningDevice,
+    path: &DerivationPath,
+    network: Network,
+) -> CoreResult<DisplayOutcome> {
+    let path = format!("m/{path}");
+    match device {
+        #[cfg(feature = "trezor")]
+        MessageSigningDevice::Trezor => {
+            let mut wallet = crate::trezor::TrezorWallet::connect().await?;
+            wallet.init_device()?;
+            match wallet.get_address(&path, network) {
+                Ok(info) => Ok(DisplayOutcome::Approved(info.address)),
+                Err(error) if crate::trezor::is_user_cancellation(error.inner()) => {
+                    Ok(DisplayOutcome::Rejected)
+                }
+                Err(error) => Err(error),
+            }
+        }
+        #[cfg(feature = "jade")]
+        MessageSigningDevice::Jade => {
+            match crate::jade::generate_jade_address(&path, network, &Default::default()).await {
+                Ok(result) => Ok(DisplayOutcome::Approved(result.address)),
+                Err(error) if crate::jade::is_user_cancellation(error.inner()) => {
+                    Ok(DisplayOutcome::Rejected)
+                }
+                Err(error) => Err(error),
+            }
+        }
+        #[cfg(feature = "coldcard")]
+        MessageSigningDevice::Coldcard => {
+            let _ = network;
+            let mut wallet = crate::coldcard::ColdcardWallet::connect().await?;
+            Ok(DisplayOutcome::Shown(wallet.get_address(&path)?.address))
+        }
+        #[cfg(feature = "bitbox")]
+        MessageSigningDevice::Bitbox => {
+            let wallet = crate::bitbox::BitboxWallet::connect().await?;
+            Ok(DisplayOutcome::Approved(
+                wallet.get_address(&path, network).await?,
+            ))
+        }
+        #[allow(unreachable_patterns)]
+        _ => {
+            let _ = (path, network);
+            bail!("Support for {device} is not enabled in this build")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+    fn path(path: &str) -> Result<DerivationPath> {
+        Ok(DerivationPath::from_str(path)?)
+    }
+
+    fn verification(
+        outcome: DisplayOutcome,
+        expected: Option<&str>,
+    ) -> Result<AddressVerification> {
+        Ok(AddressVerification::new(
+            MessageSigningDevice::Jade,
+            Network::Bitcoin,
+            &path("m/84h/0h/0h/0/0")?,
+            outcome,
+            expected.map(str::to_string),
+        ))
+    }
+
+    #[test]
+    fn test_verification_outcomes() -> Result<()> {
+        let approved = verification(DisplayOutcome::Approved(ADDRESS.to_string()), Some(ADDRESS))?;
+        assert_eq!(approved.derivation_path, "m/84'/0'/0'/0/0");
+        assert_eq!(approved.matches, Some(true));
+        assert!(approved.is_verified());
+
+        let mismatch = verification(
+            DisplayOutcome::Approved(ADDRESS.to_string()),
+            Some("bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el"),
+        )?;
+        assert_eq!(mismatch.matches, Some(false));
+        assert!(!mismatch.is_verified());
+
+        let rejected = verification(DisplayOutcome::Rejected, Some(ADDRESS))?;
+        assert_eq!(rejected.approved, Some(false));
+        assert_eq!(rejected.matches, None);
+        assert!(!rejected.is_verified());
+
+        let shown = verification(DisplayOutcome::Shown(ADDRESS.to_string()), None)?;
+        assert_eq!(shown.approved, None);
+        assert!(shown.is_verified());
+        Ok(())
+    }
+
+    #[test]
+    fn test_supported_paths() -> Result<()> {
+        let taproot = path("m/86'/0'/0'/0/0")?;
+        assert!(ensure_supported_path(MessageSigningDevice::Trezor, &taproot).is_ok());
+        assert!(ensure_supported_path(MessageSigningDevice::Coldcard, &taproot).is_err());
+        assert!(
+            ensure_supported_path(MessageSigningDevice::Coldcard, &path("m/84'/1'/0'/1/3")?)
+                .is_ok()
+        );
+        assert!(
+            ensure_supported_path(MessageSigningDevice::Jade, &path("m/48'/0'/0'/2'/0/0")?)
+                .is_err()
+        );
+        Ok(())
+    }
+}
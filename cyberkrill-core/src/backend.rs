@@ -0,0 +1,642 @@
+//! Blockchain backends (Electrum, Esplora, Bitcoin Core, compact block filters)
+//! behind one async interface
+//!
+//! UTXO listing, PSBT funding, broadcasting, fee estimation and transaction
+//! lookup all go through [`BlockchainBackend`], so callers pick a backend once
+//! instead of threading connection strings through every function.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bdk_wallet::chain::{BlockId, ConfirmationBlockTime, TxUpdate};
+use bdk_wallet::{KeychainKind, Update};
+use bitcoin::{BlockHash, Network, Transaction, Txid};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::bdk_wallet::{
+    BdkUtxo, ScanOptions, ScanWallet, scan_and_list_utxos_electrum, scan_and_list_utxos_esplora,
+    stream_utxos_electrum, stream_utxos_esplora,
+};
+use crate::bitcoin_rpc::{BitcoinRpcClient, UtxoOutput, btc_per_kvb_to_sat_per_vb};
+use crate::cbf::{Peer, sync_wallet};
+use crate::chain_cache::cached_transaction;
+use crate::client_options::ClientOptions;
+use crate::electrum::ElectrumServers;
+use crate::error::{CoreResult, bail, ensure};
+use crate::esplora::{
+    broadcast_transaction_esplora, estimate_fee_rate_esplora, fetch_transaction_esplora,
+    fetch_tx_status_esplora, test_mempool_accept_esplora,
+};
+use crate::mempool_accept::MempoolAcceptance;
+use crate::tx_watch::TxStatus;
+
+/// Source of chain data and destination for broadcasts
+#[async_trait]
+pub trait BlockchainBackend: Send + Sync {
+    /// Short backend name for logs and reports
+    fn name(&self) -> &'static str;
+
+    /// Unspent outputs of `descriptor`, largest first
+    async fn list_utxos(&self, descriptor: &str, network: Network) -> CoreResult<Vec<BdkUtxo>>;
+
+    /// [`Self::list_utxos`] handing each UTXO to `on_utxo` as soon as the part of
+    /// the scan that found it (a keychain or descriptor path) completes, in no
+    /// particular order. Backends that scan everything at once hand them all
+    /// out at the end. An error from `on_utxo` stops the scan.
+    async fn stream_utxos(
+        &self,
+        descriptor: &str,
+        network: Network,
+        on_utxo: &mut (dyn FnMut(BdkUtxo) -> Result<()> + Send),
+    ) -> CoreResult<()> {
+        for utxo in self.list_utxos(descriptor, network).await? {
+            on_utxo(utxo)?;
+        }
+        Ok(())
+    }
+
+    /// BDK wallet for `descriptor`, synced with the chain and ready to build transactions
+    async fn synced_wallet(&self, descriptor: &str, network: Network) -> CoreResult<ScanWallet>;
+
+    async fn get_transaction(&self, txid: &Txid) -> CoreResult<Transaction>;
+
+    /// Submit a signed transaction, returning its txid
+    async fn broadcast(&self, tx: &Transaction) -> CoreResult<Txid>;
+
+    /// Whether the mempool would accept `tx`, checked without broadcasting it
+    async fn test_mempool_accept(&self, tx: &Transaction) -> CoreResult<MempoolAcceptance>;
+
+    /// Whether `txid` is unknown, in the mempool or confirmed, and how deeply
+    async fn tx_status(&self, txid: &Txid) -> CoreResult<TxStatus>;
+
+    /// Fee rate in sat/vB expected to confirm within `conf_target` blocks
+    async fn estimate_fee_rate(&self, conf_target: u32) -> CoreResult<f64>;
+}
+
+/// Electrum backend over one or more interchangeable servers
+pub struct ElectrumBackend {
+    servers: ElectrumServers,
+    scan: ScanOptions,
+}
+
+impl ElectrumBackend {
+    pub fn new(servers: ElectrumServers, scan: ScanOptions) -> Self {
+        Self { servers, scan }
+    }
+}
+
+#[async_trait]
+impl BlockchainBackend for ElectrumBackend {
+    fn name(&self) -> &'static str {
+        "electrum"
+    }
+
+    async fn list_utxos(&self, descriptor: &str, network: Network) -> CoreResult<Vec<BdkUtxo>> {
+        scan_and_list_utxos_electrum(descriptor, network, &self.servers, &self.scan).await
+    }
+
+    async fn stream_utxos(
+        &self,
+        descriptor: &str,
+        network: Network,
+        on_utxo: &mut (dyn FnMut(BdkUtxo) -> Result<()> + Send),
+    ) -> CoreResult<()> {
+        stream_utxos_electrum(descriptor, network, &self.servers, &self.scan, on_utxo).await
+    }
+
+    async fn synced_wallet(&self, descriptor: &str, network: Network) -> CoreResult<ScanWallet> {
+        let mut wallet = ScanWallet::open(descriptor, network, self.scan.wallet_db.as_deref())?;
+        wallet.sync_electrum(&self.servers, &self.scan)?;
+        Ok(wallet)
+    }
+
+    async fn get_transaction(&self, txid: &Txid) -> CoreResult<Transaction> {
+        use bdk_electrum::electrum_client::ElectrumApi;
+
+        cached_transaction(self.scan.client.chain_cache.as_deref(), txid, async {
+            self.servers.call(&self.scan.client, |client| {
+                client
+                    .transaction_get(txid)
+                    .with_context(|| format!("Failed to fetch transaction {txid} from Electrum"))
+            })
+        })
+        .await
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> CoreResult<Txid> {
+        use bdk_electrum::electrum_client::ElectrumApi;
+
+        self.servers.call(&self.scan.client, |client| {
+            client
+                .transaction_broadcast(tx)
+                .context("Electrum server rejected the transaction")
+        })
+    }
+
+    async fn test_mempool_accept(&self, _tx: &Transaction) -> CoreResult<MempoolAcceptance> {
+        bail!(
+            "Electrum servers cannot test mempool acceptance; use Bitcoin Core or a mempool.space-compatible --esplora"
+        )
+    }
+
+    /// Electrum indexes scripts, not txids, so the status is looked up in the
+    /// history of the transaction's first output script
+    async fn tx_status(&self, txid: &Txid) -> CoreResult<TxStatus> {
+        use bdk_electrum::electrum_client::{ElectrumApi, Error as ElectrumError};
+
+        self.servers.call(&self.scan.client, |client| {
+            let tx = match client.transaction_get(txid) {
+                Ok(tx) => tx,
+                // Servers answer unknown txids with an error response
+                Err(ElectrumError::Protocol(_)) => return Ok(TxStatus::not_found(txid)),
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to fetch transaction {txid} from Electrum")
+                    });
+                }
+            };
+            let script = &tx
+                .output
+                .first()
+                .with_context(|| format!("Transaction {txid} has no outputs"))?
+                .script_pubkey;
+            let height = client
+                .script_get_history(script)
+                .context("Failed to fetch script history from Electrum")?
+                .into_iter()
+                .find(|entry| entry.tx_hash == *txid)
+                .map_or(0, |entry| entry.height);
+            // Heights of 0 and -1 mean unconfirmed (with or without unconfirmed parents)
+            let Ok(height @ 1..) = u32::try_from(height) else {
+                return Ok(TxStatus::mempool(txid));
+            };
+            let tip_height = client
+                .block_headers_subscribe()
+                .context("Failed to fetch the chain tip from Electrum")?
+                .height as u32;
+            let block_hash = client
+                .block_header(height as usize)
+                .with_context(|| format!("Failed to fetch block header {height}"))?
+                .block_hash();
+            Ok(TxStatus::confirmed(
+                txid,
+                height,
+                block_hash.to_string(),
+                tip_height,
+            ))
+        })
+    }
+
+    async fn estimate_fee_rate(&self, conf_target: u32) -> CoreResult<f64> {
+        use bdk_electrum::electrum_client::{ElectrumApi, Param};
+
+        let btc_per_kvb = self.servers.call(&self.scan.client, |client| {
+            client
+                .raw_call(
+                    "blockchain.estimatefee",
+                    [Param::Usize(conf_target as usize)],
+                )
+                .context("Electrum fee estimation failed")?
+                .as_f64()
+                .context("Unexpected blockchain.estimatefee response")
+        })?;
+        // Servers answer -1 when they have no estimate for the target
+        if btc_per_kvb < 0.0 {
+            bail!("Electrum server has no fee estimate for {conf_target} blocks");
+        }
+        Ok(btc_per_kvb_to_sat_per_vb(btc_per_kvb))
+    }
+}
+
+/// Esplora HTTP API backend
+pub struct EsploraBackend {
+    url: String,
+    scan: ScanOptions,
+}
+
+impl EsploraBackend {
+    pub fn new(url: impl Into<String>, scan: ScanOptions) -> Self {
+        Self {
+            url: url.into(),
+            scan,
+        }
+    }
+}
+
+#[async_trait]
+impl BlockchainBackend for EsploraBackend {
+    fn name(&self) -> &'static str {
+        "esplora"
+    }
+
+    async fn list_utxos(&self, descriptor: &str, network: Network) -> CoreResult<Vec<BdkUtxo>> {
+        scan_and_list_utxos_esplora(descriptor, network, &self.url, &self.scan).await
+    }
+
+    async fn stream_utxos(
+        &self,
+        descriptor: &str,
+        network: Network,
+        on_utxo: &mut (dyn FnMut(BdkUtxo) -> Result<()> + Send),
+    ) -> CoreResult<()> {
+        stream_utxos_esplora(descriptor, network, &self.url, &self.scan, on_utxo).await
+    }
+
+    async fn synced_wallet(&self, descriptor: &str, network: Network) -> CoreResult<ScanWallet> {
+        let mut wallet = ScanWallet::open(descriptor, network, self.scan.wallet_db.as_deref())?;
+        wallet.sync_esplora(&self.url, &self.scan)?;
+        Ok(wallet)
+    }
+
+    async fn get_transaction(&self, txid: &Txid) -> CoreResult<Transaction> {
+        let options = &self.scan.client;
+        cached_transaction(
+            options.chain_cache.as_deref(),
+            txid,
+            fetch_transaction_esplora(&self.url, txid, options),
+        )
+        .await
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> CoreResult<Txid> {
+        broadcast_transaction_esplora(&self.url, tx, &self.scan.client).await
+    }
+
+    async fn test_mempool_accept(&self, tx: &Transaction) -> CoreResult<MempoolAcceptance> {
+        test_mempool_accept_esplora(&self.url, tx, &self.scan.client).await
+    }
+
+    async fn tx_status(&self, txid: &Txid) -> CoreResult<TxStatus> {
+        fetch_tx_status_esplora(&self.url, txid, &self.scan.client).await
+    }
+
+    async fn estimate_fee_rate(&self, conf_target: u32) -> CoreResult<f64> {
+        estimate_fee_rate_esplora(&self.url, conf_target, &self.scan.client).await
+    }
+}
+
+/// Bitcoin Core RPC backend
+///
+/// UTXOs come from Bitcoin Core itself rather than a BDK sync. Wallets for PSBT
+/// building are rebuilt each run from a `scantxoutset` snapshot, so the
+/// `wallet_db` scan option does not apply.
+pub struct BitcoindBackend {
+    client: BitcoinRpcClient,
+}
+
+impl BitcoindBackend {
+    pub fn new(client: BitcoinRpcClient) -> Self {
+        Self { client }
+    }
+
+    /// Connect using `rpc_url`, with credentials from the cookie in `bitcoin_dir` or user/password
+    pub fn connect(
+        rpc_url: String,
+        bitcoin_dir: Option<&Path>,
+        rpc_user: Option<String>,
+        rpc_password: Option<String>,
+        options: &ClientOptions,
+    ) -> CoreResult<Self> {
+        Ok(Self::new(BitcoinRpcClient::new_auto(
+            rpc_url,
+            bitcoin_dir,
+            rpc_user,
+            rpc_password,
+            options,
+        )?))
+    }
+
+    pub fn client(&self) -> &BitcoinRpcClient {
+        &self.client
+    }
+
+    async fn block_time(&self, block_hash: &BlockHash) -> Result<u64> {
+        let header = self
+            .client
+            .rpc_call(
+                "getblockheader",
+                serde_json::json!([block_hash.to_string()]),
+            )
+            .await?;
+        header
+            .get("time")
+            .and_then(|v| v.as_u64())
+            .with_context(|| format!("Missing time in header of block {block_hash}"))
+    }
+}
+
+/// Derivation index of a `scantxoutset` result descriptor such as
+/// `wpkh([d34db33f/84h/0h/0h/1/7]03...)#checksum`
+fn snapshot_derivation_index(desc: &str) -> Option<u32> {
+    let origin = desc.split_once('[')?.1.split_once(']')?.0;
+    origin
+        .rsplit('/')
+        .next()?
+        .trim_end_matches(['h', 'H', '\''])
+        .parse()
+        .ok()
+}
+
+/// Bitcoin Core's `listunspent` output as a [`BdkUtxo`]. Addresses of outputs
+/// it doesn't report one for are derived from the scriptPubKey. Keychain and
+/// derivation index aren't reported and are left as defaults.
+fn listed_utxo(utxo: UtxoOutput, network: Network) -> BdkUtxo {
+    // Without this, UTXOs would appear to be "missing" if code filters by address presence
+    let address = if let Some(addr) = utxo.address {
+        addr
+    } else {
+        hex::decode(&utxo.script_pub_key)
+            .ok()
+            .and_then(|script_bytes| {
+                let script = bitcoin::ScriptBuf::from(script_bytes);
+                bitcoin::Address::from_script(&script, network).ok()
+            })
+            .map(|address| address.to_string())
+            // Fall back to the script hex
+            .unwrap_or_else(|| format!("script:{script}", script = utxo.script_pub_key))
+    };
+
+    BdkUtxo {
+        txid: utxo.txid,
+        vout: utxo.vout,
+        address,
+        amount: utxo.amount_sats,
+        amount_btc: bitcoin::Amount::from_sat(utxo.amount_sats).to_btc(),
+        confirmations: utxo.confirmations,
+        is_change: false,                // We don't have this info from RPC
+        keychain: "unknown".to_string(), // We don't have this info from RPC
+        derivation_index: None,
+    }
+}
+
+#[async_trait]
+impl BlockchainBackend for BitcoindBackend {
+    fn name(&self) -> &'static str {
+        "bitcoind"
+    }
+
+    async fn list_utxos(&self, descriptor: &str, network: Network) -> CoreResult<Vec<BdkUtxo>> {
+        let utxo_result = self.client.list_utxos_for_descriptor(descriptor).await?;
+        let mut all_utxos: Vec<BdkUtxo> = utxo_result
+            .utxos
+            .into_iter()
+            .map(|utxo| listed_utxo(utxo, network))
+            .collect();
+        all_utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+
+        Ok(all_utxos)
+    }
+
+    async fn stream_utxos(
+        &self,
+        descriptor: &str,
+        network: Network,
+        on_utxo: &mut (dyn FnMut(BdkUtxo) -> Result<()> + Send),
+    ) -> CoreResult<()> {
+        self.client
+            .stream_unspent_for_descriptor(descriptor, |utxo| {
+                on_utxo(listed_utxo(utxo.into(), network))
+            })
+            .await
+    }
+
+    async fn synced_wallet(&self, descriptor: &str, network: Network) -> CoreResult<ScanWallet> {
+        let mut wallet = ScanWallet::open(descriptor, network, None)?;
+        let snapshot = self.client.utxo_snapshot(descriptor).await?;
+
+        // Reveal far enough for BDK to recognize every scanned output as its own
+        if let Some(max_index) = snapshot
+            .utxos
+            .iter()
+            .filter_map(|utxo| snapshot_derivation_index(&utxo.desc))
+            .max()
+        {
+            let keychains: Vec<KeychainKind> =
+                wallet.keychains().map(|(keychain, _)| keychain).collect();
+            for keychain in keychains {
+                wallet
+                    .reveal_addresses_to(keychain, max_index)
+                    .for_each(drop);
+            }
+        }
+
+        let tip = BlockId {
+            height: u32::try_from(snapshot.block_height)?,
+            hash: snapshot.block_hash.parse()?,
+        };
+        let mut chain = wallet.latest_checkpoint().insert(tip);
+        let mut tx_update = TxUpdate::<ConfirmationBlockTime>::default();
+        let mut blocks: BTreeMap<u64, ConfirmationBlockTime> = BTreeMap::new();
+        let mut seen = BTreeSet::new();
+
+        for utxo in &snapshot.utxos {
+            let txid: Txid = utxo.txid.parse()?;
+            if !seen.insert(txid) {
+                continue;
+            }
+            let anchor = match blocks.get(&utxo.height) {
+                Some(anchor) => *anchor,
+                None => {
+                    let hash = self.client.get_block_hash(utxo.height).await?;
+                    let anchor = ConfirmationBlockTime {
+                        block_id: BlockId {
+                            height: u32::try_from(utxo.height)?,
+                            hash,
+                        },
+                        confirmation_time: self.block_time(&hash).await?,
+                    };
+                    chain = chain.insert(anchor.block_id);
+                    blocks.insert(utxo.height, anchor);
+                    anchor
+                }
+            };
+            debug!("Loading {txid} from block {height}", height = utxo.height);
+            let tx = cached_transaction(
+                self.client.options().chain_cache.as_deref(),
+                &txid,
+                self.client
+                    .get_raw_transaction_in_block(&txid, &anchor.block_id.hash),
+            )
+            .await?;
+            tx_update.txs.push(Arc::new(tx));
+            tx_update.anchors.insert((anchor, txid));
+        }
+
+        wallet.apply_update(Update {
+            tx_update,
+            chain: Some(chain),
+            ..Default::default()
+        })?;
+        Ok(wallet)
+    }
+
+    async fn get_transaction(&self, txid: &Txid) -> CoreResult<Transaction> {
+        cached_transaction(
+            self.client.options().chain_cache.as_deref(),
+            txid,
+            self.client.get_raw_transaction(txid),
+        )
+        .await
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> CoreResult<Txid> {
+        self.client.send_raw_transaction(tx).await
+    }
+
+    async fn test_mempool_accept(&self, tx: &Transaction) -> CoreResult<MempoolAcceptance> {
+        self.client.test_mempool_accept(tx).await
+    }
+
+    /// Without `-txindex` only mempool and wallet transactions can be found, so
+    /// `gettransaction` is tried when `getrawtransaction` does not know the txid
+    async fn tx_status(&self, txid: &Txid) -> CoreResult<TxStatus> {
+        let verbose = match self
+            .client
+            .rpc_call(
+                "getrawtransaction",
+                serde_json::json!([txid.to_string(), true]),
+            )
+            .await
+        {
+            Ok(verbose) => verbose,
+            Err(e) if format!("{e:#}").contains("No such mempool or blockchain transaction") => {
+                match self
+                    .client
+                    .rpc_call("gettransaction", serde_json::json!([txid.to_string()]))
+                    .await
+                {
+                    Ok(wallet_tx) => wallet_tx,
+                    Err(_) => return Ok(TxStatus::not_found(txid)),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+
+        let confirmations = verbose["confirmations"].as_i64().unwrap_or(0);
+        let (Ok(confirmations @ 1..), Some(block_hash)) =
+            (u32::try_from(confirmations), verbose["blockhash"].as_str())
+        else {
+            // Negative confirmations mark wallet transactions that conflict with the chain
+            return Ok(if confirmations < 0 {
+                TxStatus::not_found(txid)
+            } else {
+                TxStatus::mempool(txid)
+            });
+        };
+        let header = self
+            .client
+            .rpc_call("getblockheader", serde_json::json!([block_hash]))
+            .await?;
+        let height = header["height"]
+            .as_u64()
+            .and_then(|height| u32::try_from(height).ok())
+            .with_context(|| format!("Missing height in header of block {block_hash}"))?;
+        Ok(TxStatus::confirmed(
+            txid,
+            height,
+            block_hash.to_string(),
+            height + confirmations - 1,
+        ))
+    }
+
+    async fn estimate_fee_rate(&self, conf_target: u32) -> CoreResult<f64> {
+        self.client.estimate_smart_fee(conf_target).await
+    }
+}
+
+/// Compact block filter (BIP157/158) backend over the P2P network
+///
+/// Scans match the wallet's scripts against block filters locally, so no
+/// Electrum/Esplora server or node wallet is trusted with them. Only confirmed
+/// transactions are seen, and the P2P protocol has no lookup by txid, mempool
+/// test or fee estimate.
+pub struct CbfBackend {
+    peers: Vec<String>,
+    network: Network,
+    /// First block height to scan for a wallet without synced state
+    birthday: u32,
+    scan: ScanOptions,
+}
+
+impl CbfBackend {
+    /// `peers` are `host[:port]` nodes serving compact block filters, tried in order
+    pub fn new(peers: Vec<String>, network: Network, birthday: u32, scan: ScanOptions) -> Self {
+        Self {
+            peers,
+            network,
+            birthday,
+            scan,
+        }
+    }
+}
+
+#[async_trait]
+impl BlockchainBackend for CbfBackend {
+    fn name(&self) -> &'static str {
+        "cbf"
+    }
+
+    async fn list_utxos(&self, descriptor: &str, network: Network) -> CoreResult<Vec<BdkUtxo>> {
+        Ok(self
+            .synced_wallet(descriptor, network)
+            .await?
+            .utxos(network))
+    }
+
+    async fn synced_wallet(&self, descriptor: &str, network: Network) -> CoreResult<ScanWallet> {
+        ensure!(
+            network == self.network,
+            "Compact block filter peers are on {peers_network}, not {network}",
+            peers_network = self.network
+        );
+        let mut wallet = ScanWallet::open(descriptor, network, self.scan.wallet_db.as_deref())?;
+        sync_wallet(&mut wallet, &self.peers, network, self.birthday, &self.scan).await?;
+        wallet.persist()?;
+        Ok(wallet)
+    }
+
+    async fn get_transaction(&self, txid: &Txid) -> CoreResult<Transaction> {
+        cached_transaction(self.scan.client.chain_cache.as_deref(), txid, async {
+            Err(anyhow::anyhow!(
+                "Transaction {txid} is not cached and cannot be looked up over compact block filters"
+            ))
+        })
+        .await
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> CoreResult<Txid> {
+        let mut peer =
+            Peer::connect_any(&self.peers, self.network, self.scan.client.proxy.as_ref()).await?;
+        peer.broadcast(tx).await?;
+        Ok(tx.compute_txid())
+    }
+
+    async fn test_mempool_accept(&self, _tx: &Transaction) -> CoreResult<MempoolAcceptance> {
+        bail!("Mempool acceptance tests are not available over compact block filters")
+    }
+
+    async fn tx_status(&self, txid: &Txid) -> CoreResult<TxStatus> {
+        bail!("The status of {txid} cannot be looked up over compact block filters")
+    }
+
+    async fn estimate_fee_rate(&self, _conf_target: u32) -> CoreResult<f64> {
+        bail!("Fee estimation is not available over compact block filters; pass --fee-rate")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_derivation_index() -> Result<()> {
+        let desc = "wpkh([d34db33f/84h/0h/0h/1/7]0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)#abcdefgh";
+        assert_eq!(snapshot_derivation_index(desc), Some(7));
+        let hardened = "tr([d34db33f/86'/0'/0'/12']79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)";
+        assert_eq!(snapshot_derivation_index(hardened), Some(12));
+        assert_eq!(snapshot_derivation_index("addr(bc1qexample)"), None);
+        Ok(())
+    }
+}
@@ -1,12 +1,68 @@
-use anyhow::{Context, Result, bail, ensure};
+use anyhow::{Context, Result};
 use base64::Engine;
-use bdk_wallet::{KeychainKind, Wallet};
-use bitcoin::{Amount, FeeRate, Network, OutPoint, Txid};
+use bdk_wallet::chain::spk_client::{FullScanRequest, SyncRequest};
+use bdk_wallet::rusqlite::Connection;
+use bdk_wallet::{KeychainKind, PersistedWallet, Update, Wallet};
+use bitcoin::psbt::Psbt;
+use bitcoin::{Amount, FeeRate, Network, OutPoint, ScriptBuf, Sequence, Txid};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use tracing::{debug, warn};
 
+use crate::backend::BlockchainBackend;
+use crate::bitcoin_rpc::PsbtOptions;
+use crate::client_options::ClientOptions;
+use crate::descriptor::{MAX_PARALLEL_DESCRIPTOR_SCANS, expand_multipath_descriptor};
+use crate::destination_split::DestinationSplit;
+use crate::electrum::ElectrumServers;
+use crate::error::InsufficientFunds;
+use crate::error::{CoreResult, bail, ensure};
+use crate::network::check_transaction_network;
+use crate::retry::retry_blocking;
+use crate::utxo_filter::UtxoSelection;
+
+/// Default number of consecutive unused scripts before a keychain scan stops
+pub const DEFAULT_STOP_GAP: u32 = 200;
+/// Default number of concurrent Esplora requests (Electrum batch size)
+pub const DEFAULT_PARALLEL_REQUESTS: usize = 10;
+
+/// How BDK wallets are scanned against Electrum/Esplora and where their state is kept
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Consecutive unused scripts after which a keychain scan stops
+    pub stop_gap: u32,
+    /// Concurrent Esplora requests, or the Electrum request batch size
+    pub parallel_requests: usize,
+    /// SQLite database for incremental sync; `None` rescans from scratch
+    pub wallet_db: Option<PathBuf>,
+    /// Proxy, retries, certificate pin and trace of the backend connections
+    pub client: ClientOptions,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            stop_gap: DEFAULT_STOP_GAP,
+            parallel_requests: DEFAULT_PARALLEL_REQUESTS,
+            wallet_db: None,
+            client: ClientOptions::default(),
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Options for each of `scans` scans running side by side: they share the
+    /// request budget, so a server sees at most `parallel_requests` at once
+    fn shared_by(&self, scans: usize) -> ScanOptions {
+        ScanOptions {
+            parallel_requests: (self.parallel_requests / scans.max(1)).max(1),
+            ..self.clone()
+        }
+    }
+}
+
 /// UTXO information returned by BDK wallet
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BdkUtxo {
@@ -30,39 +86,358 @@ pub struct BdkUtxo {
     pub derivation_index: Option<u32>,
 }
 
-/// Expand multipath descriptors (e.g., <0;1>) into individual descriptors
-///
-/// BDK 2.0 doesn't natively support multipath descriptors, which are commonly used
-/// in wallet descriptors to specify both external (0) and internal/change (1) derivation paths.
-/// This function expands descriptors like "wpkh(xpub.../<0;1>/*)" into:
-/// - "wpkh(xpub.../0/*)" for external addresses
-/// - "wpkh(xpub.../1/*)" for internal (change) addresses
+/// Apply transaction-level options (locktime, RBF signalling) to a BDK transaction builder
+fn apply_tx_options<Cs>(tx_builder: &mut bdk_wallet::TxBuilder<'_, Cs>, options: &PsbtOptions) {
+    if let Some(locktime) = options.locktime {
+        tx_builder.nlocktime(locktime);
+    }
+
+    match options.rbf {
+        Some(true) => {
+            tx_builder.set_exact_sequence(Sequence::ENABLE_RBF_NO_LOCKTIME);
+        }
+        Some(false) => {
+            tx_builder.set_exact_sequence(Sequence::ENABLE_LOCKTIME_NO_RBF);
+        }
+        None => {}
+    }
+}
+
+/// Blocking Esplora client for BDK syncs, through the proxy of `client` if any.
 ///
-/// This is necessary for compatibility with descriptors from other tools like
-/// Bitcoin Core's `getdescriptorinfo` which often use this notation.
-fn expand_multipath_descriptor(descriptor: &str) -> Vec<String> {
-    if descriptor.contains("<") && descriptor.contains(">") {
-        // Extract the multipath part and expand it
-        let mut expanded = Vec::new();
-
-        // For now, handle the simple case of <0;1>
-        if descriptor.contains("<0;1>") {
-            expanded.push(descriptor.replace("<0;1>", "0"));
-            expanded.push(descriptor.replace("<0;1>", "1"));
+/// The blocking client cannot use a SOCKS5 proxy or pin certificates. With a
+/// proxy, a certificate pin or an RPC trace the client talks to a local relay,
+/// which sends its requests through the proxy over a pinned TLS session and
+/// records or replays them.
+pub(crate) fn esplora_blocking_client(
+    url: &str,
+    client: &ClientOptions,
+) -> Result<bdk_esplora::esplora_client::BlockingClient> {
+    if client.proxy.is_some() || client.trace.is_some() || client.cert_pins.for_url(url).is_some() {
+        // The relay applies the attempt timeout and the pin to its upstream requests
+        let relay = crate::trace_relay::esplora_url(url, client)?;
+        return Ok(bdk_esplora::esplora_client::Builder::new(&relay).build_blocking());
+    }
+    let timeout = client.retry.attempt_timeout;
+    Ok(bdk_esplora::esplora_client::Builder::new(url)
+        .timeout(timeout.as_secs())
+        .build_blocking())
+}
+
+/// Electrum or Esplora client used to sync a [`ScanWallet`]
+pub(crate) enum SyncClient {
+    Electrum(Box<bdk_electrum::BdkElectrumClient<bdk_electrum::electrum_client::Client>>),
+    Esplora(bdk_esplora::esplora_client::BlockingClient),
+}
+
+impl SyncClient {
+    fn full_scan(
+        &self,
+        request: FullScanRequest<KeychainKind>,
+        scan: &ScanOptions,
+    ) -> Result<Update> {
+        use bdk_esplora::EsploraExt;
+
+        let stop_gap = scan.stop_gap as usize;
+        Ok(match self {
+            SyncClient::Electrum(client) => client
+                .full_scan(request, stop_gap, scan.parallel_requests, false)
+                .context("Electrum full scan failed")?
+                .into(),
+            SyncClient::Esplora(client) => client
+                .full_scan(request, stop_gap, scan.parallel_requests)
+                .context("Esplora full scan failed")?
+                .into(),
+        })
+    }
+
+    fn sync(
+        &self,
+        request: SyncRequest<(KeychainKind, u32)>,
+        scan: &ScanOptions,
+    ) -> Result<Update> {
+        use bdk_esplora::EsploraExt;
+
+        Ok(match self {
+            SyncClient::Electrum(client) => client
+                .sync(request, scan.parallel_requests, false)
+                .context("Electrum sync failed")?
+                .into(),
+            SyncClient::Esplora(client) => client
+                .sync(request, scan.parallel_requests)
+                .context("Esplora sync failed")?
+                .into(),
+        })
+    }
+}
+
+/// A BDK wallet built in memory for a single run, or loaded from a SQLite
+/// database (`--wallet-db`) so later runs only sync what changed
+pub enum ScanWallet {
+    InMemory(Box<Wallet>),
+    Persisted {
+        wallet: Box<PersistedWallet<Connection>>,
+        db: Connection,
+    },
+}
+
+impl Deref for ScanWallet {
+    type Target = Wallet;
+
+    fn deref(&self) -> &Wallet {
+        match self {
+            ScanWallet::InMemory(wallet) => wallet,
+            ScanWallet::Persisted { wallet, .. } => wallet,
+        }
+    }
+}
+
+impl DerefMut for ScanWallet {
+    fn deref_mut(&mut self) -> &mut Wallet {
+        match self {
+            ScanWallet::InMemory(wallet) => wallet,
+            ScanWallet::Persisted { wallet, .. } => wallet,
+        }
+    }
+}
+
+impl ScanWallet {
+    /// Open the wallet for `descriptor`, loading it from `wallet_db` when given.
+    ///
+    /// Both keychains of a `<0;1>` descriptor live in the same wallet. A persisted
+    /// wallet refuses to load if the stored descriptor or network differ.
+    pub fn open(descriptor: &str, network: Network, wallet_db: Option<&Path>) -> CoreResult<Self> {
+        let descriptors = expand_multipath_descriptor(descriptor)?;
+        let (external, internal) = match descriptors.as_slice() {
+            [external] => (external.clone(), None),
+            [external, internal] => (external.clone(), Some(internal.clone())),
+            _ => bail!(
+                "A wallet has at most two paths (receive and change), got {count}: {descriptor}",
+                count = descriptors.len()
+            ),
+        };
+
+        let Some(path) = wallet_db else {
+            let wallet = match internal {
+                Some(internal) => Wallet::create(external, internal)
+                    .network(network)
+                    .create_wallet_no_persist()?,
+                None => Wallet::create_single(external)
+                    .network(network)
+                    .create_wallet_no_persist()?,
+            };
+            return Ok(ScanWallet::InMemory(Box::new(wallet)));
+        };
+
+        let mut db = Connection::open(path).with_context(|| {
+            format!(
+                "Failed to open wallet database: {path}",
+                path = path.display()
+            )
+        })?;
+        let loaded = Wallet::load()
+            .descriptor(KeychainKind::External, Some(external.clone()))
+            .descriptor(KeychainKind::Internal, internal.clone())
+            .check_network(network)
+            .load_wallet(&mut db)
+            .with_context(|| {
+                format!(
+                    "Wallet database {path} was created for a different descriptor or network",
+                    path = path.display()
+                )
+            })?;
+        let wallet = match (loaded, internal) {
+            (Some(wallet), _) => wallet,
+            (None, Some(internal)) => Wallet::create(external, internal)
+                .network(network)
+                .create_wallet(&mut db)?,
+            (None, None) => Wallet::create_single(external)
+                .network(network)
+                .create_wallet(&mut db)?,
+        };
+        Ok(ScanWallet::Persisted {
+            wallet: Box::new(wallet),
+            db,
+        })
+    }
+
+    /// Bring the wallet up to date with the chain.
+    ///
+    /// In-memory wallets and new databases get a full scan. A database that has
+    /// already been scanned only syncs its revealed scripts, plus `stop_gap`
+    /// scripts past the last used index of each keychain.
+    pub(crate) fn sync(&mut self, client: &SyncClient, scan: &ScanOptions) -> Result<()> {
+        let incremental =
+            matches!(self, ScanWallet::Persisted { .. }) && self.latest_checkpoint().height() > 0;
+        if incremental {
+            self.sync_revealed(client, scan)?;
         } else {
-            // If it's a different multipath pattern, just return the original
-            expanded.push(descriptor.to_string());
+            let request = self
+                .start_full_scan()
+                .inspect(|keychain, spk_i, _| {
+                    // Progress output to stderr to keep stdout clean for JSON
+                    debug!("Scanning {keychain:?} at index {spk_i}");
+                })
+                .build();
+            let update = client.full_scan(request, scan)?;
+            self.apply_update(update)?;
         }
+        Ok(self.persist()?)
+    }
 
-        expanded
-    } else {
-        vec![descriptor.to_string()]
+    /// [`Self::sync`] against the fastest healthy Electrum server, failing over on errors
+    pub(crate) fn sync_electrum(
+        &mut self,
+        servers: &ElectrumServers,
+        scan: &ScanOptions,
+    ) -> Result<()> {
+        Ok(servers.call(&scan.client, |client| {
+            let client =
+                SyncClient::Electrum(Box::new(bdk_electrum::BdkElectrumClient::new(client)));
+            self.sync(&client, scan)
+        })?)
+    }
+
+    /// [`Self::sync`] against an Esplora server, retrying transient failures
+    pub(crate) fn sync_esplora(&mut self, url: &str, scan: &ScanOptions) -> Result<()> {
+        Ok(retry_blocking(&scan.client.retry, "Esplora sync", || {
+            let client = esplora_blocking_client(url, &scan.client)?;
+            self.sync(&SyncClient::Esplora(client), scan)
+        })?)
+    }
+
+    /// Write staged changes (sync results, revealed addresses) to the database, if any
+    pub fn persist(&mut self) -> CoreResult<()> {
+        if let ScanWallet::Persisted { wallet, db } = self {
+            wallet
+                .persist(db)
+                .context("Failed to write wallet database")?;
+        }
+        Ok(())
+    }
+
+    fn sync_revealed(&mut self, client: &SyncClient, scan: &ScanOptions) -> Result<()> {
+        let stop_gap = scan.stop_gap;
+        let keychains: Vec<KeychainKind> = self.keychains().map(|(keychain, _)| keychain).collect();
+        let mut previous_targets = None;
+        // Repeat while new activity pushes the gap window further out
+        loop {
+            let targets: Vec<(KeychainKind, u32)> = keychains
+                .iter()
+                .map(|keychain| {
+                    let target = self
+                        .spk_index()
+                        .last_used_index(*keychain)
+                        .map_or(stop_gap.saturating_sub(1), |index| {
+                            index.saturating_add(stop_gap)
+                        });
+                    (*keychain, target)
+                })
+                .collect();
+            if previous_targets.as_ref() == Some(&targets) {
+                return Ok(());
+            }
+            for (keychain, target) in &targets {
+                self.reveal_addresses_to(*keychain, *target).for_each(drop);
+            }
+            debug!("Syncing revealed scripts up to {targets:?}");
+            let request = self.start_sync_with_revealed_spks().build();
+            let update = client.sync(request, scan)?;
+            self.apply_update(update)?;
+            previous_targets = Some(targets);
+        }
     }
+
+    /// Unspent outputs of the synced wallet, largest first
+    pub fn utxos(&self, network: Network) -> Vec<BdkUtxo> {
+        let tip_height = self.latest_checkpoint().height();
+        let mut utxos: Vec<BdkUtxo> = self
+            .list_unspent()
+            .filter_map(|utxo| {
+                let address =
+                    match bitcoin::Address::from_script(&utxo.txout.script_pubkey, network) {
+                        Ok(address) => address,
+                        Err(e) => {
+                            warn!("Failed to derive address from script: {e}");
+                            return None;
+                        }
+                    };
+                let confirmations = match &utxo.chain_position {
+                    bdk_wallet::chain::ChainPosition::Confirmed { anchor, .. } => {
+                        let block_height = anchor.block_id.height;
+                        if tip_height >= block_height {
+                            tip_height - block_height + 1
+                        } else {
+                            0
+                        }
+                    }
+                    bdk_wallet::chain::ChainPosition::Unconfirmed { .. } => 0,
+                };
+                Some(BdkUtxo {
+                    txid: utxo.outpoint.txid.to_string(),
+                    vout: utxo.outpoint.vout,
+                    address: address.to_string(),
+                    amount: utxo.txout.value.to_sat(),
+                    amount_btc: utxo.txout.value.to_btc(),
+                    confirmations,
+                    is_change: utxo.keychain == KeychainKind::Internal,
+                    keychain: match utxo.keychain {
+                        KeychainKind::External => "external",
+                        KeychainKind::Internal => "internal",
+                    }
+                    .to_string(),
+                    derivation_index: Some(utxo.derivation_index),
+                })
+            })
+            .collect();
+        utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+        utxos
+    }
+}
+
+/// Scan every descriptor expanded from `descriptor` (e.g. both halves of `<0;1>`)
+/// on its own thread, at most [`MAX_PARALLEL_DESCRIPTOR_SCANS`] at a time,
+/// handing the UTXOs of each to `on_utxo` as soon as its scan completes
+fn scan_descriptors_parallel<F>(
+    descriptor: &str,
+    scan: F,
+    mut on_utxo: impl FnMut(BdkUtxo) -> Result<()>,
+) -> Result<()>
+where
+    F: Fn(&str) -> Result<Vec<BdkUtxo>> + Sync,
+{
+    let descriptors = expand_multipath_descriptor(descriptor)?;
+    for batch in descriptors.chunks(MAX_PARALLEL_DESCRIPTOR_SCANS) {
+        std::thread::scope(|scope| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let handles: Vec<_> = batch
+                .iter()
+                .map(|desc| {
+                    let sender = sender.clone();
+                    let scan = &scan;
+                    scope.spawn(move || sender.send(scan(desc)))
+                })
+                .collect();
+            drop(sender);
+            let streamed = receiver
+                .iter()
+                .try_for_each(|utxos| utxos?.into_iter().try_for_each(&mut on_utxo));
+            // Join every thread, so a panic is reported instead of re-raised
+            let panicked = handles
+                .into_iter()
+                .map(|handle| handle.join())
+                .filter(Result::is_err)
+                .count();
+            ensure!(panicked == 0, "Descriptor scan thread panicked");
+            streamed
+        })?;
+    }
+    Ok(())
 }
 
 /// List UTXOs using BDK wallet
-pub fn list_utxos_bdk(descriptor: &str, network: Network) -> Result<Vec<BdkUtxo>> {
-    let descriptors = expand_multipath_descriptor(descriptor);
+pub fn list_utxos_bdk(descriptor: &str, network: Network) -> CoreResult<Vec<BdkUtxo>> {
+    let descriptors = expand_multipath_descriptor(descriptor)?;
     let mut all_utxos = Vec::new();
 
     for desc in &descriptors {
@@ -126,177 +501,49 @@ pub fn list_utxos_bdk(descriptor: &str, network: Network) -> Result<Vec<BdkUtxo>
 pub async fn scan_and_list_utxos_electrum(
     descriptor: &str,
     network: Network,
-    electrum_url: &str,
-    stop_gap: u32,
-) -> Result<Vec<BdkUtxo>> {
-    use bdk_electrum::{BdkElectrumClient, electrum_client};
-
-    let descriptors = expand_multipath_descriptor(descriptor);
-    let mut all_utxos = Vec::new();
-
-    // Create Electrum client once for all descriptors
-    let client = BdkElectrumClient::new(
-        electrum_client::Client::new(electrum_url).context("Failed to create Electrum client")?,
-    );
-
-    for desc in &descriptors {
-        // Create wallet with only external descriptor - fail fast on invalid descriptor
-        let mut wallet = Wallet::create_single(desc.clone())
-            .network(network)
-            .create_wallet_no_persist()
-            .with_context(|| format!("Failed to create wallet for descriptor '{desc}'"))?;
-
-        // Create a full scan request
-        let request = wallet
-            .start_full_scan()
-            .inspect({
-                move |keychain, spk_i, _| {
-                    // Progress output to stderr to keep stdout clean for JSON
-                    debug!("Scanning {:?} at index {}", keychain, spk_i);
-                }
-            })
-            .build();
-
-        // Perform the scan - fail fast on scan errors
-        let update = client
-            .full_scan(request, stop_gap as usize, 10, false)
-            .with_context(|| format!("Failed to scan with Electrum for descriptor '{desc}'"))?;
-
-        // Apply the update to the wallet - fail fast on update errors
-        wallet
-            .apply_update(update)
-            .with_context(|| format!("Failed to apply update for descriptor '{desc}'"))?;
-
-        // Get current tip height for confirmation calculations
-        let tip_height = wallet.latest_checkpoint().height();
-
-        // List unspent outputs
-        let utxos = wallet.list_unspent();
-
-        for utxo in utxos {
-            // Get the address for this output
-            let script = &utxo.txout.script_pubkey;
-            match bitcoin::Address::from_script(script, network) {
-                Ok(address) => {
-                    // Determine keychain type
-                    let keychain = match utxo.keychain {
-                        KeychainKind::External => "external",
-                        KeychainKind::Internal => "internal",
-                    };
-                    let is_change = utxo.keychain == KeychainKind::Internal;
-
-                    // Calculate confirmations based on chain position
-                    let confirmations = match &utxo.chain_position {
-                        bdk_wallet::chain::ChainPosition::Confirmed { anchor, .. } => {
-                            let block_height = anchor.block_id.height;
-                            if tip_height >= block_height {
-                                tip_height - block_height + 1
-                            } else {
-                                0
-                            }
-                        }
-                        bdk_wallet::chain::ChainPosition::Unconfirmed { .. } => 0,
-                    };
-
-                    all_utxos.push(BdkUtxo {
-                        txid: utxo.outpoint.txid.to_string(),
-                        vout: utxo.outpoint.vout,
-                        address: address.to_string(),
-                        amount: utxo.txout.value.to_sat(),
-                        amount_btc: utxo.txout.value.to_btc(),
-                        confirmations,
-                        is_change,
-                        keychain: keychain.to_string(),
-                        derivation_index: None,
-                    });
-                }
-                Err(e) => {
-                    warn!("Failed to derive address from script: {e}");
-                }
-            }
-        }
-    }
-
-    // Sort by amount descending
-    all_utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
-
-    Ok(all_utxos)
+    servers: &ElectrumServers,
+    scan: &ScanOptions,
+) -> CoreResult<Vec<BdkUtxo>> {
+    let mut utxos = Vec::new();
+    stream_utxos_electrum(descriptor, network, servers, scan, |utxo| {
+        utxos.push(utxo);
+        Ok(())
+    })
+    .await?;
+    utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+    Ok(utxos)
 }
 
-/// Scan blockchain for UTXOs using BDK wallet with Bitcoin Core RPC backend
-///
-/// This function bridges BDK wallet functionality with Bitcoin Core RPC. It uses
-/// the existing BitcoinRpcClient to query UTXOs via `scantxoutset` and converts
-/// the results to BDK-compatible structures.
-///
-/// # Important Implementation Details:
-///
-/// 1. **Address Derivation**: Bitcoin Core's `scantxoutset` doesn't return addresses,
-///    only scriptPubKey hex. We derive addresses from the scriptPubKey to provide
-///    a complete UTXO representation.
-///
-/// 2. **Why not use bdk_bitcoind_rpc?**: The bdk_bitcoind_rpc crate underwent major
-///    API changes in v0.20 that are incompatible with the current BDK 2.0 release.
-///    Using the existing RPC client provides a stable integration path.
-///
-/// 3. **Missing BDK metadata**: Some BDK-specific fields (keychain type, derivation
-///    index) aren't available from Bitcoin Core and are filled with defaults.
-pub async fn scan_and_list_utxos_bitcoind(
+/// [`scan_and_list_utxos_electrum`] handing the UTXOs of each keychain to
+/// `on_utxo` as soon as that keychain is scanned, instead of collecting them.
+/// A `wallet_db` wallet syncs both keychains at once, so its UTXOs come after
+/// the whole sync. An error from `on_utxo` stops the scan.
+pub async fn stream_utxos_electrum(
     descriptor: &str,
-    _network: Network,
-    bitcoin_dir: &Path,
-) -> Result<Vec<BdkUtxo>> {
-    use crate::BitcoinRpcClient;
-
-    let mut all_utxos = Vec::new();
-
-    // Use our existing Bitcoin RPC client to get UTXOs for the descriptor
-    let client = BitcoinRpcClient::new_auto(
-        "http://127.0.0.1:8332".to_string(),
-        Some(bitcoin_dir),
-        None,
-        None,
-    )?;
-
-    // Use the existing list_utxos_for_descriptor method
-    let utxo_result = client.list_utxos_for_descriptor(descriptor).await?;
-
-    // Convert from RPC UTXOs to BDK UTXOs
-    for utxo in utxo_result.utxos {
-        // Try to derive address from scriptPubKey if not provided
-        // This is a critical step because Bitcoin Core's scantxoutset doesn't include addresses
-        // in the response. Without this, UTXOs would appear to be "missing" if code filters
-        // by address presence (a common pattern that caused issues during development).
-        let address = if let Some(addr) = utxo.address {
-            addr
-        } else {
-            // Try to decode the scriptPubKey hex and derive address
-            match hex::decode(&utxo.script_pub_key) {
-                Ok(script_bytes) => {
-                    let script = bitcoin::ScriptBuf::from(script_bytes);
-                    match bitcoin::Address::from_script(&script, _network) {
-                        Ok(addr) => addr.to_string(),
-                        Err(_) => format!("script:{script}", script = utxo.script_pub_key), // Fallback to script hex
-                    }
-                }
-                Err(_) => format!("script:{script}", script = utxo.script_pub_key), // Fallback to script hex
-            }
-        };
-
-        all_utxos.push(BdkUtxo {
-            txid: utxo.txid,
-            vout: utxo.vout,
-            address,
-            amount: utxo.amount_sats,
-            amount_btc: bitcoin::Amount::from_sat(utxo.amount_sats).to_btc(),
-            confirmations: utxo.confirmations,
-            is_change: false,                // We don't have this info from RPC
-            keychain: "unknown".to_string(), // We don't have this info from RPC
-            derivation_index: None,
-        });
+    network: Network,
+    servers: &ElectrumServers,
+    scan: &ScanOptions,
+    mut on_utxo: impl FnMut(BdkUtxo) -> Result<()>,
+) -> CoreResult<()> {
+    if let Some(wallet_db) = &scan.wallet_db {
+        let mut wallet = ScanWallet::open(descriptor, network, Some(wallet_db))?;
+        wallet.sync_electrum(servers, scan)?;
+        return Ok(wallet.utxos(network).into_iter().try_for_each(on_utxo)?);
     }
 
-    Ok(all_utxos)
+    Ok(scan_descriptors_parallel(
+        descriptor,
+        |desc| {
+            // Each keychain gets its own connection so scans run concurrently
+            let mut wallet = ScanWallet::open(desc, network, None)
+                .with_context(|| format!("Failed to create wallet for descriptor '{desc}'"))?;
+            wallet
+                .sync_electrum(servers, scan)
+                .with_context(|| format!("Failed to scan with Electrum for descriptor '{desc}'"))?;
+            Ok(wallet.utxos(network))
+        },
+        &mut on_utxo,
+    )?)
 }
 
 /// Scan blockchain for UTXOs using BDK wallet with Esplora backend
@@ -304,98 +551,48 @@ pub async fn scan_and_list_utxos_esplora(
     descriptor: &str,
     network: Network,
     esplora_url: &str,
-    stop_gap: u32,
-) -> Result<Vec<BdkUtxo>> {
-    use bdk_esplora::{EsploraExt, esplora_client};
-
-    let descriptors = expand_multipath_descriptor(descriptor);
-    let mut all_utxos = Vec::new();
-
-    // Create Esplora client once for all descriptors
-    let client = esplora_client::Builder::new(esplora_url).build_blocking();
-
-    for desc in &descriptors {
-        // Create wallet with only external descriptor - fail fast on invalid descriptor
-        let mut wallet = Wallet::create_single(desc.clone())
-            .network(network)
-            .create_wallet_no_persist()
-            .with_context(|| format!("Failed to create wallet for descriptor '{desc}'"))?;
-
-        // Create a full scan request
-        let request = wallet
-            .start_full_scan()
-            .inspect({
-                move |keychain, spk_i, _| {
-                    // Progress output to stderr to keep stdout clean for JSON
-                    debug!("Scanning {:?} at index {}", keychain, spk_i);
-                }
-            })
-            .build();
-
-        // Perform the scan with Esplora - fail fast on scan errors
-        let update = client
-            .full_scan(request, stop_gap as usize, 10)
-            .with_context(|| format!("Failed to scan with Esplora for descriptor '{desc}'"))?;
-
-        // Apply the update to the wallet - fail fast on update errors
-        wallet
-            .apply_update(update)
-            .with_context(|| format!("Failed to apply update for descriptor '{desc}'"))?;
-
-        // Get current tip height for confirmation calculations
-        let tip_height = wallet.latest_checkpoint().height();
-
-        // List unspent outputs
-        let utxos = wallet.list_unspent();
-
-        for utxo in utxos {
-            // Get the address for this output
-            let script = &utxo.txout.script_pubkey;
-            match bitcoin::Address::from_script(script, network) {
-                Ok(address) => {
-                    // Determine keychain type
-                    let keychain = match utxo.keychain {
-                        KeychainKind::External => "external",
-                        KeychainKind::Internal => "internal",
-                    };
-                    let is_change = utxo.keychain == KeychainKind::Internal;
-
-                    // Calculate confirmations based on chain position
-                    let confirmations = match &utxo.chain_position {
-                        bdk_wallet::chain::ChainPosition::Confirmed { anchor, .. } => {
-                            let block_height = anchor.block_id.height;
-                            if tip_height >= block_height {
-                                tip_height - block_height + 1
-                            } else {
-                                0
-                            }
-                        }
-                        bdk_wallet::chain::ChainPosition::Unconfirmed { .. } => 0,
-                    };
+    scan: &ScanOptions,
+) -> CoreResult<Vec<BdkUtxo>> {
+    let mut utxos = Vec::new();
+    stream_utxos_esplora(descriptor, network, esplora_url, scan, |utxo| {
+        utxos.push(utxo);
+        Ok(())
+    })
+    .await?;
+    utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+    Ok(utxos)
+}
 
-                    all_utxos.push(BdkUtxo {
-                        txid: utxo.outpoint.txid.to_string(),
-                        vout: utxo.outpoint.vout,
-                        address: address.to_string(),
-                        amount: utxo.txout.value.to_sat(),
-                        amount_btc: utxo.txout.value.to_btc(),
-                        confirmations,
-                        is_change,
-                        keychain: keychain.to_string(),
-                        derivation_index: None,
-                    });
-                }
-                Err(e) => {
-                    warn!("Failed to derive address from script: {e}");
-                }
-            }
-        }
+/// [`scan_and_list_utxos_esplora`] handing the UTXOs of each keychain to
+/// `on_utxo` as soon as that keychain is scanned, like [`stream_utxos_electrum`]
+pub async fn stream_utxos_esplora(
+    descriptor: &str,
+    network: Network,
+    esplora_url: &str,
+    scan: &ScanOptions,
+    mut on_utxo: impl FnMut(BdkUtxo) -> Result<()>,
+) -> CoreResult<()> {
+    if let Some(wallet_db) = &scan.wallet_db {
+        let mut wallet = ScanWallet::open(descriptor, network, Some(wallet_db))?;
+        wallet.sync_esplora(esplora_url, scan)?;
+        return Ok(wallet.utxos(network).into_iter().try_for_each(on_utxo)?);
     }
 
-    // Sort by amount descending
-    all_utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
-
-    Ok(all_utxos)
+    // Public instances rate-limit per client, so the keychains split the budget
+    let scans = expand_multipath_descriptor(descriptor)?.len();
+    let scan = scan.shared_by(scans.min(MAX_PARALLEL_DESCRIPTOR_SCANS));
+    Ok(scan_descriptors_parallel(
+        descriptor,
+        |desc| {
+            let mut wallet = ScanWallet::open(desc, network, None)
+                .with_context(|| format!("Failed to create wallet for descriptor '{desc}'"))?;
+            wallet
+                .sync_esplora(esplora_url, &scan)
+                .with_context(|| format!("Failed to scan with Esplora for descriptor '{desc}'"))?;
+            Ok(wallet.utxos(network))
+        },
+        &mut on_utxo,
+    )?)
 }
 
 /// Summary of UTXOs
@@ -410,6 +607,9 @@ pub struct BdkUtxoSummary {
     /// Count by confirmation status
     pub confirmed_count: usize,
     pub unconfirmed_count: usize,
+    /// Bytes received from the backend (reported by low-bandwidth scans)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_transferred: Option<u64>,
     /// List of UTXOs
     pub utxos: Vec<BdkUtxo>,
 }
@@ -426,6 +626,7 @@ pub fn get_utxo_summary(utxos: Vec<BdkUtxo>) -> BdkUtxoSummary {
         total_amount_btc: bitcoin::Amount::from_sat(total_amount).to_btc(),
         confirmed_count,
         unconfirmed_count,
+        bytes_transferred: None,
         utxos,
     }
 }
@@ -441,6 +642,30 @@ pub struct BdkPsbtResponse {
     pub change_position: Option<u32>,
 }
 
+/// BDK fee rate from sat/vB, keeping fractional rates such as fee estimates
+fn fee_rate_from_sat_per_vb(rate: f64) -> Result<FeeRate> {
+    ensure!(
+        rate.is_finite() && rate >= 0.0,
+        "Invalid fee rate: {rate} sat/vB"
+    );
+    // 1 sat/vB = 250 sat/kwu
+    Ok(FeeRate::from_sat_per_kwu((rate * 250.0).ceil() as u64))
+}
+
+/// Position of the output paying back to the wallet other than to one of the
+/// recipients: BDK sends change to the internal keychain, or to the external
+/// one of a single-path descriptor
+fn change_position(wallet: &Wallet, psbt: &Psbt, recipients: &[ScriptBuf]) -> Option<u32> {
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .position(|txout| {
+            !recipients.contains(&txout.script_pubkey)
+                && wallet.is_mine(txout.script_pubkey.clone())
+        })
+        .and_then(|position| u32::try_from(position).ok())
+}
+
 /// Input structure that can be either a UTXO (txid:vout) or a descriptor
 #[derive(Debug, Clone)]
 pub enum InputSpec {
@@ -478,89 +703,18 @@ pub async fn create_psbt_bdk(
     fee_rate: Option<f64>, // sat/vB
     descriptor: &str,
     network: Network,
-    backend: &str,
-) -> Result<BdkPsbtResponse> {
-    // Create wallet and sync with backend
-    let mut wallet = Wallet::create_single(descriptor.to_string())
-        .network(network)
-        .create_wallet_no_persist()?;
-
-    // Sync wallet with blockchain and get UTXOs
-    let utxos = if backend.starts_with("electrum://") {
-        let url = backend.strip_prefix("electrum://").unwrap();
-        use bdk_electrum::{BdkElectrumClient, electrum_client};
-
-        let client = BdkElectrumClient::new(
-            electrum_client::Client::new(url).context("Failed to create Electrum client")?,
-        );
-
-        let request = wallet.start_full_scan().build();
-        let update = client.full_scan(request, 200, 10, false)?;
-        wallet.apply_update(update)?;
-
-        // Now get the UTXOs from the synced wallet
-        let wallet_utxos = wallet.list_unspent();
-        let mut utxos = Vec::new();
-        for utxo in wallet_utxos {
-            utxos.push(BdkUtxo {
-                txid: utxo.outpoint.txid.to_string(),
-                vout: utxo.outpoint.vout,
-                address: bitcoin::Address::from_script(&utxo.txout.script_pubkey, network)?
-                    .to_string(),
-                amount: utxo.txout.value.to_sat(),
-                amount_btc: utxo.txout.value.to_btc(),
-                confirmations: 0, // Not needed for this use case
-                is_change: utxo.keychain == KeychainKind::Internal,
-                keychain: match utxo.keychain {
-                    KeychainKind::External => "external",
-                    KeychainKind::Internal => "internal",
-                }
-                .to_string(),
-                derivation_index: None,
-            });
-        }
-        utxos
-    } else if backend.starts_with("esplora://") {
-        let url = backend.strip_prefix("esplora://").unwrap();
-        use bdk_esplora::{EsploraExt, esplora_client};
-
-        let client = esplora_client::Builder::new(url).build_blocking();
-        let request = wallet.start_full_scan().build();
-        let update = client.full_scan(request, 200, 10)?;
-        wallet.apply_update(update)?;
+    backend: &dyn BlockchainBackend,
+    options: &PsbtOptions,
+) -> CoreResult<BdkPsbtResponse> {
+    check_transaction_network(
+        outputs.iter().map(|(address, _)| address.as_str()),
+        Some(descriptor),
+        network,
+    )?;
 
-        // Now get the UTXOs from the synced wallet
-        let wallet_utxos = wallet.list_unspent();
-        let mut utxos = Vec::new();
-        for utxo in wallet_utxos {
-            utxos.push(BdkUtxo {
-                txid: utxo.outpoint.txid.to_string(),
-                vout: utxo.outpoint.vout,
-                address: bitcoin::Address::from_script(&utxo.txout.script_pubkey, network)?
-                    .to_string(),
-                amount: utxo.txout.value.to_sat(),
-                amount_btc: utxo.txout.value.to_btc(),
-                confirmations: 0, // Not needed for this use case
-                is_change: utxo.keychain == KeychainKind::Internal,
-                keychain: match utxo.keychain {
-                    KeychainKind::External => "external",
-                    KeychainKind::Internal => "internal",
-                }
-                .to_string(),
-                derivation_index: None,
-            });
-        }
-        utxos
-    } else if backend.starts_with("bitcoind://") {
-        let path_str = backend.strip_prefix("bitcoind://").unwrap();
-        let path = Path::new(path_str);
-        scan_and_list_utxos_bitcoind(descriptor, network, path).await?
-    } else {
-        bail!(
-            "Unsupported backend: {}. Expected electrum://, esplora://, or bitcoind://",
-            backend
-        )
-    };
+    // Create wallet and sync with backend
+    let mut wallet = backend.synced_wallet(descriptor, network).await?;
+    let utxos = wallet.utxos(network);
 
     // Parse inputs
     let mut input_specs = Vec::new();
@@ -601,30 +755,44 @@ pub async fn create_psbt_bdk(
     }
 
     // Add outputs
+    let mut recipients = Vec::with_capacity(outputs.len());
     for (address, amount) in outputs {
         let script = bitcoin::Address::from_str(address)?
             .require_network(network)?
             .script_pubkey();
+        recipients.push(script.clone());
         tx_builder.add_recipient(script, *amount);
     }
 
+    // Zero-value OP_RETURN data output
+    if let Some(script) = options.op_return_script()? {
+        tx_builder.add_recipient(script, Amount::ZERO);
+    }
+
     // Set fee rate if provided
     if let Some(rate) = fee_rate {
-        // BDK expects fee rate in sat/vB
-        tx_builder.fee_rate(FeeRate::from_sat_per_vb(rate as u64).expect("Valid fee rate"));
+        tx_builder.fee_rate(fee_rate_from_sat_per_vb(rate)?);
     }
 
+    apply_tx_options(&mut tx_builder, options);
+
     // Manually select UTXOs (disable coin selection)
     tx_builder.manually_selected_only();
 
     // Finish building
-    let psbt = tx_builder.finish()?;
-
-    // Calculate fee
+    let mut psbt = tx_builder.finish()?;
     let fee = psbt.fee()?;
-
-    // Find change position if any
-    let change_position = None; // TODO: Detect change output
+    if let Some(limits) = &options.fee_limits {
+        limits.check_psbt(&psbt, fee.to_sat(), |_, txout| {
+            recipients.contains(&txout.script_pubkey)
+        })?;
+    }
+    if let Some(order) = options.output_order {
+        order.apply(&mut psbt, &recipients);
+    }
+    let change_position = change_position(&wallet, &psbt, &recipients);
+    // Record the change address revealed by this transaction
+    wallet.persist()?;
 
     // Serialize PSBT to base64
     let psbt_bytes = psbt.serialize();
@@ -644,80 +812,64 @@ pub async fn create_funded_psbt_bdk(
     fee_rate: Option<f64>, // sat/vB
     descriptor: &str,
     network: Network,
-    backend: &str,
-) -> Result<BdkPsbtResponse> {
-    // Create wallet and sync with backend
-    let mut wallet = Wallet::create_single(descriptor.to_string())
-        .network(network)
-        .create_wallet_no_persist()?;
-
-    // Sync wallet with blockchain
-    if backend.starts_with("electrum://") {
-        let url = backend.strip_prefix("electrum://").unwrap();
-        use bdk_electrum::{BdkElectrumClient, electrum_client};
-
-        let client = BdkElectrumClient::new(
-            electrum_client::Client::new(url).context("Failed to create Electrum client")?,
-        );
+    backend: &dyn BlockchainBackend,
+    options: &PsbtOptions,
+) -> CoreResult<BdkPsbtResponse> {
+    check_transaction_network(
+        outputs.iter().map(|(address, _)| address.as_str()),
+        Some(descriptor),
+        network,
+    )?;
 
-        let request = wallet.start_full_scan().build();
-        let update = client.full_scan(request, 200, 10, false)?;
-        wallet.apply_update(update)?;
-    } else if backend.starts_with("esplora://") {
-        let url = backend.strip_prefix("esplora://").unwrap();
-        use bdk_esplora::{EsploraExt, esplora_client};
-
-        let client = esplora_client::Builder::new(url).build_blocking();
-        let request = wallet.start_full_scan().build();
-        let update = client.full_scan(request, 200, 10)?;
-        wallet.apply_update(update)?;
-    } else if backend.starts_with("bitcoind://") {
-        // For Bitcoin Core, we'll use the existing RPC approach
-        // since BDK's bitcoind integration is limited
-        let path_str = backend.strip_prefix("bitcoind://").unwrap();
-        let path = Path::new(path_str);
-        let _utxos = scan_and_list_utxos_bitcoind(descriptor, network, path).await?;
-        // Note: Bitcoin Core backend doesn't provide the same update mechanism
-        // so the wallet won't be fully aware of pending transactions
-    } else {
-        bail!(
-            "Unsupported backend: {}. Expected electrum://, esplora://, or bitcoind://",
-            backend
-        )
-    }
+    // Create wallet and sync with backend
+    let mut wallet = backend.synced_wallet(descriptor, network).await?;
 
     // Build transaction
     let mut tx_builder = wallet.build_tx();
 
     // Add outputs
+    let mut recipients = Vec::with_capacity(outputs.len());
     for (address, amount) in outputs {
         let script = bitcoin::Address::from_str(address)?
             .require_network(network)?
             .script_pubkey();
+        recipients.push(script.clone());
         tx_builder.add_recipient(script, *amount);
     }
 
+    // Zero-value OP_RETURN data output
+    if let Some(script) = options.op_return_script()? {
+        tx_builder.add_recipient(script, Amount::ZERO);
+    }
+
     // Set fee rate
     if let Some(rate) = fee_rate {
-        // BDK expects fee rate in sat/vB
-        tx_builder.fee_rate(FeeRate::from_sat_per_vb(rate as u64).expect("Valid fee rate"));
-    } else if let Some(_target) = conf_target {
-        // TODO: Implement fee estimation based on confirmation target
-        // For now, use a default fee rate
-        tx_builder.fee_rate(FeeRate::from_sat_per_vb(10).expect("Valid fee rate"));
+        tx_builder.fee_rate(fee_rate_from_sat_per_vb(rate)?);
+    } else if let Some(target) = conf_target {
+        let rate = backend.estimate_fee_rate(target).await?;
+        debug!(
+            "Estimated {rate} sat/vB for {target} blocks from {backend}",
+            backend = backend.name()
+        );
+        tx_builder.fee_rate(fee_rate_from_sat_per_vb(rate)?);
     }
 
-    // Enable RBF is not available in BDK 2.0
-    // tx_builder.enable_rbf();
+    apply_tx_options(&mut tx_builder, options);
 
     // Finish building
-    let psbt = tx_builder.finish()?;
-
-    // Calculate fee
+    let mut psbt = tx_builder.finish()?;
     let fee = psbt.fee()?;
-
-    // Find change position
-    let change_position = None; // TODO: Detect change output position
+    if let Some(limits) = &options.fee_limits {
+        limits.check_psbt(&psbt, fee.to_sat(), |_, txout| {
+            recipients.contains(&txout.script_pubkey)
+        })?;
+    }
+    if let Some(order) = options.output_order {
+        order.apply(&mut psbt, &recipients);
+    }
+    let change_position = change_position(&wallet, &psbt, &recipients);
+    // Record the change address revealed by this transaction
+    wallet.persist()?;
 
     // Serialize PSBT to base64
     let psbt_bytes = psbt.serialize();
@@ -730,7 +882,8 @@ pub async fn create_funded_psbt_bdk(
     })
 }
 
-/// Move/consolidate UTXOs to a single destination using BDK
+/// Move/consolidate UTXOs to one destination, or split them across several
+/// (see [`DestinationSplit`]), using BDK
 #[allow(clippy::too_many_arguments)]
 pub async fn move_utxos_bdk(
     inputs: &[String],
@@ -738,91 +891,22 @@ pub async fn move_utxos_bdk(
     fee_rate: Option<f64>,
     fee_sats: Option<u64>,
     max_amount: Option<Amount>,
+    selection: UtxoSelection,
     descriptor: &str,
     network: Network,
-    backend: &str,
-) -> Result<BdkPsbtResponse> {
-    // Create wallet and sync with backend
-    let mut wallet = Wallet::create_single(descriptor.to_string())
-        .network(network)
-        .create_wallet_no_persist()?;
-
-    // Sync wallet with blockchain and get UTXOs
-    let utxos = if backend.starts_with("electrum://") {
-        let url = backend.strip_prefix("electrum://").unwrap();
-        use bdk_electrum::{BdkElectrumClient, electrum_client};
-
-        let client = BdkElectrumClient::new(
-            electrum_client::Client::new(url).context("Failed to create Electrum client")?,
-        );
-
-        let request = wallet.start_full_scan().build();
-        let update = client.full_scan(request, 200, 10, false)?;
-        wallet.apply_update(update)?;
-
-        // Now get the UTXOs from the synced wallet
-        let wallet_utxos = wallet.list_unspent();
-        let mut utxos = Vec::new();
-        for utxo in wallet_utxos {
-            utxos.push(BdkUtxo {
-                txid: utxo.outpoint.txid.to_string(),
-                vout: utxo.outpoint.vout,
-                address: bitcoin::Address::from_script(&utxo.txout.script_pubkey, network)?
-                    .to_string(),
-                amount: utxo.txout.value.to_sat(),
-                amount_btc: utxo.txout.value.to_btc(),
-                confirmations: 0, // Not needed for this use case
-                is_change: utxo.keychain == KeychainKind::Internal,
-                keychain: match utxo.keychain {
-                    KeychainKind::External => "external",
-                    KeychainKind::Internal => "internal",
-                }
-                .to_string(),
-                derivation_index: None,
-            });
-        }
-        utxos
-    } else if backend.starts_with("esplora://") {
-        let url = backend.strip_prefix("esplora://").unwrap();
-        use bdk_esplora::{EsploraExt, esplora_client};
-
-        let client = esplora_client::Builder::new(url).build_blocking();
-        let request = wallet.start_full_scan().build();
-        let update = client.full_scan(request, 200, 10)?;
-        wallet.apply_update(update)?;
+    backend: &dyn BlockchainBackend,
+    options: &PsbtOptions,
+) -> CoreResult<BdkPsbtResponse> {
+    let split = DestinationSplit::from_str(destination)?;
+    check_transaction_network(
+        split.destinations().iter().map(|d| d.address.as_str()),
+        Some(descriptor),
+        network,
+    )?;
 
-        // Now get the UTXOs from the synced wallet
-        let wallet_utxos = wallet.list_unspent();
-        let mut utxos = Vec::new();
-        for utxo in wallet_utxos {
-            utxos.push(BdkUtxo {
-                txid: utxo.outpoint.txid.to_string(),
-                vout: utxo.outpoint.vout,
-                address: bitcoin::Address::from_script(&utxo.txout.script_pubkey, network)?
-                    .to_string(),
-                amount: utxo.txout.value.to_sat(),
-                amount_btc: utxo.txout.value.to_btc(),
-                confirmations: 0, // Not needed for this use case
-                is_change: utxo.keychain == KeychainKind::Internal,
-                keychain: match utxo.keychain {
-                    KeychainKind::External => "external",
-                    KeychainKind::Internal => "internal",
-                }
-                .to_string(),
-                derivation_index: None,
-            });
-        }
-        utxos
-    } else if backend.starts_with("bitcoind://") {
-        let path_str = backend.strip_prefix("bitcoind://").unwrap();
-        let path = Path::new(path_str);
-        scan_and_list_utxos_bitcoind(descriptor, network, path).await?
-    } else {
-        bail!(
-            "Unsupported backend: {}. Expected electrum://, esplora://, or bitcoind://",
-            backend
-        )
-    };
+    // Create wallet and sync with backend
+    let mut wallet = backend.synced_wallet(descriptor, network).await?;
+    let utxos = wallet.utxos(network);
 
     // Parse inputs
     let mut input_specs = Vec::new();
@@ -850,11 +934,19 @@ pub async fn move_utxos_bdk(
         }
     }
 
-    // Apply max amount selection if specified
-    if let Some(max_amt) = max_amount {
-        // Sort by amount descending
-        selected_utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+    // Order (and for dust-below, narrow) the candidates by the selection policy
+    selection.apply(
+        &mut selected_utxos,
+        |utxo| utxo.amount,
+        |utxo| utxo.confirmations,
+    );
+    ensure!(
+        !selected_utxos.is_empty(),
+        "No UTXOs left to move after applying the {selection:?} selection"
+    );
 
+    // Apply max amount selection if specified, picking in policy order
+    if let Some(max_amt) = max_amount {
         let mut total = 0u64;
         let mut final_selection = Vec::new();
 
@@ -869,47 +961,102 @@ pub async fn move_utxos_bdk(
         selected_utxos = final_selection;
     }
 
-    // Build transaction
-    let mut tx_builder = wallet.build_tx();
-
-    // Add all selected inputs
-    for utxo in &selected_utxos {
-        let outpoint = OutPoint {
-            txid: Txid::from_str(&utxo.txid)?,
-            vout: utxo.vout,
-        };
-        tx_builder.add_utxo(outpoint)?;
-    }
-
-    // Calculate total input value
-    let total_input: u64 = selected_utxos.iter().map(|u| u.amount).sum();
-
-    // Determine fee
+    let outpoints = selected_utxos
+        .iter()
+        .map(|utxo| {
+            Ok(OutPoint {
+                txid: Txid::from_str(&utxo.txid)?,
+                vout: utxo.vout,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let total_sats: u64 = selected_utxos.iter().map(|utxo| utxo.amount).sum();
+
+    let scripts = split
+        .destinations()
+        .iter()
+        .map(|d| {
+            Ok(bitcoin::Address::from_str(&d.address)
+                .with_context(|| format!("Invalid address: {address}", address = d.address))?
+                .require_network(network)?
+                .script_pubkey())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let drain = split.drain_index();
+
+    // Determine fee. BDK sizes the inputs from the descriptor's satisfaction
+    // weight, so taproot key-path and script-path (e.g. multi_a) spends are
+    // estimated correctly. For a fee rate, a probe transaction with the same
+    // outputs (at the dust limit) gives the fee the split has to leave out
     let fee = if let Some(sats) = fee_sats {
-        sats
+        Amount::from_sat(sats)
     } else if let Some(rate) = fee_rate {
-        // Estimate fee based on transaction size
-        // Rough estimate: 10 + 41*inputs + 32*outputs
-        let estimated_vbytes = 10 + 41 * selected_utxos.len() + 32;
-        (estimated_vbytes as f64 * rate) as u64
+        let placeholders: Vec<_> = scripts
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != drain)
+            .map(|(_, script)| (script.clone(), script.minimal_non_dust()))
+            .collect();
+        let mut tx_builder = wallet.build_tx();
+        for outpoint in &outpoints {
+            tx_builder.add_utxo(*outpoint)?;
+        }
+        tx_builder.fee_rate(fee_rate_from_sat_per_vb(rate)?);
+        tx_builder.set_recipients(placeholders);
+        tx_builder.drain_to(scripts[drain].clone());
+        apply_tx_options(&mut tx_builder, options);
+        tx_builder.manually_selected_only();
+        tx_builder.finish()?.fee()?
     } else {
         bail!("Must specify either fee_rate or fee_sats");
     };
 
-    // Add single output (total - fee)
-    let output_amount = total_input.saturating_sub(fee);
-    ensure!(output_amount != 0, "Output amount would be zero after fees");
+    let available = total_sats.checked_sub(fee.to_sat()).ok_or_else(|| {
+        InsufficientFunds(format!(
+            "inputs={total_sats} sats, fee={fee} sats",
+            fee = fee.to_sat()
+        ))
+    })?;
+    let amounts = split.allocate(available)?;
 
-    let dest_script = bitcoin::Address::from_str(destination)?
-        .require_network(network)?
-        .script_pubkey();
-    tx_builder.add_recipient(dest_script, Amount::from_sat(output_amount));
+    // Build transaction
+    let mut tx_builder = wallet.build_tx();
+
+    // Add all selected inputs
+    for outpoint in &outpoints {
+        tx_builder.add_utxo(*outpoint)?;
+    }
+    tx_builder.fee_absolute(fee);
+
+    // The drain destination receives everything left after the other
+    // destinations and the fee
+    tx_builder.set_recipients(
+        scripts
+            .iter()
+            .zip(&amounts)
+            .enumerate()
+            .filter(|(index, _)| *index != drain)
+            .map(|(_, (script, amount))| (script.clone(), Amount::from_sat(*amount)))
+            .collect(),
+    );
+    tx_builder.drain_to(scripts[drain].clone());
+
+    apply_tx_options(&mut tx_builder, options);
 
     // Manually select UTXOs (disable coin selection)
     tx_builder.manually_selected_only();
 
-    // Finish building
-    let psbt = tx_builder.finish()?;
+    // Finish building; fails if the inputs can't cover the fee
+    let mut psbt = tx_builder.finish()?;
+    let fee = psbt.fee()?;
+    if let Some(limits) = &options.fee_limits {
+        limits.check_psbt(&psbt, fee.to_sat(), |_, _| true)?;
+    }
+    if let Some(order) = options.output_order {
+        order.apply(&mut psbt, &scripts);
+    }
+    // Record the change address revealed by this transaction
+    wallet.persist()?;
 
     // Serialize PSBT to base64
     let psbt_bytes = psbt.serialize();
@@ -917,11 +1064,126 @@ pub async fn move_utxos_bdk(
 
     Ok(BdkPsbtResponse {
         psbt: psbt_base64,
-        fee_sats: fee,
+        fee_sats: fee.to_sat(),
         change_position: None, // No change in consolidation
     })
 }
 
+/// Result of sweeping a wallet with [`sweep_wallet_bdk`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BdkSweepResponse {
+    /// Base64-encoded PSBT
+    pub psbt: String,
+    /// Fee amount in satoshis
+    pub fee_sats: u64,
+    /// Amount paid to the destination in satoshis
+    pub amount_sats: u64,
+    /// Number of UTXOs spent
+    pub swept_utxos: usize,
+    /// Number of UTXOs left in the wallet for being under the dust threshold
+    pub kept_utxos: usize,
+    /// Value of the UTXOs left in the wallet in satoshis
+    pub kept_sats: u64,
+}
+
+/// Sweep every UTXO of the wallet to `destination`, which receives the total
+/// minus the fee. With `keep_dust_threshold`, UTXOs under the threshold are
+/// left in the wallet rather than spent at a loss.
+#[allow(clippy::too_many_arguments)]
+pub async fn sweep_wallet_bdk(
+    destination: &str,
+    fee_rate: Option<f64>, // sat/vB
+    conf_target: Option<u32>,
+    keep_dust_threshold: Option<Amount>,
+    descriptor: &str,
+    network: Network,
+    backend: &dyn BlockchainBackend,
+    options: &PsbtOptions,
+) -> CoreResult<BdkSweepResponse> {
+    check_transaction_network([destination], Some(descriptor), network)?;
+    let script = bitcoin::Address::from_str(destination)
+        .with_context(|| format!("Invalid address: {destination}"))?
+        .require_network(network)?
+        .script_pubkey();
+
+    // Create wallet and sync with backend
+    let mut wallet = backend.synced_wallet(descriptor, network).await?;
+    let (swept, kept): (Vec<_>, Vec<_>) = wallet.utxos(network).into_iter().partition(|utxo| {
+        keep_dust_threshold.is_none_or(|threshold| utxo.amount >= threshold.to_sat())
+    });
+    ensure!(
+        !swept.is_empty(),
+        "No UTXOs to sweep: the wallet has {kept} UTXO(s), all under the dust threshold",
+        kept = kept.len()
+    );
+
+    // Build transaction
+    let mut tx_builder = wallet.build_tx();
+    if kept.is_empty() {
+        tx_builder.drain_wallet();
+    } else {
+        for utxo in &swept {
+            tx_builder.add_utxo(OutPoint {
+                txid: Txid::from_str(&utxo.txid)?,
+                vout: utxo.vout,
+            })?;
+        }
+        tx_builder.manually_selected_only();
+    }
+    // The destination receives everything left after the fee
+    tx_builder.drain_to(script.clone());
+
+    // Zero-value OP_RETURN data output
+    if let Some(script) = options.op_return_script()? {
+        tx_builder.add_recipient(script, Amount::ZERO);
+    }
+
+    // Set fee rate
+    if let Some(rate) = fee_rate {
+        tx_builder.fee_rate(fee_rate_from_sat_per_vb(rate)?);
+    } else if let Some(target) = conf_target {
+        let rate = backend.estimate_fee_rate(target).await?;
+        debug!(
+            "Estimated {rate} sat/vB for {target} blocks from {backend}",
+            backend = backend.name()
+        );
+        tx_builder.fee_rate(fee_rate_from_sat_per_vb(rate)?);
+    }
+
+    apply_tx_options(&mut tx_builder, options);
+
+    // Finish building; fails if the UTXOs can't cover the fee
+    let mut psbt = tx_builder.finish()?;
+    let fee = psbt.fee()?;
+    if let Some(limits) = &options.fee_limits {
+        limits.check_psbt(&psbt, fee.to_sat(), |_, _| true)?;
+    }
+    if let Some(order) = options.output_order {
+        order.apply(&mut psbt, std::slice::from_ref(&script));
+    }
+    let amount_sats = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|txout| txout.script_pubkey == script)
+        .map(|txout| txout.value.to_sat())
+        .sum();
+    wallet.persist()?;
+
+    // Serialize PSBT to base64
+    let psbt_bytes = psbt.serialize();
+    let psbt_base64 = base64::engine::general_purpose::STANDARD.encode(&psbt_bytes);
+
+    Ok(BdkSweepResponse {
+        psbt: psbt_base64,
+        fee_sats: fee.to_sat(),
+        amount_sats,
+        swept_utxos: psbt.unsigned_tx.input.len(),
+        kept_utxos: kept.len(),
+        kept_sats: kept.iter().map(|utxo| utxo.amount).sum(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -940,4 +1202,167 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_fee_rate_from_sat_per_vb() -> Result<()> {
+        assert_eq!(
+            fee_rate_from_sat_per_vb(10.0)?,
+            FeeRate::from_sat_per_vb_unchecked(10)
+        );
+        // Fractional estimates round up instead of truncating
+        assert_eq!(fee_rate_from_sat_per_vb(8.5)?.to_sat_per_kwu(), 2125);
+        assert!(fee_rate_from_sat_per_vb(-1.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_options_shared_by() {
+        let scan = ScanOptions::default();
+        assert_eq!(scan.shared_by(1).parallel_requests, 10);
+        assert_eq!(scan.shared_by(2).parallel_requests, 5);
+        // Every scan keeps at least one request in flight
+        let single = ScanOptions {
+            parallel_requests: 1,
+            ..ScanOptions::default()
+        };
+        assert_eq!(single.shared_by(2).parallel_requests, 1);
+        assert_eq!(single.shared_by(0).parallel_requests, 1);
+    }
+
+    #[test]
+    fn test_scan_descriptors_parallel_is_bounded() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let xpub = "xpub6BosfCnifzxcJJ1wYuntGJfF2zPJkDeG9ELNHcKNjezuea4tumswN9sH1psMdSVqCMoJC21Bv8usSeqSP4Sp1tLzW7aY59fGn9GCYzx5UTo";
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let mut utxos = Vec::new();
+        let scan = |desc: &str| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            let path: u64 = desc
+                .rsplit('/')
+                .nth(1)
+                .and_then(|step| step.parse().ok())
+                .context("Expanded descriptor has no path step")?;
+            Ok(vec![BdkUtxo {
+                txid: "a".repeat(64),
+                vout: 0,
+                address: String::new(),
+                amount: path * 1_000,
+                amount_btc: 0.0,
+                confirmations: 1,
+                is_change: false,
+                keychain: "external".to_string(),
+                derivation_index: Some(0),
+            }])
+        };
+        scan_descriptors_parallel(&format!("wpkh({xpub}/<0;1;2;3;4;5>/*)"), scan, |utxo| {
+            utxos.push(utxo);
+            Ok(())
+        })?;
+
+        assert!(peak.load(Ordering::SeqCst) <= MAX_PARALLEL_DESCRIPTOR_SCANS);
+        // Each path's UTXOs arrive when its scan completes, in any order
+        utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+        let amounts: Vec<u64> = utxos.iter().map(|utxo| utxo.amount).collect();
+        assert_eq!(amounts, [5_000, 4_000, 3_000, 2_000, 1_000, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_esplora_sync_enforces_pin() -> Result<()> {
+        let pin = crate::CertFingerprint::of(b"umbrel");
+        let options = ClientOptions {
+            cert_pins: crate::CertPins::new([
+                format!("http://umbrel.local:3002={pin}").parse()?,
+                format!("https://start9.local/api={pin}").parse()?,
+            ])?,
+            ..Default::default()
+        };
+        // A pin is enforced by the relay, so a plaintext URL is refused rather than ignored
+        assert!(esplora_blocking_client("http://umbrel.local:3002", &options).is_err());
+        esplora_blocking_client("https://start9.local/api", &options)?;
+        esplora_blocking_client("https://blockstream.info/api", &options)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_taproot_multipath_descriptor() -> Result<()> {
+        // Key-path key plus a 2-of-2 multi_a script-path leaf
+        let internal = "[c258d2e4/84h/1h/0h]tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE";
+        let cosigner1 = "[73c5da0a/86h/1h/0h]tpubDDfvzhdVV4unsoKt5aE6dcsNsfeWbTgmLZPi8LQDYU2xixrYemMfWJ3BaVneH3u7DBQePdTwhpybaKRU95pi6PMUtLPBJLVQRpzEnjfjZzX";
+        let cosigner2 = "[73c5da0a/86h/1h/1h]tpubDDfvzhdVV4unxTr3yqNZkM2u5c8u9ecmsdWK6bFzZ4hE2tbjhSjhDa1dzsVymqrr19pZ43YK9c5CQwp7fcTNwHZg59Yt6Mdsu5myM4oUX42";
+        let multipath =
+            format!("tr({internal}/<0;1>/*,multi_a(2,{cosigner1}/<0;1>/*,{cosigner2}/<0;1>/*))");
+        let checksum = crate::descriptor::descriptor_checksum(&multipath)?;
+
+        // The checksum only matches the multipath form, so it is dropped
+        let expanded = expand_multipath_descriptor(&format!("{multipath}#{checksum}"))?;
+        assert_eq!(
+            expanded,
+            vec![
+                multipath.replace("<0;1>", "0"),
+                multipath.replace("<0;1>", "1")
+            ]
+        );
+
+        let mut wallet =
+            ScanWallet::open(&format!("{multipath}#{checksum}"), Network::Testnet, None)?;
+        let receive = wallet.reveal_next_address(KeychainKind::External).address;
+        let change = wallet.reveal_next_address(KeychainKind::Internal).address;
+        assert!(receive.to_string().starts_with("tb1p"));
+        assert!(change.to_string().starts_with("tb1p"));
+        assert_ne!(receive, change);
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_position() -> Result<()> {
+        let descriptor = "wpkh([c258d2e4/84h/1h/0h]tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE/<0;1>/*)";
+        let mut wallet = ScanWallet::open(descriptor, Network::Testnet, None)?;
+        let receive = wallet
+            .reveal_next_address(KeychainKind::External)
+            .script_pubkey();
+        let change = wallet
+            .reveal_next_address(KeychainKind::Internal)
+            .script_pubkey();
+        let external = bitcoin::Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")?
+            .assume_checked()
+            .script_pubkey();
+
+        let psbt = |scripts: &[&ScriptBuf]| -> Result<Psbt> {
+            Ok(Psbt::from_unsigned_tx(bitcoin::Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![],
+                output: scripts
+                    .iter()
+                    .map(|script| bitcoin::TxOut {
+                        value: Amount::from_sat(10_000),
+                        script_pubkey: (*script).clone(),
+                    })
+                    .collect(),
+            })?)
+        };
+
+        let recipients = [external.clone()];
+        assert_eq!(
+            change_position(&wallet, &psbt(&[&external, &change])?, &recipients),
+            Some(1)
+        );
+        assert_eq!(
+            change_position(&wallet, &psbt(&[&external])?, &recipients),
+            None
+        );
+        // Paying one of the wallet's own addresses is not change
+        let recipients = [receive.clone()];
+        assert_eq!(
+            change_position(&wallet, &psbt(&[&change, &receive])?, &recipients),
+            Some(0)
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,277 @@
+//! BitBox02 hardware wallet integration
+//!
+//! The BitBox02 talks over an encrypted noise channel. The first connection
+//! from a host shows a pairing code on both the device and stderr, which the
+//! user confirms on the device.
+
+use anyhow::{Context, Result};
+use bitbox_api::{Keypath, PairedBitBox, pb, runtime::TokioRuntime};
+use bitcoin::Network;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpub};
+use bitcoin::psbt::Psbt;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::error::{CoreResult, bail};
+use crate::hardware_wallet::{DeviceInfo, SignedPsbt};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BitboxAddressOutput {
+    pub address: String,
+    pub derivation_path: String,
+    pub xpub: String,
+    pub network: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BitboxXpubOutput {
+    pub xpub: String,
+    pub derivation_path: String,
+    pub master_fingerprint: String,
+    pub network: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BitboxSignOutput {
+    pub psbt_base64: String,
+    pub psbt_hex: String,
+    pub is_complete: bool,
+}
+
+/// BitBox02 hardware wallet implementation
+pub struct BitboxWallet {
+    device: PairedBitBox<TokioRuntime>,
+}
+
+impl BitboxWallet {
+    /// Connect to the first available BitBox02, unlock it and pair with this host
+    pub async fn connect() -> CoreResult<Self> {
+        let hid_device = bitbox_api::usb::get_any_bitbox02().context(
+            "Failed to find BitBox02 device. Make sure your BitBox02 is connected via USB.",
+        )?;
+        let noise_config = Box::new(bitbox_api::NoiseConfigNoCache {});
+        let bitbox = bitbox_api::BitBox::<TokioRuntime>::from_hid_device(hid_device, noise_config)
+            .await
+            .context("Failed to open BitBox02 device")?;
+        let pairing = bitbox
+            .unlock_and_pair()
+            .await
+            .context("Failed to unlock BitBox02. Please enter the password on the device.")?;
+        if let Some(pairing_code) = pairing.get_pairing_code() {
+            eprintln!("BitBox02 pairing code (confirm it on the device):\n{pairing_code}");
+        }
+        let device = pairing
+            .wait_confirm()
+            .await
+            .context("BitBox02 pairing was not confirmed")?;
+
+        Ok(Self { device })
+    }
+
+    /// Get device information
+    pub async fn get_device_info(&self) -> CoreResult<DeviceInfo> {
+        let info = self
+            .device
+            .device_info()
+            .await
+            .context("Failed to get BitBox02 device info")?;
+        let fingerprint = self.master_fingerprint().await?;
+
+        Ok(DeviceInfo {
+            device_type: "BitBox02".to_string(),
+            version: info.version,
+            initialized: info.initialized,
+            fingerprint: Some(fingerprint.to_string()),
+        })
+    }
+
+    /// Master key fingerprint
+    pub async fn master_fingerprint(&self) -> CoreResult<bitcoin::bip32::Fingerprint> {
+        let fingerprint = self
+            .device
+            .root_fingerprint()
+            .await
+            .context("Failed to get BitBox02 root fingerprint")?;
+        Ok(bitcoin::bip32::Fingerprint::from_str(&fingerprint)
+            .context("Invalid root fingerprint from BitBox02")?)
+    }
+
+    /// Get the extended public key at `path`
+    pub async fn get_xpub(&self, path: &str, network: Network) -> CoreResult<Xpub> {
+        let keypath = keypath(path)?;
+        let xpub_type = if network == Network::Bitcoin {
+            pb::btc_pub_request::XPubType::Xpub
+        } else {
+            pb::btc_pub_request::XPubType::Tpub
+        };
+        let xpub = self
+            .device
+            .btc_xpub(coin(network), &keypath, xpub_type, false)
+            .await
+            .with_context(|| format!("Failed to get xpub at path: {path}"))?;
+        Ok(Xpub::from_str(&xpub).context("Failed to parse xpub from BitBox02")?)
+    }
+
+    /// Get the address at `path`, showing it on the device for confirmation.
+    /// The script type follows the path purpose: 49', 84' or 86'.
+    pub async fn get_address(&self, path: &str, network: Network) -> CoreResult<String> {
+        let derivation_path = DerivationPath::from_str(path)
+            .with_context(|| format!("Invalid derivation path: {path}"))?;
+        let script_type = determine_script_type(&derivation_path)?;
+        let script_config = bitbox_api::btc::make_script_config_simple(script_type);
+        Ok(self
+            .device
+            .btc_address(coin(network), &keypath(path)?, &script_config, true)
+            .await
+            .with_context(|| format!("Failed to get address at path: {path}"))?)
+    }
+
+    /// Sign `message` with the key at `path` after confirmation on the device,
+    /// returning the 65-byte recoverable signature.
+    /// The BitBox02 only signs messages for m/49'/ and m/84'/ paths.
+    pub async fn sign_message(
+        &self,
+        path: &str,
+        message: &str,
+        network: Network,
+    ) -> CoreResult<Vec<u8>> {
+        let derivation_path = DerivationPath::from_str(path)
+            .with_context(|| format!("Invalid derivation path: {path}"))?;
+        let script_type = match determine_script_type(&derivation_path)? {
+            pb::btc_script_config::SimpleType::P2tr => {
+                bail!("BitBox02 cannot sign messages for taproot paths; use BIP322 instead")
+            }
+            script_type => script_type,
+        };
+        let script_config = pb::BtcScriptConfigWithKeypath {
+            script_config: Some(bitbox_api::btc::make_script_config_simple(script_type)),
+            keypath: keypath(path)?.to_vec(),
+        };
+        let signature = self
+            .device
+            .btc_sign_message(coin(network), script_config, message.as_bytes())
+            .await
+            .context("Failed to sign message with BitBox02")?;
+        Ok(signature.electrum_sig65)
+    }
+
+    /// Sign a PSBT (Partially Signed Bitcoin Transaction)
+    pub async fn sign_psbt(&self, psbt_bytes: &[u8], network: Network) -> CoreResult<SignedPsbt> {
+        use base64::Engine;
+
+        let mut psbt = Psbt::deserialize(psbt_bytes).context("Failed to deserialize PSBT")?;
+        self.device
+            .btc_sign_psbt(
+                coin(network),
+                &mut psbt,
+                None,
+                pb::btc_sign_init_request::FormatUnit::Default,
+            )
+            .await
+            .context("Failed to sign PSBT with BitBox02")?;
+
+        let signed_psbt_bytes = psbt.serialize();
+        let psbt_base64 = base64::engine::general_purpose::STANDARD.encode(&signed_psbt_bytes);
+
+        Ok(SignedPsbt {
+            psbt: signed_psbt_bytes,
+            psbt_base64,
+            is_complete: false, // BitBox02 adds signatures but does not finalize
+        })
+    }
+}
+
+fn keypath(path: &str) -> Result<Keypath> {
+    Keypath::try_from(path).map_err(|_| anyhow::anyhow!("Invalid derivation path: {path}"))
+}
+
+fn coin(network: Network) -> pb::BtcCoin {
+    match network {
+        Network::Bitcoin => pb::BtcCoin::Btc,
+        Network::Regtest => pb::BtcCoin::Rbtc,
+        _ => pb::BtcCoin::Tbtc,
+    }
+}
+
+/// Determine script type from the derivation path purpose
+fn determine_script_type(path: &DerivationPath) -> Result<pb::btc_script_config::SimpleType> {
+    match path.into_iter().next() {
+        Some(ChildNumber::Hardened { index: 49 }) => {
+            Ok(pb::btc_script_config::SimpleType::P2wpkhP2sh)
+        }
+        Some(ChildNumber::Hardened { index: 84 }) => Ok(pb::btc_script_config::SimpleType::P2wpkh),
+        Some(ChildNumber::Hardened { index: 86 }) => Ok(pb::btc_script_config::SimpleType::P2tr),
+        _ => bail!("BitBox02 supports m/49'/..., m/84'/... and m/86'/... address paths"),
+    }
+}
+
+/// Generate a Bitcoin address from BitBox02
+pub async fn generate_bitbox_address(
+    path: &str,
+    network: Network,
+) -> CoreResult<BitboxAddressOutput> {
+    let wallet = BitboxWallet::connect().await?;
+    let address = wallet.get_address(path, network).await?;
+
+    let xpub = wallet.get_xpub(path, network).await?;
+
+    Ok(BitboxAddressOutput {
+        address,
+        derivation_path: path.to_string(),
+        xpub: xpub.to_string(),
+        network: network.to_string(),
+    })
+}
+
+/// Get extended public key and master fingerprint from BitBox02
+pub async fn generate_bitbox_xpub(path: &str, network: Network) -> CoreResult<BitboxXpubOutput> {
+    let wallet = BitboxWallet::connect().await?;
+    let xpub = wallet.get_xpub(path, network).await?;
+    let master_fingerprint = wallet.master_fingerprint().await?;
+
+    Ok(BitboxXpubOutput {
+        xpub: xpub.to_string(),
+        derivation_path: path.to_string(),
+        master_fingerprint: master_fingerprint.to_string(),
+        network: network.to_string(),
+    })
+}
+
+/// Sign a PSBT with BitBox02
+pub async fn sign_psbt_with_bitbox(
+    psbt_data: &[u8],
+    network: Network,
+) -> CoreResult<BitboxSignOutput> {
+    let wallet = BitboxWallet::connect().await?;
+    let signed = wallet.sign_psbt(psbt_data, network).await?;
+
+    Ok(BitboxSignOutput {
+        psbt_base64: signed.psbt_base64,
+        psbt_hex: hex::encode(&signed.psbt),
+        is_complete: signed.is_complete,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_determine_script_type() -> Result<()> {
+        let script_type = |path: &str| determine_script_type(&DerivationPath::from_str(path)?);
+        assert_eq!(
+            script_type("m/84'/0'/0'/0/0")?,
+            pb::btc_script_config::SimpleType::P2wpkh
+        );
+        assert_eq!(
+            script_type("m/49'/1'/0'/0/0")?,
+            pb::btc_script_config::SimpleType::P2wpkhP2sh
+        );
+        assert_eq!(
+            script_type("m/86'/0'/0'/1/3")?,
+            pb::btc_script_config::SimpleType::P2tr
+        );
+        assert!(script_type("m/44'/0'/0'/0/0").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,692 @@
+//! Compact block filter (BIP157/158) light client
+//!
+//! Connects to a Bitcoin P2P node that serves compact block filters (Bitcoin
+//! Core with `-blockfilterindex=1 -peerblockfilters=1`), syncs and checks the
+//! header chain, and matches the wallet's scripts against each block's filter
+//! locally. Only matching blocks are downloaded, so no Electrum/Esplora server
+//! or node wallet is involved and the peer does not learn which scripts are
+//! being watched.
+//!
+//! Headers are checked for proof of work and, on networks that enforce it,
+//! difficulty retargeting; blocks are checked against their header's merkle
+//! root. A peer can withhold matches but cannot forge transactions, so a node
+//! you run is the best peer. Unconfirmed transactions are not seen.
+
+use anyhow::{Context, Result, anyhow};
+use bdk_wallet::chain::{BlockId, ConfirmationBlockTime, TxUpdate};
+use bdk_wallet::{KeychainKind, Update, Wallet};
+use bitcoin::bip158::BlockFilter;
+use bitcoin::block::Header;
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::address::Address;
+use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::p2p::message_blockdata::{GetHeadersMessage, Inventory};
+use bitcoin::p2p::message_filter::GetCFilters;
+use bitcoin::p2p::message_network::VersionMessage;
+use bitcoin::p2p::{Magic, ServiceFlags};
+use bitcoin::params::Params;
+use bitcoin::pow::CompactTarget;
+use bitcoin::{Block, BlockHash, Network, OutPoint, ScriptBuf, Transaction, Work};
+use std::collections::{BTreeMap, HashSet, btree_map};
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+use crate::bdk_wallet::{ScanOptions, ScanWallet};
+use crate::chain_cache::ChainCache;
+use crate::error::{bail, ensure};
+use crate::proxy::{NetworkProxy, connect_tcp};
+
+const USER_AGENT: &str = concat!("/cyberkrill:", env!("CARGO_PKG_VERSION"), "/");
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest message accepted; blocks are at most 4 MB serialized
+const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+const MAX_FILTERS_PER_REQUEST: u32 = 1000;
+const BASIC_FILTER_TYPE: u8 = 0;
+
+/// Default P2P port of `network`
+pub fn default_p2p_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8333,
+        Network::Testnet => 18333,
+        Network::Testnet4 => 48333,
+        Network::Signet => 38333,
+        Network::Regtest => 18444,
+    }
+}
+
+/// Best header chain known to the client, from genesis
+pub(crate) struct HeaderChain {
+    network: Network,
+    headers: Vec<Header>,
+    hashes: Vec<BlockHash>,
+}
+
+impl HeaderChain {
+    pub(crate) fn new(network: Network) -> Self {
+        let genesis = bitcoin::constants::genesis_block(network).header;
+        Self {
+            network,
+            headers: vec![genesis],
+            hashes: vec![genesis.block_hash()],
+        }
+    }
+
+    /// The chain stored in `cache`, or just genesis
+    pub(crate) fn load(network: Network, cache: Option<&ChainCache>) -> Self {
+        let mut chain = Self::new(network);
+        let Some(cache) = cache else {
+            return chain;
+        };
+        let cached = cache.header_chain(network);
+        // Cached headers are validated like any others
+        if let Err(e) = chain.connect(&cached) {
+            warn!("Ignoring cached header chain: {e:#}");
+            chain = Self::new(network);
+        }
+        chain
+    }
+
+    /// Store the chain in `cache`, if any
+    pub(crate) fn save(&self, cache: Option<&ChainCache>) {
+        if let Some(cache) = cache {
+            cache.store_header_chain(self.network, &self.headers[1..]);
+        }
+    }
+
+    pub(crate) fn tip_height(&self) -> u32 {
+        (self.headers.len() - 1) as u32
+    }
+
+    pub(crate) fn tip(&self) -> BlockId {
+        BlockId {
+            height: self.tip_height(),
+            hash: self.hashes[self.hashes.len() - 1],
+        }
+    }
+
+    pub(crate) fn hash_at(&self, height: u32) -> Option<BlockHash> {
+        self.hashes.get(height as usize).copied()
+    }
+
+    fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        self.hashes
+            .iter()
+            .rposition(|known| known == hash)
+            .map(|height| height as u32)
+    }
+
+    /// Hashes to start a `getheaders` from: the last ten blocks, then
+    /// exponentially sparser back to genesis
+    fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut height = self.hashes.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(self.hashes[height]);
+            if height == 0 {
+                return locator;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+    }
+
+    /// Add `headers`, which must connect to the chain, returning how many were
+    /// added. A fork replaces the blocks after the fork point only if it has
+    /// more work.
+    pub(crate) fn connect(&mut self, headers: &[Header]) -> Result<usize> {
+        let Some(first) = headers.first() else {
+            return Ok(0);
+        };
+        let fork_height = self
+            .height_of(&first.prev_blockhash)
+            .context("Headers do not connect to the known chain")?;
+        let keep = fork_height as usize + 1;
+        let replaced_headers = self.headers.split_off(keep);
+        let replaced_hashes = self.hashes.split_off(keep);
+
+        let result = headers.iter().try_for_each(|header| self.push(header));
+        let work = |headers: &[Header]| {
+            headers
+                .iter()
+                .fold(Work::from_be_bytes([0; 32]), |work, header| {
+                    work + header.work()
+                })
+        };
+        let more_work = work(&self.headers[keep..]) > work(&replaced_headers);
+        if result.is_err() || !more_work {
+            self.headers.truncate(keep);
+            self.hashes.truncate(keep);
+            self.headers.extend(replaced_headers);
+            self.hashes.extend(replaced_hashes);
+            result?;
+            return Ok(0);
+        }
+        if !replaced_headers.is_empty() {
+            warn!(
+                "Reorg: replaced {count} block(s) after height {fork_height}",
+                count = replaced_headers.len()
+            );
+        }
+        Ok(headers.len())
+    }
+
+    /// Append `header` after checking it extends the tip with valid proof of work
+    fn push(&mut self, header: &Header) -> Result<()> {
+        let height = self.headers.len();
+        let prev = self.headers[height - 1];
+        ensure!(
+            header.prev_blockhash == self.hashes[height - 1],
+            "Header {height} does not connect to the previous header"
+        );
+
+        let params = Params::new(self.network);
+        ensure!(
+            header.target() <= params.max_attainable_target,
+            "Header {height} is below the minimum difficulty"
+        );
+        // Testnets and regtest allow minimum-difficulty blocks at any time
+        if !params.allow_min_difficulty_blocks {
+            let interval = params.difficulty_adjustment_interval() as usize;
+            let expected = if height.is_multiple_of(interval) {
+                CompactTarget::from_header_difficulty_adjustment(
+                    self.headers[height - interval],
+                    prev,
+                    &params,
+                )
+            } else {
+                prev.bits
+            };
+            ensure!(
+                header.bits == expected,
+                "Header {height} has unexpected difficulty bits"
+            );
+        }
+        let hash = header
+            .validate_pow(header.target())
+            .with_context(|| format!("Header {height} has invalid proof of work"))?;
+
+        self.headers.push(*header);
+        self.hashes.push(hash);
+        Ok(())
+    }
+}
+
+/// P2P connection to a node serving compact block filters
+pub(crate) struct Peer {
+    address: String,
+    magic: Magic,
+    stream: BufReader<TcpStream>,
+}
+
+impl Peer {
+    /// Connect to the first reachable peer in `peers` (`host[:port]`), through
+    /// `proxy` if given
+    pub(crate) async fn connect_any(
+        peers: &[String],
+        network: Network,
+        proxy: Option<&NetworkProxy>,
+    ) -> Result<Self> {
+        let mut failures = Vec::new();
+        for peer in peers {
+            let address = with_default_port(peer, network);
+            match Self::connect(&address, network, proxy).await {
+                Ok(peer) => return Ok(peer),
+                Err(e) => {
+                    warn!("Peer {address} failed: {e:#}");
+                    failures.push(format!("{address}: {e:#}"));
+                }
+            }
+        }
+        bail!(
+            "No compact block filter peer could be used:\n{failures}",
+            failures = failures.join("\n")
+        )
+    }
+
+    async fn connect(
+        address: &str,
+        network: Network,
+        proxy: Option<&NetworkProxy>,
+    ) -> Result<Self> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, connect_tcp(address, proxy))
+            .await
+            .with_context(|| format!("Timed out connecting to {address}"))??;
+        let mut peer = Self {
+            address: address.to_string(),
+            magic: network.magic(),
+            stream: BufReader::new(stream),
+        };
+        peer.handshake()
+            .await
+            .with_context(|| format!("P2P handshake with {address} failed"))?;
+        debug!("Connected to {address}");
+        Ok(peer)
+    }
+
+    async fn handshake(&mut self) -> Result<()> {
+        let unspecified = Address::new(&SocketAddr::from(([0, 0, 0, 0], 0)), ServiceFlags::NONE);
+        // `relay` stays false: no transaction announcements are wanted
+        let version = VersionMessage::new(
+            ServiceFlags::NONE,
+            chrono::Utc::now().timestamp(),
+            unspecified.clone(),
+            unspecified,
+            rand::random(),
+            USER_AGENT.to_string(),
+            0,
+        );
+        self.send(NetworkMessage::Version(version)).await?;
+
+        let peer_version = self
+            .expect(|message| match message {
+                NetworkMessage::Version(version) => Some(version),
+                _ => None,
+            })
+            .await?;
+        ensure!(
+            peer_version.services.has(ServiceFlags::COMPACT_FILTERS),
+            "Peer does not serve compact block filters (Bitcoin Core needs \
+             -blockfilterindex=1 -peerblockfilters=1)"
+        );
+        self.send(NetworkMessage::Verack).await?;
+        self.expect(|message| matches!(message, NetworkMessage::Verack).then_some(()))
+            .await
+    }
+
+    /// Extend `chain` with the peer's headers until it has nothing newer
+    pub(crate) async fn sync_headers(&mut self, chain: &mut HeaderChain) -> Result<()> {
+        loop {
+            let request = GetHeadersMessage::new(chain.locator(), BlockHash::all_zeros());
+            self.send(NetworkMessage::GetHeaders(request)).await?;
+            let headers = self
+                .expect(|message| match message {
+                    NetworkMessage::Headers(headers) => Some(headers),
+                    _ => None,
+                })
+                .await?;
+            let added = chain.connect(&headers).with_context(|| {
+                format!(
+                    "{address} sent an invalid header chain",
+                    address = self.address
+                )
+            })?;
+            if added > 0 {
+                info!(
+                    "Synced headers to height {height}",
+                    height = chain.tip_height()
+                );
+            }
+            if added == 0 || headers.len() < MAX_HEADERS_PER_MESSAGE {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Heights in `range` whose basic filter matches any of `scripts`
+    pub(crate) async fn matching_heights(
+        &mut self,
+        chain: &HeaderChain,
+        range: RangeInclusive<u32>,
+        scripts: &[ScriptBuf],
+    ) -> Result<Vec<u32>> {
+        let mut matches = Vec::new();
+        let (mut start, end) = range.into_inner();
+        while start <= end {
+            let stop = end.min(start.saturating_add(MAX_FILTERS_PER_REQUEST - 1));
+            let stop_hash = chain
+                .hash_at(stop)
+                .with_context(|| format!("No header at height {stop}"))?;
+            self.send(NetworkMessage::GetCFilters(GetCFilters {
+                filter_type: BASIC_FILTER_TYPE,
+                start_height: start,
+                stop_hash,
+            }))
+            .await?;
+
+            for height in start..=stop {
+                let block_hash = chain
+                    .hash_at(height)
+                    .with_context(|| format!("No header at height {height}"))?;
+                let cfilter = self
+                    .expect(|message| match message {
+                        NetworkMessage::CFilter(cfilter) => Some(cfilter),
+                        _ => None,
+                    })
+                    .await?;
+                ensure!(
+                    cfilter.filter_type == BASIC_FILTER_TYPE && cfilter.block_hash == block_hash,
+                    "{address} sent a filter for an unexpected block",
+                    address = self.address
+                );
+                let matched = BlockFilter::new(&cfilter.filter)
+                    .match_any(&block_hash, scripts.iter().map(|script| script.as_bytes()))
+                    .with_context(|| format!("Invalid filter for block {height}"))?;
+                if matched {
+                    debug!("Filter match in block {height}");
+                    matches.push(height);
+                }
+            }
+            info!("Scanned filters up to height {stop}");
+            start = stop + 1;
+        }
+        Ok(matches)
+    }
+
+    /// Download block `hash`, checking its transactions against the header
+    pub(crate) async fn block(&mut self, hash: BlockHash) -> Result<Block> {
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(hash)]))
+            .await?;
+        let address = self.address.clone();
+        let block = self
+            .expect(|message| match message {
+                NetworkMessage::Block(block) if block.block_hash() == hash => Some(Ok(block)),
+                NetworkMessage::NotFound(_) => {
+                    Some(Err(anyhow!("{address} does not have block {hash}")))
+                }
+                _ => None,
+            })
+            .await??;
+        ensure!(
+            block.check_merkle_root() && block.check_witness_commitment(),
+            "Block {hash} does not match its header"
+        );
+        Ok(block)
+    }
+
+    /// Relay `tx` to the peer. There is no acceptance reply in the P2P
+    /// protocol; a ping round trip only makes sure it was processed.
+    pub(crate) async fn broadcast(&mut self, tx: &Transaction) -> Result<()> {
+        self.send(NetworkMessage::Tx(tx.clone())).await?;
+        let nonce = rand::random();
+        self.send(NetworkMessage::Ping(nonce)).await?;
+        self.expect(|message| {
+            matches!(message, NetworkMessage::Pong(n) if n == nonce).then_some(())
+        })
+        .await
+    }
+
+    async fn send(&mut self, message: NetworkMessage) -> Result<()> {
+        let raw = serialize(&RawNetworkMessage::new(self.magic, message));
+        self.stream
+            .get_mut()
+            .write_all(&raw)
+            .await
+            .with_context(|| format!("Failed to send to {address}", address = self.address))
+    }
+
+    /// Wait for the first message `pick` accepts, answering pings and
+    /// skipping everything else
+    async fn expect<T>(&mut self, mut pick: impl FnMut(NetworkMessage) -> Option<T>) -> Result<T> {
+        loop {
+            let message = tokio::time::timeout(MESSAGE_TIMEOUT, self.receive())
+                .await
+                .with_context(|| {
+                    format!("Timed out waiting for {address}", address = self.address)
+                })??;
+            if let NetworkMessage::Ping(nonce) = message {
+                self.send(NetworkMessage::Pong(nonce)).await?;
+                continue;
+            }
+            if let Some(value) = pick(message) {
+                return Ok(value);
+            }
+        }
+    }
+
+    async fn receive(&mut self) -> Result<NetworkMessage> {
+        // magic (4), command (12), payload length (4), checksum (4)
+        let mut raw = vec![0u8; 24];
+        self.stream.read_exact(&mut raw).await?;
+        ensure!(
+            raw[..4] == self.magic.to_bytes(),
+            "{address} is on a different network",
+            address = self.address
+        );
+        let length = u32::from_le_bytes([raw[16], raw[17], raw[18], raw[19]]) as usize;
+        ensure!(
+            length <= MAX_MESSAGE_SIZE,
+            "{address} sent a {length} byte message",
+            address = self.address
+        );
+        raw.resize(24 + length, 0);
+        self.stream.read_exact(&mut raw[24..]).await?;
+        let message: RawNetworkMessage = deserialize(&raw)
+            .with_context(|| format!("Invalid message from {address}", address = self.address))?;
+        Ok(message.into_payload())
+    }
+}
+
+/// `peer` with the network's default port appended when it has none
+fn with_default_port(peer: &str, network: Network) -> String {
+    let has_port = peer
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok());
+    if has_port {
+        peer.to_string()
+    } else {
+        format!("{peer}:{port}", port = default_p2p_port(network))
+    }
+}
+
+/// Bring `wallet` up to date from compact block filters.
+///
+/// Scanning starts at `birthday`, or after the newest wallet checkpoint still on
+/// the best chain for a wallet loaded from `--wallet-db`. Like the Electrum and
+/// Esplora scans, each keychain is watched up to the `stop_gap` of `scan`
+/// scripts past its last used index, and the scan repeats for scripts revealed
+/// by new activity.
+pub(crate) async fn sync_wallet(
+    wallet: &mut ScanWallet,
+    peers: &[String],
+    network: Network,
+    birthday: u32,
+    scan: &ScanOptions,
+) -> Result<()> {
+    let stop_gap = scan.stop_gap;
+    let cache = scan.client.chain_cache.as_deref();
+    let mut peer = Peer::connect_any(peers, network, scan.client.proxy.as_ref()).await?;
+    let mut chain = HeaderChain::load(network, cache);
+    peer.sync_headers(&mut chain).await?;
+    chain.save(cache);
+
+    let start = wallet
+        .latest_checkpoint()
+        .iter()
+        .find(|checkpoint| chain.hash_at(checkpoint.height()) == Some(checkpoint.hash()))
+        .map_or(0, |checkpoint| checkpoint.height() + 1)
+        .max(birthday);
+    let tip = chain.tip_height();
+
+    let keychains: Vec<KeychainKind> = wallet.keychains().map(|(keychain, _)| keychain).collect();
+    let mut next_index: BTreeMap<KeychainKind, u32> = BTreeMap::new();
+    let mut blocks: BTreeMap<u32, Block> = BTreeMap::new();
+    loop {
+        let mut scripts = Vec::new();
+        for keychain in &keychains {
+            let target = wallet
+                .spk_index()
+                .last_used_index(*keychain)
+                .map_or(stop_gap.saturating_sub(1), |index| {
+                    index.saturating_add(stop_gap)
+                });
+            let next = next_index.get(keychain).copied().unwrap_or(0);
+            if target < next {
+                continue;
+            }
+            wallet.reveal_addresses_to(*keychain, target).for_each(drop);
+            scripts.extend(
+                (next..=target).map(|index| wallet.peek_address(*keychain, index).script_pubkey()),
+            );
+            next_index.insert(*keychain, target + 1);
+        }
+        if scripts.is_empty() {
+            return Ok(());
+        }
+
+        if start <= tip {
+            debug!(
+                "Matching {count} scripts against filters {start}..={tip}",
+                count = scripts.len()
+            );
+            for height in peer.matching_heights(&chain, start..=tip, &scripts).await? {
+                if let btree_map::Entry::Vacant(entry) = blocks.entry(height) {
+                    let hash = chain
+                        .hash_at(height)
+                        .with_context(|| format!("No header at height {height}"))?;
+                    entry.insert(peer.block(hash).await?);
+                }
+            }
+        }
+        let update = wallet_update(wallet, &chain, &blocks);
+        wallet.apply_update(update)?;
+    }
+}
+
+/// Wallet update with the transactions in `blocks` that pay to or spend from
+/// the wallet, anchored in their blocks, and the chain tip
+fn wallet_update(wallet: &Wallet, chain: &HeaderChain, blocks: &BTreeMap<u32, Block>) -> Update {
+    let mut ours: HashSet<OutPoint> = wallet.list_output().map(|output| output.outpoint).collect();
+    let mut tx_update = TxUpdate::<ConfirmationBlockTime>::default();
+    let mut checkpoint = wallet.latest_checkpoint();
+    for (height, block) in blocks {
+        let anchor = ConfirmationBlockTime {
+            block_id: BlockId {
+                height: *height,
+                hash: block.block_hash(),
+            },
+            confirmation_time: u64::from(block.header.time),
+        };
+        let mut relevant = false;
+        for tx in &block.txdata {
+            let txid = tx.compute_txid();
+            let spends = tx
+                .input
+                .iter()
+                .any(|input| ours.contains(&input.previous_output));
+            let mut receives = false;
+            for (vout, output) in tx.output.iter().enumerate() {
+                if wallet.is_mine(output.script_pubkey.clone()) {
+                    receives = true;
+                    ours.insert(OutPoint::new(txid, vout as u32));
+                }
+            }
+            if spends || receives {
+                tx_update.txs.push(Arc::new(tx.clone()));
+                tx_update.anchors.insert((anchor, txid));
+                relevant = true;
+            }
+        }
+        if relevant {
+            checkpoint = checkpoint.insert(anchor.block_id);
+        }
+    }
+
+    Update {
+        tx_update,
+        chain: Some(checkpoint.insert(chain.tip())),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Regtest headers on top of genesis; any nonce works at the regtest target
+    /// after a few tries
+    fn mine(chain: &HeaderChain, count: usize, time_offset: u32) -> Vec<Header> {
+        let mut prev = chain.headers[chain.headers.len() - 1];
+        let mut prev_hash = chain.hashes[chain.hashes.len() - 1];
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            let mut header = Header {
+                prev_blockhash: prev_hash,
+                time: prev.time + 600 + time_offset,
+                nonce: 0,
+                ..prev
+            };
+            while header.validate_pow(header.target()).is_err() {
+                header.nonce += 1;
+            }
+            prev_hash = header.block_hash();
+            prev = header;
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_header_chain_connect() -> Result<()> {
+        let mut chain = HeaderChain::new(Network::Regtest);
+        let headers = mine(&chain, 20, 0);
+        assert_eq!(chain.connect(&headers)?, 20);
+        assert_eq!(chain.tip_height(), 20);
+        assert_eq!(chain.hash_at(20), Some(headers[19].block_hash()));
+
+        let locator = chain.locator();
+        assert_eq!(locator.first(), chain.hash_at(20).as_ref());
+        assert_eq!(locator.last(), chain.hash_at(0).as_ref());
+
+        // Headers that don't connect are rejected and leave the chain untouched
+        let mut orphan = HeaderChain::new(Network::Regtest);
+        orphan.connect(&mine(&orphan, 5, 1))?;
+        let unconnected = mine(&orphan, 1, 0);
+        assert!(chain.connect(&unconnected).is_err());
+
+        // A header with a bad proof of work is rejected
+        let mut tampered = mine(&chain, 1, 0);
+        tampered[0].nonce = tampered[0].nonce.wrapping_add(1);
+        if tampered[0].validate_pow(tampered[0].target()).is_err() {
+            assert!(chain.connect(&tampered).is_err());
+        }
+        assert_eq!(chain.tip_height(), 20);
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_chain_reorg() -> Result<()> {
+        let mut chain = HeaderChain::new(Network::Regtest);
+        chain.connect(&mine(&chain, 10, 0))?;
+
+        // A shorter fork from height 5 is ignored
+        let mut fork = HeaderChain::new(Network::Regtest);
+        fork.connect(&chain.headers[1..=5])?;
+        let short = mine(&fork, 3, 1);
+        assert_eq!(chain.connect(&short)?, 0);
+        assert_eq!(chain.tip_height(), 10);
+
+        // A longer one replaces the blocks after the fork point
+        let long = mine(&fork, 7, 1);
+        assert_eq!(chain.connect(&long)?, 7);
+        assert_eq!(chain.tip_height(), 12);
+        assert_eq!(chain.hash_at(6), Some(long[0].block_hash()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_default_port() {
+        assert_eq!(
+            with_default_port("127.0.0.1", Network::Signet),
+            "127.0.0.1:38333"
+        );
+        assert_eq!(
+            with_default_port("node.example.com:8334", Network::Bitcoin),
+            "node.example.com:8334"
+        );
+        assert_eq!(
+            with_default_port("[::1]:18444", Network::Regtest),
+            "[::1]:18444"
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! TLS certificate pinning for self-hosted Electrum and Esplora servers
+//!
+//! Node-in-a-box setups (Umbrel, Start9) serve their backends with self-signed
+//! certificates. Rather than disabling verification, `--backend-cert-fingerprint`
+//! accepts exactly the certificate whose SHA-256 fingerprint was given; CA trust
+//! and hostnames are not consulted. Each pin can be tied to one server URL
+//! (`URL=FINGERPRINT`), so a list of Electrum servers can mix pinned and
+//! CA-validated hosts.
+
+use crate::error::ensure;
+use anyhow::{Context, Result};
+use bitcoin::hashes::{Hash, sha256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// SHA-256 fingerprint of a DER-encoded certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CertFingerprint([u8; 32]);
+
+impl FromStr for CertFingerprint {
+    type Err = anyhow::Error;
+
+    /// Accepts hex with or without colons, as printed by
+    /// `openssl x509 -noout -fingerprint -sha256`
+    fn from_str(s: &str) -> Result<Self> {
+        let digits: String = s
+            .trim()
+            .chars()
+            .filter(|c| *c != ':' && !c.is_whitespace())
+            .collect();
+        let bytes = hex::decode(&digits)
+            .with_context(|| format!("Invalid certificate fingerprint: '{s}'"))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            anyhow::anyhow!(
+                "Certificate fingerprint must be a 32-byte SHA-256 digest, got {len} bytes",
+                len = bytes.len()
+            )
+        })?;
+        Ok(Self(bytes))
+    }
+}
+
+impl TryFrom<String> for CertFingerprint {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for CertFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: Vec<String> = self.0.iter().map(|byte| format!("{byte:02X}")).collect();
+        write!(f, "{}", hex.join(":"))
+    }
+}
+
+impl CertFingerprint {
+    pub fn of(der: &[u8]) -> Self {
+        Self(sha256::Hash::hash(der).to_byte_array())
+    }
+
+    /// TLS configuration that only accepts the pinned certificate
+    pub(crate) fn tls_config(&self) -> Result<rustls::ClientConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = Arc::new(PinnedCertVerifier {
+            pin: *self,
+            provider: provider.clone(),
+        });
+        Ok(rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .context("Failed to configure TLS")?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth())
+    }
+
+    /// Refuse to pin a plaintext URL, where the pin would silently do nothing
+    pub(crate) fn ensure_tls(url: &str, tls_scheme: &str) -> Result<()> {
+        ensure!(
+            url.starts_with(&format!("{tls_scheme}://")),
+            "Certificate pinning requires a {tls_scheme}:// URL, got '{url}'"
+        );
+        Ok(())
+    }
+}
+
+/// One `--backend-cert-fingerprint` value: a fingerprint, optionally for a
+/// single server URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertPin {
+    pub url: Option<String>,
+    pub fingerprint: CertFingerprint,
+}
+
+impl FromStr for CertPin {
+    type Err = anyhow::Error;
+
+    /// `FINGERPRINT` or `URL=FINGERPRINT`; fingerprints never contain `=`
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.rsplit_once('=') {
+            Some((url, fingerprint)) => {
+                let url = url.trim();
+                ensure!(
+                    url.contains("://"),
+                    "Certificate pin must be FINGERPRINT or URL=FINGERPRINT, got '{s}'"
+                );
+                Self {
+                    url: Some(pin_key(url).to_string()),
+                    fingerprint: fingerprint.parse()?,
+                }
+            }
+            None => Self {
+                url: None,
+                fingerprint: s.parse()?,
+            },
+        })
+    }
+}
+
+/// Certificate pins of the backend servers, keyed by URL
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CertPins {
+    by_url: Vec<(String, CertFingerprint)>,
+    /// Pin given without a URL, only meaningful with a single server
+    bare: Option<CertFingerprint>,
+}
+
+impl CertPins {
+    pub fn new(pins: impl IntoIterator<Item = CertPin>) -> Result<Self> {
+        let mut result = Self::default();
+        for pin in pins {
+            match pin.url {
+                Some(url) => {
+                    ensure!(
+                        result.by_url.iter().all(|(pinned, _)| *pinned != url),
+                        "More than one certificate pin for {url}"
+                    );
+                    result.by_url.push((url, pin.fingerprint));
+                }
+                None => {
+                    ensure!(
+                        result.bare.is_none(),
+                        "More than one certificate pin without a URL; pin each server as URL=FINGERPRINT"
+                    );
+                    result.bare = Some(pin.fingerprint);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Pin of the server at `url`: its own pin, else the pin given without a URL
+    pub fn for_url(&self, url: &str) -> Option<CertFingerprint> {
+        let url = pin_key(url);
+        self.by_url
+            .iter()
+            .find(|(pinned, _)| pinned == url)
+            .map(|(_, fingerprint)| *fingerprint)
+            .or(self.bare)
+    }
+
+    /// Refuse a pin without a URL when it would apply to several servers,
+    /// which cannot all present the same certificate
+    pub(crate) fn ensure_unambiguous(&self, servers: usize) -> Result<()> {
+        ensure!(
+            self.bare.is_none() || servers <= 1,
+            "A certificate pin without a URL cannot be used with {servers} servers; pin each server as URL=FINGERPRINT"
+        );
+        Ok(())
+    }
+}
+
+/// URLs are matched without a trailing slash
+fn pin_key(url: &str) -> &str {
+    url.trim().trim_end_matches('/')
+}
+
+/// Accepts the server certificate only if its fingerprint matches the pin.
+/// Handshake signatures are still verified, so the server must hold the key.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pin: CertFingerprint,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let presented = CertFingerprint::of(end_entity);
+        if presented != self.pin {
+            return Err(rustls::Error::General(format!(
+                "server certificate {presented} does not match pinned fingerprint {pin}",
+                pin = self.pin
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "5E:88:48:98:DA:28:04:71:51:D0:E5:6F:8D:C6:29:27:73:60:3D:0D:6A:AB:BD:D6:2A:11:EF:72:1D:15:42:D8";
+
+    #[test]
+    fn test_parse_fingerprint() -> Result<()> {
+        let pin = CertFingerprint::from_str(FINGERPRINT)?;
+        assert_eq!(pin, CertFingerprint::of(b"password"));
+        assert_eq!(pin.to_string(), FINGERPRINT);
+
+        let bare = FINGERPRINT.replace(':', "").to_lowercase();
+        assert_eq!(CertFingerprint::from_str(&bare)?, pin);
+
+        assert!(CertFingerprint::from_str("5E:88:48").is_err());
+        assert!(CertFingerprint::from_str("not a fingerprint").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pins_by_url() -> Result<()> {
+        let umbrel = CertFingerprint::of(b"umbrel");
+        let start9 = CertFingerprint::of(b"start9");
+        let pins = CertPins::new([
+            format!("ssl://umbrel.local:50002={umbrel}").parse()?,
+            format!("https://start9.local/api/={start9}").parse()?,
+        ])?;
+        assert_eq!(pins.for_url("ssl://umbrel.local:50002"), Some(umbrel));
+        assert_eq!(pins.for_url("https://start9.local/api"), Some(start9));
+        assert_eq!(pins.for_url("ssl://electrum.blockstream.info:50002"), None);
+        pins.ensure_unambiguous(2)?;
+
+        let bare = CertPins::new([CertPin::from_str(FINGERPRINT)?])?;
+        assert_eq!(
+            bare.for_url("ssl://umbrel.local:50002"),
+            Some(CertFingerprint::of(b"password"))
+        );
+        bare.ensure_unambiguous(1)?;
+        assert!(bare.ensure_unambiguous(2).is_err());
+
+        let twice = format!("ssl://umbrel.local:50002={umbrel}");
+        assert!(CertPins::new([twice.parse()?, twice.parse()?]).is_err());
+        assert!(CertPin::from_str(&format!("umbrel={umbrel}")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verifier_rejects_other_certificates() -> Result<()> {
+        let verifier = PinnedCertVerifier {
+            pin: CertFingerprint::of(b"pinned"),
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+        };
+        let server_name = ServerName::try_from("umbrel.local")?;
+        let now = UnixTime::now();
+
+        let pinned = CertificateDer::from(b"pinned".to_vec());
+        assert!(
+            verifier
+                .verify_server_cert(&pinned, &[], &server_name, &[], now)
+                .is_ok()
+        );
+        let other = CertificateDer::from(b"other".to_vec());
+        assert!(
+            verifier
+                .verify_server_cert(&other, &[], &server_name, &[], now)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ensure_tls() {
+        assert!(CertFingerprint::ensure_tls("ssl://umbrel.local:50002", "ssl").is_ok());
+        assert!(CertFingerprint::ensure_tls("tcp://umbrel.local:50001", "ssl").is_err());
+        assert!(CertFingerprint::ensure_tls("http://umbrel.local:3002", "https").is_err());
+    }
+}
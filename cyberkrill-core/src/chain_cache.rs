@@ -0,0 +1,284 @@
+//! On-disk cache for immutable chain data
+//!
+//! Transactions are cached by txid, and confirmation details and block headers
+//! once they are buried deep enough that a reorg is not a concern. Repeated DCA
+//! reports and prevout lookups then only ask the backend for what is new.
+//!
+//! Layout under the cache directory:
+//! - `tx/<txid>.hex`: consensus-encoded transaction
+//! - `confirmations/<txid>.json`: block height and time of a confirmed transaction
+//! - `headers/<network>/<height>.hex`: consensus-encoded block header
+//! - `headers/<network>/chain.bin`: the header chain after genesis, 80 bytes per
+//!   header, as synced by the compact block filter client
+
+use crate::error::{CoreError, CoreResult};
+use anyhow::{Context, Result};
+use bitcoin::block::Header;
+use bitcoin::consensus::encode::{deserialize, deserialize_hex, serialize, serialize_hex};
+use bitcoin::{Network, Transaction, Txid};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Confirmations after which block data is treated as final
+pub const REORG_SAFETY_DEPTH: u32 = 6;
+
+/// Block a transaction was confirmed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxConfirmation {
+    pub block_height: u32,
+    pub block_time: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChainCache {
+    dir: PathBuf,
+}
+
+impl ChainCache {
+    pub fn open(dir: &Path) -> CoreResult<Self> {
+        std::fs::create_dir_all(dir).with_context(|| {
+            format!(
+                "Failed to create chain cache directory {dir}",
+                dir = dir.display()
+            )
+        })?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn transaction(&self, txid: &Txid) -> Option<Transaction> {
+        let tx: Transaction = self.read(&self.tx_path(txid), deserialize_hex)?;
+        // A damaged file must not be mistaken for the transaction
+        (tx.compute_txid() == *txid).then_some(tx)
+    }
+
+    pub fn store_transaction(&self, tx: &Transaction) {
+        self.write(&self.tx_path(&tx.compute_txid()), serialize_hex(tx));
+    }
+
+    pub fn confirmation(&self, txid: &Txid) -> Option<TxConfirmation> {
+        self.read(&self.confirmation_path(txid), |contents| {
+            serde_json::from_str(contents)
+        })
+    }
+
+    /// Cache `confirmation` if it has at least [`REORG_SAFETY_DEPTH`] confirmations
+    pub fn store_confirmation(
+        &self,
+        txid: &Txid,
+        confirmation: TxConfirmation,
+        confirmations: u32,
+    ) {
+        if confirmations < REORG_SAFETY_DEPTH {
+            return;
+        }
+        match serde_json::to_string(&confirmation) {
+            Ok(contents) => self.write(&self.confirmation_path(txid), &contents),
+            Err(e) => warn!("Failed to encode confirmation of {txid}: {e}"),
+        }
+    }
+
+    pub fn header(&self, network: Network, height: u32) -> Option<Header> {
+        self.read(&self.header_path(network, height), deserialize_hex)
+    }
+
+    /// Cache the header at `height` if it is at least [`REORG_SAFETY_DEPTH`] blocks deep
+    pub fn store_header(&self, network: Network, height: u32, header: &Header, tip_height: u32) {
+        if tip_height.saturating_sub(height) + 1 < REORG_SAFETY_DEPTH {
+            return;
+        }
+        self.write(&self.header_path(network, height), serialize_hex(header));
+    }
+
+    /// Header chain after the genesis block, as stored by [`Self::store_header_chain`]
+    pub fn header_chain(&self, network: Network) -> Vec<Header> {
+        let path = self.header_chain_path(network);
+        let Ok(bytes) = std::fs::read(&path) else {
+            return Vec::new();
+        };
+        match bytes.chunks(80).map(deserialize).collect() {
+            Ok(headers) => {
+                debug!("Chain cache hit: {path}", path = path.display());
+                headers
+            }
+            Err(e) => {
+                warn!(
+                    "Ignoring corrupt cache entry {path}: {e}",
+                    path = path.display()
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Cache the header chain after genesis, leaving out headers less than
+    /// [`REORG_SAFETY_DEPTH`] blocks deep
+    pub fn store_header_chain(&self, network: Network, headers: &[Header]) {
+        let buried = headers
+            .len()
+            .saturating_sub(REORG_SAFETY_DEPTH as usize - 1);
+        let bytes: Vec<u8> = headers[..buried].iter().flat_map(serialize).collect();
+        self.write(&self.header_chain_path(network), bytes);
+    }
+
+    fn tx_path(&self, txid: &Txid) -> PathBuf {
+        self.dir.join("tx").join(format!("{txid}.hex"))
+    }
+
+    fn confirmation_path(&self, txid: &Txid) -> PathBuf {
+        self.dir.join("confirmations").join(format!("{txid}.json"))
+    }
+
+    fn header_path(&self, network: Network, height: u32) -> PathBuf {
+        self.dir
+            .join("headers")
+            .join(network.to_string())
+            .join(format!("{height}.hex"))
+    }
+
+    fn header_chain_path(&self, network: Network) -> PathBuf {
+        self.dir
+            .join("headers")
+            .join(network.to_string())
+            .join("chain.bin")
+    }
+
+    /// Cached value at `path`; unreadable entries count as misses
+    fn read<T, E: std::fmt::Display>(
+        &self,
+        path: &Path,
+        parse: impl FnOnce(&str) -> std::result::Result<T, E>,
+    ) -> Option<T> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        match parse(contents.trim()) {
+            Ok(value) => {
+                debug!("Chain cache hit: {path}", path = path.display());
+                Some(value)
+            }
+            Err(e) => {
+                warn!(
+                    "Ignoring corrupt cache entry {path}: {e}",
+                    path = path.display()
+                );
+                None
+            }
+        }
+    }
+
+    /// Best-effort write: a failure only costs a refetch next time
+    fn write(&self, path: &Path, contents: impl AsRef<[u8]>) {
+        let result = (|| -> Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // Write then rename so concurrent readers never see a partial file
+            let partial = path.with_extension("partial");
+            std::fs::write(&partial, contents)?;
+            std::fs::rename(&partial, path)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            warn!(
+                "Failed to write cache entry {path}: {e}",
+                path = path.display()
+            );
+        }
+    }
+}
+
+/// `txid` from `cache`, or from `fetch` (then cached)
+pub(crate) async fn cached_transaction<Fut, E>(
+    cache: Option<&ChainCache>,
+    txid: &Txid,
+    fetch: Fut,
+) -> CoreResult<Transaction>
+where
+    Fut: Future<Output = std::result::Result<Transaction, E>>,
+    E: Into<CoreError>,
+{
+    if let Some(tx) = cache.and_then(|cache| cache.transaction(txid)) {
+        return Ok(tx);
+    }
+    let tx = fetch.await.map_err(Into::into)?;
+    if let Some(cache) = cache {
+        cache.store_transaction(&tx);
+    }
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tx() -> Transaction {
+        Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(50_000),
+                script_pubkey: bitcoin::ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_transaction_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ChainCache::open(dir.path())?;
+        let tx = sample_tx();
+        let txid = tx.compute_txid();
+
+        assert_eq!(cache.transaction(&txid), None);
+        cache.store_transaction(&tx);
+        assert_eq!(cache.transaction(&txid), Some(tx));
+
+        // A corrupt entry is a miss, not an error
+        std::fs::write(cache.tx_path(&txid), "not hex")?;
+        assert_eq!(cache.transaction(&txid), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shallow_data_is_not_cached() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ChainCache::open(dir.path())?;
+        let txid = sample_tx().compute_txid();
+        let confirmation = TxConfirmation {
+            block_height: 850_000,
+            block_time: Some(1_719_000_000),
+        };
+
+        cache.store_confirmation(&txid, confirmation, REORG_SAFETY_DEPTH - 1);
+        assert_eq!(cache.confirmation(&txid), None);
+        cache.store_confirmation(&txid, confirmation, REORG_SAFETY_DEPTH);
+        assert_eq!(cache.confirmation(&txid), Some(confirmation));
+
+        let header = bitcoin::constants::genesis_block(Network::Bitcoin).header;
+        cache.store_header(Network::Bitcoin, 100, &header, 102);
+        assert_eq!(cache.header(Network::Bitcoin, 100), None);
+        cache.store_header(Network::Bitcoin, 100, &header, 105);
+        assert_eq!(cache.header(Network::Bitcoin, 100), Some(header));
+        assert_eq!(cache.header(Network::Testnet, 100), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_chain_keeps_buried_headers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ChainCache::open(dir.path())?;
+        let header = bitcoin::constants::genesis_block(Network::Regtest).header;
+        let headers = vec![header; 10];
+
+        assert!(cache.header_chain(Network::Regtest).is_empty());
+        cache.store_header_chain(Network::Regtest, &headers);
+        let cached = cache.header_chain(Network::Regtest);
+        assert_eq!(cached.len(), 10 - (REORG_SAFETY_DEPTH as usize - 1));
+        assert!(cached.iter().all(|cached| *cached == header));
+        assert!(cache.header_chain(Network::Signet).is_empty());
+        Ok(())
+    }
+}
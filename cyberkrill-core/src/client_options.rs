@@ -0,0 +1,51 @@
+//! Settings shared by the clients of backends and web services
+//!
+//! `--proxy`, the retry flags, `--backend-cert-fingerprint`, `--record-rpc` /
+//! `--replay-rpc`, the caches and `--offline` all end up here. Callers build
+//! one [`ClientOptions`] and hand it to backend constructors (usually through
+//! [`ScanOptions`](crate::ScanOptions)) and to the functions that reach the
+//! network.
+
+use anyhow::Context;
+use std::sync::Arc;
+
+use crate::cert_pin::CertPins;
+use crate::chain_cache::ChainCache;
+use crate::error::CoreResult;
+use crate::price_cache::PriceCache;
+use crate::proxy::{NetworkProxy, http_client_builder};
+use crate::retry::RetryPolicy;
+use crate::rpc_trace::RpcTrace;
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Proxy for every outgoing connection, e.g. Tor
+    pub proxy: Option<NetworkProxy>,
+    /// Retries and timeouts of backend calls
+    pub retry: RetryPolicy,
+    /// Certificates accepted from Electrum and Esplora servers instead of CA trust
+    pub cert_pins: CertPins,
+    /// Records or replays backend exchanges
+    pub trace: Option<Arc<RpcTrace>>,
+    /// Confirmed transactions and block headers kept across runs
+    pub chain_cache: Option<Arc<ChainCache>>,
+    /// Daily BTC prices kept across runs
+    pub price_cache: Option<Arc<PriceCache>>,
+    /// Answer price lookups from the cache only, never from a price provider
+    pub offline: bool,
+}
+
+impl ClientOptions {
+    /// HTTP client builder that goes through the proxy, if any
+    pub fn http_client_builder(&self) -> reqwest::ClientBuilder {
+        http_client_builder(self.proxy.as_ref())
+    }
+
+    /// Default HTTP client that goes through the proxy, if any
+    pub fn http_client(&self) -> CoreResult<reqwest::Client> {
+        Ok(self
+            .http_client_builder()
+            .build()
+            .context("Failed to build HTTP client")?)
+    }
+}
@@ -1,5 +1,7 @@
-use anyhow::{Context, Result, anyhow, ensure};
-use bitcoin::bip32::Xpub;
+use anyhow::{Context, Result, anyhow};
+use bitcoin::bip32::{ChildNumber, Xpub};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Address, CompressedPublicKey, Network, NetworkKind};
 use coldcard::{
     Api, Coldcard as ColdcardDevice, SignMode,
     protocol::{AddressFormat, DerivationPath},
@@ -7,6 +9,8 @@ use coldcard::{
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+use crate::descriptor::descriptor_checksum;
+use crate::error::{CoreResult, bail, ensure};
 use crate::hardware_wallet::{AddressInfo, DeviceInfo, SignedPsbt};
 
 /// Convert our u32 derivation path to Coldcard's DerivationPath type
@@ -51,9 +55,166 @@ pub struct ColdcardSignOutput {
     pub is_complete: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ColdcardXpubOutput {
+    pub xpub: String,
+    pub derivation_path: String,
+    pub master_fingerprint: String,
+}
+
+/// Coldcard's generic JSON wallet export ("Export Wallet > Generic JSON"),
+/// rebuilt over USB from the device's xpubs
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ColdcardWalletExport {
+    /// `BTC` for mainnet, `XTN` for testnet
+    pub chain: String,
+    /// Master key fingerprint
+    pub xfp: String,
+    pub account: u32,
+    /// Master xpub (`m`)
+    pub xpub: String,
+    pub bip44: WalletExportSection,
+    pub bip49: WalletExportSection,
+    pub bip84: WalletExportSection,
+    pub bip86: WalletExportSection,
+    pub bip48_1: WalletExportSection,
+    pub bip48_2: WalletExportSection,
+}
+
+/// One standard derivation of a wallet export
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalletExportSection {
+    /// Script type, e.g. `p2wpkh`
+    pub name: String,
+    /// Account derivation path
+    pub deriv: String,
+    /// Fingerprint of the account key itself
+    pub xfp: String,
+    pub xpub: String,
+    /// Receive/change descriptor; single-sig derivations only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    /// First receive address; single-sig derivations only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<String>,
+}
+
+/// Standard derivations included in a wallet export
+#[derive(Debug, Clone, Copy)]
+enum ExportDerivation {
+    Bip44,
+    Bip49,
+    Bip84,
+    Bip86,
+    Bip48P2shP2wsh,
+    Bip48P2wsh,
+}
+
+impl ExportDerivation {
+    fn name(self) -> &'static str {
+        match self {
+            ExportDerivation::Bip44 => "p2pkh",
+            ExportDerivation::Bip49 => "p2sh-p2wpkh",
+            ExportDerivation::Bip84 => "p2wpkh",
+            ExportDerivation::Bip86 => "p2tr",
+            ExportDerivation::Bip48P2shP2wsh => "p2sh-p2wsh",
+            ExportDerivation::Bip48P2wsh => "p2wsh",
+        }
+    }
+
+    fn path(self, coin: u32, account: u32) -> String {
+        match self {
+            ExportDerivation::Bip44 => format!("m/44'/{coin}'/{account}'"),
+            ExportDerivation::Bip49 => format!("m/49'/{coin}'/{account}'"),
+            ExportDerivation::Bip84 => format!("m/84'/{coin}'/{account}'"),
+            ExportDerivation::Bip86 => format!("m/86'/{coin}'/{account}'"),
+            ExportDerivation::Bip48P2shP2wsh => format!("m/48'/{coin}'/{account}'/1'"),
+            ExportDerivation::Bip48P2wsh => format!("m/48'/{coin}'/{account}'/2'"),
+        }
+    }
+
+    /// Descriptor wrapper around the key, for single-sig derivations
+    fn descriptor(self, key: &str) -> Option<String> {
+        match self {
+            ExportDerivation::Bip44 => Some(format!("pkh({key})")),
+            ExportDerivation::Bip49 => Some(format!("sh(wpkh({key}))")),
+            ExportDerivation::Bip84 => Some(format!("wpkh({key})")),
+            ExportDerivation::Bip86 => Some(format!("tr({key})")),
+            ExportDerivation::Bip48P2shP2wsh | ExportDerivation::Bip48P2wsh => None,
+        }
+    }
+
+    /// First receive address (`/0/0`) of a single-sig account
+    fn first_address(self, account_xpub: &Xpub, network: Network) -> Result<Option<String>> {
+        let secp = Secp256k1::verification_only();
+        let child = account_xpub
+            .derive_pub(&secp, &[ChildNumber::from(0), ChildNumber::from(0)])
+            .context("Failed to derive first receive address")?;
+        let pubkey = CompressedPublicKey(child.public_key);
+        let address = match self {
+            ExportDerivation::Bip44 => Address::p2pkh(pubkey, network),
+            ExportDerivation::Bip49 => Address::p2shwpkh(&pubkey, network),
+            ExportDerivation::Bip84 => Address::p2wpkh(&pubkey, network),
+            ExportDerivation::Bip86 => Address::p2tr(&secp, child.to_x_only_pub(), None, network),
+            ExportDerivation::Bip48P2shP2wsh | ExportDerivation::Bip48P2wsh => return Ok(None),
+        };
+        Ok(Some(address.to_string()))
+    }
+}
+
+/// Build the generic JSON export for `account` from the master xpub, fetching
+/// each account xpub with `account_xpub`
+fn build_wallet_export(
+    master_xpub: &Xpub,
+    account: u32,
+    mut account_xpub: impl FnMut(&str) -> Result<Xpub>,
+) -> Result<ColdcardWalletExport> {
+    let (chain, coin, network) = match master_xpub.network {
+        NetworkKind::Main => ("BTC", 0, Network::Bitcoin),
+        NetworkKind::Test => ("XTN", 1, Network::Testnet),
+    };
+    let master_fingerprint = master_xpub.fingerprint();
+
+    let mut section = |derivation: ExportDerivation| -> Result<WalletExportSection> {
+        let path = derivation.path(coin, account);
+        let xpub = account_xpub(&path)?;
+        ensure!(
+            xpub.network == master_xpub.network,
+            "Coldcard returned an xpub for a different network at {path}"
+        );
+        let origin = path.trim_start_matches("m/").replace('\'', "h");
+        let key = format!("[{master_fingerprint}/{origin}]{xpub}/<0;1>/*");
+        let desc = derivation
+            .descriptor(&key)
+            .map(|body| descriptor_checksum(&body).map(|checksum| format!("{body}#{checksum}")))
+            .transpose()?;
+        Ok(WalletExportSection {
+            name: derivation.name().to_string(),
+            deriv: path,
+            xfp: xpub.fingerprint().to_string().to_uppercase(),
+            xpub: xpub.to_string(),
+            desc,
+            first: derivation.first_address(&xpub, network)?,
+        })
+    };
+
+    Ok(ColdcardWalletExport {
+        chain: chain.to_string(),
+        xfp: master_fingerprint.to_string().to_uppercase(),
+        account,
+        xpub: master_xpub.to_string(),
+        bip44: section(ExportDerivation::Bip44)?,
+        bip49: section(ExportDerivation::Bip49)?,
+        bip84: section(ExportDerivation::Bip84)?,
+        bip86: section(ExportDerivation::Bip86)?,
+        bip48_1: section(ExportDerivation::Bip48P2shP2wsh)?,
+        bip48_2: section(ExportDerivation::Bip48P2wsh)?,
+    })
+}
+
 impl ColdcardWallet {
     /// Connect to the first available Coldcard device
-    pub async fn connect() -> Result<Self> {
+    pub async fn connect() -> CoreResult<Self> {
         let mut api = Api::new().context("Failed to initialize Coldcard API")?;
 
         let serials = api.detect().context(
@@ -82,7 +243,7 @@ impl ColdcardWallet {
     }
 
     /// Connect to a specific Coldcard by serial number
-    pub async fn connect_serial(serial: &str) -> Result<Self> {
+    pub async fn connect_serial(serial: &str) -> CoreResult<Self> {
         let api = Api::new().context("Failed to initialize Coldcard API")?;
 
         let (device, xpub_info) = api
@@ -101,7 +262,7 @@ impl ColdcardWallet {
     }
 
     /// List all connected Coldcard devices
-    pub async fn list_devices() -> Result<Vec<String>> {
+    pub async fn list_devices() -> CoreResult<Vec<String>> {
         let mut api = Api::new().context("Failed to initialize Coldcard API")?;
 
         let serials = api.detect().context("Failed to detect Coldcard devices")?;
@@ -118,7 +279,7 @@ impl ColdcardWallet {
 }
 
 impl ColdcardWallet {
-    pub fn get_device_info(&mut self) -> Result<DeviceInfo> {
+    pub fn get_device_info(&mut self) -> CoreResult<DeviceInfo> {
         let version = self
             .device
             .version()
@@ -132,7 +293,7 @@ impl ColdcardWallet {
         })
     }
 
-    pub fn get_address(&mut self, path: &str) -> Result<AddressInfo> {
+    pub fn get_address(&mut self, path: &str) -> CoreResult<AddressInfo> {
         // Parse the derivation path
         let derivation_path = crate::hardware_wallet::parse_derivation_path(path)?;
 
@@ -159,7 +320,7 @@ impl ColdcardWallet {
         })
     }
 
-    pub fn get_xpub(&mut self, path: &str) -> Result<Xpub> {
+    pub fn get_xpub(&mut self, path: &str) -> CoreResult<Xpub> {
         let derivation_path = crate::hardware_wallet::parse_derivation_path(path)?;
 
         // Convert to Coldcard's DerivationPath type
@@ -174,10 +335,10 @@ impl ColdcardWallet {
             .xpub(coldcard_path)
             .with_context(|| format!("Failed to get xpub at path: {path}"))?;
 
-        Xpub::from_str(&xpub_str).context("Failed to parse xpub from Coldcard")
+        Ok(Xpub::from_str(&xpub_str).context("Failed to parse xpub from Coldcard")?)
     }
 
-    pub fn sign_psbt(&mut self, psbt: &[u8]) -> Result<SignedPsbt> {
+    pub fn sign_psbt(&mut self, psbt: &[u8]) -> CoreResult<SignedPsbt> {
         use base64::Engine;
 
         // Sign the PSBT - note: sign_psbt doesn't return the signed PSBT directly
@@ -205,15 +366,40 @@ impl ColdcardWallet {
         })
     }
 
-    pub fn ping(&mut self) -> Result<bool> {
+    /// Sign `message` with the key at `path` after approval on the device,
+    /// returning the 65-byte recoverable signature.
+    /// The address format follows the path purpose: 44', 49' or 84'.
+    pub fn sign_message(&mut self, message: &str, path: &str) -> CoreResult<Vec<u8>> {
+        let derivation_path = crate::hardware_wallet::parse_derivation_path(path)?;
+        // The purpose is the first (hardened) component
+        let addr_fmt = match derivation_path.first().copied() {
+            Some(purpose) if purpose == 44 + 0x80000000 => AddressFormat::P2PKH,
+            Some(purpose) if purpose == 49 + 0x80000000 => AddressFormat::P2WPKH_P2SH,
+            Some(purpose) if purpose == 84 + 0x80000000 => AddressFormat::P2WPKH,
+            _ => bail!("Coldcard signs messages for m/44'/, m/49'/ and m/84'/ paths"),
+        };
+        let coldcard_path = convert_to_coldcard_path(&derivation_path)?;
+
+        self.device
+            .sign_message(message.as_bytes(), Some(coldcard_path), addr_fmt)
+            .context("Failed to sign message with Coldcard")?;
+
+        Ok(self
+            .device
+            .get_signed_message()
+            .context("Failed to retrieve signed message from Coldcard")?
+            .context("Message signing was not approved on the Coldcard")?)
+    }
+
+    pub fn ping(&mut self) -> CoreResult<bool> {
         // Try to get version as a ping test
-        self.device.version().map(|_| true).or(Ok(false))
+        Ok(self.device.version().is_ok())
     }
 }
 
 /// Generate a Bitcoin address from Coldcard
 /// Note: The address network depends on the Coldcard's internal settings
-pub async fn generate_coldcard_address(path: &str) -> Result<ColdcardAddressOutput> {
+pub async fn generate_coldcard_address(path: &str) -> CoreResult<ColdcardAddressOutput> {
     let mut wallet = ColdcardWallet::connect().await?;
     let info = wallet.get_device_info()?;
     let address_info = wallet.get_address(path)?;
@@ -226,8 +412,32 @@ pub async fn generate_coldcard_address(path: &str) -> Result<ColdcardAddressOutp
     })
 }
 
+/// Get the extended public key at `path` and the master fingerprint from Coldcard
+pub async fn generate_coldcard_xpub(path: &str) -> CoreResult<ColdcardXpubOutput> {
+    let mut wallet = ColdcardWallet::connect().await?;
+    let xpub = wallet.get_xpub(path)?;
+    let master_fingerprint = wallet.get_xpub("m/")?.fingerprint();
+
+    Ok(ColdcardXpubOutput {
+        xpub: xpub.to_string(),
+        derivation_path: path.to_string(),
+        master_fingerprint: master_fingerprint.to_string(),
+    })
+}
+
+/// Export the standard derivations of `account` in Coldcard's generic JSON
+/// wallet format, for setting up a watch-only wallet.
+/// The chain (mainnet or testnet) follows the Coldcard's own setting.
+pub async fn export_coldcard_wallet(account: u32) -> CoreResult<ColdcardWalletExport> {
+    let mut wallet = ColdcardWallet::connect().await?;
+    let master_xpub = wallet.get_xpub("m/")?;
+    Ok(build_wallet_export(&master_xpub, account, |path| {
+        Ok(wallet.get_xpub(path)?)
+    })?)
+}
+
 /// Sign a PSBT with Coldcard
-pub async fn sign_psbt_with_coldcard(psbt_data: &[u8]) -> Result<ColdcardSignOutput> {
+pub async fn sign_psbt_with_coldcard(psbt_data: &[u8]) -> CoreResult<ColdcardSignOutput> {
     let mut wallet = ColdcardWallet::connect().await?;
     let signed = wallet.sign_psbt(psbt_data)?;
 
@@ -239,7 +449,7 @@ pub async fn sign_psbt_with_coldcard(psbt_data: &[u8]) -> Result<ColdcardSignOut
 }
 
 /// Export a PSBT to Coldcard's SD card (air-gapped operation)
-pub async fn export_psbt_to_coldcard(psbt_data: &[u8], filename: &str) -> Result<String> {
+pub async fn export_psbt_to_coldcard(psbt_data: &[u8], filename: &str) -> CoreResult<String> {
     use base64::Engine;
 
     let mut wallet = ColdcardWallet::connect().await?;
@@ -280,6 +490,56 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_build_wallet_export() -> Result<()> {
+        use bitcoin::bip32::{DerivationPath, Xpriv};
+
+        // "abandon abandon ... about" BIP39 seed
+        let seed = hex::decode(
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4",
+        )?;
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(Network::Bitcoin, &seed)?;
+        let master_xpub = Xpub::from_priv(&secp, &master);
+
+        let export = build_wallet_export(&master_xpub, 0, |path| {
+            let path = DerivationPath::from_str(path)?;
+            Ok(Xpub::from_priv(&secp, &master.derive_priv(&secp, &path)?))
+        })?;
+
+        assert_eq!(export.chain, "BTC");
+        assert_eq!(export.xfp, "73C5DA0A");
+        assert_eq!(export.bip84.deriv, "m/84'/0'/0'");
+        assert_eq!(
+            export.bip84.xpub,
+            "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V"
+        );
+        assert_eq!(
+            export.bip84.first.as_deref(),
+            Some("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu")
+        );
+        assert_eq!(
+            export.bip44.first.as_deref(),
+            Some("1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA")
+        );
+        assert_eq!(
+            export.bip49.first.as_deref(),
+            Some("37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf")
+        );
+        assert_eq!(
+            export.bip86.first.as_deref(),
+            Some("bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr")
+        );
+        let desc = export.bip84.desc.as_deref().unwrap_or_default();
+        assert!(desc.starts_with("wpkh([73c5da0a/84h/0h/0h]xpub6CatWdiZ"));
+        assert!(desc.contains("/<0;1>/*)#"));
+
+        assert_eq!(export.bip48_2.deriv, "m/48'/0'/0'/2'");
+        assert_eq!(export.bip48_2.name, "p2wsh");
+        assert!(export.bip48_2.desc.is_none() && export.bip48_2.first.is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_coldcard_sign_output_serialization() -> Result<()> {
         let output = ColdcardSignOutput {
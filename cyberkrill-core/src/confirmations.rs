@@ -0,0 +1,276 @@
+//! Minimum confirmation recommendations scaled by the amount at risk
+//!
+//! Uses the attacker catch-up probability from section 11 of the Bitcoin
+//! whitepaper: given an attacker controlling a share `q` of the hashrate, the
+//! probability of rewriting `z` blocks shrinks exponentially with `z`. The
+//! recommendation is the smallest depth at which the expected loss
+//! (`amount * probability`) falls below a tolerated threshold.
+
+use anyhow::Context;
+use bitcoin::psbt::Psbt;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::BlockchainBackend;
+use crate::error::{CoreResult, bail, ensure};
+
+/// Default hashrate share assumed for an attacker (10%).
+pub const DEFAULT_ATTACKER_HASHRATE_SHARE: f64 = 0.1;
+/// Default expected loss, in satoshis, that is considered acceptable.
+pub const DEFAULT_MAX_EXPECTED_LOSS_SATS: u64 = 1_000;
+/// Deepest reorg observed on mainnet in recent history; recommendations never go below this + 1.
+pub const DEFAULT_OBSERVED_REORG_DEPTH: u32 = 1;
+/// Upper bound for recommendations (roughly one day of blocks).
+pub const MAX_RECOMMENDED_CONFIRMATIONS: u32 = 144;
+
+/// Inputs that drive the confirmation recommendation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmationPolicy {
+    /// Fraction of the network hashrate assumed to be controlled by an attacker (0 < q < 0.5)
+    pub attacker_hashrate_share: f64,
+    /// Expected loss (amount * attack success probability) tolerated, in satoshis
+    pub max_expected_loss_sats: u64,
+    /// Deepest reorg observed on the network; the recommendation is always deeper than this
+    pub observed_reorg_depth: u32,
+}
+
+impl Default for ConfirmationPolicy {
+    fn default() -> Self {
+        Self {
+            attacker_hashrate_share: DEFAULT_ATTACKER_HASHRATE_SHARE,
+            max_expected_loss_sats: DEFAULT_MAX_EXPECTED_LOSS_SATS,
+            observed_reorg_depth: DEFAULT_OBSERVED_REORG_DEPTH,
+        }
+    }
+}
+
+impl ConfirmationPolicy {
+    /// Fail if no recommendation can be made under this policy
+    pub fn validate(&self) -> CoreResult<()> {
+        let q = self.attacker_hashrate_share;
+        ensure!(
+            q > 0.0 && q < 0.5,
+            "Attacker hashrate share must be between 0 and 0.5 (exclusive), got {q}"
+        );
+        if self.observed_reorg_depth >= MAX_RECOMMENDED_CONFIRMATIONS {
+            bail!(
+                "Observed reorg depth {depth} exceeds the maximum supported recommendation of {MAX_RECOMMENDED_CONFIRMATIONS} confirmations",
+                depth = self.observed_reorg_depth
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Recommended confirmation depth for a given amount
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmationRecommendation {
+    pub amount_sats: u64,
+    pub min_confirmations: u32,
+    /// Probability that the attacker catches up from `min_confirmations` blocks behind
+    pub attacker_success_probability: f64,
+    /// `amount_sats * attacker_success_probability`
+    pub expected_loss_sats: f64,
+    /// True when the cap was reached before the expected loss dropped below the threshold
+    pub capped: bool,
+    pub policy: ConfirmationPolicy,
+}
+
+/// Probability that an attacker with hashrate share `q` ever catches up from `z` blocks behind.
+pub fn attacker_success_probability(q: f64, z: u32) -> f64 {
+    if q <= 0.0 {
+        return 0.0;
+    }
+    let p = 1.0 - q;
+    if q >= p {
+        return 1.0;
+    }
+
+    let lambda = f64::from(z) * (q / p);
+    let mut poisson = (-lambda).exp();
+    let mut sum = 1.0;
+    for k in 0..=z {
+        if k > 0 {
+            poisson *= lambda / f64::from(k);
+        }
+        sum -= poisson * (1.0 - (q / p).powi((z - k) as i32));
+    }
+    sum.clamp(0.0, 1.0)
+}
+
+/// Recommend a minimum confirmation count for `amount_sats` under `policy`.
+pub fn recommend_min_confirmations(
+    amount_sats: u64,
+    policy: &ConfirmationPolicy,
+) -> CoreResult<ConfirmationRecommendation> {
+    policy.validate()?;
+
+    let floor = policy.observed_reorg_depth + 1;
+    let amount = amount_sats as f64;
+    let threshold = policy.max_expected_loss_sats as f64;
+    let mut z = floor;
+    let mut probability = attacker_success_probability(policy.attacker_hashrate_share, z);
+    while amount * probability > threshold && z < MAX_RECOMMENDED_CONFIRMATIONS {
+        z += 1;
+        probability = attacker_success_probability(policy.attacker_hashrate_share, z);
+    }
+
+    Ok(ConfirmationRecommendation {
+        amount_sats,
+        min_confirmations: z,
+        attacker_success_probability: probability,
+        expected_loss_sats: amount * probability,
+        capped: amount * probability > threshold,
+        policy: policy.clone(),
+    })
+}
+
+/// Fail if `confirmations` is below the recommendation for `amount_sats`.
+pub fn ensure_min_confirmations(
+    amount_sats: u64,
+    confirmations: u32,
+    policy: &ConfirmationPolicy,
+) -> CoreResult<ConfirmationRecommendation> {
+    let recommendation = recommend_min_confirmations(amount_sats, policy)?;
+    ensure!(
+        confirmations >= recommendation.min_confirmations,
+        "{amount_sats} sats has {confirmations} confirmation(s) but the policy requires at least {min}",
+        min = recommendation.min_confirmations
+    );
+    Ok(recommendation)
+}
+
+/// Fail unless every input of `psbt` spends an output with the confirmations
+/// `policy` recommends for its value, as reported by `backend`
+pub async fn ensure_psbt_input_confirmations(
+    psbt: &Psbt,
+    backend: &dyn BlockchainBackend,
+    policy: &ConfirmationPolicy,
+) -> CoreResult<()> {
+    policy.validate()?;
+    for (index, txin) in psbt.unsigned_tx.input.iter().enumerate() {
+        let outpoint = txin.previous_output;
+        let known_value = psbt.inputs.get(index).and_then(|input| {
+            input
+                .witness_utxo
+                .as_ref()
+                .map(|txout| txout.value)
+                .or_else(|| {
+                    input
+                        .non_witness_utxo
+                        .as_ref()
+                        .and_then(|tx| tx.output.get(outpoint.vout as usize))
+                        .map(|txout| txout.value)
+                })
+        });
+        let value = match known_value {
+            Some(value) => value,
+            None => {
+                let tx = backend.get_transaction(&outpoint.txid).await?;
+                tx.output
+                    .get(outpoint.vout as usize)
+                    .map(|txout| txout.value)
+                    .with_context(|| format!("Input {index} spends a missing output {outpoint}"))?
+            }
+        };
+        let status = backend
+            .tx_status(&outpoint.txid)
+            .await
+            .with_context(|| format!("Failed to get the status of {txid}", txid = outpoint.txid))?;
+        ensure_min_confirmations(value.to_sat(), status.confirmations, policy)
+            .with_context(|| format!("Input {index} ({outpoint}) is not buried deep enough"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_attacker_success_probability_matches_whitepaper() -> Result<()> {
+        // Values from section 11 of the Bitcoin whitepaper for q = 0.1
+        let expected = [
+            (0, 1.0),
+            (1, 0.2045873),
+            (2, 0.0509779),
+            (5, 0.0009137),
+            (10, 0.0000012),
+        ];
+        for (z, probability) in expected {
+            let actual = attacker_success_probability(0.1, z);
+            assert!(
+                (actual - probability).abs() < 1e-6,
+                "z={z}: expected {probability}, got {actual}"
+            );
+        }
+
+        // q = 0.3 requires far more blocks for the same safety
+        assert!((attacker_success_probability(0.3, 5) - 0.1773523).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recommendation_scales_with_amount() -> Result<()> {
+        let policy = ConfirmationPolicy::default();
+
+        let small = recommend_min_confirmations(10_000, &policy)?;
+        let medium = recommend_min_confirmations(1_000_000, &policy)?;
+        let large = recommend_min_confirmations(100_000_000, &policy)?;
+        let whale = recommend_min_confirmations(100_000_000_000, &policy)?;
+
+        assert_eq!(small.min_confirmations, 2);
+        assert_eq!(medium.min_confirmations, 5);
+        assert_eq!(large.min_confirmations, 9);
+        assert!(whale.min_confirmations > large.min_confirmations);
+        assert!(!whale.capped);
+        assert!(whale.expected_loss_sats <= policy.max_expected_loss_sats as f64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recommendation_respects_observed_reorg_depth() -> Result<()> {
+        let policy = ConfirmationPolicy {
+            observed_reorg_depth: 6,
+            ..Default::default()
+        };
+        let recommendation = recommend_min_confirmations(1, &policy)?;
+        assert_eq!(recommendation.min_confirmations, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_hashrate_share_rejected() -> Result<()> {
+        for q in [0.0, 0.5, 0.7, -0.1] {
+            let policy = ConfirmationPolicy {
+                attacker_hashrate_share: q,
+                ..Default::default()
+            };
+            assert!(recommend_min_confirmations(1_000, &policy).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_ensure_min_confirmations() -> Result<()> {
+        let policy = ConfirmationPolicy::default();
+        assert!(ensure_min_confirmations(100_000_000, 9, &policy).is_ok());
+        assert!(ensure_min_confirmations(100_000_000, 8, &policy).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_rejects_deep_reorg_depth() -> Result<()> {
+        let policy = ConfirmationPolicy {
+            observed_reorg_depth: MAX_RECOMMENDED_CONFIRMATIONS,
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        let policy = ConfirmationPolicy {
+            observed_reorg_depth: MAX_RECOMMENDED_CONFIRMATIONS - 1,
+            ..Default::default()
+        };
+        policy.validate()?;
+        Ok(())
+    }
+}
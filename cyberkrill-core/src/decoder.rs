@@ -1,4 +1,4 @@
-use anyhow::{Context, Result, bail, ensure};
+use anyhow::{Context, Result};
 use bitcoin::hashes::Hash;
 use chrono::{DateTime, Utc};
 use lightning_invoice::{Bolt11Invoice, Currency, InvoiceBuilder};
@@ -7,6 +7,10 @@ use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
 use strum::{Display, EnumString};
 use url::Url;
 
+use crate::client_options::ClientOptions;
+use crate::error::{CoreError, CoreResult, bail, ensure};
+use crate::retry::{RetryPolicy, retry};
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Display, EnumString)]
 #[strum(serialize_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
@@ -49,7 +53,7 @@ impl Network {
 pub struct PaymentHash([u8; 32]);
 
 impl PaymentHash {
-    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+    pub fn from_slice(slice: &[u8]) -> CoreResult<Self> {
         ensure!(slice.len() == 32, "Payment hash must be exactly 32 bytes");
         let mut arr = [0u8; 32];
         arr.copy_from_slice(slice);
@@ -90,7 +94,7 @@ impl<'de> Deserialize<'de> for PaymentHash {
 pub struct PaymentSecret([u8; 32]);
 
 impl PaymentSecret {
-    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+    pub fn from_slice(slice: &[u8]) -> CoreResult<Self> {
         ensure!(slice.len() == 32, "Payment secret must be exactly 32 bytes");
         let mut arr = [0u8; 32];
         arr.copy_from_slice(slice);
@@ -131,13 +135,13 @@ impl<'de> Deserialize<'de> for PaymentSecret {
 pub struct PublicKey(bitcoin::secp256k1::PublicKey);
 
 impl PublicKey {
-    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+    pub fn from_slice(slice: &[u8]) -> CoreResult<Self> {
         let key =
             bitcoin::secp256k1::PublicKey::from_slice(slice).context("Invalid public key bytes")?;
         Ok(Self(key))
     }
 
-    pub fn from_hex(hex_str: &str) -> Result<Self> {
+    pub fn from_hex(hex_str: &str) -> CoreResult<Self> {
         let bytes = hex::decode(hex_str).context("Invalid hex string")?;
         Self::from_slice(&bytes)
     }
@@ -181,7 +185,7 @@ impl fmt::Display for PublicKey {
 pub struct Sha256Hash([u8; 32]);
 
 impl Sha256Hash {
-    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+    pub fn from_slice(slice: &[u8]) -> CoreResult<Self> {
         ensure!(slice.len() == 32, "SHA256 hash must be exactly 32 bytes");
         let mut arr = [0u8; 32];
         arr.copy_from_slice(slice);
@@ -254,6 +258,68 @@ pub struct InvoiceOutput {
     pub min_final_cltv_expiry: u64,
     pub fallback_addresses: Vec<String>,
     pub routes: Vec<Vec<RouteHintHopOutput>>,
+    // Derived when decoding; ignored by `encode_invoice`
+    /// `amount_msats` in whole satoshis (rounded down)
+    #[serde(default)]
+    pub amount_sats: Option<u64>,
+    #[serde(default)]
+    pub amount_btc: Option<f64>,
+    /// `timestamp` plus `expiry_seconds`
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the invoice had expired when it was decoded
+    #[serde(default)]
+    pub expired: bool,
+    /// Seconds left to pay the invoice when it was decoded; 0 once expired
+    #[serde(default)]
+    pub seconds_until_expiry: u64,
+    /// Fees and CLTV deltas of each entry of `routes`
+    #[serde(default)]
+    pub route_hint_summaries: Vec<RouteHintSummary>,
+}
+
+/// What paying through one route hint adds on top of the invoice amount
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RouteHintSummary {
+    pub hops: usize,
+    /// Public node the hinted route starts from
+    pub entry_node: PublicKey,
+    pub base_fee_msat: u64,
+    pub proportional_millionths: u64,
+    pub cltv_expiry_delta: u32,
+    /// Fee the hinted hops charge to forward the invoice amount, for invoices
+    /// with one
+    pub fee_msats: Option<u64>,
+}
+
+impl RouteHintSummary {
+    fn new(route: &[RouteHintHopOutput], amount_msats: Option<u64>) -> Option<Self> {
+        let entry = route.first()?;
+        // Each hop charges on what it forwards, so walk back from the payee
+        let fee_msats = amount_msats.map(|amount| {
+            let forwarded = route.iter().rev().fold(amount, |forwarded, hop| {
+                let proportional = u128::from(forwarded)
+                    * u128::from(hop.fees.proportional_millionths)
+                    / 1_000_000;
+                forwarded + u64::from(hop.fees.base_msat) + proportional as u64
+            });
+            forwarded - amount
+        });
+        Some(Self {
+            hops: route.len(),
+            entry_node: entry.src_node_id.clone(),
+            base_fee_msat: route.iter().map(|hop| u64::from(hop.fees.base_msat)).sum(),
+            proportional_millionths: route
+                .iter()
+                .map(|hop| u64::from(hop.fees.proportional_millionths))
+                .sum(),
+            cltv_expiry_delta: route
+                .iter()
+                .map(|hop| u32::from(hop.cltv_expiry_delta))
+                .sum(),
+            fee_msats,
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -377,9 +443,34 @@ impl TryFrom<lightning_invoice::Bolt11Invoice> for InvoiceOutput {
             _ => None,
         };
 
+        let amount_msats = invoice.amount_milli_satoshis();
+        let expiry_seconds = invoice.expiry_time().as_secs();
+        let expires_at = i64::try_from(expiry_seconds)
+            .ok()
+            .and_then(chrono::TimeDelta::try_seconds)
+            .and_then(|expiry| datetime.checked_add_signed(expiry));
+        let seconds_until_expiry = expires_at
+            .map(|expires_at| (expires_at - Utc::now()).num_seconds().max(0) as u64)
+            .unwrap_or(u64::MAX);
+        let routes = invoice
+            .route_hints()
+            .iter()
+            .map(|hints| {
+                hints
+                    .0
+                    .iter()
+                    .map(RouteHintHopOutput::try_from)
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let route_hint_summaries = routes
+            .iter()
+            .filter_map(|route| RouteHintSummary::new(route, amount_msats))
+            .collect();
+
         let result = Self {
             network: Network::from_currency(&invoice.currency()),
-            amount_msats: invoice.amount_milli_satoshis(),
+            amount_msats,
             timestamp: datetime,
             timestamp_millis,
             payment_hash,
@@ -393,24 +484,20 @@ impl TryFrom<lightning_invoice::Bolt11Invoice> for InvoiceOutput {
             },
             description_hash,
             destination,
-            expiry_seconds: invoice.expiry_time().as_secs(),
+            expiry_seconds,
             min_final_cltv_expiry: invoice.min_final_cltv_expiry_delta(),
             fallback_addresses: invoice
                 .fallback_addresses()
                 .iter()
                 .map(|a| a.to_string())
                 .collect(),
-            routes: invoice
-                .route_hints()
-                .iter()
-                .map(|hints| {
-                    hints
-                        .0
-                        .iter()
-                        .map(RouteHintHopOutput::try_from)
-                        .collect::<Result<Vec<_>>>()
-                })
-                .collect::<Result<Vec<_>>>()?,
+            routes,
+            amount_sats: amount_msats.map(|msats| msats / 1000),
+            amount_btc: amount_msats.map(|msats| msats as f64 / 100_000_000_000.0),
+            expires_at,
+            expired: seconds_until_expiry == 0,
+            seconds_until_expiry,
+            route_hint_summaries,
         };
         Ok(result)
     }
@@ -426,22 +513,67 @@ pub struct LnurlOutput {
     pub query_params: std::collections::HashMap<String, String>,
 }
 
-pub fn decode_invoice(input: &str) -> Result<InvoiceOutput> {
+pub fn decode_invoice(input: &str) -> CoreResult<InvoiceOutput> {
     let invoice = Bolt11Invoice::from_str(input)
-        .map_err(|e| anyhow::anyhow!("Failed to parse invoice: {e:?}"))?;
-    InvoiceOutput::try_from(invoice)
+        .map_err(|e| CoreError::parse(anyhow::anyhow!("Failed to parse invoice: {e:?}")))?;
+    Ok(InvoiceOutput::try_from(invoice)?)
+}
+
+/// Outcome of checking a payment preimage against an invoice
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PaymentVerification {
+    /// Whether SHA256(preimage) is the invoice's payment hash, i.e. whether the
+    /// preimage proves the invoice was paid
+    pub valid: bool,
+    pub payment_hash: PaymentHash,
+    pub preimage: String,
+    /// SHA256 of the preimage
+    pub preimage_hash: PaymentHash,
+    /// Node that issued the invoice and released the preimage
+    pub payee: PublicKey,
+    pub amount_msats: Option<u64>,
+    pub description: Option<String>,
+    pub description_hash: Option<Sha256Hash>,
+    pub timestamp: DateTime<Utc>,
 }
 
-pub fn decode_lnurl(input: &str) -> Result<LnurlOutput> {
+/// Check that `preimage` (32 bytes, hex) is the proof of payment of `invoice`
+pub fn verify_payment(invoice: &str, preimage: &str) -> CoreResult<PaymentVerification> {
+    let decoded = decode_invoice(invoice.trim())?;
+    let preimage = preimage.trim().to_lowercase();
+    let preimage_bytes = hex::decode(&preimage).context("Preimage is not valid hex")?;
+    ensure!(
+        preimage_bytes.len() == 32,
+        "Preimage must be 32 bytes, got {len}",
+        len = preimage_bytes.len()
+    );
+    let preimage_hash = PaymentHash::from_slice(
+        bitcoin::hashes::sha256::Hash::hash(&preimage_bytes).as_byte_array(),
+    )?;
+
+    Ok(PaymentVerification {
+        valid: preimage_hash == decoded.payment_hash,
+        payment_hash: decoded.payment_hash,
+        preimage,
+        preimage_hash,
+        payee: decoded.destination,
+        amount_msats: decoded.amount_msats,
+        description: decoded.description,
+        description_hash: decoded.description_hash,
+        timestamp: decoded.timestamp,
+    })
+}
+
+pub fn decode_lnurl(input: &str) -> CoreResult<LnurlOutput> {
     let input = input.trim();
-    anyhow::ensure!(
+    ensure!(
         input.to_uppercase().starts_with("LNURL"),
         "Input must start with 'LNURL'"
     );
 
     let (hrp, data) = bech32::decode(input)?;
     let hrp_str = hrp.as_str();
-    anyhow::ensure!(
+    ensure!(
         hrp == bech32::primitives::hrp::Hrp::parse("lnurl")?,
         "Invalid HRP (human-readable part): expected 'lnurl', got '{hrp_str}'"
     );
@@ -507,16 +639,23 @@ pub struct GeneratedInvoiceOutput {
 /// 1. Resolves the Lightning address to an LNURL-pay endpoint
 /// 2. Fetches payment request metadata from the endpoint
 /// 3. Generates an invoice with the specified amount and optional comment
+/// 4. Checks that the invoice is for that amount and that its description hash
+///    commits to the endpoint's metadata
+///
+/// Endpoints must use HTTPS (plain HTTP only for `.onion` hosts), and an error
+/// reported by the service is returned with its own reason.
 ///
 /// # Arguments
 /// * `address` - Lightning address in format user@domain.com
 /// * `amount` - Amount to request (supports various formats: BTC, sats, msats)
 /// * `comment` - Optional comment to include with payment request
+/// * `options` - Proxy and retries of the LNURL requests
 pub async fn generate_invoice_from_address(
     address: &str,
     amount: &crate::bitcoin_rpc::AmountInput,
     comment: Option<&str>,
-) -> Result<GeneratedInvoiceOutput> {
+    options: &ClientOptions,
+) -> CoreResult<GeneratedInvoiceOutput> {
     let amount_msats = amount.as_millisats();
     // Parse lightning address
     let parts: Vec<&str> = address.split('@').collect();
@@ -527,19 +666,35 @@ pub async fn generate_invoice_from_address(
 
     let (user, domain) = (parts[0], parts[1]);
 
-    // Construct the .well-known URL
-    let well_known_url = format!("https://{domain}/.well-known/lnurlp/{user}");
+    // LUD-16: onion services are reached over plain HTTP, everything else over HTTPS
+    let scheme = if domain.ends_with(".onion") {
+        "http"
+    } else {
+        "https"
+    };
+    let well_known_url = Url::parse(&format!("{scheme}://{domain}/.well-known/lnurlp/{user}"))
+        .with_context(|| format!("Invalid Lightning address domain: {domain}"))?;
 
     // Make initial request to get LNURL-pay request
-    let client = reqwest::Client::new();
-    let lnurl_pay_request: LnurlPayRequest =
-        client.get(&well_known_url).send().await?.json().await?;
+    let client = options.http_client()?;
+    let lnurl_pay_request: LnurlPayRequest = lnurl_get(
+        &client,
+        &options.retry,
+        &well_known_url,
+        "LNURL-pay request",
+    )
+    .await?;
+    ensure!(
+        lnurl_pay_request.tag == "payRequest",
+        "LNURL endpoint returned tag '{tag}', expected 'payRequest'",
+        tag = lnurl_pay_request.tag
+    );
 
     // Validate amount
     if amount_msats < lnurl_pay_request.min_sendable
         || amount_msats > lnurl_pay_request.max_sendable
     {
-        anyhow::bail!(
+        bail!(
             "Amount {amount_msats} msats is outside allowed range: {min_sendable} - {max_sendable} msats",
             min_sendable = lnurl_pay_request.min_sendable,
             max_sendable = lnurl_pay_request.max_sendable
@@ -551,14 +706,19 @@ pub async fn generate_invoice_from_address(
         && let Some(max_comment_len) = lnurl_pay_request.comment_allowed
         && comment.len() > max_comment_len as usize
     {
-        anyhow::bail!(
+        bail!(
             "Comment length {comment_len} exceeds maximum allowed length: {max_comment_len}",
             comment_len = comment.len()
         );
     }
 
     // Build callback URL with parameters
-    let mut callback_url = Url::parse(&lnurl_pay_request.callback)?;
+    let mut callback_url = Url::parse(&lnurl_pay_request.callback).with_context(|| {
+        format!(
+            "Invalid LNURL-pay callback URL: {callback}",
+            callback = lnurl_pay_request.callback
+        )
+    })?;
     callback_url
         .query_pairs_mut()
         .append_pair("amount", &amount_msats.to_string());
@@ -570,15 +730,12 @@ pub async fn generate_invoice_from_address(
     }
 
     // Make callback request to get invoice
-    let callback_response: LnurlPayCallback = client
-        .get(callback_url.as_str())
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    // Decode the received invoice
+    let callback_response: LnurlPayCallback =
+        lnurl_get(&client, &options.retry, &callback_url, "LNURL-pay callback").await?;
+
+    // Decode the received invoice and make sure it is the one we asked for
     let decoded_invoice = decode_invoice(&callback_response.payment_request)?;
+    check_lnurl_invoice(&decoded_invoice, &lnurl_pay_request.metadata, amount_msats)?;
 
     Ok(GeneratedInvoiceOutput {
         lightning_address: address.to_string(),
@@ -589,6 +746,91 @@ pub async fn generate_invoice_from_address(
     })
 }
 
+/// Reject LNURL endpoints that are neither HTTPS nor an onion service over HTTP
+fn check_lnurl_url(url: &Url) -> Result<()> {
+    let onion = url.host_str().is_some_and(|host| host.ends_with(".onion"));
+    ensure!(
+        url.scheme() == "https" || (url.scheme() == "http" && onion),
+        "LNURL endpoint {url} must use https (or http for a .onion host)"
+    );
+    Ok(())
+}
+
+/// GET an LNURL endpoint and parse its JSON response
+async fn lnurl_get<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    policy: &RetryPolicy,
+    url: &Url,
+    what: &str,
+) -> Result<T> {
+    check_lnurl_url(url)?;
+    Ok(retry(policy, what, || async {
+        let response = client.get(url.clone()).send().await?;
+        // Redirects must not downgrade the connection either
+        check_lnurl_url(response.url())?;
+        let status = response.status();
+        let body = response.text().await?;
+        parse_lnurl_response(status, &body, what)
+    })
+    .await?)
+}
+
+/// Parse an LNURL response body, surfacing the service's own error message.
+/// Services report errors as `{"status": "ERROR", "reason": "..."}`, with
+/// either a success or an error HTTP status.
+fn parse_lnurl_response<T: serde::de::DeserializeOwned>(
+    status: reqwest::StatusCode,
+    body: &str,
+    what: &str,
+) -> Result<T> {
+    let json = serde_json::from_str::<serde_json::Value>(body);
+    if let Ok(json) = &json
+        && json
+            .get("status")
+            .and_then(|status| status.as_str())
+            .is_some_and(|status| status.eq_ignore_ascii_case("error"))
+    {
+        let reason = json
+            .get("reason")
+            .and_then(|reason| reason.as_str())
+            .unwrap_or("no reason given");
+        bail!("{what} failed: {reason}");
+    }
+    if !status.is_success() {
+        return Err(crate::retry::status_error(
+            status,
+            format!("{what} failed with HTTP {status}: {body}"),
+        ));
+    }
+    serde_json::from_value(json.with_context(|| format!("{what} returned invalid JSON"))?)
+        .with_context(|| format!("{what} returned an unexpected response"))
+}
+
+/// Check that an LNURL-pay invoice is for the requested amount and commits to
+/// the endpoint's metadata (LUD-06: its description hash is SHA256 of the
+/// metadata string)
+fn check_lnurl_invoice(invoice: &InvoiceOutput, metadata: &str, amount_msats: u64) -> Result<()> {
+    match invoice.amount_msats {
+        Some(invoice_amount) if invoice_amount == amount_msats => {}
+        Some(invoice_amount) => bail!(
+            "LNURL-pay invoice is for {invoice_amount} msats, but {amount_msats} msats were requested"
+        ),
+        None => bail!("LNURL-pay invoice has no amount, but {amount_msats} msats were requested"),
+    }
+    let expected = bitcoin::hashes::sha256::Hash::hash(metadata.as_bytes()).to_byte_array();
+    match &invoice.description_hash {
+        Some(hash) if *hash.as_bytes() == expected => Ok(()),
+        Some(hash) => bail!(
+            "LNURL-pay invoice description hash {hash} does not match the endpoint's metadata hash {expected}",
+            hash = hash.to_hex(),
+            expected = hex::encode(expected)
+        ),
+        None => {
+            bail!("LNURL-pay invoice has no description hash committing to the endpoint's metadata")
+        }
+    }
+}
+
 /// Encode a Lightning invoice from JSON output structure back to BOLT11 string.
 ///
 /// This function takes an InvoiceOutput structure (typically from decoding)
@@ -604,7 +846,7 @@ pub async fn generate_invoice_from_address(
 pub fn encode_invoice(
     invoice_data: &InvoiceOutput,
     private_key: &bitcoin::secp256k1::SecretKey,
-) -> Result<String> {
+) -> CoreResult<String> {
     use bitcoin::hashes::Hash as BitcoinHash;
     use bitcoin::secp256k1::{Message, Secp256k1};
 
@@ -824,12 +1066,132 @@ mod tests {
         assert_eq!(route.fees.base_msat, 0);
         assert_eq!(route.fees.proportional_millionths, 0);
 
+        // Derived fields
+        assert_eq!(output.amount_sats, Some(9981031));
+        assert_eq!(output.amount_btc, Some(0.09981031));
+        assert_eq!(
+            output.expires_at,
+            Some(DateTime::parse_from_rfc3339("2025-02-09T18:29:50Z")?.with_timezone(&Utc))
+        );
+        assert!(output.expired);
+        assert_eq!(output.seconds_until_expiry, 0);
+        assert_eq!(output.route_hint_summaries.len(), 1);
+        let summary = &output.route_hint_summaries[0];
+        assert_eq!(summary.hops, 1);
+        assert_eq!(summary.entry_node, route.src_node_id);
+        assert_eq!(summary.cltv_expiry_delta, 40);
+        assert_eq!(summary.fee_msats, Some(0));
+
         // Empty fields
         assert!(output.fallback_addresses.is_empty());
 
         Ok(())
     }
 
+    #[test]
+    fn test_route_hint_summary_fees() -> Result<()> {
+        let hop = |node: &str, base_msat, proportional_millionths, cltv_expiry_delta| {
+            Ok::<_, anyhow::Error>(RouteHintHopOutput {
+                src_node_id: PublicKey::from_hex(node)?,
+                short_channel_id: 1,
+                fees: RoutingFeesOutput {
+                    base_msat,
+                    proportional_millionths,
+                },
+                cltv_expiry_delta,
+                htlc_minimum_msat: None,
+                htlc_maximum_msat: None,
+            })
+        };
+        let route = vec![
+            hop(
+                "021c97a90a411ff2b10dc2a8e32de2f29d2fa49d41bfbb52bd416e460db0747d0d",
+                1000,
+                100,
+                40,
+            )?,
+            hop(
+                "03fb2a0ca79c005f493f1faa83071d3a937cf220d4051dc48b8fe3a087879cf14a",
+                500,
+                1000,
+                144,
+            )?,
+        ];
+
+        // The last hop charges 500 + 1000ppm of 1_000_000; the first one
+        // 1000 + 100ppm of the 1_001_500 it forwards
+        let summary = RouteHintSummary::new(&route, Some(1_000_000)).context("summary")?;
+        assert_eq!(summary.hops, 2);
+        assert_eq!(summary.entry_node, route[0].src_node_id);
+        assert_eq!(summary.base_fee_msat, 1500);
+        assert_eq!(summary.proportional_millionths, 1100);
+        assert_eq!(summary.cltv_expiry_delta, 184);
+        assert_eq!(summary.fee_msats, Some(2600));
+
+        assert_eq!(
+            RouteHintSummary::new(&route, None)
+                .context("summary")?
+                .fee_msats,
+            None
+        );
+        assert!(RouteHintSummary::new(&[], Some(1_000_000)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_payment() -> Result<()> {
+        use bitcoin::secp256k1::SecretKey;
+
+        let private_key = SecretKey::from_slice(&[0x03; 32])?;
+        let preimage = [0x42u8; 32];
+        let payment_hash = PaymentHash::from_slice(
+            bitcoin::hashes::sha256::Hash::hash(&preimage).as_byte_array(),
+        )?;
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let destination = PublicKey::from_slice(
+            &bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &private_key).serialize(),
+        )?;
+        let invoice_data = InvoiceOutput {
+            network: Network::Bitcoin,
+            amount_msats: Some(50_000),
+            timestamp: DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")?.with_timezone(&Utc),
+            timestamp_millis: 1704067200000,
+            payment_hash: payment_hash.clone(),
+            payment_secret: PaymentSecret::from_slice(&[0x11; 32])?,
+            features: vec![],
+            description: Some("Coffee".to_string()),
+            description_hash: None,
+            destination: destination.clone(),
+            expiry_seconds: 3600,
+            min_final_cltv_expiry: 18,
+            fallback_addresses: vec![],
+            routes: vec![],
+            amount_sats: None,
+            amount_btc: None,
+            expires_at: None,
+            expired: false,
+            seconds_until_expiry: 0,
+            route_hint_summaries: vec![],
+        };
+        let invoice = encode_invoice(&invoice_data, &private_key)?;
+
+        let verification = verify_payment(&invoice, &hex::encode(preimage).to_uppercase())?;
+        assert!(verification.valid);
+        assert_eq!(verification.payment_hash, payment_hash);
+        assert_eq!(verification.preimage, hex::encode(preimage));
+        assert_eq!(verification.payee, destination);
+        assert_eq!(verification.amount_msats, Some(50_000));
+        assert_eq!(verification.description, Some("Coffee".to_string()));
+
+        let wrong = verify_payment(&invoice, &hex::encode([0x43u8; 32]))?;
+        assert!(!wrong.valid);
+        assert_ne!(wrong.preimage_hash, payment_hash);
+
+        assert!(verify_payment(&invoice, "abcd").is_err());
+        assert!(verify_payment(&invoice, "not hex").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_decode_lnurl() -> Result<()> {
         let lnurl = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E5K7TELWY7NXENRXVMRGDTZXSENJCM98PJNWXQ96S9";
@@ -865,6 +1227,48 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_lnurl_validation() -> Result<()> {
+        assert!(check_lnurl_url(&Url::parse("https://getalby.com/lnurlp/user")?).is_ok());
+        assert!(check_lnurl_url(&Url::parse("http://abcdef.onion/lnurlp/user")?).is_ok());
+        assert!(check_lnurl_url(&Url::parse("http://getalby.com/lnurlp/user")?).is_err());
+
+        // Service errors are surfaced verbatim, whatever the HTTP status
+        for status in [reqwest::StatusCode::OK, reqwest::StatusCode::BAD_REQUEST] {
+            let error = parse_lnurl_response::<LnurlPayCallback>(
+                status,
+                r#"{"status":"ERROR","reason":"Amount is too small"}"#,
+                "LNURL-pay callback",
+            )
+            .expect_err("error response")
+            .to_string();
+            assert_eq!(error, "LNURL-pay callback failed: Amount is too small");
+        }
+        let callback: LnurlPayCallback = parse_lnurl_response(
+            reqwest::StatusCode::OK,
+            r#"{"pr":"lnbc1...","routes":[]}"#,
+            "LNURL-pay callback",
+        )?;
+        assert_eq!(callback.payment_request, "lnbc1...");
+
+        let metadata = r#"[["text/plain","Pay to user"]]"#;
+        let mut invoice = decode_invoice(
+            "lnbc99810310n1pju0sy7pp555srgtgcg6t4jr4j5v0jysgee4zy6nr4msylnycfjezxm5w6t3csdy9wdmkzupq95s8xcmjd9c8gw3qx5cnyvrrvymrwvnrxgmrzd3cxsckxdf4v3jxgcmzx9jxgenpxserjenyxv6nzwf3vsmnyctxvsuxvdehvdnrswryxgcnzdf5ve3rjvph8q6njcqzxgxq97zvuqrzjqgwf02g2gy0l9vgdc25wxt0z72wjlfyagxlmk54ag9hyvrdsw37smapyqqqqqqqq2qqqqqqqqqqqqqqq9qsp59ge5l9ndweyes4ntfrws3a3tshpkqt8eysuxnt5pmucy9hvxthmq9qyyssqaqwn0j2jf2xvcv42yl9p0yaw4t6gcqld2t44cmnfud49dxgl3dnpnjpj75kaf22yuynqtc8uzmtuckzxvfunxnr405gud8cexc5axqqphlk58z",
+        )?;
+        invoice.description_hash = Some(Sha256Hash::from_slice(
+            &bitcoin::hashes::sha256::Hash::hash(metadata.as_bytes()).to_byte_array(),
+        )?);
+        check_lnurl_invoice(&invoice, metadata, 9981031000)?;
+        let error = check_lnurl_invoice(&invoice, metadata, 1000)
+            .expect_err("amount mismatch")
+            .to_string();
+        assert!(error.contains("is for 9981031000 msats"), "{error}");
+        assert!(check_lnurl_invoice(&invoice, "[]", 9981031000).is_err());
+        invoice.description_hash = None;
+        assert!(check_lnurl_invoice(&invoice, metadata, 9981031000).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_parse_lightning_address() -> Result<()> {
         let address = "user@domain.com";
@@ -942,6 +1346,12 @@ mod tests {
             min_final_cltv_expiry: 18,
             fallback_addresses: vec![],
             routes: vec![],
+            amount_sats: None,
+            amount_btc: None,
+            expires_at: None,
+            expired: false,
+            seconds_until_expiry: 0,
+            route_hint_summaries: vec![],
         };
 
         // Encode the invoice
@@ -1013,6 +1423,12 @@ mod tests {
             min_final_cltv_expiry: 144,
             fallback_addresses: vec![],
             routes: vec![],
+            amount_sats: None,
+            amount_btc: None,
+            expires_at: None,
+            expired: false,
+            seconds_until_expiry: 0,
+            route_hint_summaries: vec![],
         };
 
         let encoded = encode_invoice(&invoice_data, &private_key)?;
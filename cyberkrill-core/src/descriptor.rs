@@ -0,0 +1,1213 @@
+//! Offline output descriptor tools
+//!
+//! Checksums (BIP380), a summary of the script type and key origins, a
+//! plain-language walk through the spending conditions, and expansion of ranged
+//! descriptors to concrete addresses, so a descriptor can be checked before it
+//! is handed to a wallet or a scan. A bare extended public key becomes a
+//! single-key descriptor with [`xpub_descriptor`], so addresses can be derived
+//! from it on an air-gapped machine as well.
+
+use crate::error::{CoreResult, bail, ensure};
+use anyhow::{Context, Result};
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey, ForEachKey};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Network, NetworkKind};
+use serde::Serialize;
+use std::str::FromStr;
+use strum::{Display, EnumString};
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LENGTH: usize = 8;
+
+/// Absolute locktimes below this are block heights, above it UNIX timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// BIP68: relative locktime in units of 512 seconds rather than blocks
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_VALUE_MASK: u32 = 0xffff;
+
+/// Most descriptors (or paths of a multipath descriptor) scanned at the same time
+pub const MAX_PARALLEL_DESCRIPTOR_SCANS: usize = 4;
+
+/// A descriptor with its checksum
+#[derive(Debug, Clone, Serialize)]
+pub struct DescriptorChecksum {
+    /// Descriptor with `#checksum` appended
+    pub descriptor: String,
+    pub checksum: String,
+    /// Whether the input already carried the (correct) checksum
+    pub provided: bool,
+}
+
+/// Key found in a descriptor
+#[derive(Debug, Clone, Serialize)]
+pub struct DescriptorKey {
+    pub key: String,
+    pub master_fingerprint: String,
+    /// Derivation path from the master key to this key, when the origin is given
+    pub origin_path: Option<String>,
+    /// Ends in a `*` wildcard
+    pub ranged: bool,
+    /// Has a `<a;b>` multipath step
+    pub multipath: bool,
+}
+
+/// What a descriptor describes
+#[derive(Debug, Clone, Serialize)]
+pub struct DescriptorSummary {
+    /// Canonical form, with checksum
+    pub descriptor: String,
+    pub checksum: String,
+    /// e.g. `p2wpkh`, `p2sh-p2wsh`, `p2tr`
+    pub script_type: String,
+    pub ranged: bool,
+    pub multipath: bool,
+    pub keys: Vec<DescriptorKey>,
+}
+
+/// Address derived from a descriptor
+#[derive(Debug, Clone, Serialize)]
+pub struct DerivedAddress {
+    /// Position in a `<a;b;...>` multipath step (0 is usually receive, 1 change)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<usize>,
+    pub index: u32,
+    /// Address, or `script:<hex>` for scripts without one
+    pub address: String,
+    pub script_pubkey: String,
+}
+
+/// BIP380 checksum of `descriptor`, which must not include a `#checksum`
+pub fn descriptor_checksum(descriptor: &str) -> CoreResult<String> {
+    fn polymod(c: u64, value: u64) -> u64 {
+        const GENERATORS: [u64; 5] = [
+            0xf5dee51989,
+            0xa9fdca3312,
+            0x1bab10e32d,
+            0x3706b1677a,
+            0x644d626ffd,
+        ];
+        let top = c >> 35;
+        let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+        for (bit, generator) in GENERATORS.iter().enumerate() {
+            if (top >> bit) & 1 == 1 {
+                c ^= generator;
+            }
+        }
+        c
+    }
+
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET
+            .find(ch)
+            .with_context(|| format!("Invalid character {ch:?} in descriptor"))?
+            as u64;
+        c = polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..CHECKSUM_LENGTH {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    Ok((0..CHECKSUM_LENGTH)
+        .map(|i| CHECKSUM_CHARSET[((c >> (5 * (CHECKSUM_LENGTH - 1 - i))) & 31) as usize] as char)
+        .collect())
+}
+
+/// Compute the checksum of `descriptor`, checking it against the one given, if any
+pub fn check_descriptor_checksum(descriptor: &str) -> CoreResult<DescriptorChecksum> {
+    let descriptor = descriptor.trim();
+    let (body, provided) = match descriptor.split_once('#') {
+        Some((body, checksum)) => (body, Some(checksum)),
+        None => (descriptor, None),
+    };
+    let checksum = descriptor_checksum(body)?;
+    if let Some(provided) = provided {
+        ensure!(
+            provided == checksum,
+            "Invalid descriptor checksum #{provided}; expected #{checksum}"
+        );
+    }
+    Ok(DescriptorChecksum {
+        descriptor: format!("{body}#{checksum}"),
+        checksum,
+        provided: provided.is_some(),
+    })
+}
+
+/// Expand a BIP389 multipath descriptor into one descriptor per path.
+///
+/// Every `<a;b;...>` specifier must have the same number of elements and the
+/// i-th descriptor takes the i-th element of each, so `<0;1>` yields the receive
+/// and change descriptors. Specifiers may appear at any step of a key's
+/// derivation path (but not in its origin, and at most once per key) and in any
+/// nested expression, such as taproot leaves. A descriptor without specifiers is
+/// returned as is; expanded ones drop the `#checksum`, which only matched the
+/// multipath form.
+pub fn expand_multipath_descriptor(descriptor: &str) -> CoreResult<Vec<String>> {
+    let descriptor = descriptor.trim();
+    let body = descriptor
+        .split_once('#')
+        .map_or(descriptor, |(body, _)| body);
+
+    // Text between specifiers, and the elements of each specifier
+    let mut literals = Vec::new();
+    let mut specifiers: Vec<Vec<&str>> = Vec::new();
+    let mut literal_start = 0;
+    let mut in_origin = false;
+    let mut key_has_specifier = false;
+    let mut position = 0;
+    while let Some(offset) = body[position..].find(['[', ']', ',', '(', ')', '{', '}', '<', '>']) {
+        let index = position + offset;
+        position = index + 1;
+        match body.as_bytes()[index] {
+            b'[' => in_origin = true,
+            b']' => in_origin = false,
+            b'<' => {
+                ensure!(
+                    !in_origin,
+                    "Multipath specifier not allowed in a key origin: {descriptor}"
+                );
+                ensure!(
+                    !key_has_specifier,
+                    "Key with more than one multipath specifier: {descriptor}"
+                );
+                let end = body[index..]
+                    .find('>')
+                    .map(|end| index + end)
+                    .with_context(|| format!("Unterminated multipath specifier: {descriptor}"))?;
+                specifiers.push(multipath_elements(&body[index + 1..end])?);
+                literals.push(&body[literal_start..index]);
+                literal_start = end + 1;
+                position = end + 1;
+                key_has_specifier = true;
+            }
+            b'>' => bail!("Unexpected '>' in descriptor: {descriptor}"),
+            // Anything else ends the current key
+            _ => key_has_specifier = false,
+        }
+    }
+    literals.push(&body[literal_start..]);
+
+    let Some(paths) = specifiers.first().map(Vec::len) else {
+        return Ok(vec![descriptor.to_string()]);
+    };
+    ensure!(
+        specifiers.iter().all(|elements| elements.len() == paths),
+        "Multipath specifiers have mismatched lengths: {descriptor}"
+    );
+    Ok((0..paths)
+        .map(|path| {
+            let mut expanded = literals[0].to_string();
+            for (elements, literal) in specifiers.iter().zip(&literals[1..]) {
+                expanded.push_str(elements[path]);
+                expanded.push_str(literal);
+            }
+            expanded
+        })
+        .collect())
+}
+
+/// Combine separate receive and change descriptors into one multipath
+/// descriptor with a checksum.
+///
+/// Many wallets export the two keychains as separate strings. They must be the
+/// same descriptor except for one derivation step per key (usually `/0/*` and
+/// `/1/*`), which becomes an `<a;b>` specifier, so the result expands back to
+/// the two descriptors given.
+pub fn combine_descriptors(receive: &str, change: &str) -> CoreResult<String> {
+    let receive = check_descriptor_checksum(receive).context("Invalid receive descriptor")?;
+    let change = check_descriptor_checksum(change).context("Invalid change descriptor")?;
+    let receive = receive
+        .descriptor
+        .split_once('#')
+        .map_or(receive.descriptor.as_str(), |(body, _)| body);
+    let change = change
+        .descriptor
+        .split_once('#')
+        .map_or(change.descriptor.as_str(), |(body, _)| body);
+    ensure!(
+        !receive.contains('<') && !change.contains('<'),
+        "The receive descriptor is already multipath; give it without a change descriptor"
+    );
+
+    let receive_steps: Vec<&str> = receive.split('/').collect();
+    let change_steps: Vec<&str> = change.split('/').collect();
+    ensure!(
+        receive_steps.len() == change_steps.len(),
+        "Receive and change descriptors have different derivation paths"
+    );
+    let is_step = |c: char| c.is_ascii_digit() || matches!(c, '\'' | 'h' | 'H');
+    let mut combined = Vec::with_capacity(receive_steps.len());
+    for (receive_step, change_step) in receive_steps.iter().zip(&change_steps) {
+        if receive_step == change_step {
+            combined.push(receive_step.to_string());
+            continue;
+        }
+        // A path step, followed by the same text (e.g. "0)" and "1)")
+        let receive_rest = receive_step.trim_start_matches(is_step);
+        let change_rest = change_step.trim_start_matches(is_step);
+        let receive_index = &receive_step[..receive_step.len() - receive_rest.len()];
+        let change_index = &change_step[..change_step.len() - change_rest.len()];
+        ensure!(
+            receive_rest == change_rest && !receive_index.is_empty() && !change_index.is_empty(),
+            "Receive and change descriptors differ beyond a derivation step: \
+             '{receive_step}' and '{change_step}'"
+        );
+        combined.push(format!("<{receive_index};{change_index}>{receive_rest}"));
+    }
+    let combined = combined.join("/");
+    ensure!(
+        combined != receive,
+        "Receive and change descriptors are the same"
+    );
+
+    // Specifiers in key origins, or in only some keys, don't expand back
+    let expanded = expand_multipath_descriptor(&combined)
+        .context("Receive and change descriptors can't be combined")?;
+    ensure!(
+        expanded == [receive, change],
+        "Receive and change descriptors can't be combined"
+    );
+    Ok(check_descriptor_checksum(&combined)?.descriptor)
+}
+
+/// Elements of a multipath specifier (the text between `<` and `>`)
+fn multipath_elements(specifier: &str) -> Result<Vec<&str>> {
+    let elements: Vec<&str> = specifier.split(';').collect();
+    ensure!(
+        elements.len() >= 2,
+        "Multipath specifier <{specifier}> must have at least two elements"
+    );
+    let mut steps = Vec::with_capacity(elements.len());
+    for element in &elements {
+        let (number, hardened) = match element.strip_suffix(['\'', 'h', 'H']) {
+            Some(number) => (number, true),
+            None => (*element, false),
+        };
+        let index = number
+            .parse::<u32>()
+            .ok()
+            .filter(|index| *index < 1 << 31 && number.bytes().all(|b| b.is_ascii_digit()))
+            .with_context(|| format!("Invalid derivation step '{element}' in <{specifier}>"))?;
+        ensure!(
+            !steps.contains(&(index, hardened)),
+            "Duplicate derivation step '{element}' in <{specifier}>"
+        );
+        steps.push((index, hardened));
+    }
+    Ok(elements)
+}
+
+/// Parse and validate `descriptor`, checking its checksum when present
+fn parse(descriptor: &str) -> Result<Descriptor<DescriptorPublicKey>> {
+    let descriptor = descriptor.trim();
+    check_descriptor_checksum(descriptor)?;
+    Descriptor::<DescriptorPublicKey>::from_str(descriptor)
+        .with_context(|| format!("Invalid descriptor '{descriptor}'"))
+}
+
+/// Script type, key origins and range of `descriptor`
+pub fn parse_descriptor(descriptor: &str) -> CoreResult<DescriptorSummary> {
+    let parsed = parse(descriptor)?;
+    let canonical = check_descriptor_checksum(&parsed.to_string())?;
+
+    let mut keys = Vec::new();
+    parsed.for_each_key(|key| {
+        let text = key.to_string();
+        let origin_path = text
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+            .and_then(|(origin, _)| origin.split_once('/'))
+            .map(|(_, path)| format!("m/{path}"));
+        keys.push(DescriptorKey {
+            key: text,
+            master_fingerprint: key.master_fingerprint().to_string(),
+            origin_path,
+            ranged: key.has_wildcard(),
+            multipath: key.is_multipath(),
+        });
+        true
+    });
+
+    let tree = ScriptNode::parse(&canonical.descriptor)?;
+    Ok(DescriptorSummary {
+        script_type: tree.script_type(),
+        ranged: parsed.has_wildcard(),
+        multipath: parsed.is_multipath(),
+        descriptor: canonical.descriptor,
+        checksum: canonical.checksum,
+        keys,
+    })
+}
+
+/// Plain-language outline of what it takes to spend from `descriptor`
+pub fn explain_descriptor(descriptor: &str) -> CoreResult<String> {
+    let parsed = parse(descriptor)?;
+    let canonical = check_descriptor_checksum(&parsed.to_string())?;
+    let tree = ScriptNode::parse(&canonical.descriptor)?;
+    let mut lines = Vec::new();
+    tree.explain_output(&mut lines);
+    Ok(lines.join("\n"))
+}
+
+/// `count` addresses of `descriptor` from derivation index `start`, for every
+/// branch of a multipath descriptor. A descriptor without a wildcard has a
+/// single address.
+pub fn expand_descriptor(
+    descriptor: &str,
+    network: Network,
+    start: u32,
+    count: u32,
+) -> CoreResult<Vec<DerivedAddress>> {
+    let parsed = parse(descriptor)?;
+    let multipath = parsed.is_multipath();
+    let branches = parsed
+        .into_single_descriptors()
+        .context("Failed to split the multipath descriptor")?;
+
+    let mut addresses = Vec::new();
+    for (branch, single) in branches.iter().enumerate() {
+        let indexes = if single.has_wildcard() {
+            let end = start
+                .checked_add(count)
+                .context("Derivation index range overflows")?;
+            start..end
+        } else {
+            0..1
+        };
+        for index in indexes {
+            let derived = single
+                .at_derivation_index(index)
+                .with_context(|| format!("Failed to derive index {index}"))?;
+            let script = derived.script_pubkey();
+            let address = match derived.address(network) {
+                Ok(address) => address.to_string(),
+                Err(_) => format!("script:{script}", script = script.to_hex_string()),
+            };
+            addresses.push(DerivedAddress {
+                branch: multipath.then_some(branch),
+                index,
+                address,
+                script_pubkey: script.to_hex_string(),
+            });
+        }
+    }
+    Ok(addresses)
+}
+
+/// Addresses derived offline from a descriptor
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressDerivation {
+    /// Descriptor the addresses come from, with checksum
+    pub descriptor: String,
+    pub network: String,
+    pub addresses: Vec<DerivedAddress>,
+}
+
+/// Derive the addresses at indexes `start..=end` of every branch of
+/// `descriptor`, after checking its checksum (when given) and that its keys
+/// are for `network`
+pub fn derive_addresses(
+    descriptor: &str,
+    network: Network,
+    start: u32,
+    end: u32,
+) -> CoreResult<AddressDerivation> {
+    ensure!(
+        start <= end,
+        "Invalid index range {start}-{end}: the start is past the end"
+    );
+    let descriptor = check_descriptor_checksum(descriptor)?.descriptor;
+    check_descriptor_network(&descriptor, network)?;
+    let count = (end - start)
+        .checked_add(1)
+        .context("Derivation index range overflows")?;
+    Ok(AddressDerivation {
+        addresses: expand_descriptor(&descriptor, network, start, count)?,
+        descriptor,
+        network: network.to_string(),
+    })
+}
+
+/// Parse an inclusive index range such as `0-50`, or a single index
+pub fn parse_index_range(range: &str) -> CoreResult<(u32, u32)> {
+    let parse = |index: &str| {
+        index
+            .trim()
+            .parse::<u32>()
+            .with_context(|| format!("Invalid derivation index '{index}' in range '{range}'"))
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => {
+            let index = parse(range)?;
+            (index, index)
+        }
+    };
+    ensure!(
+        start <= end,
+        "Invalid index range '{range}': the start is past the end"
+    );
+    Ok((start, end))
+}
+
+/// Script of a single-key descriptor built from an extended public key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum XpubScriptType {
+    /// Legacy P2PKH
+    Pkh,
+    /// P2WPKH nested in P2SH
+    ShWpkh,
+    /// Native segwit P2WPKH
+    Wpkh,
+    /// Taproot key path
+    Tr,
+}
+
+/// Receive/change descriptor (`/<0;1>/*`) of an account-level extended public
+/// key in any SLIP-132 format. Without `script_type`, the one implied by the
+/// prefix is used (`ypub` is sh-wpkh, `zpub` is wpkh); `xpub`/`tpub` don't
+/// imply one and need it given.
+pub fn xpub_descriptor(xpub: &str, script_type: Option<XpubScriptType>) -> CoreResult<String> {
+    use crate::slip132::Slip132Format;
+
+    let converted = crate::slip132::convert_xpub(xpub, None)?;
+    let implied = match converted.input_format {
+        Slip132Format::Ypub | Slip132Format::Upub => Some(XpubScriptType::ShWpkh),
+        Slip132Format::Zpub | Slip132Format::Vpub => Some(XpubScriptType::Wpkh),
+        Slip132Format::Xpub | Slip132Format::Tpub => None,
+        multisig => bail!(
+            "{multisig} is a multisig key; build a multisig descriptor (e.g. with onchain-multisig-setup) instead"
+        ),
+    };
+    let script_type = script_type.or(implied).with_context(|| {
+        format!(
+            "{format} keys don't imply a script type; choose one (pkh, sh-wpkh, wpkh or tr)",
+            format = converted.input_format
+        )
+    })?;
+    let key = format!("{standard}/<0;1>/*", standard = converted.standard);
+    let body = match script_type {
+        XpubScriptType::Pkh => format!("pkh({key})"),
+        XpubScriptType::ShWpkh => format!("sh(wpkh({key}))"),
+        XpubScriptType::Wpkh => format!("wpkh({key})"),
+        XpubScriptType::Tr => format!("tr({key})"),
+    };
+    Ok(check_descriptor_checksum(&body)?.descriptor)
+}
+
+/// Check that the extended keys of `descriptor` are for `network`, so its
+/// receive and change addresses derive on that chain. Private descriptors are
+/// accepted too; single keys carry no network and always pass.
+pub fn check_descriptor_network(descriptor: &str, network: Network) -> CoreResult<()> {
+    let secp = Secp256k1::new();
+    let (parsed, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())
+        .with_context(|| format!("Invalid descriptor '{descriptor}'"))?;
+    let expected = NetworkKind::from(network);
+    let mut mismatched = None;
+    parsed.for_each_key(|key| {
+        let kind = match key {
+            DescriptorPublicKey::XPub(xkey) => Some(xkey.xkey.network),
+            DescriptorPublicKey::MultiXPub(xkey) => Some(xkey.xkey.network),
+            DescriptorPublicKey::Single(_) => None,
+        };
+        if kind.is_some_and(|kind| kind != expected) {
+            mismatched = Some(key.to_string());
+            return false;
+        }
+        true
+    });
+    if let Some(key) = mismatched {
+        let found = match expected {
+            NetworkKind::Main => "a test network",
+            NetworkKind::Test => "mainnet",
+        };
+        bail!("Descriptor key {key} is for {found}, not {network}");
+    }
+    Ok(())
+}
+
+/// Address of a single-key descriptor together with the key's full derivation,
+/// so a hardware wallet can be asked for the same address
+#[derive(Debug, Clone, Serialize)]
+pub struct SingleKeyAddress {
+    pub address: String,
+    pub master_fingerprint: String,
+    /// Path from the master key, e.g. `m/84'/0'/0'/0/5`
+    pub derivation_path: String,
+}
+
+/// Derive the address at `index` on `branch` of a single-key descriptor.
+/// `branch` selects the `<a;b;...>` element and must be 0 for descriptors
+/// without a multipath step.
+pub fn derive_single_key_address(
+    descriptor: &str,
+    network: Network,
+    branch: usize,
+    index: u32,
+) -> CoreResult<SingleKeyAddress> {
+    let singles = parse(descriptor)?
+        .into_single_descriptors()
+        .context("Failed to split the multipath descriptor")?;
+    let single = singles.get(branch).with_context(|| {
+        format!(
+            "Descriptor has no branch {branch} (it has {count})",
+            count = singles.len()
+        )
+    })?;
+    let derived = single
+        .at_derivation_index(index)
+        .with_context(|| format!("Failed to derive index {index}"))?;
+
+    let mut keys = Vec::new();
+    derived.for_each_key(|key| {
+        keys.push(key.clone());
+        true
+    });
+    let [key] = keys.as_slice() else {
+        bail!(
+            "Expected a single-key descriptor, found {count} keys",
+            count = keys.len()
+        );
+    };
+    let path = key
+        .as_descriptor_public_key()
+        .full_derivation_path()
+        .context("Descriptor key has no single derivation path")?;
+    let address = derived
+        .address(network)
+        .context("Descriptor has no address form")?;
+
+    Ok(SingleKeyAddress {
+        address: address.to_string(),
+        master_fingerprint: key.master_fingerprint().to_string(),
+        derivation_path: format!("m/{path}"),
+    })
+}
+
+/// Descriptor expression: a fragment with arguments, or a bare value (key,
+/// number, hash). Taproot script trees `{a,b}` are fragments named `{}`.
+#[derive(Debug, Clone, PartialEq)]
+struct ScriptNode {
+    name: String,
+    args: Vec<ScriptNode>,
+}
+
+impl ScriptNode {
+    fn parse(descriptor: &str) -> Result<Self> {
+        let body = descriptor
+            .split_once('#')
+            .map_or(descriptor, |(body, _)| body);
+        let mut rest = body.trim();
+        let node = Self::parse_expression(&mut rest)?;
+        ensure!(
+            rest.is_empty(),
+            "Unexpected '{rest}' at the end of the descriptor"
+        );
+        Ok(node)
+    }
+
+    fn parse_expression(input: &mut &str) -> Result<Self> {
+        if let Some(rest) = input.strip_prefix('{') {
+            *input = rest;
+            let args = Self::parse_arguments(input, '}')?;
+            return Ok(Self {
+                name: "{}".to_string(),
+                args,
+            });
+        }
+        let end = input.find(['(', ')', ',', '{', '}']).unwrap_or(input.len());
+        let name = input[..end].to_string();
+        *input = &input[end..];
+        let args = match input.strip_prefix('(') {
+            Some(rest) => {
+                *input = rest;
+                Self::parse_arguments(input, ')')?
+            }
+            None => Vec::new(),
+        };
+        Ok(Self { name, args })
+    }
+
+    fn parse_arguments(input: &mut &str, close: char) -> Result<Vec<Self>> {
+        let mut args = vec![Self::parse_expression(input)?];
+        loop {
+            let Some(next) = input.chars().next() else {
+                bail!("Missing '{close}' in descriptor");
+            };
+            *input = &input[next.len_utf8()..];
+            match next {
+                ',' => args.push(Self::parse_expression(input)?),
+                c if c == close => return Ok(args),
+                c => bail!("Unexpected '{c}' in descriptor"),
+            }
+        }
+    }
+
+    /// Fragment name without miniscript wrappers (`v:`, `s:`, ...), which
+    /// don't change what is required to spend
+    fn fragment(&self) -> &str {
+        self.name
+            .rsplit_once(':')
+            .map_or(self.name.as_str(), |(_, fragment)| fragment)
+    }
+
+    fn arg(&self, index: usize) -> &str {
+        self.args.get(index).map_or("", |arg| arg.name.as_str())
+    }
+
+    fn script_type(&self) -> String {
+        let inner = self.args.first().map(Self::fragment);
+        match (self.fragment(), inner) {
+            ("sh", Some("wpkh")) => "p2sh-p2wpkh",
+            ("sh", Some("wsh")) => "p2sh-p2wsh",
+            ("sh", _) => "p2sh",
+            ("wsh", _) => "p2wsh",
+            ("wpkh", _) => "p2wpkh",
+            ("pkh", _) => "p2pkh",
+            ("pk", _) => "p2pk",
+            ("tr" | "rawtr", _) => "p2tr",
+            ("multi" | "sortedmulti", _) => "bare-multisig",
+            ("addr", _) => "address",
+            (other, _) => other,
+        }
+        .to_string()
+    }
+
+    /// Lines for the output-level fragment (the descriptor's outer layer)
+    fn explain_output(&self, lines: &mut Vec<String>) {
+        let key = || short_key(self.arg(0));
+        match self.fragment() {
+            "sh" => {
+                lines.push("P2SH (pay to script hash) of:".to_string());
+                self.explain_children(lines, 0, Self::explain_output);
+            }
+            "wsh" => {
+                lines.push("P2WSH (native segwit v0 script) requiring:".to_string());
+                self.explain_children(lines, 0, Self::explain_script);
+            }
+            "wpkh" => lines.push(format!(
+                "P2WPKH (native segwit v0): signature from {key}",
+                key = key()
+            )),
+            "pkh" => lines.push(format!("P2PKH (legacy): signature from {key}", key = key())),
+            "pk" => lines.push(format!("P2PK: signature from {key}", key = key())),
+            "combo" => lines.push(format!(
+                "P2PK, P2PKH and, for compressed keys, P2WPKH and P2SH-P2WPKH outputs of {key}",
+                key = key()
+            )),
+            "tr" => {
+                lines.push(format!(
+                    "Taproot: key path spend with a signature from {key}",
+                    key = key()
+                ));
+                if let Some(tree) = self.args.get(1) {
+                    lines.push("or a script path spend with any of:".to_string());
+                    let mut leaves = Vec::new();
+                    tree.taproot_leaves(&mut leaves);
+                    for leaf in leaves {
+                        let mut leaf_lines = Vec::new();
+                        leaf.explain_script(&mut leaf_lines);
+                        indent(lines, leaf_lines, 1);
+                    }
+                }
+            }
+            "rawtr" => lines.push(format!(
+                "Taproot output key {key} (script paths, if any, are not known)",
+                key = key()
+            )),
+            "addr" => lines.push(format!("Address {address}", address = self.arg(0))),
+            "raw" => lines.push(format!("Raw script {script}", script = self.arg(0))),
+            _ => self.explain_script(lines),
+        }
+    }
+
+    /// Lines for a (mini)script fragment
+    fn explain_script(&self, lines: &mut Vec<String>) {
+        let key = || short_key(self.arg(0));
+        match self.fragment() {
+            "pk" | "pkh" | "pk_k" | "pk_h" => {
+                lines.push(format!("Signature from {key}", key = key()))
+            }
+            fragment @ ("multi" | "sortedmulti" | "multi_a" | "sortedmulti_a") => {
+                let sorted = if fragment.starts_with("sorted") {
+                    " (keys sorted)"
+                } else {
+                    ""
+                };
+                lines.push(format!(
+                    "{threshold}-of-{count} multisig{sorted} with keys:",
+                    threshold = self.arg(0),
+                    count = self.args.len().saturating_sub(1)
+                ));
+                for key in self.args.iter().skip(1) {
+                    lines.push(format!("  - {key}", key = short_key(&key.name)));
+                }
+            }
+            "thresh" => {
+                lines.push(format!(
+                    "{threshold} of these {count}:",
+                    threshold = self.arg(0),
+                    count = self.args.len().saturating_sub(1)
+                ));
+                self.explain_children(lines, 1, Self::explain_script);
+            }
+            "and_v" | "and_b" | "and_n" => {
+                lines.push("All of:".to_string());
+                self.explain_children(lines, 0, Self::explain_script);
+            }
+            "or_b" | "or_c" | "or_d" | "or_i" => {
+                lines.push("Any of:".to_string());
+                self.explain_children(lines, 0, Self::explain_script);
+            }
+            "andor" => {
+                lines.push("Either all of:".to_string());
+                for child in self.args.iter().take(2) {
+                    let mut child_lines = Vec::new();
+                    child.explain_script(&mut child_lines);
+                    indent(lines, child_lines, 1);
+                }
+                lines.push("or:".to_string());
+                if let Some(child) = self.args.get(2) {
+                    let mut child_lines = Vec::new();
+                    child.explain_script(&mut child_lines);
+                    indent(lines, child_lines, 1);
+                }
+            }
+            "older" => lines.push(describe_relative_timelock(self.arg(0))),
+            "after" => lines.push(describe_absolute_timelock(self.arg(0))),
+            hash @ ("sha256" | "hash256" | "ripemd160" | "hash160") => lines.push(format!(
+                "Preimage of {hash} hash {digest}",
+                hash = hash.to_uppercase(),
+                digest = self.arg(0)
+            )),
+            "0" => lines.push("Never satisfiable".to_string()),
+            "1" => lines.push("Always satisfiable".to_string()),
+            _ => lines.push(self.to_string()),
+        }
+    }
+
+    /// Explain the arguments from `skip` on, one level deeper
+    fn explain_children(
+        &self,
+        lines: &mut Vec<String>,
+        skip: usize,
+        explain: fn(&Self, &mut Vec<String>),
+    ) {
+        for child in self.args.iter().skip(skip) {
+            let mut child_lines = Vec::new();
+            explain(child, &mut child_lines);
+            indent(lines, child_lines, 1);
+        }
+    }
+
+    /// Scripts at the leaves of a taproot tree
+    fn taproot_leaves<'a>(&'a self, leaves: &mut Vec<&'a Self>) {
+        if self.name == "{}" {
+            for branch in &self.args {
+                branch.taproot_leaves(leaves);
+            }
+        } else {
+            leaves.push(self);
+        }
+    }
+}
+
+impl std::fmt::Display for ScriptNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        match (self.name.as_str(), self.args.is_empty()) {
+            ("{}", _) => write!(f, "{{{args}}}"),
+            (name, true) => write!(f, "{name}"),
+            (name, false) => write!(f, "{name}({args})"),
+        }
+    }
+}
+
+/// Append `child_lines` indented by `depth` levels
+fn indent(lines: &mut Vec<String>, child_lines: Vec<String>, depth: usize) {
+    let prefix = "  ".repeat(depth);
+    lines.extend(
+        child_lines
+            .into_iter()
+            .map(|line| format!("{prefix}{line}")),
+    );
+}
+
+/// `key` with a long extended key shortened to its ends, keeping the origin and
+/// derivation steps that tell keys apart
+fn short_key(key: &str) -> String {
+    let (origin, rest) = match key.split_once(']') {
+        Some((origin, rest)) => (&key[..origin.len() + 1], rest),
+        None => ("", key),
+    };
+    let (base, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if base.len() <= 24 || !base.is_ascii() {
+        return key.to_string();
+    }
+    format!(
+        "{origin}{head}…{tail}{path}",
+        head = &base[..8],
+        tail = &base[base.len() - 4..]
+    )
+}
+
+/// `older(n)` in words (BIP68 relative locktime)
+fn describe_relative_timelock(value: &str) -> String {
+    let Ok(sequence) = value.parse::<u32>() else {
+        return format!("Relative timelock {value}");
+    };
+    let amount = sequence & SEQUENCE_VALUE_MASK;
+    if sequence & SEQUENCE_TYPE_FLAG != 0 {
+        let seconds = u64::from(amount) * 512;
+        format!(
+            "Relative timelock: {seconds} seconds (~{approx}) after the output confirms",
+            approx = approximate_duration(seconds)
+        )
+    } else {
+        format!(
+            "Relative timelock: {amount} blocks (~{approx}) after the output confirms",
+            approx = approximate_duration(u64::from(amount) * 600)
+        )
+    }
+}
+
+/// `after(n)` in words (absolute locktime)
+fn describe_absolute_timelock(value: &str) -> String {
+    let Ok(locktime) = value.parse::<u32>() else {
+        return format!("Absolute timelock {value}");
+    };
+    if locktime < LOCKTIME_THRESHOLD {
+        return format!("Absolute timelock: after block height {locktime}");
+    }
+    match chrono::DateTime::from_timestamp(i64::from(locktime), 0) {
+        Some(time) => format!(
+            "Absolute timelock: after {time} (median time past)",
+            time = time.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        None => format!("Absolute timelock: after UNIX time {locktime}"),
+    }
+}
+
+fn approximate_duration(seconds: u64) -> String {
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 24 * HOUR;
+    if seconds >= DAY {
+        format!("{days:.1} days", days = seconds as f64 / DAY as f64)
+    } else {
+        format!("{hours:.1} hours", hours = seconds as f64 / HOUR as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLESIG_RECEIVE: &str = "wpkh([84577e03/84'/0'/0']xpub6CzKyqKif638s7uJ5myMt89Ludi5C7G1r7jJLRTcCvcHGBvmgm4Xd7PiifFNYJ9TugWcfh4jSviQUQCBtyKhkR18utMtriyjT8GUCAqCaC7/0/*)#x703tmpk";
+    const MULTISIG_RECEIVE: &str = "wsh(sortedmulti(2,[1c2b4725/48'/0'/0'/2']xpub6Dp8hC2nCxMi8E8LwP8Wd2KzTnoe7PE8eRXt411uwvNqvwxYCGRiAxZvu4GRQQmXisb5PvUmERsehSPCU7SJAJDYri3BB3q9YzymHs4idPS/0/*,[88c3e90a/48'/0'/0'/2']xpub6DrbCDR2BEyrc9yFqvwb1rUPamk9ULmn9RSFBykKxWu3Ryh7bYoFAM9vhGiZ7fgVSSu2MB4UzUGvkreuVuH19rwAcZ4skxVb9R5PzXn1dMu/0/*,[a0342720/48'/0'/0'/2']xpub6Er6q7NDEyU4Z4KRZFMqh5R5vWbQXhnL4PhCpkXMW1CVq4N7VdccEX2RoTuAZXTmVqaTirR4JcmnDkEASVwetHZkisiqmhjmKBUd6KndpcC/0/*))#vcntmeq2";
+
+    fn without_checksum(descriptor: &str) -> &str {
+        descriptor
+            .split_once('#')
+            .map_or(descriptor, |(body, _)| body)
+    }
+
+    #[test]
+    fn test_descriptor_checksum() -> Result<()> {
+        assert_eq!(descriptor_checksum("raw(deadbeef)")?, "89f8spxm");
+        assert_eq!(
+            descriptor_checksum(without_checksum(SINGLESIG_RECEIVE))?,
+            "x703tmpk"
+        );
+        assert_eq!(
+            descriptor_checksum(without_checksum(MULTISIG_RECEIVE))?,
+            "vcntmeq2"
+        );
+        assert!(descriptor_checksum("raw(deadbeef)\u{e9}").is_err());
+
+        let checked = check_descriptor_checksum("raw(deadbeef)")?;
+        assert_eq!(checked.descriptor, "raw(deadbeef)#89f8spxm");
+        assert!(!checked.provided);
+        assert!(check_descriptor_checksum("raw(deadbeef)#89f8spxm")?.provided);
+        assert!(check_descriptor_checksum("raw(deadbeef)#89f8spxn").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_descriptor() -> Result<()> {
+        let summary = parse_descriptor(MULTISIG_RECEIVE)?;
+        assert_eq!(summary.script_type, "p2wsh");
+        assert!(summary.ranged);
+        assert!(!summary.multipath);
+        assert_eq!(summary.keys.len(), 3);
+        assert_eq!(summary.keys[0].master_fingerprint, "1c2b4725");
+        assert_eq!(
+            summary.keys[0].origin_path.as_deref(),
+            Some("m/48'/0'/0'/2'")
+        );
+
+        let multipath = without_checksum(SINGLESIG_RECEIVE).replace("/0/*", "/<0;1>/*");
+        let summary = parse_descriptor(&multipath)?;
+        assert_eq!(summary.script_type, "p2wpkh");
+        assert!(summary.multipath);
+
+        assert!(parse_descriptor("wpkh(notakey)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_descriptor() -> Result<()> {
+        let addresses = expand_descriptor(SINGLESIG_RECEIVE, Network::Bitcoin, 0, 2)?;
+        let addresses: Vec<&str> = addresses.iter().map(|a| a.address.as_str()).collect();
+        assert_eq!(
+            addresses,
+            [
+                "bc1qrmyaygpejj2kczmuxc29g4mtust8058xz5tuay",
+                "bc1q5shm02hkpexm6mwt695t5hmmsc7lkd4rl0af2n"
+            ]
+        );
+
+        let multipath = without_checksum(MULTISIG_RECEIVE).replace("/0/*", "/<0;1>/*");
+        let addresses = expand_descriptor(&multipath, Network::Bitcoin, 1, 1)?;
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0].branch, Some(0));
+        assert_eq!(
+            addresses[0].address,
+            "bc1qjykfx8d9jse8f8gmu63r5n5gw95nx0yrur5y2exszsq4k64jc07szyr27h"
+        );
+        assert_eq!(addresses[1].branch, Some(1));
+        assert_eq!(
+            addresses[1].address,
+            "bc1qu3u5lc2pcm50etd3n7d83jhgdwpdjxx0g7ulkp64h47xwlwnj4zqdkyhnu"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_addresses_from_xpub() -> Result<()> {
+        assert_eq!(parse_index_range("0-50")?, (0, 50));
+        assert_eq!(parse_index_range(" 7 ")?, (7, 7));
+        assert!(parse_index_range("5-1").is_err());
+        assert!(parse_index_range("a-b").is_err());
+
+        let xpub = "xpub6CzKyqKif638s7uJ5myMt89Ludi5C7G1r7jJLRTcCvcHGBvmgm4Xd7PiifFNYJ9TugWcfh4jSviQUQCBtyKhkR18utMtriyjT8GUCAqCaC7";
+        assert!(xpub_descriptor(xpub, None).is_err());
+        let descriptor = xpub_descriptor(xpub, Some(XpubScriptType::from_str("wpkh")?))?;
+        assert!(descriptor.starts_with(&format!("wpkh({xpub}/<0;1>/*)#")));
+
+        // A zpub implies wpkh and gives the same descriptor
+        let zpub = crate::slip132::convert_xpub(xpub, Some(crate::Slip132Format::Zpub))?.converted;
+        assert_eq!(xpub_descriptor(&zpub, None)?, descriptor);
+
+        let derivation = derive_addresses(&descriptor, Network::Bitcoin, 0, 1)?;
+        assert_eq!(derivation.addresses.len(), 4);
+        assert_eq!(
+            derivation.addresses[0].address,
+            "bc1qrmyaygpejj2kczmuxc29g4mtust8058xz5tuay"
+        );
+        assert_eq!(
+            derivation.addresses[1].address,
+            "bc1q5shm02hkpexm6mwt695t5hmmsc7lkd4rl0af2n"
+        );
+        assert_eq!(derivation.addresses[2].branch, Some(1));
+        assert!(derive_addresses(&descriptor, Network::Testnet, 0, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let tree = ScriptNode::parse(MULTISIG_RECEIVE)?;
+        assert_eq!(tree.to_string(), without_checksum(MULTISIG_RECEIVE));
+        let mut lines = Vec::new();
+        tree.explain_output(&mut lines);
+        assert_eq!(lines[0], "P2WSH (native segwit v0 script) requiring:");
+        assert_eq!(lines[1], "  2-of-3 multisig (keys sorted) with keys:");
+        assert_eq!(lines[2], "    - [1c2b4725/48'/0'/0'/2']xpub6Dp8…idPS/0/*");
+
+        let tree = ScriptNode::parse(
+            "tr(A,{and_v(v:pk(B),older(4320)),{pk(C),and_v(v:pk(D),after(900000))}})",
+        )?;
+        let mut lines = Vec::new();
+        tree.explain_output(&mut lines);
+        assert_eq!(
+            lines,
+            [
+                "Taproot: key path spend with a signature from A",
+                "or a script path spend with any of:",
+                "  All of:",
+                "    Signature from B",
+                "    Relative timelock: 4320 blocks (~30.0 days) after the output confirms",
+                "  Signature from C",
+                "  All of:",
+                "    Signature from D",
+                "    Absolute timelock: after block height 900000",
+            ]
+        );
+
+        assert!(ScriptNode::parse("wpkh(A").is_err());
+        assert!(ScriptNode::parse("wpkh(A))").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timelocks() {
+        assert_eq!(
+            describe_relative_timelock(&(SEQUENCE_TYPE_FLAG | 10).to_string()),
+            "Relative timelock: 5120 seconds (~1.4 hours) after the output confirms"
+        );
+        assert_eq!(
+            describe_absolute_timelock("1700000000"),
+            "Absolute timelock: after 2023-11-14 22:13:20 UTC (median time past)"
+        );
+    }
+
+    #[test]
+    fn test_expand_multipath_descriptor() -> Result<()> {
+        // No specifier: returned unchanged, checksum included
+        let single = without_checksum(SINGLESIG_RECEIVE);
+        assert_eq!(
+            expand_multipath_descriptor(SINGLESIG_RECEIVE)?,
+            vec![SINGLESIG_RECEIVE.to_string()]
+        );
+
+        // <0;1> in every key of a multisig; the checksum is dropped
+        let multipath = without_checksum(MULTISIG_RECEIVE).replace("/0/*", "/<0;1>/*");
+        let checksum = descriptor_checksum(&multipath)?;
+        assert_eq!(
+            expand_multipath_descriptor(&format!("{multipath}#{checksum}"))?,
+            vec![
+                without_checksum(MULTISIG_RECEIVE).to_string(),
+                multipath.replace("<0;1>", "1"),
+            ]
+        );
+
+        // Arbitrary sets, hardened steps and specifiers in the middle of the path
+        assert_eq!(
+            expand_multipath_descriptor("tr(xpub/<2;5;7h>/3/*,pk(xpub2/9/<0;1;2>))")?,
+            vec![
+                "tr(xpub/2/3/*,pk(xpub2/9/0))",
+                "tr(xpub/5/3/*,pk(xpub2/9/1))",
+                "tr(xpub/7h/3/*,pk(xpub2/9/2))",
+            ]
+        );
+        assert_eq!(
+            expand_multipath_descriptor(&single.replace("/0/*", "/<0';1'>/*"))?[1],
+            single.replace("/0/*", "/1'/*")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_multipath_descriptor_errors() {
+        for descriptor in [
+            // Mismatched lengths
+            "wsh(multi(1,xpub/<0;1>/*,xpub2/<0;1;2>/*))",
+            // Two specifiers in one key
+            "wpkh(xpub/<0;1>/<2;3>/*)",
+            // Specifier in the key origin
+            "wpkh([d34db33f/<0;1>]xpub/0/*)",
+            // Single element, duplicates and invalid steps
+            "wpkh(xpub/<0>/*)",
+            "wpkh(xpub/<1;1>/*)",
+            "wpkh(xpub/<1h;1'>/*)",
+            "wpkh(xpub/<0;x>/*)",
+            "wpkh(xpub/<0;2147483648>/*)",
+            "wpkh(xpub/<0;1/*)",
+            "wpkh(xpub/0;1>/*)",
+        ] {
+            assert!(
+                expand_multipath_descriptor(descriptor).is_err(),
+                "{descriptor} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_combine_descriptors() -> Result<()> {
+        let change = without_checksum(SINGLESIG_RECEIVE).replace("/0/*", "/1/*");
+        let combined = combine_descriptors(SINGLESIG_RECEIVE, &change)?;
+        check_descriptor_checksum(&combined)?;
+        assert_eq!(
+            without_checksum(&combined),
+            without_checksum(SINGLESIG_RECEIVE).replace("/0/*", "/<0;1>/*")
+        );
+
+        // Every key of a multisig
+        let change = without_checksum(MULTISIG_RECEIVE).replace("/0/*", "/1/*");
+        let combined = combine_descriptors(MULTISIG_RECEIVE, &change)?;
+        assert_eq!(
+            expand_multipath_descriptor(&combined)?,
+            vec![without_checksum(MULTISIG_RECEIVE).to_string(), change]
+        );
+
+        let single = without_checksum(SINGLESIG_RECEIVE);
+        for (receive, change) in [
+            // Identical
+            (single.to_string(), single.to_string()),
+            // Different keys
+            (
+                single.to_string(),
+                without_checksum(MULTISIG_RECEIVE).to_string(),
+            ),
+            // Already multipath
+            (
+                single.replace("/0/*", "/<0;1>/*"),
+                single.replace("/0/*", "/1/*"),
+            ),
+            // Different paths before the wildcard
+            (single.to_string(), single.replace("/0/*", "/0/1/*")),
+            // Different origins
+            (
+                single.to_string(),
+                single.replace("/0']", "/1']").replace("/0/*", "/1/*"),
+            ),
+        ] {
+            assert!(
+                combine_descriptors(&receive, &change).is_err(),
+                "{receive} and {change} should not combine"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_single_key_address() -> Result<()> {
+        // BIP84 test vectors for the "abandon ... about" mnemonic
+        let descriptor = "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)";
+        let receive = derive_single_key_address(descriptor, Network::Bitcoin, 0, 0)?;
+        assert_eq!(
+            receive.address,
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(receive.master_fingerprint, "73c5da0a");
+        assert_eq!(receive.derivation_path, "m/84'/0'/0'/0/0");
+
+        let change = derive_single_key_address(descriptor, Network::Bitcoin, 1, 0)?;
+        assert_eq!(change.address, "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el");
+        assert_eq!(change.derivation_path, "m/84'/0'/0'/1/0");
+
+        assert!(derive_single_key_address(descriptor, Network::Bitcoin, 2, 0).is_err());
+        assert!(derive_single_key_address(MULTISIG_RECEIVE, Network::Bitcoin, 0, 0).is_err());
+        Ok(())
+    }
+}
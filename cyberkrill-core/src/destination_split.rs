@@ -0,0 +1,193 @@
+//! Splitting a consolidation across several destinations
+//!
+//! `onchain-move-utxos` sends everything left after the fee. With several
+//! destinations the value is shared either by percentages adding up to 100%
+//! (`addr1:60%,addr2:40%`) or by fixed amounts plus one bare address taking
+//! the remainder (`addr1:0.1btc,addr2`). A single bare address keeps the
+//! original behavior of one output receiving everything.
+
+use anyhow::{Context, Result};
+use std::str::FromStr;
+
+use crate::bitcoin_rpc::AmountInput;
+use crate::error::{CoreResult, InsufficientFunds, ensure};
+
+/// What one destination receives
+#[derive(Debug, Clone, PartialEq)]
+pub enum DestinationShare {
+    /// Percentage of the value left after the fee
+    Percent(f64),
+    /// Fixed amount in satoshis
+    Fixed(u64),
+    /// Whatever the other destinations and the fee leave
+    Remainder,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitDestination {
+    pub address: String,
+    pub share: DestinationShare,
+}
+
+/// Destinations of a consolidation, in the order given
+#[derive(Debug, Clone, PartialEq)]
+pub struct DestinationSplit {
+    destinations: Vec<SplitDestination>,
+}
+
+impl FromStr for DestinationSplit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut destinations: Vec<SplitDestination> = Vec::new();
+        for entry in s.split(',').map(str::trim) {
+            ensure!(!entry.is_empty(), "Empty destination in '{s}'");
+            let (address, share) = match entry.rsplit_once(':') {
+                None => (entry, DestinationShare::Remainder),
+                Some((address, share)) => {
+                    let share = share.trim();
+                    let share = if let Some(percent) = share.strip_suffix('%') {
+                        let percent: f64 = percent
+                            .trim()
+                            .parse()
+                            .with_context(|| format!("Invalid percentage in '{entry}'"))?;
+                        ensure!(
+                            percent > 0.0 && percent <= 100.0,
+                            "Percentage must be between 0% and 100% in '{entry}'"
+                        );
+                        DestinationShare::Percent(percent)
+                    } else {
+                        let amount = AmountInput::from_str(share)
+                            .with_context(|| format!("Invalid amount in '{entry}'"))?;
+                        ensure!(amount.as_sat() > 0, "Amount must be positive in '{entry}'");
+                        DestinationShare::Fixed(amount.as_sat())
+                    };
+                    (address.trim(), share)
+                }
+            };
+            ensure!(
+                destinations.iter().all(|d| d.address != address),
+                "Destination {address} is listed more than once"
+            );
+            destinations.push(SplitDestination {
+                address: address.to_string(),
+                share,
+            });
+        }
+
+        let percents = destinations
+            .iter()
+            .filter(|d| matches!(d.share, DestinationShare::Percent(_)))
+            .count();
+        let remainders = destinations
+            .iter()
+            .filter(|d| d.share == DestinationShare::Remainder)
+            .count();
+        if percents > 0 {
+            ensure!(
+                percents == destinations.len(),
+                "Percentages can't be mixed with fixed amounts or a remainder destination"
+            );
+            let total: f64 = destinations
+                .iter()
+                .map(|d| match d.share {
+                    DestinationShare::Percent(percent) => percent,
+                    _ => 0.0,
+                })
+                .sum();
+            ensure!(
+                (total - 100.0).abs() < 1e-6,
+                "Percentages must add up to 100%, got {total}%"
+            );
+        } else {
+            ensure!(
+                remainders == 1,
+                "Exactly one destination without an amount must receive the remainder"
+            );
+        }
+        Ok(Self { destinations })
+    }
+}
+
+impl DestinationSplit {
+    pub fn destinations(&self) -> &[SplitDestination] {
+        &self.destinations
+    }
+
+    pub fn addresses(&self) -> Vec<String> {
+        self.destinations
+            .iter()
+            .map(|d| d.address.clone())
+            .collect()
+    }
+
+    /// Index of the destination absorbing rounding and the fee: the remainder
+    /// destination, or the last one of a percentage split
+    pub fn drain_index(&self) -> usize {
+        self.destinations
+            .iter()
+            .position(|d| d.share == DestinationShare::Remainder)
+            .unwrap_or(self.destinations.len() - 1)
+    }
+
+    /// Sats each destination receives out of `available` (inputs minus fee),
+    /// in the order given
+    pub fn allocate(&self, available: u64) -> CoreResult<Vec<u64>> {
+        let drain = self.drain_index();
+        let mut amounts: Vec<u64> = self
+            .destinations
+            .iter()
+            .enumerate()
+            .map(|(index, d)| match d.share {
+                _ if index == drain => 0,
+                DestinationShare::Percent(percent) => {
+                    (available as f64 * percent / 100.0).floor() as u64
+                }
+                DestinationShare::Fixed(sats) => sats,
+                DestinationShare::Remainder => 0,
+            })
+            .collect();
+        let allocated: u64 = amounts.iter().sum();
+        let Some(rest) = available.checked_sub(allocated).filter(|rest| *rest > 0) else {
+            return Err(InsufficientFunds(format!(
+                "{available} sats after the fee can't cover {allocated} sats of fixed amounts and the remainder"
+            ))
+            .into());
+        };
+        amounts[drain] = rest;
+        Ok(amounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CoreError;
+
+    #[test]
+    fn test_destination_split() -> Result<()> {
+        let single: DestinationSplit = "bc1qa".parse()?;
+        assert_eq!(single.addresses(), ["bc1qa"]);
+        assert_eq!(single.allocate(10_000)?, [10_000]);
+
+        let percent: DestinationSplit = "bc1qa:60%, bc1qb:40%".parse()?;
+        assert_eq!(percent.drain_index(), 1);
+        assert_eq!(percent.allocate(10_001)?, [6_000, 4_001]);
+
+        let fixed: DestinationSplit = "bc1qa:0.0001btc,bc1qb,bc1qc:500sats".parse()?;
+        assert_eq!(fixed.drain_index(), 1);
+        assert_eq!(fixed.allocate(20_000)?, [10_000, 9_500, 500]);
+        assert!(
+            fixed
+                .allocate(10_500)
+                .is_err_and(|e| matches!(e, CoreError::InsufficientFunds(_)))
+        );
+
+        assert!("bc1qa:60%,bc1qb:30%".parse::<DestinationSplit>().is_err());
+        assert!("bc1qa:60%,bc1qb".parse::<DestinationSplit>().is_err());
+        assert!("bc1qa:1000sats".parse::<DestinationSplit>().is_err());
+        assert!("bc1qa,bc1qb".parse::<DestinationSplit>().is_err());
+        assert!("bc1qa:50%,bc1qa:50%".parse::<DestinationSplit>().is_err());
+        Ok(())
+    }
+}
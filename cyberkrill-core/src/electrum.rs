@@ -0,0 +1,461 @@
+//! Electrum server lists with latency-based selection and failover
+//!
+//! `--electrum` accepts a single URL, a comma-separated list, or the path to a
+//! servers.json file. Servers are pinged once on first use and ranked by
+//! latency; every call then goes to the fastest healthy server and falls over
+//! to the next one when it fails, so a single flaky server no longer aborts
+//! the whole command.
+
+use anyhow::{Context, Result};
+use bdk_electrum::electrum_client::{Client, ConfigBuilder, ElectrumApi, Socks5Config};
+use rustls::pki_types::ServerName;
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::cert_pin::CertFingerprint;
+use crate::client_options::ClientOptions;
+use crate::error::{CoreResult, bail, ensure};
+use crate::proxy::ProxyKind;
+use crate::retry::{RetryPolicy, retry_blocking};
+
+/// One Electrum server and its connection settings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ElectrumServer {
+    /// e.g. `ssl://electrum.blockstream.info:50002` or `tcp://localhost:50001`
+    pub url: String,
+    /// Verify the TLS certificate of `ssl://` servers against the system roots
+    #[serde(default = "default_validate_certificate")]
+    pub validate_certificate: bool,
+    /// Socket timeout in seconds, defaulting to the retry policy's attempt timeout
+    #[serde(default)]
+    pub timeout: Option<u8>,
+    /// Accept only this certificate instead of validating it; defaults to the
+    /// `--backend-cert-fingerprint` pin for this URL
+    #[serde(default)]
+    pub cert_fingerprint: Option<CertFingerprint>,
+}
+
+fn default_validate_certificate() -> bool {
+    true
+}
+
+impl ElectrumServer {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            validate_certificate: true,
+            timeout: None,
+            cert_fingerprint: None,
+        }
+    }
+
+    /// Connect through the SOCKS5 proxy of `options`, if any. While an RPC
+    /// trace is in use the client talks to a local relay that records or
+    /// replays its calls.
+    pub fn connect(&self, options: &ClientOptions) -> CoreResult<Client> {
+        if let Some(trace) = &options.trace {
+            return Ok(crate::trace_relay::electrum_client(
+                self,
+                options,
+                trace.clone(),
+            )?);
+        }
+        Ok(self.connect_upstream(options)?)
+    }
+
+    /// Connect to the server itself, bypassing any trace
+    pub(crate) fn connect_upstream(&self, options: &ClientOptions) -> Result<Client> {
+        if let Some(pin) = self
+            .cert_fingerprint
+            .or_else(|| options.cert_pins.for_url(&self.url))
+        {
+            return self.connect_pinned(&pin, options);
+        }
+        let mut config = ConfigBuilder::new()
+            .validate_domain(self.validate_certificate)
+            .timeout(Some(self.socket_timeout(&options.retry)));
+        if let Some(proxy) = &options.proxy {
+            ensure!(
+                proxy.kind() == ProxyKind::Socks5,
+                "Electrum connections only support SOCKS5 proxies"
+            );
+            let socks = match proxy.credentials() {
+                Some((user, password)) => {
+                    Socks5Config::with_credentials(proxy.address(), user.into(), password.into())
+                }
+                None => Socks5Config::new(proxy.address()),
+            };
+            config = config.socks5(Some(socks));
+        }
+        Client::from_config(&self.url, config.build())
+            .with_context(|| format!("Failed to connect to Electrum server {url}", url = self.url))
+    }
+
+    /// TLS connection that only accepts the certificate matching `pin`.
+    ///
+    /// The Electrum client can't be given a custom TLS verifier, so it talks
+    /// plain TCP to a local relay that forwards each connection over a pinned
+    /// TLS session.
+    fn connect_pinned(&self, pin: &CertFingerprint, options: &ClientOptions) -> Result<Client> {
+        CertFingerprint::ensure_tls(&self.url, "ssl")?;
+        ensure!(
+            options.proxy.is_none(),
+            "Electrum certificate pinning is not supported through --proxy"
+        );
+        let timeout = self.socket_timeout(&options.retry);
+        // Connect now so a pin mismatch is reported as such
+        let first = Mutex::new(Some(self.pinned_stream(pin, timeout)?));
+        let server = self.clone();
+        let pin = *pin;
+        let port = crate::trace_relay::spawn_relay("Electrum TLS", move |local| {
+            let tls = match first.lock().ok().and_then(|mut first| first.take()) {
+                Some(tls) => tls,
+                None => server.pinned_stream(&pin, timeout)?,
+            };
+            relay_tls(local, tls)
+        })?;
+        let config = ConfigBuilder::new().timeout(Some(timeout)).build();
+        Client::from_config(&format!("tcp://127.0.0.1:{port}"), config)
+            .with_context(|| format!("Failed to connect to Electrum server {url}", url = self.url))
+    }
+
+    fn pinned_stream(&self, pin: &CertFingerprint, timeout: u8) -> Result<PinnedStream> {
+        let address = &self.url["ssl://".len()..];
+        let (host, _port) = address
+            .rsplit_once(':')
+            .with_context(|| format!("Electrum URL has no port: '{url}'", url = self.url))?;
+        let server_name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+            .with_context(|| format!("Invalid Electrum server name: '{host}'"))?;
+
+        let mut tcp = TcpStream::connect(address).with_context(|| {
+            format!("Failed to connect to Electrum server {url}", url = self.url)
+        })?;
+        let timeout = Duration::from_secs(timeout.into());
+        tcp.set_read_timeout(Some(timeout))?;
+        tcp.set_write_timeout(Some(timeout))?;
+        let mut tls = rustls::ClientConnection::new(Arc::new(pin.tls_config()?), server_name)
+            .context("Failed to start TLS session")?;
+        while tls.is_handshaking() {
+            tls.complete_io(&mut tcp).with_context(|| {
+                format!(
+                    "TLS handshake with Electrum server {url} failed",
+                    url = self.url
+                )
+            })?;
+        }
+        Ok(rustls::StreamOwned::new(tls, tcp))
+    }
+
+    fn socket_timeout(&self, retry: &RetryPolicy) -> u8 {
+        self.timeout.unwrap_or_else(|| {
+            let secs = retry.attempt_timeout.as_secs();
+            u8::try_from(secs).unwrap_or(u8::MAX).max(1)
+        })
+    }
+
+    /// Round-trip time of a fresh connection plus a `server.ping`
+    fn probe(&self, options: &ClientOptions) -> Result<Duration> {
+        let started = Instant::now();
+        self.connect(options)?.ping().with_context(|| {
+            format!("Electrum server {url} did not answer ping", url = self.url)
+        })?;
+        Ok(started.elapsed())
+    }
+}
+
+/// Entry of a servers.json file: a bare URL or a full server object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServerEntry {
+    Url(String),
+    Server(ElectrumServer),
+}
+
+/// Electrum servers to use interchangeably, in order of preference
+#[derive(Debug, Clone)]
+pub struct ElectrumServers {
+    servers: Vec<ElectrumServer>,
+    /// Indexes into `servers`, fastest healthy server first
+    ranking: OnceLock<Vec<usize>>,
+}
+
+impl FromStr for ElectrumServers {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.ends_with(".json") || Path::new(s).is_file() {
+            return Ok(Self::from_file(Path::new(s))?);
+        }
+        Ok(Self::new(
+            s.split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(ElectrumServer::new)
+                .collect(),
+        )?)
+    }
+}
+
+impl ElectrumServers {
+    pub fn new(servers: Vec<ElectrumServer>) -> CoreResult<Self> {
+        ensure!(!servers.is_empty(), "No Electrum servers given");
+        Ok(Self {
+            servers,
+            ranking: OnceLock::new(),
+        })
+    }
+
+    /// Load a servers.json file: a JSON array of URLs and/or objects with
+    /// `url`, `validate_certificate`, `timeout` and `cert_fingerprint`
+    pub fn from_file(path: &Path) -> CoreResult<Self> {
+        let contents = std::fs::read_to_string(path).with_context(|| {
+            format!(
+                "Failed to read Electrum server list {path}",
+                path = path.display()
+            )
+        })?;
+        let entries: Vec<ServerEntry> = serde_json::from_str(&contents).with_context(|| {
+            format!("Invalid Electrum server list {path}", path = path.display())
+        })?;
+        Self::new(
+            entries
+                .into_iter()
+                .map(|entry| match entry {
+                    ServerEntry::Url(url) => ElectrumServer::new(url),
+                    ServerEntry::Server(server) => server,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn servers(&self) -> &[ElectrumServer] {
+        &self.servers
+    }
+
+    /// Server indexes in the order they should be tried
+    fn ranking(&self, options: &ClientOptions) -> &[usize] {
+        self.ranking.get_or_init(|| {
+            if self.servers.len() == 1 {
+                return vec![0];
+            }
+            // Probe all servers concurrently so one unreachable host only costs its timeout
+            let latencies: Vec<Option<Duration>> = std::thread::scope(|scope| {
+                let probes: Vec<_> = self
+                    .servers
+                    .iter()
+                    .map(|server| scope.spawn(move || server.probe(options)))
+                    .collect();
+                probes
+                    .into_iter()
+                    .zip(&self.servers)
+                    .map(|(probe, server)| match probe.join() {
+                        Ok(Ok(latency)) => {
+                            debug!(
+                                "Electrum server {url} answered in {latency:?}",
+                                url = server.url
+                            );
+                            Some(latency)
+                        }
+                        Ok(Err(e)) => {
+                            warn!(
+                                "Electrum server {url} is unhealthy: {e:#}",
+                                url = server.url
+                            );
+                            None
+                        }
+                        Err(_) => None,
+                    })
+                    .collect()
+            });
+            rank_by_latency(&latencies)
+        })
+    }
+
+    /// Connect to the fastest server that accepts the connection
+    pub fn connect(&self, options: &ClientOptions) -> CoreResult<Client> {
+        self.call(options, Ok)
+    }
+
+    /// Run `op` on a fresh connection, failing over to the next server on error.
+    /// When every server fails transiently the whole round is retried with backoff.
+    pub fn call<T>(
+        &self,
+        options: &ClientOptions,
+        mut op: impl FnMut(Client) -> Result<T>,
+    ) -> CoreResult<T> {
+        options.cert_pins.ensure_unambiguous(self.servers.len())?;
+        retry_blocking(&options.retry, "Electrum call", || {
+            self.call_once(options, &mut op)
+        })
+    }
+
+    fn call_once<T>(
+        &self,
+        options: &ClientOptions,
+        op: &mut impl FnMut(Client) -> Result<T>,
+    ) -> Result<T> {
+        let mut failures = Vec::new();
+        let mut last_error = None;
+        for &index in self.ranking(options) {
+            let server = &self.servers[index];
+            match server
+                .connect(options)
+                .map_err(anyhow::Error::from)
+                .and_then(&mut *op)
+            {
+                Ok(value) => return Ok(value),
+                Err(e) if self.servers.len() == 1 => return Err(e),
+                Err(e) => {
+                    warn!(
+                        "Electrum server {url} failed, trying the next one: {e:#}",
+                        url = server.url
+                    );
+                    failures.push(format!("{url}: {e:#}", url = server.url));
+                    last_error = Some(e);
+                }
+            }
+        }
+        // Keep the last error as the source so retries can tell whether it was transient
+        let summary = format!(
+            "All Electrum servers failed:\n{failures}",
+            failures = failures.join("\n")
+        );
+        match last_error {
+            Some(e) => Err(e.context(summary)),
+            None => bail!(summary),
+        }
+    }
+}
+
+type PinnedStream = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+
+/// Copy bytes both ways between `local` and `tls` until either side closes
+fn relay_tls(mut local: TcpStream, mut tls: PinnedStream) -> Result<()> {
+    // Short read timeouts let one thread poll both sides
+    let poll = Duration::from_millis(20);
+    local.set_read_timeout(Some(poll))?;
+    tls.sock.set_read_timeout(Some(poll))?;
+    let mut buffer = [0u8; 16 * 1024];
+    loop {
+        match local.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                tls.write_all(&buffer[..n])?;
+                tls.flush()?;
+            }
+            Err(e) if is_poll_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        match tls.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => local.write_all(&buffer[..n])?,
+            Err(e) if is_poll_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn is_poll_timeout(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Healthy servers by ascending latency, then unhealthy ones in their given order
+fn rank_by_latency(latencies: &[Option<Duration>]) -> Vec<usize> {
+    let mut ranking: Vec<usize> = (0..latencies.len()).collect();
+    ranking.sort_by_key(|&index| (latencies[index].is_none(), latencies[index]));
+    ranking
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_server_list() -> Result<()> {
+        let servers = ElectrumServers::from_str(
+            "ssl://electrum.blockstream.info:50002, tcp://localhost:50001,",
+        )?;
+        assert_eq!(
+            servers.servers(),
+            [
+                ElectrumServer::new("ssl://electrum.blockstream.info:50002"),
+                ElectrumServer::new("tcp://localhost:50001"),
+            ]
+        );
+        assert!(ElectrumServers::from_str(" , ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_servers_json() -> Result<()> {
+        let mut file = tempfile::Builder::new().suffix(".json").tempfile()?;
+        write!(
+            file,
+            r#"[
+                "ssl://electrum.blockstream.info:50002",
+                {{"url": "ssl://umbrel.local:50002", "validate_certificate": false, "timeout": 10}},
+                {{"url": "ssl://start9.local:50002", "cert_fingerprint": "{fingerprint}"}}
+            ]"#,
+            fingerprint = CertFingerprint::of(b"start9")
+        )?;
+        let path = file.path().to_str().context("Non UTF-8 temp path")?;
+        let servers = ElectrumServers::from_str(path)?;
+        assert_eq!(servers.servers().len(), 3);
+        assert!(servers.servers()[0].validate_certificate);
+        assert_eq!(
+            servers.servers()[1],
+            ElectrumServer {
+                url: "ssl://umbrel.local:50002".to_string(),
+                validate_certificate: false,
+                timeout: Some(10),
+                cert_fingerprint: None,
+            }
+        );
+        assert_eq!(
+            servers.servers()[2].cert_fingerprint,
+            Some(CertFingerprint::of(b"start9"))
+        );
+
+        assert!(ElectrumServers::from_str("/nonexistent/servers.json").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bare_pin_needs_single_server() -> Result<()> {
+        let options = ClientOptions {
+            cert_pins: crate::CertPins::new([crate::CertPin {
+                url: None,
+                fingerprint: CertFingerprint::of(b"umbrel"),
+            }])?,
+            ..Default::default()
+        };
+        let servers =
+            ElectrumServers::from_str("ssl://umbrel.local:50002,ssl://start9.local:50002")?;
+        let Err(err) = servers.connect(&options) else {
+            bail!("A bare pin was accepted for two servers");
+        };
+        assert!(err.to_string().contains("URL=FINGERPRINT"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rank_by_latency() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            rank_by_latency(&[None, Some(ms(300)), Some(ms(50)), None, Some(ms(120))]),
+            vec![2, 4, 1, 0, 3]
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Error type of the public API
+//!
+//! Public functions in this crate return [`CoreResult`]. Internally errors are
+//! still built with `anyhow` so every layer can add context; at the API
+//! boundary the error is sorted into a [`CoreError`] class by the typed cause in
+//! its chain, keeping the whole chain for display, so callers such as the MCP
+//! server can tell a backend that may come back from input that will never
+//! work:
+//!
+//! ```
+//! use cyberkrill_core::CoreError;
+//!
+//! let error = cyberkrill_core::decode_invoice("lnbc1notaninvoice").err();
+//! assert!(matches!(error, Some(CoreError::Parse(_))));
+//!
+//! let error = CoreError::from(anyhow::Error::new(cyberkrill_core::InsufficientFunds(
+//!     "inputs=1000 sats, fee=1500 sats".to_string(),
+//! )));
+//! assert_eq!(error.code(), "insufficient_funds");
+//! assert!(!error.is_retryable());
+//! ```
+
+use crate::bitcoin_rpc::{AmountInputError, RpcError};
+
+/// Result of the public functions of this crate
+pub type CoreResult<T> = std::result::Result<T, CoreError>;
+
+/// `anyhow::bail!` that also returns from functions with a [`CoreResult`]
+macro_rules! bail {
+    ($($arg:tt)+) => {
+        return ::std::result::Result::Err(::anyhow::anyhow!($($arg)+).into())
+    };
+}
+
+/// `anyhow::ensure!` that also returns from functions with a [`CoreResult`]
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        match $cond {
+            true => {}
+            false => $crate::error::bail!($($arg)+),
+        }
+    };
+}
+
+pub(crate) use {bail, ensure};
+
+/// The wallet inputs cannot pay for the outputs and the fee
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Insufficient funds: {0}")]
+pub struct InsufficientFunds(pub String);
+
+/// An error from this crate, classified by its cause.
+///
+/// Display and `source` are those of the wrapped error, so `{:#}` still prints
+/// the full context chain.
+#[derive(Debug, thiserror::Error)]
+pub enum CoreError {
+    /// The Bitcoin Core node rejected the call
+    #[error(transparent)]
+    Rpc(anyhow::Error),
+    /// The backend could not be reached, is overloaded or is still starting up
+    #[error(transparent)]
+    BackendUnavailable(anyhow::Error),
+    /// A descriptor or one of its keys could not be parsed
+    #[error(transparent)]
+    InvalidDescriptor(anyhow::Error),
+    /// A hardware wallet or smartcard failed, was disconnected or refused
+    #[error(transparent)]
+    Device(anyhow::Error),
+    /// An amount, address, PSBT, transaction or other input is malformed
+    #[error(transparent)]
+    Parse(anyhow::Error),
+    /// The selected coins cannot cover the outputs and the fee
+    #[error(transparent)]
+    InsufficientFunds(anyhow::Error),
+    /// Anything not covered by the classes above
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl CoreError {
+    /// Stable snake_case name of the class, for logs and JSON
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Rpc(_) => "rpc",
+            Self::BackendUnavailable(_) => "backend_unavailable",
+            Self::InvalidDescriptor(_) => "invalid_descriptor",
+            Self::Device(_) => "device",
+            Self::Parse(_) => "parse",
+            Self::InsufficientFunds(_) => "insufficient_funds",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// Whether the same call may succeed later without changing its input
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::BackendUnavailable(_))
+    }
+
+    /// Code of the node's RPC error, when the call was rejected by one
+    pub fn rpc_code(&self) -> Option<i64> {
+        causes(self.inner())
+            .into_iter()
+            .find_map(|cause| cause.downcast_ref::<RpcError>())
+            .map(|error| error.code)
+    }
+
+    pub fn inner(&self) -> &anyhow::Error {
+        match self {
+            Self::Rpc(error)
+            | Self::BackendUnavailable(error)
+            | Self::InvalidDescriptor(error)
+            | Self::Device(error)
+            | Self::Parse(error)
+            | Self::InsufficientFunds(error)
+            | Self::Other(error) => error,
+        }
+    }
+
+    /// Tag an input error that the classifier can't recognize by type
+    pub(crate) fn parse(error: impl Into<anyhow::Error>) -> Self {
+        Self::Parse(error.into())
+    }
+
+    fn class(&self) -> Option<Class> {
+        match self {
+            Self::Rpc(_) => Some(Class::Rpc),
+            Self::BackendUnavailable(_) => Some(Class::BackendUnavailable),
+            Self::InvalidDescriptor(_) => Some(Class::InvalidDescriptor),
+            Self::Device(_) => Some(Class::Device),
+            Self::Parse(_) => Some(Class::Parse),
+            Self::InsufficientFunds(_) => Some(Class::InsufficientFunds),
+            Self::Other(_) => None,
+        }
+    }
+
+    pub fn into_inner(self) -> anyhow::Error {
+        match self {
+            Self::Rpc(error)
+            | Self::BackendUnavailable(error)
+            | Self::InvalidDescriptor(error)
+            | Self::Device(error)
+            | Self::Parse(error)
+            | Self::InsufficientFunds(error)
+            | Self::Other(error) => error,
+        }
+    }
+}
+
+/// Class of an error, without the error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Rpc,
+    BackendUnavailable,
+    InvalidDescriptor,
+    Device,
+    Parse,
+    InsufficientFunds,
+}
+
+impl From<anyhow::Error> for CoreError {
+    /// Classify by the outermost typed cause in the chain; context messages
+    /// added with `.context` are skipped, and a [`CoreError`] in the chain
+    /// keeps its class
+    fn from(error: anyhow::Error) -> Self {
+        // `downcast` also looks behind `.context`, which would drop the context
+        let error = match error
+            .chain()
+            .next()
+            .is_some_and(|head| head.is::<CoreError>())
+        {
+            true => match error.downcast::<CoreError>() {
+                Ok(error) => return error,
+                Err(error) => error,
+            },
+            false => error,
+        };
+        let class = error.chain().find_map(classify);
+        match class {
+            Some(Class::Rpc) => Self::Rpc(error),
+            Some(Class::BackendUnavailable) => Self::BackendUnavailable(error),
+            Some(Class::InvalidDescriptor) => Self::InvalidDescriptor(error),
+            Some(Class::Device) => Self::Device(error),
+            Some(Class::Parse) => Self::Parse(error),
+            Some(Class::InsufficientFunds) => Self::InsufficientFunds(error),
+            None => Self::Other(error),
+        }
+    }
+}
+
+/// The chain of `error`, looking through any [`CoreError`] in it.
+///
+/// A [`CoreError`] shows its wrapped error's message but hides that error's
+/// type, so downcasting the plain chain would miss it.
+pub(crate) fn causes(error: &anyhow::Error) -> Vec<&(dyn std::error::Error + 'static)> {
+    let mut causes = Vec::new();
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<CoreError>() {
+            // The rest of the chain is the wrapped error's
+            causes.extend(self::causes(error.inner()));
+            break;
+        }
+        causes.push(cause);
+    }
+    causes
+}
+
+impl From<InsufficientFunds> for CoreError {
+    fn from(error: InsufficientFunds) -> Self {
+        Self::InsufficientFunds(error.into())
+    }
+}
+
+/// Let `?` turn these causes into a [`CoreError`], classified like any other
+/// error
+macro_rules! from_causes {
+    ($($cause:ty),+ $(,)?) => {
+        $(
+            impl From<$cause> for CoreError {
+                fn from(error: $cause) -> Self {
+                    Self::from(anyhow::Error::new(error))
+                }
+            }
+        )+
+    };
+}
+
+from_causes!(
+    std::io::Error,
+    std::num::TryFromIntError,
+    std::array::TryFromSliceError,
+    std::string::FromUtf8Error,
+    serde_json::Error,
+    url::ParseError,
+    bech32::DecodeError,
+    bech32::primitives::hrp::Error,
+    bip39::Error,
+    bitcoin::address::ParseError,
+    bitcoin::amount::ParseAmountError,
+    bitcoin::base58::Error,
+    bitcoin::bip32::Error,
+    bitcoin::hex::HexToArrayError,
+    bitcoin::psbt::Error,
+    bitcoin::sighash::P2wpkhError,
+    bitcoin::sighash::TaprootError,
+    bdk_wallet::chain::local_chain::CannotConnectError,
+    bdk_wallet::descriptor::DescriptorError,
+    bdk_wallet::miniscript::Error,
+    bdk_wallet::error::CreateTxError,
+    bdk_wallet::rusqlite::Error,
+    bdk_wallet::tx_builder::AddUtxoError,
+    bdk_wallet::CreateWithPersistError<bdk_wallet::rusqlite::Error>,
+    plotters::drawing::DrawingAreaErrorKind<std::io::Error>,
+    hex::FromHexError,
+);
+
+#[cfg(feature = "jade")]
+from_causes!(jade_bitcoin::Error);
+#[cfg(feature = "trezor")]
+from_causes!(trezor_client::Error);
+#[cfg(any(feature = "smartcards", feature = "trezor"))]
+from_causes!(rusb::Error);
+
+fn classify(cause: &(dyn std::error::Error + 'static)) -> Option<Class> {
+    if let Some(error) = cause.downcast_ref::<CoreError>() {
+        return error.class();
+    }
+    if cause.is::<InsufficientFunds>()
+        || cause.is::<bdk_wallet::coin_selection::InsufficientFunds>()
+        || matches!(
+            cause.downcast_ref::<bdk_wallet::error::CreateTxError>(),
+            Some(bdk_wallet::error::CreateTxError::CoinSelection(_))
+        )
+    {
+        return Some(Class::InsufficientFunds);
+    }
+    if let Some(error) = cause.downcast_ref::<RpcError>() {
+        return Some(match error.code {
+            RpcError::WALLET_INSUFFICIENT_FUNDS => Class::InsufficientFunds,
+            RpcError::IN_WARMUP => Class::BackendUnavailable,
+            _ => Class::Rpc,
+        });
+    }
+    if is_device_error(cause) {
+        return Some(Class::Device);
+    }
+    if cause.is::<bdk_wallet::miniscript::Error>()
+        || cause.is::<bdk_wallet::descriptor::DescriptorError>()
+    {
+        return Some(Class::InvalidDescriptor);
+    }
+    if is_parse_error(cause) {
+        return Some(Class::Parse);
+    }
+    if crate::retry::is_transient_cause(cause) {
+        return Some(Class::BackendUnavailable);
+    }
+    None
+}
+
+fn is_device_error(cause: &(dyn std::error::Error + 'static)) -> bool {
+    #[cfg(feature = "jade")]
+    if cause.is::<jade_bitcoin::Error>() {
+        return true;
+    }
+    #[cfg(feature = "trezor")]
+    if cause.is::<trezor_client::Error>() {
+        return true;
+    }
+    #[cfg(any(feature = "smartcards", feature = "trezor"))]
+    if cause.is::<rusb::Error>() {
+        return true;
+    }
+    let _ = cause;
+    false
+}
+
+fn is_parse_error(cause: &(dyn std::error::Error + 'static)) -> bool {
+    cause.is::<AmountInputError>()
+        || cause.is::<bitcoin::amount::ParseAmountError>()
+        || cause.is::<bitcoin::address::ParseError>()
+        || cause.is::<bitcoin::consensus::encode::Error>()
+        || cause.is::<bitcoin::psbt::Error>()
+        || cause.is::<bitcoin::psbt::PsbtParseError>()
+        || cause.is::<bitcoin::bip32::Error>()
+        || cause.is::<bitcoin::hex::HexToArrayError>()
+        || cause.is::<bech32::DecodeError>()
+        || cause.is::<base64::DecodeError>()
+        || cause.is::<hex::FromHexError>()
+        || cause.is::<url::ParseError>()
+        || cause.is::<std::num::ParseIntError>()
+        || cause.is::<std::num::ParseFloatError>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn rpc(code: i64) -> anyhow::Error {
+        anyhow::Error::new(RpcError {
+            code,
+            message: "failed".to_string(),
+        })
+        .context("Failed to call the node")
+    }
+
+    #[test]
+    fn test_classifies_rpc_errors_by_code() -> anyhow::Result<()> {
+        assert_eq!(CoreError::from(rpc(-5)).code(), "rpc");
+        assert_eq!(CoreError::from(rpc(-5)).rpc_code(), Some(-5));
+        assert_eq!(CoreError::from(rpc(-6)).code(), "insufficient_funds");
+        let warmup = CoreError::from(rpc(-28));
+        assert_eq!(warmup.code(), "backend_unavailable");
+        assert!(warmup.is_retryable());
+        Ok(())
+    }
+
+    #[test]
+    fn test_classifies_through_context() -> anyhow::Result<()> {
+        let error = anyhow::Error::new(InsufficientFunds("inputs=1 sats".to_string()))
+            .context("Failed to build the PSBT");
+        let error = CoreError::from(error);
+        assert_eq!(error.code(), "insufficient_funds");
+        // The chain is kept for display
+        assert_eq!(
+            format!("{error:#}"),
+            "Failed to build the PSBT: Insufficient funds: inputs=1 sats"
+        );
+        assert_eq!(error.to_string(), "Failed to build the PSBT");
+        Ok(())
+    }
+
+    #[test]
+    fn test_classifies_input_errors() -> anyhow::Result<()> {
+        let error = match crate::bitcoin_rpc::AmountInput::from_str("lots") {
+            Ok(amount) => bail!("Parsed {amount:?}"),
+            Err(error) => anyhow::Error::new(error),
+        };
+        assert_eq!(CoreError::from(error).code(), "parse");
+
+        let descriptor = bdk_wallet::miniscript::Descriptor::<
+            bdk_wallet::miniscript::DescriptorPublicKey,
+        >::from_str("wpkh(not-a-key)");
+        let error = match descriptor {
+            Ok(descriptor) => bail!("Parsed {descriptor}"),
+            Err(error) => anyhow::Error::new(error).context("Invalid descriptor"),
+        };
+        assert_eq!(CoreError::from(error).code(), "invalid_descriptor");
+
+        // A malformed backend response is not an input error
+        let error = match serde_json::from_str::<serde_json::Value>("{") {
+            Ok(value) => bail!("Parsed {value}"),
+            Err(error) => anyhow::Error::new(error),
+        };
+        assert_eq!(CoreError::from(error).code(), "other");
+        Ok(())
+    }
+
+    #[test]
+    fn test_keeps_class_when_wrapped() -> anyhow::Result<()> {
+        let error = CoreError::Parse(anyhow::anyhow!("Invalid PSBT"));
+        let wrapped = anyhow::Error::new(error).context("Failed to sign");
+        let error = CoreError::from(wrapped);
+        assert_eq!(error.code(), "parse");
+        assert_eq!(format!("{error:#}"), "Failed to sign: Invalid PSBT");
+
+        // Converting back and forth keeps the error as it was
+        let error = CoreError::from(anyhow::Error::new(error));
+        assert_eq!(error.code(), "parse");
+        Ok(())
+    }
+
+    #[test]
+    fn test_classifies_backend_failures() -> anyhow::Result<()> {
+        let error = crate::retry::status_error(
+            reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            "HTTP error: 503".to_string(),
+        );
+        assert_eq!(CoreError::from(error).code(), "backend_unavailable");
+
+        let error = crate::retry::status_error(
+            reqwest::StatusCode::UNAUTHORIZED,
+            "HTTP error: 401".to_string(),
+        );
+        let error = CoreError::from(error);
+        assert_eq!(error.code(), "other");
+        assert!(!error.is_retryable());
+        Ok(())
+    }
+
+    #[cfg(feature = "jade")]
+    #[test]
+    fn test_classifies_device_errors() -> anyhow::Result<()> {
+        let error = anyhow::Error::new(jade_bitcoin::Error::UserCancelled).context("Jade signing");
+        assert_eq!(CoreError::from(error).code(), "device");
+        Ok(())
+    }
+}
@@ -0,0 +1,965 @@
+//! Low-bandwidth UTXO scanning against an Esplora HTTP API
+//!
+//! The BDK Esplora sync downloads the full transaction history (with prevouts)
+//! of every used script. For listing UTXOs most of that is unnecessary, so this
+//! scanner only fetches per-script statistics to drive the gap limit and asks
+//! the `/utxo` endpoint for scripts that still hold funds. Responses are
+//! requested gzip-compressed and the bytes received are counted.
+//!
+//! Public instances cap and rate-limit what they serve. A `/utxo` answer that
+//! disagrees with the script's funded/spent counts is rebuilt from the script's
+//! history, paged 25 transactions at a time, and a history that still comes up
+//! short fails the scan instead of returning a partial UTXO set.
+
+use anyhow::{Context, Result};
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey};
+use bitcoin::hashes::{Hash, sha256};
+use bitcoin::{Network, ScriptBuf, Transaction, Txid};
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{debug, warn};
+
+use crate::bdk_wallet::{BdkUtxo, ScanOptions};
+use crate::cert_pin::CertFingerprint;
+use crate::client_options::ClientOptions;
+use crate::descriptor::expand_multipath_descriptor;
+use crate::error::{CoreResult, bail, ensure};
+use crate::mempool_accept::{MempoolAcceptance, parse_test_mempool_accept};
+use crate::retry::{RetryPolicy, http_status, response_error, retry};
+use crate::rpc_trace::{RpcTrace, TraceMode};
+use crate::tx_watch::TxStatus;
+
+/// Result of a low-bandwidth scan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LowBandwidthScan {
+    pub utxos: Vec<BdkUtxo>,
+    /// Bytes received from the server (compressed size when the server supports gzip)
+    pub bytes_transferred: u64,
+    /// Number of HTTP requests issued
+    pub requests: u64,
+}
+
+/// Traffic of a streamed low-bandwidth scan
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EsploraTransfer {
+    /// Bytes received from the server (compressed size when the server supports gzip)
+    pub bytes_transferred: u64,
+    /// Number of HTTP requests issued
+    pub requests: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TxoStats {
+    funded_txo_count: u64,
+    spent_txo_count: u64,
+    tx_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptHashStats {
+    chain_stats: TxoStats,
+    mempool_stats: TxoStats,
+}
+
+impl ScriptHashStats {
+    fn is_used(&self) -> bool {
+        self.chain_stats.tx_count + self.mempool_stats.tx_count > 0
+    }
+
+    fn has_unspent(&self) -> bool {
+        self.unspent_count() > 0
+    }
+
+    /// Outputs received and not yet spent, counting the mempool
+    fn unspent_count(&self) -> u64 {
+        (self.chain_stats.funded_txo_count + self.mempool_stats.funded_txo_count)
+            .saturating_sub(self.chain_stats.spent_txo_count + self.mempool_stats.spent_txo_count)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UtxoStatus {
+    confirmed: bool,
+    block_height: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraUtxo {
+    txid: String,
+    vout: u32,
+    value: u64,
+    status: UtxoStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryInput {
+    txid: String,
+    vout: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryOutput {
+    scriptpubkey: String,
+    value: u64,
+}
+
+/// Transaction from a script's `/txs` history
+#[derive(Debug, Deserialize)]
+struct HistoryTx {
+    txid: String,
+    vin: Vec<HistoryInput>,
+    vout: Vec<HistoryOutput>,
+    status: UtxoStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTxStatus {
+    confirmed: bool,
+    block_height: Option<u32>,
+    block_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTx {
+    status: EsploraTxStatus,
+}
+
+/// Minimal Esplora client that tracks how much data it receives
+struct MeteredEsploraClient {
+    base_url: String,
+    http: reqwest::Client,
+    retry: RetryPolicy,
+    trace: Option<Arc<RpcTrace>>,
+    bytes: AtomicU64,
+    requests: AtomicU64,
+}
+
+/// HTTP client for the Esplora server at `base_url`, with the proxy,
+/// certificate pin and attempt timeout of `options`
+pub(crate) fn esplora_http_client(
+    base_url: &str,
+    options: &ClientOptions,
+) -> Result<reqwest::Client> {
+    let mut builder = options
+        .http_client_builder()
+        .timeout(options.retry.attempt_timeout);
+    if let Some(pin) = options.cert_pins.for_url(base_url) {
+        CertFingerprint::ensure_tls(base_url, "https")?;
+        builder = builder.use_preconfigured_tls(pin.tls_config()?);
+    }
+    builder
+        .build()
+        .context("Failed to build Esplora HTTP client")
+}
+
+impl MeteredEsploraClient {
+    fn new(base_url: &str, options: &ClientOptions) -> Result<Self> {
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: esplora_http_client(base_url, options)?,
+            retry: options.retry,
+            trace: options.trace.clone(),
+            bytes: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        let Some(trace) = &self.trace else {
+            return self.fetch(path).await;
+        };
+
+        // Traces store the decoded body plus the wire size so replays report the same totals
+        let request = serde_json::json!({ "method": "GET", "path": path });
+        let recorded = trace
+            .exchange("esplora", request, || async {
+                let before = self.bytes.load(Ordering::Relaxed);
+                let body = self.fetch(path).await?;
+                Ok(serde_json::json!({
+                    "body": String::from_utf8_lossy(&body),
+                    "wire_bytes": self.bytes.load(Ordering::Relaxed) - before,
+                }))
+            })
+            .await?;
+        if trace.mode() == TraceMode::Replay {
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            let wire_bytes = recorded["wire_bytes"].as_u64().unwrap_or_default();
+            self.bytes.fetch_add(wire_bytes, Ordering::Relaxed);
+        }
+        let body = recorded["body"]
+            .as_str()
+            .context("Recorded Esplora exchange has no body")?;
+        Ok(body.as_bytes().to_vec())
+    }
+
+    async fn fetch(&self, path: &str) -> Result<Vec<u8>> {
+        Ok(retry(&self.retry, &format!("Esplora GET {path}"), || {
+            self.fetch_once(path)
+        })
+        .await?)
+    }
+
+    async fn fetch_once(&self, path: &str) -> Result<Vec<u8>> {
+        let url = format!("{base}{path}", base = self.base_url);
+        let response = self
+            .http
+            .get(&url)
+            .header(reqwest::header::ACCEPT_ENCODING, "gzip")
+            .send()
+            .await
+            .with_context(|| format!("Esplora request failed: {url}"))?;
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let gzipped = response
+            .headers()
+            .get(reqwest::header::CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"gzip"));
+        let body = response
+            .bytes()
+            .await
+            .with_context(|| format!("Failed to read Esplora response: {url}"))?;
+        self.bytes.fetch_add(body.len() as u64, Ordering::Relaxed);
+
+        let body = if gzipped {
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(body.as_ref())
+                .read_to_end(&mut decoded)
+                .with_context(|| format!("Failed to decompress Esplora response: {url}"))?;
+            decoded
+        } else {
+            body.to_vec()
+        };
+
+        if !status.is_success() {
+            return Err(response_error(
+                status,
+                &headers,
+                format!(
+                    "Esplora returned {status} for {url}: {body}",
+                    body = String::from_utf8_lossy(&body)
+                ),
+            ));
+        }
+        Ok(body)
+    }
+
+    /// POST a text body, returning the response text (used for broadcasting)
+    async fn post(&self, path: &str, body: String) -> Result<String> {
+        let Some(trace) = &self.trace else {
+            return self.send(path, body).await;
+        };
+
+        let request = serde_json::json!({ "method": "POST", "path": path, "body": body });
+        let recorded = trace
+            .exchange("esplora", request, || async {
+                Ok(serde_json::json!({ "body": self.send(path, body.clone()).await? }))
+            })
+            .await?;
+        recorded["body"]
+            .as_str()
+            .map(str::to_string)
+            .context("Recorded Esplora exchange has no body")
+    }
+
+    async fn send(&self, path: &str, body: String) -> Result<String> {
+        Ok(retry(&self.retry, &format!("Esplora POST {path}"), || {
+            self.send_once(path, body.clone())
+        })
+        .await?)
+    }
+
+    async fn send_once(&self, path: &str, body: String) -> Result<String> {
+        let url = format!("{base}{path}", base = self.base_url);
+        let response = self
+            .http
+            .post(&url)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Esplora request failed: {url}"))?;
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let text = response
+            .text()
+            .await
+            .with_context(|| format!("Failed to read Esplora response: {url}"))?;
+        self.bytes.fetch_add(text.len() as u64, Ordering::Relaxed);
+        if !status.is_success() {
+            return Err(response_error(
+                status,
+                &headers,
+                format!("Esplora returned {status} for {url}: {text}"),
+            ));
+        }
+        Ok(text)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let body = self.get(path).await?;
+        serde_json::from_slice(&body)
+            .with_context(|| format!("Invalid Esplora response for {path}"))
+    }
+
+    async fn tip_height(&self) -> Result<u32> {
+        let body = self.get("/blocks/tip/height").await?;
+        String::from_utf8_lossy(&body)
+            .trim()
+            .parse()
+            .context("Invalid tip height from Esplora")
+    }
+
+    /// Full history of a script: its mempool transactions, then confirmed ones
+    /// paged with `/txs/chain/:last_seen_txid` until `stats` are accounted for.
+    /// A history that ends early or repeats itself is an error.
+    async fn script_history(&self, hash: &str, stats: &ScriptHashStats) -> Result<Vec<HistoryTx>> {
+        let mut history: Vec<HistoryTx> = self
+            .get_json(&format!("/scripthash/{hash}/txs/mempool"))
+            .await?;
+        // The mempool list is not paged; servers return at most 50 entries
+        ensure!(
+            history.len() as u64 >= stats.mempool_stats.tx_count,
+            "Esplora returned {found} of the {expected} mempool transactions of script hash {hash}",
+            found = history.len(),
+            expected = stats.mempool_stats.tx_count
+        );
+
+        let mut seen: HashSet<String> = history.iter().map(|tx| tx.txid.clone()).collect();
+        let mut confirmed = 0u64;
+        let mut path = format!("/scripthash/{hash}/txs/chain");
+        while confirmed < stats.chain_stats.tx_count {
+            let page: Vec<HistoryTx> = self.get_json(&path).await?;
+            let Some(last) = page.last() else {
+                bail!(
+                    "Esplora history of script hash {hash} ended after {confirmed} of {expected} \
+                     confirmed transactions",
+                    expected = stats.chain_stats.tx_count
+                );
+            };
+            path = format!("/scripthash/{hash}/txs/chain/{txid}", txid = last.txid);
+            for tx in page {
+                if !seen.insert(tx.txid.clone()) {
+                    bail!(
+                        "Esplora repeated transaction {txid} while paging the history of \
+                         script hash {hash}",
+                        txid = tx.txid
+                    );
+                }
+                if tx.status.confirmed {
+                    confirmed += 1;
+                }
+                history.push(tx);
+            }
+        }
+        Ok(history)
+    }
+
+    /// Unspent outputs of a script, checked against the count in its `stats`.
+    ///
+    /// A `/utxo` answer with the wrong count (or refused as too large) is
+    /// rebuilt from the paged history.
+    async fn script_utxos(
+        &self,
+        hash: &str,
+        script: &ScriptBuf,
+        stats: &ScriptHashStats,
+    ) -> Result<Vec<EsploraUtxo>> {
+        let expected = stats.unspent_count();
+        let listed = match self
+            .get_json::<Vec<EsploraUtxo>>(&format!("/scripthash/{hash}/utxo"))
+            .await
+        {
+            Ok(entries) if entries.len() as u64 == expected => return Ok(entries),
+            Ok(entries) => entries.len().to_string(),
+            // electrs refuses scripts with more UTXOs than its configured limit
+            Err(e) if http_status(&e) == Some(reqwest::StatusCode::BAD_REQUEST) => {
+                format!("none ({e:#})")
+            }
+            Err(e) => return Err(e),
+        };
+        warn!(
+            "Esplora listed {listed} of {expected} UTXOs for script hash {hash}; \
+             rebuilding them from its transaction history"
+        );
+
+        let entries = unspent_outputs(&self.script_history(hash, stats).await?, script);
+        ensure!(
+            entries.len() as u64 == expected,
+            "Esplora history of script hash {hash} has {found} unspent outputs but its stats \
+             count {expected}; the server may be truncating results or the script changed \
+             during the scan",
+            found = entries.len()
+        );
+        Ok(entries)
+    }
+}
+
+/// Outputs paying to `script` in `history` that no transaction in it spends
+fn unspent_outputs(history: &[HistoryTx], script: &ScriptBuf) -> Vec<EsploraUtxo> {
+    let script_hex = script.to_hex_string();
+    let spent: HashSet<(&str, u32)> = history
+        .iter()
+        .flat_map(|tx| tx.vin.iter())
+        .map(|input| (input.txid.as_str(), input.vout))
+        .collect();
+    history
+        .iter()
+        .flat_map(|tx| {
+            (0u32..)
+                .zip(&tx.vout)
+                .map(move |(vout, output)| (tx, vout, output))
+        })
+        .filter(|(tx, vout, output)| {
+            output.scriptpubkey.eq_ignore_ascii_case(&script_hex)
+                && !spent.contains(&(tx.txid.as_str(), *vout))
+        })
+        .map(|(tx, vout, output)| EsploraUtxo {
+            txid: tx.txid.clone(),
+            vout,
+            value: output.value,
+            status: UtxoStatus {
+                confirmed: tx.status.confirmed,
+                block_height: tx.status.block_height,
+            },
+        })
+        .collect()
+}
+
+/// Fetch a raw transaction from Esplora
+pub async fn fetch_transaction_esplora(
+    esplora_url: &str,
+    txid: &Txid,
+    options: &ClientOptions,
+) -> CoreResult<Transaction> {
+    let client = MeteredEsploraClient::new(esplora_url, options)?;
+    let body = client.get(&format!("/tx/{txid}/hex")).await?;
+    let tx_hex = String::from_utf8_lossy(&body);
+    let bytes = hex::decode(tx_hex.trim())
+        .with_context(|| format!("Invalid transaction hex for {txid}"))?;
+    Ok(bitcoin::consensus::deserialize(&bytes)
+        .with_context(|| format!("Failed to decode transaction {txid}"))?)
+}
+
+/// Status of a transaction from `/tx/:txid`, which is a 404 for unknown transactions
+pub async fn fetch_tx_status_esplora(
+    esplora_url: &str,
+    txid: &Txid,
+    options: &ClientOptions,
+) -> CoreResult<TxStatus> {
+    let client = MeteredEsploraClient::new(esplora_url, options)?;
+    let tx: EsploraTx = match client.get_json(&format!("/tx/{txid}")).await {
+        Ok(tx) => tx,
+        Err(e) if http_status(&e) == Some(reqwest::StatusCode::NOT_FOUND) => {
+            return Ok(TxStatus::not_found(txid));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    match tx.status {
+        EsploraTxStatus {
+            confirmed: true,
+            block_height: Some(height),
+            block_hash: Some(block_hash),
+        } => Ok(TxStatus::confirmed(
+            txid,
+            height,
+            block_hash,
+            client.tip_height().await?,
+        )),
+        _ => Ok(TxStatus::mempool(txid)),
+    }
+}
+
+/// Broadcast a signed transaction through Esplora
+pub async fn broadcast_transaction_esplora(
+    esplora_url: &str,
+    tx: &Transaction,
+    options: &ClientOptions,
+) -> CoreResult<Txid> {
+    let client = MeteredEsploraClient::new(esplora_url, options)?;
+    let body = client
+        .post("/tx", bitcoin::consensus::encode::serialize_hex(tx))
+        .await?;
+    Ok(body
+        .trim()
+        .parse()
+        .with_context(|| format!("Unexpected Esplora broadcast response: {body}"))?)
+}
+
+/// Check mempool acceptance with `/txs/test`, which mempool.space-compatible
+/// servers provide (Blockstream's Esplora does not)
+pub async fn test_mempool_accept_esplora(
+    esplora_url: &str,
+    tx: &Transaction,
+    options: &ClientOptions,
+) -> CoreResult<MempoolAcceptance> {
+    let client = MeteredEsploraClient::new(esplora_url, options)?;
+    let body = serde_json::json!([bitcoin::consensus::encode::serialize_hex(tx)]).to_string();
+    let response = client.post("/txs/test", body).await.with_context(|| {
+        format!("Esplora server {esplora_url} cannot test mempool acceptance (requires /txs/test)")
+    })?;
+    let response: serde_json::Value = serde_json::from_str(&response)
+        .with_context(|| format!("Unexpected Esplora /txs/test response: {response}"))?;
+    Ok(parse_test_mempool_accept(&response)?)
+}
+
+/// Fee rate in sat/vB for confirmation within `conf_target` blocks, from `/fee-estimates`
+pub async fn estimate_fee_rate_esplora(
+    esplora_url: &str,
+    conf_target: u32,
+    options: &ClientOptions,
+) -> CoreResult<f64> {
+    let client = MeteredEsploraClient::new(esplora_url, options)?;
+    let estimates: HashMap<String, f64> = client.get_json("/fee-estimates").await?;
+    let estimates: HashMap<u32, f64> = estimates
+        .into_iter()
+        .filter_map(|(target, rate)| Some((target.parse().ok()?, rate)))
+        .collect();
+    Ok(fee_rate_for_target(&estimates, conf_target)
+        .with_context(|| format!("Esplora returned no fee estimates for {conf_target} blocks"))?)
+}
+
+/// Pick the estimate for the slowest target that still meets `conf_target`, falling
+/// back to the fastest one when `conf_target` is below every published target
+fn fee_rate_for_target(estimates: &HashMap<u32, f64>, conf_target: u32) -> Option<f64> {
+    estimates
+        .iter()
+        .filter(|(target, _)| **target <= conf_target)
+        .max_by_key(|(target, _)| **target)
+        .or_else(|| estimates.iter().min_by_key(|(target, _)| **target))
+        .map(|(_, rate)| *rate)
+}
+
+/// Esplora script hash: SHA256 of the script, hex-encoded in reverse byte order
+fn script_hash(script: &ScriptBuf) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// Scan `descriptor` for UTXOs while minimizing the data downloaded from Esplora.
+///
+/// Multipath descriptors (`<0;1>`) are scanned as external and internal keychains.
+/// Scanning stops after `stop_gap` consecutive unused scripts, with up to
+/// `parallel_requests` requests in flight.
+pub async fn scan_and_list_utxos_esplora_low_bandwidth(
+    descriptor: &str,
+    network: Network,
+    esplora_url: &str,
+    scan: &ScanOptions,
+) -> CoreResult<LowBandwidthScan> {
+    let mut utxos = Vec::new();
+    let transfer =
+        stream_utxos_esplora_low_bandwidth(descriptor, network, esplora_url, scan, |utxo| {
+            utxos.push(utxo);
+            Ok(())
+        })
+        .await?;
+
+    // Sort by amount descending, like the BDK scanners
+    utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+
+    Ok(LowBandwidthScan {
+        utxos,
+        bytes_transferred: transfer.bytes_transferred,
+        requests: transfer.requests,
+    })
+}
+
+/// [`scan_and_list_utxos_esplora_low_bandwidth`] handing each UTXO to `on_utxo`
+/// as soon as its script is scanned, in derivation order, instead of
+/// collecting them. An error from `on_utxo` stops the scan.
+pub async fn stream_utxos_esplora_low_bandwidth(
+    descriptor: &str,
+    network: Network,
+    esplora_url: &str,
+    scan: &ScanOptions,
+    mut on_utxo: impl FnMut(BdkUtxo) -> Result<()>,
+) -> CoreResult<EsploraTransfer> {
+    let client = &MeteredEsploraClient::new(esplora_url, &scan.client)?;
+    let tip_height = client.tip_height().await?;
+    let parallel = scan.parallel_requests.max(1);
+    let parallel_scripts = u32::try_from(parallel).unwrap_or(u32::MAX);
+
+    for (keychain_index, desc) in expand_multipath_descriptor(descriptor)?.iter().enumerate() {
+        let parsed = Descriptor::<DescriptorPublicKey>::from_str(desc)
+            .with_context(|| format!("Invalid descriptor '{desc}'"))?;
+        let is_change = keychain_index == 1;
+        let keychain = if is_change { "internal" } else { "external" };
+
+        let mut unused_streak = 0;
+        let mut next_index = 0u32;
+        while unused_streak < scan.stop_gap {
+            // Never ask past where the gap could end, so the concurrent requests
+            // cover exactly the scripts a one-by-one scan would. Descriptors
+            // without wildcards only have a single script.
+            let window = if parsed.has_wildcard() {
+                (scan.stop_gap - unused_streak).min(parallel_scripts)
+            } else {
+                1
+            };
+            let scripts = (next_index..next_index.saturating_add(window))
+                .map(|index| {
+                    let script = parsed
+                        .at_derivation_index(index)
+                        .with_context(|| format!("Failed to derive index {index} of '{desc}'"))?
+                        .script_pubkey();
+                    Ok((index, script_hash(&script), script))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            next_index = next_index.saturating_add(window);
+
+            let stats: Vec<ScriptHashStats> = futures::stream::iter(&scripts)
+                .map(|(index, hash, _)| async move {
+                    debug!("Low-bandwidth scan of {keychain} index {index}");
+                    client.get_json(&format!("/scripthash/{hash}")).await
+                })
+                .buffered(parallel)
+                .try_collect()
+                .await?;
+            for stats in &stats {
+                if stats.is_used() {
+                    unused_streak = 0;
+                } else {
+                    unused_streak += 1;
+                }
+            }
+
+            let funded: Vec<_> = scripts
+                .iter()
+                .zip(&stats)
+                .filter(|(_, stats)| stats.has_unspent())
+                .collect();
+            let funded_utxos: Vec<Vec<EsploraUtxo>> = futures::stream::iter(&funded)
+                .map(|((_, hash, script), stats)| client.script_utxos(hash, script, stats))
+                .buffered(parallel)
+                .try_collect()
+                .await?;
+
+            for (((index, _, script), _), entries) in funded.into_iter().zip(funded_utxos) {
+                let address = bitcoin::Address::from_script(script, network)
+                    .map(|address| address.to_string())
+                    .unwrap_or_else(|_| {
+                        format!("script:{script}", script = script.to_hex_string())
+                    });
+                for entry in entries {
+                    let confirmations = match (entry.status.confirmed, entry.status.block_height) {
+                        (true, Some(height)) if tip_height >= height => tip_height - height + 1,
+                        _ => 0,
+                    };
+                    on_utxo(BdkUtxo {
+                        txid: entry.txid,
+                        vout: entry.vout,
+                        address: address.clone(),
+                        amount: entry.value,
+                        amount_btc: bitcoin::Amount::from_sat(entry.value).to_btc(),
+                        confirmations,
+                        is_change,
+                        keychain: keychain.to_string(),
+                        derivation_index: Some(*index),
+                    })?;
+                }
+            }
+
+            if !parsed.has_wildcard() {
+                break;
+            }
+        }
+    }
+
+    Ok(EsploraTransfer {
+        bytes_transferred: client.bytes.load(Ordering::Relaxed),
+        requests: client.requests.load(Ordering::Relaxed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const TEST_DESCRIPTOR: &str = "wpkh(xpub6BosfCnifzxcJJ1wYuntGJfF2zPJkDeG9ELNHcKNjezuea4tumswN9sH1psMdSVqCMoJC21Bv8usSeqSP4Sp1tLzW7aY59fGn9GCYzx5UTo/0/*)";
+
+    fn stats_json(tx_count: u64, funded: u64, spent: u64) -> String {
+        format!(
+            r#"{{"chain_stats":{{"funded_txo_count":{funded},"spent_txo_count":{spent},"tx_count":{tx_count}}},"mempool_stats":{{"funded_txo_count":0,"spent_txo_count":0,"tx_count":0}}}}"#
+        )
+    }
+
+    #[test]
+    fn test_script_hash_is_reversed_sha256() -> Result<()> {
+        // Empty script: sha256("") reversed
+        assert_eq!(
+            script_hash(&ScriptBuf::new()),
+            "55b852781b9995a44c939b64e441ae2724b96f99c8f4fb9a141cfc9842c4b0e3"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_rate_for_target() -> Result<()> {
+        let estimates = HashMap::from([(1, 20.0), (3, 12.0), (6, 8.5), (144, 1.0)]);
+        assert_eq!(fee_rate_for_target(&estimates, 6), Some(8.5));
+        assert_eq!(fee_rate_for_target(&estimates, 10), Some(8.5));
+        assert_eq!(fee_rate_for_target(&estimates, 1008), Some(1.0));
+        assert_eq!(
+            fee_rate_for_target(&HashMap::from([(2, 15.0)]), 1),
+            Some(15.0)
+        );
+        assert_eq!(fee_rate_for_target(&HashMap::new(), 6), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_and_fee_estimates() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let txid = "b".repeat(64);
+        let broadcast = server
+            .mock("POST", "/tx")
+            .with_body(&txid)
+            .expect(1)
+            .create_async()
+            .await;
+        let _fees = server
+            .mock("GET", "/fee-estimates")
+            .with_body(r#"{"1":30.5,"2":20.0,"6":9.25}"#)
+            .create_async()
+            .await;
+
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        let options = ClientOptions::default();
+        let broadcast_txid = broadcast_transaction_esplora(&server.url(), &tx, &options).await?;
+        broadcast.assert_async().await;
+        assert_eq!(broadcast_txid.to_string(), txid);
+        assert_eq!(
+            estimate_fee_rate_esplora(&server.url(), 3, &options).await?,
+            20.0
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_low_bandwidth_scan() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(TEST_DESCRIPTOR)?;
+        let first = script_hash(&descriptor.at_derivation_index(0)?.script_pubkey());
+        let second = script_hash(&descriptor.at_derivation_index(1)?.script_pubkey());
+
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzipped.write_all(b"100")?;
+        let tip_body = gzipped.finish()?;
+        let _tip = server
+            .mock("GET", "/blocks/tip/height")
+            .with_header("content-encoding", "gzip")
+            .with_body(tip_body.clone())
+            .create_async()
+            .await;
+
+        // Index 0 was funded and spent: used, but no UTXO lookup needed
+        let _first = server
+            .mock("GET", format!("/scripthash/{first}").as_str())
+            .with_body(stats_json(2, 1, 1))
+            .create_async()
+            .await;
+        // Index 1 holds a confirmed UTXO
+        let _second = server
+            .mock("GET", format!("/scripthash/{second}").as_str())
+            .with_body(stats_json(1, 1, 0))
+            .create_async()
+            .await;
+        let utxo_body = format!(
+            r#"[{{"txid":"{txid}","vout":1,"value":50000,"status":{{"confirmed":true,"block_height":91}}}}]"#,
+            txid = "a".repeat(64)
+        );
+        let utxo_mock = server
+            .mock("GET", format!("/scripthash/{second}/utxo").as_str())
+            .with_body(utxo_body.clone())
+            .expect(1)
+            .create_async()
+            .await;
+        // Everything else is unused
+        let unused_body = stats_json(0, 0, 0);
+        let _unused = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/scripthash/[0-9a-f]{64}$".to_string()),
+            )
+            .with_body(unused_body.clone())
+            .create_async()
+            .await;
+
+        let scan = scan_and_list_utxos_esplora_low_bandwidth(
+            TEST_DESCRIPTOR,
+            Network::Bitcoin,
+            &server.url(),
+            &ScanOptions {
+                stop_gap: 3,
+                ..ScanOptions::default()
+            },
+        )
+        .await?;
+
+        utxo_mock.assert_async().await;
+        assert_eq!(scan.utxos.len(), 1);
+        let utxo = &scan.utxos[0];
+        assert_eq!(utxo.amount, 50_000);
+        assert_eq!(utxo.confirmations, 10);
+        assert_eq!(utxo.derivation_index, Some(1));
+        assert!(!utxo.is_change);
+
+        // tip + 2 used + 3 unused stats + 1 utxo lookup
+        assert_eq!(scan.requests, 7);
+        let expected_bytes = tip_body.len()
+            + stats_json(2, 1, 1).len()
+            + stats_json(1, 1, 0).len()
+            + 3 * unused_body.len()
+            + utxo_body.len();
+        assert_eq!(scan.bytes_transferred, expected_bytes as u64);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_low_bandwidth_scan_pages_history() -> Result<()> {
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(TEST_DESCRIPTOR)?;
+        let script = descriptor.at_derivation_index(0)?.script_pubkey();
+        let hash = script_hash(&script);
+        let tx = |txid: char, inputs: &[(char, u32)], outputs: &[u64], height: u32| {
+            let vin: Vec<String> = inputs
+                .iter()
+                .map(|(prev, vout)| {
+                    format!(
+                        r#"{{"txid":"{prev}","vout":{vout}}}"#,
+                        prev = prev.to_string().repeat(64)
+                    )
+                })
+                .collect();
+            let vout: Vec<String> = outputs
+                .iter()
+                .map(|value| {
+                    format!(
+                        r#"{{"scriptpubkey":"{script}","value":{value}}}"#,
+                        script = script.to_hex_string()
+                    )
+                })
+                .collect();
+            format!(
+                r#"{{"txid":"{txid}","vin":[{vin}],"vout":[{vout}],"status":{{"confirmed":true,"block_height":{height}}}}}"#,
+                txid = txid.to_string().repeat(64),
+                vin = vin.join(","),
+                vout = vout.join(",")
+            )
+        };
+        // a funds the script, b spends a:0 and pays it twice, c pays it once
+        let newest = format!(
+            "[{c},{b}]",
+            c = tx('c', &[], &[3_000], 95),
+            b = tx('b', &[('a', 0)], &[1_000, 2_000], 92)
+        );
+        let oldest = format!("[{a}]", a = tx('a', &[], &[5_000], 90));
+
+        for truncated in [false, true] {
+            let mut server = mockito::Server::new_async().await;
+            let _tip = server
+                .mock("GET", "/blocks/tip/height")
+                .with_body("100")
+                .create_async()
+                .await;
+            let _stats = server
+                .mock("GET", format!("/scripthash/{hash}").as_str())
+                .with_body(stats_json(3, 4, 1))
+                .create_async()
+                .await;
+            // The server lists only one of the three UTXOs
+            let utxo_body = format!(
+                r#"[{{"txid":"{txid}","vout":0,"value":3000,"status":{{"confirmed":true,"block_height":95}}}}]"#,
+                txid = "c".repeat(64)
+            );
+            let _utxo = server
+                .mock("GET", format!("/scripthash/{hash}/utxo").as_str())
+                .with_body(utxo_body)
+                .create_async()
+                .await;
+            let _mempool = server
+                .mock("GET", format!("/scripthash/{hash}/txs/mempool").as_str())
+                .with_body("[]")
+                .create_async()
+                .await;
+            let _first_page = server
+                .mock("GET", format!("/scripthash/{hash}/txs/chain").as_str())
+                .with_body(&newest)
+                .create_async()
+                .await;
+            let second_page = server
+                .mock(
+                    "GET",
+                    format!("/scripthash/{hash}/txs/chain/{txid}", txid = "b".repeat(64)).as_str(),
+                )
+                .with_body(if truncated { "[]" } else { oldest.as_str() })
+                .expect(1)
+                .create_async()
+                .await;
+            let _unused = server
+                .mock(
+                    "GET",
+                    mockito::Matcher::Regex(r"^/scripthash/[0-9a-f]{64}$".to_string()),
+                )
+                .with_body(stats_json(0, 0, 0))
+                .create_async()
+                .await;
+
+            let scan = scan_and_list_utxos_esplora_low_bandwidth(
+                TEST_DESCRIPTOR,
+                Network::Bitcoin,
+                &server.url(),
+                &ScanOptions {
+                    stop_gap: 2,
+                    ..ScanOptions::default()
+                },
+            )
+            .await;
+            second_page.assert_async().await;
+
+            if truncated {
+                let error = scan.err().context("A short history should fail the scan")?;
+                assert!(
+                    format!("{error:#}").contains("ended after 2 of 3 confirmed transactions"),
+                    "{error:#}"
+                );
+                continue;
+            }
+            let utxos: Vec<(String, u32, u64, u32)> = scan?
+                .utxos
+                .into_iter()
+                .map(|utxo| {
+                    (
+                        utxo.txid[..1].to_string(),
+                        utxo.vout,
+                        utxo.amount,
+                        utxo.confirmations,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                utxos,
+                [
+                    ("c".to_string(), 0, 3_000, 6),
+                    ("b".to_string(), 1, 2_000, 9),
+                    ("b".to_string(), 0, 1_000, 9)
+                ]
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tracing::debug;
+
+use crate::dca_report::DcaUtxo;
+use crate::error::{CoreResult, bail};
+
+/// How far before a deposit its purchases may have happened
+pub const MATCH_WINDOW_DAYS: i64 = 30;
+/// Relative difference allowed between a deposit and what was bought, to cover
+/// exchange withdrawal fees
+pub const MATCH_AMOUNT_TOLERANCE: f64 = 0.01;
+
+/// Bitcoin purchase from an exchange's trade history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExchangePurchase {
+    pub exchange: String,
+    pub date: String, // YYYY-MM-DD format
+    pub amount_btc: f64,
+    /// Total paid, fees included
+    pub cost: f64,
+}
+
+impl ExchangePurchase {
+    /// Price actually paid per BTC, fees included
+    pub fn price(&self) -> f64 {
+        self.cost / self.amount_btc
+    }
+}
+
+/// Layout of an exchange CSV export
+#[derive(Debug, Clone)]
+pub enum ExchangeCsvFormat {
+    /// Kraken trades export (trades.csv)
+    Kraken,
+    /// Coinbase transaction history report
+    Coinbase,
+    /// Any CSV, given which columns hold the date, BTC amount and price or total
+    Generic(CsvColumns),
+}
+
+/// Column names of a generic purchase CSV. Either `price` (per BTC) or `total`
+/// (amount paid) must be set; `total` wins when both are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvColumns {
+    pub date: String,
+    pub amount: String,
+    pub price: Option<String>,
+    pub total: Option<String>,
+}
+
+impl FromStr for CsvColumns {
+    type Err = anyhow::Error;
+
+    /// Parse a mapping like `date=Date,amount=BTC,total=Paid`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut columns: HashMap<&str, String> = HashMap::new();
+        for pair in s.split(',') {
+            let (key, column) = pair
+                .split_once('=')
+                .with_context(|| format!("Invalid column mapping {pair}, expected key=column"))?;
+            let key = key.trim();
+            if !matches!(key, "date" | "amount" | "price" | "total") {
+                bail!("Unknown column mapping key {key} (expected date, amount, price or total)");
+            }
+            columns.insert(key, column.trim().to_string());
+        }
+        let columns = CsvColumns {
+            date: columns
+                .remove("date")
+                .context("Column mapping is missing date=<column>")?,
+            amount: columns
+                .remove("amount")
+                .context("Column mapping is missing amount=<column>")?,
+            price: columns.remove("price"),
+            total: columns.remove("total"),
+        };
+        if columns.price.is_none() && columns.total.is_none() {
+            bail!("Column mapping needs price=<column> or total=<column>");
+        }
+        Ok(columns)
+    }
+}
+
+/// Bitcoin purchases in an exchange CSV export
+pub fn parse_exchange_csv(
+    contents: &str,
+    format: &ExchangeCsvFormat,
+) -> CoreResult<Vec<ExchangePurchase>> {
+    Ok(match format {
+        ExchangeCsvFormat::Kraken => parse_kraken(contents)?,
+        ExchangeCsvFormat::Coinbase => parse_coinbase(contents)?,
+        ExchangeCsvFormat::Generic(columns) => parse_generic(contents, columns)?,
+    })
+}
+
+fn parse_kraken(contents: &str) -> Result<Vec<ExchangePurchase>> {
+    let table = CsvTable::parse(contents, |header| header.contains(&"pair"))?;
+    let mut purchases = Vec::new();
+    for row in table.rows() {
+        let pair = row.get("pair")?;
+        // Kraken names bitcoin XBT, e.g. XXBTZUSD, XBTEUR or XBT/USD
+        if !pair.to_uppercase().contains("XBT") || row.get("type")? != "buy" {
+            continue;
+        }
+        let cost = parse_number(row.get("cost")?)? + parse_number(row.get("fee")?)?;
+        purchases.push(ExchangePurchase {
+            exchange: "kraken".to_string(),
+            date: parse_date(row.get("time")?)?,
+            amount_btc: parse_number(row.get("vol")?)?,
+            cost,
+        });
+    }
+    Ok(purchases)
+}
+
+fn parse_coinbase(contents: &str) -> Result<Vec<ExchangePurchase>> {
+    // Reports start with a few lines about the account before the header
+    let table = CsvTable::parse(contents, |header| {
+        header.contains(&"Timestamp") && header.contains(&"Transaction Type")
+    })?;
+    let mut purchases = Vec::new();
+    for row in table.rows() {
+        if row.get("Asset")? != "BTC" || !row.get("Transaction Type")?.ends_with("Buy") {
+            continue;
+        }
+        // Older reports name it "Total (inclusive of fees)"
+        let total = row
+            .get("Total (inclusive of fees and/or spread)")
+            .or_else(|_| row.get("Total (inclusive of fees)"))?;
+        purchases.push(ExchangePurchase {
+            exchange: "coinbase".to_string(),
+            date: parse_date(row.get("Timestamp")?)?,
+            amount_btc: parse_number(row.get("Quantity Transacted")?)?,
+            cost: parse_number(total)?,
+        });
+    }
+    Ok(purchases)
+}
+
+fn parse_generic(contents: &str, columns: &CsvColumns) -> Result<Vec<ExchangePurchase>> {
+    let table = CsvTable::parse(contents, |header| header.contains(&columns.date.as_str()))?;
+    let mut purchases = Vec::new();
+    for row in table.rows() {
+        let amount_btc = parse_number(row.get(&columns.amount)?)?;
+        let cost = match (&columns.total, &columns.price) {
+            (Some(total), _) => parse_number(row.get(total)?)?,
+            (None, Some(price)) => parse_number(row.get(price)?)? * amount_btc,
+            (None, None) => bail!("Column mapping needs price=<column> or total=<column>"),
+        };
+        purchases.push(ExchangePurchase {
+            exchange: "csv".to_string(),
+            date: parse_date(row.get(&columns.date)?)?,
+            amount_btc,
+            cost,
+        });
+    }
+    Ok(purchases)
+}
+
+/// Use the prices actually paid on an exchange for the UTXOs that look like
+/// withdrawals of those purchases. A deposit matches the unmatched purchases of
+/// the `MATCH_WINDOW_DAYS` before it, taken oldest first, that add up to its
+/// amount within `MATCH_AMOUNT_TOLERANCE`. Returns the number of UTXOs matched.
+pub fn match_purchases(utxos: &mut [DcaUtxo], purchases: &[ExchangePurchase]) -> usize {
+    let mut purchases: Vec<(&ExchangePurchase, NaiveDate)> = purchases
+        .iter()
+        .filter(|purchase| purchase.amount_btc > 0.0)
+        .filter_map(|purchase| Some((purchase, purchase.date.parse().ok()?)))
+        .collect();
+    purchases.sort_by_key(|(_, date)| *date);
+    let mut used = vec![false; purchases.len()];
+
+    let mut order: Vec<usize> = (0..utxos.len()).collect();
+    order.sort_by(|a, b| utxos[*a].date.cmp(&utxos[*b].date));
+
+    let mut matched = 0;
+    for index in order {
+        let utxo = &mut utxos[index];
+        let Ok(deposit_date) = utxo.date.parse::<NaiveDate>() else {
+            continue;
+        };
+        let candidates: Vec<usize> = (0..purchases.len())
+            .filter(|&i| !used[i])
+            .filter(|&i| {
+                let days = (deposit_date - purchases[i].1).num_days();
+                (0..=MATCH_WINDOW_DAYS).contains(&days)
+            })
+            .collect();
+        let within_tolerance =
+            |bought: f64| (bought - utxo.amount_btc).abs() <= bought * MATCH_AMOUNT_TOLERANCE;
+
+        // A single purchase withdrawn on its own, the closest one first
+        let single = candidates
+            .iter()
+            .rev()
+            .find(|&&i| within_tolerance(purchases[i].0.amount_btc))
+            .map(|&i| vec![i]);
+        // Otherwise several purchases accumulated before one withdrawal
+        let accumulated = || {
+            let mut bought = 0.0;
+            for (taken, &i) in candidates.iter().enumerate() {
+                bought += purchases[i].0.amount_btc;
+                if within_tolerance(bought) {
+                    return Some(candidates[..=taken].to_vec());
+                }
+            }
+            None
+        };
+        let Some(selected) = single.or_else(accumulated) else {
+            continue;
+        };
+
+        let bought: f64 = selected.iter().map(|&i| purchases[i].0.amount_btc).sum();
+        let cost: f64 = selected.iter().map(|&i| purchases[i].0.cost).sum();
+        let price = cost / bought;
+        debug!(
+            "Matched {txid}:{vout} to {count} purchases at {price:.2}",
+            txid = utxo.txid,
+            vout = utxo.vout,
+            count = selected.len()
+        );
+        utxo.price_at_purchase = Some(price);
+        utxo.cost_basis = Some(utxo.amount_btc * price);
+        utxo.exchange = Some(purchases[selected[0]].0.exchange.clone());
+        for i in selected {
+            used[i] = true;
+        }
+        matched += 1;
+    }
+    matched
+}
+
+/// Date of a timestamp like `2024-01-15 10:23:45.1234`, `2024-01-15T10:23:45Z`
+/// or `01/15/2024`, as YYYY-MM-DD
+fn parse_date(value: &str) -> Result<String> {
+    let value = value.trim();
+    let date = value
+        .get(..10)
+        .and_then(|prefix| NaiveDate::parse_from_str(prefix, "%Y-%m-%d").ok())
+        .or_else(|| {
+            let date = value.split_whitespace().next()?;
+            NaiveDate::parse_from_str(date, "%m/%d/%Y").ok()
+        })
+        .with_context(|| format!("Invalid date: {value}"))?;
+    Ok(date.format("%Y-%m-%d").to_string())
+}
+
+/// Number with optional currency symbol and thousands separators, e.g. `$1,234.56`
+fn parse_number(value: &str) -> Result<f64> {
+    let cleaned: String = value
+        .chars()
+        .filter(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | 'e' | 'E'))
+        .collect();
+    cleaned
+        .parse()
+        .with_context(|| format!("Invalid number: {value}"))
+}
+
+/// CSV with a header row, found as the first line `is_header` accepts
+struct CsvTable {
+    header: Vec<String>,
+    records: Vec<Vec<String>>,
+}
+
+impl CsvTable {
+    fn parse(contents: &str, is_header: impl Fn(&[&str]) -> bool) -> Result<Self> {
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .by_ref()
+            .map(split_csv_line)
+            .find(|fields| {
+                let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+                is_header(&fields)
+            })
+            .context("CSV header not found")?;
+        let records = lines.map(split_csv_line).collect();
+        Ok(Self { header, records })
+    }
+
+    fn rows(&self) -> impl Iterator<Item = CsvRow<'_>> {
+        self.records
+            .iter()
+            .enumerate()
+            .map(|(index, fields)| CsvRow {
+                header: &self.header,
+                fields,
+                record: index + 1,
+            })
+    }
+}
+
+struct CsvRow<'a> {
+    header: &'a [String],
+    fields: &'a [String],
+    record: usize,
+}
+
+impl CsvRow<'_> {
+    fn get(&self, column: &str) -> Result<&str> {
+        let index = self
+            .header
+            .iter()
+            .position(|name| name == column)
+            .with_context(|| format!("CSV has no {column} column"))?;
+        self.fields.get(index).map(String::as_str).with_context(|| {
+            format!(
+                "CSV record {record} has no {column} value",
+                record = self.record
+            )
+        })
+    }
+}
+
+/// Fields of one CSV line, honoring double quotes
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(txid: &str, amount_btc: f64, date: &str) -> DcaUtxo {
+        DcaUtxo {
+            txid: txid.to_string(),
+            vout: 0,
+            amount_btc,
+            block_height: 850000,
+            block_time: None,
+            date: date.to_string(),
+            price_at_purchase: None,
+            cost_basis: None,
+            exchange: None,
+        }
+    }
+
+    fn purchase(date: &str, amount_btc: f64, cost: f64) -> ExchangePurchase {
+        ExchangePurchase {
+            exchange: "kraken".to_string(),
+            date: date.to_string(),
+            amount_btc,
+            cost,
+        }
+    }
+
+    #[test]
+    fn test_parse_kraken_trades() -> Result<()> {
+        let csv = "\"txid\",\"ordertxid\",\"pair\",\"time\",\"type\",\"ordertype\",\"price\",\"cost\",\"fee\",\"vol\",\"margin\",\"misc\",\"ledgers\"\n\
+            \"T1\",\"O1\",\"XXBTZUSD\",\"2024-01-15 10:23:45.1234\",\"buy\",\"market\",\"42000.0\",\"420.00\",\"1.68\",\"0.01000000\",\"0.00000\",\"\",\"L1,L2\"\n\
+            \"T2\",\"O2\",\"XXBTZUSD\",\"2024-01-16 09:00:00.0000\",\"sell\",\"market\",\"43000.0\",\"430.00\",\"1.72\",\"0.01000000\",\"0.00000\",\"\",\"L3\"\n\
+            \"T3\",\"O3\",\"XETHZUSD\",\"2024-01-16 09:00:00.0000\",\"buy\",\"market\",\"2500.0\",\"250.00\",\"1.00\",\"0.10000000\",\"0.00000\",\"\",\"L4\"\n";
+        let purchases = parse_exchange_csv(csv, &ExchangeCsvFormat::Kraken)?;
+
+        assert_eq!(purchases.len(), 1);
+        assert_eq!(purchases[0].date, "2024-01-15");
+        assert_eq!(purchases[0].amount_btc, 0.01);
+        assert!((purchases[0].cost - 421.68).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_coinbase_report() -> Result<()> {
+        let csv = "You can use this transaction report to inform your likely tax obligations.\n\
+            \n\
+            Transactions\n\
+            User,user@example.com,abc\n\
+            ID,Timestamp,Transaction Type,Asset,Quantity Transacted,Price Currency,Price at Transaction,Subtotal,Total (inclusive of fees and/or spread),Fees and/or Spread,Notes\n\
+            1,2024-02-01 12:00:00 UTC,Buy,BTC,0.028,USD,$43000.00,\"$1,204.00\",\"$1,207.99\",$3.99,Bought\n\
+            2,2024-02-02 12:00:00 UTC,Send,BTC,0.005,USD,$43000.00,$215.00,$215.00,$0.00,Sent\n\
+            3,2024-02-03 12:00:00 UTC,Advanced Trade Buy,BTC,0.001,USD,$44000.00,$44.00,$44.20,$0.20,\n";
+        let purchases = parse_exchange_csv(csv, &ExchangeCsvFormat::Coinbase)?;
+
+        assert_eq!(purchases.len(), 2);
+        assert_eq!(purchases[0].date, "2024-02-01");
+        assert!((purchases[0].cost - 1207.99).abs() < 1e-9);
+        assert_eq!(purchases[1].amount_btc, 0.001);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_generic_csv() -> Result<()> {
+        let columns: CsvColumns = "date=Date, amount=BTC, price=Rate".parse()?;
+        let csv = "Date,BTC,Rate\n03/01/2024,0.5,60000\n";
+        let purchases = parse_exchange_csv(csv, &ExchangeCsvFormat::Generic(columns))?;
+
+        assert_eq!(purchases[0].date, "2024-03-01");
+        assert_eq!(purchases[0].cost, 30000.0);
+        assert!("date=Date,amount=BTC".parse::<CsvColumns>().is_err());
+        assert!(
+            "date=Date,amount=BTC,fee=Fee"
+                .parse::<CsvColumns>()
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_single_and_accumulated_purchases() -> Result<()> {
+        let purchases = vec![
+            purchase("2024-01-01", 0.01, 400.0),
+            purchase("2024-01-08", 0.01, 500.0),
+            purchase("2024-01-09", 0.2, 9000.0),
+        ];
+        let mut utxos = vec![
+            // Two weekly buys withdrawn together, minus a withdrawal fee
+            utxo("accumulated", 0.0199, "2024-01-10"),
+            utxo("single", 0.2, "2024-01-10"),
+            utxo("unrelated", 1.0, "2024-01-10"),
+        ];
+        let matched = match_purchases(&mut utxos, &purchases);
+
+        assert_eq!(matched, 2);
+        assert_eq!(utxos[1].price_at_purchase, Some(45000.0));
+        assert_eq!(utxos[0].price_at_purchase, Some(45000.0));
+        assert_eq!(utxos[0].exchange.as_deref(), Some("kraken"));
+        assert_eq!(utxos[2].price_at_purchase, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_ignores_purchases_outside_window() -> Result<()> {
+        let purchases = vec![
+            purchase("2024-01-01", 0.1, 4000.0),
+            purchase("2024-03-01", 0.1, 6000.0),
+        ];
+        let mut utxos = vec![utxo("late", 0.1, "2024-02-15")];
+
+        assert_eq!(match_purchases(&mut utxos, &purchases), 0);
+        assert_eq!(utxos[0].cost_basis, None);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,24 @@
 use anyhow::{Context, Result};
-use bitcoin::Network;
-use serde::Deserialize;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv, Xpub};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::{Address, CompressedPublicKey, Network};
+use serde::{Deserialize, Serialize};
 use std::io::BufReader;
 use std::path::Path;
+use std::str::FromStr;
+
+use crate::descriptor::descriptor_checksum;
+use crate::error::{CoreResult, bail, ensure};
+use crate::multisig_setup::CosignerKey;
+use crate::slip132::{Slip132Format, to_slip132_string};
 
 use frozenkrill_core::wallet_export::GenericOutputExportJson;
 
+/// Addresses of each keychain listed in a generated export
+pub const DEFAULT_EXPORT_ADDRESS_COUNT: u32 = 100;
+
 // Re-define the structures with public fields to work around private field access
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct SingleSigWalletData {
     #[allow(dead_code)]
     wallet: String,
@@ -38,7 +49,7 @@ struct SingleSigWalletData {
     change_addresses: Vec<AddressInfo>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct MultiSigWalletData {
     #[allow(dead_code)]
     wallet: String,
@@ -54,7 +65,7 @@ struct MultiSigWalletData {
     change_addresses: Vec<AddressInfo>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct AddressInfo {
     address: String,
     #[allow(dead_code)]
@@ -65,6 +76,13 @@ struct AddressInfo {
     index: Option<u32>,
 }
 
+/// Policy of a frozenkrill multisig export: `threshold` of the cosigner keys
+#[derive(Debug, Clone)]
+pub struct FrozenkrillMultisig {
+    pub threshold: usize,
+    pub cosigners: Vec<CosignerKey>,
+}
+
 /// Represents a frozenkrill wallet export, either single-sig or multi-sig
 #[derive(Debug)]
 #[allow(private_interfaces)]
@@ -77,7 +95,7 @@ pub enum FrozenkrillWallet {
 
 impl FrozenkrillWallet {
     /// Load a frozenkrill wallet from a JSON export file
-    pub fn from_file(path: &Path) -> Result<Self> {
+    pub fn from_file(path: &Path) -> CoreResult<Self> {
         // First pass: detect wallet type
         let file = std::fs::File::open(path).with_context(|| {
             format!("Failed to open wallet file: {path}", path = path.display())
@@ -96,13 +114,188 @@ impl FrozenkrillWallet {
                 Ok(Self::SingleSig(wallet))
             }
             Some(frozenkrill_core::wallet_description::SigType::Multisig(_)) => {
-                let wallet: MultiSigWalletData = serde_json::from_str(&content)?;
-                Ok(Self::MultiSig(wallet))
+                let wallet = Self::MultiSig(serde_json::from_str(&content)?);
+                wallet.multisig()?;
+                Ok(wallet)
             }
-            _ => anyhow::bail!("Unknown or missing wallet signature type"),
+            _ => bail!("Unknown or missing wallet signature type"),
         }
     }
 
+    /// Build the public export frozenkrill writes for a singlesig (segwit-native)
+    /// wallet of a BIP39 mnemonic, listing `address_count` addresses per keychain
+    pub fn singlesig_from_mnemonic(
+        mnemonic: &str,
+        passphrase: &str,
+        network: Network,
+        address_count: u32,
+    ) -> CoreResult<Self> {
+        let mnemonic =
+            bip39::Mnemonic::parse_normalized(mnemonic.trim()).context("Invalid BIP39 mnemonic")?;
+        let seed = mnemonic.to_seed_normalized(passphrase);
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(network, &seed).context("Failed to derive master key")?;
+        let fingerprint = master.fingerprint(&secp);
+
+        let (network_name, coin_type, singlesig_format, multisig_format) = match network {
+            Network::Bitcoin => (
+                "bitcoin",
+                0,
+                Slip132Format::Zpub,
+                Slip132Format::ZpubMultisig,
+            ),
+            Network::Testnet => (
+                "testnet",
+                1,
+                Slip132Format::Vpub,
+                Slip132Format::VpubMultisig,
+            ),
+            Network::Signet => (
+                "signet",
+                1,
+                Slip132Format::Vpub,
+                Slip132Format::VpubMultisig,
+            ),
+            Network::Regtest => (
+                "regtest",
+                1,
+                Slip132Format::Vpub,
+                Slip132Format::VpubMultisig,
+            ),
+            other => bail!("frozenkrill does not support {other}"),
+        };
+        let singlesig_path = format!("84'/{coin_type}'/0'");
+        let multisig_path = format!("48'/{coin_type}'/0'/2'");
+        let singlesig_xpub = account_xpub(&secp, &master, &singlesig_path)?;
+        let multisig_xpub = account_xpub(&secp, &master, &multisig_path)?;
+
+        let singlesig_descriptor = |keychain: u32| -> Result<String> {
+            let body =
+                format!("wpkh([{fingerprint}/{singlesig_path}]{singlesig_xpub}/{keychain}/*)");
+            let checksum = descriptor_checksum(&body)?;
+            Ok(format!("{body}#{checksum}"))
+        };
+        let multisig_key =
+            |keychain: u32| format!("[{fingerprint}/{multisig_path}]{multisig_xpub}/{keychain}/*");
+        let addresses = |keychain: u32| -> Result<Vec<AddressInfo>> {
+            (0..address_count)
+                .map(|index| {
+                    let child = singlesig_xpub.derive_pub(
+                        &secp,
+                        &[
+                            ChildNumber::from_normal_idx(keychain)?,
+                            ChildNumber::from_normal_idx(index)?,
+                        ],
+                    )?;
+                    let address = Address::p2wpkh(&CompressedPublicKey(child.public_key), network);
+                    Ok(AddressInfo {
+                        address: address.to_string(),
+                        derivation_path: Some(format!("{singlesig_path}/{keychain}/{index}")),
+                        index: None,
+                    })
+                })
+                .collect()
+        };
+
+        Ok(Self::SingleSig(SingleSigWalletData {
+            wallet: "frozenkrill".to_string(),
+            version: 0,
+            sigtype: "singlesig".to_string(),
+            master_fingerprint: fingerprint.to_string(),
+            singlesig_xpub: to_slip132_string(&singlesig_xpub, singlesig_format)?,
+            singlesig_derivation_path: singlesig_path.clone(),
+            multisig_xpub: to_slip132_string(&multisig_xpub, multisig_format)?,
+            multisig_derivation_path: multisig_path.clone(),
+            singlesig_receiving_output_descriptor: singlesig_descriptor(0)?,
+            singlesig_change_output_descriptor: singlesig_descriptor(1)?,
+            multisig_receiving_output_descriptor_key: multisig_key(0),
+            multisig_change_output_descriptor_key: multisig_key(1),
+            script_type: "segwit-native".to_string(),
+            network: network_name.to_string(),
+            receiving_addresses: addresses(0)?,
+            change_addresses: addresses(1)?,
+        }))
+    }
+
+    /// Serialize as a frozenkrill JSON export
+    pub fn to_json(&self) -> CoreResult<String> {
+        let json = match self {
+            Self::SingleSig(s) => serde_json::to_string_pretty(s)?,
+            Self::MultiSig(m) => serde_json::to_string_pretty(m)?,
+        };
+        Ok(json)
+    }
+
+    /// Write the JSON export to `path`
+    pub fn save(&self, path: &Path) -> CoreResult<()> {
+        std::fs::write(path, self.to_json()?).with_context(|| {
+            format!("Failed to write wallet file: {path}", path = path.display())
+        })?;
+        Ok(())
+    }
+
+    /// Threshold and cosigner keys of a multisig export, checked against its
+    /// `wsh(sortedmulti)` descriptors; `None` for singlesig wallets
+    pub fn multisig(&self) -> CoreResult<Option<FrozenkrillMultisig>> {
+        let Self::MultiSig(m) = self else {
+            return Ok(None);
+        };
+        let (threshold, total): (usize, usize) = m
+            .sigtype
+            .split_once("-of-")
+            .and_then(|(threshold, total)| Some((threshold.parse().ok()?, total.parse().ok()?)))
+            .with_context(|| format!("Invalid multisig type '{sigtype}'", sigtype = m.sigtype))?;
+
+        let body = strip_checksum(&m.receiving_output_descriptor);
+        let (descriptor_threshold, keys) = body
+            .strip_prefix("wsh(sortedmulti(")
+            .and_then(|inner| inner.strip_suffix("))"))
+            .and_then(|inner| inner.split_once(','))
+            .with_context(|| {
+                format!("Expected a wsh(sortedmulti(...)) receiving descriptor, got {body}")
+            })?;
+        ensure!(
+            descriptor_threshold == threshold.to_string(),
+            "Descriptor threshold {descriptor_threshold} does not match {sigtype}",
+            sigtype = m.sigtype
+        );
+        let cosigners = keys
+            .split(',')
+            .map(|key| {
+                let key = key
+                    .strip_suffix("/0/*")
+                    .with_context(|| format!("Cosigner key {key} is not a receiving key"))?;
+                key.parse::<CosignerKey>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            cosigners.len() == total,
+            "{sigtype} wallet has {count} cosigner keys",
+            sigtype = m.sigtype,
+            count = cosigners.len()
+        );
+        self.descriptor()?;
+
+        Ok(Some(FrozenkrillMultisig {
+            threshold,
+            cosigners,
+        }))
+    }
+
+    /// Descriptor covering both the receiving and change keychains, with a
+    /// `<0;1>` step in place of `0` and `1`
+    pub fn descriptor(&self) -> CoreResult<String> {
+        let receiving = strip_checksum(self.receiving_descriptor());
+        let change = strip_checksum(self.change_descriptor());
+        ensure!(
+            receiving.replace("/0/*", "/1/*") == change,
+            "Receiving and change descriptors differ beyond the keychain index"
+        );
+        let body = receiving.replace("/0/*", "/<0;1>/*");
+        let checksum = descriptor_checksum(&body)?;
+        Ok(format!("{body}#{checksum}"))
+    }
+
     /// Get the receiving (external) descriptor
     pub fn receiving_descriptor(&self) -> &str {
         match self {
@@ -126,13 +319,7 @@ impl FrozenkrillWallet {
             Self::MultiSig(m) => &m.network,
         };
 
-        match network_str.as_str() {
-            "bitcoin" => Network::Bitcoin,
-            "testnet" => Network::Testnet,
-            "regtest" => Network::Regtest,
-            "signet" => Network::Signet,
-            _ => Network::Bitcoin, // Default to mainnet
-        }
+        crate::network::parse_network(network_str).unwrap_or(Network::Bitcoin) // Default to mainnet
     }
 
     /// Check if an address belongs to this wallet
@@ -224,6 +411,22 @@ impl FrozenkrillWallet {
     }
 }
 
+fn strip_checksum(descriptor: &str) -> &str {
+    descriptor
+        .split_once('#')
+        .map_or(descriptor, |(body, _)| body)
+}
+
+/// Account-level xpub at `path` (relative to the master key)
+fn account_xpub<C: Signing>(secp: &Secp256k1<C>, master: &Xpriv, path: &str) -> Result<Xpub> {
+    let path = DerivationPath::from_str(&format!("m/{path}"))
+        .with_context(|| format!("Invalid derivation path: {path}"))?;
+    let account = master
+        .derive_priv(secp, &path)
+        .context("Failed to derive account key")?;
+    Ok(Xpub::from_priv(secp, &account))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -362,6 +565,35 @@ mod tests {
         );
         assert_eq!(wallet.master_fingerprint(), None); // No fingerprint for multisig
 
+        let multisig = wallet.multisig()?.context("Expected a multisig policy")?;
+        assert_eq!(multisig.threshold, 2);
+        assert_eq!(
+            multisig
+                .cosigners
+                .iter()
+                .map(|cosigner| cosigner.fingerprint.to_string())
+                .collect::<Vec<_>>(),
+            vec!["1c2b4725", "88c3e90a", "a0342720"]
+        );
+        assert!(
+            wallet
+                .descriptor()?
+                .starts_with("wsh(sortedmulti(2,[1c2b4725/48'/0'/0'/2']xpub6Dp8hC2nCxMi8E8LwP8Wd2KzTnoe7PE8eRXt411uwvNqvwxYCGRiAxZvu4GRQQmXisb5PvUmERsehSPCU7SJAJDYri3BB3q9YzymHs4idPS/<0;1>/*,")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multisig_threshold_mismatch() -> Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(
+            create_test_multisig_wallet()
+                .replace("\"2-of-3\"", "\"3-of-3\"")
+                .as_bytes(),
+        )?;
+
+        assert!(FrozenkrillWallet::from_file(temp_file.path()).is_err());
         Ok(())
     }
 
@@ -374,6 +606,45 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_singlesig_export_from_mnemonic() -> Result<()> {
+        let wallet = FrozenkrillWallet::singlesig_from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "",
+            Network::Bitcoin,
+            2,
+        )?;
+
+        assert_eq!(wallet.master_fingerprint(), Some("73c5da0a"));
+        assert_eq!(wallet.wallet_type(), "singlesig");
+        assert!(
+            wallet
+                .receiving_descriptor()
+                .starts_with("wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*)#")
+        );
+        // BIP84 test vectors
+        assert_eq!(
+            wallet.receiving_addresses(),
+            vec![
+                "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+                "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g"
+            ]
+        );
+        assert_eq!(
+            wallet.change_addresses()[0],
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el"
+        );
+
+        // The export reads back as a frozenkrill wallet
+        let temp_file = NamedTempFile::new()?;
+        wallet.save(temp_file.path())?;
+        let loaded = FrozenkrillWallet::from_file(temp_file.path())?;
+        assert_eq!(loaded.receiving_descriptor(), wallet.receiving_descriptor());
+        assert_eq!(loaded.network(), Network::Bitcoin);
+
+        Ok(())
+    }
+
     #[test]
     fn test_wallet_descriptors() -> Result<()> {
         let mut temp_file = NamedTempFile::new()?;
@@ -393,6 +664,8 @@ mod tests {
         );
         assert!(wallet.receiving_descriptor().ends_with("/0/*)#x703tmpk"));
         assert!(wallet.change_descriptor().ends_with("/1/*)#h22skw3w"));
+        assert!(wallet.descriptor()?.contains("/<0;1>/*)#"));
+        assert!(wallet.multisig()?.is_none());
 
         Ok(())
     }
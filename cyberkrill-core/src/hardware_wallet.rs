@@ -1,5 +1,12 @@
-use anyhow::{Context, Result};
+use anyhow::Context;
+use bitcoin::Network;
+use bitcoin::bip32::Fingerprint;
+use bitcoin::psbt::Psbt;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::error::{CoreResult, bail};
+use crate::message_signing::MessageSigningDevice;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AddressInfo {
@@ -24,10 +31,283 @@ pub struct DeviceInfo {
     pub fingerprint: Option<String>,
 }
 
+/// Device found by [`list_hardware_wallets`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectedDevice {
+    /// trezor, jade, coldcard, bitbox, tapsigner, satscard or satschip
+    pub device: String,
+    pub version: Option<String>,
+    pub master_fingerprint: Option<String>,
+    /// Serial port, USB serial number or similar, when the backend reports one
+    pub path: Option<String>,
+    /// Why the device was found but could not be fully queried (e.g. locked)
+    pub error: Option<String>,
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "trezor",
+        feature = "jade",
+        feature = "coldcard",
+        feature = "bitbox",
+        feature = "smartcards"
+    )),
+    allow(dead_code)
+)]
+impl DetectedDevice {
+    fn new(device: &str, path: Option<String>) -> Self {
+        Self {
+            device: device.to_string(),
+            version: None,
+            master_fingerprint: None,
+            path,
+            error: None,
+        }
+    }
+
+    /// Record the first error hit while querying the device
+    fn record_error(&mut self, error: anyhow::Error) {
+        if self.error.is_none() {
+            self.error = Some(format!("{error:#}"));
+        }
+    }
+}
+
+/// Probe every supported backend and list the connected devices.
+///
+/// Backends with nothing connected are skipped; a device that is found but
+/// cannot be queried is listed with `error` set.
+pub async fn list_hardware_wallets() -> Vec<DetectedDevice> {
+    #[allow(unused_mut)]
+    let mut devices = Vec::new();
+
+    #[cfg(feature = "trezor")]
+    devices.extend(probe_trezor().await);
+
+    #[cfg(feature = "jade")]
+    devices.extend(probe_jade().await);
+
+    #[cfg(feature = "coldcard")]
+    devices.extend(probe_coldcard().await);
+
+    #[cfg(feature = "bitbox")]
+    devices.extend(probe_bitbox().await);
+
+    #[cfg(feature = "smartcards")]
+    devices.extend(probe_cktap().await);
+
+    devices
+}
+
+/// Connected signing device whose master fingerprint is `fingerprint`
+pub async fn find_device_by_fingerprint(
+    fingerprint: Fingerprint,
+) -> CoreResult<MessageSigningDevice> {
+    select_device_by_fingerprint(&list_hardware_wallets().await, fingerprint)
+}
+
+/// The single signing device in `devices` with master fingerprint `fingerprint`
+pub fn select_device_by_fingerprint(
+    devices: &[DetectedDevice],
+    fingerprint: Fingerprint,
+) -> CoreResult<MessageSigningDevice> {
+    let fingerprint = fingerprint.to_string();
+    let mut matching = devices
+        .iter()
+        .filter(|device| device.master_fingerprint.as_deref() == Some(fingerprint.as_str()))
+        .filter_map(|device| device.device.parse::<MessageSigningDevice>().ok());
+    match (matching.next(), matching.next()) {
+        (Some(device), None) => Ok(device),
+        (Some(_), Some(_)) => bail!("More than one connected device has fingerprint {fingerprint}"),
+        (None, _) => bail!(
+            "No connected device has fingerprint {fingerprint} (found {count} device(s))",
+            count = devices.len()
+        ),
+    }
+}
+
+/// Result of signing a PSBT with whichever device holds one of its keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HwSignOutput {
+    pub device: String,
+    /// Fingerprint that matched the PSBT; `None` when the device was chosen with `--device`
+    pub master_fingerprint: Option<String>,
+    pub psbt_base64: String,
+    pub psbt_hex: String,
+}
+
+/// Master fingerprints in the key origins of a PSBT's inputs
+pub fn psbt_input_fingerprints(psbt: &Psbt) -> BTreeSet<Fingerprint> {
+    psbt.inputs
+        .iter()
+        .flat_map(|input| {
+            input
+                .bip32_derivation
+                .values()
+                .map(|(fingerprint, _)| *fingerprint)
+                .chain(
+                    input
+                        .tap_key_origins
+                        .values()
+                        .map(|(_, (fingerprint, _))| *fingerprint),
+                )
+        })
+        .collect()
+}
+
+/// The connected signing device holding one of `fingerprints`
+pub fn select_psbt_device(
+    devices: &[DetectedDevice],
+    fingerprints: &BTreeSet<Fingerprint>,
+) -> CoreResult<(MessageSigningDevice, Fingerprint)> {
+    let matching = fingerprints
+        .iter()
+        .filter_map(|fingerprint| {
+            select_device_by_fingerprint(devices, *fingerprint)
+                .ok()
+                .map(|device| (device, *fingerprint))
+        })
+        .collect::<Vec<_>>();
+    match matching.as_slice() {
+        [selected] => Ok(*selected),
+        [] => bail!(
+            "No connected device matches the PSBT key fingerprints ({fingerprints}); pass --device to choose one",
+            fingerprints = fingerprints
+                .iter()
+                .map(Fingerprint::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        _ => bail!(
+            "Several connected devices hold keys for this PSBT ({devices}); pass --device to choose one",
+            devices = matching
+                .iter()
+                .map(|(device, fingerprint)| format!("{device} {fingerprint}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Sign `psbt_data` with `device`, or with the connected device whose master
+/// fingerprint appears in the PSBT's key origins
+pub async fn sign_psbt_with_hw(
+    psbt_data: &[u8],
+    network: Network,
+    device: Option<MessageSigningDevice>,
+) -> CoreResult<HwSignOutput> {
+    use base64::Engine;
+
+    let (device, master_fingerprint) = match device {
+        Some(device) => (device, None),
+        None => {
+            let psbt = Psbt::deserialize(psbt_data).context("Failed to parse PSBT")?;
+            let fingerprints = psbt_input_fingerprints(&psbt);
+            if fingerprints.is_empty() {
+                bail!("PSBT inputs carry no key origins; pass --device to choose a signer");
+            }
+            let (device, fingerprint) =
+                select_psbt_device(&list_hardware_wallets().await, &fingerprints)?;
+            (device, Some(fingerprint.to_string()))
+        }
+    };
+
+    let signed = crate::message_signing::sign_psbt_with_device(device, psbt_data, network).await?;
+    Ok(HwSignOutput {
+        device: device.to_string(),
+        master_fingerprint,
+        psbt_base64: base64::engine::general_purpose::STANDARD.encode(&signed),
+        psbt_hex: hex::encode(&signed),
+    })
+}
+
+#[cfg(feature = "trezor")]
+async fn probe_trezor() -> Option<DetectedDevice> {
+    let mut wallet = crate::trezor::TrezorWallet::connect().await.ok()?;
+    let mut detected = DetectedDevice::new("trezor", None);
+    match wallet.get_device_info() {
+        Ok(info) => detected.version = Some(info.version),
+        Err(error) => detected.record_error(error.into()),
+    }
+    match wallet.get_xpub(
+        crate::message_signing::MASTER_CHILD_PATH,
+        bitcoin::Network::Bitcoin,
+    ) {
+        Ok(xpub) => detected.master_fingerprint = Some(xpub.parent_fingerprint.to_string()),
+        Err(error) => detected.record_error(error.into()),
+    }
+    Some(detected)
+}
+
+/// Jade only reports its version without a PIN, so the fingerprint is left empty
+#[cfg(feature = "jade")]
+async fn probe_jade() -> Vec<DetectedDevice> {
+    let mut devices = Vec::new();
+    for device in jade_bitcoin::JadeClient::list_devices() {
+        let mut detected = DetectedDevice::new("jade", Some(device.port_name.clone()));
+        match jade_bitcoin::JadeClient::connect_to(&device.port_name).await {
+            Ok(mut client) => match client.get_version_info().await {
+                Ok(info) => detected.version = Some(info.jade_version),
+                Err(error) => detected.record_error(error.into()),
+            },
+            Err(error) => detected.record_error(error.into()),
+        }
+        devices.push(detected);
+    }
+    devices
+}
+
+#[cfg(feature = "coldcard")]
+async fn probe_coldcard() -> Option<DetectedDevice> {
+    let serials = crate::coldcard::ColdcardWallet::list_devices().await.ok()?;
+    let serial = serials.into_iter().next()?;
+    let mut detected = DetectedDevice::new("coldcard", Some(serial));
+    match crate::coldcard::ColdcardWallet::connect().await {
+        Ok(mut wallet) => match wallet.get_device_info() {
+            Ok(info) => {
+                detected.version = Some(info.version);
+                detected.master_fingerprint = info.fingerprint;
+            }
+            Err(error) => detected.record_error(error.into()),
+        },
+        Err(error) => detected.record_error(error.into()),
+    }
+    Some(detected)
+}
+
+#[cfg(feature = "bitbox")]
+async fn probe_bitbox() -> Option<DetectedDevice> {
+    let wallet = crate::bitbox::BitboxWallet::connect().await.ok()?;
+    let mut detected = DetectedDevice::new("bitbox", None);
+    match wallet.get_device_info().await {
+        Ok(info) => {
+            detected.version = Some(info.version);
+            detected.master_fingerprint = info.fingerprint;
+        }
+        Err(error) => detected.record_error(error.into()),
+    }
+    Some(detected)
+}
+
+#[cfg(feature = "smartcards")]
+async fn probe_cktap() -> Option<DetectedDevice> {
+    use cktap_direct::CkTapCard;
+
+    let card = cktap_direct::discovery::find_first().await.ok()?;
+    let device = match card {
+        CkTapCard::TapSigner(_) => "tapsigner",
+        CkTapCard::SatsCard(_) => "satscard",
+        CkTapCard::SatsChip(_) => "satschip",
+        #[allow(unreachable_patterns)]
+        _ => "cktap",
+    };
+    Some(DetectedDevice::new(device, None))
+}
+
 /// Helper function to parse and validate BIP32 derivation paths
-pub fn parse_derivation_path(path: &str) -> Result<Vec<u32>> {
+pub fn parse_derivation_path(path: &str) -> CoreResult<Vec<u32>> {
     if !path.starts_with("m/") {
-        anyhow::bail!("Derivation path must start with 'm/'");
+        bail!("Derivation path must start with 'm/'");
     }
 
     let path_str = &path[2..]; // Remove "m/"
@@ -64,6 +344,7 @@ pub fn parse_derivation_path(path: &str) -> Result<Vec<u32>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use anyhow::Result;
 
     #[test]
     fn test_parse_derivation_path() -> Result<()> {
@@ -103,4 +384,86 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_select_device_by_fingerprint() -> Result<()> {
+        use std::str::FromStr;
+
+        let mut trezor = DetectedDevice::new("trezor", None);
+        trezor.master_fingerprint = Some("73c5da0a".to_string());
+        let mut coldcard = DetectedDevice::new("coldcard", Some("serial".to_string()));
+        coldcard.master_fingerprint = Some("0f056943".to_string());
+        let mut tapsigner = DetectedDevice::new("tapsigner", None);
+        tapsigner.master_fingerprint = Some("d34db33f".to_string());
+        let devices = vec![trezor, coldcard, tapsigner];
+
+        assert_eq!(
+            select_device_by_fingerprint(&devices, Fingerprint::from_str("0f056943")?)?,
+            MessageSigningDevice::Coldcard
+        );
+        // Only signing devices can be selected
+        assert!(
+            select_device_by_fingerprint(&devices, Fingerprint::from_str("d34db33f")?).is_err()
+        );
+        assert!(
+            select_device_by_fingerprint(&devices, Fingerprint::from_str("00000000")?).is_err()
+        );
+
+        let duplicate = vec![devices[0].clone(), devices[0].clone()];
+        assert!(
+            select_device_by_fingerprint(&duplicate, Fingerprint::from_str("73c5da0a")?).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_psbt_device() -> Result<()> {
+        use bitcoin::bip32::DerivationPath;
+        use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+        use bitcoin::{Transaction, absolute, transaction};
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[1u8; 32])?;
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: Vec::new(),
+        })?;
+        psbt.inputs[0].bip32_derivation.insert(
+            PublicKey::from_secret_key(&secp, &secret),
+            (
+                Fingerprint::from_str("0f056943")?,
+                DerivationPath::from_str("m/84'/0'/0'/0/0")?,
+            ),
+        );
+        let fingerprints = psbt_input_fingerprints(&psbt);
+        assert_eq!(
+            fingerprints
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["0f056943"]
+        );
+
+        let mut trezor = DetectedDevice::new("trezor", None);
+        trezor.master_fingerprint = Some("73c5da0a".to_string());
+        let mut coldcard = DetectedDevice::new("coldcard", None);
+        coldcard.master_fingerprint = Some("0f056943".to_string());
+
+        let (device, fingerprint) =
+            select_psbt_device(&[trezor.clone(), coldcard.clone()], &fingerprints)?;
+        assert_eq!(device, MessageSigningDevice::Coldcard);
+        assert_eq!(fingerprint.to_string(), "0f056943");
+        assert!(select_psbt_device(std::slice::from_ref(&trezor), &fingerprints).is_err());
+
+        let both = fingerprints
+            .iter()
+            .copied()
+            .chain([Fingerprint::from_str("73c5da0a")?])
+            .collect();
+        assert!(select_psbt_device(&[trezor, coldcard], &both).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,298 @@
+//! Output descriptors built from hardware wallet xpubs
+//!
+//! The device is asked for the account xpub at the standard BIP84/BIP86/BIP48
+//! path and for the master fingerprint, and the key is written with a `<0;1>`
+//! multipath suffix so one descriptor covers both the receive and change chains.
+
+use anyhow::{Context, Result, anyhow};
+use bitcoin::bip32::{Fingerprint, Xpub};
+use bitcoin::{Network, NetworkKind};
+use serde::Serialize;
+use strum::{Display, EnumString};
+
+use crate::descriptor::{check_descriptor_checksum, expand_multipath_descriptor, parse_descriptor};
+use crate::error::{CoreResult, bail, ensure};
+use crate::message_signing::MessageSigningDevice;
+
+/// Receive/change multipath suffix appended to account keys
+const MULTIPATH_SUFFIX: &str = "/<0;1>/*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum HwDescriptorType {
+    /// Single-sig native segwit (BIP84)
+    Wpkh,
+    /// Single-sig taproot key path (BIP86)
+    Tr,
+    /// Native segwit `sortedmulti` (BIP48 script type 2)
+    WshMulti,
+}
+
+impl HwDescriptorType {
+    /// Account derivation path for `network` and `account`
+    pub fn account_path(self, network: Network, account: u32) -> String {
+        let coin = if network == Network::Bitcoin { 0 } else { 1 };
+        match self {
+            HwDescriptorType::Wpkh => format!("m/84'/{coin}'/{account}'"),
+            HwDescriptorType::Tr => format!("m/86'/{coin}'/{account}'"),
+            HwDescriptorType::WshMulti => format!("m/48'/{coin}'/{account}'/2'"),
+        }
+    }
+}
+
+/// Descriptor pair for a device account
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedDescriptor {
+    pub device: String,
+    pub descriptor_type: String,
+    pub master_fingerprint: String,
+    pub derivation_path: String,
+    pub xpub: String,
+    /// `[fingerprint/path]xpub/<0;1>/*`, for use as a cosigner key elsewhere
+    pub key: String,
+    /// Multipath descriptor covering both chains; `None` for `wsh-multi` without cosigners
+    pub descriptor: Option<String>,
+    pub receive_descriptor: Option<String>,
+    pub change_descriptor: Option<String>,
+}
+
+/// Build the descriptors for an account xpub already fetched from a device
+#[allow(clippy::too_many_arguments)]
+pub fn build_exported_descriptor(
+    device: MessageSigningDevice,
+    descriptor_type: HwDescriptorType,
+    master_fingerprint: Fingerprint,
+    xpub: &Xpub,
+    network: Network,
+    account: u32,
+    cosigners: &[String],
+    threshold: Option<usize>,
+) -> CoreResult<ExportedDescriptor> {
+    ensure!(
+        xpub.network == NetworkKind::from(network),
+        "Device returned an xpub for the wrong network (expected {network})"
+    );
+    let derivation_path = descriptor_type.account_path(network, account);
+    let origin = derivation_path.trim_start_matches("m/");
+    let key = format!("[{master_fingerprint}/{origin}]{xpub}{MULTIPATH_SUFFIX}");
+
+    let body = match descriptor_type {
+        HwDescriptorType::Wpkh | HwDescriptorType::Tr => {
+            ensure!(
+                cosigners.is_empty() && threshold.is_none(),
+                "--cosigner and --threshold only apply to wsh-multi"
+            );
+            Some(format!("{descriptor_type}({key})"))
+        }
+        HwDescriptorType::WshMulti if cosigners.is_empty() => {
+            ensure!(
+                threshold.is_none(),
+                "--threshold requires at least one --cosigner"
+            );
+            None
+        }
+        HwDescriptorType::WshMulti => {
+            let threshold = threshold.context("--threshold is required with --cosigner")?;
+            let keys = std::iter::once(Ok(key.clone()))
+                .chain(cosigners.iter().map(|cosigner| cosigner_key(cosigner)))
+                .collect::<Result<Vec<_>>>()?;
+            ensure!(
+                (1..=keys.len()).contains(&threshold),
+                "Threshold {threshold} must be between 1 and {count} keys",
+                count = keys.len()
+            );
+            Some(format!(
+                "wsh(sortedmulti({threshold},{keys}))",
+                keys = keys.join(",")
+            ))
+        }
+    };
+
+    let (descriptor, receive_descriptor, change_descriptor) = match body {
+        Some(body) => {
+            let descriptor = check_descriptor_checksum(&body)?.descriptor;
+            parse_descriptor(&descriptor)?;
+            let [receive, change]: [String; 2] = expand_multipath_descriptor(&body)?
+                .try_into()
+                .map_err(|_| anyhow!("Expected receive and change paths in {descriptor}"))?;
+            let receive = check_descriptor_checksum(&receive)?.descriptor;
+            let change = check_descriptor_checksum(&change)?.descriptor;
+            (Some(descriptor), Some(receive), Some(change))
+        }
+        None => (None, None, None),
+    };
+
+    Ok(ExportedDescriptor {
+        device: device.to_string(),
+        descriptor_type: descriptor_type.to_string(),
+        master_fingerprint: master_fingerprint.to_string(),
+        derivation_path,
+        xpub: xpub.to_string(),
+        key,
+        descriptor,
+        receive_descriptor,
+        change_descriptor,
+    })
+}
+
+/// Cosigner key with the multipath suffix added when it has no derivation of its own
+fn cosigner_key(cosigner: &str) -> Result<String> {
+    let cosigner = cosigner.trim();
+    if cosigner.ends_with(MULTIPATH_SUFFIX) {
+        Ok(cosigner.to_string())
+    } else if cosigner.ends_with("/*") {
+        bail!("Cosigner key '{cosigner}' must end with {MULTIPATH_SUFFIX} or have no derivation")
+    } else {
+        Ok(format!("{cosigner}{MULTIPATH_SUFFIX}"))
+    }
+}
+
+/// Fetch the account xpub and master fingerprint from `device` and build its descriptors
+pub async fn export_hw_descriptor(
+    device: MessageSigningDevice,
+    descriptor_type: HwDescriptorType,
+    network: Network,
+    account: u32,
+    cosigners: &[String],
+    threshold: Option<usize>,
+) -> CoreResult<ExportedDescriptor> {
+    let path = descriptor_type.account_path(network, account);
+    let (master_fingerprint, xpub) =
+        crate::message_signing::fetch_device_key_origin(device, &path, network).await?;
+    build_exported_descriptor(
+        device,
+        descriptor_type,
+        master_fingerprint,
+        &xpub,
+        network,
+        account,
+        cosigners,
+        threshold,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Account xpubs of the "abandon ... about" test mnemonic, fingerprint 73c5da0a
+    const XPUB_ACCOUNT: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    const TPUB_ACCOUNT: &str = "tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ";
+
+    fn fingerprint() -> Result<Fingerprint> {
+        Ok(Fingerprint::from_str("73c5da0a")?)
+    }
+
+    #[test]
+    fn test_account_paths() -> Result<()> {
+        assert_eq!(
+            HwDescriptorType::Wpkh.account_path(Network::Bitcoin, 0),
+            "m/84'/0'/0'"
+        );
+        assert_eq!(
+            HwDescriptorType::Tr.account_path(Network::Testnet, 1),
+            "m/86'/1'/1'"
+        );
+        assert_eq!(
+            HwDescriptorType::WshMulti.account_path(Network::Signet, 0),
+            "m/48'/1'/0'/2'"
+        );
+        assert_eq!(
+            HwDescriptorType::from_str("wsh-multi")?,
+            HwDescriptorType::WshMulti
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_wpkh_descriptor_pair() -> Result<()> {
+        let xpub = Xpub::from_str(XPUB_ACCOUNT)?;
+        let exported = build_exported_descriptor(
+            MessageSigningDevice::Jade,
+            HwDescriptorType::Wpkh,
+            fingerprint()?,
+            &xpub,
+            Network::Bitcoin,
+            0,
+            &[],
+            None,
+        )?;
+        assert_eq!(
+            exported.key,
+            format!("[73c5da0a/84'/0'/0']{XPUB_ACCOUNT}/<0;1>/*")
+        );
+        let descriptor = exported.descriptor.context("no descriptor")?;
+        assert!(descriptor.starts_with(&format!("wpkh({key})#", key = exported.key)));
+        let receive = exported
+            .receive_descriptor
+            .context("no receive descriptor")?;
+        assert!(receive.starts_with(&format!("wpkh([73c5da0a/84'/0'/0']{XPUB_ACCOUNT}/0/*)#")));
+        let change = exported.change_descriptor.context("no change descriptor")?;
+        assert!(change.contains(&format!("{XPUB_ACCOUNT}/1/*)#")));
+        check_descriptor_checksum(&receive)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_wsh_multi_with_cosigner() -> Result<()> {
+        let xpub = Xpub::from_str(TPUB_ACCOUNT)?;
+        let cosigner = format!("[00000000/48'/1'/0'/2']{TPUB_ACCOUNT}");
+        let exported = build_exported_descriptor(
+            MessageSigningDevice::Trezor,
+            HwDescriptorType::WshMulti,
+            fingerprint()?,
+            &xpub,
+            Network::Testnet,
+            0,
+            std::slice::from_ref(&cosigner),
+            Some(2),
+        )?;
+        let descriptor = exported.descriptor.context("no descriptor")?;
+        assert!(descriptor.starts_with(&format!(
+            "wsh(sortedmulti(2,[73c5da0a/48'/1'/0'/2']{TPUB_ACCOUNT}/<0;1>/*,{cosigner}/<0;1>/*))#"
+        )));
+
+        let key_only = build_exported_descriptor(
+            MessageSigningDevice::Trezor,
+            HwDescriptorType::WshMulti,
+            fingerprint()?,
+            &xpub,
+            Network::Testnet,
+            0,
+            &[],
+            None,
+        )?;
+        assert!(key_only.descriptor.is_none());
+
+        let too_high = build_exported_descriptor(
+            MessageSigningDevice::Trezor,
+            HwDescriptorType::WshMulti,
+            fingerprint()?,
+            &xpub,
+            Network::Testnet,
+            0,
+            &[cosigner],
+            Some(3),
+        );
+        assert!(too_high.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_wrong_network_xpub() -> Result<()> {
+        let xpub = Xpub::from_str(XPUB_ACCOUNT)?;
+        let result = build_exported_descriptor(
+            MessageSigningDevice::Coldcard,
+            HwDescriptorType::Tr,
+            fingerprint()?,
+            &xpub,
+            Network::Testnet,
+            0,
+            &[],
+            None,
+        );
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -1,9 +1,50 @@
 //! Jade hardware wallet integration
 
-use anyhow::{Context, Result, bail};
-use jade_bitcoin::{JadeClient, Network as JadeNetwork};
+use anyhow::{Context, Result};
+use bitcoin::Network;
+use jade_bitcoin::{
+    JadeClient, JadeEvent, MultisigDescriptor, MultisigSigner, Network as JadeNetwork,
+    PinServerConfig, PinServerDetails,
+};
 use serde::{Deserialize, Serialize};
 
+use crate::cert_pin::CertFingerprint;
+use crate::error::{CoreResult, bail, ensure};
+use crate::multisig_setup::{JadeMultisigRegistration, MultisigSetup};
+use crate::proxy::{NetworkProxy, http_client_builder};
+
+/// How to reach the Jade and, while unlocking, its PIN server
+#[derive(Debug, Clone, Default)]
+pub struct JadeConnectOptions {
+    /// Serial port of the Jade; the first one found when unset
+    pub serial_port: Option<String>,
+    /// Connect over Bluetooth LE instead of USB serial
+    pub ble: bool,
+    /// Name or address of the Jade to use over Bluetooth; the first one found when unset
+    pub ble_device: Option<String>,
+    /// Contact this PIN server (scheme, host and port) instead of the URL the
+    /// device asks for, e.g. a self-hosted one reachable under another name
+    pub pinserver_url: Option<String>,
+    /// Accept only the PIN server TLS certificate with this SHA-256 fingerprint
+    pub pinserver_cert_fingerprint: Option<CertFingerprint>,
+    /// Use the PIN server's onion URL; needs a Tor proxy
+    pub pinserver_onion: bool,
+    /// Never contact a PIN server, for a SeedQR temporary signer
+    pub offline: bool,
+    /// Proxy for PIN server and firmware server requests, e.g. Tor
+    pub proxy: Option<NetworkProxy>,
+}
+
+/// Result of changing the PIN server of a Jade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JadePinServerResult {
+    /// `None` after going back to Blockstream's PIN server
+    pub url: Option<String>,
+    pub onion_url: Option<String>,
+    pub pubkey: Option<String>,
+    pub certificate: bool,
+}
+
 /// Result of Jade address generation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JadeAddressResult {
@@ -18,6 +59,7 @@ pub struct JadeXpubResult {
     pub xpub: String,
     pub path: String,
     pub network: String,
+    pub master_fingerprint: String,
 }
 
 /// Result of Jade PSBT signing
@@ -27,27 +69,110 @@ pub struct JadeSignedPsbtResult {
     pub psbt_hex: String,
 }
 
-/// Parse network string to Jade network enum
-fn parse_network(network: &str) -> Result<JadeNetwork> {
-    match network.to_lowercase().as_str() {
-        "bitcoin" | "mainnet" | "main" => Ok(JadeNetwork::Bitcoin),
-        "testnet" | "test" => Ok(JadeNetwork::Testnet),
-        "regtest" => Ok(JadeNetwork::Regtest),
-        "signet" => Ok(JadeNetwork::Signet),
-        _ => bail!(
-            "Invalid network: {}. Use mainnet, testnet, regtest, or signet",
-            network
-        ),
+/// Result of registering a multisig wallet on Jade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JadeMultisigResult {
+    pub multisig_name: String,
+    pub network: String,
+    /// First receive address, as shown and computed by the device
+    pub first_address: String,
+    /// Names of every multisig wallet now registered on the device
+    pub registered_multisigs: Vec<String>,
+}
+
+/// Jade's network for a Bitcoin network; Jade has one test network for
+/// testnet3 and testnet4
+fn jade_network(network: Network) -> JadeNetwork {
+    match network {
+        Network::Bitcoin => JadeNetwork::Bitcoin,
+        Network::Testnet | Network::Testnet4 => JadeNetwork::Testnet,
+        Network::Regtest => JadeNetwork::Regtest,
+        Network::Signet => JadeNetwork::Signet,
     }
 }
 
-/// Generate a Bitcoin address from Jade
-pub async fn generate_jade_address(path: &str, network: &str) -> Result<JadeAddressResult> {
-    let jade_network = parse_network(network)?;
+/// Client for PIN server requests, when the defaults (the device's URL and
+/// root certificates, no proxy) do not do
+fn pinserver_http_client(options: &JadeConnectOptions) -> Result<Option<reqwest::Client>> {
+    let pin = options.pinserver_cert_fingerprint;
+    if pin.is_none() && options.proxy.is_none() {
+        return Ok(None);
+    }
 
-    let mut client = JadeClient::connect()
+    let mut builder = http_client_builder(options.proxy.as_ref());
+    if let Some(pin) = pin {
+        if let Some(url) = &options.pinserver_url {
+            CertFingerprint::ensure_tls(url, "https")?;
+        }
+        builder = builder.use_preconfigured_tls(pin.tls_config()?);
+    }
+    let client = builder
+        .build()
+        .context("Failed to build PIN server HTTP client")?;
+    Ok(Some(client))
+}
+
+#[cfg(feature = "jade-ble")]
+async fn connect_ble(device: Option<&str>) -> Result<JadeClient> {
+    eprintln!("Scanning for Jade over Bluetooth (pair with the passkey it shows if asked)...");
+    JadeClient::connect_ble(device)
         .await
-        .context("Failed to connect to Jade device")?;
+        .context("Failed to connect to Jade over Bluetooth")
+}
+
+#[cfg(not(feature = "jade-ble"))]
+async fn connect_ble(_device: Option<&str>) -> Result<JadeClient> {
+    bail!("Bluetooth support for Jade is not enabled in this build (--features jade-ble)")
+}
+
+/// Connect to the Jade described by `options`, telling the user on stderr
+/// whenever the device waits for them
+pub(crate) async fn connect(options: &JadeConnectOptions) -> Result<JadeClient> {
+    ensure!(
+        !options.pinserver_onion || options.proxy.is_some(),
+        "--pinserver-onion needs a Tor proxy (--proxy socks5://127.0.0.1:9050)"
+    );
+
+    let mut client = if options.ble {
+        connect_ble(options.ble_device.as_deref()).await?
+    } else {
+        match options.serial_port.as_deref() {
+            Some(port) => JadeClient::connect_to(port)
+                .await
+                .with_context(|| format!("Failed to connect to Jade device on {port}"))?,
+            None => JadeClient::connect()
+                .await
+                .context("Failed to connect to Jade device")?,
+        }
+    };
+    client.on_event(|event| match event {
+        JadeEvent::AwaitingPin => eprintln!("Unlock your Jade with its PIN if prompted..."),
+        JadeEvent::AwaitingConfirmation { action } => {
+            eprintln!("Confirm on your Jade to {action}...")
+        }
+        JadeEvent::Retrying { method, attempt } => {
+            eprintln!("Jade did not answer {method}, retrying (attempt {attempt})...")
+        }
+        JadeEvent::InteractionComplete => {}
+    });
+    client.set_pinserver_config(PinServerConfig {
+        url: options.pinserver_url.clone(),
+        prefer_onion: options.pinserver_onion,
+        offline: options.offline,
+        http_client: pinserver_http_client(options)?,
+    });
+    Ok(client)
+}
+
+/// Generate a Bitcoin address from Jade
+pub async fn generate_jade_address(
+    path: &str,
+    network: Network,
+    options: &JadeConnectOptions,
+) -> CoreResult<JadeAddressResult> {
+    let jade_network = jade_network(network);
+
+    let mut client = connect(options).await?;
 
     // Always try to unlock - the unlock method will check if already unlocked
     client.unlock(jade_network)
@@ -70,12 +195,14 @@ pub async fn generate_jade_address(path: &str, network: &str) -> Result<JadeAddr
 }
 
 /// Get extended public key from Jade
-pub async fn generate_jade_xpub(path: &str, network: &str) -> Result<JadeXpubResult> {
-    let jade_network = parse_network(network)?;
+pub async fn generate_jade_xpub(
+    path: &str,
+    network: Network,
+    options: &JadeConnectOptions,
+) -> CoreResult<JadeXpubResult> {
+    let jade_network = jade_network(network);
 
-    let mut client = JadeClient::connect()
-        .await
-        .context("Failed to connect to Jade device")?;
+    let mut client = connect(options).await?;
 
     // Always try to unlock - the unlock method will check if already unlocked
     client.unlock(jade_network)
@@ -91,15 +218,55 @@ pub async fn generate_jade_xpub(path: &str, network: &str) -> Result<JadeXpubRes
         .context("Failed to get xpub from Jade")?;
 
     Ok(JadeXpubResult {
-        xpub,
+        xpub: xpub.xpub.to_string(),
         path: path.to_string(),
         network: network.to_string(),
+        master_fingerprint: xpub.master_fingerprint.to_string(),
     })
 }
 
+/// Sign `message` with the key at `path`, returning Jade's 65-byte
+/// recoverable signature (the header always uses the compressed P2PKH range)
+pub async fn sign_jade_message(
+    path: &str,
+    message: &str,
+    network: Network,
+    options: &JadeConnectOptions,
+) -> CoreResult<Vec<u8>> {
+    use base64::Engine;
+
+    let jade_network = jade_network(network);
+
+    let mut client = connect(options).await?;
+
+    client.unlock(jade_network)
+        .await
+        .context("Failed to unlock Jade device. Please ensure you enter the PIN on the device when prompted.")?;
+
+    // Give the device a moment after unlock
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let signature = client
+        .sign_message(message, path)
+        .await
+        .context("Failed to sign message with Jade")?;
+
+    Ok(base64::engine::general_purpose::STANDARD
+        .decode(signature.trim())
+        .context("Jade returned a signature that is not valid base64")?)
+}
+
 /// Sign a PSBT with Jade
-pub async fn sign_psbt_with_jade(psbt_input: &str, network: &str) -> Result<JadeSignedPsbtResult> {
-    let jade_network = parse_network(network)?;
+///
+/// With `anti_exfil` the device signs through the anti-exfil protocol and every
+/// signature is checked on the host against fresh host entropy.
+pub async fn sign_psbt_with_jade(
+    psbt_input: &str,
+    network: Network,
+    options: &JadeConnectOptions,
+    anti_exfil: bool,
+) -> CoreResult<JadeSignedPsbtResult> {
+    let jade_network = jade_network(network);
 
     // Parse PSBT from hex or base64
     let psbt_bytes = if psbt_input.chars().all(|c| c.is_ascii_hexdigit()) {
@@ -109,9 +276,7 @@ pub async fn sign_psbt_with_jade(psbt_input: &str, network: &str) -> Result<Jade
             .context("Failed to decode PSBT from base64")?
     };
 
-    let mut client = JadeClient::connect()
-        .await
-        .context("Failed to connect to Jade device")?;
+    let mut client = connect(options).await?;
 
     // Always try to unlock - the unlock method will check if already unlocked
     client.unlock(jade_network)
@@ -121,10 +286,17 @@ pub async fn sign_psbt_with_jade(psbt_input: &str, network: &str) -> Result<Jade
     // Give the device a moment after unlock
     tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
 
-    let signed_psbt = client
-        .sign_psbt(&psbt_bytes, jade_network)
-        .await
-        .context("Failed to sign PSBT with Jade")?;
+    let signed_psbt = if anti_exfil {
+        client
+            .sign_psbt_anti_exfil(&psbt_bytes, jade_network)
+            .await
+            .context("Failed to sign PSBT with Jade using anti-exfil")?
+    } else {
+        client
+            .sign_psbt(&psbt_bytes, jade_network)
+            .await
+            .context("Failed to sign PSBT with Jade")?
+    };
 
     let psbt_base64 =
         base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &signed_psbt);
@@ -134,3 +306,177 @@ pub async fn sign_psbt_with_jade(psbt_input: &str, network: &str) -> Result<Jade
         psbt_hex: hex::encode(&signed_psbt),
     })
 }
+
+/// Make the Jade use another PIN server, or Blockstream's again when `details`
+/// is `None`, after confirmation on the device
+///
+/// Changing the PIN server of an initialized Jade makes its PIN unusable, so
+/// the wallet has to be restored from its seed afterwards.
+pub async fn set_jade_pinserver(
+    details: Option<&PinServerDetails>,
+    options: &JadeConnectOptions,
+) -> CoreResult<JadePinServerResult> {
+    let mut client = connect(options).await?;
+
+    match details {
+        Some(details) => {
+            client
+                .set_pinserver(details)
+                .await
+                .context("Failed to set the PIN server on Jade")?;
+            Ok(JadePinServerResult {
+                url: Some(details.url.clone()),
+                onion_url: details.onion_url.clone(),
+                pubkey: Some(hex::encode(details.pubkey)),
+                certificate: details.certificate.is_some(),
+            })
+        }
+        None => {
+            client
+                .reset_pinserver()
+                .await
+                .context("Failed to reset the PIN server on Jade")?;
+            Ok(JadePinServerResult {
+                url: None,
+                onion_url: None,
+                pubkey: None,
+                certificate: false,
+            })
+        }
+    }
+}
+
+/// Whether `error` comes from the user rejecting the request on the Jade
+pub(crate) fn is_user_cancellation(error: &anyhow::Error) -> bool {
+    crate::error::causes(error).into_iter().any(|cause| {
+        matches!(
+            cause.downcast_ref::<jade_bitcoin::Error>(),
+            Some(jade_bitcoin::Error::UserCancelled)
+        )
+    })
+}
+
+/// Convert the registration from a multisig setup into Jade's descriptor format
+pub fn jade_multisig_descriptor(
+    registration: &JadeMultisigRegistration,
+) -> CoreResult<MultisigDescriptor> {
+    let signers = registration
+        .descriptor
+        .signers
+        .iter()
+        .map(|signer| {
+            let fingerprint = hex::decode(&signer.fingerprint)
+                .ok()
+                .and_then(|bytes| <[u8; 4]>::try_from(bytes).ok())
+                .with_context(|| {
+                    format!(
+                        "Invalid signer fingerprint: {fingerprint}",
+                        fingerprint = signer.fingerprint
+                    )
+                })?;
+            Ok(MultisigSigner {
+                fingerprint,
+                derivation: signer.derivation.clone(),
+                xpub: signer.xpub.clone(),
+                path: signer.path.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(MultisigDescriptor {
+        variant: registration.descriptor.variant.clone(),
+        sorted: registration.descriptor.sorted,
+        threshold: u32::try_from(registration.descriptor.threshold)
+            .context("Multisig threshold out of range")?,
+        signers,
+    })
+}
+
+/// Register the wallet from a multisig setup on Jade and check its first address
+///
+/// The device asks the user to confirm the wallet, then shows the first receive
+/// address, which must match the address computed from the setup's descriptor.
+pub async fn register_jade_multisig(
+    setup: &MultisigSetup,
+    options: &JadeConnectOptions,
+) -> CoreResult<JadeMultisigResult> {
+    let registration = &setup.jade_registration;
+    let jade_network = jade_network(crate::network::parse_network(&registration.network)?);
+    let descriptor = jade_multisig_descriptor(registration)?;
+
+    let mut client = connect(options).await?;
+
+    // Always try to unlock - the unlock method will check if already unlocked
+    client.unlock(jade_network)
+        .await
+        .context("Failed to unlock Jade device. Please ensure you enter the PIN on the device when prompted.")?;
+
+    // Give the device a moment after unlock
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    client
+        .register_multisig(&registration.multisig_name, &descriptor, jade_network)
+        .await
+        .context("Failed to register multisig wallet on Jade")?;
+
+    // Every signer derives the first receive address at 0/0 below its account key
+    let paths = vec!["0/0"; descriptor.signers.len()];
+    let first_address = client
+        .get_multisig_address(&registration.multisig_name, &paths, jade_network)
+        .await
+        .context("Failed to get multisig address from Jade")?;
+    ensure!(
+        first_address == setup.first_address,
+        "Jade derived {first_address} but the setup expects {expected}",
+        expected = setup.first_address
+    );
+
+    let registered_multisigs = client
+        .get_registered_multisigs()
+        .await
+        .context("Failed to list multisig wallets registered on Jade")?;
+
+    Ok(JadeMultisigResult {
+        multisig_name: registration.multisig_name.clone(),
+        network: registration.network.clone(),
+        first_address,
+        registered_multisigs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multisig_setup::{JadeMultisigDescriptor, JadeMultisigSigner};
+
+    fn registration(fingerprint: &str) -> JadeMultisigRegistration {
+        JadeMultisigRegistration {
+            network: "testnet".to_string(),
+            multisig_name: "vault".to_string(),
+            descriptor: JadeMultisigDescriptor {
+                variant: "wsh(multi(k))".to_string(),
+                sorted: true,
+                threshold: 2,
+                signers: vec![JadeMultisigSigner {
+                    fingerprint: fingerprint.to_string(),
+                    derivation: vec![0x8000_0030, 0x8000_0001, 0x8000_0000, 0x8000_0002],
+                    xpub: "tpubexample".to_string(),
+                    path: Vec::new(),
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn test_converts_registration_to_jade_descriptor() -> Result<()> {
+        let descriptor = jade_multisig_descriptor(&registration("73c5da0a"))?;
+        assert_eq!(descriptor.threshold, 2);
+        assert!(descriptor.sorted);
+        assert_eq!(descriptor.signers[0].fingerprint, [0x73, 0xc5, 0xda, 0x0a]);
+        assert_eq!(descriptor.signers[0].derivation[0], 0x8000_0030);
+
+        assert!(jade_multisig_descriptor(&registration("73c5da")).is_err());
+        assert!(jade_multisig_descriptor(&registration("not hex!")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+//! Jade firmware updates over OTA
+//!
+//! Blockstream publishes firmware per board under
+//! `https://jadefw.blockstream.com/bin/<board>/`, with an `index.json` listing
+//! the builds of each release channel. Images are zlib-compressed and signed;
+//! a Jade with secure boot refuses anything Blockstream did not sign, and checks
+//! the image hashes before switching to it.
+
+use anyhow::{Context, Result};
+use jade_bitcoin::{FirmwareVersion, JadeClient, Network as JadeNetwork, VersionInfo};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{CoreError, CoreResult, bail, ensure};
+use crate::jade::{JadeConnectOptions, connect};
+
+const FIRMWARE_SERVER: &str = "https://jadefw.blockstream.com/bin";
+
+/// Release channel of Blockstream's firmware server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirmwareChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl FromStr for FirmwareChannel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "stable" => Ok(Self::Stable),
+            "beta" => Ok(Self::Beta),
+            _ => bail!("Invalid firmware channel: {s}. Use stable or beta"),
+        }
+    }
+}
+
+impl fmt::Display for FirmwareChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stable => write!(f, "stable"),
+            Self::Beta => write!(f, "beta"),
+        }
+    }
+}
+
+/// Where the new firmware comes from
+#[derive(Debug, Clone)]
+pub enum FirmwareSource {
+    /// Latest build for the device's board and configuration on `channel`;
+    /// with `force` it is installed even when it is not newer
+    Server {
+        channel: FirmwareChannel,
+        force: bool,
+    },
+    /// Compressed image downloaded beforehand, keeping Blockstream's file name
+    /// (`<version>_<config>_<size>_fw.bin`). Its hash is read from `hash` or
+    /// from a `.hash` file next to it.
+    File {
+        path: PathBuf,
+        hash: Option<[u8; 32]>,
+    },
+}
+
+/// Outcome of a firmware update or check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JadeFirmwareUpdate {
+    pub board_type: String,
+    /// Firmware configuration: "BLE" or "NORADIO"
+    pub config: String,
+    pub current_version: String,
+    /// Newest version on the channel; `None` for a local file
+    pub available_version: Option<String>,
+    /// URL or path of the firmware image
+    pub firmware: String,
+    /// Whether the image was uploaded and accepted by the device
+    pub updated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct FirmwareIndex {
+    #[serde(default)]
+    stable: Option<FirmwareRelease>,
+    #[serde(default)]
+    beta: Option<FirmwareRelease>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FirmwareRelease {
+    #[serde(default)]
+    full: Vec<FirmwareBuild>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FirmwareBuild {
+    filename: String,
+    version: String,
+    config: String,
+    fwsize: usize,
+    /// SHA-256 of the uncompressed image, as hex
+    #[serde(default)]
+    fwhash: Option<String>,
+}
+
+/// Directory of the firmware server holding builds for the device's board
+fn firmware_board(info: &VersionInfo) -> Result<String> {
+    let board = match info.board_type.as_str() {
+        "JADE" => "jade",
+        "JADE_V1_1" => "jade1.1",
+        "JADE_V2" => "jade2.0",
+        other => bail!(
+            "Blockstream publishes no firmware for board type {other}; use --file with a build for it"
+        ),
+    };
+    // Devices without secure boot run the unsigned development builds
+    Ok(if info.has_secure_boot() {
+        board.to_string()
+    } else {
+        format!("{board}dev")
+    })
+}
+
+/// Full build of `channel` for the firmware configuration `config`
+fn select_build(
+    index: &FirmwareIndex,
+    channel: FirmwareChannel,
+    config: &str,
+) -> Result<FirmwareBuild> {
+    let release = match channel {
+        FirmwareChannel::Stable => index.stable.as_ref(),
+        FirmwareChannel::Beta => index.beta.as_ref(),
+    };
+    release
+        .into_iter()
+        .flat_map(|release| &release.full)
+        .filter(|build| build.config.eq_ignore_ascii_case(config))
+        .max_by_key(|build| build.version.parse::<FirmwareVersion>().ok())
+        .cloned()
+        .with_context(|| format!("No {channel} firmware published for configuration {config}"))
+}
+
+/// Uncompressed size encoded in a firmware file name like `1.0.31_ble_1918976_fw.bin`
+fn firmware_size_from_filename(path: &Path) -> Result<usize> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let parts: Vec<&str> = name.split('_').collect();
+    match parts[..] {
+        [_, _, size, "fw.bin"] => size
+            .parse()
+            .with_context(|| format!("Invalid firmware size in file name: {name}")),
+        _ => bail!(
+            "Cannot tell the firmware size from {name}; keep Blockstream's file name (<version>_<config>_<size>_fw.bin)"
+        ),
+    }
+}
+
+/// SHA-256 given as hex, as found in `.hash` files
+pub fn parse_firmware_hash(hex_hash: &str) -> CoreResult<[u8; 32]> {
+    let bytes = hex::decode(hex_hash.trim())
+        .with_context(|| format!("Invalid firmware hash: {hex_hash}"))?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        CoreError::parse(anyhow::anyhow!(
+            "Firmware hash must be 32 bytes, got {len}",
+            len = bytes.len()
+        ))
+    })
+}
+
+async fn fetch_bytes(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Request to {url} failed"))?
+        .error_for_status()
+        .with_context(|| format!("{url} returned an unsuccessful status"))?;
+    let bytes = response
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to read {url}"))?;
+    Ok(bytes.to_vec())
+}
+
+/// Install new firmware on a Jade, or with `check_only` just report whether a
+/// newer one is available
+pub async fn update_jade_firmware(
+    source: &FirmwareSource,
+    check_only: bool,
+    options: &JadeConnectOptions,
+) -> CoreResult<JadeFirmwareUpdate> {
+    let mut client = connect(options).await?;
+    let info = client
+        .get_version_info()
+        .await
+        .context("Failed to get Jade version info")?;
+    let current = info
+        .version()
+        .context("Jade reported an unknown firmware version")?;
+
+    let mut result = JadeFirmwareUpdate {
+        board_type: info.board_type.clone(),
+        config: info.jade_config.clone(),
+        current_version: current.to_string(),
+        available_version: None,
+        firmware: String::new(),
+        updated: false,
+    };
+
+    let (compressed, firmware_size, firmware_hash) = match source {
+        FirmwareSource::Server { channel, force } => {
+            let board = firmware_board(&info)?;
+            let http = crate::proxy::http_client_builder(options.proxy.as_ref())
+                .build()
+                .context("Failed to build HTTP client")?;
+            let index_url = format!("{FIRMWARE_SERVER}/{board}/index.json");
+            let index: FirmwareIndex =
+                serde_json::from_slice(&fetch_bytes(&http, &index_url).await?)
+                    .with_context(|| format!("Invalid firmware index at {index_url}"))?;
+            let build = select_build(&index, *channel, &info.jade_config)?;
+            let available: FirmwareVersion = build
+                .version
+                .parse()
+                .context("Firmware index lists an invalid version")?;
+
+            let url = format!(
+                "{FIRMWARE_SERVER}/{board}/{filename}",
+                filename = build.filename
+            );
+            result.available_version = Some(available.to_string());
+            result.firmware = url.clone();
+            if check_only || (available <= current && !force) {
+                return Ok(result);
+            }
+
+            eprintln!("Downloading Jade firmware {available} from {url}...");
+            let compressed = fetch_bytes(&http, &url).await?;
+            let hash = match &build.fwhash {
+                Some(hash) => parse_firmware_hash(hash)?,
+                None => {
+                    let hash = fetch_bytes(&http, &format!("{url}.hash")).await?;
+                    parse_firmware_hash(&String::from_utf8_lossy(&hash))?
+                }
+            };
+            (compressed, build.fwsize, hash)
+        }
+        FirmwareSource::File { path, hash } => {
+            result.firmware = path.display().to_string();
+            let firmware_size = firmware_size_from_filename(path)?;
+            let hash = match hash {
+                Some(hash) => *hash,
+                None => {
+                    let mut hash_path = path.as_os_str().to_owned();
+                    hash_path.push(".hash");
+                    let hash = std::fs::read_to_string(&hash_path).with_context(|| {
+                        format!(
+                            "Failed to read firmware hash from {hash_path}; pass --hash instead",
+                            hash_path = hash_path.display()
+                        )
+                    })?;
+                    parse_firmware_hash(&hash)?
+                }
+            };
+            if check_only {
+                return Ok(result);
+            }
+            let compressed = std::fs::read(path).with_context(|| {
+                format!(
+                    "Failed to read firmware file: {path}",
+                    path = path.display()
+                )
+            })?;
+            (compressed, firmware_size, hash)
+        }
+    };
+    ensure!(!compressed.is_empty(), "Firmware image is empty");
+
+    if info.is_locked() {
+        let network = if info.jade_networks == "TEST" {
+            JadeNetwork::Testnet
+        } else {
+            JadeNetwork::Bitcoin
+        };
+        client
+            .unlock(network)
+            .await
+            .context("Failed to unlock Jade before the firmware update")?;
+    }
+
+    upload(&mut client, &compressed, firmware_size, firmware_hash).await?;
+    result.updated = true;
+    Ok(result)
+}
+
+async fn upload(
+    client: &mut JadeClient,
+    compressed: &[u8],
+    firmware_size: usize,
+    firmware_hash: [u8; 32],
+) -> Result<()> {
+    let mut last_percent = None;
+    client
+        .update_firmware(
+            compressed,
+            firmware_size,
+            Some(firmware_hash),
+            |sent, total| {
+                let percent = sent * 100 / total.max(1);
+                if last_percent != Some(percent) {
+                    last_percent = Some(percent);
+                    eprint!("\rUploading firmware: {percent}%");
+                }
+            },
+        )
+        .await
+        .context("Jade firmware update failed")?;
+    eprintln!("\nFirmware accepted; Jade is rebooting");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version_info(board_type: &str, features: &str) -> Result<VersionInfo> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "JADE_VERSION": "1.0.29",
+            "JADE_OTA_MAX_CHUNK": 4096,
+            "JADE_CONFIG": "BLE",
+            "BOARD_TYPE": board_type,
+            "JADE_FEATURES": features,
+            "IDF_VERSION": "v5.1.2",
+            "CHIP_FEATURES": "32000000",
+            "EFUSEMAC": "A1B2C3D4E5F6",
+            "BATTERY_STATUS": 5,
+            "JADE_STATE": "READY",
+            "JADE_NETWORKS": "ALL",
+            "JADE_HAS_PIN": true
+        }))?)
+    }
+
+    #[test]
+    fn test_firmware_board() -> Result<()> {
+        assert_eq!(
+            firmware_board(&version_info("JADE_V1_1", "SB")?)?,
+            "jade1.1"
+        );
+        assert_eq!(firmware_board(&version_info("JADE_V2", "SB")?)?, "jade2.0");
+        assert_eq!(firmware_board(&version_info("JADE", "")?)?, "jadedev");
+        assert!(firmware_board(&version_info("M5_CORE2", "")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_build() -> Result<()> {
+        let index: FirmwareIndex = serde_json::from_value(serde_json::json!({
+            "stable": {"full": [
+                {"filename": "1.0.30_ble_1900000_fw.bin", "version": "1.0.30", "config": "ble", "fwsize": 1900000},
+                {"filename": "1.0.31_noradio_1500000_fw.bin", "version": "1.0.31", "config": "noradio", "fwsize": 1500000},
+                {"filename": "1.0.31_ble_1918976_fw.bin", "version": "1.0.31", "config": "ble", "fwsize": 1918976}
+            ]},
+            "beta": {"full": []}
+        }))?;
+
+        let build = select_build(&index, FirmwareChannel::Stable, "BLE")?;
+        assert_eq!(build.filename, "1.0.31_ble_1918976_fw.bin");
+        assert_eq!(build.fwsize, 1918976);
+        assert!(select_build(&index, FirmwareChannel::Beta, "BLE").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_firmware_file_metadata() -> Result<()> {
+        assert_eq!(
+            firmware_size_from_filename(Path::new("/tmp/1.0.31_ble_1918976_fw.bin"))?,
+            1918976
+        );
+        assert!(firmware_size_from_filename(Path::new("firmware.bin")).is_err());
+
+        let hash = parse_firmware_hash(&format!("{}\n", "ab".repeat(32)))?;
+        assert_eq!(hash, [0xab; 32]);
+        assert!(parse_firmware_hash("abcd").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,511 @@
+//! Encrypted local keystore for software keys
+//!
+//! Keys are stored by name in `~/.cyberkrill/keys.json`, each encrypted on its
+//! own with XChaCha20-Poly1305 under a key derived from a password with
+//! Argon2id, so signing commands can reference `--key <name>` instead of taking
+//! raw private keys as arguments that end up in shell history. The name and
+//! kind are authenticated with the ciphertext; the KDF parameters are stored
+//! per entry so they can be raised later without breaking existing keys.
+
+use anyhow::{Context, Result, anyhow};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey};
+use bitcoin::bip32::Xpriv;
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::{Network, PrivateKey};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use strum::{Display, EnumString};
+use zeroize::Zeroizing;
+
+use crate::error::{CoreResult, bail, ensure};
+use crate::signer::SoftwareSigner;
+
+const KEYSTORE_VERSION: u32 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+const KEY_LENGTH: usize = 32;
+
+/// Kind of secret held by a keystore entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyKind {
+    /// BIP39 mnemonic
+    Mnemonic,
+    /// Extended private key (xprv/tprv), used as a master key
+    Xprv,
+    /// Output descriptor with private keys
+    Descriptor,
+    /// Single private key in WIF
+    Wif,
+    /// Single 32-byte private key in hex
+    Hex,
+}
+
+impl KeyKind {
+    /// Recognize the kind of `secret`, checking that it parses
+    pub fn detect(secret: &str) -> CoreResult<Self> {
+        let secret = secret.trim();
+        let kind = if secret.contains('(') {
+            let secp = Secp256k1::new();
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, secret)
+                .context("Invalid descriptor")?;
+            KeyKind::Descriptor
+        } else if secret.contains(' ') {
+            bip39::Mnemonic::parse_normalized(secret).context("Invalid BIP39 mnemonic")?;
+            KeyKind::Mnemonic
+        } else if secret.starts_with("xprv") || secret.starts_with("tprv") {
+            Xpriv::from_str(secret).context("Invalid extended private key")?;
+            KeyKind::Xprv
+        } else if secret.len() == 64 && secret.chars().all(|c| c.is_ascii_hexdigit()) {
+            SecretKey::from_str(secret).context("Invalid hex private key")?;
+            KeyKind::Hex
+        } else {
+            PrivateKey::from_wif(secret).context(
+                "Unrecognized key: expected a mnemonic, xprv, descriptor, WIF or hex private key",
+            )?;
+            KeyKind::Wif
+        };
+        Ok(kind)
+    }
+}
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Encrypted keystore entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredKey {
+    pub name: String,
+    pub kind: KeyKind,
+    /// Master fingerprint, for mnemonics and xprvs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub kdf: KdfParams,
+    /// Hex-encoded Argon2id salt
+    pub salt: String,
+    /// Hex-encoded XChaCha20-Poly1305 nonce
+    pub nonce: String,
+    /// Base64-encoded ciphertext
+    pub ciphertext: String,
+}
+
+/// Decrypted keystore entry
+pub struct UnlockedKey {
+    pub name: String,
+    pub kind: KeyKind,
+    pub secret: Zeroizing<String>,
+}
+
+impl StoredKey {
+    /// Encrypt `secret` under `password`
+    pub fn encrypt(name: &str, secret: &str, password: &str, kdf: KdfParams) -> CoreResult<Self> {
+        ensure!(!name.is_empty(), "Key name must not be empty");
+        ensure!(!password.is_empty(), "Keystore password must not be empty");
+        let secret = secret.trim();
+        let kind = KeyKind::detect(secret)?;
+
+        let mut rng = rand::rng();
+        let mut salt = [0u8; SALT_LENGTH];
+        rng.fill(&mut salt);
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rng.fill(&mut nonce);
+
+        let cipher = cipher(password, &salt, kdf)?;
+        let aad = associated_data(name, kind);
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: secret.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt key"))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            fingerprint: master_fingerprint(kind, secret),
+            created_at: Utc::now(),
+            kdf,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: base64::engine::general_purpose::STANDARD.encode(ciphertext),
+        })
+    }
+
+    /// Decrypt the entry with `password`
+    pub fn decrypt(&self, password: &str) -> CoreResult<UnlockedKey> {
+        let salt = hex::decode(&self.salt).context("Invalid salt in keystore entry")?;
+        let nonce = hex::decode(&self.nonce).context("Invalid nonce in keystore entry")?;
+        ensure!(
+            nonce.len() == NONCE_LENGTH,
+            "Invalid nonce length in keystore entry"
+        );
+        let ciphertext = base64::engine::general_purpose::STANDARD
+            .decode(&self.ciphertext)
+            .context("Invalid ciphertext in keystore entry")?;
+
+        let cipher = cipher(password, &salt, self.kdf)?;
+        let aad = associated_data(&self.name, self.kind);
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: &ciphertext,
+                        aad: &aad,
+                    },
+                )
+                .map_err(|_| {
+                    anyhow!(
+                        "Failed to decrypt key '{name}': wrong password or corrupted entry",
+                        name = self.name
+                    )
+                })?,
+        );
+        let secret = String::from_utf8(plaintext.to_vec()).context("Decrypted key is not text")?;
+
+        Ok(UnlockedKey {
+            name: self.name.clone(),
+            kind: self.kind,
+            secret: Zeroizing::new(secret),
+        })
+    }
+}
+
+impl UnlockedKey {
+    /// PSBT signer for a mnemonic (with `passphrase`), xprv or descriptor
+    pub fn software_signer(
+        &self,
+        passphrase: &str,
+        network: Network,
+    ) -> CoreResult<SoftwareSigner> {
+        match self.kind {
+            KeyKind::Mnemonic => SoftwareSigner::from_mnemonic(&self.secret, passphrase, network),
+            KeyKind::Xprv => SoftwareSigner::from_xprv(&self.secret, network),
+            KeyKind::Descriptor => SoftwareSigner::from_descriptor(&self.secret, network),
+            KeyKind::Wif | KeyKind::Hex => bail!(
+                "Key '{name}' is a single {kind} key; PSBT signing needs a mnemonic, xprv or descriptor",
+                name = self.name,
+                kind = self.kind
+            ),
+        }
+    }
+
+    /// Single private key, for WIF and hex entries
+    pub fn private_key(&self, network: Network) -> CoreResult<PrivateKey> {
+        match self.kind {
+            KeyKind::Wif => {
+                Ok(PrivateKey::from_wif(&self.secret).context("Invalid WIF private key")?)
+            }
+            KeyKind::Hex => Ok(PrivateKey::new(
+                SecretKey::from_str(&self.secret).context("Invalid hex private key")?,
+                network,
+            )),
+            _ => bail!(
+                "Key '{name}' is a {kind}, not a single private key",
+                name = self.name,
+                kind = self.kind
+            ),
+        }
+    }
+}
+
+/// Keystore persisted as JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub keys: Vec<StoredKey>,
+}
+
+impl Default for Keystore {
+    fn default() -> Self {
+        Self {
+            version: KEYSTORE_VERSION,
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl Keystore {
+    /// Default keystore location: `~/.cyberkrill/keys.json`
+    pub fn default_path() -> CoreResult<PathBuf> {
+        let home = std::env::var("HOME").context("HOME is not set")?;
+        Ok(Path::new(&home).join(".cyberkrill").join("keys.json"))
+    }
+
+    /// Load the keystore from `path`; a missing file is an empty keystore
+    pub fn load(path: &Path) -> CoreResult<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read keystore: {path}", path = path.display()))?;
+        let keystore: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid keystore file: {path}", path = path.display()))?;
+        ensure!(
+            keystore.version == KEYSTORE_VERSION,
+            "Unsupported keystore version {version}",
+            version = keystore.version
+        );
+        Ok(keystore)
+    }
+
+    /// Write the keystore to `path`, readable only by the owner on Unix
+    pub fn save(&self, path: &Path) -> CoreResult<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!(
+                    "Failed to create keystore directory: {parent}",
+                    parent = parent.display()
+                )
+            })?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        Ok(write_private_file(path, content.as_bytes())
+            .with_context(|| format!("Failed to write keystore: {path}", path = path.display()))?)
+    }
+
+    pub fn add(&mut self, key: StoredKey) -> CoreResult<()> {
+        ensure!(
+            self.get(&key.name).is_err(),
+            "Key '{name}' already exists",
+            name = key.name
+        );
+        self.keys.push(key);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> CoreResult<&StoredKey> {
+        Ok(self
+            .keys
+            .iter()
+            .find(|key| key.name == name)
+            .with_context(|| format!("Key '{name}' is not in the keystore"))?)
+    }
+
+    pub fn remove(&mut self, name: &str) -> CoreResult<StoredKey> {
+        let position = self
+            .keys
+            .iter()
+            .position(|key| key.name == name)
+            .with_context(|| format!("Key '{name}' is not in the keystore"))?;
+        Ok(self.keys.remove(position))
+    }
+
+    /// Decrypt the key called `name`
+    pub fn unlock(&self, name: &str, password: &str) -> CoreResult<UnlockedKey> {
+        self.get(name)?.decrypt(password)
+    }
+}
+
+/// Replace `path` with `content`, readable only by the owner on Unix.
+///
+/// The content goes to a temporary file in the same directory, which is synced
+/// and then renamed over `path`, so a crash leaves either the old or the new
+/// file and never a truncated one. The mode is set on the new file, so it also
+/// applies when `path` existed with wider permissions.
+pub fn write_private_file(path: &Path, content: &[u8]) -> CoreResult<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Not a file path: {path}", path = path.display()))?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let temp_path = directory.join(format!(
+        ".{name}.{pid}.tmp",
+        name = file_name.to_string_lossy(),
+        pid = std::process::id()
+    ));
+
+    let result = write_synced(&temp_path, content).and_then(|()| {
+        std::fs::rename(&temp_path, path).with_context(|| {
+            format!(
+                "Failed to move {temp} to {path}",
+                temp = temp_path.display(),
+                path = path.display()
+            )
+        })
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+        return Ok(result?);
+    }
+
+    // Persist the rename itself
+    #[cfg(unix)]
+    std::fs::File::open(directory)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync {dir}", dir = directory.display()))?;
+    Ok(())
+}
+
+/// Create `path` (mode 0600 on Unix), write `content` and sync it to disk
+fn write_synced(path: &Path, content: &[u8]) -> Result<()> {
+    // A leftover from an interrupted write would keep its old mode
+    let _ = std::fs::remove_file(path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {path}", path = path.display()))?;
+    std::io::Write::write_all(&mut file, content)
+        .with_context(|| format!("Failed to write {path}", path = path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("Failed to sync {path}", path = path.display()))
+}
+
+fn cipher(password: &str, salt: &[u8], kdf: KdfParams) -> Result<XChaCha20Poly1305> {
+    let params = Params::new(
+        kdf.memory_kib,
+        kdf.iterations,
+        kdf.parallelism,
+        Some(KEY_LENGTH),
+    )
+    .map_err(|e| anyhow!("Invalid Argon2 parameters: {e}"))?;
+    let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key[..])
+        .map_err(|e| anyhow!("Failed to derive keystore key: {e}"))?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key[..])))
+}
+
+/// Bind the ciphertext to its entry so it cannot be moved to another name or kind
+fn associated_data(name: &str, kind: KeyKind) -> Vec<u8> {
+    format!("cyberkrill-keystore/v{KEYSTORE_VERSION}/{kind}/{name}").into_bytes()
+}
+
+fn master_fingerprint(kind: KeyKind, secret: &str) -> Option<String> {
+    let secp = Secp256k1::new();
+    let master = match kind {
+        KeyKind::Mnemonic => {
+            let seed = bip39::Mnemonic::parse_normalized(secret)
+                .ok()?
+                .to_seed_normalized("");
+            Xpriv::new_master(Network::Bitcoin, &seed).ok()?
+        }
+        KeyKind::Xprv => Xpriv::from_str(secret).ok()?,
+        _ => return None,
+    };
+    Some(master.fingerprint(&secp).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    const WIF: &str = "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy";
+
+    // Cheap parameters keep the tests fast
+    const TEST_KDF: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_detect_key_kind() -> Result<()> {
+        assert_eq!(KeyKind::detect(MNEMONIC)?, KeyKind::Mnemonic);
+        assert_eq!(KeyKind::detect(WIF)?, KeyKind::Wif);
+        assert_eq!(KeyKind::detect(&"01".repeat(32))?, KeyKind::Hex);
+        assert!(KeyKind::detect("not a key").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() -> Result<()> {
+        let key = StoredKey::encrypt("cold", MNEMONIC, "hunter2", TEST_KDF)?;
+        assert_eq!(key.kind, KeyKind::Mnemonic);
+        assert_eq!(key.fingerprint.as_deref(), Some("73c5da0a"));
+        assert!(!key.ciphertext.contains("abandon"));
+
+        let unlocked = key.decrypt("hunter2")?;
+        assert_eq!(unlocked.secret.as_str(), MNEMONIC);
+        assert!(key.decrypt("wrong").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_renamed_entry_fails_to_decrypt() -> Result<()> {
+        let mut key = StoredKey::encrypt("hot", WIF, "hunter2", TEST_KDF)?;
+        key.name = "other".to_string();
+        assert!(key.decrypt("hunter2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_keystore_save_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("keys.json");
+
+        let mut keystore = Keystore::default();
+        keystore.add(StoredKey::encrypt("hot", WIF, "pw", TEST_KDF)?)?;
+        assert!(
+            keystore
+                .add(StoredKey::encrypt("hot", WIF, "pw", TEST_KDF)?)
+                .is_err()
+        );
+        keystore.save(&path)?;
+
+        let loaded = Keystore::load(&path)?;
+        let unlocked = loaded.unlock("hot", "pw")?;
+        assert_eq!(
+            unlocked.private_key(Network::Testnet)?,
+            PrivateKey::from_wif(WIF)?
+        );
+        assert!(unlocked.software_signer("", Network::Testnet).is_err());
+        assert!(loaded.unlock("missing", "pw").is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_replaces_file_owner_only() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("keys.json");
+        std::fs::write(&path, "stale")?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))?;
+
+        let mut keystore = Keystore::default();
+        keystore.add(StoredKey::encrypt("hot", WIF, "pw", TEST_KDF)?)?;
+        keystore.save(&path)?;
+
+        let mode = std::fs::metadata(&path)?.permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+        assert_eq!(Keystore::load(&path)?.keys.len(), 1);
+        // Only the keystore is left in the directory
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+}
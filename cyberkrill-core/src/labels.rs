@@ -0,0 +1,118 @@
+//! Wallet labels in the BIP-329 export format
+//!
+//! A BIP-329 file is JSON lines, one label per line: `{"type": "output", "ref":
+//! "txid:vout", "label": "..."}`. Output labels are the most specific; a UTXO
+//! without one falls back to the label of its address, then of its transaction.
+
+use crate::error::CoreResult;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::debug;
+
+#[derive(Debug, Deserialize)]
+struct LabelRecord {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "ref")]
+    reference: String,
+    label: Option<String>,
+    spendable: Option<bool>,
+}
+
+/// Labels imported from a BIP-329 file
+#[derive(Debug, Clone, Default)]
+pub struct WalletLabels {
+    outputs: HashMap<String, String>,
+    addresses: HashMap<String, String>,
+    transactions: HashMap<String, String>,
+    unspendable: Vec<String>,
+}
+
+impl WalletLabels {
+    pub fn load(path: &Path) -> CoreResult<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read labels file {path}", path = path.display()))?;
+        Ok(Self::parse(&content)
+            .with_context(|| format!("Invalid BIP-329 labels in {path}", path = path.display()))?)
+    }
+
+    pub fn parse(content: &str) -> CoreResult<Self> {
+        let mut labels = Self::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let record: LabelRecord = serde_json::from_str(line)
+                .with_context(|| format!("Invalid label on line {line}", line = number + 1))?;
+            if record.kind == "output" && record.spendable == Some(false) {
+                labels.unspendable.push(record.reference.clone());
+            }
+            let Some(label) = record.label.filter(|label| !label.is_empty()) else {
+                continue;
+            };
+            let target = match record.kind.as_str() {
+                "output" => &mut labels.outputs,
+                "addr" => &mut labels.addresses,
+                "tx" => &mut labels.transactions,
+                other => {
+                    debug!("Ignoring BIP-329 label of type {other}");
+                    continue;
+                }
+            };
+            target.insert(record.reference, label);
+        }
+        Ok(labels)
+    }
+
+    /// Label of a UTXO: its own, else its address's, else its transaction's
+    pub fn utxo_label(&self, txid: &str, vout: u32, address: &str) -> Option<&str> {
+        self.outputs
+            .get(&format!("{txid}:{vout}"))
+            .or_else(|| self.addresses.get(address))
+            .or_else(|| self.transactions.get(txid))
+            .map(String::as_str)
+    }
+
+    /// Whether the output was marked `"spendable": false` (frozen)
+    pub fn is_frozen(&self, txid: &str, vout: u32) -> bool {
+        let outpoint = format!("{txid}:{vout}");
+        self.unspendable.contains(&outpoint)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+            && self.addresses.is_empty()
+            && self.transactions.is_empty()
+            && self.unspendable.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_bip329_label_precedence() -> Result<()> {
+        let labels = WalletLabels::parse(
+            r#"{"type": "tx", "ref": "aa", "label": "Salary"}
+{"type": "addr", "ref": "bc1qaddr", "label": "Donations"}
+{"type": "output", "ref": "aa:1", "label": "KYC-free", "spendable": true}
+{"type": "output", "ref": "bb:0", "spendable": false}
+{"type": "xpub", "ref": "xpub...", "label": "Cold"}
+"#,
+        )?;
+        assert_eq!(labels.utxo_label("aa", 1, "bc1qaddr"), Some("KYC-free"));
+        assert_eq!(labels.utxo_label("aa", 0, "bc1qaddr"), Some("Donations"));
+        assert_eq!(labels.utxo_label("aa", 0, "bc1qother"), Some("Salary"));
+        assert_eq!(labels.utxo_label("cc", 0, "bc1qother"), None);
+        assert!(labels.is_frozen("bb", 0));
+        assert!(!labels.is_frozen("aa", 1));
+
+        assert!(WalletLabels::parse("{\"type\": \"output\"}").is_err());
+        Ok(())
+    }
+}
@@ -1,18 +1,64 @@
+pub mod address_verification;
+pub mod backend;
 pub mod bdk_wallet;
+#[cfg(feature = "bitbox")]
+pub mod bitbox;
 pub mod bitcoin_rpc;
+pub mod cbf;
+pub mod cert_pin;
+pub mod chain_cache;
+pub mod client_options;
+pub mod confirmations;
 pub mod dca_report;
 pub mod decoder;
+pub mod descriptor;
+pub mod destination_split;
+pub mod electrum;
+pub mod error;
+pub mod esplora;
+pub mod exchange_import;
 #[cfg(feature = "frozenkrill")]
 pub mod frozenkrill;
+pub mod hw_descriptor;
+pub mod keystore;
+pub mod labels;
+pub mod lightning_node;
+pub mod mempool_accept;
+pub mod message_signing;
+pub mod multisig_setup;
+pub mod network;
+pub mod node_wallet;
+pub mod nostr;
+pub mod output_order;
+pub mod price_cache;
 pub mod price_feed;
+pub mod price_history;
+pub mod privacy_report;
+pub mod proof_of_reserves;
+pub mod proxy;
+pub mod psbt_analysis;
+pub mod psbt_decode;
+pub mod psbt_file;
+pub mod remote_signer;
+pub mod retry;
+pub mod rpc_trace;
 #[cfg(feature = "smartcards")]
 pub mod satscard;
-#[cfg(feature = "trezor")]
+pub mod seed_tools;
+pub mod signer;
 pub mod slip132;
 #[cfg(feature = "smartcards")]
 pub mod tapsigner;
+mod trace_relay;
 #[cfg(feature = "trezor")]
 pub mod trezor;
+pub mod tx_decode;
+pub mod tx_preview;
+pub mod tx_watch;
+pub mod utxo_filter;
+pub mod utxo_watch;
+pub mod wallet_registry;
+pub mod zmq;
 
 // Hardware wallet common trait
 #[cfg(feature = "coldcard")]
@@ -20,31 +66,186 @@ pub mod coldcard;
 pub mod hardware_wallet;
 #[cfg(feature = "jade")]
 pub mod jade;
+#[cfg(feature = "jade")]
+pub mod jade_firmware;
 
 // Re-export main functionality for easier access
 pub use decoder::{
-    GeneratedInvoiceOutput, InvoiceOutput, LnurlOutput, decode_invoice, decode_lnurl,
-    encode_invoice, generate_invoice_from_address,
+    GeneratedInvoiceOutput, InvoiceOutput, LnurlOutput, PaymentVerification, decode_invoice,
+    decode_lnurl, encode_invoice, generate_invoice_from_address, verify_payment,
 };
 
 #[cfg(feature = "smartcards")]
-pub use satscard::{SatscardAddressOutput, SatscardInfo, generate_satscard_address};
+pub use satscard::{
+    SatscardAddressOutput, SatscardInfo, SatscardSweepOutput, SatscardUnsealOutput,
+    generate_satscard_address, sweep_satscard, unseal_satscard,
+};
 
 #[cfg(feature = "smartcards")]
 pub use tapsigner::{
     TapsignerAddressOutput, TapsignerInitOutput, generate_tapsigner_address, initialize_tapsigner,
 };
 
-pub use bitcoin_rpc::{AmountInput, BitcoinRpcClient};
+pub use bitcoin_rpc::{
+    AmountInput, BitcoinRpcClient, DEFAULT_CHANGE_GAP_LIMIT, DEFAULT_MAX_FEE_PERCENT,
+    DEFAULT_MAX_FEE_RATE, FeeLimits, PsbtOptions, RpcError, SnapshotUtxo, UtxoSnapshot,
+    parse_op_return_data, url_has_credentials,
+};
+
+pub use error::{CoreError, CoreResult, InsufficientFunds};
 
+pub use price_cache::{CurrencyCacheStatus, DEFAULT_PRICE_CACHE_TTL, PriceCache, PriceCacheStatus};
 pub use price_feed::{BtcPrice, PriceQuote, fetch_btc_price};
 
+pub use price_history::{BtcPricePoint, PriceProvider, PriceSource, btc_price};
+
+pub use cert_pin::{CertFingerprint, CertPin, CertPins};
+
+pub use chain_cache::{ChainCache, TxConfirmation};
+
+pub use client_options::ClientOptions;
+
+pub use confirmations::{
+    ConfirmationPolicy, ConfirmationRecommendation, ensure_min_confirmations,
+    ensure_psbt_input_confirmations, recommend_min_confirmations,
+};
+
+pub use backend::{
+    BitcoindBackend, BlockchainBackend, CbfBackend, ElectrumBackend, EsploraBackend,
+};
+
 pub use bdk_wallet::{
-    BdkPsbtResponse, BdkUtxo, BdkUtxoSummary, create_funded_psbt_bdk, create_psbt_bdk,
-    get_utxo_summary, list_utxos_bdk, move_utxos_bdk, scan_and_list_utxos_bitcoind,
-    scan_and_list_utxos_electrum, scan_and_list_utxos_esplora,
+    BdkPsbtResponse, BdkSweepResponse, BdkUtxo, BdkUtxoSummary, ScanOptions, ScanWallet,
+    create_funded_psbt_bdk, create_psbt_bdk, get_utxo_summary, list_utxos_bdk, move_utxos_bdk,
+    sweep_wallet_bdk,
+};
+
+pub use descriptor::{
+    AddressDerivation, DerivedAddress, DescriptorChecksum, DescriptorKey, DescriptorSummary,
+    SingleKeyAddress, XpubScriptType, check_descriptor_checksum, check_descriptor_network,
+    combine_descriptors, derive_addresses, derive_single_key_address, descriptor_checksum,
+    expand_descriptor, expand_multipath_descriptor, explain_descriptor, parse_descriptor,
+    parse_index_range, xpub_descriptor,
+};
+
+pub use destination_split::{DestinationShare, DestinationSplit, SplitDestination};
+
+pub use electrum::{ElectrumServer, ElectrumServers};
+
+pub use esplora::{
+    EsploraTransfer, LowBandwidthScan, scan_and_list_utxos_esplora_low_bandwidth,
+    stream_utxos_esplora_low_bandwidth,
+};
+
+pub use address_verification::{
+    AddressVerification, verify_address_on_device, verify_descriptor_address_on_device,
+};
+
+pub use hardware_wallet::{
+    DetectedDevice, HwSignOutput, find_device_by_fingerprint, list_hardware_wallets,
+    psbt_input_fingerprints, select_device_by_fingerprint, select_psbt_device, sign_psbt_with_hw,
+};
+
+pub use hw_descriptor::{
+    ExportedDescriptor, HwDescriptorType, build_exported_descriptor, export_hw_descriptor,
+};
+
+pub use keystore::{KdfParams, KeyKind, Keystore, StoredKey, UnlockedKey, write_private_file};
+
+pub use labels::WalletLabels;
+
+pub use lightning_node::{
+    LndGraphClient, NodeAddress, NodeAddressKind, NodeFeature, NodeGraphInfo, NodeInfo, decode_node,
+};
+
+pub use mempool_accept::{MempoolAcceptance, explain_reject_reason};
+
+pub use message_signing::{
+    MessageAddressType, MessageSignatureFormat, MessageSigningDevice, MessageVerification,
+    SignedMessage, sign_message_bip137_with_device, sign_message_bip322_with_device,
+    sign_message_with_key, verify_message,
+};
+
+pub use multisig_setup::{
+    CosignerKey, JadeMultisigRegistration, MultisigSetup, build_multisig_setup, parse_cosigner_file,
+};
+
+pub use node_wallet::{
+    DescriptorImport, DescriptorImportResult, ImportTimestamp, NodeWalletBalances, NodeWalletInfo,
+    RescanResult, ScanProgress,
+};
+
+pub use nostr::{NostrKeys, derive_nostr_keys};
+
+pub use output_order::OutputOrder;
+
+pub use privacy_report::{
+    AddressCluster, HistoryTx, HistoryTxo, IdentifiableChange, PrivacyReport, ReusedAddress,
+    RoundPayment, analyze_privacy, privacy_report, wallet_history,
+};
+
+pub use proof_of_reserves::{
+    AddressOwnershipProof, ReserveChainCheck, ReserveProof, ReserveProofVerification,
+    ReserveSigner, ReserveUtxo, build_reserve_proof, check_reserve_proof_chain,
+    verify_reserve_proof,
+};
+
+pub use psbt_analysis::{InputAnalysis, PsbtAnalysis, PsbtRole, analyze_psbt};
+
+pub use psbt_decode::{
+    InputSigningState, KeyOrigin, PsbtDetails, PsbtGlobalDetails, PsbtInputDetails,
+    PsbtOutputDetails, SigningStatus, psbt_details,
+};
+
+pub use psbt_file::{
+    PSBT_MAGIC, PsbtFormat, decode_psbt_data, parse_psbt_data, read_psbt_input, write_psbt_file,
 };
 
+pub use remote_signer::{
+    HttpRemoteSigner, HttpRemoteSignerConfig, RemoteSignOutput, RemoteSigner, RemoteXpubOutput,
+    get_remote_signer_xpub, sign_psbt_with_remote_signer,
+};
+
+pub use network::{check_address_network, check_transaction_network, parse_network};
+
+pub use proxy::{NetworkProxy, ProxyKind};
+
+pub use retry::RetryPolicy;
+
+pub use rpc_trace::RpcTrace;
+
+pub use seed_tools::{
+    RecoveredSeed, SeedCheck, SeedCorrection, Slip39Backup, Slip39Group, Slip39GroupShares,
+    UnknownWord, check_mnemonic, recover_bip39_mnemonic, seedxor_combine, seedxor_split,
+    slip39_recover, slip39_split, split_bip39_mnemonic,
+};
+
+pub use signer::{SoftwareSignOutput, SoftwareSigner, sign_psbt_with_software};
+
+pub use slip132::{ConvertedXpub, Slip132Format, convert_xpub, to_slip132_string};
+
+pub use tx_decode::{
+    DecodedInput, DecodedOutput, DecodedTransaction, decode_transaction, parse_raw_transaction,
+    resolve_prevouts,
+};
+
+pub use tx_preview::{PreviewOutput, TransactionPreview, preview_psbt};
+
+pub use tx_watch::{TxState, TxStatus, WatchOptions, watch_transaction};
+
+pub use utxo_filter::{ListedUtxo, UtxoFilter, UtxoSelection, UtxoSort};
+
+pub use utxo_watch::{
+    UtxoEvent, UtxoEventKind, UtxoTracker, UtxoWatchOptions, WEBHOOK_SIGNATURE_HEADER, Webhook,
+    watch_utxos,
+};
+
+pub use wallet_registry::{
+    RegisteredWallet, WalletEnvironment, WalletRegistry, infer_psbt_network_kind,
+};
+
+pub use zmq::{ZmqNotification, ZmqSubscriber, ZmqTopic};
+
 // Re-export bitcoin types needed by CLI
 pub use bitcoin::{self, Network};
 
@@ -53,13 +254,14 @@ pub use fedimint_lite;
 
 // Re-export frozenkrill functionality
 #[cfg(feature = "frozenkrill")]
-pub use frozenkrill::FrozenkrillWallet;
+pub use frozenkrill::{FrozenkrillMultisig, FrozenkrillWallet};
 
 // Re-export coldcard functionality
 #[cfg(feature = "coldcard")]
 pub use coldcard::{
-    ColdcardAddressOutput, ColdcardSignOutput, ColdcardWallet, export_psbt_to_coldcard,
-    generate_coldcard_address, sign_psbt_with_coldcard,
+    ColdcardAddressOutput, ColdcardSignOutput, ColdcardWallet, ColdcardWalletExport,
+    ColdcardXpubOutput, WalletExportSection, export_coldcard_wallet, export_psbt_to_coldcard,
+    generate_coldcard_address, generate_coldcard_xpub, sign_psbt_with_coldcard,
 };
 
 // Re-export trezor functionality
@@ -69,12 +271,32 @@ pub use trezor::{
     sign_psbt_with_trezor,
 };
 
+// Re-export bitbox functionality
+#[cfg(feature = "bitbox")]
+pub use bitbox::{
+    BitboxAddressOutput, BitboxSignOutput, BitboxWallet, BitboxXpubOutput, generate_bitbox_address,
+    generate_bitbox_xpub, sign_psbt_with_bitbox,
+};
+
 // Re-export jade functionality
 #[cfg(feature = "jade")]
 pub use jade::{
-    JadeAddressResult, JadeSignedPsbtResult, JadeXpubResult, generate_jade_address,
-    generate_jade_xpub, sign_psbt_with_jade,
+    JadeAddressResult, JadeConnectOptions, JadeMultisigResult, JadePinServerResult,
+    JadeSignedPsbtResult, JadeXpubResult, generate_jade_address, generate_jade_xpub,
+    jade_multisig_descriptor, register_jade_multisig, set_jade_pinserver, sign_psbt_with_jade,
+};
+#[cfg(feature = "jade")]
+pub use jade_bitcoin::PinServerDetails;
+#[cfg(feature = "jade")]
+pub use jade_firmware::{
+    FirmwareChannel, FirmwareSource, JadeFirmwareUpdate, parse_firmware_hash, update_jade_firmware,
 };
 
 // Re-export DCA report functionality
-pub use dca_report::{Backend, DcaMetrics, DcaReport, DcaUtxo, generate_dca_report};
+pub use dca_report::{
+    Backend, CapitalGain, CostBasisMethod, DcaMetrics, DcaReport, DcaUtxo, TaxReport,
+    WalletMovement, generate_dca_report, generate_tax_report, realized_gains,
+};
+pub use exchange_import::{
+    CsvColumns, ExchangeCsvFormat, ExchangePurchase, match_purchases, parse_exchange_csv,
+};
@@ -0,0 +1,437 @@
+//! Lightning node IDs and URIs
+//!
+//! A node is shared as its public key, or as a URI `pubkey@host:port` telling
+//! peers where to connect. [`decode_node`] validates the key and classifies the
+//! address the way BOLT 7 node announcements do (IPv4, IPv6, Tor v3 or DNS
+//! hostname). With an LND REST endpoint, [`LndGraphClient`] adds what the
+//! node announces in the channel graph: alias, color, addresses and features.
+
+use anyhow::{Context, Result};
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::cert_pin::CertFingerprint;
+use crate::error::{CoreResult, bail, ensure};
+use crate::proxy::{NetworkProxy, http_client_builder};
+
+/// Port Lightning nodes listen on unless told otherwise
+pub const DEFAULT_LIGHTNING_PORT: u16 = 9735;
+
+/// Length of the base32 label of a Tor v3 onion address
+const TOR_V3_LABEL_LENGTH: usize = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeAddressKind {
+    Ipv4,
+    Ipv6,
+    TorV3,
+    Dns,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeAddress {
+    pub host: String,
+    pub port: u16,
+    /// Whether the port was given or defaulted to 9735
+    pub explicit_port: bool,
+    pub kind: NodeAddressKind,
+}
+
+impl FromStr for NodeAddress {
+    type Err = anyhow::Error;
+
+    /// `host`, `host:port`, `[ipv6]` or `[ipv6]:port`; a bare IPv6 address
+    /// can't carry a port
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        ensure!(!s.is_empty(), "Empty node address");
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .with_context(|| format!("Missing ']' in node address '{s}'"))?;
+            match rest {
+                "" => (host, None),
+                _ => {
+                    let port = rest
+                        .strip_prefix(':')
+                        .with_context(|| format!("Invalid node address '{s}'"))?;
+                    (host, Some(port))
+                }
+            }
+        } else if s.parse::<Ipv6Addr>().is_ok() {
+            (s, None)
+        } else {
+            match s.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            }
+        };
+
+        let explicit_port = port.is_some();
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port != 0)
+                .with_context(|| format!("Invalid port in node address '{s}'"))?,
+            None => DEFAULT_LIGHTNING_PORT,
+        };
+
+        Ok(Self {
+            host: host.to_lowercase(),
+            port,
+            explicit_port,
+            kind: classify_host(host)?,
+        })
+    }
+}
+
+impl std::fmt::Display for NodeAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            NodeAddressKind::Ipv6 => {
+                write!(f, "[{host}]:{port}", host = self.host, port = self.port)
+            }
+            _ => write!(f, "{host}:{port}", host = self.host, port = self.port),
+        }
+    }
+}
+
+fn classify_host(host: &str) -> Result<NodeAddressKind> {
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return Ok(NodeAddressKind::Ipv4);
+    }
+    if host.parse::<Ipv6Addr>().is_ok() {
+        return Ok(NodeAddressKind::Ipv6);
+    }
+    let host = host.to_lowercase();
+    if let Some(label) = host.strip_suffix(".onion") {
+        ensure!(
+            label.len() == TOR_V3_LABEL_LENGTH
+                && label.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7')),
+            "'{host}' is not a Tor v3 onion address (Tor v2 addresses are no longer reachable)"
+        );
+        return Ok(NodeAddressKind::TorV3);
+    }
+    ensure!(
+        host.len() <= 255
+            && host.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            }),
+        "Invalid host '{host}': expected an IPv4 or IPv6 address, a Tor v3 onion or a hostname"
+    );
+    Ok(NodeAddressKind::Dns)
+}
+
+/// A decoded node ID or URI, plus what the graph knows about the node when it
+/// was looked up
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeInfo {
+    pub pubkey: String,
+    pub address: Option<NodeAddress>,
+    /// `pubkey@host:port`, when an address was given
+    pub uri: Option<String>,
+    pub graph: Option<NodeGraphInfo>,
+}
+
+/// Decode `pubkey` or `pubkey@host[:port]`
+pub fn decode_node(input: &str) -> CoreResult<NodeInfo> {
+    let input = input.trim();
+    let (pubkey, address) = match input.split_once('@') {
+        Some((pubkey, address)) => (pubkey, Some(NodeAddress::from_str(address)?)),
+        None => (input, None),
+    };
+    ensure!(
+        pubkey.len() == 66,
+        "Node public key must be 33 bytes (66 hex characters), got {len} characters",
+        len = pubkey.len()
+    );
+    let pubkey = PublicKey::from_str(pubkey)
+        .with_context(|| format!("Invalid node public key '{pubkey}'"))?
+        .to_string();
+    let uri = address
+        .as_ref()
+        .map(|address| format!("{pubkey}@{address}"));
+    Ok(NodeInfo {
+        pubkey,
+        address,
+        uri,
+        graph: None,
+    })
+}
+
+/// What a node announces in the channel graph
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeGraphInfo {
+    pub alias: String,
+    pub color: String,
+    pub addresses: Vec<String>,
+    pub features: Vec<NodeFeature>,
+    pub num_channels: u32,
+    pub total_capacity_sats: u64,
+    /// Unix time of the node's latest announcement
+    pub last_update: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeFeature {
+    pub bit: u32,
+    pub name: String,
+    pub required: bool,
+    /// Whether the queried node's software understands the feature
+    pub known: bool,
+}
+
+/// Channel graph lookups through LND's REST API
+#[derive(Debug, Clone)]
+pub struct LndGraphClient {
+    /// REST endpoint, e.g. `https://127.0.0.1:8080`
+    pub url: String,
+    /// Hex-encoded macaroon (a `readonly.macaroon` is enough)
+    pub macaroon_hex: String,
+    /// SHA-256 fingerprint of LND's self-signed `tls.cert`
+    pub cert_fingerprint: Option<CertFingerprint>,
+    /// Proxy to reach the endpoint through, e.g. Tor for an onion REST address
+    pub proxy: Option<NetworkProxy>,
+}
+
+#[derive(Deserialize)]
+struct LndNodeInfo {
+    node: LndNode,
+    #[serde(default)]
+    num_channels: u32,
+    #[serde(default, deserialize_with = "deserialize_lnd_u64")]
+    total_capacity: u64,
+}
+
+#[derive(Deserialize)]
+struct LndNode {
+    #[serde(default)]
+    alias: String,
+    #[serde(default)]
+    color: String,
+    #[serde(default)]
+    addresses: Vec<LndNodeAddress>,
+    #[serde(default)]
+    features: BTreeMap<String, LndFeature>,
+    #[serde(default, deserialize_with = "deserialize_lnd_u64")]
+    last_update: u64,
+}
+
+#[derive(Deserialize)]
+struct LndNodeAddress {
+    addr: String,
+}
+
+#[derive(Deserialize)]
+struct LndFeature {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    is_required: bool,
+    #[serde(default)]
+    is_known: bool,
+}
+
+/// LND's REST gateway writes 64-bit integers as JSON strings
+fn deserialize_lnd_u64<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+        serde_json::Value::Number(n) => n
+            .as_u64()
+            .ok_or_else(|| serde::de::Error::custom(format!("Invalid integer {n}"))),
+        other => Err(serde::de::Error::custom(format!(
+            "Expected an integer, got {other}"
+        ))),
+    }
+}
+
+impl LndGraphClient {
+    /// Read the macaroon from `macaroon_path` (binary, as LND writes it)
+    pub fn new(
+        url: &str,
+        macaroon_path: &std::path::Path,
+        cert_fingerprint: Option<CertFingerprint>,
+        proxy: Option<NetworkProxy>,
+    ) -> CoreResult<Self> {
+        let macaroon = std::fs::read(macaroon_path).with_context(|| {
+            format!(
+                "Failed to read macaroon: {path}",
+                path = macaroon_path.display()
+            )
+        })?;
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            macaroon_hex: hex::encode(macaroon),
+            cert_fingerprint,
+            proxy,
+        })
+    }
+
+    /// The node's announcement, or an error when the graph doesn't know it
+    pub async fn node_info(&self, pubkey: &str) -> CoreResult<NodeGraphInfo> {
+        let mut builder = http_client_builder(self.proxy.as_ref());
+        if let Some(pin) = &self.cert_fingerprint {
+            CertFingerprint::ensure_tls(&self.url, "https")?;
+            builder = builder.use_preconfigured_tls(pin.tls_config()?);
+        }
+        let client = builder.build().context("Failed to build LND HTTP client")?;
+
+        let url = format!(
+            "{base}/v1/graph/node/{pubkey}?include_channels=false",
+            base = self.url
+        );
+        let response = client
+            .get(&url)
+            .header("Grpc-Metadata-macaroon", &self.macaroon_hex)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach LND at {url}", url = self.url))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            bail!("Node {pubkey} is not in the channel graph");
+        }
+        let info: LndNodeInfo = response
+            .error_for_status()
+            .context("LND rejected the node lookup")?
+            .json()
+            .await
+            .context("Invalid node info from LND")?;
+
+        let mut features = info
+            .node
+            .features
+            .into_iter()
+            .map(|(bit, feature)| {
+                Ok(NodeFeature {
+                    bit: bit
+                        .parse()
+                        .with_context(|| format!("Invalid feature bit '{bit}' from LND"))?,
+                    name: feature.name,
+                    required: feature.is_required,
+                    known: feature.is_known,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        features.sort_by_key(|feature| feature.bit);
+
+        Ok(NodeGraphInfo {
+            alias: info.node.alias,
+            color: info.node.color,
+            addresses: info
+                .node
+                .addresses
+                .into_iter()
+                .map(|address| address.addr)
+                .collect(),
+            features,
+            num_channels: info.num_channels,
+            total_capacity_sats: info.total_capacity,
+            last_update: info.node.last_update,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: &str = "03fb2a0ca79c005f493f1faa83071d3a937cf220d4051dc48b8fe3a087879cf14a";
+    const ONION: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+
+    #[test]
+    fn test_decode_node() -> Result<()> {
+        let node = decode_node(PUBKEY)?;
+        assert_eq!(node.pubkey, PUBKEY);
+        assert!(node.address.is_none());
+
+        let node = decode_node(&format!("{PUBKEY}@203.0.113.7:9736"))?;
+        let address = node.address.context("address")?;
+        assert_eq!(address.kind, NodeAddressKind::Ipv4);
+        assert_eq!(address.port, 9736);
+        assert!(address.explicit_port);
+
+        let node = decode_node(&format!("{PUBKEY}@[2001:db8::1]"))?;
+        let address = node.address.context("address")?;
+        assert_eq!(address.kind, NodeAddressKind::Ipv6);
+        assert_eq!(address.port, DEFAULT_LIGHTNING_PORT);
+        assert_eq!(node.uri, Some(format!("{PUBKEY}@[2001:db8::1]:9735")));
+
+        let node = decode_node(&format!("{PUBKEY}@{ONION}:9735"))?;
+        assert_eq!(
+            node.address.context("address")?.kind,
+            NodeAddressKind::TorV3
+        );
+
+        let node = decode_node(&format!("{PUBKEY}@node.example.com"))?;
+        assert_eq!(node.address.context("address")?.kind, NodeAddressKind::Dns);
+
+        assert!(decode_node(&PUBKEY[..64]).is_err());
+        assert!(decode_node(&PUBKEY.replace('f', "g")).is_err());
+        assert!(decode_node(&format!("{PUBKEY}@expyuzz4wqqyqhjn.onion:9735")).is_err());
+        assert!(decode_node(&format!("{PUBKEY}@203.0.113.7:70000")).is_err());
+        assert!(decode_node(&format!("{PUBKEY}@bad_host")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lnd_node_info() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", format!("/v1/graph/node/{PUBKEY}").as_str())
+            .match_query(mockito::Matcher::UrlEncoded(
+                "include_channels".into(),
+                "false".into(),
+            ))
+            .match_header("Grpc-Metadata-macaroon", "0201")
+            .with_body(
+                serde_json::json!({
+                    "node": {
+                        "last_update": 1700000000,
+                        "pub_key": PUBKEY,
+                        "alias": "ACINQ",
+                        "addresses": [{"network": "tcp", "addr": "3.33.236.230:9735"}],
+                        "color": "#49daaa",
+                        "features": {
+                            "9": {"name": "tlv-onion", "is_required": false, "is_known": true},
+                            "1": {"name": "data-loss-protect", "is_required": true, "is_known": true}
+                        }
+                    },
+                    "num_channels": 2500,
+                    "total_capacity": "38000000000",
+                    "channels": []
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let client = LndGraphClient {
+            url: server.url(),
+            macaroon_hex: "0201".to_string(),
+            cert_fingerprint: None,
+            proxy: None,
+        };
+        let info = client.node_info(PUBKEY).await?;
+        mock.assert_async().await;
+        assert_eq!(info.alias, "ACINQ");
+        assert_eq!(info.addresses, ["3.33.236.230:9735"]);
+        assert_eq!(info.num_channels, 2500);
+        assert_eq!(info.total_capacity_sats, 38_000_000_000);
+        assert_eq!(info.last_update, 1_700_000_000);
+        let bits: Vec<u32> = info.features.iter().map(|f| f.bit).collect();
+        assert_eq!(bits, [1, 9]);
+        assert!(info.features[0].required);
+        Ok(())
+    }
+}
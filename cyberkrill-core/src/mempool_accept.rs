@@ -0,0 +1,233 @@
+//! Pre-flight mempool acceptance checks
+//!
+//! Bitcoin Core's `testmempoolaccept` (and the `/txs/test` endpoint of
+//! mempool.space-compatible Esplora servers, which proxies it) runs a
+//! transaction through the node's policy checks without relaying it. The
+//! rejection reason is reported along with a plain-language explanation, so a
+//! low fee or a spent input is caught before broadcasting.
+
+use crate::error::ensure;
+use anyhow::{Context, Result};
+use bitcoin::Amount;
+use serde::{Deserialize, Serialize};
+
+/// Whether a transaction would be accepted into the mempool
+#[derive(Debug, Clone, Serialize)]
+pub struct MempoolAcceptance {
+    pub txid: String,
+    pub allowed: bool,
+    pub vsize: Option<u64>,
+    pub fee_sats: Option<u64>,
+    pub fee_rate_sat_per_vb: Option<f64>,
+    /// Reason reported by the node, e.g. `min relay fee not met`
+    pub reject_reason: Option<String>,
+    /// What the rejection reason means, for the common ones
+    pub explanation: Option<String>,
+}
+
+/// One entry of a `testmempoolaccept` response
+#[derive(Deserialize)]
+struct RawAcceptance {
+    txid: String,
+    allowed: Option<bool>,
+    vsize: Option<u64>,
+    fees: Option<RawFees>,
+    #[serde(rename = "reject-reason")]
+    reject_reason: Option<String>,
+    #[serde(rename = "reject-details")]
+    reject_details: Option<String>,
+    #[serde(rename = "package-error")]
+    package_error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawFees {
+    base: f64,
+}
+
+/// Parse a `testmempoolaccept` response for a single transaction
+pub(crate) fn parse_test_mempool_accept(response: &serde_json::Value) -> Result<MempoolAcceptance> {
+    let mut results: Vec<RawAcceptance> = serde_json::from_value(response.clone())
+        .context("Unexpected testmempoolaccept response")?;
+    ensure!(
+        results.len() == 1,
+        "testmempoolaccept returned {count} results for one transaction",
+        count = results.len()
+    );
+    let raw = results.remove(0);
+
+    let fee_sats = raw
+        .fees
+        .map(|fees| Amount::from_btc(fees.base).map(Amount::to_sat))
+        .transpose()
+        .context("Invalid fee in testmempoolaccept response")?;
+    let fee_rate_sat_per_vb = match (fee_sats, raw.vsize) {
+        (Some(fee), Some(vsize)) if vsize > 0 => Some(fee as f64 / vsize as f64),
+        _ => None,
+    };
+    let reject_reason = match (raw.reject_reason.or(raw.package_error), raw.reject_details) {
+        // Newer nodes repeat the reason at the start of the details
+        (Some(reason), Some(details)) if details.starts_with(&reason) => Some(details),
+        (Some(reason), Some(details)) => Some(format!("{reason} ({details})")),
+        (reason, _) => reason,
+    };
+    let explanation = reject_reason
+        .as_deref()
+        .and_then(explain_reject_reason)
+        .map(str::to_string);
+
+    Ok(MempoolAcceptance {
+        txid: raw.txid,
+        allowed: raw.allowed.unwrap_or(false),
+        vsize: raw.vsize,
+        fee_sats,
+        fee_rate_sat_per_vb,
+        reject_reason,
+        explanation,
+    })
+}
+
+/// Plain-language meaning of common mempool rejection reasons
+pub fn explain_reject_reason(reason: &str) -> Option<&'static str> {
+    const EXPLANATIONS: &[(&str, &str)] = &[
+        (
+            "min relay fee not met",
+            "Fee too low: the fee rate is below the node's minimum relay fee",
+        ),
+        (
+            "mempool min fee not met",
+            "Fee too low: the mempool is full and the fee rate is below its current minimum",
+        ),
+        (
+            "insufficient fee",
+            "Fee too low to replace the conflicting transaction (RBF rules)",
+        ),
+        (
+            "missing-inputs",
+            "Missing inputs: an input is unknown or already spent",
+        ),
+        (
+            "bad-txns-inputs-missingorspent",
+            "Missing inputs: an input is unknown or already spent",
+        ),
+        (
+            "txn-mempool-conflict",
+            "An input is already spent by a mempool transaction that cannot be replaced",
+        ),
+        (
+            "txn-already-in-mempool",
+            "The transaction is already in the mempool",
+        ),
+        ("txn-already-known", "The transaction is already known"),
+        (
+            "bad-txns-in-belowout",
+            "Invalid: outputs spend more than the inputs provide",
+        ),
+        (
+            "mandatory-script-verify-flag-failed",
+            "Invalid signature or script: the transaction is not (fully) signed correctly",
+        ),
+        (
+            "non-mandatory-script-verify-flag",
+            "Non-standard script or signature encoding",
+        ),
+        ("dust", "Non-standard: an output is below the dust limit"),
+        (
+            "scriptpubkey",
+            "Non-standard: an output script type is not relayed",
+        ),
+        (
+            "bare-multisig",
+            "Non-standard: bare multisig outputs are not relayed",
+        ),
+        (
+            "multi-op-return",
+            "Non-standard: more than one OP_RETURN output",
+        ),
+        ("tx-size", "Non-standard: the transaction is too large"),
+        ("version", "Non-standard: unsupported transaction version"),
+        (
+            "non-final",
+            "The transaction's locktime has not been reached",
+        ),
+        (
+            "non-BIP68-final",
+            "A relative timelock (nSequence) has not been reached",
+        ),
+        (
+            "too-long-mempool-chain",
+            "Too many unconfirmed ancestors or descendants in the mempool",
+        ),
+        (
+            "max-fee-exceeded",
+            "The fee rate exceeds the node's maximum (maxfeerate)",
+        ),
+        ("absurdly-high-fee", "The fee exceeds the node's maximum"),
+    ];
+
+    EXPLANATIONS
+        .iter()
+        .find(|(prefix, _)| reason.starts_with(prefix))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TXID: &str = "b5d5eae1c5a1f3b0fc2e3b5a0cd71c1b6a1f0ed3ebf2cbf2fd1e7f7c8f5e6a7b";
+
+    #[test]
+    fn test_parse_accepted() -> Result<()> {
+        let acceptance = parse_test_mempool_accept(&serde_json::json!([{
+            "txid": TXID,
+            "wtxid": TXID,
+            "allowed": true,
+            "vsize": 141,
+            "fees": {"base": 0.00001410, "effective-feerate": 0.0001, "effective-includes": [TXID]},
+        }]))?;
+        assert!(acceptance.allowed);
+        assert_eq!(acceptance.fee_sats, Some(1410));
+        assert_eq!(acceptance.fee_rate_sat_per_vb, Some(10.0));
+        assert!(acceptance.reject_reason.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rejected() -> Result<()> {
+        let acceptance = parse_test_mempool_accept(&serde_json::json!([{
+            "txid": TXID,
+            "wtxid": TXID,
+            "allowed": false,
+            "reject-reason": "min relay fee not met",
+            "reject-details": "min relay fee not met, 0 < 141",
+        }]))?;
+        assert!(!acceptance.allowed);
+        assert_eq!(
+            acceptance.reject_reason.as_deref(),
+            Some("min relay fee not met, 0 < 141")
+        );
+        assert!(
+            acceptance
+                .explanation
+                .is_some_and(|e| e.starts_with("Fee too low"))
+        );
+
+        // Unknown inputs are reported as a package error by some versions
+        let acceptance = parse_test_mempool_accept(&serde_json::json!([{
+            "txid": TXID,
+            "package-error": "missing-inputs",
+        }]))?;
+        assert!(!acceptance.allowed);
+        assert_eq!(acceptance.reject_reason.as_deref(), Some("missing-inputs"));
+        assert!(acceptance.explanation.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_reject_reason() {
+        assert!(explain_reject_reason("dust").is_some());
+        assert!(explain_reject_reason("mandatory-script-verify-flag-failed (Signature must be zero for failed CHECK(MULTI)SIG operation)").is_some());
+        assert_eq!(explain_reject_reason("something-new"), None);
+    }
+}
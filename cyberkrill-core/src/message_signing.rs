@@ -0,0 +1,1003 @@
+//! Message signing and verification (BIP322 simple signatures, BIP137 and legacy signmessage)
+//!
+//! BIP322 signs a virtual transaction spending an output locked to the address,
+//! so any key that can sign a PSBT (including hardware wallets) can produce a
+//! proof. Legacy signatures are the 65-byte recoverable format used by Bitcoin
+//! Core's `signmessage` and only apply to P2PKH addresses. BIP137 reuses that
+//! format for P2SH-P2WPKH and P2WPKH by encoding the address type in the header
+//! byte, which is what hardware wallets produce natively.
+
+use crate::error::{CoreResult, bail, ensure};
+use anyhow::{Context, Result};
+use base64::Engine;
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{Hash, HashEngine, sha256};
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache};
+use bitcoin::sign_message::{MessageSignature, signed_msg_hash};
+use bitcoin::{
+    Address, Amount, CompressedPublicKey, Network, OutPoint, PrivateKey, PublicKey, ScriptBuf,
+    Sequence, Transaction, TxIn, TxOut, Txid, Witness, XOnlyPublicKey, absolute, ecdsa, opcodes,
+    taproot, transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use strum::{Display, EnumString};
+
+/// BIP340 tag for the BIP322 message hash
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// The parent fingerprint of the xpub at this path is the master fingerprint
+#[cfg(any(
+    feature = "trezor",
+    feature = "jade",
+    feature = "coldcard",
+    feature = "bitbox"
+))]
+pub(crate) const MASTER_CHILD_PATH: &str = "m/0'";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum MessageSignatureFormat {
+    /// BIP322 simple signature (P2WPKH and P2TR key-path addresses)
+    Bip322,
+    /// Legacy `signmessage` recoverable signature (P2PKH addresses)
+    Legacy,
+    /// BIP137 recoverable signature whose header encodes the address type
+    /// (P2PKH, P2SH-P2WPKH or P2WPKH)
+    Bip137,
+}
+
+/// Address type to sign for when signing with a raw private key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum MessageAddressType {
+    P2pkh,
+    #[serde(rename = "p2sh-p2wpkh")]
+    #[strum(serialize = "p2sh-p2wpkh")]
+    P2shP2wpkh,
+    P2wpkh,
+    P2tr,
+}
+
+impl MessageAddressType {
+    /// First BIP137 header byte for this address type (compressed keys);
+    /// the recovery id is added to it
+    fn bip137_header_base(self) -> Result<u8> {
+        match self {
+            MessageAddressType::P2pkh => Ok(31),
+            MessageAddressType::P2shP2wpkh => Ok(35),
+            MessageAddressType::P2wpkh => Ok(39),
+            MessageAddressType::P2tr => {
+                bail!("BIP137 signatures cannot commit to p2tr addresses; use bip322")
+            }
+        }
+    }
+
+    /// Address of `key` for this address type
+    fn address(self, key: &CompressedPublicKey, network: Network) -> Address {
+        match self {
+            MessageAddressType::P2pkh => Address::p2pkh(key, network),
+            MessageAddressType::P2shP2wpkh => Address::p2shwpkh(key, network),
+            MessageAddressType::P2wpkh => Address::p2wpkh(key, network),
+            MessageAddressType::P2tr => Address::p2tr(
+                &Secp256k1::verification_only(),
+                XOnlyPublicKey::from(key.0),
+                None,
+                network,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedMessage {
+    pub address: String,
+    pub message: String,
+    /// Base64-encoded signature
+    pub signature: String,
+    pub format: MessageSignatureFormat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageVerification {
+    pub address: String,
+    pub message: String,
+    pub valid: bool,
+    /// Format the signature was decoded as
+    pub format: Option<MessageSignatureFormat>,
+    /// Why verification failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// BIP322 message hash: `tagged_hash("BIP0322-signed-message", message)`
+pub fn bip322_message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(BIP322_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message);
+    sha256::Hash::from_engine(engine)
+}
+
+/// The virtual `to_spend` transaction committing to the message and address
+fn to_spend_tx(script_pubkey: &ScriptBuf, message: &[u8]) -> Transaction {
+    let message_hash = bip322_message_hash(message);
+    let script_sig = bitcoin::script::Builder::new()
+        .push_opcode(opcodes::OP_0)
+        .push_slice(message_hash.to_byte_array())
+        .into_script();
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: Txid::all_zeros(),
+                vout: 0xFFFF_FFFF,
+            },
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The virtual `to_sign` transaction spending `to_spend`
+fn to_sign_tx(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: to_spend.compute_txid(),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: bitcoin::script::Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+/// Unsigned BIP322 `to_sign` PSBT for `address`, signable by any PSBT signer
+pub fn bip322_to_sign_psbt(address: &Address, message: &str) -> CoreResult<Psbt> {
+    let to_spend = to_spend_tx(&address.script_pubkey(), message.as_bytes());
+    let mut psbt = Psbt::from_unsigned_tx(to_sign_tx(&to_spend))?;
+    psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+    Ok(psbt)
+}
+
+/// Extract the BIP322 simple signature from a signed `to_sign` PSBT or final transaction
+pub fn bip322_signature_from_signed(signed: &[u8]) -> CoreResult<String> {
+    let witness = match Psbt::deserialize(signed) {
+        Ok(psbt) => {
+            let input = psbt.inputs.first().context("Signed PSBT has no inputs")?;
+            if let Some(witness) = &input.final_script_witness {
+                witness.clone()
+            } else if let Some(signature) = &input.tap_key_sig {
+                Witness::p2tr_key_spend(signature)
+            } else if let Some((pubkey, signature)) = input.partial_sigs.iter().next() {
+                Witness::p2wpkh(signature, &pubkey.inner)
+            } else {
+                bail!("Signed PSBT does not contain a signature for the message");
+            }
+        }
+        Err(_) => {
+            let tx: Transaction =
+                deserialize(signed).context("Signer returned neither a PSBT nor a transaction")?;
+            tx.input
+                .first()
+                .map(|input| input.witness.clone())
+                .context("Signed transaction has no inputs")?
+        }
+    };
+    ensure!(!witness.is_empty(), "Signer did not produce a witness");
+    Ok(base64::engine::general_purpose::STANDARD.encode(serialize(&witness)))
+}
+
+/// Sign `message` with a raw private key for the given address type.
+///
+/// Legacy signatures require a P2PKH address; BIP322 supports P2WPKH and P2TR.
+pub fn sign_message_with_key(
+    message: &str,
+    private_key: &PrivateKey,
+    address_type: MessageAddressType,
+    format: MessageSignatureFormat,
+    network: Network,
+) -> CoreResult<SignedMessage> {
+    let secp = Secp256k1::new();
+    let public_key = private_key.public_key(&secp);
+    let compressed = CompressedPublicKey::try_from(public_key)
+        .context("Message signing requires a compressed public key")?;
+
+    let (address, signature) = match (format, address_type) {
+        (MessageSignatureFormat::Legacy, MessageAddressType::P2pkh)
+        | (MessageSignatureFormat::Bip137, _) => {
+            let header_base = address_type.bip137_header_base()?;
+            let digest = signed_msg_hash(message);
+            let signature = secp.sign_ecdsa_recoverable(
+                &Message::from_digest(digest.to_byte_array()),
+                &private_key.inner,
+            );
+            (
+                address_type.address(&compressed, network),
+                base64::engine::general_purpose::STANDARD
+                    .encode(bip137_signature(&signature, header_base)),
+            )
+        }
+        (MessageSignatureFormat::Legacy, _) => {
+            bail!(
+                "Legacy message signatures are only defined for p2pkh addresses; use bip137 or bip322"
+            )
+        }
+        (MessageSignatureFormat::Bip322, MessageAddressType::P2wpkh) => {
+            let address = Address::p2wpkh(&compressed, network);
+            let to_spend = to_spend_tx(&address.script_pubkey(), message.as_bytes());
+            let mut to_sign = to_sign_tx(&to_spend);
+            let sighash = SighashCache::new(&to_sign).p2wpkh_signature_hash(
+                0,
+                &address.script_pubkey(),
+                Amount::ZERO,
+                EcdsaSighashType::All,
+            )?;
+            let signature = ecdsa::Signature::sighash_all(
+                secp.sign_ecdsa_low_r(&Message::from(sighash), &private_key.inner),
+            );
+            to_sign.input[0].witness = Witness::p2wpkh(&signature, &compressed.0);
+            (address, encode_witness(&to_sign.input[0].witness))
+        }
+        (MessageSignatureFormat::Bip322, MessageAddressType::P2tr) => {
+            let keypair = Keypair::from_secret_key(&secp, &private_key.inner);
+            let (internal_key, _) = keypair.x_only_public_key();
+            let address = Address::p2tr(&secp, internal_key, None, network);
+            let to_spend = to_spend_tx(&address.script_pubkey(), message.as_bytes());
+            let mut to_sign = to_sign_tx(&to_spend);
+            let sighash = SighashCache::new(&to_sign).taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&to_spend.output),
+                bitcoin::TapSighashType::Default,
+            )?;
+            let tweaked = keypair.tap_tweak(&secp, None);
+            let signature = taproot::Signature {
+                signature: secp
+                    .sign_schnorr_no_aux_rand(&Message::from(sighash), &tweaked.to_keypair()),
+                sighash_type: bitcoin::TapSighashType::Default,
+            };
+            to_sign.input[0].witness = Witness::p2tr_key_spend(&signature);
+            (address, encode_witness(&to_sign.input[0].witness))
+        }
+        (MessageSignatureFormat::Bip322, MessageAddressType::P2pkh) => {
+            bail!("BIP322 signing is supported for p2wpkh and p2tr addresses; use legacy for p2pkh")
+        }
+        (MessageSignatureFormat::Bip322, MessageAddressType::P2shP2wpkh) => {
+            bail!(
+                "BIP322 signing is supported for p2wpkh and p2tr addresses; use bip137 for p2sh-p2wpkh"
+            )
+        }
+    };
+
+    Ok(SignedMessage {
+        address: address.to_string(),
+        message: message.to_string(),
+        signature,
+        format,
+    })
+}
+
+/// 65-byte BIP137 signature: header byte followed by the compact signature
+fn bip137_signature(signature: &RecoverableSignature, header_base: u8) -> [u8; 65] {
+    let (recovery_id, compact) = signature.serialize_compact();
+    let mut serialized = [0u8; 65];
+    serialized[0] = header_base + recovery_id.to_i32() as u8;
+    serialized[1..].copy_from_slice(&compact);
+    serialized
+}
+
+/// Split a 65-byte BIP137/legacy signature into its header and recoverable signature
+fn parse_recoverable(signature: &[u8]) -> Result<(u8, RecoverableSignature)> {
+    ensure!(
+        signature.len() == 65,
+        "Recoverable signatures are 65 bytes, got {len}",
+        len = signature.len()
+    );
+    let header = signature[0];
+    ensure!(
+        (27..=42).contains(&header),
+        "Invalid signature header byte {header}"
+    );
+    let recovery_id = RecoveryId::from_i32(i32::from((header - 27) % 4))?;
+    let signature = RecoverableSignature::from_compact(&signature[1..], recovery_id)
+        .context("Invalid recoverable signature")?;
+    Ok((header, signature))
+}
+
+fn encode_witness(witness: &Witness) -> String {
+    base64::engine::general_purpose::STANDARD.encode(serialize(witness))
+}
+
+/// Verify `signature` over `message` for `address`, detecting the signature format.
+pub fn verify_message(
+    address: &str,
+    message: &str,
+    signature: &str,
+    network: Network,
+) -> CoreResult<MessageVerification> {
+    let parsed = Address::from_str(address)
+        .with_context(|| format!("Invalid address: {address}"))?
+        .require_network(network)
+        .with_context(|| format!("Address {address} is not valid for {network}"))?;
+    let signature_bytes = base64::engine::general_purpose::STANDARD
+        .decode(signature.trim())
+        .context("Signature is not valid base64")?;
+
+    let script_pubkey = parsed.script_pubkey();
+    let recoverable = signature_bytes.len() == 65
+        && (script_pubkey.is_p2pkh() || script_pubkey.is_p2sh() || script_pubkey.is_p2wpkh());
+    let (format, outcome) = if recoverable {
+        let format = if script_pubkey.is_p2pkh() {
+            MessageSignatureFormat::Legacy
+        } else {
+            MessageSignatureFormat::Bip137
+        };
+        (
+            format,
+            verify_recoverable(&parsed, message, &signature_bytes, network),
+        )
+    } else {
+        (
+            MessageSignatureFormat::Bip322,
+            verify_bip322(&parsed, message, &signature_bytes),
+        )
+    };
+
+    Ok(MessageVerification {
+        address: address.to_string(),
+        message: message.to_string(),
+        valid: outcome.is_ok(),
+        format: Some(format),
+        error: outcome.err().map(|e| format!("{e:#}")),
+    })
+}
+
+/// Verify a legacy or BIP137 signature by recovering the key and rebuilding the address.
+///
+/// Headers 27-30 (uncompressed P2PKH) and 31-34 (compressed P2PKH) are
+/// `signmessage` signatures; 35-38 and 39-42 are BIP137 P2SH-P2WPKH and P2WPKH.
+/// Like Electrum and Sparrow, a 31-34 header is also accepted for segwit
+/// addresses of the same key.
+fn verify_recoverable(
+    address: &Address,
+    message: &str,
+    signature: &[u8],
+    network: Network,
+) -> Result<()> {
+    if signature
+        .first()
+        .is_some_and(|header| (27..=30).contains(header))
+    {
+        let secp = Secp256k1::verification_only();
+        let signature =
+            MessageSignature::from_slice(signature).context("Invalid legacy signature")?;
+        let signed_by = signature
+            .is_signed_by_address(&secp, address, signed_msg_hash(message))
+            .context("Failed to recover the signing key")?;
+        ensure!(signed_by, "Signature was not made by {address}");
+        return Ok(());
+    }
+
+    let (header, signature) = parse_recoverable(signature)?;
+    let secp = Secp256k1::verification_only();
+    let digest = signed_msg_hash(message);
+    let public_key = secp
+        .recover_ecdsa(&Message::from_digest(digest.to_byte_array()), &signature)
+        .context("Failed to recover the signing key")?;
+    let key = CompressedPublicKey(public_key);
+    let candidates: &[MessageAddressType] = match header {
+        31..=34 => &[
+            MessageAddressType::P2pkh,
+            MessageAddressType::P2shP2wpkh,
+            MessageAddressType::P2wpkh,
+        ],
+        35..=38 => &[MessageAddressType::P2shP2wpkh],
+        _ => &[MessageAddressType::P2wpkh],
+    };
+    ensure!(
+        candidates
+            .iter()
+            .any(|address_type| address_type.address(&key, network) == *address),
+        "Signature was not made by {address}"
+    );
+    Ok(())
+}
+
+fn verify_bip322(address: &Address, message: &str, signature: &[u8]) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+    let witness: Witness = deserialize(signature).context("Invalid BIP322 signature encoding")?;
+    let script_pubkey = address.script_pubkey();
+    let to_spend = to_spend_tx(&script_pubkey, message.as_bytes());
+    let to_sign = to_sign_tx(&to_spend);
+
+    if script_pubkey.is_p2wpkh() {
+        ensure!(
+            witness.len() == 2,
+            "P2WPKH signature must have 2 witness elements"
+        );
+        let (Some(signature), Some(pubkey)) = (witness.nth(0), witness.nth(1)) else {
+            bail!("P2WPKH signature must have 2 witness elements");
+        };
+        let pubkey = PublicKey::from_slice(pubkey).context("Invalid public key in witness")?;
+        let compressed =
+            CompressedPublicKey::try_from(pubkey).context("Public key must be compressed")?;
+        ensure!(
+            ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash()) == script_pubkey,
+            "Public key does not match {address}"
+        );
+        let signature = ecdsa::Signature::from_slice(signature).context("Invalid signature")?;
+        let sighash = SighashCache::new(&to_sign).p2wpkh_signature_hash(
+            0,
+            &script_pubkey,
+            Amount::ZERO,
+            signature.sighash_type,
+        )?;
+        secp.verify_ecdsa(&Message::from(sighash), &signature.signature, &pubkey.inner)
+            .context("Signature verification failed")?;
+    } else if script_pubkey.is_p2tr() {
+        ensure!(
+            witness.len() == 1,
+            "P2TR key-path signature must have 1 witness element"
+        );
+        let signature = witness
+            .nth(0)
+            .context("P2TR key-path signature must have 1 witness element")?;
+        let signature = taproot::Signature::from_slice(signature).context("Invalid signature")?;
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..34])
+            .context("Invalid taproot output key")?;
+        let sighash = SighashCache::new(&to_sign).taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(&to_spend.output),
+            signature.sighash_type,
+        )?;
+        secp.verify_schnorr(&signature.signature, &Message::from(sighash), &output_key)
+            .context("Signature verification failed")?;
+    } else {
+        bail!("BIP322 verification is supported for p2wpkh and p2tr addresses");
+    }
+    Ok(())
+}
+
+/// Hardware wallet that signs the BIP322 `to_sign` transaction as a PSBT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum MessageSigningDevice {
+    Trezor,
+    Jade,
+    Coldcard,
+    Bitbox,
+}
+
+/// Sign `message` with a BIP322 simple signature using a hardware wallet.
+///
+/// The address type follows the path purpose: 84' for P2WPKH, 86' for P2TR.
+pub async fn sign_message_bip322_with_device(
+    device: MessageSigningDevice,
+    path: &str,
+    message: &str,
+    network: Network,
+) -> CoreResult<SignedMessage> {
+    let derivation_path = DerivationPath::from_str(path)
+        .with_context(|| format!("Invalid derivation path: {path}"))?;
+    let address_type = match derivation_path.into_iter().next() {
+        Some(bitcoin::bip32::ChildNumber::Hardened { index: 84 }) => MessageAddressType::P2wpkh,
+        Some(bitcoin::bip32::ChildNumber::Hardened { index: 86 }) => MessageAddressType::P2tr,
+        _ => bail!("BIP322 device signing supports m/84'/... (p2wpkh) and m/86'/... (p2tr) paths"),
+    };
+
+    let (fingerprint, key) = fetch_device_key_origin(device, path, network).await?;
+    let public_key = PublicKey::new(key.public_key);
+    let secp = Secp256k1::verification_only();
+
+    let address = match address_type {
+        MessageAddressType::P2wpkh => {
+            Address::p2wpkh(&CompressedPublicKey(public_key.inner), network)
+        }
+        _ => Address::p2tr(&secp, XOnlyPublicKey::from(public_key.inner), None, network),
+    };
+    let mut psbt = bip322_to_sign_psbt(&address, message)?;
+    match address_type {
+        MessageAddressType::P2wpkh => {
+            psbt.inputs[0]
+                .bip32_derivation
+                .insert(public_key.inner, (fingerprint, derivation_path));
+        }
+        _ => {
+            let x_only = XOnlyPublicKey::from(public_key.inner);
+            psbt.inputs[0].tap_internal_key = Some(x_only);
+            psbt.inputs[0]
+                .tap_key_origins
+                .insert(x_only, (Vec::new(), (fingerprint, derivation_path)));
+        }
+    }
+
+    let signed = sign_psbt_with_device(device, &psbt.serialize(), network).await?;
+    Ok(SignedMessage {
+        address: address.to_string(),
+        message: message.to_string(),
+        signature: bip322_signature_from_signed(&signed)?,
+        format: MessageSignatureFormat::Bip322,
+    })
+}
+
+/// Sign `message` with a BIP137 signature using a hardware wallet.
+///
+/// The address type follows the path purpose: 44' for P2PKH, 49' for
+/// P2SH-P2WPKH and 84' for P2WPKH. The address is rebuilt from the key
+/// recovered from the device's signature, and the header byte is rewritten to
+/// the BIP137 value for that address type.
+pub async fn sign_message_bip137_with_device(
+    device: MessageSigningDevice,
+    path: &str,
+    message: &str,
+    network: Network,
+) -> CoreResult<SignedMessage> {
+    let derivation_path = DerivationPath::from_str(path)
+        .with_context(|| format!("Invalid derivation path: {path}"))?;
+    let address_type = bip137_address_type(&derivation_path)?;
+
+    let signature = sign_message_on_device(device, path, message, network).await?;
+    let (address, signature) = bip137_from_device(&signature, message, address_type, network)?;
+
+    let format = if address_type == MessageAddressType::P2pkh {
+        MessageSignatureFormat::Legacy
+    } else {
+        MessageSignatureFormat::Bip137
+    };
+    Ok(SignedMessage {
+        address: address.to_string(),
+        message: message.to_string(),
+        signature: base64::engine::general_purpose::STANDARD.encode(signature),
+        format,
+    })
+}
+
+/// Address type of a BIP137 signature made with the key at `path`
+fn bip137_address_type(path: &DerivationPath) -> Result<MessageAddressType> {
+    match path.into_iter().next() {
+        Some(bitcoin::bip32::ChildNumber::Hardened { index: 44 }) => Ok(MessageAddressType::P2pkh),
+        Some(bitcoin::bip32::ChildNumber::Hardened { index: 49 }) => {
+            Ok(MessageAddressType::P2shP2wpkh)
+        }
+        Some(bitcoin::bip32::ChildNumber::Hardened { index: 84 }) => Ok(MessageAddressType::P2wpkh),
+        _ => bail!(
+            "BIP137 device signing supports m/44'/, m/49'/ and m/84'/ paths; use bip322 for m/86'/"
+        ),
+    }
+}
+
+/// Normalize a device's 65-byte recoverable signature to the BIP137 header for
+/// `address_type`, returning it with the address of the recovered key
+fn bip137_from_device(
+    signature: &[u8],
+    message: &str,
+    address_type: MessageAddressType,
+    network: Network,
+) -> Result<(Address, [u8; 65])> {
+    let (_, signature) =
+        parse_recoverable(signature).context("Device returned an invalid message signature")?;
+    let digest = signed_msg_hash(message);
+    let public_key = Secp256k1::verification_only()
+        .recover_ecdsa(&Message::from_digest(digest.to_byte_array()), &signature)
+        .context("Failed to recover the signing key from the device signature")?;
+    let address = address_type.address(&CompressedPublicKey(public_key), network);
+    Ok((
+        address,
+        bip137_signature(&signature, address_type.bip137_header_base()?),
+    ))
+}
+
+/// Raw 65-byte recoverable signature of `message` by the key at `path` on `device`
+async fn sign_message_on_device(
+    device: MessageSigningDevice,
+    path: &str,
+    message: &str,
+    network: Network,
+) -> CoreResult<Vec<u8>> {
+    match device {
+        #[cfg(feature = "trezor")]
+        MessageSigningDevice::Trezor => {
+            let mut wallet = crate::trezor::TrezorWallet::connect().await?;
+            wallet.init_device()?;
+            wallet.sign_message(message, path, network)
+        }
+        #[cfg(feature = "jade")]
+        MessageSigningDevice::Jade => {
+            crate::jade::sign_jade_message(path, message, network, &Default::default()).await
+        }
+        #[cfg(feature = "coldcard")]
+        MessageSigningDevice::Coldcard => {
+            let _ = network;
+            let mut wallet = crate::coldcard::ColdcardWallet::connect().await?;
+            wallet.sign_message(message, path)
+        }
+        #[cfg(feature = "bitbox")]
+        MessageSigningDevice::Bitbox => {
+            let wallet = crate::bitbox::BitboxWallet::connect().await?;
+            wallet.sign_message(path, message, network).await
+        }
+        #[allow(unreachable_patterns)]
+        _ => {
+            let _ = (path, message, network);
+            bail!("Support for {device} is not enabled in this build")
+        }
+    }
+}
+
+/// Master key fingerprint and the xpub at `path`, used to fill PSBT key
+/// origins. Devices that do not report the fingerprint directly are asked for
+/// the xpub at m/0', whose parent it is.
+pub(crate) async fn fetch_device_key_origin(
+    device: MessageSigningDevice,
+    path: &str,
+    network: Network,
+) -> CoreResult<(Fingerprint, Xpub)> {
+    match device {
+        #[cfg(feature = "trezor")]
+        MessageSigningDevice::Trezor => {
+            let mut wallet = crate::trezor::TrezorWallet::connect().await?;
+            wallet.init_device()?;
+            Ok((
+                wallet
+                    .get_xpub(MASTER_CHILD_PATH, network)?
+                    .parent_fingerprint,
+                wallet.get_xpub(path, network)?,
+            ))
+        }
+        #[cfg(feature = "jade")]
+        MessageSigningDevice::Jade => {
+            let key = crate::jade::generate_jade_xpub(path, network, &Default::default()).await?;
+            Ok((
+                Fingerprint::from_str(&key.master_fingerprint)
+                    .context("Invalid master fingerprint from Jade")?,
+                Xpub::from_str(&key.xpub).context("Invalid xpub from Jade")?,
+            ))
+        }
+        #[cfg(feature = "coldcard")]
+        MessageSigningDevice::Coldcard => {
+            let _ = network;
+            let mut wallet = crate::coldcard::ColdcardWallet::connect().await?;
+            Ok((
+                wallet.get_xpub(MASTER_CHILD_PATH)?.parent_fingerprint,
+                wallet.get_xpub(path)?,
+            ))
+        }
+        #[cfg(feature = "bitbox")]
+        MessageSigningDevice::Bitbox => {
+            let wallet = crate::bitbox::BitboxWallet::connect().await?;
+            Ok((
+                wallet.master_fingerprint().await?,
+                wallet.get_xpub(path, network).await?,
+            ))
+        }
+        #[allow(unreachable_patterns)]
+        _ => {
+            let _ = (path, network);
+            bail!("Support for {device} is not enabled in this build")
+        }
+    }
+}
+
+/// Sign a serialized PSBT with `device`, returning the signed PSBT bytes
+pub(crate) async fn sign_psbt_with_device(
+    device: MessageSigningDevice,
+    psbt: &[u8],
+    network: Network,
+) -> CoreResult<Vec<u8>> {
+    match device {
+        #[cfg(feature = "trezor")]
+        MessageSigningDevice::Trezor => {
+            let signed = crate::trezor::sign_psbt_with_trezor(psbt, network).await?;
+            Ok(hex::decode(signed.psbt_hex)?)
+        }
+        #[cfg(feature = "jade")]
+        MessageSigningDevice::Jade => {
+            let signed = crate::jade::sign_psbt_with_jade(
+                &hex::encode(psbt),
+                network,
+                &Default::default(),
+                false,
+            )
+            .await?;
+            Ok(hex::decode(signed.psbt_hex)?)
+        }
+        #[cfg(feature = "coldcard")]
+        MessageSigningDevice::Coldcard => {
+            let _ = network;
+            let signed = crate::coldcard::sign_psbt_with_coldcard(psbt).await?;
+            Ok(hex::decode(signed.psbt_hex)?)
+        }
+        #[cfg(feature = "bitbox")]
+        MessageSigningDevice::Bitbox => {
+            let signed = crate::bitbox::sign_psbt_with_bitbox(psbt, network).await?;
+            Ok(hex::decode(signed.psbt_hex)?)
+        }
+        #[allow(unreachable_patterns)]
+        _ => {
+            let _ = (psbt, network);
+            bail!("Support for {device} is not enabled in this build")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors from BIP322
+    const TEST_WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const TEST_P2WPKH: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const TEST_P2TR: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    #[test]
+    fn test_bip322_message_hash() -> Result<()> {
+        assert_eq!(
+            bip322_message_hash(b"").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            bip322_message_hash(b"Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bip322_p2wpkh_vectors() -> Result<()> {
+        let key = PrivateKey::from_wif(TEST_WIF)?;
+        let signed = sign_message_with_key(
+            "Hello World",
+            &key,
+            MessageAddressType::P2wpkh,
+            MessageSignatureFormat::Bip322,
+            Network::Bitcoin,
+        )?;
+        assert_eq!(signed.address, TEST_P2WPKH);
+        // Low-R grinding over RFC6979 nonces matches Bitcoin Core, so the vector is reproduced exactly
+        assert_eq!(
+            signed.signature,
+            "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        );
+
+        let empty = verify_message(
+            TEST_P2WPKH,
+            "",
+            "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=",
+            Network::Bitcoin,
+        )?;
+        assert!(empty.valid, "{error:?}", error = empty.error);
+
+        let wrong_message =
+            verify_message(TEST_P2WPKH, "Hello", &signed.signature, Network::Bitcoin)?;
+        assert!(!wrong_message.valid);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bip322_p2tr_vector() -> Result<()> {
+        let verification = verify_message(
+            TEST_P2TR,
+            "Hello World",
+            "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==",
+            Network::Bitcoin,
+        )?;
+        assert!(verification.valid, "{error:?}", error = verification.error);
+
+        let key = PrivateKey::from_wif(TEST_WIF)?;
+        let signed = sign_message_with_key(
+            "Hello World",
+            &key,
+            MessageAddressType::P2tr,
+            MessageSignatureFormat::Bip322,
+            Network::Bitcoin,
+        )?;
+        assert_eq!(signed.address, TEST_P2TR);
+        assert!(
+            verify_message(
+                TEST_P2TR,
+                "Hello World",
+                &signed.signature,
+                Network::Bitcoin
+            )?
+            .valid
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_round_trip() -> Result<()> {
+        let key = PrivateKey::from_wif(TEST_WIF)?;
+        let signed = sign_message_with_key(
+            "attestation",
+            &key,
+            MessageAddressType::P2pkh,
+            MessageSignatureFormat::Legacy,
+            Network::Bitcoin,
+        )?;
+        let verification = verify_message(
+            &signed.address,
+            "attestation",
+            &signed.signature,
+            Network::Bitcoin,
+        )?;
+        assert_eq!(verification.format, Some(MessageSignatureFormat::Legacy));
+        assert!(verification.valid);
+        assert!(
+            !verify_message(
+                &signed.address,
+                "forged",
+                &signed.signature,
+                Network::Bitcoin
+            )?
+            .valid
+        );
+
+        assert!(
+            sign_message_with_key(
+                "attestation",
+                &key,
+                MessageAddressType::P2wpkh,
+                MessageSignatureFormat::Legacy,
+                Network::Bitcoin,
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bip137_round_trip() -> Result<()> {
+        let key = PrivateKey::from_wif(TEST_WIF)?;
+        for address_type in [MessageAddressType::P2shP2wpkh, MessageAddressType::P2wpkh] {
+            let signed = sign_message_with_key(
+                "attestation",
+                &key,
+                address_type,
+                MessageSignatureFormat::Bip137,
+                Network::Bitcoin,
+            )?;
+            let verification = verify_message(
+                &signed.address,
+                "attestation",
+                &signed.signature,
+                Network::Bitcoin,
+            )?;
+            assert_eq!(verification.format, Some(MessageSignatureFormat::Bip137));
+            assert!(verification.valid, "{address_type}: {verification:?}");
+            assert!(
+                !verify_message(
+                    &signed.address,
+                    "forged",
+                    &signed.signature,
+                    Network::Bitcoin
+                )?
+                .valid
+            );
+        }
+        let signed = sign_message_with_key(
+            "attestation",
+            &key,
+            MessageAddressType::P2wpkh,
+            MessageSignatureFormat::Bip137,
+            Network::Bitcoin,
+        )?;
+        assert_eq!(signed.address, TEST_P2WPKH);
+
+        assert!(
+            sign_message_with_key(
+                "attestation",
+                &key,
+                MessageAddressType::P2tr,
+                MessageSignatureFormat::Bip137,
+                Network::Bitcoin,
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bip137_from_device_signature() -> Result<()> {
+        // Devices like Jade answer with a compressed P2PKH header whatever the path
+        let key = PrivateKey::from_wif(TEST_WIF)?;
+        let legacy = sign_message_with_key(
+            "attestation",
+            &key,
+            MessageAddressType::P2pkh,
+            MessageSignatureFormat::Legacy,
+            Network::Bitcoin,
+        )?;
+        let device_signature =
+            base64::engine::general_purpose::STANDARD.decode(&legacy.signature)?;
+        assert!((31..=34).contains(&device_signature[0]));
+
+        // Electrum-style header 31-34 is accepted for the key's segwit address too
+        assert!(
+            verify_message(
+                TEST_P2WPKH,
+                "attestation",
+                &legacy.signature,
+                Network::Bitcoin
+            )?
+            .valid
+        );
+
+        let (address, signature) = bip137_from_device(
+            &device_signature,
+            "attestation",
+            MessageAddressType::P2wpkh,
+            Network::Bitcoin,
+        )?;
+        assert_eq!(address.to_string(), TEST_P2WPKH);
+        assert!((39..=42).contains(&signature[0]));
+        assert_eq!(signature[1..], device_signature[1..]);
+        assert!(
+            verify_message(
+                TEST_P2WPKH,
+                "attestation",
+                &base64::engine::general_purpose::STANDARD.encode(signature),
+                Network::Bitcoin
+            )?
+            .valid
+        );
+
+        assert!(bip137_address_type(&DerivationPath::from_str("m/86'/0'/0'/0/0")?).is_err());
+        assert!(
+            bip137_from_device(
+                &device_signature[1..],
+                "attestation",
+                MessageAddressType::P2wpkh,
+                Network::Bitcoin
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_from_signed_psbt() -> Result<()> {
+        let key = PrivateKey::from_wif(TEST_WIF)?;
+        let signed = sign_message_with_key(
+            "Hello World",
+            &key,
+            MessageAddressType::P2wpkh,
+            MessageSignatureFormat::Bip322,
+            Network::Bitcoin,
+        )?;
+        let witness: Witness =
+            deserialize(&base64::engine::general_purpose::STANDARD.decode(&signed.signature)?)?;
+
+        let address = Address::from_str(TEST_P2WPKH)?.require_network(Network::Bitcoin)?;
+        let mut psbt = bip322_to_sign_psbt(&address, "Hello World")?;
+        psbt.inputs[0].final_script_witness = Some(witness);
+        assert_eq!(
+            bip322_signature_from_signed(&psbt.serialize())?,
+            signed.signature
+        );
+        Ok(())
+    }
+}
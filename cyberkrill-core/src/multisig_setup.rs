@@ -0,0 +1,426 @@
+//! Multisig wallet setup: turn the cosigners' xpubs into a `wsh(sortedmulti)`
+//! descriptor pair and the files each party needs to register the wallet
+//!
+//! Besides the descriptors this produces a BIP129 (BSMS) descriptor record, a
+//! Coldcard multisig setup file and the parameters of Jade's `register_multisig`
+//! call, so every signing device can verify the wallet it is asked to sign for.
+
+use anyhow::{Context, Result, anyhow};
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use bitcoin::{Network, NetworkKind};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::descriptor::{
+    check_descriptor_checksum, expand_descriptor, expand_multipath_descriptor, parse_descriptor,
+};
+use crate::error::{CoreResult, bail, ensure};
+use crate::slip132::parse_slip132_xpub;
+
+/// Most cosigners Coldcard and Jade accept in a multisig wallet
+const MAX_COSIGNERS: usize = 15;
+/// Longest wallet name Jade accepts (Coldcard allows 20)
+const MAX_NAME_LENGTH: usize = 15;
+
+/// A cosigner's account key with its origin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CosignerKey {
+    pub fingerprint: Fingerprint,
+    pub derivation_path: DerivationPath,
+    pub xpub: Xpub,
+}
+
+impl CosignerKey {
+    /// Key in descriptor form, `[fingerprint/path]xpub/<0;1>/*`
+    pub fn descriptor_key(&self) -> String {
+        format!(
+            "[{fingerprint}/{path}]{xpub}/<0;1>/*",
+            fingerprint = self.fingerprint,
+            path = self.derivation_path,
+            xpub = self.xpub
+        )
+    }
+
+    /// `m/`-prefixed derivation path
+    fn path(&self) -> String {
+        format!("m/{path}", path = self.derivation_path)
+    }
+}
+
+impl FromStr for CosignerKey {
+    type Err = anyhow::Error;
+
+    /// Parse `[fingerprint/path]xpub`, optionally followed by `/<0;1>/*` or `/**`
+    fn from_str(key: &str) -> Result<Self> {
+        let key = key.trim();
+        let key = ["/<0;1>/*", "/**"]
+            .iter()
+            .find_map(|suffix| key.strip_suffix(suffix))
+            .unwrap_or(key);
+        let (origin, xpub) = key
+            .strip_prefix('[')
+            .and_then(|key| key.split_once(']'))
+            .with_context(|| {
+                format!("Cosigner key '{key}' needs its origin: [fingerprint/path]xpub")
+            })?;
+        let (fingerprint, path) = origin
+            .split_once('/')
+            .with_context(|| format!("Cosigner key origin '[{origin}]' has no derivation path"))?;
+        ensure!(
+            !xpub.contains('/'),
+            "Cosigner key '{key}' must be an account xpub without further derivation"
+        );
+        Ok(CosignerKey {
+            fingerprint: Fingerprint::from_str(fingerprint)
+                .with_context(|| format!("Invalid fingerprint '{fingerprint}'"))?,
+            derivation_path: DerivationPath::from_str(&format!("m/{path}"))
+                .with_context(|| format!("Invalid derivation path '{path}'"))?,
+            xpub: parse_slip132_xpub(xpub).with_context(|| format!("Invalid xpub '{xpub}'"))?,
+        })
+    }
+}
+
+/// Cosigner keys from a file: `hw-export-descriptor` output, a Coldcard
+/// multisig xpub export, or one `[fingerprint/path]xpub` per line
+pub fn parse_cosigner_file(content: &str) -> CoreResult<Vec<CosignerKey>> {
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(content) {
+        let field = |name: &str| json.get(name).and_then(|value| value.as_str());
+        if let Some(key) = field("key") {
+            return Ok(vec![key.parse()?]);
+        }
+        if let (Some(xfp), Some(path), Some(xpub)) =
+            (field("xfp"), field("p2wsh_deriv"), field("p2wsh"))
+        {
+            let path = path.trim_start_matches("m/");
+            return Ok(vec![format!("[{xfp}/{path}]{xpub}").parse()?]);
+        }
+        bail!("Unrecognized cosigner JSON: expected hw-export-descriptor or Coldcard output");
+    }
+    let keys = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(CosignerKey::from_str)
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(!keys.is_empty(), "No cosigner keys found");
+    Ok(keys)
+}
+
+/// Cosigner as listed in the setup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultisigCosigner {
+    pub fingerprint: String,
+    pub derivation_path: String,
+    pub xpub: String,
+}
+
+/// Parameters of Jade's `register_multisig` call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JadeMultisigRegistration {
+    pub network: String,
+    pub multisig_name: String,
+    pub descriptor: JadeMultisigDescriptor,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JadeMultisigDescriptor {
+    pub variant: String,
+    pub sorted: bool,
+    pub threshold: usize,
+    pub signers: Vec<JadeMultisigSigner>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JadeMultisigSigner {
+    pub fingerprint: String,
+    pub derivation: Vec<u32>,
+    pub xpub: String,
+    /// Derivation below the xpub, empty for account keys
+    pub path: Vec<u32>,
+}
+
+/// Everything the cosigners need to set up and register the wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultisigSetup {
+    pub name: String,
+    pub threshold: usize,
+    pub network: String,
+    pub cosigners: Vec<MultisigCosigner>,
+    /// `<0;1>` descriptor covering both chains
+    pub descriptor: String,
+    pub receive_descriptor: String,
+    pub change_descriptor: String,
+    pub first_address: String,
+    /// BIP129 descriptor record
+    pub bsms: String,
+    /// Coldcard multisig setup file (import from SD card or paste over USB)
+    pub coldcard_file: String,
+    pub jade_registration: JadeMultisigRegistration,
+}
+
+/// Build a `threshold`-of-`keys.len()` `wsh(sortedmulti)` wallet setup named `name`
+pub fn build_multisig_setup(
+    name: &str,
+    threshold: usize,
+    keys: &[CosignerKey],
+    network: Network,
+) -> CoreResult<MultisigSetup> {
+    ensure!(
+        !name.is_empty()
+            && name.len() <= MAX_NAME_LENGTH
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' '),
+        "Wallet name must be 1-{MAX_NAME_LENGTH} letters, digits, spaces, '-' or '_'"
+    );
+    ensure!(
+        (2..=MAX_COSIGNERS).contains(&keys.len()),
+        "A multisig setup needs 2 to {MAX_COSIGNERS} cosigners, got {count}",
+        count = keys.len()
+    );
+    ensure!(
+        (1..=keys.len()).contains(&threshold),
+        "Threshold {threshold} must be between 1 and {count} cosigners",
+        count = keys.len()
+    );
+    for (index, key) in keys.iter().enumerate() {
+        ensure!(
+            key.xpub.network == NetworkKind::from(network),
+            "Cosigner {fingerprint} has an xpub for the wrong network (expected {network})",
+            fingerprint = key.fingerprint
+        );
+        if keys[..index].iter().any(|other| other.xpub == key.xpub) {
+            bail!(
+                "Cosigner {fingerprint} is listed more than once",
+                fingerprint = key.fingerprint
+            );
+        }
+    }
+
+    let body = format!(
+        "wsh(sortedmulti({threshold},{keys}))",
+        keys = keys
+            .iter()
+            .map(CosignerKey::descriptor_key)
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let descriptor = check_descriptor_checksum(&body)?.descriptor;
+    parse_descriptor(&descriptor)?;
+    let [receive, change]: [String; 2] = expand_multipath_descriptor(&body)?
+        .try_into()
+        .map_err(|_| anyhow!("Expected receive and change paths in {descriptor}"))?;
+    let receive_descriptor = check_descriptor_checksum(&receive)?.descriptor;
+    let change_descriptor = check_descriptor_checksum(&change)?.descriptor;
+    let first_address = expand_descriptor(&receive_descriptor, network, 0, 1)?
+        .into_iter()
+        .next()
+        .context("Descriptor derived no address")?
+        .address;
+
+    Ok(MultisigSetup {
+        name: name.to_string(),
+        threshold,
+        network: network.to_string(),
+        cosigners: keys
+            .iter()
+            .map(|key| MultisigCosigner {
+                fingerprint: key.fingerprint.to_string(),
+                derivation_path: key.path(),
+                xpub: key.xpub.to_string(),
+            })
+            .collect(),
+        bsms: bsms_record(&body, &first_address),
+        coldcard_file: coldcard_file(name, threshold, keys),
+        jade_registration: jade_registration(name, threshold, keys, network),
+        descriptor,
+        receive_descriptor,
+        change_descriptor,
+        first_address,
+    })
+}
+
+/// BIP129 descriptor record: template with `/**`, path restrictions and first address
+fn bsms_record(body: &str, first_address: &str) -> String {
+    let template = body.replace("/<0;1>/*", "/**");
+    format!("BSMS 1.0\n{template}\n/0/*,/1/*\n{first_address}\n")
+}
+
+/// Coldcard multisig setup file, with per-key derivations when they differ
+fn coldcard_file(name: &str, threshold: usize, keys: &[CosignerKey]) -> String {
+    let shared_path = keys
+        .iter()
+        .all(|key| key.derivation_path == keys[0].derivation_path);
+    let mut lines = vec![
+        "# Coldcard multisig setup file (created by cyberkrill)".to_string(),
+        format!("Name: {name}"),
+        format!("Policy: {threshold} of {count}", count = keys.len()),
+    ];
+    if shared_path {
+        lines.push(format!("Derivation: {path}", path = keys[0].path()));
+    }
+    lines.push("Format: P2WSH".to_string());
+    lines.push(String::new());
+    for key in keys {
+        if !shared_path {
+            lines.push(format!("# derivation: {path}", path = key.path()));
+        }
+        lines.push(format!(
+            "{fingerprint}: {xpub}",
+            fingerprint = key.fingerprint.to_string().to_uppercase(),
+            xpub = key.xpub
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn jade_registration(
+    name: &str,
+    threshold: usize,
+    keys: &[CosignerKey],
+    network: Network,
+) -> JadeMultisigRegistration {
+    JadeMultisigRegistration {
+        network: network.to_string(),
+        multisig_name: name.to_string(),
+        descriptor: JadeMultisigDescriptor {
+            variant: "wsh(multi(k))".to_string(),
+            sorted: true,
+            threshold,
+            signers: keys
+                .iter()
+                .map(|key| JadeMultisigSigner {
+                    fingerprint: key.fingerprint.to_string(),
+                    derivation: key
+                        .derivation_path
+                        .into_iter()
+                        .map(|child| u32::from(*child))
+                        .collect(),
+                    xpub: key.xpub.to_string(),
+                    path: Vec::new(),
+                })
+                .collect(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "abandon ... about" test mnemonic, fingerprint 73c5da0a, and a second cosigner
+    const COSIGNER_A: &str = "[73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ";
+    const COSIGNER_B: &str = "[73c5da0a/48'/1'/1'/2']tpubDEYM1BmQ5rp2PWKvCgvQxNeUrEv8gu5819xRdmu6S23fYpS8x2icwAeoVaBTLyN3fGWJQcWoaiKMduTXWKtG9bXNpVrZPRF7XVxrANtAEcR";
+
+    #[test]
+    fn test_parse_cosigner_key() -> Result<()> {
+        let key = CosignerKey::from_str(COSIGNER_A)?;
+        assert_eq!(key.fingerprint.to_string(), "73c5da0a");
+        assert_eq!(key.path(), "m/48'/1'/0'/2'");
+        assert_eq!(key.descriptor_key(), format!("{COSIGNER_A}/<0;1>/*"));
+        assert_eq!(
+            CosignerKey::from_str(&format!("{COSIGNER_A}/<0;1>/*"))?,
+            key
+        );
+        assert_eq!(CosignerKey::from_str(&format!("{COSIGNER_A}/**"))?, key);
+
+        assert!(CosignerKey::from_str(&format!("{COSIGNER_A}/0/*")).is_err());
+        assert!(CosignerKey::from_str("tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cosigner_file() -> Result<()> {
+        // hw-export-descriptor output
+        let exported =
+            serde_json::json!({ "device": "jade", "key": format!("{COSIGNER_A}/<0;1>/*") });
+        assert_eq!(
+            parse_cosigner_file(&exported.to_string())?,
+            vec![CosignerKey::from_str(COSIGNER_A)?]
+        );
+
+        // Coldcard multisig export with a SLIP132 Zpub
+        let coldcard = serde_json::json!({
+            "xfp": "73C5DA0A",
+            "p2wsh_deriv": "m/48'/0'/0'/2'",
+            "p2wsh": "Zpub74Jru6aftwwHxCUCWEvP6DgrfFsdA4U6ZRtQ5i8qJpMcC39yZGv3egBhQfV3MS9pZtH5z8iV5qWkJsK6ESs6mSzt4qvGhzJxPeeVS2e1zUG",
+        });
+        let keys = parse_cosigner_file(&coldcard.to_string())?;
+        assert_eq!(
+            keys[0].xpub.to_string(),
+            "xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf"
+        );
+        assert_eq!(keys[0].path(), "m/48'/0'/0'/2'");
+
+        // One key per line, comments skipped
+        let keys = parse_cosigner_file(&format!("# cosigners\n{COSIGNER_A}\n\n{COSIGNER_B}\n"))?;
+        assert_eq!(keys.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_multisig_setup() -> Result<()> {
+        let keys = vec![
+            CosignerKey::from_str(COSIGNER_A)?,
+            CosignerKey::from_str(COSIGNER_B)?,
+        ];
+        let setup = build_multisig_setup("vault", 2, &keys, Network::Testnet)?;
+        assert!(setup.descriptor.starts_with(&format!(
+            "wsh(sortedmulti(2,{COSIGNER_A}/<0;1>/*,{COSIGNER_B}/<0;1>/*))#"
+        )));
+        assert!(
+            setup
+                .receive_descriptor
+                .contains(&format!("{COSIGNER_A}/0/*"))
+        );
+        assert!(
+            setup
+                .change_descriptor
+                .contains(&format!("{COSIGNER_B}/1/*"))
+        );
+
+        let bsms: Vec<&str> = setup.bsms.lines().collect();
+        assert_eq!(bsms[0], "BSMS 1.0");
+        assert_eq!(
+            bsms[1],
+            format!("wsh(sortedmulti(2,{COSIGNER_A}/**,{COSIGNER_B}/**))")
+        );
+        assert_eq!(bsms[2], "/0/*,/1/*");
+        assert_eq!(bsms[3], setup.first_address);
+
+        // Paths differ, so each key carries its own derivation
+        assert!(setup.coldcard_file.contains("Policy: 2 of 2\n"));
+        assert!(!setup.coldcard_file.contains("Derivation:"));
+        assert!(
+            setup
+                .coldcard_file
+                .contains("# derivation: m/48'/1'/1'/2'\n73C5DA0A: tpubDEYM1")
+        );
+
+        let signer = &setup.jade_registration.descriptor.signers[0];
+        assert_eq!(
+            signer.derivation,
+            vec![0x8000_0030, 0x8000_0001, 0x8000_0000, 0x8000_0002]
+        );
+        assert_eq!(setup.jade_registration.descriptor.threshold, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_multisig_setup_errors() -> Result<()> {
+        let a = CosignerKey::from_str(COSIGNER_A)?;
+        let b = CosignerKey::from_str(COSIGNER_B)?;
+        let keys = vec![a.clone(), b];
+        assert!(build_multisig_setup("vault", 3, &keys, Network::Testnet).is_err());
+        assert!(build_multisig_setup("vault", 0, &keys, Network::Testnet).is_err());
+        assert!(build_multisig_setup("cold storage 15", 2, &keys, Network::Testnet).is_ok());
+        assert!(build_multisig_setup("a name far too long", 2, &keys, Network::Testnet).is_err());
+        assert!(build_multisig_setup("vault", 2, &keys, Network::Bitcoin).is_err());
+        assert!(
+            build_multisig_setup("vault", 1, std::slice::from_ref(&a), Network::Testnet).is_err()
+        );
+        assert!(build_multisig_setup("vault", 2, &[a.clone(), a], Network::Testnet).is_err());
+        Ok(())
+    }
+}
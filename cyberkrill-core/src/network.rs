@@ -0,0 +1,168 @@
+//! Parsing Bitcoin network names
+//!
+//! Every command takes a `--network`, and configs, MCP arguments and REST
+//! queries carry the same string. They all go through [`parse_network`], which
+//! accepts bitcoind's names (`main`, `test`), the `bitcoin` crate's names
+//! (`bitcoin`, `testnet4`) and the common `mainnet`, plus a bech32 address or
+//! its human-readable part, so a network can be given as `bc` or copied from
+//! a mainnet or regtest address.
+//!
+//! Before a PSBT is built, [`check_transaction_network`] makes sure the output
+//! addresses and the wallet descriptor belong to the chosen network, so a
+//! mainnet address on `--network testnet` fails up front rather than deep in
+//! the backend.
+
+use crate::error::{CoreResult, bail};
+use anyhow::Context;
+use bitcoin::Network;
+use bitcoin::address::{Address, NetworkUnchecked};
+
+/// Parse a network name, bech32 HRP or bech32 address
+///
+/// Names are case-insensitive. Testnet3, testnet4 and signet all use the `tb`
+/// HRP, so `tb` and `tb1…` addresses are rejected rather than guessed: those
+/// networks must be named.
+pub fn parse_network(network: &str) -> CoreResult<Network> {
+    let name = network.trim().to_lowercase();
+    if let Some(network) = network_from_name(&name) {
+        return Ok(network);
+    }
+    // A bech32 string carries its HRP before the last '1' separator
+    let hrp = name.rsplit_once('1').map_or(name.as_str(), |(hrp, _)| hrp);
+    match hrp {
+        "bc" => Ok(Network::Bitcoin),
+        "bcrt" => Ok(Network::Regtest),
+        "tb" => bail!(
+            "Ambiguous network: {network}. The tb prefix is shared by testnet, testnet4 and signet; pass one of them by name"
+        ),
+        _ => bail!(
+            "Invalid network: {network}. Expected one of: mainnet, testnet, testnet4, signet, regtest"
+        ),
+    }
+}
+
+/// Check that `address` can receive coins on `network`
+pub fn check_address_network(address: &str, network: Network) -> CoreResult<()> {
+    let unchecked: Address<NetworkUnchecked> = address
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid address: {address}"))?;
+    if unchecked.is_valid_for_network(network) {
+        return Ok(());
+    }
+    // Testnet, testnet4 and signet share their address formats
+    let found = [Network::Bitcoin, Network::Testnet, Network::Regtest]
+        .into_iter()
+        .find(|candidate| unchecked.is_valid_for_network(*candidate))
+        .map_or("another network", |found| match found {
+            Network::Bitcoin => "mainnet",
+            Network::Regtest => "regtest",
+            _ => "testnet/signet",
+        });
+    bail!("Address {address} is for {found}, not {network}")
+}
+
+/// Check the output addresses and, when given, the wallet descriptor (whose
+/// keys also derive the change) of a transaction against `network`
+pub fn check_transaction_network<'a>(
+    addresses: impl IntoIterator<Item = &'a str>,
+    descriptor: Option<&str>,
+    network: Network,
+) -> CoreResult<()> {
+    for address in addresses {
+        check_address_network(address, network)?;
+    }
+    if let Some(descriptor) = descriptor {
+        crate::descriptor::check_descriptor_network(descriptor, network)?;
+    }
+    Ok(())
+}
+
+fn network_from_name(name: &str) -> Option<Network> {
+    match name {
+        "mainnet" | "bitcoin" | "main" => Some(Network::Bitcoin),
+        "testnet" | "testnet3" | "test" => Some(Network::Testnet),
+        "testnet4" => Some(Network::Testnet4),
+        "signet" => Some(Network::Signet),
+        "regtest" => Some(Network::Regtest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parse_network() -> Result<()> {
+        assert_eq!(parse_network("mainnet")?, Network::Bitcoin);
+        assert_eq!(parse_network("Bitcoin")?, Network::Bitcoin);
+        assert_eq!(parse_network("main")?, Network::Bitcoin);
+        assert_eq!(parse_network("testnet")?, Network::Testnet);
+        assert_eq!(parse_network("TESTNET4")?, Network::Testnet4);
+        assert_eq!(parse_network("signet")?, Network::Signet);
+        assert_eq!(parse_network(" regtest ")?, Network::Regtest);
+
+        // Bech32 HRPs and full addresses
+        assert_eq!(parse_network("bc")?, Network::Bitcoin);
+        assert_eq!(parse_network("bcrt")?, Network::Regtest);
+        assert_eq!(
+            parse_network("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")?,
+            Network::Bitcoin
+        );
+        assert_eq!(
+            parse_network("bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw")?,
+            Network::Regtest
+        );
+
+        // tb can't tell testnet3, testnet4 and signet apart
+        for ambiguous in ["tb", "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"] {
+            match parse_network(ambiguous) {
+                Ok(network) => anyhow::bail!("{ambiguous} parsed as {network}"),
+                Err(e) => assert!(
+                    e.to_string()
+                        .contains("shared by testnet, testnet4 and signet"),
+                    "{e}"
+                ),
+            }
+        }
+
+        assert!(parse_network("liquid").is_err());
+        assert!(parse_network("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_address_network() -> Result<()> {
+        let mainnet = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        let testnet = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        let regtest = "bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw";
+
+        check_address_network(mainnet, Network::Bitcoin)?;
+        check_address_network(testnet, Network::Testnet)?;
+        check_address_network(testnet, Network::Testnet4)?;
+        check_address_network(testnet, Network::Signet)?;
+        check_address_network(regtest, Network::Regtest)?;
+
+        let error = check_address_network(mainnet, Network::Testnet)
+            .expect_err("mainnet address on testnet")
+            .to_string();
+        assert_eq!(
+            error,
+            format!("Address {mainnet} is for mainnet, not testnet")
+        );
+        let error = check_address_network(testnet, Network::Regtest)
+            .expect_err("testnet address on regtest")
+            .to_string();
+        assert!(
+            error.contains("is for testnet/signet, not regtest"),
+            "{error}"
+        );
+
+        assert!(check_address_network("not-an-address", Network::Bitcoin).is_err());
+        check_transaction_network([mainnet], None, Network::Bitcoin)?;
+        assert!(check_transaction_network([mainnet, testnet], None, Network::Bitcoin).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,541 @@
+//! Watch-only wallet lifecycle on a Bitcoin Core node
+//!
+//! `onchain-wallet create` makes a blank descriptor wallet without private keys
+//! and imports the receive and change descriptors into it, rescanning from a
+//! chosen point in time. Rescans can take hours, so progress is polled from
+//! `getwalletinfo` while `importdescriptors` or `rescanblockchain` runs.
+
+use anyhow::{Context, Result};
+use bitcoin::Amount;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::bitcoin_rpc::BitcoinRpcClient;
+use crate::descriptor::expand_multipath_descriptor;
+use crate::error::{CoreResult, bail, ensure};
+
+/// Last derivation index imported for ranged descriptors unless told otherwise
+pub const DEFAULT_IMPORT_RANGE_END: u32 = 1000;
+
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How far back the node rescans for an imported descriptor's transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportTimestamp {
+    /// Only transactions from now on; no rescan
+    Now,
+    /// Rescan from this UNIX time (the node allows a 2 hour margin)
+    Time(i64),
+}
+
+impl ImportTimestamp {
+    /// Rescan the whole chain
+    pub const GENESIS: ImportTimestamp = ImportTimestamp::Time(0);
+
+    fn to_rpc(self) -> serde_json::Value {
+        match self {
+            ImportTimestamp::Now => "now".into(),
+            ImportTimestamp::Time(time) => time.into(),
+        }
+    }
+}
+
+impl FromStr for ImportTimestamp {
+    type Err = anyhow::Error;
+
+    /// Accepts `now`, `genesis`, a UNIX timestamp or a `YYYY-MM-DD` date
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "now" => Ok(ImportTimestamp::Now),
+            "genesis" => Ok(ImportTimestamp::GENESIS),
+            s => {
+                if let Ok(time) = s.parse::<i64>() {
+                    ensure!(time >= 0, "Rescan timestamp cannot be negative: {time}");
+                    return Ok(ImportTimestamp::Time(time));
+                }
+                let date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").with_context(|| {
+                    format!(
+                        "Invalid rescan start '{s}'. Expected now, genesis, a UNIX timestamp or YYYY-MM-DD"
+                    )
+                })?;
+                Ok(ImportTimestamp::Time(
+                    date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp(),
+                ))
+            }
+        }
+    }
+}
+
+/// A descriptor to import and whether it derives change addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorImport {
+    pub descriptor: String,
+    pub internal: bool,
+}
+
+impl DescriptorImport {
+    /// Receive and change imports for `descriptor`, splitting a two-path multipath
+    /// descriptor such as `<0;1>`; `change` overrides the change half
+    pub fn receive_and_change(
+        descriptor: &str,
+        change: Option<&str>,
+    ) -> CoreResult<Vec<DescriptorImport>> {
+        let paths = expand_multipath_descriptor(descriptor)?;
+        ensure!(
+            paths.len() <= 2,
+            "Expected at most two paths (receive and change), got {count}: {descriptor}",
+            count = paths.len()
+        );
+        let mut paths = paths.into_iter();
+        let receive = paths.next().context("Descriptor expanded to no paths")?;
+        let multipath_change = paths.next();
+        let mut imports = vec![DescriptorImport {
+            descriptor: receive,
+            internal: false,
+        }];
+        if let Some(change) = change.map(str::to_string).or(multipath_change) {
+            imports.push(DescriptorImport {
+                descriptor: change,
+                internal: true,
+            });
+        }
+        Ok(imports)
+    }
+}
+
+/// Outcome of one descriptor import
+#[derive(Debug, Clone, Serialize)]
+pub struct DescriptorImportResult {
+    /// Descriptor with checksum, as imported
+    pub descriptor: String,
+    pub internal: bool,
+    pub warnings: Vec<String>,
+}
+
+/// Block range covered by a completed `rescanblockchain`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RescanResult {
+    pub start_height: u32,
+    pub stop_height: u32,
+}
+
+/// Rescan in progress, as reported by `getwalletinfo`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScanProgress {
+    /// Seconds since the rescan started
+    pub duration: u64,
+    /// Fraction of blocks scanned, from 0 to 1
+    pub progress: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeWalletInfo {
+    #[serde(rename = "walletname")]
+    pub name: String,
+    pub descriptors: bool,
+    pub private_keys_enabled: bool,
+    #[serde(rename = "txcount")]
+    pub tx_count: u64,
+    #[serde(default, deserialize_with = "deserialize_scanning")]
+    pub scanning: Option<ScanProgress>,
+    /// Confirmed and pending balances in satoshis
+    #[serde(default)]
+    pub balances: Option<NodeWalletBalances>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct NodeWalletBalances {
+    pub trusted_sats: u64,
+    pub untrusted_pending_sats: u64,
+    pub immature_sats: u64,
+}
+
+/// `getwalletinfo` reports `"scanning": false` when no rescan is running
+fn deserialize_scanning<'de, D>(deserializer: D) -> Result<Option<ScanProgress>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        progress @ serde_json::Value::Object(_) => serde_json::from_value(progress)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeWalletBalances {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Mine {
+            trusted: f64,
+            untrusted_pending: f64,
+            immature: f64,
+        }
+
+        let mine = Mine::deserialize(deserializer)?;
+        let sats = |btc: f64| {
+            Amount::from_btc(btc)
+                .map(Amount::to_sat)
+                .map_err(serde::de::Error::custom)
+        };
+        Ok(Self {
+            trusted_sats: sats(mine.trusted)?,
+            untrusted_pending_sats: sats(mine.untrusted_pending)?,
+            immature_sats: sats(mine.immature)?,
+        })
+    }
+}
+
+impl BitcoinRpcClient {
+    /// Create a blank descriptor wallet without private keys (`createwallet`)
+    pub async fn create_watch_only_wallet(
+        &self,
+        name: &str,
+        load_on_startup: bool,
+    ) -> CoreResult<()> {
+        let result = self
+            .rpc_call(
+                "createwallet",
+                serde_json::json!({
+                    "wallet_name": name,
+                    "disable_private_keys": true,
+                    "blank": true,
+                    "descriptors": true,
+                    "load_on_startup": load_on_startup,
+                }),
+            )
+            .await
+            .with_context(|| format!("Failed to create wallet '{name}'"))?;
+        warn_rpc_warnings("createwallet", &result);
+        Ok(())
+    }
+
+    pub async fn load_wallet(&self, name: &str, load_on_startup: Option<bool>) -> CoreResult<()> {
+        let mut params = serde_json::json!({ "filename": name });
+        if let Some(load_on_startup) = load_on_startup {
+            params["load_on_startup"] = load_on_startup.into();
+        }
+        let result = self
+            .rpc_call("loadwallet", params)
+            .await
+            .with_context(|| format!("Failed to load wallet '{name}'"))?;
+        warn_rpc_warnings("loadwallet", &result);
+        Ok(())
+    }
+
+    pub async fn unload_wallet(&self, name: &str, load_on_startup: Option<bool>) -> CoreResult<()> {
+        let mut params = serde_json::json!({ "wallet_name": name });
+        if let Some(load_on_startup) = load_on_startup {
+            params["load_on_startup"] = load_on_startup.into();
+        }
+        let result = self
+            .rpc_call("unloadwallet", params)
+            .await
+            .with_context(|| format!("Failed to unload wallet '{name}'"))?;
+        warn_rpc_warnings("unloadwallet", &result);
+        Ok(())
+    }
+
+    /// `getwalletinfo` and `getbalances` of the selected wallet
+    pub async fn node_wallet_info(&self) -> CoreResult<NodeWalletInfo> {
+        let mut info: NodeWalletInfo = self.wallet_status().await?;
+        let balances = self.rpc_call("getbalances", serde_json::json!([])).await?;
+        info.balances = Some(
+            serde_json::from_value(balances["mine"].clone())
+                .context("Failed to parse getbalances response")?,
+        );
+        Ok(info)
+    }
+
+    async fn wallet_status(&self) -> Result<NodeWalletInfo> {
+        let result = self
+            .rpc_call("getwalletinfo", serde_json::json!([]))
+            .await?;
+        serde_json::from_value(result).context("Failed to parse getwalletinfo response")
+    }
+
+    /// Import watch-only descriptors into the selected wallet with `importdescriptors`.
+    ///
+    /// Ranged descriptors are imported for indexes `0..=range_end` and become the
+    /// wallet's active receive/change descriptors. While the rescan runs,
+    /// `on_progress` is called with the fraction of blocks scanned. Fails if any
+    /// descriptor is rejected.
+    pub async fn import_descriptors(
+        &self,
+        imports: &[DescriptorImport],
+        timestamp: ImportTimestamp,
+        range_end: u32,
+        active: bool,
+        on_progress: impl FnMut(ScanProgress),
+    ) -> CoreResult<Vec<DescriptorImportResult>> {
+        let mut requests = Vec::with_capacity(imports.len());
+        let mut imported = Vec::with_capacity(imports.len());
+        for import in imports {
+            let info = self
+                .rpc_call("getdescriptorinfo", serde_json::json!([import.descriptor]))
+                .await
+                .with_context(|| {
+                    format!(
+                        "Invalid descriptor: {descriptor}",
+                        descriptor = import.descriptor
+                    )
+                })?;
+            let descriptor = info["descriptor"]
+                .as_str()
+                .context("Failed to get descriptor with checksum")?
+                .to_string();
+            let mut request = serde_json::json!({
+                "desc": descriptor,
+                "timestamp": timestamp.to_rpc(),
+                "internal": import.internal,
+            });
+            if info["isrange"].as_bool().unwrap_or(false) {
+                request["range"] = serde_json::json!([0, range_end]);
+                request["active"] = active.into();
+            }
+            requests.push(request);
+            imported.push((descriptor, import.internal));
+        }
+
+        let response = self
+            .with_scan_progress(
+                self.rpc_call("importdescriptors", serde_json::json!([requests])),
+                on_progress,
+            )
+            .await?;
+        Ok(parse_import_response(&response, imported)?)
+    }
+
+    /// Rescan the selected wallet over blocks `from_height..=to_height` (to the tip
+    /// when `to_height` is not given), calling `on_progress` while it runs
+    pub async fn rescan_blockchain(
+        &self,
+        from_height: u32,
+        to_height: Option<u32>,
+        on_progress: impl FnMut(ScanProgress),
+    ) -> CoreResult<RescanResult> {
+        let mut params = vec![serde_json::json!(from_height)];
+        params.extend(to_height.map(serde_json::Value::from));
+        let result = self
+            .with_scan_progress(
+                self.rpc_call("rescanblockchain", serde_json::Value::Array(params)),
+                on_progress,
+            )
+            .await
+            .context("Rescan failed")?;
+        Ok(serde_json::from_value(result).context("Failed to parse rescanblockchain response")?)
+    }
+
+    /// Drive `call` while polling `getwalletinfo` for the progress of the rescan it runs
+    async fn with_scan_progress<T>(
+        &self,
+        call: impl Future<Output = CoreResult<T>>,
+        mut on_progress: impl FnMut(ScanProgress),
+    ) -> CoreResult<T> {
+        tokio::pin!(call);
+        let mut poll = tokio::time::interval(PROGRESS_POLL_INTERVAL);
+        // The first tick completes immediately
+        poll.tick().await;
+        loop {
+            tokio::select! {
+                result = &mut call => return result,
+                _ = poll.tick() => {
+                    // Polling is best effort: the call itself reports real failures
+                    if let Ok(NodeWalletInfo { scanning: Some(progress), .. }) =
+                        self.wallet_status().await
+                    {
+                        on_progress(progress);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Pair each `importdescriptors` result with its descriptor, failing on any error
+fn parse_import_response(
+    response: &serde_json::Value,
+    imported: Vec<(String, bool)>,
+) -> Result<Vec<DescriptorImportResult>> {
+    let results = response
+        .as_array()
+        .context("Unexpected importdescriptors response")?;
+    ensure!(
+        results.len() == imported.len(),
+        "importdescriptors returned {got} results for {expected} descriptors",
+        got = results.len(),
+        expected = imported.len()
+    );
+
+    let mut failures = Vec::new();
+    let mut outcomes = Vec::with_capacity(results.len());
+    for (result, (descriptor, internal)) in results.iter().zip(imported) {
+        if result["success"].as_bool() != Some(true) {
+            let message = result["error"]["message"]
+                .as_str()
+                .unwrap_or("unknown error");
+            failures.push(format!("{descriptor}: {message}"));
+            continue;
+        }
+        let warnings = result["warnings"]
+            .as_array()
+            .map(|warnings| {
+                warnings
+                    .iter()
+                    .filter_map(|w| w.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        outcomes.push(DescriptorImportResult {
+            descriptor,
+            internal,
+            warnings,
+        });
+    }
+    if !failures.is_empty() {
+        bail!(
+            "Descriptor import failed:\n{failures}",
+            failures = failures.join("\n")
+        );
+    }
+    Ok(outcomes)
+}
+
+fn warn_rpc_warnings(method: &str, result: &serde_json::Value) {
+    let warnings = match &result["warnings"] {
+        serde_json::Value::Array(warnings) => warnings.iter().filter_map(|w| w.as_str()).collect(),
+        serde_json::Value::String(warning) if !warning.is_empty() => vec![warning.as_str()],
+        _ => Vec::new(),
+    };
+    for warning in warnings {
+        tracing::warn!("{method}: {warning}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_options::ClientOptions;
+
+    #[test]
+    fn test_parse_import_timestamp() -> Result<()> {
+        assert_eq!(ImportTimestamp::from_str("now")?, ImportTimestamp::Now);
+        assert_eq!(
+            ImportTimestamp::from_str("genesis")?,
+            ImportTimestamp::Time(0)
+        );
+        assert_eq!(
+            ImportTimestamp::from_str("1700000000")?,
+            ImportTimestamp::Time(1_700_000_000)
+        );
+        assert_eq!(
+            ImportTimestamp::from_str("2024-01-01")?,
+            ImportTimestamp::Time(1_704_067_200)
+        );
+        assert!(ImportTimestamp::from_str("-5").is_err());
+        assert!(ImportTimestamp::from_str("yesterday").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive_and_change() -> Result<()> {
+        let imports =
+            DescriptorImport::receive_and_change("wpkh([d34db33f/84h/0h/0h]xpub/<0;1>/*)", None)?;
+        assert_eq!(imports.len(), 2);
+        assert_eq!(imports[0].descriptor, "wpkh([d34db33f/84h/0h/0h]xpub/0/*)");
+        assert!(!imports[0].internal);
+        assert_eq!(imports[1].descriptor, "wpkh([d34db33f/84h/0h/0h]xpub/1/*)");
+        assert!(imports[1].internal);
+
+        let imports = DescriptorImport::receive_and_change("wpkh(xpub/0/*)", None)?;
+        assert_eq!(imports.len(), 1);
+
+        let imports =
+            DescriptorImport::receive_and_change("wpkh(xpub/0/*)", Some("wpkh(xpub/1/*)"))?;
+        assert_eq!(imports[1].descriptor, "wpkh(xpub/1/*)");
+        assert!(imports[1].internal);
+
+        assert!(DescriptorImport::receive_and_change("wpkh(xpub/<0;1;2>/*)", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_import_response() -> Result<()> {
+        let imported = vec![
+            ("wpkh(xpub/0/*)#abc".to_string(), false),
+            ("wpkh(xpub/1/*)#def".to_string(), true),
+        ];
+        let response = serde_json::json!([
+            {"success": true, "warnings": ["Range not given, using default keypool range"]},
+            {"success": true},
+        ]);
+        let results = parse_import_response(&response, imported.clone())?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].warnings.len(), 1);
+        assert!(results[1].internal);
+
+        let response = serde_json::json!([
+            {"success": true},
+            {"success": false, "error": {"code": -4, "message": "Cannot import descriptor without private keys to a wallet with private keys enabled"}},
+        ]);
+        let error = parse_import_response(&response, imported)
+            .err()
+            .context("Rejected import should fail")?;
+        assert!(format!("{error}").contains("wpkh(xpub/1/*)#def: Cannot import"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_wallet_info() -> Result<()> {
+        let info: NodeWalletInfo = serde_json::from_value(serde_json::json!({
+            "walletname": "cold",
+            "walletversion": 169900,
+            "format": "sqlite",
+            "txcount": 12,
+            "keypoolsize": 3000,
+            "private_keys_enabled": false,
+            "avoid_reuse": false,
+            "scanning": {"duration": 42, "progress": 0.25},
+            "descriptors": true,
+        }))?;
+        assert_eq!(info.name, "cold");
+        assert_eq!(info.scanning.map(|s| s.progress), Some(0.25));
+
+        let info: NodeWalletInfo = serde_json::from_value(serde_json::json!({
+            "walletname": "cold",
+            "txcount": 0,
+            "private_keys_enabled": false,
+            "scanning": false,
+            "descriptors": true,
+        }))?;
+        assert!(info.scanning.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rescan_blockchain() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let rescan = server
+            .mock("POST", "/wallet/cold")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "method": "rescanblockchain",
+                "params": [680000, 700000],
+            })))
+            .with_body(r#"{"result":{"start_height":680000,"stop_height":700000},"error":null,"id":"cyberkrill"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = BitcoinRpcClient::new(server.url(), None, None, &ClientOptions::default())?
+            .with_wallet(Some("cold".to_string()));
+        let result = client
+            .rescan_blockchain(680_000, Some(700_000), |_| {})
+            .await?;
+        rescan.assert_async().await;
+        assert_eq!(result.start_height, 680_000);
+        assert_eq!(result.stop_height, 700_000);
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! Nostr keys derived from a BIP39 mnemonic (NIP-06)
+//!
+//! NIP-06 derives the key at `m/44'/1237'/<account>'/0/0`; the public key is
+//! its x-only (BIP340) form. Both are shown as hex and as NIP-19 bech32
+//! (`nsec`/`npub`).
+
+use crate::error::CoreResult;
+use anyhow::{Context, Result};
+use bech32::{Bech32, Hrp};
+use bitcoin::Network;
+use bitcoin::bip32::{DerivationPath, Xpriv};
+use bitcoin::secp256k1::Secp256k1;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// SLIP-44 coin type registered for Nostr
+const NOSTR_COIN_TYPE: u32 = 1237;
+
+/// Nostr key pair derived per NIP-06
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NostrKeys {
+    pub account: u32,
+    pub derivation_path: String,
+    pub private_key_hex: String,
+    pub nsec: String,
+    /// x-only public key
+    pub public_key_hex: String,
+    pub npub: String,
+}
+
+/// Derive the NIP-06 Nostr keys of `account` from a BIP39 mnemonic and its
+/// optional passphrase
+pub fn derive_nostr_keys(mnemonic: &str, passphrase: &str, account: u32) -> CoreResult<NostrKeys> {
+    let mnemonic =
+        bip39::Mnemonic::parse_normalized(mnemonic.trim()).context("Invalid BIP39 mnemonic")?;
+    let seed = mnemonic.to_seed_normalized(passphrase);
+
+    let secp = Secp256k1::new();
+    // The network only affects xprv serialization, which is not exposed
+    let master =
+        Xpriv::new_master(Network::Bitcoin, &seed).context("Failed to derive master key")?;
+    let path = format!("m/44'/{NOSTR_COIN_TYPE}'/{account}'/0/0");
+    let derivation_path = DerivationPath::from_str(&path)
+        .with_context(|| format!("Invalid account index {account}"))?;
+    let xpriv = master
+        .derive_priv(&secp, &derivation_path)
+        .context("Failed to derive Nostr key")?;
+
+    let secret_key = xpriv.private_key;
+    let (public_key, _parity) = secret_key.x_only_public_key(&secp);
+    let secret_bytes = secret_key.secret_bytes();
+    let public_bytes = public_key.serialize();
+
+    Ok(NostrKeys {
+        account,
+        derivation_path: path,
+        private_key_hex: hex::encode(secret_bytes),
+        nsec: encode_nip19("nsec", &secret_bytes)?,
+        public_key_hex: hex::encode(public_bytes),
+        npub: encode_nip19("npub", &public_bytes)?,
+    })
+}
+
+/// NIP-19 bech32 encoding of a 32-byte key
+fn encode_nip19(hrp: &str, key: &[u8]) -> Result<String> {
+    let hrp = Hrp::parse(hrp).with_context(|| format!("Invalid bech32 prefix {hrp}"))?;
+    bech32::encode::<Bech32>(hrp, key).context("Failed to encode bech32 key")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nip06_vector() -> Result<()> {
+        let keys = derive_nostr_keys(
+            "leader monkey parrot ring guide accident before fence cannon height naive bean",
+            "",
+            0,
+        )?;
+        assert_eq!(keys.derivation_path, "m/44'/1237'/0'/0/0");
+        assert_eq!(
+            keys.private_key_hex,
+            "7f7ff03d123792d6ac594bfa67bf6d0c0ab55b6b1fdb6249303fe861f1ccba9a"
+        );
+        assert_eq!(
+            keys.nsec,
+            "nsec10allq0gjx7fddtzef0ax00mdps9t2kmtrldkyjfs8l5xruwvh2dq0lhhkp"
+        );
+        assert_eq!(
+            keys.public_key_hex,
+            "17162c921dc4d2518f9a101db33695df1afb56ab82f5ff3e5da6eec3ca5cd917"
+        );
+        assert_eq!(
+            keys.npub,
+            "npub1zutzeysacnf9rru6zqwmxd54mud0k44tst6l70ja5mhv8jjumytsd2x7nu"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_accounts_and_passphrase_differ() -> Result<()> {
+        let mnemonic =
+            "leader monkey parrot ring guide accident before fence cannon height naive bean";
+        let base = derive_nostr_keys(mnemonic, "", 0)?;
+        let account = derive_nostr_keys(mnemonic, "", 1)?;
+        let passphrase = derive_nostr_keys(mnemonic, "secret", 0)?;
+        assert_eq!(account.derivation_path, "m/44'/1237'/1'/0/0");
+        assert_ne!(base.npub, account.npub);
+        assert_ne!(base.npub, passphrase.npub);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_mnemonic() {
+        assert!(derive_nostr_keys("leader monkey parrot", "", 0).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Ordering the inputs and outputs of a built PSBT
+//!
+//! Left alone, the builders order transactions differently: BDK shuffles,
+//! `createpsbt` follows its (sorted) output object and `walletcreatefundedpsbt`
+//! puts the change at a random position. With `--output-order` every builder
+//! reorders the finished PSBT the same way, and reports where the change ended up.
+
+use bitcoin::ScriptBuf;
+use bitcoin::hashes::Hash;
+use bitcoin::psbt::Psbt;
+use rand::seq::SliceRandom;
+use strum::{Display, EnumString};
+
+/// Order of the inputs and outputs of a built transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum OutputOrder {
+    /// BIP69: inputs by previous txid and index, outputs by amount and script
+    Bip69,
+    /// Inputs and outputs shuffled, so their positions say nothing about the wallet
+    Random,
+    /// Outputs in the order the recipients were given, then change and data;
+    /// inputs as selected
+    AsGiven,
+}
+
+impl OutputOrder {
+    /// Reorder `psbt`, whose recipients were given as `recipients`. The
+    /// unsigned transaction and the PSBT maps are permuted together.
+    pub fn apply(self, psbt: &mut Psbt, recipients: &[ScriptBuf]) {
+        let mut inputs: Vec<_> = psbt
+            .unsigned_tx
+            .input
+            .drain(..)
+            .zip(psbt.inputs.drain(..))
+            .collect();
+        let mut outputs: Vec<_> = psbt
+            .unsigned_tx
+            .output
+            .drain(..)
+            .zip(psbt.outputs.drain(..))
+            .collect();
+
+        match self {
+            OutputOrder::Bip69 => {
+                // Txids compare in their displayed (reversed) byte order
+                inputs.sort_by_key(|(txin, _)| {
+                    let mut txid = txin.previous_output.txid.to_byte_array();
+                    txid.reverse();
+                    (txid, txin.previous_output.vout)
+                });
+                outputs.sort_by(|(a, _), (b, _)| {
+                    a.value
+                        .cmp(&b.value)
+                        .then_with(|| a.script_pubkey.as_bytes().cmp(b.script_pubkey.as_bytes()))
+                });
+            }
+            OutputOrder::Random => {
+                let mut rng = rand::rng();
+                inputs.shuffle(&mut rng);
+                outputs.shuffle(&mut rng);
+            }
+            OutputOrder::AsGiven => {
+                outputs.sort_by_key(|(txout, _)| {
+                    recipients
+                        .iter()
+                        .position(|script| *script == txout.script_pubkey)
+                        .unwrap_or(usize::MAX)
+                });
+            }
+        }
+
+        for (txin, input) in inputs {
+            psbt.unsigned_tx.input.push(txin);
+            psbt.inputs.push(input);
+        }
+        for (txout, output) in outputs {
+            psbt.unsigned_tx.output.push(txout);
+            psbt.outputs.push(output);
+        }
+    }
+}
+
+/// Apply `order` (when given) to `psbt` and return the new position of the
+/// output that was at `change_position`
+pub fn reorder_psbt(
+    psbt: &mut Psbt,
+    order: Option<OutputOrder>,
+    recipients: &[ScriptBuf],
+    change_position: Option<u32>,
+) -> Option<u32> {
+    let Some(order) = order else {
+        return change_position;
+    };
+    let change = change_position
+        .and_then(|position| psbt.unsigned_tx.output.get(position as usize))
+        .cloned();
+    order.apply(psbt, recipients);
+    let change = change?;
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .position(|txout| *txout == change)
+        .and_then(|position| u32::try_from(position).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{Amount, OutPoint, Transaction, TxIn, TxOut, Txid};
+    use std::str::FromStr;
+
+    fn script(byte: u8) -> ScriptBuf {
+        ScriptBuf::from_bytes(vec![0x00, 0x14, byte])
+    }
+
+    fn psbt() -> anyhow::Result<Psbt> {
+        let input = |txid: &str, vout| -> anyhow::Result<TxIn> {
+            Ok(TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_str(txid)?,
+                    vout,
+                },
+                ..Default::default()
+            })
+        };
+        let output = |sats, byte| TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey: script(byte),
+        };
+        Ok(Psbt::from_unsigned_tx(Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![
+                input(
+                    "ff00000000000000000000000000000000000000000000000000000000000000",
+                    0,
+                )?,
+                input(
+                    "0a00000000000000000000000000000000000000000000000000000000000001",
+                    1,
+                )?,
+                input(
+                    "0a00000000000000000000000000000000000000000000000000000000000001",
+                    0,
+                )?,
+            ],
+            output: vec![output(5_000, 3), output(1_000, 2), output(5_000, 1)],
+        })?)
+    }
+
+    fn values(psbt: &Psbt) -> Vec<(u64, u8)> {
+        psbt.unsigned_tx
+            .output
+            .iter()
+            .map(|txout| (txout.value.to_sat(), txout.script_pubkey.as_bytes()[2]))
+            .collect()
+    }
+
+    #[test]
+    fn test_output_order() -> anyhow::Result<()> {
+        let mut bip69 = psbt()?;
+        let change = reorder_psbt(
+            &mut bip69,
+            Some(OutputOrder::from_str("BIP69")?),
+            &[],
+            Some(0),
+        );
+        assert_eq!(values(&bip69), [(1_000, 2), (5_000, 1), (5_000, 3)]);
+        assert_eq!(change, Some(2));
+        let inputs: Vec<(String, u32)> = bip69
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| {
+                (
+                    txin.previous_output.txid.to_string()[..2].to_string(),
+                    txin.previous_output.vout,
+                )
+            })
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                ("0a".to_string(), 0),
+                ("0a".to_string(), 1),
+                ("ff".to_string(), 0)
+            ]
+        );
+        assert_eq!(bip69.inputs.len(), 3);
+        assert_eq!(bip69.outputs.len(), 3);
+
+        // Recipients in the given order, anything else (change) after them
+        let mut as_given = psbt()?;
+        let change = reorder_psbt(
+            &mut as_given,
+            Some(OutputOrder::AsGiven),
+            &[script(1), script(2)],
+            Some(0),
+        );
+        assert_eq!(values(&as_given), [(5_000, 1), (1_000, 2), (5_000, 3)]);
+        assert_eq!(change, Some(2));
+
+        let mut random = psbt()?;
+        let change = reorder_psbt(&mut random, Some(OutputOrder::Random), &[], Some(1));
+        let mut shuffled = values(&random);
+        assert_eq!(
+            change.map(|position| shuffled[position as usize]),
+            Some((1_000, 2))
+        );
+        shuffled.sort();
+        assert_eq!(shuffled, [(1_000, 2), (5_000, 1), (5_000, 3)]);
+
+        let mut untouched = psbt()?;
+        assert_eq!(reorder_psbt(&mut untouched, None, &[], Some(1)), Some(1));
+        assert_eq!(untouched, psbt()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! On-disk cache of daily BTC prices, shared by the fiat-aware commands
+//!
+//! Prices are kept in a sqlite database, one row per currency and day. The price
+//! of a day that is over never changes, so it is kept until pruned; today's price
+//! (also what "current price" lookups use) expires after the configured TTL.
+//!
+//! With offline mode on, a price missing from the cache is an error instead of a
+//! request to a price provider.
+
+use crate::error::{CoreResult, bail};
+use anyhow::{Context, Result};
+use bdk_wallet::rusqlite::{Connection, OptionalExtension, params};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How long today's price is reused before it is fetched again
+pub const DEFAULT_PRICE_CACHE_TTL: Duration = Duration::from_secs(3600);
+/// File name of the cache when a directory is given
+pub const PRICE_CACHE_FILE: &str = "prices.sqlite";
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS prices (
+    currency TEXT NOT NULL,
+    date TEXT NOT NULL,
+    price REAL NOT NULL,
+    source TEXT NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    PRIMARY KEY (currency, date)
+)";
+
+#[derive(Debug)]
+pub struct PriceCache {
+    path: PathBuf,
+    ttl: Duration,
+    connection: Mutex<Connection>,
+}
+
+/// Entries of one currency in the cache
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrencyCacheStatus {
+    pub currency: String,
+    pub entries: u64,
+    pub first_date: String,
+    pub last_date: String,
+}
+
+/// Summary of the cache contents
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceCacheStatus {
+    pub path: String,
+    pub size_bytes: u64,
+    pub ttl_seconds: u64,
+    pub entries: u64,
+    /// Prices of days not over yet that are older than the TTL
+    pub expired: u64,
+    pub currencies: Vec<CurrencyCacheStatus>,
+}
+
+impl PriceCache {
+    /// Open (or create) the cache at `path`; a directory gets a `prices.sqlite`
+    /// inside it
+    pub fn open(path: &Path, ttl: Duration) -> CoreResult<Self> {
+        let path = if path.is_dir() {
+            path.join(PRICE_CACHE_FILE)
+        } else {
+            path.to_path_buf()
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!(
+                    "Failed to create price cache directory {dir}",
+                    dir = parent.display()
+                )
+            })?;
+        }
+        let connection = Connection::open(&path)
+            .with_context(|| format!("Failed to open price cache {path}", path = path.display()))?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create the price cache schema")?;
+        Ok(Self {
+            path,
+            ttl,
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Cached price of BTC in `currency` on `date`, unless it is today's and
+    /// older than the TTL
+    pub fn price(&self, currency: &str, date: NaiveDate) -> Option<f64> {
+        let connection = self.connection.lock().ok()?;
+        let row: Option<(f64, i64)> = connection
+            .query_row(
+                "SELECT price, fetched_at FROM prices WHERE currency = ?1 AND date = ?2",
+                params![currency.to_lowercase(), date.to_string()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .unwrap_or_else(|e| {
+                warn!("Failed to read the price cache: {e}");
+                None
+            });
+        let (price, fetched_at) = row?;
+        if date >= today() && now() - fetched_at > self.ttl_seconds() {
+            debug!("Cached {currency} price for {date} expired");
+            return None;
+        }
+        Some(price)
+    }
+
+    /// Cache the price of BTC in `currency` on `date`. Failures are logged, a
+    /// cache that can't be written only costs another request next time.
+    pub fn store(&self, currency: &str, date: NaiveDate, price: f64, source: &str) {
+        let Ok(connection) = self.connection.lock() else {
+            return;
+        };
+        if let Err(e) = connection.execute(
+            "INSERT OR REPLACE INTO prices (currency, date, price, source, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                currency.to_lowercase(),
+                date.to_string(),
+                price,
+                source,
+                now()
+            ],
+        ) {
+            warn!("Failed to write the price cache: {e}");
+        }
+    }
+
+    pub fn status(&self) -> CoreResult<PriceCacheStatus> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Price cache lock poisoned"))?;
+        let mut statement = connection.prepare(
+            "SELECT currency, COUNT(*), MIN(date), MAX(date) FROM prices
+             GROUP BY currency ORDER BY currency",
+        )?;
+        let currencies = statement
+            .query_map([], |row| {
+                Ok(CurrencyCacheStatus {
+                    currency: row.get(0)?,
+                    entries: row.get(1)?,
+                    first_date: row.get(2)?,
+                    last_date: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to read the price cache")?;
+        let expired: u64 = connection.query_row(
+            "SELECT COUNT(*) FROM prices WHERE date >= ?1 AND fetched_at < ?2",
+            params![today().to_string(), now() - self.ttl_seconds()],
+            |row| row.get(0),
+        )?;
+        Ok(PriceCacheStatus {
+            path: self.path.display().to_string(),
+            size_bytes: std::fs::metadata(&self.path)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default(),
+            ttl_seconds: self.ttl.as_secs(),
+            entries: currencies.iter().map(|currency| currency.entries).sum(),
+            expired,
+            currencies,
+        })
+    }
+
+    /// Remove expired prices and, with `before`, every price of an earlier day;
+    /// `currency` limits both to that currency. Returns the number removed.
+    pub fn prune(&self, before: Option<NaiveDate>, currency: Option<&str>) -> CoreResult<usize> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Price cache lock poisoned"))?;
+        let currency = currency.map(str::to_lowercase);
+        let mut removed = connection.execute(
+            "DELETE FROM prices WHERE date >= ?1 AND fetched_at < ?2
+             AND (?3 IS NULL OR currency = ?3)",
+            params![today().to_string(), now() - self.ttl_seconds(), currency],
+        )?;
+        if let Some(before) = before {
+            removed += connection.execute(
+                "DELETE FROM prices WHERE date < ?1 AND (?2 IS NULL OR currency = ?2)",
+                params![before.to_string(), currency],
+            )?;
+        }
+        connection
+            .execute_batch("VACUUM")
+            .context("Failed to compact the price cache")?;
+        Ok(removed)
+    }
+
+    fn ttl_seconds(&self) -> i64 {
+        i64::try_from(self.ttl.as_secs()).unwrap_or(i64::MAX)
+    }
+}
+
+/// Error for a price that would need the network in offline mode
+pub fn ensure_online(offline: bool, currency: &str, date: NaiveDate) -> CoreResult<()> {
+    if offline {
+        bail!(
+            "No cached {currency} price for {date} and offline mode is on",
+            currency = currency.to_uppercase()
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn today() -> NaiveDate {
+    chrono::Utc::now().date_naive()
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_price_cache_roundtrip_and_ttl() -> Result<()> {
+        let dir = TempDir::new()?;
+        let cache = PriceCache::open(dir.path(), Duration::from_secs(3600))?;
+        assert_eq!(cache.path(), dir.path().join(PRICE_CACHE_FILE));
+
+        let day = NaiveDate::from_ymd_opt(2024, 1, 15).context("date")?;
+        assert_eq!(cache.price("usd", day), None);
+        cache.store("USD", day, 42000.0, "kraken");
+        assert_eq!(cache.price("usd", day), Some(42000.0));
+        assert_eq!(cache.price("eur", day), None);
+
+        cache.store("usd", today(), 100000.0, "coingecko");
+        assert_eq!(cache.price("usd", today()), Some(100000.0));
+        let expired = PriceCache::open(dir.path(), Duration::ZERO)?;
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(expired.price("usd", today()), None);
+        // Closed days never expire
+        assert_eq!(expired.price("usd", day), Some(42000.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_cache_status_and_prune() -> Result<()> {
+        let dir = TempDir::new()?;
+        let cache = PriceCache::open(&dir.path().join("cache.sqlite"), Duration::from_secs(3600))?;
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).context("date");
+        cache.store("usd", day(1)?, 44000.0, "kraken");
+        cache.store("usd", day(2)?, 45000.0, "kraken");
+        cache.store("eur", day(2)?, 41000.0, "kraken");
+
+        let status = cache.status()?;
+        assert_eq!(status.entries, 3);
+        assert_eq!(status.expired, 0);
+        assert_eq!(status.currencies[1].currency, "usd");
+        assert_eq!(status.currencies[1].first_date, "2024-01-01");
+
+        assert_eq!(cache.prune(Some(day(2)?), Some("usd"))?, 1);
+        assert_eq!(cache.price("usd", day(1)?), None);
+        assert_eq!(cache.prune(Some(day(3)?), None)?, 2);
+        assert_eq!(cache.status()?.entries, 0);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
-use anyhow::{Context, bail};
+use anyhow::Context;
 use reqwest::Client;
 use serde::Serialize;
 use serde_json::Value;
 use std::time::Duration;
 use tokio::task::JoinSet;
 
+use crate::client_options::ClientOptions;
+use crate::error::{CoreResult, bail};
+use crate::price_cache::{ensure_online, today};
+
 type FeedResponse = (&'static str, anyhow::Result<Option<PriceQuote>>);
 
 const MILLISATS_PER_BTC: f64 = 100_000_000_000.0;
@@ -37,8 +41,8 @@ pub struct PriceQuote {
 
 impl BtcPrice {
     /// Convert a fiat amount using this BTC price.
-    pub fn amount_to_btc(&self, amount: f64) -> anyhow::Result<crate::AmountInput> {
-        fiat_amount_to_btc_amount(amount, self.price_per_btc)
+    pub fn amount_to_btc(&self, amount: f64) -> CoreResult<crate::AmountInput> {
+        Ok(fiat_amount_to_btc_amount(amount, self.price_per_btc)?)
     }
 }
 
@@ -52,13 +56,29 @@ fn normalize_fiat_currency(currency: &str) -> anyhow::Result<String> {
 
 /// Fetch BTC price for `currency` from all configured feeds in parallel and return the median.
 ///
-/// This contacts third-party HTTPS price feeds and does not reuse Bitcoin Core proxy settings.
+/// This contacts third-party HTTPS price feeds, through the proxy of `options` if any.
 ///
 /// Errors if fewer than 3 feeds succeed (the 3-source quorum makes the median
 /// a true middle value, so a single bad/poisoned feed cannot swing the result).
-pub async fn fetch_btc_price(currency: &str) -> anyhow::Result<BtcPrice> {
+///
+/// With a price cache in `options`, a median fetched within its TTL is reused.
+pub async fn fetch_btc_price(currency: &str, options: &ClientOptions) -> CoreResult<BtcPrice> {
     let currency = normalize_fiat_currency(currency)?;
-    let client = Client::builder()
+    let cache = options.price_cache.as_deref();
+    let today = today();
+    if let Some(price_per_btc) = cache.and_then(|cache| cache.price(&currency, today)) {
+        return Ok(BtcPrice {
+            currency,
+            price_per_btc,
+            sources: vec![PriceQuote {
+                source: "cache",
+                price_per_btc,
+            }],
+        });
+    }
+    ensure_online(options.offline, &currency, today)?;
+    let client = options
+        .http_client_builder()
         .timeout(Duration::from_secs(8))
         .user_agent(concat!("cyberkrill/", env!("CARGO_PKG_VERSION")))
         .build()
@@ -119,8 +139,11 @@ pub async fn fetch_btc_price(currency: &str) -> anyhow::Result<BtcPrice> {
 
     let FeedCollection { sources, issues } = collect_feed_quotes(feeds, &currency).await;
     let result = aggregate_btc_price(currency.clone(), sources);
+    if let (Ok(price), Some(cache)) = (&result, cache) {
+        cache.store(&currency, today, price.price_per_btc, "median");
+    }
     if issues.is_empty() {
-        result
+        Ok(result?)
     } else {
         let issue_summary = format!(
             "BTC price feed issues for {currency}: {issues}",
@@ -132,7 +155,7 @@ pub async fn fetch_btc_price(currency: &str) -> anyhow::Result<BtcPrice> {
                 eprintln!("[price-feed] {issue_summary}");
                 Ok(price)
             }
-            Err(error) => Err(error).context(issue_summary),
+            Err(error) => Err(error.context(issue_summary).into()),
         }
     }
 }
@@ -1223,7 +1246,7 @@ mod tests {
     #[tokio::test]
     #[ignore]
     async fn live_fetch_btc_price() -> anyhow::Result<()> {
-        let price = fetch_btc_price("USD").await?;
+        let price = fetch_btc_price("USD", &ClientOptions::default()).await?;
         println!(
             "BTC/{currency} median = {price_per_btc} from {source_count} feeds: {sources}",
             currency = price.currency,
@@ -0,0 +1,593 @@
+//! Historical BTC prices for reports
+//!
+//! [`PriceProvider`] implementations return the BTC price in a fiat currency
+//! at the start of a UTC day. [`PriceSource`] picks one of them, or `auto` to
+//! try them in turn: a provider that is rate limited (HTTP 429) is skipped for
+//! the rest of the run, and one without a quote for the day falls through to
+//! the next.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveTime};
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::client_options::ClientOptions;
+use crate::error::{CoreResult, bail, ensure};
+use crate::price_cache::{ensure_online, today};
+use crate::retry::{http_status, status_error};
+
+/// Delay before each CoinGecko request, to stay under its keyless rate limit
+const COINGECKO_REQUEST_DELAY: Duration = Duration::from_millis(300);
+/// Currencies quoted by the mempool.space historical price API
+const MEMPOOL_CURRENCIES: [&str; 7] = ["USD", "EUR", "GBP", "CAD", "CHF", "AUD", "JPY"];
+
+/// Source of daily historical BTC prices
+#[async_trait]
+pub trait PriceProvider: Send + Sync {
+    /// Short provider name for logs and reports
+    fn name(&self) -> &'static str;
+
+    /// Price of 1 BTC in `currency` at 00:00 UTC on `date`, or `None` when the
+    /// provider has no quote for that day or currency
+    async fn historical_price(&self, date: NaiveDate, currency: &str) -> CoreResult<Option<f64>>;
+}
+
+/// BTC price in a fiat currency on one day
+#[derive(Debug, Clone, Serialize)]
+pub struct BtcPricePoint {
+    pub currency: String,
+    pub date: String, // YYYY-MM-DD format
+    pub price_per_btc: f64,
+    /// Satoshis one unit of the currency buys
+    pub sats_per_unit: f64,
+    pub source: String,
+}
+
+impl BtcPricePoint {
+    /// Value of `sats` in the currency, rounded to cents
+    pub fn fiat_value(&self, sats: u64) -> f64 {
+        (sats as f64 / 100_000_000.0 * self.price_per_btc * 100.0).round() / 100.0
+    }
+}
+
+/// Price of BTC in `currency` on `date` (today when `None`) from `source`,
+/// through the price cache of `options`
+pub async fn btc_price(
+    currency: &str,
+    date: Option<NaiveDate>,
+    source: PriceSource,
+    options: &ClientOptions,
+) -> CoreResult<BtcPricePoint> {
+    let today = today();
+    let date = date.unwrap_or(today);
+    ensure!(
+        date <= today,
+        "No BTC price for {date}, it is in the future"
+    );
+    let provider = source.provider(options)?;
+    let price = cached_price(date, currency, options, provider.as_ref())
+        .await?
+        .with_context(|| {
+            format!(
+                "No {code} price for {date} from {source}",
+                code = currency.to_uppercase()
+            )
+        })?;
+    Ok(BtcPricePoint {
+        currency: currency.to_uppercase(),
+        date: date.to_string(),
+        price_per_btc: price,
+        sats_per_unit: 100_000_000.0 / price,
+        source: source.to_string(),
+    })
+}
+
+/// Price from the price cache of `options` when it has one, otherwise from
+/// `provider` (and then cached)
+pub(crate) async fn cached_price(
+    date: NaiveDate,
+    currency: &str,
+    options: &ClientOptions,
+    provider: &dyn PriceProvider,
+) -> Result<Option<f64>> {
+    let cache = options.price_cache.as_deref();
+    if let Some(price) = cache.and_then(|cache| cache.price(currency, date)) {
+        debug!(
+            "Cache hit for {currency} on {date}: {price} {code}",
+            code = currency.to_uppercase()
+        );
+        return Ok(Some(price));
+    }
+    ensure_online(options.offline, currency, date)?;
+
+    let price = provider.historical_price(date, currency).await?;
+    match price {
+        Some(price) => {
+            info!(
+                "Fetched price for {currency} on {date} from {provider}: {price} {code}",
+                provider = provider.name(),
+                code = currency.to_uppercase()
+            );
+            if let Some(cache) = cache {
+                cache.store(currency, date, price, provider.name());
+            }
+        }
+        None => warn!(
+            "No {currency} price for {date} from {provider}",
+            provider = provider.name()
+        ),
+    }
+    Ok(price)
+}
+
+/// Price provider selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PriceSource {
+    /// CoinGecko, then Kraken, then mempool.space
+    #[default]
+    Auto,
+    CoinGecko,
+    Kraken,
+    Mempool,
+}
+
+impl PriceSource {
+    /// Provider for this source, with a fallback chain for `auto`
+    pub fn provider(self, options: &ClientOptions) -> CoreResult<Box<dyn PriceProvider>> {
+        let client = options
+            .http_client_builder()
+            .timeout(Duration::from_secs(30))
+            .user_agent(concat!("cyberkrill/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .context("Invalid price history client configuration")?;
+        Ok(match self {
+            Self::Auto => Box::new(FallbackPriceProvider::new(vec![
+                Box::new(CoinGeckoPrices::new(client.clone())),
+                Box::new(KrakenPrices::new(client.clone())),
+                Box::new(MempoolPrices::new(client)),
+            ])),
+            Self::CoinGecko => Box::new(CoinGeckoPrices::new(client)),
+            Self::Kraken => Box::new(KrakenPrices::new(client)),
+            Self::Mempool => Box::new(MempoolPrices::new(client)),
+        })
+    }
+}
+
+impl FromStr for PriceSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "coingecko" => Ok(Self::CoinGecko),
+            "kraken" => Ok(Self::Kraken),
+            "mempool" | "mempool.space" => Ok(Self::Mempool),
+            _ => bail!("Unknown price source '{s}': expected auto, coingecko, kraken or mempool"),
+        }
+    }
+}
+
+impl fmt::Display for PriceSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::CoinGecko => "coingecko",
+            Self::Kraken => "kraken",
+            Self::Mempool => "mempool",
+        })
+    }
+}
+
+/// Tries providers in order, skipping those that were rate limited
+pub struct FallbackPriceProvider {
+    providers: Vec<(Box<dyn PriceProvider>, AtomicBool)>,
+}
+
+impl FallbackPriceProvider {
+    pub fn new(providers: Vec<Box<dyn PriceProvider>>) -> Self {
+        Self {
+            providers: providers
+                .into_iter()
+                .map(|provider| (provider, AtomicBool::new(false)))
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for FallbackPriceProvider {
+    fn name(&self) -> &'static str {
+        "auto"
+    }
+
+    async fn historical_price(&self, date: NaiveDate, currency: &str) -> CoreResult<Option<f64>> {
+        let mut failures = Vec::new();
+        for (provider, rate_limited) in &self.providers {
+            if rate_limited.load(Ordering::Relaxed) {
+                continue;
+            }
+            match provider.historical_price(date, currency).await {
+                Ok(Some(price)) => return Ok(Some(price)),
+                Ok(None) => debug!(
+                    "{name} has no {currency} price for {date}",
+                    name = provider.name()
+                ),
+                Err(e) if http_status(e.inner()) == Some(StatusCode::TOO_MANY_REQUESTS) => {
+                    warn!(
+                        "{name} is rate limited, using the next price source",
+                        name = provider.name()
+                    );
+                    rate_limited.store(true, Ordering::Relaxed);
+                    failures.push(format!("{name}: {e:#}", name = provider.name()));
+                }
+                Err(e) => {
+                    warn!("{name} price request failed: {e:#}", name = provider.name());
+                    failures.push(format!("{name}: {e:#}", name = provider.name()));
+                }
+            }
+        }
+        if failures.is_empty() {
+            Ok(None)
+        } else {
+            bail!(
+                "No price source returned a {currency} price for {date}: {failures}",
+                failures = failures.join("; ")
+            )
+        }
+    }
+}
+
+/// CoinGecko `coins/bitcoin/history` (daily snapshot at 00:00 UTC)
+pub struct CoinGeckoPrices {
+    client: Client,
+}
+
+impl CoinGeckoPrices {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for CoinGeckoPrices {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    async fn historical_price(&self, date: NaiveDate, currency: &str) -> CoreResult<Option<f64>> {
+        let url = format!(
+            "https://api.coingecko.com/api/v3/coins/bitcoin/history?date={date}&localization=false",
+            date = date.format("%d-%m-%Y")
+        );
+        tokio::time::sleep(COINGECKO_REQUEST_DELAY).await;
+        let body = get_text(&self.client, &url, "CoinGecko").await?;
+        Ok(parse_coingecko_history(&body, currency)?)
+    }
+}
+
+/// Kraken daily OHLC candles (opening price of the day)
+pub struct KrakenPrices {
+    client: Client,
+}
+
+impl KrakenPrices {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for KrakenPrices {
+    fn name(&self) -> &'static str {
+        "kraken"
+    }
+
+    async fn historical_price(&self, date: NaiveDate, currency: &str) -> CoreResult<Option<f64>> {
+        let timestamp = day_start(date);
+        // `since` is exclusive; Kraken keeps only the latest 720 candles
+        let url = format!(
+            "https://api.kraken.com/0/public/OHLC?pair=XBT{currency}&interval=1440&since={since}",
+            currency = currency.to_uppercase(),
+            since = timestamp - 1
+        );
+        let body = get_text(&self.client, &url, "Kraken").await?;
+        Ok(parse_kraken_ohlc(&body, timestamp)?)
+    }
+}
+
+/// mempool.space historical prices
+pub struct MempoolPrices {
+    client: Client,
+}
+
+impl MempoolPrices {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for MempoolPrices {
+    fn name(&self) -> &'static str {
+        "mempool"
+    }
+
+    async fn historical_price(&self, date: NaiveDate, currency: &str) -> CoreResult<Option<f64>> {
+        let currency = currency.to_uppercase();
+        if !MEMPOOL_CURRENCIES.contains(&currency.as_str()) {
+            return Ok(None);
+        }
+        let url = format!(
+            "https://mempool.space/api/v1/historical-price?currency={currency}&timestamp={timestamp}",
+            timestamp = day_start(date)
+        );
+        let body = get_text(&self.client, &url, "mempool.space").await?;
+        Ok(parse_mempool_history(&body, &currency)?)
+    }
+}
+
+/// UNIX timestamp of 00:00 UTC on `date`
+fn day_start(date: NaiveDate) -> i64 {
+    date.and_time(NaiveTime::MIN).and_utc().timestamp()
+}
+
+/// Body of a successful GET, with the status kept in the error otherwise
+async fn get_text(client: &Client, url: &str, provider: &str) -> Result<String> {
+    debug!("Fetching price from {provider}: {url}");
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("{provider} request failed"))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(status_error(
+            status,
+            format!("{provider} returned an unsuccessful status"),
+        ));
+    }
+    response
+        .text()
+        .await
+        .with_context(|| format!("Failed to read {provider} response"))
+}
+
+fn parse_coingecko_history(json: &str, currency: &str) -> Result<Option<f64>> {
+    let value: Value = serde_json::from_str(json).context("Invalid CoinGecko JSON")?;
+    Ok(value
+        .get("market_data")
+        .and_then(|market_data| market_data.get("current_price"))
+        .and_then(|prices| prices.get(currency.to_lowercase()))
+        .and_then(Value::as_f64)
+        .filter(|price| *price > 0.0))
+}
+
+/// Opening price of the candle starting at `timestamp`
+fn parse_kraken_ohlc(json: &str, timestamp: i64) -> Result<Option<f64>> {
+    let value: Value = serde_json::from_str(json).context("Invalid Kraken JSON")?;
+    if let Some(errors) = value
+        .get("error")
+        .and_then(Value::as_array)
+        .filter(|errors| !errors.is_empty())
+    {
+        // Unknown pairs are reported as errors rather than an empty result
+        debug!("Kraken returned errors: {errors:?}");
+        return Ok(None);
+    }
+    let Some(result) = value.get("result").and_then(Value::as_object) else {
+        return Ok(None);
+    };
+    let candle = result
+        .iter()
+        .filter(|(pair, _)| *pair != "last")
+        .filter_map(|(_, candles)| candles.as_array())
+        .flatten()
+        .filter_map(Value::as_array)
+        .find(|candle| candle.first().and_then(Value::as_i64) == Some(timestamp));
+    let Some(candle) = candle else {
+        return Ok(None);
+    };
+    let open = candle
+        .get(1)
+        .and_then(Value::as_str)
+        .context("Kraken candle has no opening price")?;
+    let open: f64 = open
+        .parse()
+        .with_context(|| format!("Invalid Kraken opening price: {open}"))?;
+    Ok(Some(open).filter(|price| *price > 0.0))
+}
+
+fn parse_mempool_history(json: &str, currency: &str) -> Result<Option<f64>> {
+    let value: Value = serde_json::from_str(json).context("Invalid mempool.space JSON")?;
+    Ok(value
+        .get("prices")
+        .and_then(Value::as_array)
+        .and_then(|prices| prices.first())
+        .and_then(|price| price.get(currency))
+        .and_then(Value::as_f64)
+        .filter(|price| *price > 0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_cache::PriceCache;
+    use std::sync::Arc;
+
+    fn date(s: &str) -> Result<NaiveDate> {
+        Ok(NaiveDate::parse_from_str(s, "%Y-%m-%d")?)
+    }
+
+    /// Provider with a fixed price that counts its requests
+    struct CountingPrices(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl PriceProvider for CountingPrices {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn historical_price(
+            &self,
+            _date: NaiveDate,
+            _currency: &str,
+        ) -> CoreResult<Option<f64>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(Some(50000.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_price_reuses_cache() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let options = ClientOptions {
+            price_cache: Some(Arc::new(PriceCache::open(
+                dir.path(),
+                Duration::from_secs(3600),
+            )?)),
+            ..Default::default()
+        };
+        let provider = CountingPrices(std::sync::atomic::AtomicUsize::new(0));
+
+        let day = date("2024-01-15")?;
+        assert_eq!(
+            cached_price(day, "usd", &options, &provider).await?,
+            Some(50000.0)
+        );
+        assert_eq!(
+            cached_price(day, "USD", &options, &provider).await?,
+            Some(50000.0)
+        );
+        assert_eq!(provider.0.load(Ordering::Relaxed), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_btc_price_point_fiat_value() {
+        let price = BtcPricePoint {
+            currency: "USD".to_string(),
+            date: "2024-01-15".to_string(),
+            price_per_btc: 42123.45,
+            sats_per_unit: 100_000_000.0 / 42123.45,
+            source: "auto".to_string(),
+        };
+        assert_eq!(price.fiat_value(100_000_000), 42123.45);
+        assert_eq!(price.fiat_value(12_345), 5.2);
+        assert_eq!(price.fiat_value(0), 0.0);
+    }
+
+    #[test]
+    fn test_parse_coingecko_history() -> Result<()> {
+        let json =
+            r#"{"id":"bitcoin","market_data":{"current_price":{"usd":66012.5,"eur":61500.1}}}"#;
+        assert_eq!(parse_coingecko_history(json, "USD")?, Some(66012.5));
+        assert_eq!(parse_coingecko_history(json, "brl")?, None);
+        // Days before CoinGecko's data start have no market data
+        assert_eq!(parse_coingecko_history(r#"{"id":"bitcoin"}"#, "usd")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_kraken_ohlc() -> Result<()> {
+        let timestamp = day_start(date("2024-06-15")?);
+        assert_eq!(timestamp, 1718409600);
+        let json = r#"{"error":[],"result":{"XXBTZUSD":[
+            [1718409600,"66011.1","66500.0","65800.0","66200.0","66100.0","1000.5",25000],
+            [1718496000,"66200.0","66900.0","66000.0","66600.0","66400.0","900.1",21000]
+        ],"last":1718496000}}"#;
+        assert_eq!(parse_kraken_ohlc(json, timestamp)?, Some(66011.1));
+        assert_eq!(parse_kraken_ohlc(json, timestamp - 86400)?, None);
+
+        let unknown_pair = r#"{"error":["EQuery:Unknown asset pair"]}"#;
+        assert_eq!(parse_kraken_ohlc(unknown_pair, timestamp)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mempool_history() -> Result<()> {
+        let json = r#"{"prices":[{"time":1718409600,"USD":66030,"EUR":61600}],"exchangeRates":{}}"#;
+        assert_eq!(parse_mempool_history(json, "EUR")?, Some(61600.0));
+        assert_eq!(parse_mempool_history(json, "JPY")?, None);
+        assert_eq!(parse_mempool_history(r#"{"prices":[]}"#, "USD")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_source_from_str() -> Result<()> {
+        assert_eq!("auto".parse::<PriceSource>()?, PriceSource::Auto);
+        assert_eq!("CoinGecko".parse::<PriceSource>()?, PriceSource::CoinGecko);
+        assert_eq!(
+            "mempool.space".parse::<PriceSource>()?,
+            PriceSource::Mempool
+        );
+        assert_eq!(PriceSource::Kraken.to_string(), "kraken");
+        assert!("binance".parse::<PriceSource>().is_err());
+        Ok(())
+    }
+
+    struct FixedProvider {
+        name: &'static str,
+        result: fn() -> Result<Option<f64>>,
+    }
+
+    impl FixedProvider {
+        fn boxed(name: &'static str, result: fn() -> Result<Option<f64>>) -> Box<Self> {
+            Box::new(Self { name, result })
+        }
+    }
+
+    #[async_trait]
+    impl PriceProvider for FixedProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn historical_price(
+            &self,
+            _date: NaiveDate,
+            _currency: &str,
+        ) -> CoreResult<Option<f64>> {
+            Ok((self.result)()?)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_skips_rate_limited_provider() -> Result<()> {
+        let provider = FallbackPriceProvider::new(vec![
+            FixedProvider::boxed("limited", || {
+                Err(status_error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "limited returned an unsuccessful status".to_string(),
+                ))
+            }),
+            FixedProvider::boxed("empty", || Ok(None)),
+            FixedProvider::boxed("working", || Ok(Some(65000.0))),
+        ]);
+
+        let day = date("2024-06-15")?;
+        assert_eq!(provider.historical_price(day, "USD").await?, Some(65000.0));
+        assert_eq!(provider.historical_price(day, "USD").await?, Some(65000.0));
+        assert!(provider.providers[0].1.load(Ordering::Relaxed));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fallback_reports_failures() -> Result<()> {
+        let provider = FallbackPriceProvider::new(vec![FixedProvider::boxed("broken", || {
+            bail!("connection refused")
+        })]);
+        match provider.historical_price(date("2024-06-15")?, "USD").await {
+            Ok(price) => bail!("expected all providers to fail, got {price:?}"),
+            Err(error) => assert!(error.to_string().contains("broken: connection refused")),
+        }
+        Ok(())
+    }
+}
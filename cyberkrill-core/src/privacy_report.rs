@@ -0,0 +1,479 @@
+//! Privacy review of a wallet's own transaction history
+//!
+//! Looks for what a chain observer can learn from the wallet's transactions:
+//! addresses that received more than once, payments of round amounts, change
+//! outputs that stand out from the payments, and the address clusters linked by
+//! spending coins together (common-input ownership, plus change, which the
+//! other heuristics usually give away). Each finding comes with a warning on
+//! how to avoid it next time.
+
+use bdk_wallet::{KeychainKind, Wallet};
+use bitcoin::{Address, Network, OutPoint, Script};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::backend::BlockchainBackend;
+use crate::bdk_wallet::BdkUtxo;
+use crate::error::{CoreResult, bail};
+
+/// Payments that are a multiple of this (0.0001 BTC) count as round
+const ROUND_AMOUNT_SATS: u64 = 10_000;
+
+/// An input or output of a wallet transaction
+#[derive(Debug, Clone)]
+pub struct HistoryTxo {
+    pub vout: u32,
+    /// `None` for non-standard scripts and inputs whose prevout is unknown
+    pub address: Option<String>,
+    pub amount_sats: u64,
+    pub script_type: &'static str,
+    /// Pays one of the wallet's scripts
+    pub owned: bool,
+    /// Pays the wallet's change keychain
+    pub is_change: bool,
+}
+
+/// A transaction of the wallet with its inputs resolved where possible
+#[derive(Debug, Clone)]
+pub struct HistoryTx {
+    pub txid: String,
+    pub inputs: Vec<HistoryTxo>,
+    pub outputs: Vec<HistoryTxo>,
+}
+
+impl HistoryTx {
+    /// Funded by the wallet
+    fn is_outgoing(&self) -> bool {
+        self.inputs.iter().any(|input| input.owned)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReusedAddress {
+    pub address: String,
+    pub receive_count: usize,
+    pub txids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundPayment {
+    pub txid: String,
+    pub vout: u32,
+    pub amount_sats: u64,
+}
+
+/// A change output an observer can likely pick out, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentifiableChange {
+    pub txid: String,
+    pub vout: u32,
+    pub amount_sats: u64,
+    pub heuristics: Vec<&'static str>,
+}
+
+/// Wallet addresses publicly linked to each other
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressCluster {
+    pub addresses: Vec<String>,
+    /// Transactions that link them
+    pub txids: Vec<String>,
+    pub utxo_count: usize,
+    pub utxo_sats: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrivacyReport {
+    pub transactions_analyzed: usize,
+    pub addresses_used: usize,
+    pub address_reuse: Vec<ReusedAddress>,
+    pub round_payments: Vec<RoundPayment>,
+    pub identifiable_change: Vec<IdentifiableChange>,
+    /// Clusters of two or more addresses, largest first
+    pub clusters: Vec<AddressCluster>,
+    pub warnings: Vec<String>,
+}
+
+/// Sync the wallet of `descriptor` and review its history
+pub async fn privacy_report(
+    backend: &dyn BlockchainBackend,
+    descriptor: &str,
+    network: Network,
+) -> CoreResult<PrivacyReport> {
+    if backend.name() == "bitcoind" {
+        bail!(
+            "The privacy report needs the full transaction history of the wallet, use an Electrum or Esplora backend"
+        );
+    }
+    let wallet = backend.synced_wallet(descriptor, network).await?;
+    let history = wallet_history(&wallet, network);
+    Ok(analyze_privacy(&history, &wallet.utxos(network)))
+}
+
+/// The canonical transactions of `wallet`, inputs resolved from its graph
+pub fn wallet_history(wallet: &Wallet, network: Network) -> Vec<HistoryTx> {
+    let txo = |vout: u32, script: &Script, amount_sats: u64| {
+        let keychain = wallet
+            .derivation_of_spk(script.to_owned())
+            .map(|(keychain, _)| keychain);
+        HistoryTxo {
+            vout,
+            address: Address::from_script(script, network)
+                .ok()
+                .map(|address| address.to_string()),
+            amount_sats,
+            script_type: script_type(script),
+            owned: keychain.is_some(),
+            is_change: keychain == Some(KeychainKind::Internal),
+        }
+    };
+    wallet
+        .transactions()
+        .map(|wallet_tx| {
+            let tx = &wallet_tx.tx_node.tx;
+            let inputs = tx
+                .input
+                .iter()
+                .map(|input| prevout(wallet, input.previous_output, &txo))
+                .collect();
+            let outputs = tx
+                .output
+                .iter()
+                .zip(0u32..)
+                .map(|(output, vout)| txo(vout, &output.script_pubkey, output.value.to_sat()))
+                .collect();
+            HistoryTx {
+                txid: wallet_tx.tx_node.txid.to_string(),
+                inputs,
+                outputs,
+            }
+        })
+        .collect()
+}
+
+fn prevout(
+    wallet: &Wallet,
+    outpoint: OutPoint,
+    txo: &impl Fn(u32, &Script, u64) -> HistoryTxo,
+) -> HistoryTxo {
+    match wallet.tx_graph().get_txout(outpoint) {
+        Some(prevout) => txo(
+            outpoint.vout,
+            &prevout.script_pubkey,
+            prevout.value.to_sat(),
+        ),
+        None => HistoryTxo {
+            vout: outpoint.vout,
+            address: None,
+            amount_sats: 0,
+            script_type: "unknown",
+            owned: false,
+            is_change: false,
+        },
+    }
+}
+
+fn script_type(script: &Script) -> &'static str {
+    if script.is_p2wpkh() {
+        "p2wpkh"
+    } else if script.is_p2tr() {
+        "p2tr"
+    } else if script.is_p2wsh() {
+        "p2wsh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else {
+        "other"
+    }
+}
+
+/// Review `history`; `utxos` are the wallet's current coins, counted per cluster
+pub fn analyze_privacy(history: &[HistoryTx], utxos: &[BdkUtxo]) -> PrivacyReport {
+    // Address -> transactions paying it
+    let mut receipts: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for tx in history {
+        for output in tx.outputs.iter().filter(|output| output.owned) {
+            if let Some(address) = &output.address {
+                receipts.entry(address).or_default().insert(&tx.txid);
+            }
+        }
+    }
+    let address_reuse: Vec<ReusedAddress> = receipts
+        .iter()
+        .filter(|(_, txids)| txids.len() > 1)
+        .map(|(address, txids)| ReusedAddress {
+            address: address.to_string(),
+            receive_count: txids.len(),
+            txids: txids.iter().map(|txid| txid.to_string()).collect(),
+        })
+        .collect();
+
+    let mut round_payments = Vec::new();
+    let mut identifiable_change = Vec::new();
+    for tx in history.iter().filter(|tx| tx.is_outgoing()) {
+        let (change, payments): (Vec<&HistoryTxo>, Vec<&HistoryTxo>) =
+            tx.outputs.iter().partition(|output| output.owned);
+        let round: Vec<&HistoryTxo> = payments
+            .iter()
+            .copied()
+            .filter(|payment| is_round(payment.amount_sats))
+            .collect();
+        round_payments.extend(round.iter().map(|payment| RoundPayment {
+            txid: tx.txid.clone(),
+            vout: payment.vout,
+            amount_sats: payment.amount_sats,
+        }));
+        if payments.is_empty() {
+            continue;
+        }
+
+        for output in &change {
+            let mut heuristics = Vec::new();
+            if round.len() == payments.len() && !is_round(output.amount_sats) {
+                heuristics.push("round_payment");
+            }
+            if payments
+                .iter()
+                .all(|payment| payment.script_type != output.script_type)
+                && tx
+                    .inputs
+                    .iter()
+                    .any(|input| input.script_type == output.script_type)
+            {
+                heuristics.push("script_type_mismatch");
+            }
+            if output
+                .address
+                .as_deref()
+                .and_then(|address| receipts.get(address))
+                .is_some_and(|txids| txids.len() > 1)
+            {
+                heuristics.push("reused_address");
+            }
+            if !heuristics.is_empty() {
+                identifiable_change.push(IdentifiableChange {
+                    txid: tx.txid.clone(),
+                    vout: output.vout,
+                    amount_sats: output.amount_sats,
+                    heuristics,
+                });
+            }
+        }
+    }
+
+    let clusters = clusters(history, utxos);
+    let warnings = warnings(
+        &address_reuse,
+        &round_payments,
+        &identifiable_change,
+        &clusters,
+    );
+    PrivacyReport {
+        transactions_analyzed: history.len(),
+        addresses_used: receipts.len(),
+        address_reuse,
+        round_payments,
+        identifiable_change,
+        clusters,
+        warnings,
+    }
+}
+
+fn is_round(amount_sats: u64) -> bool {
+    amount_sats > 0 && amount_sats.is_multiple_of(ROUND_AMOUNT_SATS)
+}
+
+/// Union the owned input addresses of each wallet-funded transaction with each
+/// other and with its change
+fn clusters(history: &[HistoryTx], utxos: &[BdkUtxo]) -> Vec<AddressCluster> {
+    let mut parent: BTreeMap<&str, &str> = BTreeMap::new();
+    fn root<'a>(parent: &mut BTreeMap<&'a str, &'a str>, address: &'a str) -> &'a str {
+        let mut current = address;
+        while let Some(&next) = parent.get(current).filter(|&&next| next != current) {
+            current = next;
+        }
+        parent.insert(address, current);
+        current
+    }
+
+    let mut links: Vec<(&str, &str)> = Vec::new();
+    for tx in history.iter().filter(|tx| tx.is_outgoing()) {
+        let linked: Vec<&str> = tx
+            .inputs
+            .iter()
+            .filter(|input| input.owned)
+            .chain(tx.outputs.iter().filter(|output| output.is_change))
+            .filter_map(|txo| txo.address.as_deref())
+            .collect();
+        let Some((first, rest)) = linked.split_first() else {
+            continue;
+        };
+        root(&mut parent, first);
+        for address in rest {
+            let (a, b) = (root(&mut parent, first), root(&mut parent, address));
+            if a != b {
+                parent.insert(b, a);
+            }
+            if first != address {
+                links.push((first, &tx.txid));
+            }
+        }
+    }
+
+    let addresses: Vec<&str> = parent.keys().copied().collect();
+    let mut groups: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for address in addresses {
+        let root = root(&mut parent, address);
+        groups.entry(root).or_default().insert(address);
+    }
+    let mut clusters: Vec<AddressCluster> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let txids: BTreeSet<&str> = links
+                .iter()
+                .filter(|(address, _)| members.contains(address))
+                .map(|(_, txid)| *txid)
+                .collect();
+            let coins: Vec<&BdkUtxo> = utxos
+                .iter()
+                .filter(|utxo| members.contains(utxo.address.as_str()))
+                .collect();
+            AddressCluster {
+                addresses: members.iter().map(|address| address.to_string()).collect(),
+                txids: txids.iter().map(|txid| txid.to_string()).collect(),
+                utxo_count: coins.len(),
+                utxo_sats: coins.iter().map(|utxo| utxo.amount).sum(),
+            }
+        })
+        .collect();
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.addresses.len()));
+    clusters
+}
+
+fn warnings(
+    address_reuse: &[ReusedAddress],
+    round_payments: &[RoundPayment],
+    identifiable_change: &[IdentifiableChange],
+    clusters: &[AddressCluster],
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !address_reuse.is_empty() {
+        warnings.push(format!(
+            "{count} address(es) received more than once, linking those payments to each other. \
+             Give every payer a fresh address, and spend the coins of a reused address together.",
+            count = address_reuse.len()
+        ));
+    }
+    if !round_payments.is_empty() {
+        warnings.push(format!(
+            "{count} payment(s) were round amounts, which tells the payment from the change. \
+             Avoid change with exact-amount coin selection, or round the change instead.",
+            count = round_payments.len()
+        ));
+    }
+    if identifiable_change
+        .iter()
+        .any(|change| change.heuristics.contains(&"script_type_mismatch"))
+    {
+        warnings.push(
+            "Change used a different script type than the recipients. Prefer the address type \
+             most of your recipients use (e.g. taproot or native segwit) for the wallet."
+                .to_string(),
+        );
+    }
+    if let Some(largest) = clusters.first() {
+        warnings.push(format!(
+            "{count} cluster(s) of addresses are linked by spending coins together, the largest \
+             with {size} addresses. Pick inputs by hand (onchain-coin-picker) to avoid merging \
+             coins from unrelated sources.",
+            count = clusters.len(),
+            size = largest.addresses.len()
+        ));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txo(
+        vout: u32,
+        address: &str,
+        amount_sats: u64,
+        script_type: &'static str,
+        owner: Option<bool>,
+    ) -> HistoryTxo {
+        HistoryTxo {
+            vout,
+            address: Some(address.to_string()),
+            amount_sats,
+            script_type,
+            owned: owner.is_some(),
+            is_change: owner == Some(true),
+        }
+    }
+
+    #[test]
+    fn test_privacy_findings() {
+        let receive = Some(false);
+        let change = Some(true);
+        let history = vec![
+            HistoryTx {
+                txid: "t1".to_string(),
+                inputs: vec![txo(0, "ext1", 300_000, "p2tr", None)],
+                outputs: vec![txo(0, "a1", 200_000, "p2wpkh", receive)],
+            },
+            HistoryTx {
+                txid: "t2".to_string(),
+                inputs: vec![txo(1, "ext2", 60_000, "p2pkh", None)],
+                outputs: vec![txo(0, "a1", 50_000, "p2wpkh", receive)],
+            },
+            HistoryTx {
+                txid: "t3".to_string(),
+                inputs: vec![
+                    txo(0, "a1", 200_000, "p2wpkh", receive),
+                    txo(0, "a1", 50_000, "p2wpkh", receive),
+                    txo(3, "a2", 10_000, "p2wpkh", receive),
+                ],
+                outputs: vec![
+                    txo(0, "merchant", 100_000, "p2tr", None),
+                    txo(1, "c1", 158_731, "p2wpkh", change),
+                ],
+            },
+        ];
+        let utxos = vec![BdkUtxo {
+            txid: "t3".to_string(),
+            vout: 1,
+            address: "c1".to_string(),
+            amount: 158_731,
+            amount_btc: 0.00158731,
+            confirmations: 1,
+            is_change: true,
+            keychain: "internal".to_string(),
+            derivation_index: Some(0),
+        }];
+
+        let report = analyze_privacy(&history, &utxos);
+        assert_eq!(report.transactions_analyzed, 3);
+        assert_eq!(report.addresses_used, 2);
+        assert_eq!(report.address_reuse.len(), 1);
+        assert_eq!(report.address_reuse[0].txids, ["t1", "t2"]);
+
+        assert_eq!(report.round_payments.len(), 1);
+        assert_eq!(report.round_payments[0].txid, "t3");
+        assert_eq!(report.identifiable_change.len(), 1);
+        assert_eq!(
+            report.identifiable_change[0].heuristics,
+            ["round_payment", "script_type_mismatch"]
+        );
+
+        assert_eq!(report.clusters.len(), 1);
+        assert_eq!(report.clusters[0].addresses, ["a1", "a2", "c1"]);
+        assert_eq!(report.clusters[0].txids, ["t3"]);
+        assert_eq!(report.clusters[0].utxo_sats, 158_731);
+        assert_eq!(report.warnings.len(), 4);
+    }
+}
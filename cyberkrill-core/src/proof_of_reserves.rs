@@ -0,0 +1,436 @@
+//! Proof of reserves: BIP322 ownership proofs over a UTXO set snapshot
+//!
+//! A proof binds a caller-chosen message (e.g. an auditor's challenge) to the
+//! block hash of the snapshot, and signs that for every address holding funds.
+//! Anyone can verify the signatures offline; with a node, the verifier can also
+//! check that the block is still in the active chain and which outputs remain
+//! unspent.
+
+use anyhow::{Context, Result};
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpriv};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Address, Amount, Network, ScriptBuf};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::bitcoin_rpc::{BitcoinRpcClient, UtxoSnapshot};
+use crate::error::{CoreResult, bail, ensure};
+use crate::message_signing::{
+    MessageAddressType, MessageSignatureFormat, MessageSigningDevice,
+    sign_message_bip322_with_device, sign_message_with_key, verify_message,
+};
+
+/// Format version of [`ReserveProof`]
+pub const RESERVE_PROOF_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveUtxo {
+    pub txid: String,
+    pub vout: u32,
+    pub address: String,
+    pub amount_sats: u64,
+    /// Height of the block containing the output
+    pub height: u64,
+}
+
+/// BIP322 signature proving control of `address`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressOwnershipProof {
+    pub address: String,
+    pub derivation_path: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveProof {
+    pub version: u32,
+    pub network: String,
+    /// Message supplied by the prover; the signed message also commits to the block hash
+    pub message: String,
+    pub block_hash: String,
+    pub block_height: u64,
+    pub total_sats: u64,
+    pub utxos: Vec<ReserveUtxo>,
+    pub proofs: Vec<AddressOwnershipProof>,
+}
+
+/// Node-backed checks of a proof against the current chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveChainCheck {
+    /// Whether the snapshot block is still in the active chain
+    pub block_in_active_chain: bool,
+    /// Amount of the proven UTXOs that is still unspent
+    pub unspent_sats: u64,
+    /// Outpoints spent since the snapshot
+    pub spent_outpoints: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveProofVerification {
+    pub valid: bool,
+    pub block_hash: String,
+    pub block_height: u64,
+    pub total_sats: u64,
+    /// Amount held by addresses with a valid ownership proof
+    pub verified_sats: u64,
+    pub failures: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain: Option<ReserveChainCheck>,
+}
+
+/// Key used to produce the ownership proofs
+#[derive(Debug, Clone)]
+pub enum ReserveSigner {
+    /// Master extended private key of the descriptor
+    Xprv(Xpriv),
+    /// Hardware wallet holding the descriptor keys
+    Device(MessageSigningDevice),
+}
+
+/// The message actually signed for every address: the prover's message bound to the snapshot block
+pub fn reserve_proof_message(message: &str, block_hash: &str) -> String {
+    format!("{message}\nblock: {block_hash}")
+}
+
+/// Address type, master fingerprint and full derivation path from a Core inferred
+/// descriptor such as `wpkh([d34db33f/84h/0h/0h/0/5]02..)#checksum`
+fn parse_key_origin(desc: &str) -> Result<(MessageAddressType, Fingerprint, DerivationPath)> {
+    let desc = desc.split('#').next().unwrap_or(desc);
+    let (address_type, inner) = if let Some(inner) = desc.strip_prefix("wpkh(") {
+        (MessageAddressType::P2wpkh, inner)
+    } else if let Some(inner) = desc.strip_prefix("tr(") {
+        (MessageAddressType::P2tr, inner)
+    } else {
+        bail!("Proof of reserves supports single-key wpkh and tr descriptors, got: {desc}");
+    };
+    let origin = inner
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .map(|(origin, _)| origin)
+        .with_context(|| format!("Descriptor has no key origin: {desc}"))?;
+    let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+    let fingerprint = Fingerprint::from_str(fingerprint)
+        .with_context(|| format!("Invalid key origin fingerprint in {desc}"))?;
+    let path = DerivationPath::from_str(&format!("m/{path}"))
+        .with_context(|| format!("Invalid key origin path in {desc}"))?;
+    Ok((address_type, fingerprint, path))
+}
+
+/// Build a proof of reserves for every address in `snapshot`
+pub async fn build_reserve_proof(
+    snapshot: &UtxoSnapshot,
+    message: &str,
+    signer: &ReserveSigner,
+    network: Network,
+) -> CoreResult<ReserveProof> {
+    let signed_message = reserve_proof_message(message, &snapshot.block_hash);
+    let secp = Secp256k1::new();
+
+    let mut utxos = Vec::with_capacity(snapshot.utxos.len());
+    // Address -> inferred descriptor, one proof per address
+    let mut addresses: BTreeMap<String, &str> = BTreeMap::new();
+    for utxo in &snapshot.utxos {
+        let script = ScriptBuf::from_hex(&utxo.script_pubkey)
+            .with_context(|| format!("Invalid scriptPubKey for {txid}", txid = utxo.txid))?;
+        let address = Address::from_script(&script, network)
+            .with_context(|| {
+                format!(
+                    "Output {txid}:{vout} has no address",
+                    txid = utxo.txid,
+                    vout = utxo.vout
+                )
+            })?
+            .to_string();
+        addresses.entry(address.clone()).or_insert(&utxo.desc);
+        utxos.push(ReserveUtxo {
+            txid: utxo.txid.clone(),
+            vout: utxo.vout,
+            address,
+            amount_sats: Amount::from_btc(utxo.amount)
+                .with_context(|| format!("Invalid amount for {txid}", txid = utxo.txid))?
+                .to_sat(),
+            height: utxo.height,
+        });
+    }
+
+    let mut proofs = Vec::with_capacity(addresses.len());
+    for (address, desc) in addresses {
+        let (address_type, fingerprint, path) = parse_key_origin(desc)?;
+        let path_string = format!("m/{path}");
+        let signed = match signer {
+            ReserveSigner::Xprv(xprv) => {
+                ensure!(
+                    xprv.fingerprint(&secp) == fingerprint,
+                    "Key {fingerprint} for {address} does not belong to the provided xprv"
+                );
+                let private_key = xprv.derive_priv(&secp, &path)?.to_priv();
+                sign_message_with_key(
+                    &signed_message,
+                    &private_key,
+                    address_type,
+                    MessageSignatureFormat::Bip322,
+                    network,
+                )?
+            }
+            ReserveSigner::Device(device) => {
+                sign_message_bip322_with_device(*device, &path_string, &signed_message, network)
+                    .await?
+            }
+        };
+        ensure!(
+            signed.address == address,
+            "Signer derived {derived} at {path_string} but the UTXO is held by {address}",
+            derived = signed.address
+        );
+        proofs.push(AddressOwnershipProof {
+            address,
+            derivation_path: path_string,
+            signature: signed.signature,
+        });
+    }
+
+    Ok(ReserveProof {
+        version: RESERVE_PROOF_VERSION,
+        network: network.to_string(),
+        message: message.to_string(),
+        block_hash: snapshot.block_hash.clone(),
+        block_height: snapshot.block_height,
+        total_sats: utxos.iter().map(|utxo| utxo.amount_sats).sum(),
+        utxos,
+        proofs,
+    })
+}
+
+/// Verify the ownership proofs and internal consistency of `proof` offline
+pub fn verify_reserve_proof(proof: &ReserveProof, network: Network) -> ReserveProofVerification {
+    let mut failures = Vec::new();
+    if proof.version != RESERVE_PROOF_VERSION {
+        failures.push(format!(
+            "Unsupported proof version {version}",
+            version = proof.version
+        ));
+    }
+    if proof.network != network.to_string() {
+        failures.push(format!(
+            "Proof is for {proof_network}, not {network}",
+            proof_network = proof.network
+        ));
+    }
+
+    let signed_message = reserve_proof_message(&proof.message, &proof.block_hash);
+    let mut proven: BTreeMap<&str, bool> = BTreeMap::new();
+    for ownership in &proof.proofs {
+        let valid = match verify_message(
+            &ownership.address,
+            &signed_message,
+            &ownership.signature,
+            network,
+        ) {
+            Ok(verification) if verification.valid => true,
+            Ok(verification) => {
+                failures.push(format!(
+                    "Invalid signature for {address}: {error}",
+                    address = ownership.address,
+                    error = verification.error.unwrap_or_default()
+                ));
+                false
+            }
+            Err(e) => {
+                failures.push(format!(
+                    "Invalid proof for {address}: {e:#}",
+                    address = ownership.address
+                ));
+                false
+            }
+        };
+        proven.insert(&ownership.address, valid);
+    }
+
+    let mut verified_sats = 0;
+    for utxo in &proof.utxos {
+        match proven.get(utxo.address.as_str()) {
+            Some(true) => verified_sats += utxo.amount_sats,
+            Some(false) => {}
+            None => failures.push(format!(
+                "No ownership proof for {address} ({txid}:{vout})",
+                address = utxo.address,
+                txid = utxo.txid,
+                vout = utxo.vout
+            )),
+        }
+        if utxo.height > proof.block_height {
+            failures.push(format!(
+                "Output {txid}:{vout} is newer than the snapshot block",
+                txid = utxo.txid,
+                vout = utxo.vout
+            ));
+        }
+    }
+
+    let total_sats: u64 = proof.utxos.iter().map(|utxo| utxo.amount_sats).sum();
+    if total_sats != proof.total_sats {
+        failures.push(format!(
+            "Stated total {stated} sats does not match the UTXO sum {total_sats} sats",
+            stated = proof.total_sats
+        ));
+    }
+
+    ReserveProofVerification {
+        valid: failures.is_empty(),
+        block_hash: proof.block_hash.clone(),
+        block_height: proof.block_height,
+        total_sats,
+        verified_sats,
+        failures,
+        chain: None,
+    }
+}
+
+/// Check the snapshot block and the proven outputs against a node's current chain
+pub async fn check_reserve_proof_chain(
+    proof: &ReserveProof,
+    client: &BitcoinRpcClient,
+) -> CoreResult<ReserveChainCheck> {
+    let header = client
+        .rpc_call("getblockheader", serde_json::json!([proof.block_hash]))
+        .await
+        .with_context(|| format!("Snapshot block {hash} not found", hash = proof.block_hash))?;
+    // Blocks outside the active chain report -1 confirmations
+    let block_in_active_chain = header
+        .get("confirmations")
+        .and_then(|v| v.as_i64())
+        .is_some_and(|confirmations| confirmations > 0);
+
+    let mut unspent_sats = 0;
+    let mut spent_outpoints = Vec::new();
+    for utxo in &proof.utxos {
+        let txout = client
+            .rpc_call("gettxout", serde_json::json!([utxo.txid, utxo.vout, false]))
+            .await?;
+        if txout.is_null() {
+            spent_outpoints.push(format!("{txid}:{vout}", txid = utxo.txid, vout = utxo.vout));
+        } else {
+            unspent_sats += utxo.amount_sats;
+        }
+    }
+
+    Ok(ReserveChainCheck {
+        block_in_active_chain,
+        unspent_sats,
+        spent_outpoints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin_rpc::SnapshotUtxo;
+    use bitcoin::CompressedPublicKey;
+
+    const TEST_XPRV: &str = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+    const BLOCK_HASH: &str = "00000000000000000001a0a448d6cf2546b06801389cc030b2b18c6491266815";
+
+    fn snapshot_utxo(xprv: &Xpriv, path: &str, txid_byte: char, btc: f64) -> Result<SnapshotUtxo> {
+        let secp = Secp256k1::new();
+        let derivation = DerivationPath::from_str(path)?;
+        let public_key = xprv
+            .derive_priv(&secp, &derivation)?
+            .to_priv()
+            .public_key(&secp);
+        let compressed = CompressedPublicKey::try_from(public_key)?;
+        let origin = path.trim_start_matches("m/");
+        Ok(SnapshotUtxo {
+            txid: txid_byte.to_string().repeat(64),
+            vout: 0,
+            script_pubkey: ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash()).to_hex_string(),
+            desc: format!(
+                "wpkh([{fingerprint}/{origin}]{public_key})#abcdefgh",
+                fingerprint = xprv.fingerprint(&secp)
+            ),
+            amount: btc,
+            height: 800_000,
+        })
+    }
+
+    fn test_snapshot(xprv: &Xpriv) -> Result<UtxoSnapshot> {
+        Ok(UtxoSnapshot {
+            block_hash: BLOCK_HASH.to_string(),
+            block_height: 840_000,
+            utxos: vec![
+                snapshot_utxo(xprv, "m/84'/0'/0'/0/0", 'a', 0.5)?,
+                snapshot_utxo(xprv, "m/84'/0'/0'/0/0", 'b', 0.25)?,
+                snapshot_utxo(xprv, "m/84'/0'/0'/1/3", 'c', 0.001)?,
+            ],
+        })
+    }
+
+    #[test]
+    fn test_parse_key_origin() -> Result<()> {
+        let (address_type, fingerprint, path) = parse_key_origin(
+            "tr([d34db33f/86h/0h/0h/1/7]cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115)#xyz",
+        )?;
+        assert_eq!(address_type, MessageAddressType::P2tr);
+        assert_eq!(fingerprint, Fingerprint::from_str("d34db33f")?);
+        assert_eq!(path, DerivationPath::from_str("m/86'/0'/0'/1/7")?);
+
+        assert!(parse_key_origin("wpkh(02aabb)").is_err());
+        assert!(parse_key_origin("sh(multi(1,[d34db33f/48h]02aa))").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_and_verify_reserve_proof() -> Result<()> {
+        let xprv = Xpriv::from_str(TEST_XPRV)?;
+        let snapshot = test_snapshot(&xprv)?;
+        let proof = build_reserve_proof(
+            &snapshot,
+            "audit 2026-Q3",
+            &ReserveSigner::Xprv(xprv),
+            Network::Bitcoin,
+        )
+        .await?;
+        assert_eq!(proof.total_sats, 75_100_000);
+        // Two UTXOs share an address, so only two proofs are needed
+        assert_eq!(proof.proofs.len(), 2);
+
+        let verification = verify_reserve_proof(&proof, Network::Bitcoin);
+        assert!(
+            verification.valid,
+            "{failures:?}",
+            failures = verification.failures
+        );
+        assert_eq!(verification.verified_sats, 75_100_000);
+
+        // Inflating the reserves or reusing the proof for another block is detected
+        let mut inflated = proof.clone();
+        inflated.utxos[2].amount_sats = 100_000_000;
+        assert!(!verify_reserve_proof(&inflated, Network::Bitcoin).valid);
+
+        let mut moved = proof.clone();
+        moved.block_hash = "00".repeat(32);
+        let verification = verify_reserve_proof(&moved, Network::Bitcoin);
+        assert!(!verification.valid);
+        assert_eq!(verification.verified_sats, 0);
+
+        let mut unproven = proof;
+        unproven.proofs.pop();
+        assert!(!verify_reserve_proof(&unproven, Network::Bitcoin).valid);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_foreign_xprv_is_rejected() -> Result<()> {
+        let xprv = Xpriv::from_str(TEST_XPRV)?;
+        let other = Xpriv::new_master(Network::Bitcoin, &[7u8; 32])?;
+        let result = build_reserve_proof(
+            &test_snapshot(&xprv)?,
+            "audit",
+            &ReserveSigner::Xprv(other),
+            Network::Bitcoin,
+        )
+        .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+}
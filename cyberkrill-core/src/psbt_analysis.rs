@@ -0,0 +1,408 @@
+//! Offline PSBT analysis, similar to Bitcoin Core's `analyzepsbt`
+//!
+//! Everything is derived from the PSBT itself: per-input signing status,
+//! missing fields, the estimated final size and fee rate, and which BIP174
+//! role should act next.
+
+use bitcoin::opcodes::all::{OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::psbt::{Input, Psbt};
+use bitcoin::script::Instruction;
+use bitcoin::transaction::{InputWeightPrediction, predict_weight};
+use bitcoin::{Network, Script, TxIn, TxOut};
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+/// BIP174 role that should process the PSBT next
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum PsbtRole {
+    /// Needs UTXO, script or key origin information
+    Updater,
+    /// Needs more signatures
+    Signer,
+    /// Fully signed but not finalized
+    Finalizer,
+    /// Finalized; the transaction can be extracted and broadcast
+    Extractor,
+}
+
+/// Analysis of a single PSBT input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputAnalysis {
+    pub index: usize,
+    pub txid: String,
+    pub vout: u32,
+    /// Script type of the spent output (p2wpkh, p2tr, p2wsh, ...), when known
+    pub script_type: Option<String>,
+    pub value_sats: Option<u64>,
+    pub is_final: bool,
+    pub signatures: usize,
+    /// Signatures needed to satisfy the input, when it can be determined
+    pub required_signatures: Option<usize>,
+    /// PSBT fields required by the next roles that are not present
+    pub missing: Vec<String>,
+    pub next_role: PsbtRole,
+}
+
+/// Result of [`analyze_psbt`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PsbtAnalysis {
+    pub network: String,
+    pub txid: String,
+    pub inputs: Vec<InputAnalysis>,
+    pub output_count: usize,
+    pub total_output_sats: u64,
+    /// Known only when every input has UTXO information
+    pub total_input_sats: Option<u64>,
+    pub fee_sats: Option<u64>,
+    /// Estimated size of the final signed transaction
+    pub estimated_vsize: u64,
+    pub estimated_weight: u64,
+    pub estimated_fee_rate_sat_vb: Option<f64>,
+    /// True when some input sizes had to be guessed (unknown script type)
+    pub size_is_approximate: bool,
+    pub next_role: PsbtRole,
+}
+
+fn spent_output<'a>(txin: &TxIn, input: &'a Input) -> Option<&'a TxOut> {
+    input.witness_utxo.as_ref().or_else(|| {
+        input
+            .non_witness_utxo
+            .as_ref()
+            .and_then(|tx| tx.output.get(txin.previous_output.vout as usize))
+    })
+}
+
+fn script_type(script_pubkey: &Script, input: &Input) -> &'static str {
+    if script_pubkey.is_p2wpkh() {
+        "p2wpkh"
+    } else if script_pubkey.is_p2tr() {
+        "p2tr"
+    } else if script_pubkey.is_p2wsh() {
+        "p2wsh"
+    } else if script_pubkey.is_p2pkh() {
+        "p2pkh"
+    } else if script_pubkey.is_p2sh() {
+        match &input.redeem_script {
+            Some(redeem) if redeem.is_p2wpkh() => "p2sh-p2wpkh",
+            Some(redeem) if redeem.is_p2wsh() => "p2sh-p2wsh",
+            _ => "p2sh",
+        }
+    } else {
+        "unknown"
+    }
+}
+
+/// Threshold of a `multi`/`sortedmulti` script (`OP_m <keys> OP_n OP_CHECKMULTISIG`)
+fn multisig_threshold(script: &Script) -> Option<usize> {
+    match script.instructions().next()? {
+        Ok(Instruction::Op(op)) => {
+            let first = OP_PUSHNUM_1.to_u8();
+            let code = op.to_u8();
+            (first..=OP_PUSHNUM_16.to_u8())
+                .contains(&code)
+                .then(|| usize::from(code - first) + 1)
+        }
+        _ => None,
+    }
+}
+
+fn signature_count(input: &Input) -> usize {
+    input.partial_sigs.len()
+        + usize::from(input.tap_key_sig.is_some())
+        + input.tap_script_sigs.len()
+}
+
+/// Size prediction for the input once finalized; `None` if the script type is unknown
+fn predict_input(script_type: &str, input: &Input) -> Option<InputWeightPrediction> {
+    if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+        let script_sig_len = input
+            .final_script_sig
+            .as_ref()
+            .map(|script| script.len())
+            .unwrap_or(0);
+        let witness_lens: Vec<usize> = input
+            .final_script_witness
+            .as_ref()
+            .map(|witness| witness.iter().map(|element| element.len()).collect())
+            .unwrap_or_default();
+        return Some(InputWeightPrediction::new(script_sig_len, witness_lens));
+    }
+
+    match script_type {
+        "p2wpkh" => Some(InputWeightPrediction::P2WPKH_MAX),
+        // scriptSig pushes the 22-byte witness program
+        "p2sh-p2wpkh" => Some(InputWeightPrediction::new(23, [73, 33])),
+        "p2pkh" => Some(InputWeightPrediction::P2PKH_COMPRESSED_MAX),
+        "p2tr" => Some(InputWeightPrediction::P2TR_KEY_DEFAULT_SIGHASH),
+        "p2wsh" | "p2sh-p2wsh" => {
+            let witness_script = input.witness_script.as_ref()?;
+            let threshold = multisig_threshold(witness_script)?;
+            // CHECKMULTISIG dummy element, signatures, then the witness script
+            let mut witness_lens = vec![0];
+            witness_lens.extend(std::iter::repeat_n(73, threshold));
+            witness_lens.push(witness_script.len());
+            // Nested P2WSH pushes the 34-byte witness program in scriptSig
+            let script_sig_len = if script_type == "p2sh-p2wsh" { 35 } else { 0 };
+            Some(InputWeightPrediction::new(script_sig_len, witness_lens))
+        }
+        _ => None,
+    }
+}
+
+fn analyze_input(
+    index: usize,
+    txin: &TxIn,
+    input: &Input,
+) -> (InputAnalysis, Option<InputWeightPrediction>) {
+    let utxo = spent_output(txin, input);
+    let script_type = utxo.map(|txout| script_type(&txout.script_pubkey, input));
+    let is_final = input.final_script_sig.is_some() || input.final_script_witness.is_some();
+    let signatures = signature_count(input);
+
+    let required_signatures = match script_type {
+        Some("p2wpkh" | "p2sh-p2wpkh" | "p2pkh" | "p2tr") => Some(1),
+        Some("p2wsh" | "p2sh-p2wsh") => {
+            input.witness_script.as_deref().and_then(multisig_threshold)
+        }
+        Some("p2sh") => input.redeem_script.as_deref().and_then(multisig_threshold),
+        _ => None,
+    };
+
+    let mut missing = Vec::new();
+    if !is_final {
+        match script_type {
+            None => {
+                missing.push(if input.non_witness_utxo.is_some() {
+                    // non_witness_utxo present but vout out of range
+                    "non_witness_utxo (output index out of range)".to_string()
+                } else {
+                    "witness_utxo".to_string()
+                });
+            }
+            Some("p2pkh" | "p2sh") if input.non_witness_utxo.is_none() => {
+                missing.push("non_witness_utxo".to_string());
+            }
+            _ => {}
+        }
+        if matches!(script_type, Some("p2sh" | "p2sh-p2wpkh" | "p2sh-p2wsh"))
+            && input.redeem_script.is_none()
+        {
+            missing.push("redeem_script".to_string());
+        }
+        if matches!(script_type, Some("p2wsh" | "p2sh-p2wsh")) && input.witness_script.is_none() {
+            missing.push("witness_script".to_string());
+        }
+        if signatures < required_signatures.unwrap_or(1)
+            && input.bip32_derivation.is_empty()
+            && input.tap_key_origins.is_empty()
+        {
+            missing.push("bip32_derivation".to_string());
+        }
+    }
+
+    let next_role = if is_final {
+        PsbtRole::Extractor
+    } else if !missing.is_empty() {
+        PsbtRole::Updater
+    } else if signatures < required_signatures.unwrap_or(1) {
+        PsbtRole::Signer
+    } else {
+        PsbtRole::Finalizer
+    };
+
+    let prediction = script_type.and_then(|script_type| predict_input(script_type, input));
+    let analysis = InputAnalysis {
+        index,
+        txid: txin.previous_output.txid.to_string(),
+        vout: txin.previous_output.vout,
+        script_type: script_type.map(str::to_string),
+        value_sats: utxo.map(|txout| txout.value.to_sat()),
+        is_final,
+        signatures,
+        required_signatures,
+        missing,
+        next_role,
+    };
+    (analysis, prediction)
+}
+
+/// Analyze `psbt` without contacting any backend.
+pub fn analyze_psbt(psbt: &Psbt, network: Network) -> PsbtAnalysis {
+    let tx = &psbt.unsigned_tx;
+    let mut inputs = Vec::with_capacity(tx.input.len());
+    let mut predictions = Vec::with_capacity(tx.input.len());
+    let mut size_is_approximate = false;
+
+    for (index, (txin, input)) in tx.input.iter().zip(psbt.inputs.iter()).enumerate() {
+        let (analysis, prediction) = analyze_input(index, txin, input);
+        predictions.push(prediction.unwrap_or_else(|| {
+            size_is_approximate = true;
+            InputWeightPrediction::P2WPKH_MAX
+        }));
+        inputs.push(analysis);
+    }
+
+    let weight = predict_weight(
+        predictions,
+        tx.output.iter().map(|txout| txout.script_pubkey.len()),
+    );
+    let vsize = weight.to_vbytes_ceil();
+
+    let total_output_sats: u64 = tx.output.iter().map(|txout| txout.value.to_sat()).sum();
+    let total_input_sats: Option<u64> = inputs.iter().map(|input| input.value_sats).sum();
+    let fee_sats = total_input_sats.map(|total| total.saturating_sub(total_output_sats));
+
+    // The PSBT as a whole waits on the earliest role any input still needs
+    let next_role = inputs
+        .iter()
+        .map(|input| input.next_role)
+        .min()
+        .unwrap_or(PsbtRole::Updater);
+
+    PsbtAnalysis {
+        network: network.to_string(),
+        txid: tx.compute_txid().to_string(),
+        inputs,
+        output_count: tx.output.len(),
+        total_output_sats,
+        total_input_sats,
+        fee_sats,
+        estimated_vsize: vsize,
+        estimated_weight: weight.to_wu(),
+        estimated_fee_rate_sat_vb: fee_sats.map(|fee| fee as f64 / vsize as f64),
+        size_is_approximate,
+        next_role,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use bitcoin::ecdsa::Signature;
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{
+        Amount, CompressedPublicKey, OutPoint, PublicKey, ScriptBuf, Transaction, Witness,
+        absolute, transaction,
+    };
+    use std::str::FromStr;
+
+    fn p2wpkh_psbt() -> Result<(Psbt, PublicKey, SecretKey)> {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[7u8; 32])?;
+        let pubkey = PublicKey::new(secret.public_key(&secp));
+        let script_pubkey =
+            ScriptBuf::new_p2wpkh(&CompressedPublicKey(pubkey.inner).wpubkey_hash());
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::from_str(
+                    "0000000000000000000000000000000000000000000000000000000000000001:0",
+                )?,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(99_000),
+                script_pubkey: script_pubkey.clone(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        });
+        psbt.inputs[0].bip32_derivation.insert(
+            pubkey.inner,
+            (
+                Fingerprint::from_str("d34db33f")?,
+                DerivationPath::from_str("m/84'/0'/0'/0/0")?,
+            ),
+        );
+        Ok((psbt, pubkey, secret))
+    }
+
+    fn dummy_signature(secret: &SecretKey) -> Result<Signature> {
+        let secp = Secp256k1::new();
+        let message = Message::from_digest([1u8; 32]);
+        Ok(Signature::sighash_all(secp.sign_ecdsa(&message, secret)))
+    }
+
+    #[test]
+    fn test_unsigned_input_needs_signer() -> Result<()> {
+        let (psbt, _, _) = p2wpkh_psbt()?;
+        let analysis = analyze_psbt(&psbt, Network::Bitcoin);
+
+        assert_eq!(analysis.next_role, PsbtRole::Signer);
+        assert_eq!(analysis.inputs[0].script_type.as_deref(), Some("p2wpkh"));
+        assert_eq!(analysis.inputs[0].required_signatures, Some(1));
+        assert!(analysis.inputs[0].missing.is_empty());
+        assert_eq!(analysis.fee_sats, Some(1_000));
+        // 1-in 1-out P2WPKH is 110 vB with a maximum-size signature
+        assert_eq!(analysis.estimated_vsize, 110);
+        assert!(!analysis.size_is_approximate);
+        let fee_rate = analysis
+            .estimated_fee_rate_sat_vb
+            .ok_or_else(|| anyhow::anyhow!("Expected a fee rate"))?;
+        assert!((fee_rate - 1_000.0 / 110.0).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_then_finalized() -> Result<()> {
+        let (mut psbt, pubkey, secret) = p2wpkh_psbt()?;
+        let signature = dummy_signature(&secret)?;
+        psbt.inputs[0].partial_sigs.insert(pubkey, signature);
+        assert_eq!(
+            analyze_psbt(&psbt, Network::Bitcoin).next_role,
+            PsbtRole::Finalizer
+        );
+
+        psbt.inputs[0].partial_sigs.clear();
+        psbt.inputs[0].final_script_witness = Some(Witness::p2wpkh(&signature, &pubkey.inner));
+        let analysis = analyze_psbt(&psbt, Network::Bitcoin);
+        assert_eq!(analysis.next_role, PsbtRole::Extractor);
+        assert!(analysis.inputs[0].is_final);
+        assert!(analysis.estimated_vsize <= 110);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_utxo_needs_updater() -> Result<()> {
+        let (mut psbt, _, _) = p2wpkh_psbt()?;
+        psbt.inputs[0].witness_utxo = None;
+        psbt.inputs[0].bip32_derivation.clear();
+
+        let analysis = analyze_psbt(&psbt, Network::Bitcoin);
+        assert_eq!(analysis.next_role, PsbtRole::Updater);
+        assert_eq!(
+            analysis.inputs[0].missing,
+            vec!["witness_utxo".to_string(), "bip32_derivation".to_string()]
+        );
+        assert_eq!(analysis.fee_sats, None);
+        assert!(analysis.size_is_approximate);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multisig_threshold() -> Result<()> {
+        let secp = Secp256k1::new();
+        let keys: Vec<PublicKey> = (1u8..=3)
+            .map(|i| SecretKey::from_slice(&[i; 32]).map(|sk| PublicKey::new(sk.public_key(&secp))))
+            .collect::<Result<_, _>>()?;
+        let mut builder = bitcoin::script::Builder::new().push_int(2);
+        for key in &keys {
+            builder = builder.push_key(key);
+        }
+        let witness_script = builder
+            .push_int(3)
+            .push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(multisig_threshold(&witness_script), Some(2));
+        Ok(())
+    }
+}
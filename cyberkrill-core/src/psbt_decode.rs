@@ -0,0 +1,508 @@
+//! Field-level BIP174 decoding of a PSBT
+//!
+//! Lists what a multisig coordinator needs to check before signing or
+//! finalizing: global xpubs, key origins (fingerprint and derivation path) of
+//! every input and output key, sighash types, redeem/witness scripts, taproot
+//! fields, proprietary and unknown records, and which keys have signed each
+//! input so far.
+
+use bitcoin::bip32::KeySource;
+use bitcoin::psbt::{Input, Output, Psbt, raw};
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::{Network, Script, TapLeafHash};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::psbt_analysis::analyze_psbt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptDetails {
+    pub hex: String,
+    pub asm: String,
+}
+
+impl ScriptDetails {
+    fn new(script: &Script) -> Self {
+        Self {
+            hex: script.to_hex_string(),
+            asm: script.to_asm_string(),
+        }
+    }
+}
+
+/// Key with the wallet fingerprint and path it was derived from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyOrigin {
+    pub pubkey: String,
+    pub fingerprint: String,
+    pub path: String,
+    /// Taproot script leaves the key appears in
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub leaf_hashes: Vec<String>,
+}
+
+impl KeyOrigin {
+    fn new(pubkey: String, (fingerprint, path): &KeySource, leaf_hashes: &[TapLeafHash]) -> Self {
+        Self {
+            pubkey,
+            fingerprint: fingerprint.to_string(),
+            path: format!("m/{path}"),
+            leaf_hashes: leaf_hashes.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+/// Proprietary (`0xFC`) or unknown record, hex encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawRecord {
+    /// Identifier prefix, for proprietary records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Subtype of a proprietary record or type of an unknown one
+    pub key_type: u8,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialSignature {
+    pub pubkey: String,
+    /// Fingerprint of the signing key, when its origin is in the PSBT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaf_hash: Option<String>,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapLeafScript {
+    pub control_block: String,
+    pub leaf_version: u8,
+    pub script: ScriptDetails,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapTreeLeaf {
+    pub depth: u8,
+    pub leaf_version: u8,
+    pub script: ScriptDetails,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputSigningState {
+    Unsigned,
+    /// Some, but not all, of the required signatures
+    PartiallySigned,
+    /// Enough signatures; waiting for the finalizer
+    Signed,
+    Finalized,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningStatus {
+    pub state: InputSigningState,
+    pub signatures: usize,
+    /// Signatures needed to satisfy the input, when it can be determined
+    pub required_signatures: Option<usize>,
+    /// Fingerprints (or public keys, without a key origin) that signed
+    pub signed_by: Vec<String>,
+    /// Fingerprints with a key origin in the input that have not signed
+    pub awaiting: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtGlobalDetails {
+    pub psbt_version: u32,
+    pub xpubs: Vec<KeyOrigin>,
+    pub proprietary: Vec<RawRecord>,
+    pub unknown: Vec<RawRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtInputDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sighash_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redeem_script: Option<ScriptDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness_script: Option<ScriptDetails>,
+    pub bip32_derivation: Vec<KeyOrigin>,
+    pub partial_signatures: Vec<PartialSignature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_script_sig: Option<ScriptDetails>,
+    /// Hex-encoded witness stack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_script_witness: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap_internal_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap_merkle_root: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tap_key_origins: Vec<KeyOrigin>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tap_scripts: Vec<TapLeafScript>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proprietary: Vec<RawRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unknown: Vec<RawRecord>,
+    pub signing_status: SigningStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtOutputDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redeem_script: Option<ScriptDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness_script: Option<ScriptDetails>,
+    pub bip32_derivation: Vec<KeyOrigin>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap_internal_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tap_tree: Vec<TapTreeLeaf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tap_key_origins: Vec<KeyOrigin>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proprietary: Vec<RawRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unknown: Vec<RawRecord>,
+}
+
+/// Result of [`psbt_details`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtDetails {
+    pub global: PsbtGlobalDetails,
+    pub inputs: Vec<PsbtInputDetails>,
+    pub outputs: Vec<PsbtOutputDetails>,
+}
+
+fn raw_records(
+    proprietary: &BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+    unknown: &BTreeMap<raw::Key, Vec<u8>>,
+) -> (Vec<RawRecord>, Vec<RawRecord>) {
+    let proprietary = proprietary
+        .iter()
+        .map(|(key, value)| RawRecord {
+            prefix: Some(hex::encode(&key.prefix)),
+            key_type: key.subtype,
+            key: hex::encode(&key.key),
+            value: hex::encode(value),
+        })
+        .collect();
+    let unknown = unknown
+        .iter()
+        .map(|(key, value)| RawRecord {
+            prefix: None,
+            key_type: key.type_value,
+            key: hex::encode(&key.key),
+            value: hex::encode(value),
+        })
+        .collect();
+    (proprietary, unknown)
+}
+
+fn bip32_origins(
+    derivation: &BTreeMap<bitcoin::secp256k1::PublicKey, KeySource>,
+) -> Vec<KeyOrigin> {
+    derivation
+        .iter()
+        .map(|(pubkey, source)| KeyOrigin::new(pubkey.to_string(), source, &[]))
+        .collect()
+}
+
+fn tap_origins(
+    origins: &BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+) -> Vec<KeyOrigin> {
+    origins
+        .iter()
+        .map(|(pubkey, (leaf_hashes, source))| {
+            KeyOrigin::new(pubkey.to_string(), source, leaf_hashes)
+        })
+        .collect()
+}
+
+fn partial_signatures(input: &Input) -> Vec<PartialSignature> {
+    let tap_fingerprint = |key: &XOnlyPublicKey| {
+        input
+            .tap_key_origins
+            .get(key)
+            .map(|(_, (fingerprint, _))| fingerprint.to_string())
+    };
+    let ecdsa = input
+        .partial_sigs
+        .iter()
+        .map(|(pubkey, signature)| PartialSignature {
+            pubkey: pubkey.to_string(),
+            fingerprint: input
+                .bip32_derivation
+                .get(&pubkey.inner)
+                .map(|(fingerprint, _)| fingerprint.to_string()),
+            leaf_hash: None,
+            signature: hex::encode(signature.to_vec()),
+        });
+    let key_path = input.tap_key_sig.iter().filter_map(|signature| {
+        let key = input.tap_internal_key?;
+        Some(PartialSignature {
+            pubkey: key.to_string(),
+            fingerprint: tap_fingerprint(&key),
+            leaf_hash: None,
+            signature: hex::encode(signature.to_vec()),
+        })
+    });
+    let script_path = input
+        .tap_script_sigs
+        .iter()
+        .map(|((key, leaf_hash), signature)| PartialSignature {
+            pubkey: key.to_string(),
+            fingerprint: tap_fingerprint(key),
+            leaf_hash: Some(leaf_hash.to_string()),
+            signature: hex::encode(signature.to_vec()),
+        });
+    ecdsa.chain(key_path).chain(script_path).collect()
+}
+
+fn signing_status(
+    input: &Input,
+    signatures: &[PartialSignature],
+    is_final: bool,
+    required_signatures: Option<usize>,
+) -> SigningStatus {
+    let signed_by: BTreeSet<String> = signatures
+        .iter()
+        .map(|sig| {
+            sig.fingerprint
+                .clone()
+                .unwrap_or_else(|| sig.pubkey.clone())
+        })
+        .collect();
+    let awaiting: BTreeSet<String> = if is_final {
+        BTreeSet::new()
+    } else {
+        input
+            .bip32_derivation
+            .values()
+            .map(|(fingerprint, _)| fingerprint)
+            .chain(input.tap_key_origins.values().map(|(_, (fp, _))| fp))
+            .map(ToString::to_string)
+            .filter(|fingerprint| !signed_by.contains(fingerprint))
+            .collect()
+    };
+    let state = if is_final {
+        InputSigningState::Finalized
+    } else if signatures.is_empty() {
+        InputSigningState::Unsigned
+    } else if signatures.len() >= required_signatures.unwrap_or(1) {
+        InputSigningState::Signed
+    } else {
+        InputSigningState::PartiallySigned
+    };
+    SigningStatus {
+        state,
+        signatures: signatures.len(),
+        required_signatures,
+        signed_by: signed_by.into_iter().collect(),
+        awaiting: awaiting.into_iter().collect(),
+    }
+}
+
+fn output_details(output: &Output) -> PsbtOutputDetails {
+    let (proprietary, unknown) = raw_records(&output.proprietary, &output.unknown);
+    PsbtOutputDetails {
+        redeem_script: output.redeem_script.as_deref().map(ScriptDetails::new),
+        witness_script: output.witness_script.as_deref().map(ScriptDetails::new),
+        bip32_derivation: bip32_origins(&output.bip32_derivation),
+        tap_internal_key: output.tap_internal_key.map(|key| key.to_string()),
+        tap_tree: output
+            .tap_tree
+            .iter()
+            .flat_map(|tree| tree.script_leaves())
+            .map(|leaf| TapTreeLeaf {
+                depth: leaf.merkle_branch().len() as u8,
+                leaf_version: leaf.version().to_consensus(),
+                script: ScriptDetails::new(leaf.script()),
+            })
+            .collect(),
+        tap_key_origins: tap_origins(&output.tap_key_origins),
+        proprietary,
+        unknown,
+    }
+}
+
+/// Decode every BIP174 field of `psbt`
+pub fn psbt_details(psbt: &Psbt, network: Network) -> PsbtDetails {
+    let analysis = analyze_psbt(psbt, network);
+    let (proprietary, unknown) = raw_records(&psbt.proprietary, &psbt.unknown);
+    let global = PsbtGlobalDetails {
+        psbt_version: psbt.version,
+        xpubs: psbt
+            .xpub
+            .iter()
+            .map(|(xpub, source)| KeyOrigin::new(xpub.to_string(), source, &[]))
+            .collect(),
+        proprietary,
+        unknown,
+    };
+
+    let inputs = psbt
+        .inputs
+        .iter()
+        .zip(&analysis.inputs)
+        .map(|(input, analyzed)| {
+            let signatures = partial_signatures(input);
+            let signing_status = signing_status(
+                input,
+                &signatures,
+                analyzed.is_final,
+                analyzed.required_signatures,
+            );
+            let (proprietary, unknown) = raw_records(&input.proprietary, &input.unknown);
+            PsbtInputDetails {
+                sighash_type: input.sighash_type.map(|sighash| sighash.to_string()),
+                redeem_script: input.redeem_script.as_deref().map(ScriptDetails::new),
+                witness_script: input.witness_script.as_deref().map(ScriptDetails::new),
+                bip32_derivation: bip32_origins(&input.bip32_derivation),
+                partial_signatures: signatures,
+                final_script_sig: input.final_script_sig.as_deref().map(ScriptDetails::new),
+                final_script_witness: input
+                    .final_script_witness
+                    .as_ref()
+                    .map(|witness| witness.iter().map(hex::encode).collect()),
+                tap_internal_key: input.tap_internal_key.map(|key| key.to_string()),
+                tap_merkle_root: input.tap_merkle_root.map(|root| root.to_string()),
+                tap_key_origins: tap_origins(&input.tap_key_origins),
+                tap_scripts: input
+                    .tap_scripts
+                    .iter()
+                    .map(|(control_block, (script, leaf_version))| TapLeafScript {
+                        control_block: hex::encode(control_block.serialize()),
+                        leaf_version: leaf_version.to_consensus(),
+                        script: ScriptDetails::new(script),
+                    })
+                    .collect(),
+                proprietary,
+                unknown,
+                signing_status,
+            }
+        })
+        .collect();
+
+    PsbtDetails {
+        global,
+        inputs,
+        outputs: psbt.outputs.iter().map(output_details).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use bitcoin::bip32::{DerivationPath, Fingerprint, Xpriv, Xpub};
+    use bitcoin::ecdsa::Signature;
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{
+        Amount, OutPoint, PublicKey, ScriptBuf, Transaction, TxIn, TxOut, absolute, transaction,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn test_multisig_input_details_and_signing_status() -> Result<()> {
+        let secp = Secp256k1::new();
+        let secrets: Vec<SecretKey> = (1u8..=3)
+            .map(|i| SecretKey::from_slice(&[i; 32]))
+            .collect::<Result<_, _>>()?;
+        let keys: Vec<PublicKey> = secrets
+            .iter()
+            .map(|sk| PublicKey::new(sk.public_key(&secp)))
+            .collect();
+        let mut builder = bitcoin::script::Builder::new().push_int(2);
+        for key in &keys {
+            builder = builder.push_key(key);
+        }
+        let witness_script = builder
+            .push_int(3)
+            .push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::from_str(
+                    "0000000000000000000000000000000000000000000000000000000000000001:0",
+                )?,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: ScriptBuf::new_p2wsh(&witness_script.wscript_hash()),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        let xpriv = Xpriv::new_master(bitcoin::NetworkKind::Main, &[9u8; 32])?;
+        psbt.xpub.insert(
+            Xpub::from_priv(&secp, &xpriv),
+            (
+                Fingerprint::from_str("aabbccdd")?,
+                DerivationPath::from_str("m/48'/0'/0'/2'")?,
+            ),
+        );
+        psbt.proprietary.insert(
+            raw::ProprietaryKey {
+                prefix: b"ck".to_vec(),
+                subtype: 1,
+                key: vec![0x01],
+            },
+            vec![0xff],
+        );
+
+        let input = &mut psbt.inputs[0];
+        input.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: ScriptBuf::new_p2wsh(&witness_script.wscript_hash()),
+        });
+        input.witness_script = Some(witness_script);
+        for (key, fingerprint) in keys.iter().zip(["00000001", "00000002", "00000003"]) {
+            input.bip32_derivation.insert(
+                key.inner,
+                (
+                    Fingerprint::from_str(fingerprint)?,
+                    DerivationPath::from_str("m/48'/0'/0'/2'/0/0")?,
+                ),
+            );
+        }
+        let message = Message::from_digest([1u8; 32]);
+        input.partial_sigs.insert(
+            keys[1],
+            Signature::sighash_all(secp.sign_ecdsa(&message, &secrets[1])),
+        );
+
+        let details = psbt_details(&psbt, Network::Bitcoin);
+        assert_eq!(details.global.xpubs[0].fingerprint, "aabbccdd");
+        assert_eq!(details.global.xpubs[0].path, "m/48'/0'/0'/2'");
+        assert_eq!(
+            details.global.proprietary[0].prefix.as_deref(),
+            Some("636b")
+        );
+
+        let input = &details.inputs[0];
+        assert_eq!(input.bip32_derivation.len(), 3);
+        assert!(
+            input
+                .witness_script
+                .as_ref()
+                .is_some_and(|script| script.asm.contains("OP_CHECKMULTISIG"))
+        );
+        assert_eq!(
+            input.partial_signatures[0].fingerprint.as_deref(),
+            Some("00000002")
+        );
+        let status = &input.signing_status;
+        assert_eq!(status.state, InputSigningState::PartiallySigned);
+        assert_eq!(status.required_signatures, Some(2));
+        assert_eq!(status.signed_by, ["00000002"]);
+        assert_eq!(status.awaiting, ["00000001", "00000003"]);
+        Ok(())
+    }
+}
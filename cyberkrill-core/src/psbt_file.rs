@@ -0,0 +1,116 @@
+//! Reading and writing PSBTs in binary, base64 and hex form
+//!
+//! BIP 174 files are binary (what Coldcard and Sparrow save as `.psbt`), while
+//! Bitcoin Core RPC and most clipboards carry base64 text, and a few tools use
+//! hex. Readers accept all three: binary starts with the `psbt\xff` magic and
+//! the two text forms are told apart by their alphabet. Writers use the format
+//! asked for.
+
+use crate::error::{CoreResult, bail};
+use anyhow::Context;
+use base64::Engine;
+use bitcoin::psbt::Psbt;
+use std::path::Path;
+use strum::{Display, EnumString};
+
+/// Magic bytes every serialized PSBT starts with
+pub const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// How a PSBT is written to a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum PsbtFormat {
+    /// Raw BIP 174 serialization
+    #[default]
+    Binary,
+    Base64,
+    Hex,
+}
+
+impl PsbtFormat {
+    /// Encode serialized PSBT bytes in this format
+    pub fn encode(self, psbt: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Binary => psbt.to_vec(),
+            Self::Base64 => base64::engine::general_purpose::STANDARD
+                .encode(psbt)
+                .into_bytes(),
+            Self::Hex => hex::encode(psbt).into_bytes(),
+        }
+    }
+}
+
+/// Serialized PSBT bytes from binary, base64 or hex data
+pub fn decode_psbt_data(data: &[u8]) -> CoreResult<Vec<u8>> {
+    if data.starts_with(PSBT_MAGIC) {
+        return Ok(data.to_vec());
+    }
+    let text = std::str::from_utf8(data).context("PSBT data is neither binary nor text")?;
+    let text = text.trim();
+    let bytes = if !text.is_empty() && text.chars().all(|c| c.is_ascii_hexdigit()) {
+        hex::decode(text).context("Failed to decode PSBT from hex")?
+    } else {
+        base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .context("Failed to decode PSBT from base64")?
+    };
+    if !bytes.starts_with(PSBT_MAGIC) {
+        bail!("Data does not start with the PSBT magic bytes");
+    }
+    Ok(bytes)
+}
+
+/// Parse a PSBT from binary, base64 or hex data
+pub fn parse_psbt_data(data: &[u8]) -> CoreResult<Psbt> {
+    Ok(Psbt::deserialize(&decode_psbt_data(data)?).context("Failed to parse PSBT")?)
+}
+
+/// Serialized PSBT bytes from a file path, or from the argument itself when no
+/// such file exists
+pub fn read_psbt_input(input: &str) -> CoreResult<Vec<u8>> {
+    if Path::new(input).exists() {
+        let data =
+            std::fs::read(input).with_context(|| format!("Failed to read PSBT file: {input}"))?;
+        Ok(decode_psbt_data(&data).with_context(|| format!("Invalid PSBT file: {input}"))?)
+    } else {
+        decode_psbt_data(input.as_bytes())
+    }
+}
+
+/// Write serialized PSBT bytes to `path` in `format`
+pub fn write_psbt_file(path: impl AsRef<Path>, psbt: &[u8], format: PsbtFormat) -> CoreResult<()> {
+    let path = path.as_ref();
+    Ok(std::fs::write(path, format.encode(psbt))
+        .with_context(|| format!("Failed to write PSBT file: {path}", path = path.display()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    const PSBT_BASE64: &str = "cHNidP8BAHECAAAAAea2/lMA5WyAk9UuMJPJ7wfhNzrhAAAAAA0AAAA=";
+
+    #[test]
+    fn test_psbt_formats_round_trip() -> Result<()> {
+        let bytes = decode_psbt_data(PSBT_BASE64.as_bytes())?;
+        assert!(bytes.starts_with(PSBT_MAGIC));
+
+        for format in ["binary", "BASE64", "hex"] {
+            let format = PsbtFormat::from_str(format)?;
+            let encoded = format.encode(&bytes);
+            assert_eq!(decode_psbt_data(&encoded)?, bytes);
+        }
+        assert_eq!(PsbtFormat::Base64.encode(&bytes), PSBT_BASE64.as_bytes());
+        assert_eq!(&PsbtFormat::Hex.encode(&bytes)[..10], b"70736274ff");
+
+        // Surrounding whitespace from text files is ignored
+        let padded = format!("  {PSBT_BASE64}\n");
+        assert_eq!(decode_psbt_data(padded.as_bytes())?, bytes);
+
+        assert!(decode_psbt_data(b"not a psbt").is_err());
+        assert!(decode_psbt_data(b"deadbeef").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+//! Remote signing services (corporate HSM/KMS) reachable over HTTP/JSON
+//!
+//! The [`RemoteSigner`] trait lets PSBTs built by cyberkrill be signed by any
+//! external service. [`HttpRemoteSigner`] is a reference implementation that
+//! talks JSON over HTTPS and supports mutual TLS.
+//!
+//! Wire protocol (all bodies are JSON):
+//! - `POST {base_url}/xpub` with `{"path": "m/84'/0'/0'", "network": "bitcoin"}`
+//!   returns `{"xpub": "xpub..."}`
+//! - `POST {base_url}/sign-psbt` with `{"psbt": "<base64>", "network": "bitcoin"}`
+//!   returns `{"psbt": "<base64>"}`
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bitcoin::Network;
+use bitcoin::bip32::Xpub;
+use bitcoin::psbt::Psbt;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error::{CoreResult, bail, ensure};
+use crate::proxy::{NetworkProxy, http_client_builder};
+
+const DEFAULT_REMOTE_SIGNER_TIMEOUT_SECS: u64 = 120;
+
+/// A signer that lives outside this process
+#[async_trait]
+pub trait RemoteSigner: Send + Sync {
+    /// Get the extended public key at `path`
+    async fn get_xpub(&self, path: &str, network: Network) -> CoreResult<Xpub>;
+
+    /// Sign `psbt` and return the updated PSBT
+    async fn sign_psbt(&self, psbt: &Psbt, network: Network) -> CoreResult<Psbt>;
+}
+
+/// Connection settings for [`HttpRemoteSigner`]
+#[derive(Debug, Clone, Default)]
+pub struct HttpRemoteSignerConfig {
+    /// Base URL of the signing service (e.g. https://hsm.internal:8443/v1)
+    pub url: String,
+    /// PEM file containing the client certificate followed by its private key (mutual TLS)
+    pub client_identity_pem: Option<PathBuf>,
+    /// PEM file with the CA certificate used to verify the service
+    pub ca_cert_pem: Option<PathBuf>,
+    /// Request timeout (defaults to 120 seconds to allow for approval flows)
+    pub timeout: Option<Duration>,
+    /// Proxy to reach the service through
+    pub proxy: Option<NetworkProxy>,
+}
+
+/// HTTP/JSON reference implementation of [`RemoteSigner`]
+pub struct HttpRemoteSigner {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Serialize)]
+struct XpubRequest<'a> {
+    path: &'a str,
+    network: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct XpubResponse {
+    xpub: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PsbtMessage {
+    psbt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    network: Option<String>,
+}
+
+/// Result of signing a PSBT with a remote signer
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoteSignOutput {
+    pub psbt_base64: String,
+    pub psbt_hex: String,
+    pub is_complete: bool,
+}
+
+/// Result of fetching an xpub from a remote signer
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoteXpubOutput {
+    pub xpub: String,
+    pub path: String,
+    pub network: String,
+}
+
+impl HttpRemoteSigner {
+    pub fn new(config: HttpRemoteSignerConfig) -> CoreResult<Self> {
+        let base_url = config.url.trim_end_matches('/').to_string();
+        ensure!(
+            base_url.starts_with("https://") || base_url.starts_with("http://"),
+            "Remote signer URL must start with https:// or http://, got {url}",
+            url = config.url
+        );
+        if config.client_identity_pem.is_some() && !base_url.starts_with("https://") {
+            bail!("Mutual TLS requires an https:// remote signer URL");
+        }
+
+        let mut builder = http_client_builder(config.proxy.as_ref())
+            .timeout(
+                config
+                    .timeout
+                    .unwrap_or(Duration::from_secs(DEFAULT_REMOTE_SIGNER_TIMEOUT_SECS)),
+            )
+            .user_agent(concat!("cyberkrill/", env!("CARGO_PKG_VERSION")));
+
+        if let Some(identity_path) = &config.client_identity_pem {
+            let pem = std::fs::read(identity_path).with_context(|| {
+                format!(
+                    "Failed to read client identity: {path}",
+                    path = identity_path.display()
+                )
+            })?;
+            let identity = reqwest::Identity::from_pem(&pem)
+                .context("Client identity must be a PEM file with a certificate and private key")?;
+            builder = builder.identity(identity);
+        }
+
+        if let Some(ca_path) = &config.ca_cert_pem {
+            let pem = std::fs::read(ca_path).with_context(|| {
+                format!(
+                    "Failed to read CA certificate: {path}",
+                    path = ca_path.display()
+                )
+            })?;
+            let certificate =
+                reqwest::Certificate::from_pem(&pem).context("Invalid CA certificate PEM")?;
+            builder = builder.add_root_certificate(certificate);
+        }
+
+        let client = builder
+            .build()
+            .context("Invalid remote signer client configuration")?;
+
+        Ok(Self { base_url, client })
+    }
+
+    async fn post<T: Serialize + ?Sized, R: for<'de> Deserialize<'de>>(
+        &self,
+        endpoint: &str,
+        body: &T,
+    ) -> Result<R> {
+        let url = format!("{base_url}/{endpoint}", base_url = self.base_url);
+        let response = self
+            .client
+            .post(&url)
+            .json(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach remote signer at {url}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            bail!("Remote signer returned HTTP {status}: {text}");
+        }
+
+        response
+            .json()
+            .await
+            .with_context(|| format!("Invalid JSON response from {url}"))
+    }
+}
+
+#[async_trait]
+impl RemoteSigner for HttpRemoteSigner {
+    async fn get_xpub(&self, path: &str, network: Network) -> CoreResult<Xpub> {
+        crate::hardware_wallet::parse_derivation_path(path)?;
+
+        let response: XpubResponse = self
+            .post(
+                "xpub",
+                &XpubRequest {
+                    path,
+                    network: network.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(Xpub::from_str(&response.xpub).context("Remote signer returned an invalid xpub")?)
+    }
+
+    async fn sign_psbt(&self, psbt: &Psbt, network: Network) -> CoreResult<Psbt> {
+        let response: PsbtMessage = self
+            .post(
+                "sign-psbt",
+                &PsbtMessage {
+                    psbt: psbt.to_string(),
+                    network: Some(network.to_string()),
+                },
+            )
+            .await?;
+
+        let signed =
+            Psbt::from_str(&response.psbt).context("Remote signer returned an invalid PSBT")?;
+        ensure_same_transaction(psbt, &signed)?;
+        Ok(signed)
+    }
+}
+
+/// Refuse PSBTs whose unsigned transaction was changed by the signer.
+fn ensure_same_transaction(original: &Psbt, signed: &Psbt) -> Result<()> {
+    ensure!(
+        original.unsigned_tx == signed.unsigned_tx,
+        "Remote signer returned a PSBT for a different transaction (expected {expected}, got {actual})",
+        expected = original.unsigned_tx.compute_txid(),
+        actual = signed.unsigned_tx.compute_txid()
+    );
+    Ok(())
+}
+
+/// Sign a PSBT through a remote signing service
+pub async fn sign_psbt_with_remote_signer(
+    signer: &dyn RemoteSigner,
+    psbt_data: &[u8],
+    network: Network,
+) -> CoreResult<RemoteSignOutput> {
+    let psbt = Psbt::deserialize(psbt_data).context("Failed to deserialize PSBT")?;
+    let signed = signer.sign_psbt(&psbt, network).await?;
+
+    let is_complete = signed
+        .inputs
+        .iter()
+        .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some());
+    let signed_bytes = signed.serialize();
+
+    Ok(RemoteSignOutput {
+        psbt_base64: signed.to_string(),
+        psbt_hex: hex::encode(&signed_bytes),
+        is_complete,
+    })
+}
+
+/// Get an xpub from a remote signing service
+pub async fn get_remote_signer_xpub(
+    signer: &dyn RemoteSigner,
+    path: &str,
+    network: Network,
+) -> CoreResult<RemoteXpubOutput> {
+    let xpub = signer.get_xpub(path, network).await?;
+
+    Ok(RemoteXpubOutput {
+        xpub: xpub.to_string(),
+        path: path.to_string(),
+        network: network.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PSBT from BIP174 test vectors (unsigned, one P2PKH input)
+    const TEST_PSBT: &str = "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA";
+
+    #[test]
+    fn test_config_rejects_non_http_url() -> Result<()> {
+        let result = HttpRemoteSigner::new(HttpRemoteSignerConfig {
+            url: "ftp://hsm.internal".to_string(),
+            ..Default::default()
+        });
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtls_requires_https() -> Result<()> {
+        let result = HttpRemoteSigner::new(HttpRemoteSignerConfig {
+            url: "http://hsm.internal".to_string(),
+            client_identity_pem: Some(PathBuf::from("/nonexistent/client.pem")),
+            ..Default::default()
+        });
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sign_psbt_round_trip() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/sign-psbt")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "psbt": TEST_PSBT,
+                "network": "bitcoin",
+            })))
+            .with_header("content-type", "application/json")
+            .with_body(serde_json::json!({ "psbt": TEST_PSBT }).to_string())
+            .create_async()
+            .await;
+
+        let signer = HttpRemoteSigner::new(HttpRemoteSignerConfig {
+            url: format!("{url}/v1/", url = server.url()),
+            ..Default::default()
+        })?;
+        let psbt = Psbt::from_str(TEST_PSBT)?;
+        let signed = signer.sign_psbt(&psbt, Network::Bitcoin).await?;
+        assert_eq!(signed.unsigned_tx, psbt.unsigned_tx);
+
+        let output =
+            sign_psbt_with_remote_signer(&signer, &psbt.serialize(), Network::Bitcoin).await?;
+        assert_eq!(output.psbt_base64, TEST_PSBT);
+        assert!(!output.is_complete);
+
+        mock.expect(2).assert_async().await;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_psbt_rejects_different_transaction() -> Result<()> {
+        let mut psbt = Psbt::from_str(TEST_PSBT)?;
+        let original = psbt.clone();
+        psbt.unsigned_tx.lock_time = bitcoin::absolute::LockTime::from_consensus(1);
+
+        assert!(ensure_same_transaction(&original, &psbt).is_err());
+        assert!(ensure_same_transaction(&original, &original).is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_error_is_reported() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("POST", "/xpub")
+            .with_status(403)
+            .with_body("approval denied")
+            .create_async()
+            .await;
+
+        let signer = HttpRemoteSigner::new(HttpRemoteSignerConfig {
+            url: server.url(),
+            ..Default::default()
+        })?;
+        let error = match signer.get_xpub("m/84'/0'/0'", Network::Bitcoin).await {
+            Ok(_) => bail!("Expected HTTP error"),
+            Err(error) => error,
+        };
+        assert!(error.to_string().contains("approval denied"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_xpub() -> Result<()> {
+        let xpub = "xpub6BosfCnifzxcJJ1wYuntGJfF2zPJkDeG9ELNHcKNjezuea4tumswN9sH1psMdSVqCMoJC21Bv8usSeqSP4Sp1tLzW7aY59fGn9GCYzx5UTo";
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("POST", "/xpub")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({ "path": "m/84'/0'/0'" }),
+            ))
+            .with_header("content-type", "application/json")
+            .with_body(serde_json::json!({ "xpub": xpub }).to_string())
+            .create_async()
+            .await;
+
+        let signer = HttpRemoteSigner::new(HttpRemoteSignerConfig {
+            url: server.url(),
+            ..Default::default()
+        })?;
+        let output = get_remote_signer_xpub(&signer, "m/84'/0'/0'", Network::Bitcoin).await?;
+        assert_eq!(output.xpub, xpub);
+        assert_eq!(output.network, "bitcoin");
+        Ok(())
+    }
+}
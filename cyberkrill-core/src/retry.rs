@@ -0,0 +1,526 @@
+//! Retries with exponential backoff and timeouts for backend calls
+//!
+//! Bitcoin Core RPC, Electrum, Esplora, LNURL and Fedimint requests go through
+//! [`retry`] (or [`retry_blocking`] for the synchronous Electrum and Esplora
+//! clients), so a dropped connection or a 503 in the middle of a long scan is
+//! retried instead of aborting the command. Only transient failures are
+//! retried; RPC errors (other than a node still warming up), rejected
+//! transactions and bad input fail immediately.
+//! A rate-limited server's `Retry-After` is honored, so a public Esplora
+//! instance is not hammered again before it is ready.
+
+use anyhow::Result;
+use rand::Rng;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::bitcoin_rpc::RpcError;
+use crate::error::{CoreResult, bail};
+
+/// Longest `Retry-After` waited for; a server asking for more is given up on
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub retries: u32,
+    /// Backoff before the first retry, doubled for each further one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Time limit of a single attempt
+    pub attempt_timeout: Duration,
+    /// Time limit of a call across all its attempts
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            attempt_timeout: Duration::from_secs(30),
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `retry` (0-based), with "full jitter": a
+    /// uniformly random delay up to the exponential bound, so clients that
+    /// failed together do not retry together
+    fn backoff(&self, retry: u32) -> Duration {
+        let bound = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        bound.mul_f64(rand::rng().random_range(0.0..=1.0))
+    }
+
+    /// Remaining time before the deadline, if one is set
+    fn remaining(&self, started: Instant) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_sub(started.elapsed()))
+    }
+}
+
+/// Status of an unsuccessful HTTP response, kept in the error chain so callers
+/// can tell a retryable status or a 404 from other failures
+#[derive(Debug, thiserror::Error)]
+#[error("HTTP {status}")]
+pub(crate) struct HttpStatus {
+    pub status: reqwest::StatusCode,
+    /// Delay the server asked for in a `Retry-After` header (429 and 503)
+    pub retry_after: Option<Duration>,
+}
+
+impl HttpStatus {
+    pub fn new(status: reqwest::StatusCode) -> Self {
+        Self {
+            status,
+            retry_after: None,
+        }
+    }
+}
+
+fn find_http_status(error: &anyhow::Error) -> Option<&HttpStatus> {
+    crate::error::causes(error)
+        .into_iter()
+        .find_map(|cause| cause.downcast_ref::<HttpStatus>())
+}
+
+/// Status of the unsuccessful HTTP response behind `error`, if any
+pub(crate) fn http_status(error: &anyhow::Error) -> Option<reqwest::StatusCode> {
+    find_http_status(error).map(|status| status.status)
+}
+
+/// Delay asked for by a `Retry-After` header, given either as seconds or as
+/// an HTTP date
+pub(crate) fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.signed_duration_since(chrono::Utc::now());
+    Some(wait.to_std().unwrap_or_default())
+}
+
+pub(crate) fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    use reqwest::StatusCode;
+
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Error for an unsuccessful HTTP response, retryable when the status is
+pub(crate) fn status_error(status: reqwest::StatusCode, message: String) -> anyhow::Error {
+    anyhow::Error::new(HttpStatus::new(status)).context(message)
+}
+
+/// [`status_error`] for a response whose headers may carry a `Retry-After`
+pub(crate) fn response_error(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    message: String,
+) -> anyhow::Error {
+    let retry_after = parse_retry_after(headers);
+    anyhow::Error::new(HttpStatus {
+        status,
+        retry_after,
+    })
+    .context(message)
+}
+
+/// Whether `error` comes from a network failure that may succeed on retry
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    crate::error::causes(error)
+        .into_iter()
+        .any(is_transient_cause)
+}
+
+/// Whether `error` comes from a call that timed out, which the backend may
+/// still have received and acted on
+fn is_timeout(error: &anyhow::Error) -> bool {
+    crate::error::causes(error).into_iter().any(|cause| {
+        cause.is::<tokio::time::error::Elapsed>()
+            || cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(reqwest::Error::is_timeout)
+            || cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::TimedOut)
+    })
+}
+
+/// Whether a single cause in an error chain is a transient network failure
+pub(crate) fn is_transient_cause(cause: &(dyn std::error::Error + 'static)) -> bool {
+    use bdk_electrum::electrum_client::Error as ElectrumError;
+    use bdk_esplora::esplora_client::Error as EsploraError;
+
+    let electrum = |e: &ElectrumError| {
+        matches!(
+            e,
+            ElectrumError::IOError(_) | ElectrumError::SharedIOError(_)
+        )
+    };
+    let esplora = |e: &EsploraError| match e {
+        EsploraError::Minreq(_) => true,
+        EsploraError::HttpResponse { status, .. } => {
+            reqwest::StatusCode::from_u16(*status).is_ok_and(is_retryable_status)
+        }
+        _ => false,
+    };
+
+    if let Some(status) = cause.downcast_ref::<HttpStatus>() {
+        return is_retryable_status(status.status);
+    }
+    if let Some(e) = cause.downcast_ref::<RpcError>() {
+        return e.code == RpcError::IN_WARMUP;
+    }
+    if cause.is::<tokio::time::error::Elapsed>() {
+        return true;
+    }
+    if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+        return e.is_timeout() || e.is_connect() || e.status().is_some_and(is_retryable_status);
+    }
+    if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+        use std::io::ErrorKind::*;
+        return matches!(
+            e.kind(),
+            ConnectionRefused
+                | ConnectionReset
+                | ConnectionAborted
+                | NotConnected
+                | BrokenPipe
+                | TimedOut
+                | UnexpectedEof
+                | WouldBlock
+                | Interrupted
+        );
+    }
+    if let Some(e) = cause.downcast_ref::<ElectrumError>() {
+        return electrum(e);
+    }
+    if let Some(e) = cause.downcast_ref::<EsploraError>() {
+        return esplora(e);
+    }
+    cause
+        .downcast_ref::<Box<EsploraError>>()
+        .is_some_and(|e| esplora(e))
+}
+
+/// Run `op` under `policy`: each attempt is bounded by the attempt timeout,
+/// and transient failures are retried with backoff until the retries or the
+/// deadline run out
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, what: &str, op: F) -> CoreResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    Ok(retry_with(policy, what, true, op).await?)
+}
+
+/// [`retry`] for calls that must not be repeated once the backend may have
+/// received them (e.g. a broadcast): a connection that could not be opened is
+/// retried, but an attempt that timed out fails the call
+pub async fn retry_unless_timed_out<T, F, Fut>(
+    policy: &RetryPolicy,
+    what: &str,
+    op: F,
+) -> CoreResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    Ok(retry_with(policy, what, false, op).await?)
+}
+
+async fn retry_with<T, F, Fut>(
+    policy: &RetryPolicy,
+    what: &str,
+    retry_timeouts: bool,
+    mut op: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let started = Instant::now();
+    let mut retry = 0;
+    loop {
+        let limit = policy
+            .remaining(started)
+            .map_or(policy.attempt_timeout, |left| {
+                left.min(policy.attempt_timeout)
+            });
+        let error = match tokio::time::timeout(limit, op()).await {
+            Ok(Ok(value)) => return Ok(value),
+            Ok(Err(e)) => e,
+            Err(elapsed) => {
+                anyhow::Error::new(elapsed).context(format!("{what} timed out after {limit:?}"))
+            }
+        };
+        if !retry_timeouts && is_timeout(&error) {
+            return Err(error);
+        }
+        let delay = next_delay(policy, what, started, &mut retry, error)?;
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Blocking counterpart of [`retry`]. The attempt timeout cannot interrupt a
+/// blocking call, so it must be enforced by the client (e.g. a socket timeout).
+pub fn retry_blocking<T>(
+    policy: &RetryPolicy,
+    what: &str,
+    mut op: impl FnMut() -> Result<T>,
+) -> CoreResult<T> {
+    let started = Instant::now();
+    let mut retry = 0;
+    loop {
+        let error = match op() {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let delay = next_delay(policy, what, started, &mut retry, error)?;
+        std::thread::sleep(delay);
+    }
+}
+
+/// Backoff before retrying after `error`, or the error itself when giving up.
+/// A `Retry-After` from the server raises the backoff to what it asked for.
+fn next_delay(
+    policy: &RetryPolicy,
+    what: &str,
+    started: Instant,
+    retry: &mut u32,
+    error: anyhow::Error,
+) -> Result<Duration> {
+    if !is_transient(&error) || *retry >= policy.retries {
+        return Err(error);
+    }
+    let retry_after = find_http_status(&error).and_then(|status| status.retry_after);
+    if let Some(wait) = retry_after.filter(|wait| *wait > MAX_RETRY_AFTER) {
+        bail!("{what} was rate limited and asked to wait {wait:?}: {error:#}");
+    }
+    let delay = policy.backoff(*retry).max(retry_after.unwrap_or_default());
+    if policy.remaining(started).is_some_and(|left| left <= delay) {
+        bail!(
+            "{what} did not succeed within the {deadline:?} deadline: {error:#}",
+            deadline = policy.deadline.unwrap_or_default()
+        );
+    }
+    *retry += 1;
+    warn!(
+        "{what} failed (attempt {attempt} of {total}), retrying in {delay:?}: {error:#}",
+        attempt = *retry,
+        total = policy.retries + 1
+    );
+    Ok(delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    const FAST: RetryPolicy = RetryPolicy {
+        retries: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(5),
+        attempt_timeout: Duration::from_millis(50),
+        deadline: None,
+    };
+
+    #[test]
+    fn test_backoff_is_bounded() {
+        let policy = RetryPolicy::default();
+        for retry in 0..10 {
+            let bound = (policy.initial_backoff * 2u32.pow(retry)).min(policy.max_backoff);
+            assert!(policy.backoff(retry) <= bound);
+        }
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(is_transient(
+            &anyhow::Error::new(reset).context("Esplora request failed")
+        ));
+        assert!(is_transient(&status_error(
+            reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            "Esplora returned 503".to_string()
+        )));
+        assert!(!is_transient(&status_error(
+            reqwest::StatusCode::BAD_REQUEST,
+            "Esplora returned 400".to_string()
+        )));
+        assert!(!is_transient(&anyhow::anyhow!(
+            "RPC error: insufficient fee"
+        )));
+        let rpc = |code| {
+            anyhow::Error::new(RpcError {
+                code,
+                message: "failed".to_string(),
+            })
+        };
+        assert!(is_transient(&rpc(RpcError::IN_WARMUP)));
+        assert!(!is_transient(&rpc(RpcError::INVALID_ADDRESS_OR_KEY)));
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_then_succeed() -> Result<()> {
+        let attempts = AtomicU32::new(0);
+        let value = retry_with(&FAST, "test call", true, || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                bail!(HttpStatus::new(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+            }
+            Ok(42)
+        })
+        .await?;
+        assert_eq!(value, 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() -> Result<()> {
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = retry_with(&FAST, "test call", true, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            bail!(HttpStatus::new(reqwest::StatusCode::TOO_MANY_REQUESTS))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), FAST.retries + 1);
+
+        // Permanent errors are not retried
+        attempts.store(0, Ordering::SeqCst);
+        let result: Result<()> = retry_with(&FAST, "test call", true, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            bail!("RPC error: bad-txns-inputs-missingorspent")
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_after() -> Result<()> {
+        use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(7)));
+        // A date in the past means "now"
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+        // The server's delay wins over a shorter backoff...
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("2"));
+        let error = response_error(
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            "Esplora returned 429".to_string(),
+        );
+        let mut retry = 0;
+        let delay = next_delay(&FAST, "test call", Instant::now(), &mut retry, error)?;
+        assert_eq!(delay, Duration::from_secs(2));
+        assert_eq!(retry, 1);
+
+        // ...but one that would outlast the deadline or MAX_RETRY_AFTER gives up
+        let error = response_error(
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            "Esplora returned 429".to_string(),
+        );
+        let policy = RetryPolicy {
+            deadline: Some(Duration::from_secs(1)),
+            ..FAST
+        };
+        assert!(next_delay(&policy, "test call", Instant::now(), &mut 0, error).is_err());
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3600"));
+        let error = response_error(
+            reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            &headers,
+            "Esplora returned 503".to_string(),
+        );
+        let error = next_delay(&FAST, "test call", Instant::now(), &mut 0, error)
+            .expect_err("an hour is too long to wait");
+        assert!(format!("{error:#}").contains("rate limited"), "{error:#}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deadline() -> Result<()> {
+        let policy = RetryPolicy {
+            retries: 100,
+            deadline: Some(Duration::from_millis(100)),
+            ..FAST
+        };
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = retry_with(&policy, "test call", true, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            bail!(HttpStatus::new(reqwest::StatusCode::BAD_GATEWAY))
+        })
+        .await;
+        assert!(result.is_err());
+        assert!(attempts.load(Ordering::SeqCst) < 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_attempt_timeout() -> Result<()> {
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = retry_with(&FAST, "slow call", true, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            Ok(())
+        })
+        .await;
+        let error = result.err().context("Slow call should time out")?;
+        assert!(format!("{error:#}").contains("slow call timed out"));
+        assert_eq!(attempts.load(Ordering::SeqCst), FAST.retries + 1);
+
+        // A call that may have reached the backend is not sent again...
+        attempts.store(0, Ordering::SeqCst);
+        let result: Result<()> = retry_with(&FAST, "broadcast", false, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            Ok(())
+        })
+        .await;
+        let error = result.err().context("Slow broadcast should time out")?;
+        assert!(format!("{error:#}").contains("broadcast timed out"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // ...but one that could not connect is
+        attempts.store(0, Ordering::SeqCst);
+        let result: Result<()> = retry_with(&FAST, "broadcast", false, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into())
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), FAST.retries + 1);
+        Ok(())
+    }
+}
@@ -1,10 +1,26 @@
-use anyhow::{Context, Result, ensure};
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tracing::debug;
 
 // Satscard imports - correct API usage
-use bitcoin::{Address, key::CompressedPublicKey, network::Network};
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Address, Amount, EcdsaSighashType, NetworkKind, OutPoint, PrivateKey, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+    absolute::LockTime,
+    key::CompressedPublicKey,
+    network::Network,
+    secp256k1::{Message, Secp256k1},
+    sighash::SighashCache,
+    transaction::Version,
+};
 use cktap_direct::commands::Read;
-use cktap_direct::{CkTapCard, discovery::find_first}; // Required trait import for read() method
+use cktap_direct::{CkTapCard, SatsCard, discovery::find_first}; // Required trait import for read() method
+
+use crate::backend::BlockchainBackend;
+use crate::bdk_wallet::BdkUtxo;
+use crate::error::{CoreResult, bail, ensure};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SatscardAddressOutput {
@@ -26,20 +42,51 @@ pub struct SatscardInfo {
     pub card_address: Option<String>,
 }
 
-pub async fn generate_satscard_address(slot: Option<u8>) -> Result<SatscardAddressOutput> {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SatscardUnsealOutput {
+    pub slot: u8,
+    pub address: String,
+    pub pubkey: String,
+    /// Private key of the unsealed slot in WIF format
+    pub private_key: String,
+    pub network: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SatscardSweepOutput {
+    /// Slot unsealed by this sweep; `None` when a private key was given instead
+    pub slot: Option<u8>,
+    pub source_address: String,
+    pub destination: String,
+    pub input_count: usize,
+    pub input_amount_sats: u64,
+    pub output_amount_sats: u64,
+    pub fee_sats: u64,
+    pub fee_rate_sat_vb: f64,
+    pub txid: String,
+    pub tx_hex: String,
+    pub broadcast: bool,
+    /// Private key (WIF) of the slot unsealed by this sweep, kept so the funds
+    /// stay reachable if the transaction is never broadcast
+    pub private_key: Option<String>,
+}
+
+async fn connect_satscard() -> Result<SatsCard> {
     // Connect to Satscard via NFC/PCSC - this automatically gets status
     let card = find_first()
         .await
         .with_context(|| "Failed to find Satscard. Make sure your USB card reader is connected and Satscard is placed on the reader")?;
 
-    let mut satscard = match card {
-        CkTapCard::SatsCard(satscard) => satscard,
+    match card {
+        CkTapCard::SatsCard(satscard) => Ok(satscard),
         _ => {
-            anyhow::bail!(
-                "Found CkTap card but it's not a Satscard. Make sure you're using a Satscard."
-            )
+            bail!("Found CkTap card but it's not a Satscard. Make sure you're using a Satscard.")
         }
-    };
+    }
+}
+
+pub async fn generate_satscard_address(slot: Option<u8>) -> CoreResult<SatscardAddressOutput> {
+    let mut satscard = connect_satscard().await?;
 
     // Get card status information from struct fields (not a status() method)
     let current_slot = satscard.slots.0;
@@ -97,6 +144,252 @@ pub async fn generate_satscard_address(slot: Option<u8>) -> Result<SatscardAddre
     })
 }
 
+/// Public key of the active slot, read without authentication
+async fn active_slot_pubkey(satscard: &mut SatsCard) -> Result<CompressedPublicKey> {
+    let slot = satscard.slots.0;
+    let read_result = satscard
+        .read(None)
+        .await
+        .with_context(|| format!("Failed to read slot {slot} from Satscard"))?;
+    let pubkey = read_result
+        .pubkey(None)
+        .context("Failed to get public key from read response")?;
+    CompressedPublicKey::from_slice(&pubkey.serialize())
+        .context("Failed to parse compressed public key")
+}
+
+/// Unseal the active slot and return its private key, checked against the
+/// public key the card reported before unsealing
+async fn unseal_active_slot(
+    satscard: &mut SatsCard,
+    pubkey: &CompressedPublicKey,
+    network: Network,
+) -> Result<PrivateKey> {
+    let slot = satscard.slots.0;
+    let cvc = get_cvc_from_env()?;
+    let response = satscard.unseal(slot, &cvc).await.with_context(|| {
+        format!("Failed to unseal slot {slot}. Check the CVC and that the slot is still sealed.")
+    })?;
+
+    let private_key = PrivateKey::from_slice(&response.privkey, network)
+        .context("Satscard returned an invalid private key")?;
+    let secp = Secp256k1::new();
+    ensure!(
+        CompressedPublicKey::from_private_key(&secp, &private_key).ok() == Some(*pubkey),
+        "Private key returned by the Satscard for slot {slot} does not match the slot's public key"
+    );
+    Ok(private_key)
+}
+
+fn get_cvc_from_env() -> Result<String> {
+    let Ok(cvc) = std::env::var("SATSCARD_CVC") else {
+        bail!(
+            "Unsealing requires the card's 6-digit CVC (printed on the back of the Satscard).
+Example: export SATSCARD_CVC=123456"
+        );
+    };
+    ensure!(
+        cvc.len() == 6 && cvc.chars().all(|c| c.is_ascii_digit()),
+        "Invalid CVC format. SATSCARD_CVC must be exactly 6 digits."
+    );
+    Ok(cvc)
+}
+
+/// Unseal the active slot of a Satscard, revealing its private key.
+///
+/// This is irreversible: the slot can no longer receive funds safely and the
+/// key should be swept right away.
+pub async fn unseal_satscard(network: Network) -> CoreResult<SatscardUnsealOutput> {
+    let mut satscard = connect_satscard().await?;
+    let slot = satscard.slots.0;
+    let pubkey = active_slot_pubkey(&mut satscard).await?;
+    let private_key = unseal_active_slot(&mut satscard, &pubkey, network).await?;
+
+    Ok(SatscardUnsealOutput {
+        slot,
+        address: Address::p2wpkh(&pubkey, network).to_string(),
+        pubkey: pubkey.to_string(),
+        private_key: private_key.to_wif(),
+        network: network.to_string(),
+    })
+}
+
+/// Sweep every UTXO of a Satscard slot to `destination`.
+///
+/// Without `private_key` the active slot is looked up on `backend` first and
+/// only unsealed when it holds funds. With `private_key` (the WIF printed by
+/// `unseal_satscard`) no card is needed. The fee rate comes from
+/// `fee_rate` (sat/vB) or a `conf_target` estimate.
+pub async fn sweep_satscard(
+    destination: &str,
+    fee_rate: Option<f64>,
+    conf_target: u32,
+    private_key: Option<&str>,
+    network: Network,
+    backend: &dyn BlockchainBackend,
+    broadcast: bool,
+) -> CoreResult<SatscardSweepOutput> {
+    let destination_script = Address::from_str(destination)
+        .with_context(|| format!("Invalid destination address: {destination}"))?
+        .require_network(network)
+        .with_context(|| format!("Destination address is not valid for {network}"))?
+        .script_pubkey();
+
+    let secp = Secp256k1::new();
+    let mut card = None;
+    let (pubkey, known_key) = match private_key {
+        Some(wif) => {
+            let key = PrivateKey::from_wif(wif).context("Invalid WIF private key")?;
+            ensure!(
+                key.network == NetworkKind::from(network),
+                "Private key is for a different network than {network}"
+            );
+            let pubkey = CompressedPublicKey::from_private_key(&secp, &key)
+                .context("Satscard keys are compressed; got an uncompressed private key")?;
+            (pubkey, Some(key))
+        }
+        None => {
+            let mut satscard = connect_satscard().await?;
+            let pubkey = active_slot_pubkey(&mut satscard).await?;
+            card = Some(satscard);
+            (pubkey, None)
+        }
+    };
+
+    let source_address = Address::p2wpkh(&pubkey, network);
+    let descriptor = format!("wpkh({pubkey})");
+    let utxos = backend.list_utxos(&descriptor, network).await?;
+    ensure!(
+        !utxos.is_empty(),
+        "No UTXOs found for {source_address} on {backend}; nothing to sweep",
+        backend = backend.name()
+    );
+
+    let fee_rate = match fee_rate {
+        Some(rate) => rate,
+        None => {
+            let rate = backend.estimate_fee_rate(conf_target).await?;
+            debug!(
+                "Estimated {rate} sat/vB for {conf_target} blocks from {backend}",
+                backend = backend.name()
+            );
+            rate
+        }
+    };
+
+    let (slot, key, unsealed) = match (known_key, card) {
+        (Some(key), _) => (None, key, false),
+        (None, Some(mut satscard)) => {
+            let slot = satscard.slots.0;
+            let key = unseal_active_slot(&mut satscard, &pubkey, network).await?;
+            (Some(slot), key, true)
+        }
+        (None, None) => bail!("No Satscard connected and no private key given"),
+    };
+
+    let (tx, fee) = build_sweep_transaction(&utxos, &key, destination_script, fee_rate)?;
+    let input_amount_sats: u64 = utxos.iter().map(|utxo| utxo.amount).sum();
+
+    let txid = if broadcast {
+        backend.broadcast(&tx).await?
+    } else {
+        tx.compute_txid()
+    };
+
+    Ok(SatscardSweepOutput {
+        slot,
+        source_address: source_address.to_string(),
+        destination: destination.to_string(),
+        input_count: tx.input.len(),
+        input_amount_sats,
+        output_amount_sats: input_amount_sats - fee.to_sat(),
+        fee_sats: fee.to_sat(),
+        fee_rate_sat_vb: fee_rate,
+        txid: txid.to_string(),
+        tx_hex: bitcoin::consensus::encode::serialize_hex(&tx),
+        broadcast,
+        private_key: unsealed.then(|| key.to_wif()),
+    })
+}
+
+/// Build and sign a transaction spending every P2WPKH `utxo` of `key` to a
+/// single `destination` output, returning it with its fee
+fn build_sweep_transaction(
+    utxos: &[BdkUtxo],
+    key: &PrivateKey,
+    destination: ScriptBuf,
+    fee_rate: f64,
+) -> Result<(Transaction, Amount)> {
+    ensure!(!utxos.is_empty(), "No UTXOs to sweep");
+    ensure!(
+        fee_rate.is_finite() && fee_rate > 0.0,
+        "Fee rate must be positive, got {fee_rate} sat/vB"
+    );
+
+    let secp = Secp256k1::new();
+    let pubkey = CompressedPublicKey::from_private_key(&secp, key)
+        .context("Satscard keys are compressed; got an uncompressed private key")?;
+    let source_script = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash());
+
+    let input = utxos
+        .iter()
+        .map(|utxo| {
+            let txid = Txid::from_str(&utxo.txid)
+                .with_context(|| format!("Invalid UTXO txid: {txid}", txid = utxo.txid))?;
+            Ok(TxIn {
+                previous_output: OutPoint::new(txid, utxo.vout),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                // Placeholder of the largest signature size, for the fee estimate
+                witness: Witness::from_slice(&[vec![0u8; 72], pubkey.to_bytes().to_vec()]),
+                ..TxIn::default()
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let total = Amount::from_sat(utxos.iter().map(|utxo| utxo.amount).sum());
+
+    let mut tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output: vec![TxOut {
+            value: total,
+            script_pubkey: destination,
+        }],
+    };
+
+    let fee = Amount::from_sat((tx.vsize() as f64 * fee_rate).ceil() as u64);
+    let value = total.checked_sub(fee).unwrap_or(Amount::ZERO);
+    ensure!(
+        value >= tx.output[0].script_pubkey.minimal_non_dust(),
+        "Slot balance of {total} sats cannot cover the {fee} sats fee with a non-dust output",
+        total = total.to_sat(),
+        fee = fee.to_sat()
+    );
+    tx.output[0].value = value;
+
+    let mut cache = SighashCache::new(&mut tx);
+    for (index, utxo) in utxos.iter().enumerate() {
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                index,
+                &source_script,
+                Amount::from_sat(utxo.amount),
+                EcdsaSighashType::All,
+            )
+            .with_context(|| format!("Failed to compute sighash for input {index}"))?;
+        let signature = bitcoin::ecdsa::Signature {
+            signature: secp.sign_ecdsa(&Message::from_digest(sighash.to_byte_array()), &key.inner),
+            sighash_type: EcdsaSighashType::All,
+        };
+        *cache
+            .witness_mut(index)
+            .with_context(|| format!("Missing input {index}"))? =
+            Witness::p2wpkh(&signature, &pubkey.0);
+    }
+
+    Ok((tx, fee))
+}
+
 fn pubkey_to_address(pubkey: &[u8]) -> Result<String> {
     // Convert public key to Bitcoin address using proper Bitcoin libraries
     ensure!(
@@ -140,6 +433,88 @@ mod tests {
         Ok(())
     }
 
+    fn utxo(txid: &str, vout: u32, amount: u64) -> BdkUtxo {
+        BdkUtxo {
+            txid: txid.to_string(),
+            vout,
+            address: String::new(),
+            amount,
+            amount_btc: Amount::from_sat(amount).to_btc(),
+            confirmations: 1,
+            is_change: false,
+            keychain: "External".to_string(),
+            derivation_index: None,
+        }
+    }
+
+    #[test]
+    fn test_build_sweep_transaction() -> Result<()> {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_slice(&[7u8; 32], Network::Regtest)?;
+        let pubkey = CompressedPublicKey::from_private_key(&secp, &key)?;
+        let destination =
+            Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080")?.assume_checked();
+        let utxos = [
+            utxo(
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                0,
+                50_000,
+            ),
+            utxo(
+                "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                3,
+                20_000,
+            ),
+        ];
+
+        let (tx, fee) = build_sweep_transaction(&utxos, &key, destination.script_pubkey(), 2.0)?;
+
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value + fee, Amount::from_sat(70_000));
+        // Real signatures are at most as large as the placeholder used for the estimate
+        assert!(fee.to_sat() as f64 >= tx.vsize() as f64 * 2.0);
+        assert!(fee.to_sat() < 500);
+
+        // Each input carries a valid signature for its own sighash
+        let source_script = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash());
+        let mut cache = SighashCache::new(&tx);
+        for (index, input) in tx.input.iter().enumerate() {
+            let witness: Vec<&[u8]> = input.witness.iter().collect();
+            assert_eq!(witness[1], pubkey.to_bytes().as_slice());
+            let signature = bitcoin::ecdsa::Signature::from_slice(witness[0])?;
+            let sighash = cache.p2wpkh_signature_hash(
+                index,
+                &source_script,
+                Amount::from_sat(utxos[index].amount),
+                EcdsaSighashType::All,
+            )?;
+            secp.verify_ecdsa(
+                &Message::from_digest(sighash.to_byte_array()),
+                &signature.signature,
+                &pubkey.0,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_sweep_transaction_errors() -> Result<()> {
+        let key = PrivateKey::from_slice(&[7u8; 32], Network::Regtest)?;
+        let destination =
+            Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080")?.assume_checked();
+        let dust = [utxo(
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+            0,
+            600,
+        )];
+
+        assert!(build_sweep_transaction(&[], &key, destination.script_pubkey(), 1.0).is_err());
+        assert!(build_sweep_transaction(&dust, &key, destination.script_pubkey(), 5.0).is_err());
+        assert!(build_sweep_transaction(&dust, &key, destination.script_pubkey(), 0.0).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_invalid_pubkey_length() -> Result<()> {
         let invalid_pubkey = vec![0u8; 32]; // Wrong length
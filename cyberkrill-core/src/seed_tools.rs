@@ -0,0 +1,1123 @@
+//! Seed backup tools
+//!
+//! SLIP-39 Shamir backups: a master secret (the entropy of a BIP39 mnemonic)
+//! is encrypted with a passphrase and split into groups of shares, so that any
+//! `group_threshold` groups, each with its member threshold of shares, recover
+//! it. Splitting the BIP39 entropy keeps the original wallet: recovery gives
+//! back the same mnemonic, to be used with the same BIP39 passphrase as before.
+//!
+//! SeedXOR (as on Coldcard): every share is itself a valid BIP39 mnemonic, and
+//! XOR-ing the entropy of all of them gives the original. All shares are needed.
+//!
+//! [`check_mnemonic`] helps recovering users: it validates the BIP39 checksum,
+//! suggests wordlist words for misspellings and brute-forces a single unknown
+//! or missing word.
+
+use crate::error::{CoreResult, bail, ensure};
+use anyhow::{Context, Result};
+use bip39::Language;
+use bitcoin::hashes::{Hash, HashEngine, hmac, sha256};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+const SLIP39_WORDLIST: &str = include_str!("slip39_wordlist.txt");
+
+static SLIP39_WORDS: LazyLock<Vec<&'static str>> =
+    LazyLock::new(|| SLIP39_WORDLIST.lines().collect());
+
+const RADIX_BITS: usize = 10;
+const RADIX: u16 = 1 << RADIX_BITS;
+/// Identifier, extendable flag and iteration exponent, then group and member fields
+const HEADER_WORDS: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+const MIN_SECRET_BYTES: usize = 16;
+const MIN_MNEMONIC_WORDS: usize = HEADER_WORDS + CHECKSUM_WORDS + 13;
+const MAX_SHARE_COUNT: u8 = 16;
+const MAX_ITERATION_EXPONENT: u8 = 15;
+const IDENTIFIER_BITS: u32 = 15;
+const DIGEST_LENGTH: usize = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const BASE_ITERATION_COUNT: u32 = 10_000;
+const ROUND_COUNT: u8 = 4;
+const CUSTOMIZATION: &str = "shamir";
+const CUSTOMIZATION_EXTENDABLE: &str = "shamir_extendable";
+/// Word counts of BIP39 mnemonics
+const BIP39_WORD_COUNTS: [usize; 5] = [12, 15, 18, 21, 24];
+/// Largest edit distance of a spelling suggestion
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+const MAX_SUGGESTIONS: usize = 5;
+/// Placeholders accepted for an unknown word
+const UNKNOWN_WORD_PLACEHOLDERS: [&str; 3] = ["?", "_", "*"];
+/// Share counts supported by Coldcard
+const SEEDXOR_MIN_PARTS: usize = 2;
+const SEEDXOR_MAX_PARTS: usize = 4;
+
+/// Member shares of one SLIP-39 group: `threshold` of `count` recover it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slip39Group {
+    pub threshold: u8,
+    pub count: u8,
+}
+
+impl FromStr for Slip39Group {
+    type Err = anyhow::Error;
+
+    /// Parse `2of3` (also `2-of-3` or `2/3`)
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        let (threshold, count) = s
+            .split_once("-of-")
+            .or_else(|| s.split_once("of"))
+            .or_else(|| s.split_once('/'))
+            .with_context(|| format!("Invalid group: {s}. Expected THRESHOLDofCOUNT, e.g. 2of3"))?;
+        Ok(Self {
+            threshold: threshold
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid group threshold: {threshold}"))?,
+            count: count
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid group share count: {count}"))?,
+        })
+    }
+}
+
+/// Shares of one group of a SLIP-39 backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Slip39GroupShares {
+    pub group_index: u8,
+    pub member_threshold: u8,
+    pub shares: Vec<String>,
+}
+
+/// A SLIP-39 backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Slip39Backup {
+    pub identifier: u16,
+    pub extendable: bool,
+    pub iteration_exponent: u8,
+    pub group_threshold: u8,
+    pub groups: Vec<Slip39GroupShares>,
+}
+
+/// Master secret recovered from SLIP-39 shares
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveredSeed {
+    /// Hex-encoded master secret
+    pub master_secret: String,
+    /// BIP39 mnemonic with the master secret as entropy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+}
+
+/// Split the entropy of a BIP39 mnemonic into SLIP-39 shares
+pub fn split_bip39_mnemonic(
+    mnemonic: &str,
+    passphrase: &str,
+    group_threshold: u8,
+    groups: &[Slip39Group],
+    iteration_exponent: u8,
+) -> CoreResult<Slip39Backup> {
+    let mnemonic =
+        bip39::Mnemonic::parse_normalized(mnemonic.trim()).context("Invalid BIP39 mnemonic")?;
+    slip39_split(
+        &mnemonic.to_entropy(),
+        passphrase,
+        group_threshold,
+        groups,
+        iteration_exponent,
+    )
+}
+
+/// Recover the master secret of SLIP-39 `shares`, with the BIP39 mnemonic it
+/// is the entropy of
+pub fn recover_bip39_mnemonic(shares: &[String], passphrase: &str) -> CoreResult<RecoveredSeed> {
+    let master_secret = slip39_recover(shares, passphrase)?;
+    let mnemonic = bip39::Mnemonic::from_entropy(&master_secret)
+        .ok()
+        .map(|mnemonic| mnemonic.to_string());
+    Ok(RecoveredSeed {
+        master_secret: hex::encode(master_secret),
+        mnemonic,
+    })
+}
+
+/// Split a BIP39 mnemonic into `parts` SeedXOR shares of the same length
+pub fn seedxor_split(mnemonic: &str, parts: usize) -> CoreResult<Vec<String>> {
+    ensure!(
+        (SEEDXOR_MIN_PARTS..=SEEDXOR_MAX_PARTS).contains(&parts),
+        "SeedXOR supports {SEEDXOR_MIN_PARTS} to {SEEDXOR_MAX_PARTS} parts"
+    );
+    let mnemonic =
+        bip39::Mnemonic::parse_normalized(mnemonic.trim()).context("Invalid BIP39 mnemonic")?;
+    let mut last = mnemonic.to_entropy();
+
+    let mut rng = rand::rng();
+    let mut shares = Vec::with_capacity(parts);
+    for _ in 1..parts {
+        let mut entropy = vec![0u8; last.len()];
+        rng.fill(&mut entropy[..]);
+        last.iter_mut().zip(&entropy).for_each(|(a, b)| *a ^= b);
+        shares.push(bip39::Mnemonic::from_entropy(&entropy)?.to_string());
+    }
+    shares.push(bip39::Mnemonic::from_entropy(&last)?.to_string());
+    Ok(shares)
+}
+
+/// Combine SeedXOR shares into the original BIP39 mnemonic
+pub fn seedxor_combine(shares: &[String]) -> CoreResult<String> {
+    ensure!(
+        shares.len() >= SEEDXOR_MIN_PARTS,
+        "SeedXOR needs at least {SEEDXOR_MIN_PARTS} shares"
+    );
+    let mut combined: Option<Vec<u8>> = None;
+    for (index, share) in shares.iter().enumerate() {
+        let entropy = bip39::Mnemonic::parse_normalized(share.trim())
+            .with_context(|| {
+                format!(
+                    "Share {number} is not a valid BIP39 mnemonic",
+                    number = index + 1
+                )
+            })?
+            .to_entropy();
+        match &mut combined {
+            None => combined = Some(entropy),
+            Some(combined) => {
+                ensure!(
+                    combined.len() == entropy.len(),
+                    "Share {number} has a different word count than the first share",
+                    number = index + 1
+                );
+                combined.iter_mut().zip(&entropy).for_each(|(a, b)| *a ^= b);
+            }
+        }
+    }
+    let combined = combined.context("No shares given")?;
+    Ok(bip39::Mnemonic::from_entropy(&combined)?.to_string())
+}
+
+/// Result of [`check_mnemonic`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedCheck {
+    /// Whether the mnemonic is valid as given
+    pub valid: bool,
+    pub word_count: usize,
+    /// Words that are not in the BIP39 English wordlist
+    pub unknown_words: Vec<UnknownWord>,
+    /// Whether the checksum matches, when every word is known and the count is valid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_valid: Option<bool>,
+    /// Single-word changes that make the mnemonic valid
+    pub corrections: Vec<SeedCorrection>,
+}
+
+/// A word missing from the wordlist, with the closest wordlist words
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnknownWord {
+    /// 1-based position in the mnemonic
+    pub position: usize,
+    pub word: String,
+    pub suggestions: Vec<String>,
+}
+
+/// A single-word change giving a valid mnemonic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedCorrection {
+    /// 1-based position of the replaced or inserted word
+    pub position: usize,
+    /// Word that was replaced; `None` when a missing word was inserted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+    pub replacement: String,
+    pub mnemonic: String,
+}
+
+/// Check a BIP39 mnemonic and look for single-word fixes:
+/// - unknown words get spelling suggestions, and a single unknown word (or a
+///   `?` placeholder) is brute-forced against the whole wordlist
+/// - with a bad checksum, words within a small edit distance of each word are tried
+/// - with one word missing, every word is tried at `missing_position` (1-based),
+///   or at every position when it is not given
+pub fn check_mnemonic(mnemonic: &str, missing_position: Option<usize>) -> CoreResult<SeedCheck> {
+    let words: Vec<String> = mnemonic
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect();
+    ensure!(!words.is_empty(), "No words given");
+    let wordlist = Language::English.word_list();
+
+    let unknown_words: Vec<UnknownWord> = words
+        .iter()
+        .enumerate()
+        .filter(|(_, word)| Language::English.find_word(word).is_none())
+        .map(|(index, word)| UnknownWord {
+            position: index + 1,
+            word: word.clone(),
+            suggestions: if UNKNOWN_WORD_PLACEHOLDERS.contains(&word.as_str()) {
+                Vec::new()
+            } else {
+                suggest_words(word)
+            },
+        })
+        .collect();
+
+    let mut check = SeedCheck {
+        valid: false,
+        word_count: words.len(),
+        unknown_words,
+        checksum_valid: None,
+        corrections: Vec::new(),
+    };
+
+    if BIP39_WORD_COUNTS.contains(&words.len()) {
+        match check.unknown_words.as_slice() {
+            [] => {
+                let valid = is_valid_mnemonic(&words);
+                check.valid = valid;
+                check.checksum_valid = Some(valid);
+                if !valid {
+                    for (index, word) in words.iter().enumerate() {
+                        for replacement in wordlist.iter().filter(|candidate| {
+                            **candidate != word
+                                && edit_distance(candidate, word) <= MAX_SUGGESTION_DISTANCE
+                        }) {
+                            check
+                                .corrections
+                                .extend(replace_word(&words, index, replacement));
+                        }
+                    }
+                }
+            }
+            [unknown] => {
+                let index = unknown.position - 1;
+                for replacement in wordlist {
+                    check
+                        .corrections
+                        .extend(replace_word(&words, index, replacement));
+                }
+                // Likely spellings first
+                check.corrections.sort_by_key(|correction| {
+                    edit_distance(&correction.replacement, &unknown.word)
+                });
+            }
+            _ => {}
+        }
+    } else if BIP39_WORD_COUNTS.contains(&(words.len() + 1)) && check.unknown_words.is_empty() {
+        let positions = match missing_position {
+            Some(position) => {
+                ensure!(
+                    (1..=words.len() + 1).contains(&position),
+                    "The missing position must be between 1 and {count}",
+                    count = words.len() + 1
+                );
+                position - 1..position
+            }
+            None => 0..words.len() + 1,
+        };
+        for index in positions {
+            for word in wordlist {
+                let mut candidate = words.clone();
+                candidate.insert(index, word.to_string());
+                if is_valid_mnemonic(&candidate) {
+                    check.corrections.push(SeedCorrection {
+                        position: index + 1,
+                        original: None,
+                        replacement: word.to_string(),
+                        mnemonic: candidate.join(" "),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(check)
+}
+
+/// Split `master_secret` into SLIP-39 shares; any `group_threshold` of the
+/// `groups` recover it
+pub fn slip39_split(
+    master_secret: &[u8],
+    passphrase: &str,
+    group_threshold: u8,
+    groups: &[Slip39Group],
+    iteration_exponent: u8,
+) -> CoreResult<Slip39Backup> {
+    ensure!(
+        master_secret.len() >= MIN_SECRET_BYTES && master_secret.len().is_multiple_of(2),
+        "The master secret must be an even number of bytes, at least {MIN_SECRET_BYTES}"
+    );
+    ensure!(
+        iteration_exponent <= MAX_ITERATION_EXPONENT,
+        "The iteration exponent must be at most {MAX_ITERATION_EXPONENT}"
+    );
+    ensure!(
+        group_threshold >= 1 && usize::from(group_threshold) <= groups.len(),
+        "The group threshold must be between 1 and the number of groups ({count})",
+        count = groups.len()
+    );
+    for group in groups {
+        ensure!(
+            !(group.threshold == 1 && group.count > 1),
+            "A group with threshold 1 must have a single share; use 1of1 instead of 1of{count}",
+            count = group.count
+        );
+    }
+
+    let identifier = rand::rng().random_range(0..1u16 << IDENTIFIER_BITS);
+    let extendable = true;
+    let encrypted = feistel(
+        master_secret,
+        passphrase,
+        iteration_exponent,
+        &salt(identifier, extendable),
+        true,
+    )?;
+
+    let group_count = u8::try_from(groups.len()).context("Too many groups")?;
+    let group_secrets = split_secret(group_threshold, group_count, &encrypted)?;
+    let groups = groups
+        .iter()
+        .zip(group_secrets)
+        .map(|(group, (group_index, group_secret))| {
+            let shares = split_secret(group.threshold, group.count, &group_secret)?
+                .into_iter()
+                .map(|(member_index, value)| {
+                    Slip39Share {
+                        identifier,
+                        extendable,
+                        iteration_exponent,
+                        group_index,
+                        group_threshold,
+                        group_count,
+                        member_index,
+                        member_threshold: group.threshold,
+                        value,
+                    }
+                    .to_mnemonic()
+                })
+                .collect();
+            Ok(Slip39GroupShares {
+                group_index,
+                member_threshold: group.threshold,
+                shares,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Slip39Backup {
+        identifier,
+        extendable,
+        iteration_exponent,
+        group_threshold,
+        groups,
+    })
+}
+
+/// Recover the master secret from SLIP-39 `shares`
+pub fn slip39_recover(shares: &[String], passphrase: &str) -> CoreResult<Vec<u8>> {
+    let shares = shares
+        .iter()
+        .map(|share| Slip39Share::from_mnemonic(share))
+        .collect::<Result<Vec<_>>>()?;
+    let first = shares.first().context("No shares given")?;
+    ensure!(
+        shares
+            .iter()
+            .all(|share| share.identifier == first.identifier
+                && share.extendable == first.extendable
+                && share.iteration_exponent == first.iteration_exponent
+                && share.group_threshold == first.group_threshold
+                && share.group_count == first.group_count),
+        "The shares belong to different backups"
+    );
+
+    let mut groups: BTreeMap<u8, Vec<&Slip39Share>> = BTreeMap::new();
+    for share in &shares {
+        let members = groups.entry(share.group_index).or_default();
+        if let Some(other) = members
+            .iter()
+            .find(|m| m.member_index == share.member_index)
+        {
+            ensure!(
+                *other == share,
+                "Conflicting shares for member {member} of group {group}",
+                member = share.member_index + 1,
+                group = share.group_index + 1
+            );
+            continue;
+        }
+        members.push(share);
+    }
+
+    let mut group_secrets = Vec::new();
+    for (group_index, members) in &groups {
+        let member_threshold = members[0].member_threshold;
+        ensure!(
+            members
+                .iter()
+                .all(|m| m.member_threshold == member_threshold),
+            "The shares of group {group} disagree on its threshold",
+            group = group_index + 1
+        );
+        if members.len() < usize::from(member_threshold) {
+            continue;
+        }
+        let members: Vec<(u8, Vec<u8>)> = members
+            .iter()
+            .take(usize::from(member_threshold))
+            .map(|m| (m.member_index, m.value.clone()))
+            .collect();
+        group_secrets.push((*group_index, recover_secret(member_threshold, &members)?));
+    }
+    if group_secrets.len() < usize::from(first.group_threshold) {
+        let incomplete = groups
+            .iter()
+            .filter(|(_, members)| members.len() < usize::from(members[0].member_threshold))
+            .map(|(index, members)| {
+                format!(
+                    "group {group} has {have} of {need}",
+                    group = index + 1,
+                    have = members.len(),
+                    need = members[0].member_threshold
+                )
+            })
+            .collect::<Vec<_>>();
+        bail!(
+            "Not enough shares: {need} complete groups are needed, {have} given{detail}",
+            need = first.group_threshold,
+            have = group_secrets.len(),
+            detail = if incomplete.is_empty() {
+                String::new()
+            } else {
+                format!(" ({incomplete})", incomplete = incomplete.join(", "))
+            }
+        );
+    }
+    group_secrets.truncate(usize::from(first.group_threshold));
+
+    let encrypted = recover_secret(first.group_threshold, &group_secrets)?;
+    Ok(feistel(
+        &encrypted,
+        passphrase,
+        first.iteration_exponent,
+        &salt(first.identifier, first.extendable),
+        false,
+    )?)
+}
+
+/// A single decoded SLIP-39 share
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Slip39Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Vec<u8>,
+}
+
+impl Slip39Share {
+    fn to_mnemonic(&self) -> String {
+        let header = (u32::from(self.identifier) << 5)
+            | (u32::from(self.extendable) << 4)
+            | u32::from(self.iteration_exponent);
+        let fields = (u32::from(self.group_index) << 16)
+            | (u32::from(self.group_threshold - 1) << 12)
+            | (u32::from(self.group_count - 1) << 8)
+            | (u32::from(self.member_index) << 4)
+            | u32::from(self.member_threshold - 1);
+
+        let mut words = vec![
+            (header >> RADIX_BITS) as u16,
+            (header as u16) & (RADIX - 1),
+            (fields >> RADIX_BITS) as u16,
+            (fields as u16) & (RADIX - 1),
+        ];
+        words.extend(bytes_to_words(&self.value));
+        words.extend(create_checksum(customization(self.extendable), &words));
+
+        words
+            .iter()
+            .map(|&index| SLIP39_WORDS[usize::from(index)])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn from_mnemonic(mnemonic: &str) -> Result<Self> {
+        let words = mnemonic
+            .split_whitespace()
+            .map(|word| {
+                let word = word.to_lowercase();
+                SLIP39_WORDS
+                    .binary_search(&word.as_str())
+                    .map(|index| index as u16)
+                    .map_err(|_| anyhow::anyhow!("Invalid SLIP-39 word: {word}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            words.len() >= MIN_MNEMONIC_WORDS,
+            "Invalid SLIP-39 share: {count} words, at least {MIN_MNEMONIC_WORDS} expected",
+            count = words.len()
+        );
+        let padding = (RADIX_BITS * (words.len() - HEADER_WORDS - CHECKSUM_WORDS)) % 16;
+        ensure!(
+            padding <= 8,
+            "Invalid SLIP-39 share length: {count} words",
+            count = words.len()
+        );
+
+        let header = (u32::from(words[0]) << RADIX_BITS) | u32::from(words[1]);
+        let extendable = (header >> 4) & 1 == 1;
+        ensure!(
+            verify_checksum(customization(extendable), &words),
+            "Invalid SLIP-39 share checksum: {prefix}...",
+            prefix = mnemonic
+                .split_whitespace()
+                .take(4)
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+
+        let fields = (u32::from(words[2]) << RADIX_BITS) | u32::from(words[3]);
+        let share = Self {
+            identifier: (header >> 5) as u16,
+            extendable,
+            iteration_exponent: (header & 0xf) as u8,
+            group_index: ((fields >> 16) & 0xf) as u8,
+            group_threshold: ((fields >> 12) & 0xf) as u8 + 1,
+            group_count: ((fields >> 8) & 0xf) as u8 + 1,
+            member_index: ((fields >> 4) & 0xf) as u8,
+            member_threshold: (fields & 0xf) as u8 + 1,
+            value: words_to_bytes(&words[HEADER_WORDS..words.len() - CHECKSUM_WORDS], padding)?,
+        };
+        ensure!(
+            share.group_threshold <= share.group_count,
+            "Invalid SLIP-39 share: group threshold exceeds the group count"
+        );
+        Ok(share)
+    }
+}
+
+fn is_valid_mnemonic(words: &[String]) -> bool {
+    bip39::Mnemonic::parse_in_normalized(Language::English, &words.join(" ")).is_ok()
+}
+
+/// `words` with the word at `index` replaced, when that makes a valid mnemonic
+fn replace_word(words: &[String], index: usize, replacement: &str) -> Option<SeedCorrection> {
+    let mut candidate = words.to_vec();
+    let original = std::mem::replace(&mut candidate[index], replacement.to_string());
+    is_valid_mnemonic(&candidate).then(|| SeedCorrection {
+        position: index + 1,
+        original: Some(original),
+        replacement: replacement.to_string(),
+        mnemonic: candidate.join(" "),
+    })
+}
+
+/// Closest wordlist words to `word`; BIP39 words are unique in their first four
+/// letters, so a matching prefix comes first
+fn suggest_words(word: &str) -> Vec<String> {
+    let prefix: String = word.chars().take(4).collect();
+    let mut candidates: Vec<(usize, &str)> = Language::English
+        .word_list()
+        .iter()
+        .map(|candidate| {
+            let distance = if prefix.len() == 4 && candidate.starts_with(&prefix) {
+                0
+            } else {
+                edit_distance(candidate, word)
+            };
+            (distance, *candidate)
+        })
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .collect();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Big-endian bytes as 10-bit words, left-padded with zero bits
+fn bytes_to_words(bytes: &[u8]) -> Vec<u16> {
+    let word_count = (bytes.len() * 8).div_ceil(RADIX_BITS);
+    let mut bits = word_count * RADIX_BITS - bytes.len() * 8;
+    let mut accumulator = 0u32;
+    let mut words = Vec::with_capacity(word_count);
+    for &byte in bytes {
+        accumulator = (accumulator << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= RADIX_BITS {
+            bits -= RADIX_BITS;
+            words.push(((accumulator >> bits) as u16) & (RADIX - 1));
+        }
+        accumulator &= (1 << bits) - 1;
+    }
+    words
+}
+
+/// Inverse of [`bytes_to_words`]; the `padding` leading bits must be zero
+fn words_to_bytes(words: &[u16], padding: usize) -> Result<Vec<u8>> {
+    let mut skip = padding;
+    let mut bits = 0;
+    let mut accumulator = 0u32;
+    let mut bytes = Vec::with_capacity((words.len() * RADIX_BITS - padding) / 8);
+    for &word in words {
+        accumulator = (accumulator << RADIX_BITS) | u32::from(word);
+        bits += RADIX_BITS;
+        if skip > 0 {
+            ensure!(
+                accumulator >> (bits - skip) == 0,
+                "Invalid SLIP-39 share padding"
+            );
+            bits -= skip;
+            accumulator &= (1 << bits) - 1;
+            skip = 0;
+        }
+        while bits >= 8 {
+            bits -= 8;
+            bytes.push((accumulator >> bits) as u8);
+        }
+        accumulator &= (1 << bits) - 1;
+    }
+    Ok(bytes)
+}
+
+fn customization(extendable: bool) -> &'static str {
+    if extendable {
+        CUSTOMIZATION_EXTENDABLE
+    } else {
+        CUSTOMIZATION
+    }
+}
+
+fn rs1024_polymod(values: impl Iterator<Item = u16>) -> u32 {
+    const GENERATOR: [u32; 10] = [
+        0xE0E040, 0x1C1C080, 0x3838100, 0x7070200, 0xE0E0009, 0x1C0C2412, 0x38086C24, 0x3090FC48,
+        0x21B1F890, 0x3F3F120,
+    ];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 20;
+        checksum = ((checksum & 0xFFFFF) << RADIX_BITS) ^ u32::from(value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn create_checksum(customization: &str, data: &[u16]) -> [u16; CHECKSUM_WORDS] {
+    let values = customization
+        .bytes()
+        .map(u16::from)
+        .chain(data.iter().copied())
+        .chain([0; CHECKSUM_WORDS]);
+    let polymod = rs1024_polymod(values) ^ 1;
+    [2, 1, 0].map(|i| ((polymod >> (RADIX_BITS * i)) as u16) & (RADIX - 1))
+}
+
+fn verify_checksum(customization: &str, words: &[u16]) -> bool {
+    let values = customization
+        .bytes()
+        .map(u16::from)
+        .chain(words.iter().copied());
+    rs1024_polymod(values) == 1
+}
+
+/// Exponent and logarithm tables of GF(256) with the Rijndael polynomial
+static GF256: LazyLock<([u8; 255], [u8; 256])> = LazyLock::new(|| {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut value = 1u16;
+    for (i, entry) in exp.iter_mut().enumerate() {
+        *entry = value as u8;
+        log[usize::from(value)] = i as u8;
+        value = (value << 1) ^ value;
+        if value & 0x100 != 0 {
+            value ^= 0x11B;
+        }
+    }
+    (exp, log)
+});
+
+/// Value at `x` of the polynomial through `shares` (Lagrange interpolation)
+fn interpolate(shares: &[(u8, Vec<u8>)], x: u8) -> Result<Vec<u8>> {
+    if let Some((_, value)) = shares.iter().find(|(index, _)| *index == x) {
+        return Ok(value.clone());
+    }
+    let length = shares.first().context("No shares to interpolate")?.1.len();
+    ensure!(
+        shares.iter().all(|(_, value)| value.len() == length),
+        "All share values must have the same length"
+    );
+
+    let (exp, log) = &*GF256;
+    let log_of = |value: u8| i64::from(log[usize::from(value)]);
+    let log_product: i64 = shares.iter().map(|(index, _)| log_of(index ^ x)).sum();
+
+    let mut result = vec![0u8; length];
+    for (index, value) in shares {
+        let log_basis = (log_product
+            - log_of(index ^ x)
+            - shares
+                .iter()
+                .filter(|(other, _)| other != index)
+                .map(|(other, _)| log_of(index ^ other))
+                .sum::<i64>())
+        .rem_euclid(255);
+        for (byte, &share_byte) in result.iter_mut().zip(value) {
+            if share_byte != 0 {
+                *byte ^= exp[((log_of(share_byte) + log_basis) % 255) as usize];
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn split_secret(threshold: u8, count: u8, secret: &[u8]) -> Result<Vec<(u8, Vec<u8>)>> {
+    ensure!(
+        threshold >= 1 && threshold <= count && count <= MAX_SHARE_COUNT,
+        "Invalid sharing: {threshold} of {count}; the threshold must be between 1 and the count, at most {MAX_SHARE_COUNT}"
+    );
+    if threshold == 1 {
+        return Ok((0..count).map(|index| (index, secret.to_vec())).collect());
+    }
+
+    let mut rng = rand::rng();
+    let mut shares: Vec<(u8, Vec<u8>)> = (0..threshold - 2)
+        .map(|index| {
+            let mut value = vec![0u8; secret.len()];
+            rng.fill(&mut value[..]);
+            (index, value)
+        })
+        .collect();
+    let mut random_part = vec![0u8; secret.len() - DIGEST_LENGTH];
+    rng.fill(&mut random_part[..]);
+    let mut digest_share = digest(&random_part, secret).to_vec();
+    digest_share.extend_from_slice(&random_part);
+
+    let mut base = shares.clone();
+    base.push((DIGEST_INDEX, digest_share));
+    base.push((SECRET_INDEX, secret.to_vec()));
+    for index in threshold - 2..count {
+        shares.push((index, interpolate(&base, index)?));
+    }
+    Ok(shares)
+}
+
+fn recover_secret(threshold: u8, shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>> {
+    if threshold == 1 {
+        return Ok(shares.first().context("No shares given")?.1.clone());
+    }
+    let secret = interpolate(shares, SECRET_INDEX)?;
+    let digest_share = interpolate(shares, DIGEST_INDEX)?;
+    ensure!(
+        digest_share[..DIGEST_LENGTH] == digest(&digest_share[DIGEST_LENGTH..], &secret),
+        "Invalid digest of the shared secret: the shares do not belong together"
+    );
+    Ok(secret)
+}
+
+fn digest(random_part: &[u8], secret: &[u8]) -> [u8; DIGEST_LENGTH] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(random_part);
+    engine.input(secret);
+    let mac = hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+    let mut digest = [0u8; DIGEST_LENGTH];
+    digest.copy_from_slice(&mac[..DIGEST_LENGTH]);
+    digest
+}
+
+fn salt(identifier: u16, extendable: bool) -> Vec<u8> {
+    if extendable {
+        Vec::new()
+    } else {
+        let mut salt = CUSTOMIZATION.as_bytes().to_vec();
+        salt.extend_from_slice(&identifier.to_be_bytes());
+        salt
+    }
+}
+
+/// Four-round Feistel cipher keyed by the passphrase
+fn feistel(
+    input: &[u8],
+    passphrase: &str,
+    iteration_exponent: u8,
+    salt: &[u8],
+    encrypt: bool,
+) -> Result<Vec<u8>> {
+    ensure!(
+        passphrase.bytes().all(|b| (32..=126).contains(&b)),
+        "The SLIP-39 passphrase must contain only printable ASCII characters"
+    );
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / u32::from(ROUND_COUNT);
+    let (left, right) = input.split_at(input.len() / 2);
+    let (mut left, mut right) = (left.to_vec(), right.to_vec());
+
+    let rounds: Vec<u8> = if encrypt {
+        (0..ROUND_COUNT).collect()
+    } else {
+        (0..ROUND_COUNT).rev().collect()
+    };
+    for round in rounds {
+        let mut password = vec![round];
+        password.extend_from_slice(passphrase.as_bytes());
+        let mut round_salt = salt.to_vec();
+        round_salt.extend_from_slice(&right);
+
+        let mut key = vec![0u8; right.len()];
+        pbkdf2_sha256(&password, &round_salt, iterations, &mut key);
+        let next = left.iter().zip(&key).map(|(a, b)| a ^ b).collect();
+        left = std::mem::replace(&mut right, next);
+    }
+    right.extend_from_slice(&left);
+    Ok(right)
+}
+
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    for (block, chunk) in output.chunks_mut(32).enumerate() {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(password);
+        engine.input(salt);
+        engine.input(&(block as u32 + 1).to_be_bytes());
+        let mut u = hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+        let mut t = u;
+        for _ in 1..iterations {
+            let mut engine = hmac::HmacEngine::<sha256::Hash>::new(password);
+            engine.input(&u);
+            u = hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+            t.iter_mut().zip(&u).for_each(|(t, u)| *t ^= u);
+        }
+        chunk.copy_from_slice(&t[..chunk.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shares(mnemonics: &[&str]) -> Vec<String> {
+        mnemonics.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_slip39_vector_single_share() -> Result<()> {
+        let secret = slip39_recover(
+            &shares(&[
+                "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard",
+            ]),
+            "TREZOR",
+        )?;
+        assert_eq!(hex::encode(secret), "bb54aac4b89dc868ba37d9cc21b2cece");
+        Ok(())
+    }
+
+    #[test]
+    fn test_slip39_vector_two_of_three() -> Result<()> {
+        let secret = slip39_recover(
+            &shares(&[
+                "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+                "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+            ]),
+            "TREZOR",
+        )?;
+        assert_eq!(hex::encode(secret), "b43ceb7e57a0ea8766221624d01b0864");
+        Ok(())
+    }
+
+    #[test]
+    fn test_slip39_vector_invalid_checksum() {
+        assert!(
+            slip39_recover(
+                &shares(&[
+                    "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney",
+                ]),
+                "TREZOR",
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_slip39_split_and_recover_groups() -> Result<()> {
+        let secret =
+            hex::decode("00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff")?;
+        let groups = [
+            Slip39Group::from_str("1of1")?,
+            Slip39Group::from_str("2of3")?,
+            Slip39Group::from_str("3-of-5")?,
+        ];
+        let backup = slip39_split(&secret, "pass", 2, &groups, 0)?;
+        assert_eq!(backup.groups.len(), 3);
+        assert_eq!(backup.groups[2].shares.len(), 5);
+        assert!(
+            backup.groups[1].shares[0].split_whitespace().count() == 33,
+            "256-bit secrets use 33-word shares"
+        );
+
+        let mut selected = vec![backup.groups[0].shares[0].clone()];
+        selected.extend(backup.groups[2].shares[1..4].iter().cloned());
+        assert_eq!(slip39_recover(&selected, "pass")?, secret);
+
+        // A wrong passphrase yields a different secret rather than an error
+        assert_ne!(slip39_recover(&selected, "other")?, secret);
+
+        // Two shares of the 3of5 group are not enough
+        let too_few = vec![
+            backup.groups[0].shares[0].clone(),
+            backup.groups[2].shares[0].clone(),
+            backup.groups[2].shares[1].clone(),
+        ];
+        assert!(slip39_recover(&too_few, "pass").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_slip39_rejects_invalid_parameters() -> Result<()> {
+        let secret = [7u8; 16];
+        assert!(slip39_split(&secret, "", 1, &[Slip39Group::from_str("1of2")?], 0).is_err());
+        assert!(slip39_split(&secret, "", 2, &[Slip39Group::from_str("2of3")?], 0).is_err());
+        assert!(slip39_split(&[7u8; 15], "", 1, &[Slip39Group::from_str("2of3")?], 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seedxor_coldcard_vector() -> Result<()> {
+        let combined = seedxor_combine(&shares(&[
+            "romance wink lottery autumn shop bring dawn tongue range crater truth ability miss spice fitness easy legal release recall obey exchange recycle dragon room",
+            "lion misery divide hurry latin fluid camp advance illegal lab pyramid unaware eager fringe sick camera series noodle toy crowd jeans select depth lounge",
+            "vault nominee cradle silk own frown throw leg cactus recall talent worry gadget surface shy planet purpose coffee drip few seven term squeeze educate",
+        ]))?;
+        assert_eq!(
+            combined,
+            "silent toe meat possible chair blossom wait occur this worth option bag nurse find fish scene bench asthma bike wage world quit primary indoor"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_seedxor_split_and_combine() -> Result<()> {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let parts = seedxor_split(mnemonic, 3)?;
+        assert_eq!(parts.len(), 3);
+        assert!(
+            parts
+                .iter()
+                .all(|part| part.split_whitespace().count() == 12)
+        );
+        assert_eq!(seedxor_combine(&parts)?, mnemonic);
+        assert_ne!(seedxor_combine(&parts[..2])?, mnemonic);
+        assert!(seedxor_split(mnemonic, 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seedxor_rejects_invalid_share() -> Result<()> {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let mut parts = seedxor_split(mnemonic, 2)?;
+        parts[1] = parts[1].replacen(' ', " abandon ", 1);
+        assert!(seedxor_combine(&parts).is_err());
+        Ok(())
+    }
+
+    const ABANDON_ABOUT: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn test_check_valid_mnemonic() -> Result<()> {
+        let check = check_mnemonic(ABANDON_ABOUT, None)?;
+        assert!(check.valid);
+        assert_eq!(check.checksum_valid, Some(true));
+        assert!(check.corrections.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_misspelled_word() -> Result<()> {
+        let check = check_mnemonic(&ABANDON_ABOUT.replace("about", "abuot"), None)?;
+        assert!(!check.valid);
+        assert_eq!(check.unknown_words.len(), 1);
+        assert_eq!(check.unknown_words[0].position, 12);
+        assert!(
+            check.unknown_words[0]
+                .suggestions
+                .contains(&"about".to_string())
+        );
+        assert_eq!(check.corrections[0].mnemonic, ABANDON_ABOUT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_bad_checksum() -> Result<()> {
+        let check = check_mnemonic(&ABANDON_ABOUT.replace("about", "above"), None)?;
+        assert_eq!(check.checksum_valid, Some(false));
+        assert!(
+            check
+                .corrections
+                .iter()
+                .any(|correction| correction.mnemonic == ABANDON_ABOUT)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_missing_word() -> Result<()> {
+        let words: Vec<&str> = ABANDON_ABOUT.split_whitespace().collect();
+        let missing = words[..11].join(" ");
+        let check = check_mnemonic(&missing, Some(12))?;
+        assert!(
+            check
+                .corrections
+                .iter()
+                .all(|correction| correction.position == 12)
+        );
+        assert!(
+            check
+                .corrections
+                .iter()
+                .any(|correction| correction.mnemonic == ABANDON_ABOUT)
+        );
+        assert!(check_mnemonic(&missing, Some(13)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("about", "about"), 0);
+        assert_eq!(edit_distance("about", "abuot"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_bip39_roundtrip() -> Result<()> {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let backup = split_bip39_mnemonic(mnemonic, "", 1, &[Slip39Group::from_str("2of3")?], 0)?;
+        let recovered = recover_bip39_mnemonic(&backup.groups[0].shares[1..], "")?;
+        assert_eq!(recovered.mnemonic.as_deref(), Some(mnemonic));
+        Ok(())
+    }
+}
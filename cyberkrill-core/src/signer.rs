@@ -0,0 +1,412 @@
+//! Offline software signer for PSBTs
+//!
+//! Keys come from a BIP39 mnemonic (with optional passphrase), an xprv, or a
+//! descriptor holding xprvs or WIF keys. Inputs are matched through the key
+//! origins of the PSBT (`bip32_derivation` and `tap_key_origins`), so the
+//! watch-only PSBTs built by cyberkrill can be signed without a hardware
+//! wallet, and the whole build → sign → finalize pipeline can be tested.
+
+use crate::error::{CoreResult, bail, ensure};
+use anyhow::{Context, Result};
+use bdk_wallet::miniscript::descriptor::{DescriptorSecretKey, KeyMap};
+use bdk_wallet::miniscript::psbt::PsbtExt;
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey};
+use bitcoin::bip32::{self, DerivationPath, Fingerprint, KeySource, Xpriv};
+use bitcoin::psbt::{GetKey, KeyRequest, Psbt, SigningKeys};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::{Network, NetworkKind, PrivateKey};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Extended private key with the origin of its derivation
+#[derive(Debug, Clone)]
+struct OriginXpriv {
+    /// Master fingerprint of the origin (the key's own fingerprint without one)
+    fingerprint: Fingerprint,
+    /// Path from the master key to `xpriv`
+    path: DerivationPath,
+    xpriv: Xpriv,
+}
+
+/// Private keys used to sign PSBTs in this process
+#[derive(Debug, Clone, Default)]
+pub struct SoftwareSigner {
+    xprivs: Vec<OriginXpriv>,
+    keys: Vec<PrivateKey>,
+}
+
+/// Result of signing a PSBT with [`SoftwareSigner`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SoftwareSignOutput {
+    /// Master fingerprints of the signing keys
+    pub fingerprints: Vec<String>,
+    /// Indexes of the inputs that received a signature
+    pub signed_inputs: Vec<usize>,
+    pub psbt_base64: String,
+    pub psbt_hex: String,
+    /// Whether every input is finalized
+    pub is_complete: bool,
+    /// Extracted transaction, when finalizing completed every input
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_hex: Option<String>,
+}
+
+impl SoftwareSigner {
+    /// Signer for the master key of a BIP39 mnemonic and passphrase
+    pub fn from_mnemonic(mnemonic: &str, passphrase: &str, network: Network) -> CoreResult<Self> {
+        let mnemonic =
+            bip39::Mnemonic::parse_normalized(mnemonic.trim()).context("Invalid BIP39 mnemonic")?;
+        let seed = mnemonic.to_seed_normalized(passphrase);
+        let master = Xpriv::new_master(network, &seed).context("Failed to derive master key")?;
+        Ok(Self::from_master(master))
+    }
+
+    /// Signer for an xprv (or tprv), treated as a master key
+    pub fn from_xprv(xprv: &str, network: Network) -> CoreResult<Self> {
+        let xpriv = Xpriv::from_str(xprv.trim()).context("Invalid extended private key")?;
+        ensure_network(xpriv.network, network)?;
+        Ok(Self::from_master(xpriv))
+    }
+
+    /// Signer for the private keys of a descriptor; `[fingerprint/path]xprv`
+    /// origins let account-level keys sign for the master fingerprint
+    pub fn from_descriptor(descriptor: &str, network: Network) -> CoreResult<Self> {
+        let secp = Secp256k1::new();
+        let (_, key_map): (Descriptor<DescriptorPublicKey>, KeyMap) =
+            Descriptor::parse_descriptor(&secp, descriptor.trim())
+                .context("Failed to parse descriptor")?;
+        ensure!(!key_map.is_empty(), "Descriptor has no private keys");
+
+        let mut signer = Self::default();
+        for secret in key_map.into_values() {
+            match secret {
+                DescriptorSecretKey::Single(single) => {
+                    ensure_network(single.key.network, network)?;
+                    signer.keys.push(single.key);
+                }
+                DescriptorSecretKey::XPrv(xkey) => {
+                    ensure_network(xkey.xkey.network, network)?;
+                    signer
+                        .xprivs
+                        .push(origin_xpriv(&secp, xkey.origin, xkey.xkey));
+                }
+                DescriptorSecretKey::MultiXPrv(xkey) => {
+                    ensure_network(xkey.xkey.network, network)?;
+                    signer
+                        .xprivs
+                        .push(origin_xpriv(&secp, xkey.origin, xkey.xkey));
+                }
+            }
+        }
+        Ok(signer)
+    }
+
+    fn from_master(master: Xpriv) -> Self {
+        let secp = Secp256k1::new();
+        Self {
+            xprivs: vec![origin_xpriv(&secp, None, master)],
+            keys: Vec::new(),
+        }
+    }
+
+    /// Master fingerprints of the extended keys
+    pub fn fingerprints(&self) -> Vec<Fingerprint> {
+        let mut fingerprints: Vec<_> = self.xprivs.iter().map(|x| x.fingerprint).collect();
+        fingerprints.dedup();
+        fingerprints
+    }
+
+    /// Add every signature this signer can make to `psbt`, returning the
+    /// indexes of the inputs that were signed
+    pub fn sign(&self, psbt: &mut Psbt) -> CoreResult<Vec<usize>> {
+        let secp = Secp256k1::new();
+        let used = match psbt.sign(self, &secp) {
+            Ok(used) => used,
+            Err((_, errors)) => {
+                let errors = errors
+                    .iter()
+                    .map(|(index, error)| format!("input {index}: {error}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                bail!("Failed to sign PSBT: {errors}");
+            }
+        };
+        Ok(used
+            .into_iter()
+            .filter(|(_, keys)| match keys {
+                SigningKeys::Ecdsa(keys) => !keys.is_empty(),
+                SigningKeys::Schnorr(keys) => !keys.is_empty(),
+            })
+            .map(|(index, _)| index)
+            .collect())
+    }
+}
+
+impl GetKey for SoftwareSigner {
+    type Error = bip32::Error;
+
+    fn get_key<C: Signing>(
+        &self,
+        key_request: KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<PrivateKey>, Self::Error> {
+        match key_request {
+            KeyRequest::Bip32(key_source) => {
+                for xpriv in &self.xprivs {
+                    if let Some(key) = xpriv.derive(&key_source, secp)? {
+                        return Ok(Some(key));
+                    }
+                }
+                Ok(None)
+            }
+            KeyRequest::Pubkey(public_key) => Ok(self
+                .keys
+                .iter()
+                .find(|key| key.public_key(secp) == public_key)
+                .copied()),
+            KeyRequest::XOnlyPubkey(xonly) => Ok(self
+                .keys
+                .iter()
+                .find(|key| key.inner.x_only_public_key(secp).0 == xonly)
+                .copied()),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl OriginXpriv {
+    /// Private key for `key_source` when it lies below this key
+    fn derive<C: Signing>(
+        &self,
+        (fingerprint, path): &KeySource,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<PrivateKey>, bip32::Error> {
+        if *fingerprint != self.fingerprint {
+            return Ok(None);
+        }
+        let Some(rest) = path.as_ref().strip_prefix(self.path.as_ref()) else {
+            return Ok(None);
+        };
+        let child = self.xpriv.derive_priv(secp, &DerivationPath::from(rest))?;
+        Ok(Some(child.to_priv()))
+    }
+}
+
+fn origin_xpriv<C: Signing>(
+    secp: &Secp256k1<C>,
+    origin: Option<KeySource>,
+    xpriv: Xpriv,
+) -> OriginXpriv {
+    let (fingerprint, path) =
+        origin.unwrap_or_else(|| (xpriv.fingerprint(secp), DerivationPath::master()));
+    OriginXpriv {
+        fingerprint,
+        path,
+        xpriv,
+    }
+}
+
+fn ensure_network(key_network: NetworkKind, network: Network) -> Result<()> {
+    if key_network != NetworkKind::from(network) {
+        let key_network = match key_network {
+            NetworkKind::Main => "mainnet",
+            NetworkKind::Test => "testnet, signet or regtest",
+        };
+        bail!("Private key is for {key_network} but the network is {network}");
+    }
+    Ok(())
+}
+
+/// Sign a serialized PSBT with `signer`, optionally finalizing it
+pub fn sign_psbt_with_software(
+    signer: &SoftwareSigner,
+    psbt_data: &[u8],
+    finalize: bool,
+) -> CoreResult<SoftwareSignOutput> {
+    let mut psbt = Psbt::deserialize(psbt_data).context("Failed to deserialize PSBT")?;
+    let signed_inputs = signer.sign(&mut psbt)?;
+    ensure!(
+        !signed_inputs.is_empty(),
+        "None of the PSBT inputs belong to the signing keys (fingerprints: {fingerprints})",
+        fingerprints = signer
+            .fingerprints()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    if finalize {
+        let secp = Secp256k1::verification_only();
+        psbt.finalize_mut(&secp).map_err(|errors| {
+            let errors = errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::anyhow!("Failed to finalize PSBT: {errors}")
+        })?;
+    }
+
+    let is_complete = psbt
+        .inputs
+        .iter()
+        .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some());
+    let tx_hex = if is_complete {
+        let tx = psbt
+            .clone()
+            .extract_tx()
+            .context("Failed to extract the finalized transaction")?;
+        Some(bitcoin::consensus::encode::serialize_hex(&tx))
+    } else {
+        None
+    };
+
+    Ok(SoftwareSignOutput {
+        fingerprints: signer
+            .fingerprints()
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        signed_inputs,
+        psbt_base64: psbt.to_string(),
+        psbt_hex: hex::encode(psbt.serialize()),
+        is_complete,
+        tx_hex,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::key::CompressedPublicKey;
+    use bitcoin::{
+        Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Witness, absolute, transaction,
+    };
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    fn unsigned_psbt(script_pubkey: ScriptBuf) -> Result<Psbt> {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::from_str(
+                    "0000000000000000000000000000000000000000000000000000000000000001:0",
+                )?,
+                witness: Witness::new(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(99_000),
+                script_pubkey: script_pubkey.clone(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        });
+        Ok(psbt)
+    }
+
+    fn p2wpkh_psbt(master: &Xpriv, path: &str) -> Result<Psbt> {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str(path)?;
+        let key = master.derive_priv(&secp, &path)?.to_priv();
+        let pubkey = CompressedPublicKey::from_private_key(&secp, &key)?;
+
+        let mut psbt = unsigned_psbt(ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()))?;
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(pubkey.0, (master.fingerprint(&secp), path));
+        Ok(psbt)
+    }
+
+    fn test_master() -> Result<Xpriv> {
+        let seed = bip39::Mnemonic::parse_normalized(MNEMONIC)?.to_seed_normalized("");
+        Ok(Xpriv::new_master(Network::Regtest, &seed)?)
+    }
+
+    #[test]
+    fn test_mnemonic_signs_and_finalizes_p2wpkh() -> Result<()> {
+        let psbt = p2wpkh_psbt(&test_master()?, "m/84'/1'/0'/0/0")?;
+        let signer = SoftwareSigner::from_mnemonic(MNEMONIC, "", Network::Regtest)?;
+        assert_eq!(
+            signer.fingerprints(),
+            vec![Fingerprint::from_str("73c5da0a")?]
+        );
+
+        let output = sign_psbt_with_software(&signer, &psbt.serialize(), true)?;
+        assert_eq!(output.signed_inputs, vec![0]);
+        assert!(output.is_complete);
+        assert!(output.tx_hex.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_without_finalize_adds_partial_sig() -> Result<()> {
+        let psbt = p2wpkh_psbt(&test_master()?, "m/84'/1'/0'/0/3")?;
+        let signer = SoftwareSigner::from_mnemonic(MNEMONIC, "", Network::Regtest)?;
+
+        let output = sign_psbt_with_software(&signer, &psbt.serialize(), false)?;
+        assert!(!output.is_complete);
+        let signed = Psbt::from_str(&output.psbt_base64)?;
+        assert_eq!(signed.inputs[0].partial_sigs.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mnemonic_signs_taproot_key_path() -> Result<()> {
+        let secp = Secp256k1::new();
+        let master = test_master()?;
+        let path = DerivationPath::from_str("m/86'/1'/0'/0/0")?;
+        let key = master.derive_priv(&secp, &path)?.to_priv();
+        let (xonly, _) = key.inner.x_only_public_key(&secp);
+
+        let mut psbt = unsigned_psbt(ScriptBuf::new_p2tr(&secp, xonly, None))?;
+        psbt.inputs[0].tap_internal_key = Some(xonly);
+        psbt.inputs[0]
+            .tap_key_origins
+            .insert(xonly, (Vec::new(), (master.fingerprint(&secp), path)));
+
+        let signer = SoftwareSigner::from_mnemonic(MNEMONIC, "", Network::Regtest)?;
+        let output = sign_psbt_with_software(&signer, &psbt.serialize(), true)?;
+        assert!(output.is_complete);
+        Ok(())
+    }
+
+    #[test]
+    fn test_descriptor_account_xprv_matches_master_origin() -> Result<()> {
+        let secp = Secp256k1::new();
+        let master = test_master()?;
+        let account_path = DerivationPath::from_str("m/84'/1'/0'")?;
+        let account = master.derive_priv(&secp, &account_path)?;
+        let descriptor = format!(
+            "wpkh([{fingerprint}/84'/1'/0']{account}/0/*)",
+            fingerprint = master.fingerprint(&secp)
+        );
+
+        let signer = SoftwareSigner::from_descriptor(&descriptor, Network::Regtest)?;
+        let psbt = p2wpkh_psbt(&master, "m/84'/1'/0'/0/7")?;
+        let output = sign_psbt_with_software(&signer, &psbt.serialize(), true)?;
+        assert!(output.is_complete);
+        Ok(())
+    }
+
+    #[test]
+    fn test_foreign_psbt_is_rejected() -> Result<()> {
+        let psbt = p2wpkh_psbt(&test_master()?, "m/84'/1'/0'/0/0")?;
+        let signer = SoftwareSigner::from_mnemonic(MNEMONIC, "other", Network::Regtest)?;
+        assert!(sign_psbt_with_software(&signer, &psbt.serialize(), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_xprv_network_mismatch() -> Result<()> {
+        let master = test_master()?;
+        assert!(SoftwareSigner::from_xprv(&master.to_string(), Network::Bitcoin).is_err());
+        assert!(SoftwareSigner::from_xprv(&master.to_string(), Network::Regtest).is_ok());
+        Ok(())
+    }
+}
@@ -1,9 +1,14 @@
 // SLIP-0132 extended public key format support
 // Adapted from frozenkrill-core for Trezor compatibility
 
-use anyhow::{Result, bail};
+use crate::error::{CoreResult, bail, ensure};
+use anyhow::{Context, Result};
 use bitcoin::base58;
 use bitcoin::bip32::Xpub;
+use bitcoin::{Network, NetworkKind};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
 
 /// Magical version bytes for xpub: bitcoin mainnet public key for P2PKH or P2SH
 pub const VERSION_MAGIC_XPUB: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
@@ -19,16 +24,25 @@ pub const VERSION_MAGIC_UPUB: [u8; 4] = [0x04, 0x4A, 0x52, 0x62];
 /// Magical version bytes for vpub: bitcoin testnet/regtest public key for P2WPKH
 pub const VERSION_MAGIC_VPUB: [u8; 4] = [0x04, 0x5F, 0x1C, 0xF6];
 
+/// Magical version bytes for Ypub: bitcoin mainnet public key for multi-signature P2WSH in P2SH
+pub const VERSION_MAGIC_YPUB_MULTISIG: [u8; 4] = [0x02, 0x95, 0xB4, 0x3F];
+/// Magical version bytes for Zpub: bitcoin mainnet public key for multi-signature P2WSH
+pub const VERSION_MAGIC_ZPUB_MULTISIG: [u8; 4] = [0x02, 0xAA, 0x7E, 0xD3];
+/// Magical version bytes for Upub: bitcoin testnet/regtest public key for multi-signature P2WSH in P2SH
+pub const VERSION_MAGIC_UPUB_MULTISIG: [u8; 4] = [0x02, 0x42, 0x89, 0xEF];
+/// Magical version bytes for Vpub: bitcoin testnet/regtest public key for multi-signature P2WSH
+pub const VERSION_MAGIC_VPUB_MULTISIG: [u8; 4] = [0x02, 0x57, 0x54, 0x83];
+
 /// Trait for building standard BIP32 extended keys from SLIP132 variant.
 pub trait FromSlip132 {
     /// Constructs standard BIP32 extended key from SLIP132 string.
-    fn from_slip132_str(s: &str) -> Result<Self>
+    fn from_slip132_str(s: &str) -> CoreResult<Self>
     where
         Self: Sized;
 }
 
 impl FromSlip132 for Xpub {
-    fn from_slip132_str(s: &str) -> Result<Self> {
+    fn from_slip132_str(s: &str) -> CoreResult<Self> {
         let mut data = base58::decode_check(s)?;
 
         let mut prefix = [0u8; 4];
@@ -37,9 +51,17 @@ impl FromSlip132 for Xpub {
         // Convert SLIP-0132 format to standard xpub/tpub
         let slice = match prefix {
             // Mainnet variants -> xpub
-            VERSION_MAGIC_XPUB | VERSION_MAGIC_YPUB | VERSION_MAGIC_ZPUB => VERSION_MAGIC_XPUB,
+            VERSION_MAGIC_XPUB
+            | VERSION_MAGIC_YPUB
+            | VERSION_MAGIC_ZPUB
+            | VERSION_MAGIC_YPUB_MULTISIG
+            | VERSION_MAGIC_ZPUB_MULTISIG => VERSION_MAGIC_XPUB,
             // Testnet variants -> tpub
-            VERSION_MAGIC_TPUB | VERSION_MAGIC_UPUB | VERSION_MAGIC_VPUB => VERSION_MAGIC_TPUB,
+            VERSION_MAGIC_TPUB
+            | VERSION_MAGIC_UPUB
+            | VERSION_MAGIC_VPUB
+            | VERSION_MAGIC_UPUB_MULTISIG
+            | VERSION_MAGIC_VPUB_MULTISIG => VERSION_MAGIC_TPUB,
             _ => bail!("Unknown SLIP-0132 prefix: {:?}", prefix),
         };
 
@@ -50,8 +72,212 @@ impl FromSlip132 for Xpub {
     }
 }
 
+/// SLIP-0132 extended public key prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slip132Format {
+    Xpub,
+    Ypub,
+    Zpub,
+    /// `Ypub`: multisig P2WSH in P2SH
+    YpubMultisig,
+    /// `Zpub`: multisig P2WSH
+    ZpubMultisig,
+    Tpub,
+    Upub,
+    Vpub,
+    /// `Upub`: testnet multisig P2WSH in P2SH
+    UpubMultisig,
+    /// `Vpub`: testnet multisig P2WSH
+    VpubMultisig,
+}
+
+impl Slip132Format {
+    const ALL: [Slip132Format; 10] = [
+        Slip132Format::Xpub,
+        Slip132Format::Ypub,
+        Slip132Format::Zpub,
+        Slip132Format::YpubMultisig,
+        Slip132Format::ZpubMultisig,
+        Slip132Format::Tpub,
+        Slip132Format::Upub,
+        Slip132Format::Vpub,
+        Slip132Format::UpubMultisig,
+        Slip132Format::VpubMultisig,
+    ];
+
+    /// The four version bytes
+    pub fn version(self) -> [u8; 4] {
+        match self {
+            Slip132Format::Xpub => VERSION_MAGIC_XPUB,
+            Slip132Format::Ypub => VERSION_MAGIC_YPUB,
+            Slip132Format::Zpub => VERSION_MAGIC_ZPUB,
+            Slip132Format::YpubMultisig => VERSION_MAGIC_YPUB_MULTISIG,
+            Slip132Format::ZpubMultisig => VERSION_MAGIC_ZPUB_MULTISIG,
+            Slip132Format::Tpub => VERSION_MAGIC_TPUB,
+            Slip132Format::Upub => VERSION_MAGIC_UPUB,
+            Slip132Format::Vpub => VERSION_MAGIC_VPUB,
+            Slip132Format::UpubMultisig => VERSION_MAGIC_UPUB_MULTISIG,
+            Slip132Format::VpubMultisig => VERSION_MAGIC_VPUB_MULTISIG,
+        }
+    }
+
+    /// Format with the given version bytes
+    pub fn from_version(version: [u8; 4]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.version() == version)
+    }
+
+    pub fn network(self) -> NetworkKind {
+        match self {
+            Slip132Format::Xpub
+            | Slip132Format::Ypub
+            | Slip132Format::Zpub
+            | Slip132Format::YpubMultisig
+            | Slip132Format::ZpubMultisig => NetworkKind::Main,
+            _ => NetworkKind::Test,
+        }
+    }
+
+    /// Script type the prefix implies
+    pub fn script_type(self) -> &'static str {
+        match self {
+            Slip132Format::Xpub | Slip132Format::Tpub => "p2pkh or p2sh",
+            Slip132Format::Ypub | Slip132Format::Upub => "p2sh-p2wpkh",
+            Slip132Format::Zpub | Slip132Format::Vpub => "p2wpkh",
+            Slip132Format::YpubMultisig | Slip132Format::UpubMultisig => "p2sh-p2wsh multisig",
+            Slip132Format::ZpubMultisig | Slip132Format::VpubMultisig => "p2wsh multisig",
+        }
+    }
+
+    /// The same script type on the other network
+    fn on_network(self, network: NetworkKind) -> Self {
+        use Slip132Format::*;
+        match (self, network) {
+            (Xpub | Tpub, NetworkKind::Main) => Xpub,
+            (Ypub | Upub, NetworkKind::Main) => Ypub,
+            (Zpub | Vpub, NetworkKind::Main) => Zpub,
+            (YpubMultisig | UpubMultisig, NetworkKind::Main) => YpubMultisig,
+            (ZpubMultisig | VpubMultisig, NetworkKind::Main) => ZpubMultisig,
+            (Xpub | Tpub, NetworkKind::Test) => Tpub,
+            (Ypub | Upub, NetworkKind::Test) => Upub,
+            (Zpub | Vpub, NetworkKind::Test) => Vpub,
+            (YpubMultisig | UpubMultisig, NetworkKind::Test) => UpubMultisig,
+            (ZpubMultisig | VpubMultisig, NetworkKind::Test) => VpubMultisig,
+        }
+    }
+}
+
+impl fmt::Display for Slip132Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Slip132Format::Xpub => "xpub",
+            Slip132Format::Ypub => "ypub",
+            Slip132Format::Zpub => "zpub",
+            Slip132Format::YpubMultisig => "Ypub",
+            Slip132Format::ZpubMultisig => "Zpub",
+            Slip132Format::Tpub => "tpub",
+            Slip132Format::Upub => "upub",
+            Slip132Format::Vpub => "vpub",
+            Slip132Format::UpubMultisig => "Upub",
+            Slip132Format::VpubMultisig => "Vpub",
+        })
+    }
+}
+
+impl FromStr for Slip132Format {
+    type Err = anyhow::Error;
+
+    /// Parse a prefix name; case matters, `zpub` and `Zpub` are different formats
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.to_string() == s)
+            .with_context(|| {
+                format!(
+                    "Unknown SLIP-0132 format: {s}. Expected one of: xpub, ypub, zpub, Ypub, Zpub, tpub, upub, vpub, Upub, Vpub"
+                )
+            })
+    }
+}
+
+impl Serialize for Slip132Format {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Encode `xpub` with the version bytes of `format`
+pub fn to_slip132_string(xpub: &Xpub, format: Slip132Format) -> CoreResult<String> {
+    ensure!(
+        xpub.network == format.network(),
+        "Cannot encode a {network} key as {format}",
+        network = match xpub.network {
+            NetworkKind::Main => "mainnet",
+            NetworkKind::Test => "testnet",
+        }
+    );
+    let mut data = xpub.encode();
+    data[0..4].copy_from_slice(&format.version());
+    Ok(base58::encode_check(&data))
+}
+
+/// Result of converting an extended public key between SLIP-0132 prefixes
+#[derive(Debug, Clone, Serialize)]
+pub struct ConvertedXpub {
+    pub input_format: Slip132Format,
+    /// Script type implied by the input prefix
+    pub input_script_type: String,
+    pub output_format: Slip132Format,
+    pub output_script_type: String,
+    /// `bitcoin` or `testnet` (testnet prefixes also cover signet and regtest)
+    pub network: String,
+    /// Standard BIP32 form (`xpub`/`tpub`), as used in descriptors
+    pub standard: String,
+    pub converted: String,
+}
+
+/// Convert an extended public key to the prefix `to`, or to the standard
+/// `xpub`/`tpub` form when `to` is `None`. A mainnet target name given for a
+/// testnet key (or the reverse) selects the same script type on the key's network.
+pub fn convert_xpub(input: &str, to: Option<Slip132Format>) -> CoreResult<ConvertedXpub> {
+    let input = input.trim();
+    let data = base58::decode_check(input).context("Invalid extended public key encoding")?;
+    ensure!(
+        data.len() == 78,
+        "Extended public keys are 78 bytes, got {len}",
+        len = data.len()
+    );
+    let version: [u8; 4] = data[0..4].try_into()?;
+    let input_format = Slip132Format::from_version(version).with_context(|| {
+        format!(
+            "Unknown SLIP-0132 prefix: {version}",
+            version = hex::encode(version)
+        )
+    })?;
+    let xpub = Xpub::from_slip132_str(input)?;
+
+    let output_format = to
+        .unwrap_or(Slip132Format::Xpub)
+        .on_network(input_format.network());
+    let network = match xpub.network {
+        NetworkKind::Main => Network::Bitcoin,
+        NetworkKind::Test => Network::Testnet,
+    };
+
+    Ok(ConvertedXpub {
+        input_format,
+        input_script_type: input_format.script_type().to_string(),
+        output_format,
+        output_script_type: output_format.script_type().to_string(),
+        network: network.to_string(),
+        standard: xpub.to_string(),
+        converted: to_slip132_string(&xpub, output_format)?,
+    })
+}
+
 /// Helper function to convert any SLIP-0132 format to standard Xpub
-pub fn parse_slip132_xpub(xpub_str: &str) -> Result<Xpub> {
+pub fn parse_slip132_xpub(xpub_str: &str) -> CoreResult<Xpub> {
     // First try to parse as standard xpub/tpub
     if let Ok(xpub) = xpub_str.parse::<Xpub>() {
         return Ok(xpub);
@@ -81,6 +307,43 @@ mod tests {
         let zpub_str = "zpub6qUQGY8YyN3ZztQBDdN8gUrFNvgCdTdFyTNorQ79VfkfkmhMR6D4cHBZ4EnXdFog1e2ugyCJqTcyDE4ZpTGqcMiCEnyPEyJFKbPVL9knhKU";
         assert_eq!(Xpub::from_slip132_str(zpub_str)?, xpub);
 
+        // Zpub (P2WSH multisig, e.g. Coldcard exports) should convert to xpub
+        let zpub_multisig_str = "Zpub72NVPmrzYKbwRTZZAHq7WZC46iiTqpJrHj2UmfNgsSb5NxGGBVbLhQ3Urwk1Bh2aF76tZZCRig1ULPgL7gRnkqps5G5neNmFDKfMv51dh4F";
+        assert_eq!(Xpub::from_slip132_str(zpub_multisig_str)?, xpub);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_xpub() -> Result<()> {
+        let xpub_str = "xpub6BosfCnifzxcJJ1wYuntGJfF2zPJkDeG9ELNHcKNjezuea4tumswN9sH1psMdSVqCMoJC21Bv8usSeqSP4Sp1tLzW7aY59fGn9GCYzx5UTo";
+        let zpub_str = "zpub6qUQGY8YyN3ZztQBDdN8gUrFNvgCdTdFyTNorQ79VfkfkmhMR6D4cHBZ4EnXdFog1e2ugyCJqTcyDE4ZpTGqcMiCEnyPEyJFKbPVL9knhKU";
+        let zpub_multisig_str = "Zpub72NVPmrzYKbwRTZZAHq7WZC46iiTqpJrHj2UmfNgsSb5NxGGBVbLhQ3Urwk1Bh2aF76tZZCRig1ULPgL7gRnkqps5G5neNmFDKfMv51dh4F";
+
+        let converted = convert_xpub(xpub_str, Some(Slip132Format::Zpub))?;
+        assert_eq!(converted.input_format, Slip132Format::Xpub);
+        assert_eq!(converted.output_script_type, "p2wpkh");
+        assert_eq!(converted.converted, zpub_str);
+
+        let converted = convert_xpub(zpub_multisig_str, None)?;
+        assert_eq!(converted.input_format, Slip132Format::ZpubMultisig);
+        assert_eq!(converted.input_script_type, "p2wsh multisig");
+        assert_eq!(converted.converted, xpub_str);
+        assert_eq!(converted.standard, xpub_str);
+
+        let converted = convert_xpub(zpub_str, Some("Zpub".parse()?))?;
+        assert_eq!(converted.converted, zpub_multisig_str);
+
+        // A testnet key keeps its network: asking for zpub yields a vpub
+        let mut testnet = Xpub::from_str(xpub_str)?;
+        testnet.network = NetworkKind::Test;
+        let converted = convert_xpub(&testnet.to_string(), Some(Slip132Format::Zpub))?;
+        assert_eq!(converted.output_format, Slip132Format::Vpub);
+        assert!(converted.converted.starts_with("vpub"));
+        assert_eq!(converted.network, "testnet");
+
+        assert!("zPub".parse::<Slip132Format>().is_err());
+        assert!(convert_xpub("not-an-xpub", None).is_err());
         Ok(())
     }
 
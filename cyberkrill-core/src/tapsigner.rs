@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use tracing::info;
 
+use crate::error::{CoreResult, bail};
 #[cfg(test)]
 use crate::satscard::{SatscardAddressOutput, SatscardInfo};
 
@@ -38,7 +39,7 @@ pub struct TapsignerInitOutput {
     pub birth_block: usize,
 }
 
-pub async fn generate_tapsigner_address(path: &str) -> Result<TapsignerAddressOutput> {
+pub async fn generate_tapsigner_address(path: &str) -> CoreResult<TapsignerAddressOutput> {
     // Parse the derivation path and split into hardened/non-hardened parts
     let (hardened_path, non_hardened_path) = split_derivation_path(path)?;
 
@@ -79,14 +80,14 @@ pub async fn generate_tapsigner_address(path: &str) -> Result<TapsignerAddressOu
     })
 }
 
-pub async fn initialize_tapsigner(chain_code: Option<String>) -> Result<TapsignerInitOutput> {
+pub async fn initialize_tapsigner(chain_code: Option<String>) -> CoreResult<TapsignerInitOutput> {
     // Connect directly to TapSigner for initialization (bypasses wrapper)
     let mut tapsigner = connect_tapsigner_direct().await?;
 
     // Check if already initialized by looking at the path field
     // An uninitialized Tapsigner will have None for the path
     if let Some(existing_path) = &tapsigner.path {
-        anyhow::bail!(
+        bail!(
             "Tapsigner is already initialized. Current path: {:?}. Initialization can only be done once.",
             existing_path
         );
@@ -99,7 +100,7 @@ pub async fn initialize_tapsigner(chain_code: Option<String>) -> Result<Tapsigne
                 || "Invalid hex format for chain code. Must be 64 hex characters (32 bytes).",
             )?;
             if bytes.len() != 32 {
-                anyhow::bail!(
+                bail!(
                     "Chain code must be exactly 32 bytes (64 hex characters). Got {len} bytes.",
                     len = bytes.len()
                 );
@@ -136,9 +137,12 @@ pub async fn initialize_tapsigner(chain_code: Option<String>) -> Result<Tapsigne
         .with_context(|| "Failed to initialize Tapsigner. Make sure the card is properly connected and the CVC is correct.")?;
 
     // Verify initialization was successful by checking the status
-    let new_status = tapsigner.status().await?;
+    let new_status = tapsigner
+        .status()
+        .await
+        .context("Failed to read the Tapsigner status after initialization")?;
     if new_status.path.is_none() {
-        anyhow::bail!(
+        bail!(
             "Initialization appeared to succeed but card still shows uninitialized state. Please try again."
         );
     }
@@ -161,7 +165,7 @@ pub async fn initialize_tapsigner(chain_code: Option<String>) -> Result<Tapsigne
 fn parse_derivation_path(path: &str) -> Result<Vec<u32>> {
     // Simple derivation path parser (e.g., "m/84'/0'/0'/0/0")
     if !path.starts_with("m/") {
-        anyhow::bail!("Derivation path must start with 'm/'");
+        bail!("Derivation path must start with 'm/'");
     }
 
     let path_str = &path[2..]; // Remove "m/"
@@ -252,7 +256,7 @@ async fn connect_tapsigner() -> Result<TapsignerDevice<cktap_direct::usb_transpo
         CkTapCard::TapSigner(tapsigner) => Ok(TapsignerDevice::TapSigner(Box::new(tapsigner))),
         CkTapCard::SatsChip(tapsigner) => Ok(TapsignerDevice::TapSigner(Box::new(tapsigner))), // SatsChip is also a TapSigner
         _ => {
-            anyhow::bail!(
+            bail!(
                 "Found CkTap card but it's not a TapSigner. Make sure you're using a TapSigner card."
             )
         }
@@ -270,7 +274,7 @@ async fn connect_tapsigner_direct() -> Result<TapSigner<cktap_direct::usb_transp
         CkTapCard::TapSigner(tapsigner) => Ok(tapsigner),
         CkTapCard::SatsChip(tapsigner) => Ok(tapsigner), // SatsChip is also a TapSigner
         _ => {
-            anyhow::bail!(
+            bail!(
                 "Found CkTap card but it's not a TapSigner. Make sure you're using a TapSigner card."
             )
         }
@@ -285,13 +289,11 @@ fn get_cvc_from_env_or_prompt() -> Result<String> {
             return Ok(cvc_str);
         }
 
-        anyhow::bail!(
-            "Invalid PIN format. TAPSIGNER_CVC must be exactly 6 digits. Got: '{cvc_str}'"
-        );
+        bail!("Invalid PIN format. TAPSIGNER_CVC must be exactly 6 digits. Got: '{cvc_str}'");
     }
 
     // For now, return an error asking user to set environment variable
-    anyhow::bail!(
+    bail!(
         "PIN authentication required. Please set TAPSIGNER_CVC environment variable with your card's 6-digit PIN.
 Example: export TAPSIGNER_CVC=123456
 
@@ -328,7 +330,7 @@ fn create_xpub_from_result(result: &DeriveResult) -> Result<Xpub> {
 
     // Convert chain code to proper format (must be exactly 32 bytes)
     if result.chain_code.len() != 32 {
-        anyhow::bail!(
+        bail!(
             "Invalid chain code length: expected 32 bytes, got {len}",
             len = result.chain_code.len()
         );
@@ -358,7 +360,7 @@ fn software_derive_pubkey(xpub: &Xpub, path_components: &[u32]) -> Result<Vec<u8
     let mut path_str = "m".to_string();
     for &component in path_components {
         if component >= 0x80000000 {
-            anyhow::bail!("Non-hardened path contains hardened component: {component}");
+            bail!("Non-hardened path contains hardened component: {component}");
         }
         path_str.push_str(&format!("/{component}"));
     }
@@ -372,7 +374,7 @@ fn software_derive_pubkey(xpub: &Xpub, path_components: &[u32]) -> Result<Vec<u8
 fn calculate_fingerprint(pubkey: &[u8]) -> Result<String> {
     // Calculate the fingerprint (first 4 bytes of hash160 of the public key)
     if pubkey.len() != 33 {
-        anyhow::bail!(
+        bail!(
             "Invalid public key length for fingerprint: expected 33 bytes, got {pubkey_len}",
             pubkey_len = pubkey.len()
         );
@@ -386,7 +388,7 @@ fn calculate_fingerprint(pubkey: &[u8]) -> Result<String> {
 fn pubkey_to_address(pubkey: &[u8]) -> Result<String> {
     // Convert public key to Bitcoin address using proper Bitcoin libraries
     if pubkey.len() != 33 {
-        anyhow::bail!(
+        bail!(
             "Invalid public key length: expected 33 bytes, got {pubkey_len}",
             pubkey_len = pubkey.len()
         );
@@ -0,0 +1,378 @@
+//! Local relays in front of the BDK Electrum and Esplora clients
+//!
+//! BDK talks to Electrum and Esplora servers through its own blocking
+//! transports, which [`RpcTrace`] cannot wrap and whose Esplora client knows
+//! no SOCKS5 proxies. Those clients are pointed at a relay on 127.0.0.1
+//! instead: each request reaching the relay is forwarded to the real server
+//! (through the proxy, for Esplora) and recorded, or answered from the trace
+//! during replay.
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use bdk_electrum::electrum_client::{Client, ElectrumApi, Param};
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, OnceLock};
+use tracing::debug;
+
+use crate::client_options::ClientOptions;
+use crate::electrum::ElectrumServer;
+use crate::esplora::esplora_http_client;
+use crate::rpc_trace::{RpcTrace, redact};
+
+/// Serve the connections to a new local port with `handle`, one thread per
+/// connection, for the rest of the process
+pub(crate) fn spawn_relay(
+    name: &'static str,
+    handle: impl Fn(TcpStream) -> Result<()> + Send + Sync + 'static,
+) -> Result<u16> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))
+        .with_context(|| format!("Failed to start the {name} relay"))?;
+    let port = listener.local_addr()?.port();
+    let handle = Arc::new(handle);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let handle = Arc::clone(&handle);
+            std::thread::spawn(move || {
+                if let Err(e) = handle(stream) {
+                    debug!("{name} relay connection failed: {e:#}");
+                }
+            });
+        }
+    });
+    Ok(port)
+}
+
+/// Electrum client for `server` whose calls go through the trace of `options`
+pub(crate) fn electrum_client(
+    server: &ElectrumServer,
+    options: &ClientOptions,
+    trace: Arc<RpcTrace>,
+) -> Result<Client> {
+    let upstream = server.clone();
+    let options = options.clone();
+    let port = spawn_relay("Electrum", move |stream| {
+        serve_electrum(stream, &upstream, &options, &trace)
+    })?;
+    Client::new(&format!("tcp://127.0.0.1:{port}")).with_context(|| {
+        format!(
+            "Failed to connect to the trace relay for Electrum server {url}",
+            url = server.url
+        )
+    })
+}
+
+/// Answer line-delimited JSON-RPC requests, connecting to `server` on the
+/// first request that is not replayed
+fn serve_electrum(
+    stream: TcpStream,
+    server: &ElectrumServer,
+    options: &ClientOptions,
+    trace: &RpcTrace,
+) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut upstream: Option<Client> = None;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let call: Value = serde_json::from_str(&line).context("Invalid Electrum request")?;
+        let method = call["method"]
+            .as_str()
+            .context("Electrum request has no method")?
+            .to_string();
+        let params = match call.get("params") {
+            Some(Value::Array(params)) => params.clone(),
+            _ => Vec::new(),
+        };
+        let request = serde_json::json!({
+            "method": method,
+            "params": redact(&Value::Array(params.clone())),
+        });
+        let result = trace.exchange_blocking("electrum", request, || {
+            let params = params
+                .iter()
+                .map(electrum_param)
+                .collect::<Result<Vec<_>>>()?;
+            let client = match upstream.take() {
+                Some(client) => client,
+                None => server.connect_upstream(options)?,
+            };
+            let result = client
+                .raw_call(&method, params)
+                .with_context(|| format!("Electrum call {method} failed"));
+            upstream = Some(client);
+            result
+        });
+        let response = match result {
+            Ok(result) => {
+                serde_json::json!({ "jsonrpc": "2.0", "id": call["id"], "result": result })
+            }
+            Err(e) => serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": call["id"],
+                "error": { "code": -32603, "message": format!("{e:#}") },
+            }),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn electrum_param(value: &Value) -> Result<Param> {
+    Ok(match value {
+        Value::String(s) => Param::String(s.clone()),
+        Value::Bool(b) => Param::Bool(*b),
+        Value::Number(n) => match n.as_u64().and_then(|n| usize::try_from(n).ok()) {
+            Some(n) => Param::Usize(n),
+            None => bail!("Unsupported Electrum parameter: {n}"),
+        },
+        other => bail!("Unsupported Electrum parameter: {other}"),
+    })
+}
+
+/// Base URL of a relay that sends the Esplora requests it receives to
+/// `base_url` with the proxy of `options`, through its trace if any
+pub(crate) fn esplora_url(base_url: &str, options: &ClientOptions) -> Result<String> {
+    let upstream = EsploraUpstream {
+        base_url: base_url.trim_end_matches('/').to_string(),
+        http: esplora_http_client(base_url, options)?,
+        runtime: OnceLock::new(),
+    };
+    let trace = options.trace.clone();
+    let port = spawn_relay("Esplora", move |stream| {
+        serve_esplora(stream, &upstream, trace.as_deref())
+    })?;
+    Ok(format!("http://127.0.0.1:{port}"))
+}
+
+/// Esplora server behind a relay
+struct EsploraUpstream {
+    base_url: String,
+    http: reqwest::Client,
+    /// Runtime driving `http`, started on a relay thread so that it is never
+    /// dropped in an async context
+    runtime: OnceLock<tokio::runtime::Runtime>,
+}
+
+impl EsploraUpstream {
+    /// Status and body of the response to `GET path`
+    fn get(&self, path: &str) -> Result<(u16, Vec<u8>)> {
+        let runtime = match self.runtime.get() {
+            Some(runtime) => runtime,
+            None => {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(2)
+                    .enable_all()
+                    .build()
+                    .context("Failed to start the Esplora relay runtime")?;
+                self.runtime.get_or_init(|| runtime)
+            }
+        };
+        let url = format!("{base}{path}", base = self.base_url);
+        runtime.block_on(async {
+            let response = self
+                .http
+                .get(&url)
+                .send()
+                .await
+                .with_context(|| format!("Esplora GET {url} failed"))?;
+            let status = response.status().as_u16();
+            let body = response
+                .bytes()
+                .await
+                .with_context(|| format!("Failed to read Esplora response from {url}"))?;
+            Ok((status, body.to_vec()))
+        })
+    }
+}
+
+/// Answer one HTTP request; BDK only sends GETs without a body
+fn serve_esplora(
+    stream: TcpStream,
+    upstream: &EsploraUpstream,
+    trace: Option<&RpcTrace>,
+) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let result = match (
+        &request_line.split_whitespace().collect::<Vec<_>>()[..],
+        trace,
+    ) {
+        (["GET", path, _], None) => upstream.get(path),
+        (["GET", path, _], Some(trace)) => {
+            let request = serde_json::json!({ "method": "GET", "path": path });
+            trace
+                .exchange_blocking("esplora-sync", request, || {
+                    let (status, body) = upstream.get(path)?;
+                    Ok(recorded_http_response(status, &body))
+                })
+                .map_err(anyhow::Error::from)
+                .and_then(|recorded| replayed_http_response(&recorded))
+        }
+        _ => Err(anyhow::anyhow!(
+            "Unsupported request to the Esplora relay: {request_line}",
+            request_line = request_line.trim()
+        )),
+    };
+    let (status, body) = result.unwrap_or_else(|e| (502, format!("{e:#}").into_bytes()));
+    write!(
+        writer,
+        "HTTP/1.1 {status} Relayed\r\nContent-Length: {length}\r\nConnection: close\r\n\r\n",
+        length = body.len()
+    )?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Status and body of an HTTP response, keeping text bodies readable
+fn recorded_http_response(status: u16, body: &[u8]) -> Value {
+    match std::str::from_utf8(body) {
+        Ok(text) => serde_json::json!({ "status": status, "body": text }),
+        Err(_) => serde_json::json!({
+            "status": status,
+            "body_base64": base64::engine::general_purpose::STANDARD.encode(body),
+        }),
+    }
+}
+
+fn replayed_http_response(recorded: &Value) -> Result<(u16, Vec<u8>)> {
+    let status = recorded["status"]
+        .as_u64()
+        .and_then(|status| u16::try_from(status).ok())
+        .context("Recorded Esplora response has no status")?;
+    let body = match (recorded["body"].as_str(), recorded["body_base64"].as_str()) {
+        (Some(text), _) => text.as_bytes().to_vec(),
+        (None, Some(encoded)) => base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .context("Invalid body in recorded Esplora response")?,
+        (None, None) => bail!("Recorded Esplora response has no body"),
+    };
+    Ok((status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_esplora_relay_records_and_replays() -> Result<()> {
+        let mut server = mockito::Server::new();
+        let _height = server
+            .mock("GET", "/blocks/tip/height")
+            .with_body("840000")
+            .expect(1)
+            .create();
+        let temp_dir = TempDir::new()?;
+
+        let recorder = ClientOptions {
+            trace: Some(Arc::new(RpcTrace::record(temp_dir.path())?)),
+            ..Default::default()
+        };
+        let relay =
+            bdk_esplora::esplora_client::Builder::new(&esplora_url(&server.url(), &recorder)?)
+                .build_blocking();
+        assert_eq!(relay.get_height()?, 840_000);
+
+        // The upstream is unreachable during replay
+        let replayer = ClientOptions {
+            trace: Some(Arc::new(RpcTrace::replay(temp_dir.path())?)),
+            ..Default::default()
+        };
+        let relay = bdk_esplora::esplora_client::Builder::new(&esplora_url(
+            "http://127.0.0.1:1",
+            &replayer,
+        )?)
+        .build_blocking();
+        assert_eq!(relay.get_height()?, 840_000);
+        Ok(())
+    }
+
+    /// SOCKS5 proxy without authentication that serves one connection,
+    /// whatever host it is asked for, from `target`
+    fn socks5_proxy(target: std::net::SocketAddr) -> Result<String> {
+        use std::io::Read;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        std::thread::spawn(move || -> Result<()> {
+            let (mut client, _) = listener.accept()?;
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting)?;
+            client.write_all(&[0x05, 0x00])?;
+            let mut request = [0u8; 5];
+            client.read_exact(&mut request)?;
+            // Domain name, then the port
+            let mut rest = vec![0u8; usize::from(request[4]) + 2];
+            client.read_exact(&mut rest)?;
+            client.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0])?;
+
+            let upstream = TcpStream::connect(target)?;
+            let (mut from_client, mut to_upstream) = (client.try_clone()?, upstream.try_clone()?);
+            std::thread::spawn(move || std::io::copy(&mut from_client, &mut to_upstream));
+            std::io::copy(&mut &upstream, &mut &client)?;
+            Ok(())
+        });
+        Ok(format!("socks5h://{address}"))
+    }
+
+    #[test]
+    fn test_esplora_relay_uses_socks5_proxy() -> Result<()> {
+        let mut server = mockito::Server::new();
+        let _height = server
+            .mock("GET", "/blocks/tip/height")
+            .with_body("840000")
+            .expect(1)
+            .create();
+        let proxy = socks5_proxy(server.socket_address())?;
+
+        // Only the proxy knows this host
+        let options = ClientOptions {
+            proxy: Some(proxy.parse()?),
+            ..Default::default()
+        };
+        let relay = bdk_esplora::esplora_client::Builder::new(&esplora_url(
+            "http://esplora.invalid",
+            &options,
+        )?)
+        .build_blocking();
+        assert_eq!(relay.get_height()?, 840_000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_electrum_relay_replays() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let recorder = RpcTrace::record(temp_dir.path())?;
+        recorder.exchange_blocking(
+            "electrum",
+            serde_json::json!({ "method": "blockchain.estimatefee", "params": [6] }),
+            || Ok(serde_json::json!(0.0002)),
+        )?;
+
+        let replayer = Arc::new(RpcTrace::replay(temp_dir.path())?);
+        let client = electrum_client(
+            &ElectrumServer::new("tcp://127.0.0.1:1"),
+            &ClientOptions::default(),
+            replayer,
+        )?;
+        assert_eq!(client.estimate_fee(6)?, 0.0002);
+        // Not recorded, so it fails instead of reaching the server
+        assert!(client.estimate_fee(2).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{Context, Result, anyhow, ensure};
+use anyhow::{Context, Result, anyhow};
 use bitcoin::Network;
 use bitcoin::bip32::{ChildNumber, DerivationPath, Xpub};
 use serde::{Deserialize, Serialize};
@@ -8,6 +8,7 @@ use trezor_client::client::common::handle_interaction;
 use trezor_client::protos;
 use trezor_client::{InputScriptType, Trezor as TrezorClient};
 
+use crate::error::{CoreResult, ensure};
 use crate::hardware_wallet::{AddressInfo, DeviceInfo, SignedPsbt};
 use crate::slip132::parse_slip132_xpub;
 
@@ -33,7 +34,7 @@ pub struct TrezorSignOutput {
 
 impl TrezorWallet {
     /// Connect to the first available Trezor device
-    pub async fn connect() -> Result<Self> {
+    pub async fn connect() -> CoreResult<Self> {
         // Find and connect to the first available Trezor
         let client = trezor_client::unique(false)
             .context("Failed to find Trezor device. Make sure your Trezor is connected via USB.")?;
@@ -42,7 +43,7 @@ impl TrezorWallet {
     }
 
     /// Initialize the device and get basic information
-    pub fn init_device(&mut self) -> Result<()> {
+    pub fn init_device(&mut self) -> CoreResult<()> {
         self.client
             .init_device(None)
             .context("Failed to initialize Trezor device")?;
@@ -50,7 +51,7 @@ impl TrezorWallet {
     }
 
     /// Get device information
-    pub fn get_device_info(&mut self) -> Result<DeviceInfo> {
+    pub fn get_device_info(&mut self) -> CoreResult<DeviceInfo> {
         // Initialize device if not already done
         let _ = self.init_device();
 
@@ -73,7 +74,7 @@ impl TrezorWallet {
     }
 
     /// Get a Bitcoin address at the given derivation path
-    pub fn get_address(&mut self, path: &str, network: Network) -> Result<AddressInfo> {
+    pub fn get_address(&mut self, path: &str, network: Network) -> CoreResult<AddressInfo> {
         // Parse the derivation path
         let derivation_path = DerivationPath::from_str(path)
             .with_context(|| format!("Invalid derivation path: {path}"))?;
@@ -179,7 +180,7 @@ impl TrezorWallet {
     }
 
     /// Get extended public key at the given derivation path
-    pub fn get_xpub(&mut self, path: &str, network: Network) -> Result<Xpub> {
+    pub fn get_xpub(&mut self, path: &str, network: Network) -> CoreResult<Xpub> {
         let derivation_path = DerivationPath::from_str(path)
             .with_context(|| format!("Invalid derivation path: {path}"))?;
 
@@ -201,11 +202,11 @@ impl TrezorWallet {
 
         // If that didn't work, build from the HDNodeType
         // The node field is always present in the response
-        self.build_xpub_from_node(&pubkey_msg.node, network)
+        Ok(self.build_xpub_from_node(&pubkey_msg.node, network)?)
     }
 
     /// Sign a PSBT (Partially Signed Bitcoin Transaction)
-    pub fn sign_psbt(&mut self, psbt_bytes: &[u8], network: Network) -> Result<SignedPsbt> {
+    pub fn sign_psbt(&mut self, psbt_bytes: &[u8], network: Network) -> CoreResult<SignedPsbt> {
         use base64::Engine;
         use bitcoin::psbt::Psbt;
 
@@ -264,8 +265,35 @@ impl TrezorWallet {
         }
     }
 
+    /// Sign `message` with the key at `path`, returning Trezor's 65-byte
+    /// recoverable signature (BIP137 header for the path's script type)
+    pub fn sign_message(
+        &mut self,
+        message: &str,
+        path: &str,
+        network: Network,
+    ) -> CoreResult<Vec<u8>> {
+        use trezor_client::utils;
+
+        let derivation_path = DerivationPath::from_str(path)
+            .with_context(|| format!("Invalid derivation path: {path}"))?;
+
+        let mut req = protos::SignMessage::new();
+        req.address_n = utils::convert_path(&derivation_path);
+        req.set_message(message.as_bytes().to_vec());
+        req.set_coin_name(utils::coin_name(network)?);
+        req.set_script_type(determine_script_type(&derivation_path));
+
+        let response = self
+            .client
+            .call(req, Box::new(|_, m: protos::MessageSignature| Ok(m)))?;
+
+        let signed = handle_interaction(response).context("Failed to sign message with Trezor")?;
+        Ok(signed.signature().to_vec())
+    }
+
     /// Ping the device to check if it's connected
-    pub fn ping(&mut self) -> Result<bool> {
+    pub fn ping(&mut self) -> CoreResult<bool> {
         // Try to get features as a ping test
         Ok(self.client.features().is_some())
     }
@@ -289,8 +317,22 @@ fn determine_script_type(path: &DerivationPath) -> InputScriptType {
     }
 }
 
+/// Whether `error` comes from the user rejecting the request on the Trezor
+pub(crate) fn is_user_cancellation(error: &anyhow::Error) -> bool {
+    crate::error::causes(error).into_iter().any(|cause| {
+        matches!(
+            cause.downcast_ref::<trezor_client::Error>(),
+            Some(trezor_client::Error::FailureResponse(failure))
+                if failure.code() == protos::failure::FailureType::Failure_ActionCancelled
+        )
+    })
+}
+
 /// Generate a Bitcoin address from Trezor
-pub async fn generate_trezor_address(path: &str, network: Network) -> Result<TrezorAddressOutput> {
+pub async fn generate_trezor_address(
+    path: &str,
+    network: Network,
+) -> CoreResult<TrezorAddressOutput> {
     let mut wallet = TrezorWallet::connect().await?;
     wallet.init_device()?;
 
@@ -305,7 +347,10 @@ pub async fn generate_trezor_address(path: &str, network: Network) -> Result<Tre
 }
 
 /// Sign a PSBT with Trezor
-pub async fn sign_psbt_with_trezor(psbt_data: &[u8], network: Network) -> Result<TrezorSignOutput> {
+pub async fn sign_psbt_with_trezor(
+    psbt_data: &[u8],
+    network: Network,
+) -> CoreResult<TrezorSignOutput> {
     let mut wallet = TrezorWallet::connect().await?;
     wallet.init_device()?;
 
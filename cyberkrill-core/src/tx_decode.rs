@@ -0,0 +1,250 @@
+//! Structured decoding of raw Bitcoin transactions
+
+use anyhow::Context;
+use bitcoin::{Network, OutPoint, Transaction, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use tracing::warn;
+
+use crate::backend::BlockchainBackend;
+use crate::error::CoreResult;
+
+/// A transaction output, or the output an input spends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedOutput {
+    /// Output index within its transaction
+    pub index: u32,
+    pub value_sats: u64,
+    pub value_btc: f64,
+    pub script_pubkey: String,
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedInput {
+    pub index: usize,
+    pub txid: String,
+    pub vout: u32,
+    pub sequence: u32,
+    pub script_sig: String,
+    /// Witness stack items, hex-encoded
+    pub witness: Vec<String>,
+    /// Output being spent, when it could be resolved
+    pub prevout: Option<DecodedOutput>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedTransaction {
+    pub txid: String,
+    pub wtxid: String,
+    pub version: i32,
+    pub locktime: u32,
+    pub size: usize,
+    pub vsize: usize,
+    pub weight: u64,
+    /// BIP125 replaceability signaled by any input
+    pub rbf: bool,
+    pub inputs: Vec<DecodedInput>,
+    pub outputs: Vec<DecodedOutput>,
+    pub total_output_sats: u64,
+    /// Known only when every prevout was resolved
+    pub total_input_sats: Option<u64>,
+    pub fee_sats: Option<u64>,
+    pub fee_rate_sat_vb: Option<f64>,
+    /// Sigop cost (BIP141); P2SH and witness sigops are only counted when prevouts are known
+    pub sigop_cost: usize,
+    pub prevouts_resolved: bool,
+}
+
+/// Parse a consensus-encoded transaction from hex
+pub fn parse_raw_transaction(tx_hex: &str) -> CoreResult<Transaction> {
+    let bytes = hex::decode(tx_hex.trim()).context("Transaction is not valid hex")?;
+    Ok(bitcoin::consensus::deserialize(&bytes).context("Failed to decode transaction")?)
+}
+
+/// Fetch the outputs spent by `tx`. Parents that cannot be fetched are skipped with a warning.
+pub async fn resolve_prevouts(
+    backend: &dyn BlockchainBackend,
+    tx: &Transaction,
+) -> HashMap<OutPoint, TxOut> {
+    let mut parents: HashMap<Txid, Option<Transaction>> = HashMap::new();
+    let mut prevouts = HashMap::new();
+    if tx.is_coinbase() {
+        return prevouts;
+    }
+
+    for input in &tx.input {
+        let outpoint = input.previous_output;
+        if let Entry::Vacant(entry) = parents.entry(outpoint.txid) {
+            let parent = match backend.get_transaction(&outpoint.txid).await {
+                Ok(parent) => Some(parent),
+                Err(e) => {
+                    warn!(
+                        "Could not fetch prevout transaction {txid}: {e:#}",
+                        txid = outpoint.txid
+                    );
+                    None
+                }
+            };
+            entry.insert(parent);
+        }
+        if let Some(txout) = parents
+            .get(&outpoint.txid)
+            .and_then(Option::as_ref)
+            .and_then(|parent| parent.output.get(outpoint.vout as usize))
+        {
+            prevouts.insert(outpoint, txout.clone());
+        }
+    }
+    prevouts
+}
+
+fn decode_output(index: u32, txout: &TxOut, network: Network) -> DecodedOutput {
+    DecodedOutput {
+        index,
+        value_sats: txout.value.to_sat(),
+        value_btc: txout.value.to_btc(),
+        script_pubkey: txout.script_pubkey.to_hex_string(),
+        address: bitcoin::Address::from_script(&txout.script_pubkey, network)
+            .map(|address| address.to_string())
+            .ok(),
+    }
+}
+
+/// Decode `tx`, using `prevouts` (possibly empty) for input values, fee and sigops.
+pub fn decode_transaction(
+    tx: &Transaction,
+    prevouts: &HashMap<OutPoint, TxOut>,
+    network: Network,
+) -> DecodedTransaction {
+    let inputs: Vec<DecodedInput> = tx
+        .input
+        .iter()
+        .enumerate()
+        .map(|(index, input)| DecodedInput {
+            index,
+            txid: input.previous_output.txid.to_string(),
+            vout: input.previous_output.vout,
+            sequence: input.sequence.0,
+            script_sig: input.script_sig.to_hex_string(),
+            witness: input.witness.iter().map(hex::encode).collect(),
+            prevout: prevouts
+                .get(&input.previous_output)
+                .map(|txout| decode_output(input.previous_output.vout, txout, network)),
+        })
+        .collect();
+
+    let outputs: Vec<DecodedOutput> = tx
+        .output
+        .iter()
+        .enumerate()
+        .map(|(index, txout)| decode_output(index as u32, txout, network))
+        .collect();
+
+    let prevouts_resolved = !tx.is_coinbase() && inputs.iter().all(|input| input.prevout.is_some());
+    let total_output_sats: u64 = outputs.iter().map(|output| output.value_sats).sum();
+    let total_input_sats = prevouts_resolved.then(|| {
+        inputs
+            .iter()
+            .filter_map(|input| input.prevout.as_ref())
+            .map(|prevout| prevout.value_sats)
+            .sum::<u64>()
+    });
+    let fee_sats = total_input_sats.map(|total| total.saturating_sub(total_output_sats));
+    let vsize = tx.vsize();
+
+    DecodedTransaction {
+        txid: tx.compute_txid().to_string(),
+        wtxid: tx.compute_wtxid().to_string(),
+        version: tx.version.0,
+        locktime: tx.lock_time.to_consensus_u32(),
+        size: tx.total_size(),
+        vsize,
+        weight: tx.weight().to_wu(),
+        rbf: tx.is_explicitly_rbf(),
+        inputs,
+        outputs,
+        total_output_sats,
+        total_input_sats,
+        fee_sats,
+        fee_rate_sat_vb: fee_sats.map(|fee| fee as f64 / vsize as f64),
+        sigop_cost: tx.total_sigop_cost(|outpoint| prevouts.get(outpoint).cloned()),
+        prevouts_resolved,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use bitcoin::{Amount, ScriptBuf, Sequence, TxIn, Witness, absolute, transaction};
+    use std::str::FromStr;
+
+    fn spend(sequence: Sequence) -> Result<(Transaction, OutPoint, TxOut)> {
+        let prevout = OutPoint::from_str(
+            "1111111111111111111111111111111111111111111111111111111111111111:1",
+        )?;
+        let spent = TxOut {
+            value: Amount::from_sat(60_000),
+            script_pubkey: ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6")?,
+        };
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevout,
+                sequence,
+                witness: Witness::from_slice(&[vec![0x30; 72], vec![0x02; 33]]),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(59_000),
+                script_pubkey: ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6")?,
+            }],
+        };
+        Ok((tx, prevout, spent))
+    }
+
+    #[test]
+    fn test_decode_with_prevouts() -> Result<()> {
+        let (tx, outpoint, spent) = spend(Sequence::ENABLE_RBF_NO_LOCKTIME)?;
+        let prevouts = HashMap::from([(outpoint, spent)]);
+
+        let decoded = decode_transaction(&tx, &prevouts, Network::Bitcoin);
+        assert!(decoded.rbf);
+        assert!(decoded.prevouts_resolved);
+        assert_eq!(decoded.fee_sats, Some(1_000));
+        assert_eq!(decoded.vsize, tx.vsize());
+        assert_eq!(
+            decoded.outputs[0].address.as_deref(),
+            Some("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+        );
+        assert_eq!(
+            decoded.inputs[0].prevout.as_ref().map(|p| p.value_sats),
+            Some(60_000)
+        );
+        // One P2WPKH spend counts as a single witness sigop
+        assert_eq!(decoded.sigop_cost, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_without_prevouts() -> Result<()> {
+        let (tx, _, _) = spend(Sequence::MAX)?;
+        let raw = bitcoin::consensus::encode::serialize_hex(&tx);
+
+        let decoded = decode_transaction(
+            &parse_raw_transaction(&raw)?,
+            &HashMap::new(),
+            Network::Bitcoin,
+        );
+        assert!(!decoded.rbf);
+        assert!(!decoded.prevouts_resolved);
+        assert_eq!(decoded.fee_sats, None);
+        assert_eq!(decoded.total_output_sats, 59_000);
+        assert_eq!(decoded.txid, tx.compute_txid().to_string());
+        assert!(parse_raw_transaction("not hex").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! Plain-English summary of a transaction before it is written out
+//!
+//! Built from a freshly created PSBT so the user can check where the money goes
+//! (and how much of it is fee) before anything is saved or signed.
+
+use crate::error::{CoreResult, ensure};
+use crate::psbt_analysis::analyze_psbt;
+use bitcoin::psbt::Psbt;
+use bitcoin::{Address, Amount, Network};
+use serde::Serialize;
+
+/// One output of the previewed transaction
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewOutput {
+    pub index: usize,
+    /// `None` for OP_RETURN and other non-standard scripts
+    pub address: Option<String>,
+    pub amount_sats: u64,
+    pub is_change: bool,
+    pub is_op_return: bool,
+}
+
+/// Result of [`preview_psbt`]
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionPreview {
+    pub input_count: usize,
+    /// Known only when every input has UTXO information
+    pub total_input_sats: Option<u64>,
+    pub outputs: Vec<PreviewOutput>,
+    /// Paid to outputs other than change
+    pub sent_sats: u64,
+    pub change_sats: u64,
+    pub fee_sats: u64,
+    pub estimated_vsize: u64,
+    pub fee_rate_sat_vb: f64,
+    /// Fee as a percentage of everything leaving the wallet (sent + fee)
+    pub fee_percent: f64,
+}
+
+/// Summarize `psbt`. The fee is taken from the PSBT when every input carries its
+/// UTXO, otherwise `fee_sats` (as reported by the builder) is used.
+///
+/// Change is the output at `change_position`; builders that don't report it get
+/// every output not paying one of `recipients` counted as change instead.
+pub fn preview_psbt(
+    psbt: &Psbt,
+    network: Network,
+    fee_sats: u64,
+    change_position: Option<u32>,
+    recipients: &[String],
+) -> TransactionPreview {
+    let analysis = analyze_psbt(psbt, network);
+    let fee_sats = analysis.fee_sats.unwrap_or(fee_sats);
+
+    let outputs: Vec<PreviewOutput> = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .enumerate()
+        .map(|(index, txout)| {
+            let address = Address::from_script(&txout.script_pubkey, network)
+                .ok()
+                .map(|address| address.to_string());
+            let is_op_return = txout.script_pubkey.is_op_return();
+            let is_change = match change_position {
+                Some(position) => index == position as usize,
+                None => {
+                    !recipients.is_empty()
+                        && address
+                            .as_ref()
+                            .is_some_and(|address| !recipients.contains(address))
+                }
+            };
+            PreviewOutput {
+                index,
+                address,
+                amount_sats: txout.value.to_sat(),
+                is_change,
+                is_op_return,
+            }
+        })
+        .collect();
+
+    let change_sats = outputs
+        .iter()
+        .filter(|output| output.is_change)
+        .map(|output| output.amount_sats)
+        .sum();
+    let sent_sats = analysis.total_output_sats.saturating_sub(change_sats);
+    let leaving = sent_sats + fee_sats;
+    let fee_percent = if leaving == 0 {
+        0.0
+    } else {
+        fee_sats as f64 * 100.0 / leaving as f64
+    };
+
+    TransactionPreview {
+        input_count: analysis.inputs.len(),
+        total_input_sats: analysis.total_input_sats,
+        outputs,
+        sent_sats,
+        change_sats,
+        fee_sats,
+        estimated_vsize: analysis.estimated_vsize,
+        fee_rate_sat_vb: fee_sats as f64 / analysis.estimated_vsize.max(1) as f64,
+        fee_percent,
+    }
+}
+
+impl TransactionPreview {
+    /// Multi-line description, one action per line
+    pub fn summary(&self) -> String {
+        let total = self
+            .total_input_sats
+            .unwrap_or(self.sent_sats + self.change_sats + self.fee_sats);
+        let mut lines = vec![format!(
+            "Spend {total} from {count} input{s}",
+            total = btc(total),
+            count = self.input_count,
+            s = if self.input_count == 1 { "" } else { "s" },
+        )];
+        for output in &self.outputs {
+            let amount = btc(output.amount_sats);
+            lines.push(match (&output.address, output.is_change) {
+                _ if output.is_op_return => format!("Add an OP_RETURN data output ({amount})"),
+                (Some(address), true) => format!("Return {amount} as change to {address}"),
+                (Some(address), false) => format!("Send {amount} to {address}"),
+                (None, _) => format!(
+                    "Send {amount} to a non-standard script (output {index})",
+                    index = output.index
+                ),
+            });
+        }
+        lines.push(format!(
+            "Pay {fee} in fees at {rate:.1} sat/vB ({percent:.2}% of the {leaving} leaving the wallet)",
+            fee = btc(self.fee_sats),
+            rate = self.fee_rate_sat_vb,
+            percent = self.fee_percent,
+            leaving = btc(self.sent_sats + self.fee_sats),
+        ));
+        lines.join("\n")
+    }
+
+    /// Refuse a fee above `max_percent` of the amount leaving the wallet
+    pub fn check_fee_percent(&self, max_percent: f64) -> CoreResult<()> {
+        ensure!(
+            self.fee_percent <= max_percent,
+            "Fee of {fee} sats is {percent:.2}% of the amount leaving the wallet, above the {max_percent}% limit",
+            fee = self.fee_sats,
+            percent = self.fee_percent,
+        );
+        Ok(())
+    }
+}
+
+fn btc(sats: u64) -> String {
+    format!("{btc} BTC", btc = Amount::from_sat(sats).to_btc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use bitcoin::{
+        OutPoint, ScriptBuf, Transaction, TxIn, TxOut, WPubkeyHash, absolute, hashes::Hash,
+        transaction,
+    };
+    use std::str::FromStr;
+
+    fn script(byte: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([byte; 20]))
+    }
+
+    fn psbt(input_sats: u64, outputs: &[(u8, u64)]) -> Result<Psbt> {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::from_str(
+                    "0000000000000000000000000000000000000000000000000000000000000001:0",
+                )?,
+                ..Default::default()
+            }],
+            output: outputs
+                .iter()
+                .map(|&(byte, sats)| TxOut {
+                    value: Amount::from_sat(sats),
+                    script_pubkey: script(byte),
+                })
+                .collect(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(input_sats),
+            script_pubkey: script(0),
+        });
+        Ok(psbt)
+    }
+
+    fn address(byte: u8) -> Result<String> {
+        Ok(Address::from_script(&script(byte), Network::Bitcoin)?.to_string())
+    }
+
+    #[test]
+    fn test_preview_spend_with_change() -> Result<()> {
+        let psbt = psbt(100_000, &[(1, 60_000), (2, 38_000)])?;
+        let recipient = address(1)?;
+        let preview = preview_psbt(
+            &psbt,
+            Network::Bitcoin,
+            0,
+            None,
+            std::slice::from_ref(&recipient),
+        );
+
+        assert_eq!(preview.fee_sats, 2_000);
+        assert_eq!(preview.sent_sats, 60_000);
+        assert_eq!(preview.change_sats, 38_000);
+        assert!(preview.outputs[1].is_change);
+        assert!((preview.fee_percent - 2_000.0 * 100.0 / 62_000.0).abs() < 1e-9);
+
+        let summary = preview.summary();
+        assert!(summary.starts_with("Spend 0.001 BTC from 1 input\n"));
+        assert!(summary.contains(&format!("Send 0.0006 BTC to {recipient}")));
+        assert!(summary.contains("Return 0.00038 BTC as change"));
+
+        preview.check_fee_percent(5.0)?;
+        assert!(preview.check_fee_percent(3.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview_uses_change_position_and_builder_fee() -> Result<()> {
+        let mut psbt = psbt(100_000, &[(1, 90_000), (2, 9_000)])?;
+        psbt.inputs[0].witness_utxo = None;
+        let preview = preview_psbt(&psbt, Network::Bitcoin, 1_000, Some(0), &[]);
+
+        assert_eq!(preview.total_input_sats, None);
+        assert_eq!(preview.fee_sats, 1_000);
+        assert_eq!(preview.change_sats, 90_000);
+        assert_eq!(preview.sent_sats, 9_000);
+        assert!(preview.summary().starts_with("Spend 0.001 BTC"));
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+//! Watching a transaction until it reaches a confirmation depth
+//!
+//! The selected backend is polled for the transaction's status and an event is
+//! emitted whenever it changes (first seen, confirmed, reorged out), so deposit
+//! and payout scripts can block on `onchain-watch-tx` instead of polling
+//! themselves. With a Bitcoin Core ZMQ endpoint the status is re-checked as soon
+//! as the transaction is relayed or a block arrives, and polling becomes a
+//! fallback.
+
+use anyhow::Result;
+use bitcoin::Txid;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::backend::BlockchainBackend;
+use crate::confirmations::{ConfirmationPolicy, ensure_min_confirmations};
+use crate::error::{CoreResult, bail};
+use crate::zmq::{ZmqNotification, ZmqSubscriber, ZmqTopic};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxState {
+    /// Neither in the mempool nor in the chain, as far as the backend knows
+    NotFound,
+    Mempool,
+    Confirmed,
+}
+
+/// Where a transaction stands, as reported by a backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TxStatus {
+    pub txid: String,
+    pub state: TxState,
+    pub confirmations: u32,
+    pub block_height: Option<u32>,
+    pub block_hash: Option<String>,
+}
+
+impl TxStatus {
+    pub fn not_found(txid: &Txid) -> Self {
+        Self {
+            txid: txid.to_string(),
+            state: TxState::NotFound,
+            confirmations: 0,
+            block_height: None,
+            block_hash: None,
+        }
+    }
+
+    pub fn mempool(txid: &Txid) -> Self {
+        Self {
+            state: TxState::Mempool,
+            ..Self::not_found(txid)
+        }
+    }
+
+    /// Confirmed in the block at `block_height`, with the chain tip at `tip_height`
+    pub fn confirmed(txid: &Txid, block_height: u32, block_hash: String, tip_height: u32) -> Self {
+        Self {
+            txid: txid.to_string(),
+            state: TxState::Confirmed,
+            confirmations: tip_height.saturating_sub(block_height) + 1,
+            block_height: Some(block_height),
+            block_hash: Some(block_hash),
+        }
+    }
+}
+
+/// How to watch a transaction
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Confirmations to wait for; 0 returns once the transaction is in the mempool
+    pub confirmations: u32,
+    pub poll_interval: Duration,
+    /// Give up after this long
+    pub timeout: Option<Duration>,
+    /// Bitcoin Core ZMQ endpoint publishing `rawtx` and `rawblock`
+    pub zmq_endpoint: Option<String>,
+    /// Also wait for the confirmations this policy recommends for the
+    /// transaction's total output value
+    pub min_conf_policy: Option<ConfirmationPolicy>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            confirmations: 1,
+            poll_interval: Duration::from_secs(10),
+            timeout: None,
+            zmq_endpoint: None,
+            min_conf_policy: None,
+        }
+    }
+}
+
+/// Poll `backend` until `txid` has the requested confirmations, calling
+/// `on_change` with every new status. Transient lookup failures are logged and
+/// retried on the next poll.
+pub async fn watch_transaction(
+    backend: &dyn BlockchainBackend,
+    txid: &Txid,
+    options: WatchOptions,
+    mut on_change: impl FnMut(&TxStatus),
+) -> CoreResult<TxStatus> {
+    let started = Instant::now();
+    if let Some(policy) = &options.min_conf_policy {
+        policy.validate()?;
+    }
+    let mut amount_sats = None;
+    let mut zmq = match &options.zmq_endpoint {
+        Some(endpoint) => {
+            Some(ZmqSubscriber::connect(endpoint, &[ZmqTopic::RawTx, ZmqTopic::RawBlock]).await?)
+        }
+        None => None,
+    };
+    let mut last: Option<TxStatus> = None;
+    loop {
+        match backend.tx_status(txid).await {
+            Ok(status) => {
+                if last.as_ref() != Some(&status) {
+                    on_change(&status);
+                }
+                if target_reached(&status, options.confirmations) {
+                    let policy = options.min_conf_policy.as_ref();
+                    match min_conf_policy_met(backend, txid, &status, policy, &mut amount_sats)
+                        .await
+                    {
+                        Ok(true) => return Ok(status),
+                        Ok(false) => {}
+                        Err(e) => warn!("Failed to get the amount of {txid}: {e:#}"),
+                    }
+                }
+                last = Some(status);
+            }
+            Err(e) => warn!("Failed to get the status of {txid}: {e:#}"),
+        }
+        if let Some(timeout) = options.timeout
+            && started.elapsed() + options.poll_interval > timeout
+        {
+            let policy = match options.min_conf_policy {
+                Some(_) => " and the depth required by the min-conf policy",
+                None => "",
+            };
+            bail!(
+                "Transaction {txid} did not reach {confirmations} confirmation(s){policy} within {timeout:?}",
+                confirmations = options.confirmations
+            );
+        }
+        wait_for_activity(&mut zmq, txid, options.poll_interval).await;
+    }
+}
+
+/// Sleep for `poll_interval`, waking early when ZMQ reports `txid` or a new block.
+/// A broken subscription is dropped and polling carries on.
+async fn wait_for_activity(zmq: &mut Option<ZmqSubscriber>, txid: &Txid, poll_interval: Duration) {
+    let deadline = tokio::time::sleep(poll_interval);
+    tokio::pin!(deadline);
+    while let Some(subscriber) = zmq {
+        tokio::select! {
+            () = &mut deadline => return,
+            notification = subscriber.next() => match notification {
+                Ok(ZmqNotification::RawBlock(_)) => return,
+                Ok(ZmqNotification::RawTx(tx)) if tx.compute_txid() == *txid => return,
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("{e:#}; falling back to polling");
+                    *zmq = None;
+                }
+            },
+        }
+    }
+    deadline.await;
+}
+
+/// Whether `status` has the confirmations `policy` recommends for the
+/// transaction's total output value, which is fetched once into `amount_sats`
+async fn min_conf_policy_met(
+    backend: &dyn BlockchainBackend,
+    txid: &Txid,
+    status: &TxStatus,
+    policy: Option<&ConfirmationPolicy>,
+    amount_sats: &mut Option<u64>,
+) -> Result<bool> {
+    let Some(policy) = policy else {
+        return Ok(true);
+    };
+    let amount = match *amount_sats {
+        Some(amount) => amount,
+        None => {
+            let tx = backend.get_transaction(txid).await?;
+            let amount = tx.output.iter().map(|txout| txout.value.to_sat()).sum();
+            *amount_sats.insert(amount)
+        }
+    };
+    // The policy was validated up front, so an error means too few confirmations
+    Ok(ensure_min_confirmations(amount, status.confirmations, policy).is_ok())
+}
+
+fn target_reached(status: &TxStatus, confirmations: u32) -> bool {
+    match status.state {
+        TxState::NotFound => false,
+        TxState::Mempool => confirmations == 0,
+        TxState::Confirmed => status.confirmations >= confirmations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_target_reached() {
+        let txid = Txid::all_zeros();
+        let block_hash = "00".repeat(32);
+
+        assert!(!target_reached(&TxStatus::not_found(&txid), 0));
+        assert!(target_reached(&TxStatus::mempool(&txid), 0));
+        assert!(!target_reached(&TxStatus::mempool(&txid), 1));
+
+        let status = TxStatus::confirmed(&txid, 850_000, block_hash, 850_002);
+        assert_eq!(status.confirmations, 3);
+        assert!(target_reached(&status, 3));
+        assert!(!target_reached(&status, 4));
+    }
+}
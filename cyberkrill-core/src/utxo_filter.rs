@@ -0,0 +1,271 @@
+//! Filtering, sorting and truncating UTXO listings
+//!
+//! The same filter applies to the UTXOs listed through BDK ([`BdkUtxo`]) and
+//! through Bitcoin Core RPC ([`UtxoOutput`]), so every backend honors the
+//! amount, address and confirmation bounds of `onchain-list-utxos`.
+//! [`UtxoSelection`] orders the candidate coins of `onchain-move-utxos`.
+
+use anyhow::Context;
+use std::str::FromStr;
+use strum::{Display, EnumString};
+
+use crate::bdk_wallet::BdkUtxo;
+use crate::bitcoin_rpc::UtxoOutput;
+use crate::error::bail;
+
+/// Order of a filtered listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum UtxoSort {
+    /// Largest amount first
+    Value,
+    /// Oldest (most confirmations) first; unconfirmed last
+    Age,
+}
+
+/// Which UTXOs to keep, in what order and how many
+#[derive(Debug, Clone)]
+pub struct UtxoFilter {
+    pub min_amount_sats: Option<u64>,
+    pub max_amount_sats: Option<u64>,
+    /// Keep only UTXOs paying one of these addresses (all when empty)
+    pub addresses: Vec<String>,
+    pub min_confirmations: u32,
+    pub max_confirmations: u32,
+    pub sort_by: Option<UtxoSort>,
+    pub limit: Option<usize>,
+}
+
+impl Default for UtxoFilter {
+    fn default() -> Self {
+        Self {
+            min_amount_sats: None,
+            max_amount_sats: None,
+            addresses: Vec::new(),
+            min_confirmations: 0,
+            max_confirmations: u32::MAX,
+            sort_by: None,
+            limit: None,
+        }
+    }
+}
+
+/// A listed UTXO the filter can inspect
+pub trait ListedUtxo {
+    fn amount_sats(&self) -> u64;
+    fn confirmations(&self) -> u32;
+    fn address(&self) -> Option<&str>;
+}
+
+impl ListedUtxo for BdkUtxo {
+    fn amount_sats(&self) -> u64 {
+        self.amount
+    }
+
+    fn confirmations(&self) -> u32 {
+        self.confirmations
+    }
+
+    fn address(&self) -> Option<&str> {
+        Some(&self.address)
+    }
+}
+
+impl ListedUtxo for UtxoOutput {
+    fn amount_sats(&self) -> u64 {
+        self.amount_sats
+    }
+
+    fn confirmations(&self) -> u32 {
+        self.confirmations
+    }
+
+    fn address(&self) -> Option<&str> {
+        self.address.as_deref()
+    }
+}
+
+impl UtxoFilter {
+    pub fn matches(&self, utxo: &impl ListedUtxo) -> bool {
+        let amount = utxo.amount_sats();
+        self.min_amount_sats.is_none_or(|min| amount >= min)
+            && self.max_amount_sats.is_none_or(|max| amount <= max)
+            && (self.min_confirmations..=self.max_confirmations).contains(&utxo.confirmations())
+            && (self.addresses.is_empty()
+                || utxo
+                    .address()
+                    .is_some_and(|address| self.addresses.iter().any(|a| a == address)))
+    }
+
+    /// Keep the matching UTXOs, sort them and cut the list at the limit
+    pub fn apply<T: ListedUtxo>(&self, utxos: &mut Vec<T>) {
+        utxos.retain(|utxo| self.matches(utxo));
+        match self.sort_by {
+            Some(UtxoSort::Value) => {
+                utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.amount_sats()))
+            }
+            Some(UtxoSort::Age) => {
+                utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.confirmations()))
+            }
+            None => {}
+        }
+        if let Some(limit) = self.limit {
+            utxos.truncate(limit);
+        }
+    }
+}
+
+/// Which of the candidate UTXOs to consolidate first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UtxoSelection {
+    /// Most confirmations first; unconfirmed last
+    Oldest,
+    /// Smallest amount first
+    Smallest,
+    /// Largest amount first
+    #[default]
+    Largest,
+    /// Only UTXOs below this many sats, smallest first
+    DustBelow(u64),
+}
+
+impl FromStr for UtxoSelection {
+    type Err = anyhow::Error;
+
+    /// `oldest`, `smallest`, `largest` or `dust-below <amount>` (also
+    /// `dust-below:<amount>` / `dust-below=<amount>`)
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let lower = s.to_lowercase();
+        match lower.as_str() {
+            "oldest" => return Ok(Self::Oldest),
+            "smallest" => return Ok(Self::Smallest),
+            "largest" => return Ok(Self::Largest),
+            _ => {}
+        }
+        let Some(amount) = lower
+            .strip_prefix("dust-below")
+            .map(|rest| rest.trim_start_matches([' ', ':', '=']))
+            .filter(|amount| !amount.is_empty())
+        else {
+            bail!(
+                "Invalid selection '{s}'. Expected oldest, smallest, largest or dust-below <amount>"
+            );
+        };
+        let amount = crate::bitcoin_rpc::AmountInput::from_str(amount)
+            .with_context(|| format!("Invalid dust-below amount in '{s}'"))?;
+        Ok(Self::DustBelow(amount.as_sat()))
+    }
+}
+
+impl UtxoSelection {
+    /// Drop the UTXOs the policy excludes and order the rest in picking order
+    pub fn apply<T>(
+        &self,
+        utxos: &mut Vec<T>,
+        amount_sats: impl Fn(&T) -> u64,
+        confirmations: impl Fn(&T) -> u32,
+    ) {
+        match *self {
+            Self::Oldest => utxos.sort_by_key(|utxo| std::cmp::Reverse(confirmations(utxo))),
+            Self::Smallest => utxos.sort_by_key(|utxo| amount_sats(utxo)),
+            Self::Largest => utxos.sort_by_key(|utxo| std::cmp::Reverse(amount_sats(utxo))),
+            Self::DustBelow(threshold) => {
+                utxos.retain(|utxo| amount_sats(utxo) < threshold);
+                utxos.sort_by_key(|utxo| amount_sats(utxo));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn utxo(vout: u32, amount: u64, confirmations: u32, address: &str) -> BdkUtxo {
+        BdkUtxo {
+            txid: "aa".to_string(),
+            vout,
+            address: address.to_string(),
+            amount,
+            amount_btc: amount as f64 / 100_000_000.0,
+            confirmations,
+            is_change: false,
+            keychain: "External".to_string(),
+            derivation_index: Some(vout),
+        }
+    }
+
+    fn vouts(utxos: &[BdkUtxo]) -> Vec<u32> {
+        utxos.iter().map(|utxo| utxo.vout).collect()
+    }
+
+    #[test]
+    fn test_filter_sort_and_limit() -> anyhow::Result<()> {
+        let listing = vec![
+            utxo(0, 5_000, 10, "bc1qa"),
+            utxo(1, 80_000, 0, "bc1qb"),
+            utxo(2, 20_000, 200, "bc1qa"),
+            utxo(3, 500, 50, "bc1qb"),
+        ];
+
+        let mut utxos = listing.clone();
+        UtxoFilter {
+            min_amount_sats: Some(1_000),
+            sort_by: Some(UtxoSort::from_str("value")?),
+            ..Default::default()
+        }
+        .apply(&mut utxos);
+        assert_eq!(vouts(&utxos), [1, 2, 0]);
+
+        let mut utxos = listing.clone();
+        UtxoFilter {
+            min_confirmations: 1,
+            sort_by: Some(UtxoSort::from_str("AGE")?),
+            limit: Some(2),
+            ..Default::default()
+        }
+        .apply(&mut utxos);
+        assert_eq!(vouts(&utxos), [2, 3]);
+
+        let mut utxos = listing;
+        UtxoFilter {
+            max_amount_sats: Some(50_000),
+            addresses: vec!["bc1qa".to_string()],
+            max_confirmations: 100,
+            ..Default::default()
+        }
+        .apply(&mut utxos);
+        assert_eq!(vouts(&utxos), [0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_utxo_selection() -> anyhow::Result<()> {
+        let listing = vec![
+            utxo(0, 5_000, 10, "bc1qa"),
+            utxo(1, 80_000, 0, "bc1qb"),
+            utxo(2, 20_000, 200, "bc1qa"),
+            utxo(3, 500, 50, "bc1qb"),
+        ];
+        let select = |selection: &str| -> anyhow::Result<Vec<u32>> {
+            let mut utxos = listing.clone();
+            UtxoSelection::from_str(selection)?.apply(
+                &mut utxos,
+                |u| u.amount,
+                |u| u.confirmations,
+            );
+            Ok(vouts(&utxos))
+        };
+
+        assert_eq!(select("oldest")?, [2, 3, 0, 1]);
+        assert_eq!(select("Smallest")?, [3, 0, 2, 1]);
+        assert_eq!(select("largest")?, [1, 2, 0, 3]);
+        assert_eq!(select("dust-below 10000sats")?, [3, 0]);
+        assert_eq!(select("dust-below:0.0001btc")?, [3, 0]);
+        assert!(UtxoSelection::from_str("dust-below").is_err());
+        assert!(UtxoSelection::from_str("newest").is_err());
+        Ok(())
+    }
+}
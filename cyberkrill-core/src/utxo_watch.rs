@@ -0,0 +1,401 @@
+//! Watching descriptors for new coins, spends and confirmation milestones
+//!
+//! The selected backend is polled for the UTXOs of each descriptor and every
+//! listing is compared with the previous one: an outpoint that appears is a
+//! received coin, one that disappears was spent (or reorged out), and one whose
+//! confirmations cross a milestone is reported once per milestone. With a
+//! confirmation policy a coin is also reported once it has the depth the
+//! policy recommends for its amount. With a Bitcoin Core ZMQ endpoint the UTXOs
+//! are re-listed as soon as a block arrives.
+//!
+//! Events can be POSTed to a webhook, optionally signed with an HMAC-SHA256 of
+//! the body so the receiver can check they came from this watcher.
+
+use anyhow::{Context, Result};
+use bitcoin::Network;
+use bitcoin::hashes::{Hash, HashEngine, hmac, sha256};
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::backend::BlockchainBackend;
+use crate::bdk_wallet::BdkUtxo;
+use crate::client_options::ClientOptions;
+use crate::confirmations::{ConfirmationPolicy, ensure_min_confirmations};
+use crate::descriptor::MAX_PARALLEL_DESCRIPTOR_SCANS;
+use crate::error::CoreResult;
+use crate::retry::{RetryPolicy, retry};
+use crate::zmq::{ZmqSubscriber, ZmqTopic};
+
+/// Header carrying `sha256=<hex HMAC of the body>` when a webhook secret is set
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Cyberkrill-Signature";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UtxoEventKind {
+    /// A new UTXO paying the descriptor
+    UtxoReceived,
+    /// A UTXO reached one of the confirmation milestones
+    UtxoConfirmed,
+    /// A UTXO reached the confirmations the policy recommends for its amount
+    UtxoSettled,
+    /// A UTXO is no longer unspent (spent, or its transaction was dropped)
+    UtxoSpent,
+}
+
+/// One change in a watched descriptor's UTXO set
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UtxoEvent {
+    pub event: UtxoEventKind,
+    pub descriptor: String,
+    pub txid: String,
+    pub vout: u32,
+    pub address: String,
+    pub amount_sats: u64,
+    /// As of the last listing the UTXO appeared in
+    pub confirmations: u32,
+    /// The milestone reached, for `utxo_confirmed`, or the depth required by
+    /// the policy, for `utxo_settled`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub milestone: Option<u32>,
+    pub timestamp: String,
+}
+
+/// How to watch descriptors
+#[derive(Debug, Clone)]
+pub struct UtxoWatchOptions {
+    pub poll_interval: Duration,
+    /// Confirmation counts reported with a `utxo_confirmed` event
+    pub milestones: Vec<u32>,
+    /// Report the UTXOs found by the first listing as received
+    pub report_existing: bool,
+    /// Bitcoin Core ZMQ endpoint publishing `hashblock`
+    pub zmq_endpoint: Option<String>,
+    /// Report a `utxo_settled` event once a UTXO has the confirmations this
+    /// policy recommends for its amount
+    pub min_conf_policy: Option<ConfirmationPolicy>,
+}
+
+impl Default for UtxoWatchOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(30),
+            milestones: vec![1, 6],
+            report_existing: false,
+            zmq_endpoint: None,
+            min_conf_policy: None,
+        }
+    }
+}
+
+/// UTXO set of one descriptor as of its last listing
+#[derive(Debug)]
+pub struct UtxoTracker {
+    descriptor: String,
+    milestones: Vec<u32>,
+    min_conf_policy: Option<ConfirmationPolicy>,
+    /// `None` until the first listing
+    known: Option<BTreeMap<(String, u32), BdkUtxo>>,
+}
+
+impl UtxoTracker {
+    pub fn new(descriptor: impl Into<String>, milestones: &[u32]) -> Self {
+        let mut milestones = milestones.to_vec();
+        milestones.sort_unstable();
+        milestones.dedup();
+        Self {
+            descriptor: descriptor.into(),
+            milestones,
+            min_conf_policy: None,
+            known: None,
+        }
+    }
+
+    /// Also report `utxo_settled` events under `policy`
+    pub fn with_min_conf_policy(mut self, policy: Option<ConfirmationPolicy>) -> Self {
+        self.min_conf_policy = policy;
+        self
+    }
+
+    /// Confirmations the policy requires for `utxo`, once it has them
+    fn settled_depth(&self, utxo: &BdkUtxo) -> Option<u32> {
+        let policy = self.min_conf_policy.as_ref()?;
+        ensure_min_confirmations(utxo.amount, utxo.confirmations, policy)
+            .ok()
+            .map(|recommendation| recommendation.min_confirmations)
+    }
+
+    /// Compare `utxos` with the previous listing. The first listing only sets
+    /// the baseline unless `report_existing` is set.
+    pub fn update(&mut self, utxos: Vec<BdkUtxo>, report_existing: bool) -> Vec<UtxoEvent> {
+        let current: BTreeMap<(String, u32), BdkUtxo> = utxos
+            .into_iter()
+            .map(|utxo| ((utxo.txid.clone(), utxo.vout), utxo))
+            .collect();
+        let timestamp = chrono::Utc::now().to_rfc3339();
+        let event = |kind, utxo: &BdkUtxo, milestone| UtxoEvent {
+            event: kind,
+            descriptor: self.descriptor.clone(),
+            txid: utxo.txid.clone(),
+            vout: utxo.vout,
+            address: utxo.address.clone(),
+            amount_sats: utxo.amount,
+            confirmations: utxo.confirmations,
+            milestone,
+            timestamp: timestamp.clone(),
+        };
+
+        let mut events = Vec::new();
+        match &self.known {
+            None if !report_existing => {}
+            None => events.extend(
+                current
+                    .values()
+                    .map(|utxo| event(UtxoEventKind::UtxoReceived, utxo, None)),
+            ),
+            Some(known) => {
+                for (outpoint, utxo) in &current {
+                    let Some(previous) = known.get(outpoint) else {
+                        events.push(event(UtxoEventKind::UtxoReceived, utxo, None));
+                        continue;
+                    };
+                    events.extend(
+                        self.milestones
+                            .iter()
+                            .filter(|&&milestone| {
+                                previous.confirmations < milestone
+                                    && utxo.confirmations >= milestone
+                            })
+                            .map(|&milestone| {
+                                event(UtxoEventKind::UtxoConfirmed, utxo, Some(milestone))
+                            }),
+                    );
+                    if let Some(depth) = self.settled_depth(utxo)
+                        && previous.confirmations < depth
+                    {
+                        events.push(event(UtxoEventKind::UtxoSettled, utxo, Some(depth)));
+                    }
+                }
+                events.extend(
+                    known
+                        .iter()
+                        .filter(|(outpoint, _)| !current.contains_key(*outpoint))
+                        .map(|(_, utxo)| event(UtxoEventKind::UtxoSpent, utxo, None)),
+                );
+            }
+        }
+        self.known = Some(current);
+        events
+    }
+}
+
+/// Poll `backend` for the UTXOs of `descriptors` forever, calling `on_event` for
+/// every change. Listing failures are logged and retried on the next poll; an
+/// error from `on_event` stops the watch.
+pub async fn watch_utxos(
+    backend: &dyn BlockchainBackend,
+    descriptors: &[String],
+    network: Network,
+    options: UtxoWatchOptions,
+    mut on_event: impl AsyncFnMut(&UtxoEvent) -> Result<()>,
+) -> CoreResult<()> {
+    if let Some(policy) = &options.min_conf_policy {
+        policy.validate()?;
+    }
+    let mut zmq = match &options.zmq_endpoint {
+        Some(endpoint) => Some(ZmqSubscriber::connect(endpoint, &[ZmqTopic::HashBlock]).await?),
+        None => None,
+    };
+    let mut trackers: Vec<UtxoTracker> = descriptors
+        .iter()
+        .map(|descriptor| {
+            UtxoTracker::new(descriptor.as_str(), &options.milestones)
+                .with_min_conf_policy(options.min_conf_policy.clone())
+        })
+        .collect();
+    loop {
+        // Descriptors are listed concurrently; events still come out in order
+        let listings: Vec<CoreResult<Vec<BdkUtxo>>> = futures::stream::iter(&trackers)
+            .map(|tracker| backend.list_utxos(&tracker.descriptor, network))
+            .buffered(MAX_PARALLEL_DESCRIPTOR_SCANS)
+            .collect()
+            .await;
+        for (tracker, listing) in trackers.iter_mut().zip(listings) {
+            let utxos = match listing {
+                Ok(utxos) => utxos,
+                Err(e) => {
+                    warn!(
+                        "Failed to list the UTXOs of {descriptor}: {e:#}",
+                        descriptor = tracker.descriptor
+                    );
+                    continue;
+                }
+            };
+            for event in tracker.update(utxos, options.report_existing) {
+                on_event(&event).await?;
+            }
+        }
+        wait_for_block(&mut zmq, options.poll_interval).await;
+    }
+}
+
+/// Sleep for `poll_interval`, waking early when ZMQ reports a new block. A
+/// broken subscription is dropped and polling carries on.
+async fn wait_for_block(zmq: &mut Option<ZmqSubscriber>, poll_interval: Duration) {
+    let deadline = tokio::time::sleep(poll_interval);
+    tokio::pin!(deadline);
+    if let Some(subscriber) = zmq {
+        tokio::select! {
+            () = &mut deadline => return,
+            notification = subscriber.next() => match notification {
+                Ok(_) => return,
+                Err(e) => {
+                    warn!("{e:#}; falling back to polling");
+                    *zmq = None;
+                }
+            },
+        }
+    }
+    deadline.await;
+}
+
+/// Endpoint watch events are POSTed to as JSON
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    secret: Option<String>,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl Webhook {
+    /// Webhook reached through the proxy of `options`, under its retry policy
+    pub fn new(
+        url: impl Into<String>,
+        secret: Option<String>,
+        options: &ClientOptions,
+    ) -> CoreResult<Self> {
+        Ok(Self {
+            url: url.into(),
+            secret,
+            client: options.http_client()?,
+            retry: options.retry,
+        })
+    }
+
+    /// POST `payload`, retrying transient failures
+    pub async fn send(&self, payload: &impl Serialize) -> CoreResult<()> {
+        let body = serde_json::to_vec(payload)?;
+        let signature = self.secret.as_deref().map(|secret| sign(secret, &body));
+        Ok(retry(&self.retry, "Webhook", || async {
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(WEBHOOK_SIGNATURE_HEADER, signature);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        })
+        .await
+        .with_context(|| format!("Failed to deliver event to {url}", url = self.url))?)
+    }
+}
+
+/// `sha256=<hex>` HMAC-SHA256 of `body` keyed with `secret`
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+    engine.input(body);
+    let mac = hmac::Hmac::<sha256::Hash>::from_engine(engine);
+    format!("sha256={mac}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(txid: &str, vout: u32, confirmations: u32) -> BdkUtxo {
+        BdkUtxo {
+            txid: txid.to_string(),
+            vout,
+            address: "bc1qexample".to_string(),
+            amount: 10_000,
+            amount_btc: 0.0001,
+            confirmations,
+            is_change: false,
+            keychain: "External".to_string(),
+            derivation_index: Some(0),
+        }
+    }
+
+    fn kinds(events: &[UtxoEvent]) -> Vec<(UtxoEventKind, &str, Option<u32>)> {
+        events
+            .iter()
+            .map(|event| (event.event, event.txid.as_str(), event.milestone))
+            .collect()
+    }
+
+    #[test]
+    fn test_tracker_reports_received_confirmed_and_spent() {
+        let mut tracker = UtxoTracker::new("wpkh(...)", &[6, 1, 6]);
+        assert!(tracker.update(vec![utxo("aa", 0, 3)], false).is_empty());
+
+        let events = tracker.update(vec![utxo("aa", 0, 7), utxo("bb", 1, 0)], false);
+        assert_eq!(
+            kinds(&events),
+            [
+                (UtxoEventKind::UtxoConfirmed, "aa", Some(6)),
+                (UtxoEventKind::UtxoReceived, "bb", None),
+            ]
+        );
+        assert_eq!(events[0].descriptor, "wpkh(...)");
+
+        // bb jumps past both milestones between two listings; aa is spent
+        let events = tracker.update(vec![utxo("bb", 1, 6)], false);
+        assert_eq!(
+            kinds(&events),
+            [
+                (UtxoEventKind::UtxoConfirmed, "bb", Some(1)),
+                (UtxoEventKind::UtxoConfirmed, "bb", Some(6)),
+                (UtxoEventKind::UtxoSpent, "aa", None),
+            ]
+        );
+        assert_eq!(events[2].confirmations, 7);
+
+        let mut tracker = UtxoTracker::new("wpkh(...)", &[1]);
+        let events = tracker.update(vec![utxo("aa", 0, 3)], true);
+        assert_eq!(kinds(&events), [(UtxoEventKind::UtxoReceived, "aa", None)]);
+    }
+
+    #[test]
+    fn test_tracker_reports_settled_coins() {
+        // 10,000 sats needs 2 confirmations under the default policy
+        let mut tracker = UtxoTracker::new("wpkh(...)", &[1])
+            .with_min_conf_policy(Some(ConfirmationPolicy::default()));
+        assert!(tracker.update(vec![utxo("aa", 0, 0)], false).is_empty());
+        let events = tracker.update(vec![utxo("aa", 0, 1)], false);
+        assert_eq!(
+            kinds(&events),
+            [(UtxoEventKind::UtxoConfirmed, "aa", Some(1))]
+        );
+        let events = tracker.update(vec![utxo("aa", 0, 2)], false);
+        assert_eq!(
+            kinds(&events),
+            [(UtxoEventKind::UtxoSettled, "aa", Some(2))]
+        );
+        assert!(tracker.update(vec![utxo("aa", 0, 3)], false).is_empty());
+    }
+
+    #[test]
+    fn test_webhook_signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,610 @@
+//! Local registry of known wallets tagged with an environment (prod/test)
+//!
+//! The registry lets signing commands detect when a PSBT uses keys from a
+//! wallet registered for a different environment than the target network,
+//! e.g. test keys on mainnet or production keys on testnet.
+
+use crate::error::{CoreResult, bail, ensure};
+use anyhow::{Context, Result};
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey, ForEachKey};
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub};
+use bitcoin::psbt::Psbt;
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{NetworkKind, ScriptBuf, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use strum::{Display, EnumString};
+
+/// Addresses of each keychain derived when looking for a wallet's keys in a
+/// finalized transaction
+const TRANSACTION_LOOKAHEAD: u32 = 200;
+
+/// Environment a wallet is allowed to operate in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletEnvironment {
+    /// Mainnet funds
+    #[strum(to_string = "prod", serialize = "production")]
+    Prod,
+    /// Testnet, signet and regtest funds
+    Test,
+}
+
+impl WalletEnvironment {
+    /// Environment implied by the network a transaction is signed for
+    pub fn for_network(network: impl Into<NetworkKind>) -> Self {
+        match network.into() {
+            NetworkKind::Main => WalletEnvironment::Prod,
+            NetworkKind::Test => WalletEnvironment::Test,
+        }
+    }
+}
+
+/// A wallet entry in the registry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredWallet {
+    pub name: String,
+    pub descriptor: String,
+    pub environment: WalletEnvironment,
+}
+
+impl RegisteredWallet {
+    fn parsed_descriptor(&self) -> Result<Descriptor<DescriptorPublicKey>> {
+        Descriptor::from_str(&self.descriptor).with_context(|| {
+            format!(
+                "Invalid descriptor for registered wallet '{name}'",
+                name = self.name
+            )
+        })
+    }
+
+    /// Full origin (master fingerprint and path to the key in the descriptor) of
+    /// each key; a key without `[fingerprint/path]` is its own master
+    fn key_origins(&self) -> Result<Vec<(Fingerprint, DerivationPath)>> {
+        let mut origins = Vec::new();
+        self.parsed_descriptor()?.for_each_key(|key| {
+            let origin = match key {
+                DescriptorPublicKey::Single(single) => &single.origin,
+                DescriptorPublicKey::XPub(xpub) => &xpub.origin,
+                DescriptorPublicKey::MultiXPub(xpub) => &xpub.origin,
+            };
+            origins.push(match origin {
+                Some((fingerprint, path)) => (*fingerprint, path.clone()),
+                None => (key.master_fingerprint(), DerivationPath::master()),
+            });
+            true
+        });
+        Ok(origins)
+    }
+
+    /// Extended public keys embedded in the descriptor
+    fn xpubs(&self) -> Result<HashSet<Xpub>> {
+        let mut xpubs = HashSet::new();
+        self.parsed_descriptor()?.for_each_key(|key| {
+            match key {
+                DescriptorPublicKey::XPub(xpub) => xpubs.insert(xpub.xkey),
+                DescriptorPublicKey::MultiXPub(xpub) => xpubs.insert(xpub.xkey),
+                DescriptorPublicKey::Single(_) => false,
+            };
+            true
+        });
+        Ok(xpubs)
+    }
+
+    /// Whether any key in `psbt` belongs to this wallet: one of its xpubs, or a
+    /// key whose origin extends the full origin of one of the descriptor keys.
+    /// The master fingerprint alone is not enough, as the same seed may back
+    /// wallets of both environments on different account paths.
+    pub fn matches_psbt(&self, psbt: &Psbt) -> CoreResult<bool> {
+        let origins = self.key_origins()?;
+        let xpubs = self.xpubs()?;
+
+        let known = |fingerprint: &Fingerprint, path: &DerivationPath| {
+            origins.iter().any(|(origin_fingerprint, origin_path)| {
+                origin_fingerprint == fingerprint && path.as_ref().starts_with(origin_path.as_ref())
+            })
+        };
+
+        Ok(psbt
+            .xpub
+            .iter()
+            .any(|(xpub, (fingerprint, path))| xpubs.contains(xpub) || known(fingerprint, path))
+            || psbt.inputs.iter().any(|input| {
+                input
+                    .bip32_derivation
+                    .values()
+                    .any(|(fingerprint, path)| known(fingerprint, path))
+                    || input
+                        .tap_key_origins
+                        .values()
+                        .any(|(_, (fingerprint, path))| known(fingerprint, path))
+            }))
+    }
+
+    /// Whether a finalized `tx` spends from or pays to this wallet, judged by
+    /// its first [`TRANSACTION_LOOKAHEAD`] addresses of each keychain: an output
+    /// paying one of them, or one of their public keys in an input's witness or
+    /// scriptSig. Taproot key-path spends carry no key, so they only match on
+    /// their outputs.
+    pub fn matches_transaction(&self, tx: &Transaction) -> CoreResult<bool> {
+        let secp = Secp256k1::verification_only();
+        let mut scripts: HashSet<ScriptBuf> = HashSet::new();
+        let mut keys: HashSet<Vec<u8>> = HashSet::new();
+        for descriptor in self.parsed_descriptor()?.into_single_descriptors()? {
+            let count = if descriptor.has_wildcard() {
+                TRANSACTION_LOOKAHEAD
+            } else {
+                1
+            };
+            for index in 0..count {
+                // Hardened wildcards can't be derived from public keys
+                let Ok(derived) = descriptor.derived_descriptor(&secp, index) else {
+                    break;
+                };
+                scripts.insert(derived.script_pubkey());
+                derived.for_each_key(|key| {
+                    keys.insert(key.inner.serialize().to_vec());
+                    keys.insert(key.inner.x_only_public_key().0.serialize().to_vec());
+                    true
+                });
+            }
+        }
+
+        if tx
+            .output
+            .iter()
+            .any(|txout| scripts.contains(&txout.script_pubkey))
+        {
+            return Ok(true);
+        }
+        // Keys appear as witness items (p2wpkh, script-path) or inside the
+        // witness script or redeem script that is pushed
+        let contains_key = |data: &[u8]| {
+            [33, 32].into_iter().any(|length| {
+                data.len() >= length && data.windows(length).any(|window| keys.contains(window))
+            })
+        };
+        Ok(tx.input.iter().any(|input| {
+            input.witness.iter().any(contains_key)
+                || input
+                    .script_sig
+                    .instructions()
+                    .any(|instruction| match instruction {
+                        Ok(Instruction::PushBytes(bytes)) => contains_key(bytes.as_bytes()),
+                        _ => false,
+                    })
+        }))
+    }
+}
+
+/// Registry of wallets persisted as JSON
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WalletRegistry {
+    pub wallets: Vec<RegisteredWallet>,
+}
+
+impl WalletRegistry {
+    /// Default registry location: `~/.cyberkrill/wallets.json`
+    pub fn default_path() -> CoreResult<PathBuf> {
+        let home = std::env::var("HOME").context("HOME is not set")?;
+        Ok(Path::new(&home).join(".cyberkrill").join("wallets.json"))
+    }
+
+    /// Load the registry from `path`; a missing file is an empty registry
+    pub fn load(path: &Path) -> CoreResult<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path).with_context(|| {
+            format!(
+                "Failed to read wallet registry: {path}",
+                path = path.display()
+            )
+        })?;
+        Ok(serde_json::from_str(&content).with_context(|| {
+            format!(
+                "Invalid wallet registry file: {path}",
+                path = path.display()
+            )
+        })?)
+    }
+
+    /// Write the registry to `path`, creating parent directories as needed
+    pub fn save(&self, path: &Path) -> CoreResult<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!(
+                    "Failed to create registry directory: {parent}",
+                    parent = parent.display()
+                )
+            })?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        Ok(std::fs::write(path, content).with_context(|| {
+            format!(
+                "Failed to write wallet registry: {path}",
+                path = path.display()
+            )
+        })?)
+    }
+
+    pub fn add(&mut self, wallet: RegisteredWallet) -> CoreResult<()> {
+        ensure!(
+            !self.wallets.iter().any(|w| w.name == wallet.name),
+            "Wallet '{name}' is already registered",
+            name = wallet.name
+        );
+        wallet.parsed_descriptor()?;
+        self.wallets.push(wallet);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> CoreResult<RegisteredWallet> {
+        let position = self
+            .wallets
+            .iter()
+            .position(|w| w.name == name)
+            .with_context(|| format!("Wallet '{name}' is not registered"))?;
+        Ok(self.wallets.remove(position))
+    }
+
+    /// Registered wallets whose keys appear in `psbt`
+    pub fn wallets_for_psbt(&self, psbt: &Psbt) -> CoreResult<Vec<&RegisteredWallet>> {
+        let mut wallets = Vec::new();
+        for wallet in &self.wallets {
+            if wallet.matches_psbt(psbt)? {
+                wallets.push(wallet);
+            }
+        }
+        Ok(wallets)
+    }
+
+    /// Registered wallets a finalized `tx` spends from or pays to
+    pub fn wallets_for_transaction(&self, tx: &Transaction) -> CoreResult<Vec<&RegisteredWallet>> {
+        let mut wallets = Vec::new();
+        for wallet in &self.wallets {
+            if wallet.matches_transaction(tx)? {
+                wallets.push(wallet);
+            }
+        }
+        Ok(wallets)
+    }
+
+    /// Refuse to sign `psbt` for `network` if it uses keys from a wallet registered
+    /// for the other environment, unless `force` is set.
+    pub fn check_psbt_environment(
+        &self,
+        psbt: &Psbt,
+        network: impl Into<NetworkKind>,
+        force: bool,
+    ) -> CoreResult<()> {
+        Ok(check_environment(
+            self.wallets_for_psbt(psbt)?,
+            network.into(),
+            force,
+            "sign",
+        )?)
+    }
+
+    /// Refuse to broadcast `tx` on `network` if it spends from or pays to a
+    /// wallet registered for the other environment, unless `force` is set.
+    pub fn check_transaction_environment(
+        &self,
+        tx: &Transaction,
+        network: impl Into<NetworkKind>,
+        force: bool,
+    ) -> CoreResult<()> {
+        Ok(check_environment(
+            self.wallets_for_transaction(tx)?,
+            network.into(),
+            force,
+            "broadcast",
+        )?)
+    }
+}
+
+fn check_environment(
+    wallets: Vec<&RegisteredWallet>,
+    network: NetworkKind,
+    force: bool,
+    action: &str,
+) -> Result<()> {
+    let target = WalletEnvironment::for_network(network);
+    let mismatched: Vec<String> = wallets
+        .into_iter()
+        .filter(|wallet| wallet.environment != target)
+        .map(|wallet| {
+            format!(
+                "'{name}' ({environment})",
+                name = wallet.name,
+                environment = wallet.environment
+            )
+        })
+        .collect();
+
+    if mismatched.is_empty() || force {
+        return Ok(());
+    }
+    bail!(
+        "Refusing to {action} a {target} transaction with keys from wallet(s) registered for another environment: {wallets}. Use --force-environment to override",
+        wallets = mismatched.join(", ")
+    )
+}
+
+/// Network kind of a PSBT, taken from its global xpubs and from the BIP44
+/// coin type (`m/purpose'/0'` for mainnet, `m/purpose'/1'` otherwise) of the
+/// key origins in its inputs and outputs. `None` when nothing reveals the
+/// network or the hints disagree.
+pub fn infer_psbt_network_kind(psbt: &Psbt) -> Option<NetworkKind> {
+    let input_origins = psbt.inputs.iter().flat_map(|input| {
+        input
+            .bip32_derivation
+            .values()
+            .chain(input.tap_key_origins.values().map(|(_, origin)| origin))
+    });
+    let output_origins = psbt.outputs.iter().flat_map(|output| {
+        output
+            .bip32_derivation
+            .values()
+            .chain(output.tap_key_origins.values().map(|(_, origin)| origin))
+    });
+    let hints: HashSet<NetworkKind> = psbt
+        .xpub
+        .iter()
+        .flat_map(|(xpub, origin)| [Some(xpub.network), coin_type_network(&origin.1)])
+        .flatten()
+        .chain(
+            input_origins
+                .chain(output_origins)
+                .filter_map(|(_, path)| coin_type_network(path)),
+        )
+        .collect();
+
+    let mut hints = hints.into_iter();
+    match (hints.next(), hints.next()) {
+        (Some(network), None) => Some(network),
+        _ => None,
+    }
+}
+
+/// Network implied by the coin type of a BIP44-style `m/purpose'/coin'/...` path
+fn coin_type_network(path: &DerivationPath) -> Option<NetworkKind> {
+    match path.as_ref() {
+        [purpose, coin, ..] if purpose.is_hardened() => match coin {
+            ChildNumber::Hardened { index: 0 } => Some(NetworkKind::Main),
+            ChildNumber::Hardened { index: 1 } => Some(NetworkKind::Test),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{Network, absolute, transaction};
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    const TEST_XPUB: &str = "xpub6BosfCnifzxcJJ1wYuntGJfF2zPJkDeG9ELNHcKNjezuea4tumswN9sH1psMdSVqCMoJC21Bv8usSeqSP4Sp1tLzW7aY59fGn9GCYzx5UTo";
+
+    fn wallet(name: &str, environment: WalletEnvironment) -> RegisteredWallet {
+        RegisteredWallet {
+            name: name.to_string(),
+            descriptor: format!("wpkh([d34db33f/84'/0'/0']{TEST_XPUB}/<0;1>/*)"),
+            environment,
+        }
+    }
+
+    fn psbt_with_fingerprint(fingerprint: &str) -> Result<Psbt> {
+        psbt_with_origin(fingerprint, "m/84'/0'/0'/0/0")
+    }
+
+    fn psbt_with_origin(fingerprint: &str, path: &str) -> Result<Psbt> {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![Default::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let pubkey = bitcoin::secp256k1::PublicKey::from_secret_key(
+            &secp,
+            &bitcoin::secp256k1::SecretKey::from_slice(&[1u8; 32])?,
+        );
+        let mut derivation = BTreeMap::new();
+        derivation.insert(
+            pubkey,
+            (
+                Fingerprint::from_str(fingerprint)?,
+                DerivationPath::from_str(path)?,
+            ),
+        );
+        psbt.inputs[0].bip32_derivation = derivation;
+        Ok(psbt)
+    }
+
+    #[test]
+    fn test_environment_parsing() -> Result<()> {
+        assert_eq!(
+            WalletEnvironment::from_str("prod")?,
+            WalletEnvironment::Prod
+        );
+        assert_eq!(
+            WalletEnvironment::from_str("Production")?,
+            WalletEnvironment::Prod
+        );
+        assert_eq!(
+            WalletEnvironment::from_str("test")?,
+            WalletEnvironment::Test
+        );
+        assert!(WalletEnvironment::from_str("staging").is_err());
+        assert_eq!(
+            WalletEnvironment::for_network(Network::Signet),
+            WalletEnvironment::Test
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_round_trip() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("nested").join("wallets.json");
+
+        let mut registry = WalletRegistry::load(&path)?;
+        assert!(registry.wallets.is_empty());
+        registry.add(wallet("cold", WalletEnvironment::Prod))?;
+        assert!(
+            registry
+                .add(wallet("cold", WalletEnvironment::Test))
+                .is_err()
+        );
+        registry.save(&path)?;
+
+        let mut loaded = WalletRegistry::load(&path)?;
+        assert_eq!(loaded.wallets.len(), 1);
+        assert_eq!(loaded.remove("cold")?.environment, WalletEnvironment::Prod);
+        assert!(loaded.remove("cold").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_psbt_environment() -> Result<()> {
+        let mut registry = WalletRegistry::default();
+        registry.add(wallet("lab", WalletEnvironment::Test))?;
+
+        let psbt = psbt_with_fingerprint("d34db33f")?;
+        assert!(
+            registry
+                .check_psbt_environment(&psbt, Network::Bitcoin, false)
+                .is_err()
+        );
+        registry.check_psbt_environment(&psbt, Network::Bitcoin, true)?;
+        registry.check_psbt_environment(&psbt, Network::Testnet, false)?;
+
+        // Keys not in the registry are not checked
+        let unrelated = psbt_with_fingerprint("00000001")?;
+        registry.check_psbt_environment(&unrelated, Network::Bitcoin, false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_psbt_match_needs_full_origin() -> Result<()> {
+        let lab = wallet("lab", WalletEnvironment::Test);
+        assert!(lab.matches_psbt(&psbt_with_origin("d34db33f", "m/84'/0'/0'/1/3")?)?);
+        // Same seed, another account: a different wallet
+        assert!(!lab.matches_psbt(&psbt_with_origin("d34db33f", "m/84'/0'/1'/0/0")?)?);
+        assert!(!lab.matches_psbt(&psbt_with_origin("d34db33f", "m/84'/1'/0'/0/0")?)?);
+
+        let mut registry = WalletRegistry::default();
+        assert!(
+            registry
+                .add(RegisteredWallet {
+                    descriptor: "wpkh(not-a-key)".to_string(),
+                    ..lab.clone()
+                })
+                .is_err()
+        );
+        registry.add(lab)?;
+        let other_account = psbt_with_origin("d34db33f", "m/84'/0'/1'/0/0")?;
+        registry.check_psbt_environment(&other_account, Network::Bitcoin, false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_transaction_environment() -> Result<()> {
+        let lab = wallet("lab", WalletEnvironment::Test);
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let [receive, change] = <[_; 2]>::try_from(
+            lab.parsed_descriptor()?
+                .into_single_descriptors()?
+                .into_iter()
+                .map(|descriptor| descriptor.derived_descriptor(&secp, 7))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+        .map_err(|_| anyhow::anyhow!("Expected two keychains"))?;
+        let mut registry = WalletRegistry::default();
+        registry.add(lab)?;
+
+        let transaction = |witness: Vec<Vec<u8>>, script_pubkey: ScriptBuf| Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                witness: bitcoin::Witness::from_slice(&witness),
+                ..Default::default()
+            }],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(10_000),
+                script_pubkey,
+            }],
+        };
+        let unrelated_script = ScriptBuf::new_op_return([1u8; 4]);
+
+        // Pays the wallet's change
+        let paying = transaction(vec![], change.script_pubkey());
+        assert!(
+            registry
+                .check_transaction_environment(&paying, Network::Bitcoin, false)
+                .is_err()
+        );
+        registry.check_transaction_environment(&paying, Network::Testnet, false)?;
+        registry.check_transaction_environment(&paying, Network::Bitcoin, true)?;
+
+        // Spends from the wallet: its key is in the p2wpkh witness
+        let mut key = Vec::new();
+        receive.for_each_key(|pk| {
+            key = pk.to_bytes();
+            true
+        });
+        let spending = transaction(vec![vec![0x30; 71], key], unrelated_script.clone());
+        assert!(
+            registry
+                .check_transaction_environment(&spending, Network::Bitcoin, false)
+                .is_err()
+        );
+
+        let unrelated = transaction(vec![vec![0x30; 71], vec![0x02; 33]], unrelated_script);
+        registry.check_transaction_environment(&unrelated, Network::Bitcoin, false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_infer_psbt_network_kind() -> Result<()> {
+        let mainnet = psbt_with_origin("d34db33f", "m/84'/0'/0'/0/0")?;
+        assert_eq!(infer_psbt_network_kind(&mainnet), Some(NetworkKind::Main));
+        let testnet = psbt_with_origin("d34db33f", "m/86'/1'/0'/1/2")?;
+        assert_eq!(infer_psbt_network_kind(&testnet), Some(NetworkKind::Test));
+        // Non-BIP44 paths reveal nothing
+        let custom = psbt_with_origin("d34db33f", "m/0/1")?;
+        assert_eq!(infer_psbt_network_kind(&custom), None);
+
+        // Change outputs count too, and conflicting hints are ambiguous
+        let mut mixed = mainnet.clone();
+        mixed.unsigned_tx.output.push(bitcoin::TxOut::NULL);
+        mixed.outputs.push(bitcoin::psbt::Output {
+            bip32_derivation: testnet.inputs[0].bip32_derivation.clone(),
+            ..Default::default()
+        });
+        assert_eq!(infer_psbt_network_kind(&mixed), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prod_wallet_refused_on_testnet() -> Result<()> {
+        let mut registry = WalletRegistry::default();
+        registry.add(wallet("vault", WalletEnvironment::Prod))?;
+
+        let psbt = psbt_with_fingerprint("d34db33f")?;
+        let error = match registry.check_psbt_environment(&psbt, Network::Regtest, false) {
+            Ok(()) => bail!("Expected environment mismatch"),
+            Err(error) => error,
+        };
+        assert!(error.to_string().contains("'vault' (prod)"));
+        Ok(())
+    }
+}
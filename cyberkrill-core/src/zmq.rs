@@ -0,0 +1,473 @@
+//! Bitcoin Core ZMQ notifications
+//!
+//! Bitcoin Core publishes new transactions and blocks over ZMQ when started
+//! with e.g. `-zmqpubrawtx=tcp://127.0.0.1:28332` and
+//! `-zmqpubrawblock=tcp://127.0.0.1:28332`. Subscribing lets watch commands
+//! react as soon as something happens instead of polling the node.
+//!
+//! This is a minimal ZMTP 3.0 SUB client over TCP with the NULL security
+//! mechanism, which is all Bitcoin Core offers, so libzmq is not needed.
+
+use crate::error::{CoreResult, bail, ensure};
+use anyhow::{Context, Result};
+use bitcoin::hashes::Hash;
+use bitcoin::{Block, BlockHash, Transaction, Txid};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest frame accepted; blocks are at most 4 MB serialized
+const MAX_FRAME_SIZE: u64 = 32 * 1024 * 1024;
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// Notification streams published by Bitcoin Core
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ZmqTopic {
+    RawTx,
+    RawBlock,
+    HashTx,
+    HashBlock,
+}
+
+impl ZmqTopic {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RawTx => "rawtx",
+            Self::RawBlock => "rawblock",
+            Self::HashTx => "hashtx",
+            Self::HashBlock => "hashblock",
+        }
+    }
+
+    fn from_bytes(topic: &[u8]) -> Option<Self> {
+        [Self::RawTx, Self::RawBlock, Self::HashTx, Self::HashBlock]
+            .into_iter()
+            .find(|candidate| candidate.as_str().as_bytes() == topic)
+    }
+}
+
+/// A decoded notification
+#[derive(Debug, Clone)]
+pub enum ZmqNotification {
+    /// A transaction entered the mempool or was included in a connected block
+    RawTx(Transaction),
+    /// A block was connected to the tip
+    RawBlock(Block),
+    HashTx(Txid),
+    HashBlock(BlockHash),
+}
+
+/// Subscription to a Bitcoin Core ZMQ publisher
+///
+/// The socket is read by a background task, so [`ZmqSubscriber::next`] is
+/// cancel-safe and can be raced against timers in `tokio::select!`.
+pub struct ZmqSubscriber {
+    endpoint: String,
+    notifications: mpsc::Receiver<Result<ZmqNotification>>,
+    reader: JoinHandle<()>,
+}
+
+impl ZmqSubscriber {
+    /// Connect to `endpoint` (`tcp://host:port`) and subscribe to `topics`
+    pub async fn connect(endpoint: &str, topics: &[ZmqTopic]) -> CoreResult<Self> {
+        let address = endpoint.strip_prefix("tcp://").with_context(|| {
+            format!("Unsupported ZMQ endpoint {endpoint}: expected tcp://host:port")
+        })?;
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address))
+            .await
+            .with_context(|| format!("Timed out connecting to ZMQ endpoint {endpoint}"))?
+            .with_context(|| format!("Failed to connect to ZMQ endpoint {endpoint}"))?;
+        let mut connection = Connection {
+            stream: BufReader::new(stream),
+            last_sequence: HashMap::new(),
+        };
+        connection
+            .handshake(topics)
+            .await
+            .with_context(|| format!("ZMQ handshake with {endpoint} failed"))?;
+        debug!("Subscribed to {topics:?} on {endpoint}");
+
+        let (sender, notifications) = mpsc::channel(16);
+        let reader_endpoint = endpoint.to_string();
+        let reader = tokio::spawn(async move {
+            loop {
+                let notification = connection.next_notification(&reader_endpoint).await;
+                let failed = notification.is_err();
+                if sender.send(notification).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            endpoint: endpoint.to_string(),
+            notifications,
+            reader,
+        })
+    }
+
+    /// Wait for the next notification; an error means the subscription is over
+    pub async fn next(&mut self) -> CoreResult<ZmqNotification> {
+        let notification = self.notifications.recv().await.with_context(|| {
+            format!(
+                "ZMQ subscription to {endpoint} closed",
+                endpoint = self.endpoint
+            )
+        })?;
+        Ok(notification?)
+    }
+}
+
+impl Drop for ZmqSubscriber {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+struct Frame {
+    more: bool,
+    command: bool,
+    body: Vec<u8>,
+}
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+    last_sequence: HashMap<ZmqTopic, u32>,
+}
+
+impl Connection {
+    async fn handshake(&mut self, topics: &[ZmqTopic]) -> Result<()> {
+        self.stream.get_mut().write_all(&greeting()).await?;
+        let mut peer = [0u8; 64];
+        self.stream.read_exact(&mut peer).await?;
+        ensure!(
+            peer[0] == 0xff && peer[9] & 0x01 == 0x01,
+            "Peer is not a ZMQ socket"
+        );
+        ensure!(
+            peer[10] >= 3,
+            "Unsupported ZMTP version {major}.{minor}",
+            major = peer[10],
+            minor = peer[11]
+        );
+        ensure!(
+            peer[12..32].starts_with(b"NULL\0"),
+            "Unsupported ZMQ security mechanism {mechanism:?}",
+            mechanism = String::from_utf8_lossy(&peer[12..32]).trim_end_matches('\0')
+        );
+
+        self.send_frame(FLAG_COMMAND, &ready_command()).await?;
+        let frame = self.read_frame().await?;
+        ensure!(frame.command, "Expected a READY command from the peer");
+        let properties = parse_command(&frame.body, "READY")?;
+        let socket_type = properties
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Socket-Type"))
+            .map(|(_, value)| value.as_slice());
+        ensure!(
+            socket_type == Some(b"PUB".as_slice()) || socket_type == Some(b"XPUB".as_slice()),
+            "Peer is not a ZMQ publisher (socket type {socket_type:?})",
+            socket_type = socket_type.map(String::from_utf8_lossy)
+        );
+
+        // ZMTP 3.0 subscriptions are messages starting with 0x01
+        for topic in topics {
+            let mut subscription = vec![0x01];
+            subscription.extend_from_slice(topic.as_str().as_bytes());
+            self.send_frame(0, &subscription).await?;
+        }
+        Ok(())
+    }
+
+    /// Next notification for a subscribed topic, skipping anything else; only
+    /// connection failures are errors
+    async fn next_notification(&mut self, endpoint: &str) -> Result<ZmqNotification> {
+        loop {
+            let parts = self.read_message().await?;
+            let [topic, body, sequence] = parts.as_slice() else {
+                warn!(
+                    "Ignoring ZMQ message with {count} parts from {endpoint}",
+                    count = parts.len()
+                );
+                continue;
+            };
+            let Some(topic) = ZmqTopic::from_bytes(topic) else {
+                continue;
+            };
+            if let Ok(sequence) = <[u8; 4]>::try_from(sequence.as_slice()) {
+                self.check_sequence(topic, u32::from_le_bytes(sequence), endpoint);
+            }
+            match decode_notification(topic, body) {
+                Ok(notification) => return Ok(notification),
+                Err(e) => warn!("Ignoring ZMQ notification from {endpoint}: {e:#}"),
+            }
+        }
+    }
+
+    fn check_sequence(&mut self, topic: ZmqTopic, sequence: u32, endpoint: &str) {
+        if let Some(last) = self.last_sequence.insert(topic, sequence) {
+            let missed = sequence.wrapping_sub(last).wrapping_sub(1);
+            if missed > 0 {
+                warn!(
+                    "Missed {missed} {topic} notification(s) from {endpoint}",
+                    topic = topic.as_str()
+                );
+            }
+        }
+    }
+
+    /// All frames of the next message, skipping commands
+    async fn read_message(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut parts = Vec::new();
+        loop {
+            let frame = self.read_frame().await?;
+            if frame.command {
+                // ERROR carries a length-prefixed reason
+                if let Some(reason) = frame.body.strip_prefix(b"\x05ERROR") {
+                    bail!(
+                        "ZMQ peer reported an error: {reason}",
+                        reason = String::from_utf8_lossy(reason.get(1..).unwrap_or_default())
+                    );
+                }
+                continue;
+            }
+            parts.push(frame.body);
+            if !frame.more {
+                return Ok(parts);
+            }
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Frame> {
+        let flags = self.stream.read_u8().await?;
+        let size = if flags & FLAG_LONG != 0 {
+            self.stream.read_u64().await?
+        } else {
+            u64::from(self.stream.read_u8().await?)
+        };
+        ensure!(
+            size <= MAX_FRAME_SIZE,
+            "ZMQ frame of {size} bytes exceeds the {MAX_FRAME_SIZE} byte limit"
+        );
+        let mut body = vec![0u8; size as usize];
+        self.stream.read_exact(&mut body).await?;
+        Ok(Frame {
+            more: flags & FLAG_MORE != 0,
+            command: flags & FLAG_COMMAND != 0,
+            body,
+        })
+    }
+
+    async fn send_frame(&mut self, flags: u8, body: &[u8]) -> Result<()> {
+        let stream = self.stream.get_mut();
+        match u8::try_from(body.len()) {
+            Ok(size) => stream.write_all(&[flags, size]).await?,
+            Err(_) => {
+                stream.write_all(&[flags | FLAG_LONG]).await?;
+                stream.write_all(&(body.len() as u64).to_be_bytes()).await?;
+            }
+        }
+        stream.write_all(body).await?;
+        Ok(())
+    }
+}
+
+/// ZMTP 3.0 greeting for a client using the NULL mechanism
+fn greeting() -> [u8; 64] {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+fn ready_command() -> Vec<u8> {
+    let mut command = vec![5];
+    command.extend_from_slice(b"READY");
+    command.push(11);
+    command.extend_from_slice(b"Socket-Type");
+    command.extend_from_slice(&3u32.to_be_bytes());
+    command.extend_from_slice(b"SUB");
+    command
+}
+
+/// Metadata properties of the command `name`, or an error if `body` is another command
+fn parse_command(body: &[u8], name: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let (&name_len, rest) = body.split_first().context("Empty ZMQ command")?;
+    let (command, mut rest) = rest
+        .split_at_checked(name_len.into())
+        .context("Truncated ZMQ command name")?;
+    ensure!(
+        command == name.as_bytes(),
+        "Expected a {name} command, got {command:?}",
+        command = String::from_utf8_lossy(command)
+    );
+    let mut properties = Vec::new();
+    while let Some((&key_len, after)) = rest.split_first() {
+        let (key, after) = after
+            .split_at_checked(key_len.into())
+            .context("Truncated ZMQ property name")?;
+        let (value_len, after) = after
+            .split_first_chunk::<4>()
+            .context("Truncated ZMQ property length")?;
+        let (value, after) = after
+            .split_at_checked(u32::from_be_bytes(*value_len) as usize)
+            .context("Truncated ZMQ property value")?;
+        properties.push((String::from_utf8_lossy(key).into_owned(), value.to_vec()));
+        rest = after;
+    }
+    Ok(properties)
+}
+
+fn decode_notification(topic: ZmqTopic, body: &[u8]) -> Result<ZmqNotification> {
+    use bitcoin::consensus::deserialize;
+
+    // Hashes are published in display (reversed) byte order
+    let hash = || -> Result<[u8; 32]> {
+        let mut hash = <[u8; 32]>::try_from(body)
+            .with_context(|| format!("Invalid {topic} hash", topic = topic.as_str()))?;
+        hash.reverse();
+        Ok(hash)
+    };
+    Ok(match topic {
+        ZmqTopic::RawTx => {
+            ZmqNotification::RawTx(deserialize(body).context("Invalid rawtx notification")?)
+        }
+        ZmqTopic::RawBlock => {
+            ZmqNotification::RawBlock(deserialize(body).context("Invalid rawblock notification")?)
+        }
+        ZmqTopic::HashTx => ZmqNotification::HashTx(Txid::from_byte_array(hash()?)),
+        ZmqTopic::HashBlock => ZmqNotification::HashBlock(BlockHash::from_byte_array(hash()?)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn frame(flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut frame = vec![flags, body.len() as u8];
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    /// Accept one subscriber, complete the handshake and publish `messages`
+    async fn publish(listener: TcpListener, messages: Vec<Vec<Vec<u8>>>) -> Result<Vec<Vec<u8>>> {
+        let (mut stream, _) = listener.accept().await?;
+        let mut greeting = [0u8; 64];
+        stream.read_exact(&mut greeting).await?;
+        stream.write_all(&super::greeting()).await?;
+
+        let mut ready = vec![5];
+        ready.extend_from_slice(b"READY");
+        ready.push(11);
+        ready.extend_from_slice(b"Socket-Type");
+        ready.extend_from_slice(&3u32.to_be_bytes());
+        ready.extend_from_slice(b"PUB");
+        stream.write_all(&frame(FLAG_COMMAND, &ready)).await?;
+
+        // Client READY, then one subscription per topic
+        let mut subscriptions = Vec::new();
+        for index in 0..3 {
+            let flags = stream.read_u8().await?;
+            let mut body = vec![0u8; stream.read_u8().await?.into()];
+            stream.read_exact(&mut body).await?;
+            if index == 0 {
+                assert_eq!(flags, FLAG_COMMAND);
+                assert_eq!(parse_command(&body, "READY")?[0].1, b"SUB");
+            } else {
+                subscriptions.push(body);
+            }
+        }
+
+        for parts in messages {
+            let last = parts.len() - 1;
+            for (index, part) in parts.iter().enumerate() {
+                let flags = if index < last { FLAG_MORE } else { 0 };
+                stream.write_all(&frame(flags, part)).await?;
+            }
+        }
+        Ok(subscriptions)
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_and_receive() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("tcp://{address}", address = listener.local_addr()?);
+
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(50_000),
+                script_pubkey: bitcoin::ScriptBuf::new(),
+            }],
+        };
+        let mut block_hash = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin)
+            .block_hash()
+            .to_byte_array();
+        block_hash.reverse();
+        let publisher = tokio::spawn(publish(
+            listener,
+            vec![
+                vec![
+                    b"rawtx".to_vec(),
+                    bitcoin::consensus::serialize(&tx),
+                    0u32.to_le_bytes().to_vec(),
+                ],
+                // Topics that were not asked for are skipped
+                vec![
+                    b"sequence".to_vec(),
+                    vec![0; 33],
+                    0u32.to_le_bytes().to_vec(),
+                ],
+                vec![
+                    b"hashblock".to_vec(),
+                    block_hash.to_vec(),
+                    0u32.to_le_bytes().to_vec(),
+                ],
+            ],
+        ));
+
+        let mut subscriber =
+            ZmqSubscriber::connect(&endpoint, &[ZmqTopic::RawTx, ZmqTopic::HashBlock]).await?;
+        match subscriber.next().await? {
+            ZmqNotification::RawTx(received) => assert_eq!(received, tx),
+            other => bail!("Unexpected notification {other:?}"),
+        }
+        match subscriber.next().await? {
+            ZmqNotification::HashBlock(hash) => assert_eq!(
+                hash,
+                bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin).block_hash()
+            ),
+            other => bail!("Unexpected notification {other:?}"),
+        }
+
+        let subscriptions = publisher.await??;
+        assert_eq!(
+            subscriptions,
+            vec![b"\x01rawtx".to_vec(), b"\x01hashblock".to_vec()]
+        );
+
+        // The publisher hung up
+        assert!(subscriber.next().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_non_tcp_endpoints() {
+        let result = ZmqSubscriber::connect("ipc:///tmp/bitcoind.sock", &[ZmqTopic::RawTx]).await;
+        assert!(result.is_err_and(|e| e.to_string().contains("tcp://host:port")));
+    }
+}
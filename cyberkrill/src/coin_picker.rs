@@ -0,0 +1,366 @@
+//! Terminal UI for picking the inputs (and outputs) of a PSBT by hand
+
+use anyhow::Result;
+use cyberkrill_core::{BdkUtxo, WalletLabels};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+/// A UTXO as shown in the picker
+#[derive(Debug, Clone)]
+pub struct PickerCoin {
+    pub utxo: BdkUtxo,
+    pub label: Option<String>,
+    /// Marked unspendable in the labels file; can't be selected
+    pub frozen: bool,
+}
+
+impl PickerCoin {
+    pub fn outpoint(&self) -> String {
+        format!(
+            "{txid}:{vout}",
+            txid = self.utxo.txid,
+            vout = self.utxo.vout
+        )
+    }
+}
+
+/// What the user decided when leaving the picker
+#[derive(Debug, PartialEq, Eq)]
+pub enum Selection {
+    Build {
+        /// Selected inputs as txid:vout
+        inputs: Vec<String>,
+        /// Outputs typed in the picker, when they weren't given up front
+        outputs: Option<String>,
+    },
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Select,
+    Outputs,
+}
+
+#[derive(Debug)]
+pub struct CoinPicker {
+    coins: Vec<PickerCoin>,
+    selected: Vec<bool>,
+    cursor: usize,
+    mode: Mode,
+    /// Ask for the outputs after the inputs are picked
+    ask_outputs: bool,
+    outputs: String,
+    message: Option<String>,
+}
+
+impl CoinPicker {
+    /// Coins are listed largest first
+    pub fn new(mut coins: Vec<PickerCoin>, ask_outputs: bool) -> Self {
+        coins.sort_by(|a, b| b.utxo.amount.cmp(&a.utxo.amount));
+        Self {
+            selected: vec![false; coins.len()],
+            coins,
+            cursor: 0,
+            mode: Mode::Select,
+            ask_outputs,
+            outputs: String::new(),
+            message: None,
+        }
+    }
+
+    pub fn with_labels(utxos: Vec<BdkUtxo>, labels: &WalletLabels, ask_outputs: bool) -> Self {
+        let coins = utxos
+            .into_iter()
+            .map(|utxo| PickerCoin {
+                label: labels
+                    .utxo_label(&utxo.txid, utxo.vout, &utxo.address)
+                    .map(str::to_string),
+                frozen: labels.is_frozen(&utxo.txid, utxo.vout),
+                utxo,
+            })
+            .collect();
+        Self::new(coins, ask_outputs)
+    }
+
+    /// Run the picker in the terminal until the user builds or cancels
+    pub fn run(mut self) -> Result<Selection> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<Selection> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && let Some(selection) = self.handle_key(key.code)
+            {
+                return Ok(selection);
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyCode) -> Option<Selection> {
+        self.message = None;
+        match self.mode {
+            Mode::Select => self.handle_select_key(key),
+            Mode::Outputs => self.handle_outputs_key(key),
+        }
+    }
+
+    fn handle_select_key(&mut self, key: KeyCode) -> Option<Selection> {
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.cursor = (self.cursor + 1).min(self.coins.len().saturating_sub(1));
+            }
+            KeyCode::Char(' ') => self.toggle(self.cursor),
+            KeyCode::Char('a') => {
+                let select = !self.all_selected();
+                for (selected, coin) in self.selected.iter_mut().zip(&self.coins) {
+                    *selected = select && !coin.frozen;
+                }
+            }
+            KeyCode::Enter => {
+                if !self.selected.contains(&true) {
+                    self.message = Some("Select at least one coin (space)".to_string());
+                } else if self.ask_outputs {
+                    self.mode = Mode::Outputs;
+                } else {
+                    return Some(Selection::Build {
+                        inputs: self.selected_inputs(),
+                        outputs: None,
+                    });
+                }
+            }
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Selection::Cancel),
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_outputs_key(&mut self, key: KeyCode) -> Option<Selection> {
+        match key {
+            KeyCode::Char(c) => self.outputs.push(c),
+            KeyCode::Backspace => {
+                self.outputs.pop();
+            }
+            KeyCode::Esc => self.mode = Mode::Select,
+            KeyCode::Enter => {
+                let outputs = self.outputs.trim();
+                if outputs.is_empty() {
+                    self.message = Some("Enter outputs as address:amount,...".to_string());
+                } else {
+                    return Some(Selection::Build {
+                        inputs: self.selected_inputs(),
+                        outputs: Some(outputs.to_string()),
+                    });
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn toggle(&mut self, index: usize) {
+        let Some(coin) = self.coins.get(index) else {
+            return;
+        };
+        if coin.frozen {
+            self.message = Some(format!(
+                "{outpoint} is frozen in the labels file",
+                outpoint = coin.outpoint()
+            ));
+            return;
+        }
+        self.selected[index] = !self.selected[index];
+    }
+
+    fn all_selected(&self) -> bool {
+        self.coins
+            .iter()
+            .zip(&self.selected)
+            .all(|(coin, &selected)| selected || coin.frozen)
+    }
+
+    fn selected_inputs(&self) -> Vec<String> {
+        self.selected_coins().map(PickerCoin::outpoint).collect()
+    }
+
+    fn selected_coins(&self) -> impl Iterator<Item = &PickerCoin> {
+        self.coins
+            .iter()
+            .zip(&self.selected)
+            .filter(|(_, selected)| **selected)
+            .map(|(coin, _)| coin)
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [table_area, status_area, help_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let rows = self
+            .coins
+            .iter()
+            .zip(&self.selected)
+            .map(|(coin, &selected)| {
+                let mark = match (coin.frozen, selected) {
+                    (true, _) => "[-]",
+                    (false, true) => "[x]",
+                    (false, false) => "[ ]",
+                };
+                let row = Row::new([
+                    mark.to_string(),
+                    short_outpoint(coin),
+                    format!("{btc:.8}", btc = coin.utxo.amount_btc),
+                    coin.utxo.confirmations.to_string(),
+                    coin.utxo.address.clone(),
+                    coin.label.clone().unwrap_or_default(),
+                ]);
+                if coin.frozen { row.dim() } else { row }
+            });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Length(20),
+                Constraint::Length(14),
+                Constraint::Length(7),
+                Constraint::Length(44),
+                Constraint::Min(10),
+            ],
+        )
+        .header(Row::new(["", "outpoint", "value (BTC)", "confs", "address", "label"]).bold())
+        .block(Block::bordered().title(" UTXOs "))
+        .row_highlight_style(Style::new().reversed());
+        let mut state = TableState::default().with_selected(Some(self.cursor));
+        frame.render_stateful_widget(table, table_area, &mut state);
+
+        let selected_sats: u64 = self.selected_coins().map(|coin| coin.utxo.amount).sum();
+        let total_sats: u64 = self.coins.iter().map(|coin| coin.utxo.amount).sum();
+        let status = match (self.mode, &self.message) {
+            (_, Some(message)) => message.clone(),
+            (Mode::Outputs, None) => format!("Outputs: {outputs}_", outputs = self.outputs),
+            (Mode::Select, None) => format!(
+                "Selected {count} of {total} coins: {selected} of {all} BTC",
+                count = self.selected_coins().count(),
+                total = self.coins.len(),
+                selected = sats_to_btc(selected_sats),
+                all = sats_to_btc(total_sats),
+            ),
+        };
+        frame.render_widget(Paragraph::new(status).block(Block::bordered()), status_area);
+
+        let help = match self.mode {
+            Mode::Select => "↑/↓ move  space select  a all  enter continue  q quit",
+            Mode::Outputs => "address:amount[,address:amount...]  enter build  esc back",
+        };
+        frame.render_widget(Paragraph::new(help).dim(), help_area);
+    }
+}
+
+fn short_outpoint(coin: &PickerCoin) -> String {
+    let txid = &coin.utxo.txid;
+    let prefix = txid.get(..8).unwrap_or(txid);
+    let suffix = txid.get(txid.len().saturating_sub(4)..).unwrap_or_default();
+    format!("{prefix}…{suffix}:{vout}", vout = coin.utxo.vout)
+}
+
+fn sats_to_btc(sats: u64) -> String {
+    format!("{btc:.8}", btc = sats as f64 / 100_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(txid: &str, vout: u32, amount: u64) -> BdkUtxo {
+        BdkUtxo {
+            txid: txid.to_string(),
+            vout,
+            address: format!("bc1q{txid}"),
+            amount,
+            amount_btc: amount as f64 / 100_000_000.0,
+            confirmations: 6,
+            is_change: false,
+            keychain: "External".to_string(),
+            derivation_index: Some(vout),
+        }
+    }
+
+    #[test]
+    fn test_pick_inputs_then_outputs() -> Result<()> {
+        let labels =
+            WalletLabels::parse(r#"{"type": "output", "ref": "cc:0", "spendable": false}"#)?;
+        let utxos = vec![
+            utxo("aa", 0, 1_000),
+            utxo("bb", 1, 50_000),
+            utxo("cc", 0, 9_000),
+        ];
+        let mut picker = CoinPicker::with_labels(utxos, &labels, true);
+
+        // Largest first: bb:1, cc:0 (frozen), aa:0
+        assert_eq!(picker.handle_key(KeyCode::Enter), None);
+        assert!(picker.message.is_some());
+        picker.handle_key(KeyCode::Char(' '));
+        picker.handle_key(KeyCode::Down);
+        picker.handle_key(KeyCode::Char(' '));
+        assert!(
+            picker
+                .message
+                .as_deref()
+                .is_some_and(|m| m.contains("frozen"))
+        );
+        picker.handle_key(KeyCode::Char('j'));
+        picker.handle_key(KeyCode::Char(' '));
+        assert_eq!(picker.selected_inputs(), ["bb:1", "aa:0"]);
+
+        assert_eq!(picker.handle_key(KeyCode::Enter), None);
+        for c in "bc1qdest:0.0005".chars() {
+            picker.handle_key(KeyCode::Char(c));
+        }
+        assert_eq!(
+            picker.handle_key(KeyCode::Enter),
+            Some(Selection::Build {
+                inputs: vec!["bb:1".to_string(), "aa:0".to_string()],
+                outputs: Some("bc1qdest:0.0005".to_string()),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_all_skips_frozen_and_toggles() -> Result<()> {
+        let labels =
+            WalletLabels::parse(r#"{"type": "output", "ref": "aa:0", "spendable": false}"#)?;
+        let mut picker =
+            CoinPicker::with_labels(vec![utxo("aa", 0, 1), utxo("bb", 0, 2)], &labels, false);
+        picker.handle_key(KeyCode::Char('a'));
+        assert_eq!(picker.selected_inputs(), ["bb:0"]);
+        picker.handle_key(KeyCode::Char('a'));
+        assert!(picker.selected_inputs().is_empty());
+        picker.handle_key(KeyCode::Char('a'));
+        assert_eq!(
+            picker.handle_key(KeyCode::Enter),
+            Some(Selection::Build {
+                inputs: vec!["bb:0".to_string()],
+                outputs: None,
+            })
+        );
+        assert_eq!(
+            picker.handle_key(KeyCode::Char('q')),
+            Some(Selection::Cancel)
+        );
+        Ok(())
+    }
+}
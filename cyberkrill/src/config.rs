@@ -0,0 +1,455 @@
+//! Defaults read from `~/.config/cyberkrill/config.toml`
+//!
+//! Values from the file become the defaults of the matching flags of the command
+//! being run, so anything given on the command line or through an environment
+//! variable still wins. Backend, fee, signer and Lightning node settings are
+//! applied as a group: when the command line picks its own backend (or fee, or
+//! signer, or node), none of the file's settings of that group are used, so they
+//! can't mix with the explicit choice.
+//!
+//! Named profiles (`[profiles.<name>]`, picked with `--profile`) bundle a network,
+//! backend and proxy that replace the top-level ones.
+
+use anyhow::{Context, Result, bail};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Flags that choose the backend of a command
+const BACKEND_ARGS: &[&str] = &[
+    "electrum",
+    "esplora",
+    "cbf",
+    "rpc_url",
+    "bitcoin_dir",
+    "rpc_user",
+    "rpc_password",
+    "rpc_wallet",
+];
+/// Flags that choose the fee of a transaction
+const FEE_ARGS: &[&str] = &["fee_rate", "fee", "conf_target", "estimate_mode"];
+/// Flags that choose the Lightning node queried
+const LIGHTNING_ARGS: &[&str] = &["lnd_url", "lnd_macaroon", "lnd_cert_fingerprint"];
+/// Flags that choose what signs
+const SIGNER_ARGS: &[&str] = &["device", "key", "private_key", "xprv", "mnemonic_file"];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Network of the commands that take --network
+    pub network: Option<String>,
+    /// Proxy for all network traffic, as --proxy
+    pub proxy: Option<String>,
+    pub backend: BackendConfig,
+    pub fees: FeeConfig,
+    pub hardware_wallet: HardwareWalletConfig,
+    pub lightning: LightningConfig,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Settings selected together with `--profile <name>`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub network: Option<String>,
+    pub proxy: Option<String>,
+    /// Replaces the whole top-level `[backend]` when set
+    pub backend: BackendConfig,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    pub electrum: Option<String>,
+    pub esplora: Option<String>,
+    pub rpc_url: Option<String>,
+    pub bitcoin_dir: Option<String>,
+    pub rpc_user: Option<String>,
+    pub rpc_password: Option<String>,
+    pub rpc_wallet: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeConfig {
+    /// Fee rate in sats/vB, as --fee-rate
+    pub fee_rate: Option<String>,
+    /// Confirmation target in blocks for fee estimates, as --conf-target
+    pub conf_target: Option<u32>,
+    /// Largest fee as a percentage of the amount sent, as --fee-cap-percent
+    pub fee_cap_percent: Option<f64>,
+    /// Largest fee rate in sat/vB, as --fee-rate-cap
+    pub fee_rate_cap: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HardwareWalletConfig {
+    /// Hardware wallet of the commands that take --device (trezor, jade, coldcard, bitbox)
+    pub device: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LightningConfig {
+    /// LND REST URL used for graph lookups, as --lnd-url
+    pub lnd_url: Option<String>,
+    /// LND macaroon file, as --lnd-macaroon
+    pub lnd_macaroon: Option<String>,
+    /// SHA-256 fingerprint of LND's TLS certificate, as --lnd-cert-fingerprint
+    pub lnd_cert_fingerprint: Option<String>,
+}
+
+impl Config {
+    /// Default location: `$XDG_CONFIG_HOME/cyberkrill/config.toml`, falling back
+    /// to `~/.config/cyberkrill/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(base.join("cyberkrill").join("config.toml"))
+    }
+
+    /// Load `path`, or the default location when `None`; only a missing default
+    /// file is an empty configuration
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        if !explicit && !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {path}", path = path.display()))?;
+        Self::parse(&content)
+            .with_context(|| format!("Invalid config file {path}", path = path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content)?;
+        config.backend.validate()?;
+        for (name, profile) in &config.profiles {
+            profile
+                .backend
+                .validate()
+                .with_context(|| format!("Invalid profile {name}"))?;
+        }
+        Ok(config)
+    }
+
+    /// Replace the network, proxy and backend with those of profile `name`
+    pub fn with_profile(mut self, name: &str) -> Result<Self> {
+        let Some(profile) = self.profiles.remove(name) else {
+            let available = self.profiles.keys().cloned().collect::<Vec<_>>();
+            if available.is_empty() {
+                bail!("Unknown profile {name}: the config file defines no [profiles]");
+            }
+            bail!(
+                "Unknown profile {name}, available: {available}",
+                available = available.join(", ")
+            );
+        };
+        if profile.network.is_some() {
+            self.network = profile.network;
+        }
+        if profile.proxy.is_some() {
+            self.proxy = profile.proxy;
+        }
+        if profile.backend != BackendConfig::default() {
+            self.backend = profile.backend;
+        }
+        Ok(self)
+    }
+
+    /// Install the file's values as defaults of `command`, following the
+    /// subcommand path of `matches` (a parse of the same arguments without them)
+    pub fn apply(&self, command: Command, matches: &ArgMatches) -> Command {
+        let command = set_default(command, "proxy", self.proxy.as_deref());
+        self.apply_to_subcommand(command, matches)
+    }
+
+    fn apply_to_subcommand(&self, mut command: Command, matches: &ArgMatches) -> Command {
+        if let Some((name, sub_matches)) = matches.subcommand() {
+            return command.mut_subcommand(name, |sub| self.apply_to_subcommand(sub, sub_matches));
+        }
+
+        let explicit = |ids: &[&str]| {
+            ids.iter().any(|id| {
+                has_arg(&command, id)
+                    && matches!(
+                        matches.value_source(id),
+                        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                    )
+            })
+        };
+        let mut defaults = Vec::new();
+        if !explicit(&["network"]) {
+            defaults.push(("network", self.network.clone()));
+        }
+        if !explicit(BACKEND_ARGS) {
+            let backend = &self.backend;
+            defaults.extend([
+                ("electrum", backend.electrum.clone()),
+                ("esplora", backend.esplora.clone()),
+                ("rpc_url", backend.rpc_url.clone()),
+                ("bitcoin_dir", backend.bitcoin_dir.clone()),
+                ("rpc_user", backend.rpc_user.clone()),
+                ("rpc_password", backend.rpc_password.clone()),
+                ("rpc_wallet", backend.rpc_wallet.clone()),
+            ]);
+        }
+        if !explicit(FEE_ARGS) {
+            defaults.extend([
+                ("fee_rate", self.fees.fee_rate.clone()),
+                (
+                    "conf_target",
+                    self.fees.conf_target.map(|target| target.to_string()),
+                ),
+            ]);
+        }
+        // Caps are independent of how the fee is chosen, so they apply even when
+        // --fee-rate or --conf-target is given
+        defaults.extend([
+            (
+                "fee_cap_percent",
+                self.fees.fee_cap_percent.map(|percent| percent.to_string()),
+            ),
+            (
+                "fee_rate_cap",
+                self.fees.fee_rate_cap.map(|rate| rate.to_string()),
+            ),
+        ]);
+        if !explicit(SIGNER_ARGS) {
+            defaults.push(("device", self.hardware_wallet.device.clone()));
+        }
+        if !explicit(LIGHTNING_ARGS) {
+            let lightning = &self.lightning;
+            defaults.extend([
+                ("lnd_url", lightning.lnd_url.clone()),
+                ("lnd_macaroon", lightning.lnd_macaroon.clone()),
+                (
+                    "lnd_cert_fingerprint",
+                    lightning.lnd_cert_fingerprint.clone(),
+                ),
+            ]);
+        }
+
+        for (id, value) in defaults {
+            command = set_default(command, id, value.as_deref());
+        }
+        command
+    }
+}
+
+impl BackendConfig {
+    fn validate(&self) -> Result<()> {
+        let rpc = self.rpc_url.is_some()
+            || self.bitcoin_dir.is_some()
+            || self.rpc_user.is_some()
+            || self.rpc_password.is_some()
+            || self.rpc_wallet.is_some();
+        let chosen = [self.electrum.is_some(), self.esplora.is_some(), rpc];
+        if chosen.iter().filter(|&&chosen| chosen).count() > 1 {
+            bail!("[backend] must configure only one of electrum, esplora or Bitcoin Core RPC");
+        }
+        if self.bitcoin_dir.is_some() && (self.rpc_user.is_some() || self.rpc_password.is_some()) {
+            bail!(
+                "[backend] bitcoin_dir (cookie authentication) conflicts with rpc_user/rpc_password"
+            );
+        }
+        Ok(())
+    }
+}
+
+fn has_arg(command: &Command, id: &str) -> bool {
+    command.get_arguments().any(|arg| arg.get_id() == id)
+}
+
+fn set_default(command: Command, id: &str, value: Option<&str>) -> Command {
+    match value {
+        Some(value) if has_arg(&command, id) => {
+            let value = value.to_string();
+            command.mut_arg(id, |arg| arg.default_value(value))
+        }
+        _ => command,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn test_command() -> Command {
+        Command::new("cyberkrill")
+            .arg(Arg::new("proxy").long("proxy").global(true))
+            .subcommand(
+                Command::new("onchain-list-utxos")
+                    .arg(Arg::new("electrum").long("electrum"))
+                    .arg(Arg::new("esplora").long("esplora"))
+                    .arg(
+                        Arg::new("rpc_url")
+                            .long("rpc-url")
+                            .default_value("http://127.0.0.1:8332"),
+                    )
+                    .arg(Arg::new("network").long("network").default_value("mainnet"))
+                    .arg(Arg::new("fee_rate").long("fee-rate")),
+            )
+    }
+
+    fn parse_with(config: &Config, args: &[&str]) -> ArgMatches {
+        let first = test_command().get_matches_from(args.iter().copied());
+        config
+            .apply(test_command(), &first)
+            .get_matches_from(args.iter().copied())
+    }
+
+    fn value<'a>(matches: &'a ArgMatches, id: &str) -> Option<&'a str> {
+        let (_, sub) = matches.subcommand()?;
+        sub.get_one::<String>(id).map(String::as_str)
+    }
+
+    #[test]
+    fn test_parse_config() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            network = "signet"
+            proxy = "socks5://127.0.0.1:9050"
+
+            [backend]
+            esplora = "https://mutinynet.com/api"
+
+            [fees]
+            fee_rate = "2"
+            conf_target = 3
+            fee_rate_cap = 100.0
+
+            [hardware_wallet]
+            device = "jade"
+
+            [lightning]
+            lnd_url = "https://127.0.0.1:8080"
+            lnd_macaroon = "/home/user/.lnd/readonly.macaroon"
+            "#,
+        )?;
+        assert_eq!(config.network.as_deref(), Some("signet"));
+        assert_eq!(
+            config.backend.esplora.as_deref(),
+            Some("https://mutinynet.com/api")
+        );
+        assert_eq!(config.fees.conf_target, Some(3));
+        assert_eq!(config.fees.fee_rate_cap, Some(100.0));
+        assert_eq!(config.hardware_wallet.device.as_deref(), Some("jade"));
+        assert_eq!(
+            config.lightning.lnd_url.as_deref(),
+            Some("https://127.0.0.1:8080")
+        );
+
+        assert!(Config::parse("netwrok = \"signet\"").is_err());
+        assert!(
+            Config::parse("[backend]\nelectrum = \"ssl://a:50002\"\nesplora = \"https://b\"")
+                .is_err()
+        );
+        assert!(Config::parse("[backend]\nbitcoin_dir = \"/data\"\nrpc_user = \"user\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles_replace_network_and_backend() -> Result<()> {
+        let config = r#"
+            network = "mainnet"
+            [backend]
+            rpc_url = "http://10.0.0.2:8332"
+            bitcoin_dir = "/mnt/bitcoin"
+
+            [profiles.signet]
+            network = "signet"
+            [profiles.signet.backend]
+            esplora = "https://mutinynet.com/api"
+
+            [profiles.tor]
+            proxy = "socks5://127.0.0.1:9050"
+        "#;
+
+        let signet = Config::parse(config)?.with_profile("signet")?;
+        assert_eq!(signet.network.as_deref(), Some("signet"));
+        assert_eq!(
+            signet.backend,
+            BackendConfig {
+                esplora: Some("https://mutinynet.com/api".to_string()),
+                ..Default::default()
+            }
+        );
+
+        // A profile without a backend keeps the top-level one
+        let tor = Config::parse(config)?.with_profile("tor")?;
+        assert_eq!(tor.network.as_deref(), Some("mainnet"));
+        assert_eq!(tor.backend.bitcoin_dir.as_deref(), Some("/mnt/bitcoin"));
+        assert_eq!(tor.proxy.as_deref(), Some("socks5://127.0.0.1:9050"));
+
+        let unknown = Config::parse(config)?.with_profile("home-node");
+        assert!(
+            unknown
+                .err()
+                .is_some_and(|e| e.to_string().contains("available: signet, tor"))
+        );
+        assert!(
+            Config::parse("[profiles.bad.backend]\nelectrum = \"a\"\nesplora = \"b\"").is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_values_are_defaults_under_flags() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            network = "testnet"
+            proxy = "socks5://127.0.0.1:9050"
+            [backend]
+            electrum = "ssl://electrum.example:50002"
+            [fees]
+            fee_rate = "3"
+            "#,
+        )?;
+
+        let matches = parse_with(&config, &["cyberkrill", "onchain-list-utxos"]);
+        assert_eq!(value(&matches, "network"), Some("testnet"));
+        assert_eq!(
+            value(&matches, "electrum"),
+            Some("ssl://electrum.example:50002")
+        );
+        assert_eq!(value(&matches, "fee_rate"), Some("3"));
+        assert_eq!(
+            matches.get_one::<String>("proxy").map(String::as_str),
+            Some("socks5://127.0.0.1:9050")
+        );
+
+        // Flags on the command line win, and an explicit backend drops the whole
+        // configured backend instead of mixing with it
+        let matches = parse_with(
+            &config,
+            &[
+                "cyberkrill",
+                "onchain-list-utxos",
+                "--network",
+                "regtest",
+                "--esplora",
+                "http://localhost:3002",
+                "--fee-rate",
+                "10",
+            ],
+        );
+        assert_eq!(value(&matches, "network"), Some("regtest"));
+        assert_eq!(value(&matches, "electrum"), None);
+        assert_eq!(value(&matches, "esplora"), Some("http://localhost:3002"));
+        assert_eq!(value(&matches, "fee_rate"), Some("10"));
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//! Exit codes and stderr output of a failed command (`--error-format`)
+//!
+//! The exit code follows the class of the error (see
+//! [`cyberkrill_core::CoreError`]), so scripts can branch on the failure mode.
+//! The codes are stable; 2 is left to clap's usage errors.
+
+use anyhow::Result;
+use cyberkrill_core::CoreError;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::OnceLock;
+
+static ACTIVE_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `Error: ...` with the cause chain and a hint
+    #[default]
+    Text,
+    /// One JSON object on a single line
+    Json,
+}
+
+impl ErrorFormat {
+    /// Set once at startup, before any error can reach [`report`]
+    pub fn install(format: ErrorFormat) -> Result<()> {
+        ACTIVE_FORMAT
+            .set(format)
+            .map_err(|_| anyhow::anyhow!("An error format is already installed"))
+    }
+
+    pub fn active() -> ErrorFormat {
+        ACTIVE_FORMAT.get().copied().unwrap_or_default()
+    }
+}
+
+/// Any error not covered by a more specific code
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_PARSE: u8 = 3;
+pub const EXIT_INVALID_DESCRIPTOR: u8 = 4;
+pub const EXIT_INSUFFICIENT_FUNDS: u8 = 5;
+pub const EXIT_RPC: u8 = 6;
+pub const EXIT_BACKEND_UNAVAILABLE: u8 = 7;
+pub const EXIT_DEVICE: u8 = 8;
+
+pub fn exit_code(error: &CoreError) -> u8 {
+    match error {
+        CoreError::Parse(_) => EXIT_PARSE,
+        CoreError::InvalidDescriptor(_) => EXIT_INVALID_DESCRIPTOR,
+        CoreError::InsufficientFunds(_) => EXIT_INSUFFICIENT_FUNDS,
+        CoreError::Rpc(_) => EXIT_RPC,
+        CoreError::BackendUnavailable(_) => EXIT_BACKEND_UNAVAILABLE,
+        CoreError::Device(_) => EXIT_DEVICE,
+        CoreError::Other(_) => EXIT_FAILURE,
+    }
+}
+
+/// What to try next for an error of this class
+pub fn hint(error: &CoreError) -> Option<&'static str> {
+    match error {
+        CoreError::Parse(_) => Some(
+            "Check the input format: amounts like 0.001btc or 1000sats, addresses for the selected network, base64 or hex PSBTs",
+        ),
+        CoreError::InvalidDescriptor(_) => {
+            Some("Check the descriptor's script type, keys, derivation paths and checksum")
+        }
+        CoreError::InsufficientFunds(_) => {
+            Some("Select more UTXOs, or lower the amounts or the fee rate")
+        }
+        CoreError::Rpc(_) => Some(
+            "The node rejected the call; check the RPC error code, the wallet loaded in the node and the arguments",
+        ),
+        CoreError::BackendUnavailable(_) => Some(
+            "The backend is unreachable, overloaded or still starting; retry later, or raise --retries or --call-deadline",
+        ),
+        CoreError::Device(_) => {
+            Some("Check that the device is connected, unlocked and not busy with another request")
+        }
+        CoreError::Other(_) => None,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorReport {
+    /// Error class, as in [`CoreError::code`]
+    code: &'static str,
+    exit_code: u8,
+    /// The error with its causes, separated by `: `
+    message: String,
+    hint: Option<&'static str>,
+    retryable: bool,
+    rpc_code: Option<i64>,
+}
+
+/// Print `error` to stderr in the active error format and return the exit code
+/// of its class
+pub fn report(error: anyhow::Error) -> std::process::ExitCode {
+    let error = CoreError::from(error);
+    let _ = write_report(std::io::stderr().lock(), &error, ErrorFormat::active());
+    std::process::ExitCode::from(exit_code(&error))
+}
+
+fn write_report<W: Write>(mut writer: W, error: &CoreError, format: ErrorFormat) -> Result<()> {
+    match format {
+        ErrorFormat::Text => {
+            // Same layout as an error returned from main, plus the hint
+            writeln!(writer, "Error: {inner:?}", inner = error.inner())?;
+            if let Some(hint) = hint(error) {
+                writeln!(writer, "\nHint: {hint}")?;
+            }
+        }
+        ErrorFormat::Json => {
+            let report = ErrorReport {
+                code: error.code(),
+                exit_code: exit_code(error),
+                message: format!("{error:#}"),
+                hint: hint(error),
+                retryable: error.is_retryable(),
+                rpc_code: error.rpc_code(),
+            };
+            serde_json::to_writer(&mut writer, &serde_json::json!({ "error": report }))?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn rendered(error: anyhow::Error, format: ErrorFormat) -> Result<String> {
+        let mut buffer = Vec::new();
+        write_report(&mut buffer, &CoreError::from(error), format)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let codes = [
+            exit_code(&CoreError::Other(anyhow::anyhow!("x"))),
+            exit_code(&CoreError::Parse(anyhow::anyhow!("x"))),
+            exit_code(&CoreError::InvalidDescriptor(anyhow::anyhow!("x"))),
+            exit_code(&CoreError::InsufficientFunds(anyhow::anyhow!("x"))),
+            exit_code(&CoreError::Rpc(anyhow::anyhow!("x"))),
+            exit_code(&CoreError::BackendUnavailable(anyhow::anyhow!("x"))),
+            exit_code(&CoreError::Device(anyhow::anyhow!("x"))),
+        ];
+        assert_eq!(codes, [1, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_json_error_report() -> Result<()> {
+        let error = Err::<(), _>(cyberkrill_core::InsufficientFunds(
+            "inputs=1000 sats, fee=1500 sats".to_string(),
+        ))
+        .context("Failed to create the PSBT")
+        .unwrap_err();
+        let line = rendered(error, ErrorFormat::Json)?;
+        assert!(line.ends_with('\n') && line.matches('\n').count() == 1);
+        let report: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "error": {
+                    "code": "insufficient_funds",
+                    "exit_code": 5,
+                    "message": "Failed to create the PSBT: Insufficient funds: inputs=1000 sats, fee=1500 sats",
+                    "hint": "Select more UTXOs, or lower the amounts or the fee rate",
+                    "retryable": false,
+                    "rpc_code": null,
+                }
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_error_report() -> Result<()> {
+        let error = "x".parse::<u32>().context("Invalid vout").unwrap_err();
+        let text = rendered(error, ErrorFormat::Text)?;
+        assert!(text.starts_with("Error: Invalid vout\n\nCaused by:\n"));
+        assert!(text.contains("\nHint: Check the input format"));
+
+        let text = rendered(anyhow::anyhow!("Something failed"), ErrorFormat::Text)?;
+        assert!(text.starts_with("Error: Something failed\n"));
+        assert!(!text.contains("Hint:"));
+        Ok(())
+    }
+}
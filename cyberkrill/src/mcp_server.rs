@@ -1,4 +1,5 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use bitcoin::hashes::{Hash, sha256};
 use rmcp::{
     ErrorData as McpError, RoleServer,
     handler::server::ServerHandler,
@@ -11,12 +12,15 @@ use rmcp::{
     tool,
     transport::stdio,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
-use tracing::info;
+use tracing::{info, warn};
 
 /// Configuration for the MCP server
 #[derive(Debug, Clone)]
@@ -26,6 +30,187 @@ pub struct McpServerConfig {
     pub host: String,
     #[allow(dead_code)] // Will be used when SSE transport is implemented
     pub port: u16,
+    /// Hide and refuse the tools that build transactions
+    pub read_only: bool,
+    /// Bearer token clients of network transports must present
+    pub auth_token: Option<String>,
+    /// Append-only JSONL file every tool invocation is recorded to
+    pub audit_log: Option<PathBuf>,
+    /// Per-tool call limits; a `*` entry applies to tools without their own
+    pub rate_limits: Vec<ToolRateLimit>,
+    /// Backend settings used when a tool call doesn't pass its own
+    pub backend_defaults: BackendDefaults,
+    /// Proxy, retries, certificate pin, trace and caches of the backend connections
+    pub client_options: cyberkrill_core::ClientOptions,
+}
+
+const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8332";
+
+/// Server-side defaults for the backend parameters of the Bitcoin tools
+#[derive(Debug, Clone, Default)]
+pub struct BackendDefaults {
+    pub network: Option<String>,
+    pub backend: Option<String>,
+    pub backend_url: Option<String>,
+    pub bitcoin_dir: Option<String>,
+    /// Bitcoin Core RPC URL for the tools that talk to bitcoind directly
+    pub rpc_url: Option<String>,
+    /// RPC credentials for nodes whose cookie file isn't readable, e.g. remote ones
+    pub rpc_user: Option<String>,
+    pub rpc_password: Option<String>,
+}
+
+impl BackendDefaults {
+    /// Fill the backend parameters missing from a tool call's arguments
+    fn apply(&self, args: &mut serde_json::Map<String, serde_json::Value>) {
+        let defaults = [
+            ("network", &self.network),
+            ("backend", &self.backend),
+            ("backend_url", &self.backend_url),
+            ("bitcoin_dir", &self.bitcoin_dir),
+        ];
+        for (key, value) in defaults {
+            if let Some(value) = value
+                && args.get(key).is_none_or(serde_json::Value::is_null)
+            {
+                args.insert(key.to_string(), value.clone().into());
+            }
+        }
+    }
+
+    fn rpc_url(&self) -> String {
+        self.rpc_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_RPC_URL.to_string())
+    }
+}
+
+/// At most `max_calls` calls of `tool` within any `period`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolRateLimit {
+    pub tool: String,
+    pub max_calls: u32,
+    pub period: Duration,
+}
+
+impl FromStr for ToolRateLimit {
+    type Err = anyhow::Error;
+
+    /// Parse `TOOL=N/PERIOD`, with PERIOD one of `sec`, `min`, `hour` or `day`
+    fn from_str(s: &str) -> Result<Self> {
+        let (tool, limit) = s
+            .split_once('=')
+            .with_context(|| format!("Invalid rate limit {s}: expected TOOL=N/PERIOD"))?;
+        let (max_calls, period) = limit
+            .split_once('/')
+            .with_context(|| format!("Invalid rate limit {s}: expected TOOL=N/PERIOD"))?;
+        let max_calls = max_calls
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid call count in rate limit {s}"))?;
+        let period = match period.trim() {
+            "s" | "sec" | "second" => Duration::from_secs(1),
+            "m" | "min" | "minute" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            "d" | "day" => Duration::from_secs(86400),
+            other => {
+                bail!("Invalid period {other} in rate limit {s}: expected sec, min, hour or day")
+            }
+        };
+        let tool = tool.trim();
+        if tool.is_empty() {
+            bail!("Invalid rate limit {s}: missing tool name");
+        }
+        Ok(Self {
+            tool: tool.to_string(),
+            max_calls,
+            period,
+        })
+    }
+}
+
+/// Outcome of a tool invocation as recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+    Success,
+    /// The tool ran and reported an error
+    Error,
+    /// The call was refused before running the tool
+    Rejected,
+    RateLimited,
+}
+
+/// One line of the audit log
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    tool: &'a str,
+    /// SHA-256 of the JSON arguments, so calls can be correlated without
+    /// storing addresses or descriptors
+    args_sha256: String,
+    status: AuditStatus,
+    duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn hash_arguments(arguments: Option<&serde_json::Map<String, serde_json::Value>>) -> String {
+    let json = arguments
+        .map(|arguments| serde_json::to_string(arguments).unwrap_or_default())
+        .unwrap_or_default();
+    sha256::Hash::hash(json.as_bytes()).to_string()
+}
+
+/// Tools exposed in read-only mode: they only decode their input or list
+/// the wallet's coins. Anything else, including tools added later, is hidden.
+const READ_ONLY_TOOLS: &[&str] = &[
+    "decode_invoice",
+    "decode_lnurl",
+    "verify_payment",
+    "decode_fedimint_invite",
+    "list_utxos",
+    "decode_psbt",
+];
+
+impl McpServerConfig {
+    /// Whether the tool may be listed and called with this configuration
+    pub fn allows_tool(&self, name: &str) -> bool {
+        !self.read_only || READ_ONLY_TOOLS.contains(&name)
+    }
+
+    /// Check the `Authorization` header of a network transport request. Without
+    /// a configured token every request is accepted.
+    pub fn authorize(&self, authorization: Option<&str>) -> bool {
+        let Some(expected) = &self.auth_token else {
+            return true;
+        };
+        let Some(token) = authorization.and_then(|header| header.strip_prefix("Bearer ")) else {
+            return false;
+        };
+        constant_time_eq(token.trim().as_bytes(), expected.as_bytes())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Failed tool result. The text keeps the `Error: ...` message and the
+/// structured content carries the error class, so clients can tell a backend
+/// that may come back from a call that will never succeed.
+fn tool_error(error: impl Into<anyhow::Error>) -> CallToolResult {
+    let error = cyberkrill_core::CoreError::from(error.into());
+    let mut result = CallToolResult::error(vec![Content::text(format!("Error: {error}"))]);
+    result.structured_content = Some(serde_json::json!({
+        "error": {
+            "kind": error.code(),
+            "message": error.to_string(),
+            "retryable": error.is_retryable(),
+            "rpc_code": error.rpc_code(),
+        }
+    }));
+    result
 }
 
 #[derive(Debug, Clone)]
@@ -37,9 +222,50 @@ pub enum Transport {
 /// Bitcoin backend configuration
 #[derive(Debug, Clone)]
 enum BitcoinBackend {
-    Bitcoind { dir: String },
-    Electrum { url: String },
-    Esplora { url: String },
+    Bitcoind {
+        dir: String,
+        rpc_url: String,
+        rpc_user: Option<String>,
+        rpc_password: Option<String>,
+    },
+    Electrum {
+        url: String,
+    },
+    Esplora {
+        url: String,
+    },
+}
+
+impl BitcoinBackend {
+    fn connect(
+        self,
+        client_options: &cyberkrill_core::ClientOptions,
+    ) -> anyhow::Result<Box<dyn cyberkrill_core::BlockchainBackend>> {
+        let scan = cyberkrill_core::ScanOptions {
+            client: client_options.clone(),
+            ..Default::default()
+        };
+        Ok(match self {
+            BitcoinBackend::Bitcoind {
+                dir,
+                rpc_url,
+                rpc_user,
+                rpc_password,
+            } => Box::new(cyberkrill_core::BitcoindBackend::connect(
+                rpc_url,
+                Some(Path::new(&dir)),
+                rpc_user,
+                rpc_password,
+                client_options,
+            )?),
+            BitcoinBackend::Electrum { url } => {
+                Box::new(cyberkrill_core::ElectrumBackend::new(url.parse()?, scan))
+            }
+            BitcoinBackend::Esplora { url } => {
+                Box::new(cyberkrill_core::EsploraBackend::new(url, scan))
+            }
+        })
+    }
 }
 
 impl Default for McpServerConfig {
@@ -48,6 +274,12 @@ impl Default for McpServerConfig {
             transport: Transport::Stdio,
             host: "127.0.0.1".to_string(),
             port: 8080,
+            read_only: false,
+            auth_token: None,
+            audit_log: None,
+            rate_limits: Vec::new(),
+            backend_defaults: BackendDefaults::default(),
+            client_options: cyberkrill_core::ClientOptions::default(),
         }
     }
 }
@@ -56,13 +288,45 @@ impl Default for McpServerConfig {
 #[derive(Clone)]
 pub struct CyberkrillMcpServer {
     config: McpServerConfig,
-    #[allow(dead_code)] // Reserved for future shared state management
     state: Arc<Mutex<ServerState>>,
 }
 
 #[derive(Default)]
 struct ServerState {
-    // Add any shared state here if needed
+    /// Recent call times per tool, for rate limiting
+    calls: HashMap<String, VecDeque<Instant>>,
+}
+
+impl ServerState {
+    /// Record a call of `tool` at `now` unless it exceeds its limit, in which
+    /// case return how long until the next call is allowed
+    fn check_rate_limit(
+        &mut self,
+        tool: &str,
+        limits: &[ToolRateLimit],
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let Some(limit) = limits
+            .iter()
+            .find(|limit| limit.tool == tool)
+            .or_else(|| limits.iter().find(|limit| limit.tool == "*"))
+        else {
+            return Ok(());
+        };
+        let calls = self.calls.entry(tool.to_string()).or_default();
+        while calls
+            .front()
+            .is_some_and(|&call| now.duration_since(call) >= limit.period)
+        {
+            calls.pop_front();
+        }
+        if calls.len() >= limit.max_calls as usize {
+            let oldest = calls.front().copied().unwrap_or(now);
+            return Err(limit.period.saturating_sub(now.duration_since(oldest)));
+        }
+        calls.push_back(now);
+        Ok(())
+    }
 }
 
 // Lightning Network tool requests
@@ -78,6 +342,14 @@ pub struct DecodeLnurlRequest {
     pub lnurl: String,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct VerifyPaymentRequest {
+    #[schemars(description = "The BOLT11 invoice that was paid")]
+    pub invoice: String,
+    #[schemars(description = "Payment preimage (32 bytes, hex)")]
+    pub preimage: String,
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct GenerateInvoiceRequest {
     #[schemars(description = "Lightning address (e.g., user@domain.com)")]
@@ -120,7 +392,7 @@ pub struct ListUtxosRequest {
     pub descriptor: Option<String>,
     #[schemars(description = "List of Bitcoin addresses")]
     pub addresses: Option<Vec<String>>,
-    #[schemars(description = "Bitcoin network (mainnet, testnet, signet, regtest)")]
+    #[schemars(description = "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)")]
     pub network: Option<String>,
     #[schemars(description = "Backend to use (bitcoind, electrum, esplora)")]
     pub backend: Option<String>,
@@ -146,7 +418,7 @@ pub struct CreatePsbtRequest {
     pub fee_rate: Option<f64>,
     #[schemars(description = "Output descriptor for BDK backends")]
     pub descriptor: Option<String>,
-    #[schemars(description = "Bitcoin network (mainnet, testnet, signet, regtest)")]
+    #[schemars(description = "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)")]
     pub network: Option<String>,
     #[schemars(description = "Backend to use (bitcoind, electrum, esplora)")]
     pub backend: Option<String>,
@@ -170,7 +442,7 @@ pub struct CreateFundedPsbtRequest {
     pub estimate_mode: Option<String>,
     #[schemars(description = "Output descriptor for BDK backends")]
     pub descriptor: Option<String>,
-    #[schemars(description = "Bitcoin network (mainnet, testnet, signet, regtest)")]
+    #[schemars(description = "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)")]
     pub network: Option<String>,
     #[schemars(description = "Backend to use (bitcoind, electrum, esplora)")]
     pub backend: Option<String>,
@@ -184,7 +456,9 @@ pub struct CreateFundedPsbtRequest {
 pub struct MoveUtxosRequest {
     #[schemars(description = "Input specifications (txid:vout format or descriptors)")]
     pub inputs: Vec<String>,
-    #[schemars(description = "Destination Bitcoin address")]
+    #[schemars(
+        description = "Destination address, or a split like 'addr1:60%,addr2:40%' or 'addr1:0.1btc,addr2' (the bare address receives the remainder)"
+    )]
     pub destination: String,
     #[schemars(description = "Fee rate in sat/vB")]
     pub fee_rate: Option<f64>,
@@ -192,9 +466,13 @@ pub struct MoveUtxosRequest {
     pub fee: Option<u64>,
     #[schemars(description = "Maximum amount to move (e.g., '0.5btc' or '50000000sats')")]
     pub max_amount: Option<String>,
+    #[schemars(
+        description = "Which UTXOs to consolidate first: oldest, smallest, largest (default) or 'dust-below <amount>'"
+    )]
+    pub select: Option<String>,
     #[schemars(description = "Output descriptor for BDK backends")]
     pub descriptor: Option<String>,
-    #[schemars(description = "Bitcoin network (mainnet, testnet, signet, regtest)")]
+    #[schemars(description = "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)")]
     pub network: Option<String>,
     #[schemars(description = "Backend to use (bitcoind, electrum, esplora)")]
     pub backend: Option<String>,
@@ -216,8 +494,8 @@ pub struct DcaReportRequest {
     pub backend_url: Option<String>,
     #[schemars(description = "Bitcoin data directory (for bitcoind)")]
     pub bitcoin_dir: Option<String>,
-    #[schemars(description = "Cache directory for price data")]
-    pub cache_dir: Option<String>,
+    #[schemars(description = "Historical price source (auto, coingecko, kraken, mempool)")]
+    pub price_source: Option<String>,
 }
 
 impl CyberkrillMcpServer {
@@ -243,6 +521,10 @@ impl CyberkrillMcpServer {
             info!("Starting cyberkrill MCP server");
         }
 
+        if self.config.read_only && log_level != "error" {
+            info!("Read-only mode: spending tools are disabled");
+        }
+
         match self.config.transport {
             Transport::Stdio => {
                 if log_level != "error" {
@@ -252,6 +534,11 @@ impl CyberkrillMcpServer {
                 service.waiting().await?;
             }
             Transport::Sse => {
+                if self.config.auth_token.is_none() {
+                    anyhow::bail!(
+                        "Network transports require a bearer token (--auth-token or CYBERKRILL_MCP_TOKEN)"
+                    );
+                }
                 // SSE transport would require additional implementation
                 // For now, we'll focus on stdio transport
                 anyhow::bail!("SSE transport not yet implemented");
@@ -266,11 +553,19 @@ impl CyberkrillMcpServer {
 impl CyberkrillMcpServer {
     // Helper method for backend selection with fallback
     fn select_backend(
+        &self,
         backend: Option<&str>,
         backend_url: Option<String>,
         bitcoin_dir: Option<String>,
         network: cyberkrill_core::Network,
     ) -> Result<BitcoinBackend, String> {
+        let defaults = &self.config.backend_defaults;
+        let bitcoind = |dir| BitcoinBackend::Bitcoind {
+            dir,
+            rpc_url: defaults.rpc_url(),
+            rpc_user: defaults.rpc_user.clone(),
+            rpc_password: defaults.rpc_password.clone(),
+        };
         match backend {
             Some("electrum") => backend_url
                 .map(|url| BitcoinBackend::Electrum { url })
@@ -278,9 +573,9 @@ impl CyberkrillMcpServer {
             Some("esplora") => backend_url
                 .map(|url| BitcoinBackend::Esplora { url })
                 .ok_or_else(|| "backend_url required for esplora".to_string()),
-            Some("bitcoind") => Ok(BitcoinBackend::Bitcoind {
-                dir: bitcoin_dir.unwrap_or_else(|| "~/.bitcoin".to_string()),
-            }),
+            Some("bitcoind") => Ok(bitcoind(
+                bitcoin_dir.unwrap_or_else(|| "~/.bitcoin".to_string()),
+            )),
             None => {
                 // No backend specified - try bitcoind first, then fallback
                 let dir = bitcoin_dir.unwrap_or_else(|| {
@@ -292,10 +587,11 @@ impl CyberkrillMcpServer {
                     }
                 });
 
-                // Quick check if bitcoind is available (check if cookie file exists)
+                // Quick check if bitcoind is available (cookie file exists or
+                // credentials are configured)
                 let cookie_path = Path::new(&dir).join(".cookie");
-                if cookie_path.exists() {
-                    Ok(BitcoinBackend::Bitcoind { dir })
+                if cookie_path.exists() || defaults.rpc_user.is_some() {
+                    Ok(bitcoind(dir))
                 } else {
                     // Fallback to public service based on network
                     match network {
@@ -311,11 +607,11 @@ impl CyberkrillMcpServer {
                         cyberkrill_core::Network::Regtest => {
                             // For regtest, still try bitcoind even if cookie doesn't exist
                             // as it might be configured differently
-                            Ok(BitcoinBackend::Bitcoind { dir })
+                            Ok(bitcoind(dir))
                         }
                         _ => {
                             // For any future/unknown networks, try local bitcoind
-                            Ok(BitcoinBackend::Bitcoind { dir })
+                            Ok(bitcoind(dir))
                         }
                     }
                 }
@@ -334,7 +630,7 @@ impl CyberkrillMcpServer {
             Ok(result) => CallToolResult::success(vec![Content::text(
                 serde_json::to_string_pretty(&result).unwrap_or_else(|e| e.to_string()),
             )]),
-            Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+            Err(e) => tool_error(e),
         }
     }
 
@@ -347,7 +643,20 @@ impl CyberkrillMcpServer {
             Ok(result) => CallToolResult::success(vec![Content::text(
                 serde_json::to_string_pretty(&result).unwrap_or_else(|e| e.to_string()),
             )]),
-            Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+            Err(e) => tool_error(e),
+        }
+    }
+
+    #[tool(description = "Check a payment preimage against a BOLT11 invoice's payment hash")]
+    async fn verify_payment(
+        &self,
+        VerifyPaymentRequest { invoice, preimage }: VerifyPaymentRequest,
+    ) -> CallToolResult {
+        match cyberkrill_core::verify_payment(&invoice, &preimage) {
+            Ok(result) => CallToolResult::success(vec![Content::text(
+                serde_json::to_string_pretty(&result).unwrap_or_else(|e| e.to_string()),
+            )]),
+            Err(e) => tool_error(e),
         }
     }
 
@@ -363,13 +672,18 @@ impl CyberkrillMcpServer {
         // Convert amount_msats to AmountInput
         let amount = cyberkrill_core::AmountInput::from_millisats(amount_msats);
 
-        match cyberkrill_core::generate_invoice_from_address(&address, &amount, comment.as_deref())
-            .await
+        match cyberkrill_core::generate_invoice_from_address(
+            &address,
+            &amount,
+            comment.as_deref(),
+            &self.config.client_options,
+        )
+        .await
         {
             Ok(result) => CallToolResult::success(vec![Content::text(
                 serde_json::to_string_pretty(&result).unwrap_or_else(|e| e.to_string()),
             )]),
-            Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+            Err(e) => tool_error(e),
         }
     }
 
@@ -383,7 +697,7 @@ impl CyberkrillMcpServer {
             Ok(result) => CallToolResult::success(vec![Content::text(
                 serde_json::to_string_pretty(&result).unwrap_or_else(|e| e.to_string()),
             )]),
-            Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+            Err(e) => tool_error(e),
         }
     }
 
@@ -420,7 +734,7 @@ impl CyberkrillMcpServer {
             Ok(result) => CallToolResult::success(vec![Content::text(
                 serde_json::json!({ "invite_code": result }).to_string(),
             )]),
-            Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+            Err(e) => tool_error(e),
         }
     }
 
@@ -438,21 +752,14 @@ impl CyberkrillMcpServer {
         }: ListUtxosRequest,
     ) -> CallToolResult {
         let network_str = network.as_deref().unwrap_or("mainnet");
-        let network = match network_str.to_lowercase().as_str() {
-            "mainnet" | "bitcoin" => cyberkrill_core::Network::Bitcoin,
-            "testnet" => cyberkrill_core::Network::Testnet,
-            "signet" => cyberkrill_core::Network::Signet,
-            "regtest" => cyberkrill_core::Network::Regtest,
-            _ => {
-                return CallToolResult::error(vec![Content::text(format!(
-                    "Invalid network: {network_str}"
-                ))]);
-            }
+        let network = match cyberkrill_core::parse_network(network_str) {
+            Ok(network) => network,
+            Err(e) => return CallToolResult::error(vec![Content::text(e.to_string())]),
         };
 
         let result = if let Some(desc) = descriptor {
             // Select backend with fallback
-            let backend_config = match Self::select_backend(
+            let backend_config = match self.select_backend(
                 backend.as_deref(),
                 backend_url,
                 bitcoin_dir.clone(),
@@ -462,52 +769,24 @@ impl CyberkrillMcpServer {
                 Err(e) => return CallToolResult::error(vec![Content::text(e)]),
             };
 
-            // Execute with selected backend
-            match backend_config {
-                BitcoinBackend::Bitcoind { dir } => {
-                    let path = Path::new(&dir);
-                    match cyberkrill_core::scan_and_list_utxos_bitcoind(&desc, network, path).await
-                    {
-                        Ok(r) => r,
-                        Err(e) => {
-                            return CallToolResult::error(vec![Content::text(format!(
-                                "Error: {e}"
-                            ))]);
-                        }
-                    }
-                }
-                BitcoinBackend::Electrum { url } => {
-                    match cyberkrill_core::scan_and_list_utxos_electrum(&desc, network, &url, 200)
-                        .await
-                    {
-                        Ok(r) => r,
-                        Err(e) => {
-                            return CallToolResult::error(vec![Content::text(format!(
-                                "Error: {e}"
-                            ))]);
-                        }
-                    }
-                }
-                BitcoinBackend::Esplora { url } => {
-                    match cyberkrill_core::scan_and_list_utxos_esplora(&desc, network, &url, 200)
-                        .await
-                    {
-                        Ok(r) => r,
-                        Err(e) => {
-                            return CallToolResult::error(vec![Content::text(format!(
-                                "Error: {e}"
-                            ))]);
-                        }
-                    }
+            let listed = match backend_config.connect(&self.config.client_options) {
+                Ok(backend) => backend.list_utxos(&desc, network).await,
+                Err(e) => Err(e.into()),
+            };
+            match listed {
+                Ok(r) => r,
+                Err(e) => {
+                    return tool_error(e);
                 }
             }
         } else if let Some(addrs) = addresses {
             let bitcoin_path = bitcoin_dir.map(|d| std::path::Path::new(&d).to_path_buf());
             let client = match cyberkrill_core::BitcoinRpcClient::new_auto(
-                "http://127.0.0.1:8332".to_string(),
+                self.config.backend_defaults.rpc_url(),
                 bitcoin_path.as_deref(),
-                None,
-                None,
+                self.config.backend_defaults.rpc_user.clone(),
+                self.config.backend_defaults.rpc_password.clone(),
+                &self.config.client_options,
             ) {
                 Ok(c) => c,
                 Err(e) => {
@@ -519,7 +798,7 @@ impl CyberkrillMcpServer {
 
             let utxo_response = match client.list_utxos_for_addresses(addrs).await {
                 Ok(r) => r,
-                Err(e) => return CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+                Err(e) => return tool_error(e),
             };
 
             // Convert UtxoListResponse to Vec<BdkUtxo> for compatibility
@@ -609,17 +888,18 @@ impl CyberkrillMcpServer {
         }: CreatePsbtRequest,
     ) -> CallToolResult {
         let network_str = network.as_deref().unwrap_or("mainnet");
-        let network = match network_str.to_lowercase().as_str() {
-            "mainnet" | "bitcoin" => cyberkrill_core::Network::Bitcoin,
-            "testnet" => cyberkrill_core::Network::Testnet,
-            "signet" => cyberkrill_core::Network::Signet,
-            "regtest" => cyberkrill_core::Network::Regtest,
-            _ => {
-                return CallToolResult::error(vec![Content::text(format!(
-                    "Invalid network: {network_str}"
-                ))]);
-            }
+        let network = match cyberkrill_core::parse_network(network_str) {
+            Ok(network) => network,
+            Err(e) => return CallToolResult::error(vec![Content::text(e.to_string())]),
         };
+        let addresses = outputs
+            .split(',')
+            .filter_map(|output| output.trim().split(':').next());
+        if let Err(e) =
+            cyberkrill_core::check_transaction_network(addresses, descriptor.as_deref(), network)
+        {
+            return tool_error(e);
+        }
 
         let fee_rate_input = if let Some(rate) = fee_rate {
             match cyberkrill_core::AmountInput::from_btc(rate) {
@@ -636,7 +916,7 @@ impl CyberkrillMcpServer {
 
         if let Some(desc) = descriptor {
             // BDK path - select backend with fallback
-            let backend_config = match Self::select_backend(
+            let backend_config = match self.select_backend(
                 backend.as_deref(),
                 backend_url.clone(),
                 bitcoin_dir.clone(),
@@ -646,11 +926,11 @@ impl CyberkrillMcpServer {
                 Err(e) => return CallToolResult::error(vec![Content::text(e)]),
             };
 
-            // Convert backend config to BDK URL format
-            let backend_url_str = match backend_config {
-                BitcoinBackend::Bitcoind { dir } => format!("bitcoind://{dir}"),
-                BitcoinBackend::Electrum { url } => format!("electrum://{url}"),
-                BitcoinBackend::Esplora { url } => format!("esplora://{url}"),
+            let backend = match backend_config.connect(&self.config.client_options) {
+                Ok(b) => b,
+                Err(e) => {
+                    return tool_error(e);
+                }
             };
 
             // Parse outputs into proper format for BDK
@@ -685,23 +965,25 @@ impl CyberkrillMcpServer {
                 fee_rate_input.map(|r| r.as_sat() as f64 / 100.0),
                 &desc,
                 network,
-                &backend_url_str,
+                backend.as_ref(),
+                &cyberkrill_core::PsbtOptions::default(),
             )
             .await
             {
                 Ok(r) => CallToolResult::success(vec![Content::text(
                     serde_json::to_string_pretty(&r).unwrap_or_else(|e| e.to_string()),
                 )]),
-                Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+                Err(e) => tool_error(e),
             }
         } else {
             // Bitcoin Core RPC path
             let bitcoin_path = bitcoin_dir.map(|d| std::path::Path::new(&d).to_path_buf());
             let client = match cyberkrill_core::BitcoinRpcClient::new_auto(
-                "http://127.0.0.1:8332".to_string(),
+                self.config.backend_defaults.rpc_url(),
                 bitcoin_path.as_deref(),
-                None,
-                None,
+                self.config.backend_defaults.rpc_user.clone(),
+                self.config.backend_defaults.rpc_password.clone(),
+                &self.config.client_options,
             ) {
                 Ok(c) => c,
                 Err(e) => {
@@ -711,11 +993,19 @@ impl CyberkrillMcpServer {
                 }
             };
 
-            match client.create_psbt(&inputs, &outputs, fee_rate_input).await {
+            match client
+                .create_psbt(
+                    &inputs,
+                    &outputs,
+                    fee_rate_input,
+                    &cyberkrill_core::PsbtOptions::default(),
+                )
+                .await
+            {
                 Ok(r) => CallToolResult::success(vec![Content::text(
                     serde_json::to_string_pretty(&r).unwrap_or_else(|e| e.to_string()),
                 )]),
-                Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+                Err(e) => tool_error(e),
             }
         }
     }
@@ -737,17 +1027,18 @@ impl CyberkrillMcpServer {
         }: CreateFundedPsbtRequest,
     ) -> CallToolResult {
         let network_str = network.as_deref().unwrap_or("mainnet");
-        let network = match network_str.to_lowercase().as_str() {
-            "mainnet" | "bitcoin" => cyberkrill_core::Network::Bitcoin,
-            "testnet" => cyberkrill_core::Network::Testnet,
-            "signet" => cyberkrill_core::Network::Signet,
-            "regtest" => cyberkrill_core::Network::Regtest,
-            _ => {
-                return CallToolResult::error(vec![Content::text(format!(
-                    "Invalid network: {network_str}"
-                ))]);
-            }
+        let network = match cyberkrill_core::parse_network(network_str) {
+            Ok(network) => network,
+            Err(e) => return CallToolResult::error(vec![Content::text(e.to_string())]),
         };
+        let addresses = outputs
+            .split(',')
+            .filter_map(|output| output.trim().split(':').next());
+        if let Err(e) =
+            cyberkrill_core::check_transaction_network(addresses, descriptor.as_deref(), network)
+        {
+            return tool_error(e);
+        }
 
         let fee_rate_input = if let Some(rate) = fee_rate {
             match cyberkrill_core::AmountInput::from_btc(rate) {
@@ -764,7 +1055,7 @@ impl CyberkrillMcpServer {
 
         if let Some(desc) = descriptor {
             // BDK path - select backend with fallback
-            let backend_config = match Self::select_backend(
+            let backend_config = match self.select_backend(
                 backend.as_deref(),
                 backend_url.clone(),
                 bitcoin_dir.clone(),
@@ -774,11 +1065,11 @@ impl CyberkrillMcpServer {
                 Err(e) => return CallToolResult::error(vec![Content::text(e)]),
             };
 
-            // Convert backend config to BDK URL format
-            let backend_url_str = match backend_config {
-                BitcoinBackend::Bitcoind { dir } => format!("bitcoind://{dir}"),
-                BitcoinBackend::Electrum { url } => format!("electrum://{url}"),
-                BitcoinBackend::Esplora { url } => format!("esplora://{url}"),
+            let backend = match backend_config.connect(&self.config.client_options) {
+                Ok(b) => b,
+                Err(e) => {
+                    return tool_error(e);
+                }
             };
 
             // Parse outputs into proper format for BDK
@@ -813,27 +1104,29 @@ impl CyberkrillMcpServer {
                 fee_rate_input.map(|r| r.as_sat() as f64 / 100.0),
                 &desc,
                 network,
-                &backend_url_str,
+                backend.as_ref(),
+                &cyberkrill_core::PsbtOptions::default(),
             )
             .await
             {
                 Ok(r) => CallToolResult::success(vec![Content::text(
                     serde_json::to_string_pretty(&r).unwrap_or_else(|e| e.to_string()),
                 )]),
-                Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+                Err(e) => tool_error(e),
             }
         } else {
             // Bitcoin Core RPC path
             let bitcoin_dir_path = bitcoin_dir.as_ref().map(std::path::Path::new);
 
-            // Create RPC client - using cookie auth with bitcoin_dir
-            let rpc_url = backend_url.unwrap_or_else(|| "http://127.0.0.1:8332".to_string());
+            // Create RPC client - cookie auth with bitcoin_dir, or the server's credentials
+            let rpc_url = backend_url.unwrap_or_else(|| self.config.backend_defaults.rpc_url());
 
             match cyberkrill_core::BitcoinRpcClient::new_auto(
                 rpc_url,
                 bitcoin_dir_path,
-                None, // rpc_user - not provided via MCP
-                None, // rpc_password - not provided via MCP
+                self.config.backend_defaults.rpc_user.clone(),
+                self.config.backend_defaults.rpc_password.clone(),
+                &self.config.client_options,
             ) {
                 Ok(client) => {
                     // Convert inputs from Option<Vec<String>> to Vec<String> for the RPC call
@@ -860,13 +1153,14 @@ impl CyberkrillMcpServer {
                             conf_target,
                             estimate_mode.as_deref(),
                             fee_rate_amt,
+                            &cyberkrill_core::PsbtOptions::default(),
                         )
                         .await
                     {
                         Ok(r) => CallToolResult::success(vec![Content::text(
                             serde_json::to_string_pretty(&r).unwrap_or_else(|e| e.to_string()),
                         )]),
-                        Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+                        Err(e) => tool_error(e),
                     }
                 }
                 Err(e) => CallToolResult::error(vec![Content::text(format!(
@@ -876,7 +1170,7 @@ impl CyberkrillMcpServer {
         }
     }
 
-    #[tool(description = "Consolidate/move UTXOs to a single destination")]
+    #[tool(description = "Consolidate/move UTXOs to one destination or split across several")]
     async fn move_utxos(
         &self,
         MoveUtxosRequest {
@@ -885,6 +1179,7 @@ impl CyberkrillMcpServer {
             fee_rate,
             fee,
             max_amount,
+            select,
             descriptor,
             network,
             backend,
@@ -893,17 +1188,21 @@ impl CyberkrillMcpServer {
         }: MoveUtxosRequest,
     ) -> CallToolResult {
         let network_str = network.as_deref().unwrap_or("mainnet");
-        let network = match network_str.to_lowercase().as_str() {
-            "mainnet" | "bitcoin" => cyberkrill_core::Network::Bitcoin,
-            "testnet" => cyberkrill_core::Network::Testnet,
-            "signet" => cyberkrill_core::Network::Signet,
-            "regtest" => cyberkrill_core::Network::Regtest,
-            _ => {
-                return CallToolResult::error(vec![Content::text(format!(
-                    "Invalid network: {network_str}"
-                ))]);
-            }
+        let network = match cyberkrill_core::parse_network(network_str) {
+            Ok(network) => network,
+            Err(e) => return CallToolResult::error(vec![Content::text(e.to_string())]),
+        };
+        let recipients = match destination.parse::<cyberkrill_core::DestinationSplit>() {
+            Ok(split) => split.addresses(),
+            Err(e) => return tool_error(e),
         };
+        if let Err(e) = cyberkrill_core::check_transaction_network(
+            recipients.iter().map(String::as_str),
+            descriptor.as_deref(),
+            network,
+        ) {
+            return tool_error(e);
+        }
 
         let fee_rate_input = if let Some(rate) = fee_rate {
             match cyberkrill_core::AmountInput::from_btc(rate) {
@@ -930,10 +1229,20 @@ impl CyberkrillMcpServer {
         } else {
             None
         };
+        let selection = match select
+            .as_deref()
+            .map(cyberkrill_core::UtxoSelection::from_str)
+            .transpose()
+        {
+            Ok(selection) => selection.unwrap_or_default(),
+            Err(e) => {
+                return CallToolResult::error(vec![Content::text(format!("Invalid select: {e}"))]);
+            }
+        };
 
         if let Some(desc) = descriptor {
             // BDK path - select backend with fallback
-            let backend_config = match Self::select_backend(
+            let backend_config = match self.select_backend(
                 backend.as_deref(),
                 backend_url.clone(),
                 bitcoin_dir.clone(),
@@ -943,11 +1252,11 @@ impl CyberkrillMcpServer {
                 Err(e) => return CallToolResult::error(vec![Content::text(e)]),
             };
 
-            // Convert backend config to BDK URL format
-            let backend_url_str = match backend_config {
-                BitcoinBackend::Bitcoind { dir } => format!("bitcoind://{dir}"),
-                BitcoinBackend::Electrum { url } => format!("electrum://{url}"),
-                BitcoinBackend::Esplora { url } => format!("esplora://{url}"),
+            let backend = match backend_config.connect(&self.config.client_options) {
+                Ok(b) => b,
+                Err(e) => {
+                    return tool_error(e);
+                }
             };
 
             match cyberkrill_core::move_utxos_bdk(
@@ -956,25 +1265,28 @@ impl CyberkrillMcpServer {
                 fee_rate_input.map(|r| r.as_sat() as f64 / 100.0),
                 fee_input.map(|f| f.as_sat()),
                 max_amount_input.map(|amt| bitcoin::Amount::from_sat(amt.as_sat())),
+                selection,
                 &desc,
                 network,
-                &backend_url_str,
+                backend.as_ref(),
+                &cyberkrill_core::PsbtOptions::default(),
             )
             .await
             {
                 Ok(r) => CallToolResult::success(vec![Content::text(
                     serde_json::to_string_pretty(&r).unwrap_or_else(|e| e.to_string()),
                 )]),
-                Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+                Err(e) => tool_error(e),
             }
         } else {
             // Bitcoin Core RPC path
             let bitcoin_path = bitcoin_dir.map(|d| std::path::Path::new(&d).to_path_buf());
             let client = match cyberkrill_core::BitcoinRpcClient::new_auto(
-                "http://127.0.0.1:8332".to_string(),
+                self.config.backend_defaults.rpc_url(),
                 bitcoin_path.as_deref(),
-                None,
-                None,
+                self.config.backend_defaults.rpc_user.clone(),
+                self.config.backend_defaults.rpc_password.clone(),
+                &self.config.client_options,
             ) {
                 Ok(c) => c,
                 Err(e) => {
@@ -991,13 +1303,15 @@ impl CyberkrillMcpServer {
                     fee_rate_input,
                     fee_input,
                     max_amount_input,
+                    selection,
+                    &cyberkrill_core::PsbtOptions::default(),
                 )
                 .await
             {
                 Ok(r) => CallToolResult::success(vec![Content::text(
                     serde_json::to_string_pretty(&r).unwrap_or_else(|e| e.to_string()),
                 )]),
-                Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {e}"))]),
+                Err(e) => tool_error(e),
             }
         }
     }
@@ -1011,10 +1325,18 @@ impl CyberkrillMcpServer {
             backend,
             backend_url,
             bitcoin_dir,
-            cache_dir,
+            price_source,
         }: DcaReportRequest,
     ) -> CallToolResult {
         let currency_str = currency.as_deref().unwrap_or("USD");
+        let price_source = match price_source
+            .as_deref()
+            .map(str::parse::<cyberkrill_core::PriceSource>)
+            .transpose()
+        {
+            Ok(source) => source.unwrap_or_default(),
+            Err(e) => return CallToolResult::error(vec![Content::text(e.to_string())]),
+        };
 
         // For DCA report, we need to determine network from descriptor
         // This is a simplified approach - in production you might want to parse the descriptor properly
@@ -1022,27 +1344,35 @@ impl CyberkrillMcpServer {
 
         // Select backend with fallback
         let backend_config =
-            match Self::select_backend(backend.as_deref(), backend_url, bitcoin_dir, network) {
+            match self.select_backend(backend.as_deref(), backend_url, bitcoin_dir, network) {
                 Ok(b) => b,
                 Err(e) => return CallToolResult::error(vec![Content::text(e)]),
             };
 
         // Convert our backend config to cyberkrill_core's Backend enum
         let backend_enum = match backend_config {
-            BitcoinBackend::Bitcoind { dir } => cyberkrill_core::Backend::BitcoinCore {
-                bitcoin_dir: PathBuf::from(dir),
+            BitcoinBackend::Bitcoind {
+                dir,
+                rpc_url,
+                rpc_user,
+                rpc_password,
+            } => cyberkrill_core::Backend::BitcoinCore {
+                rpc_url,
+                bitcoin_dir: Some(PathBuf::from(dir)),
+                rpc_user,
+                rpc_password,
             },
             BitcoinBackend::Electrum { url } => cyberkrill_core::Backend::Electrum { url },
             BitcoinBackend::Esplora { url } => cyberkrill_core::Backend::Esplora { url },
         };
 
-        let cache_path = cache_dir.map(|d| std::path::Path::new(&d).to_path_buf());
-
         match cyberkrill_core::generate_dca_report(
             &descriptor,
             backend_enum,
             currency_str,
-            cache_path.as_deref(),
+            price_source,
+            &[],
+            &self.config.client_options,
         )
         .await
         {
@@ -1084,7 +1414,31 @@ impl ServerHandler for CyberkrillMcpServer {
         _request: Option<PaginatedRequestParam>,
         _context: RequestContext<RoleServer>,
     ) -> Result<ListToolsResult, McpError> {
-        let tools = vec![
+        Ok(ListToolsResult {
+            tools: self.tools(),
+            next_cursor: None,
+            meta: None,
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let (_, result) = self.invoke(&request.name, request.arguments).await;
+        result
+    }
+}
+
+impl CyberkrillMcpServer {
+    pub fn config(&self) -> &McpServerConfig {
+        &self.config
+    }
+
+    /// Tools available with this configuration
+    pub fn tools(&self) -> Vec<Tool> {
+        let mut tools = vec![
             create_tool(
                 "decode_invoice",
                 "Decode a BOLT11 Lightning Network invoice",
@@ -1113,6 +1467,24 @@ impl ServerHandler for CyberkrillMcpServer {
                     "required": ["lnurl"]
                 }),
             ),
+            create_tool(
+                "verify_payment",
+                "Check a payment preimage against a BOLT11 invoice's payment hash",
+                serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "invoice": {
+                            "type": "string",
+                            "description": "The BOLT11 invoice that was paid"
+                        },
+                        "preimage": {
+                            "type": "string",
+                            "description": "Payment preimage (32 bytes, hex)"
+                        }
+                    },
+                    "required": ["invoice", "preimage"]
+                }),
+            ),
             create_tool(
                 "generate_invoice",
                 "Generate a Lightning invoice from a Lightning address",
@@ -1200,7 +1572,7 @@ impl ServerHandler for CyberkrillMcpServer {
                         },
                         "network": {
                             "type": "string",
-                            "description": "Bitcoin network (mainnet, testnet, signet, regtest)"
+                            "description": "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)"
                         },
                         "backend": {
                             "type": "string",
@@ -1256,7 +1628,7 @@ impl ServerHandler for CyberkrillMcpServer {
                         },
                         "network": {
                             "type": "string",
-                            "description": "Bitcoin network (mainnet, testnet, signet, regtest)"
+                            "description": "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)"
                         },
                         "backend": {
                             "type": "string",
@@ -1307,7 +1679,7 @@ impl ServerHandler for CyberkrillMcpServer {
                         },
                         "network": {
                             "type": "string",
-                            "description": "Bitcoin network (mainnet, testnet, signet, regtest)"
+                            "description": "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)"
                         },
                         "backend": {
                             "type": "string",
@@ -1338,7 +1710,7 @@ impl ServerHandler for CyberkrillMcpServer {
                         },
                         "destination": {
                             "type": "string",
-                            "description": "Destination Bitcoin address"
+                            "description": "Destination address, or a split like 'addr1:60%,addr2:40%' or 'addr1:0.1btc,addr2' (the bare address receives the remainder)"
                         },
                         "fee_rate": {
                             "type": "number",
@@ -1352,13 +1724,17 @@ impl ServerHandler for CyberkrillMcpServer {
                             "type": "string",
                             "description": "Maximum amount to move (e.g., '0.5btc' or '50000000sats')"
                         },
+                        "select": {
+                            "type": "string",
+                            "description": "Which UTXOs to consolidate first: oldest, smallest, largest (default) or 'dust-below <amount>'"
+                        },
                         "descriptor": {
                             "type": "string",
                             "description": "Output descriptor for BDK backends"
                         },
                         "network": {
                             "type": "string",
-                            "description": "Bitcoin network (mainnet, testnet, signet, regtest)"
+                            "description": "Bitcoin network (mainnet, testnet, testnet4, signet, regtest)"
                         },
                         "backend": {
                             "type": "string",
@@ -1402,31 +1778,104 @@ impl ServerHandler for CyberkrillMcpServer {
                             "type": "string",
                             "description": "Bitcoin data directory (for bitcoind)"
                         },
-                        "cache_dir": {
+                        "price_source": {
                             "type": "string",
-                            "description": "Cache directory for price data"
+                            "description": "Historical price source (auto, coingecko, kraken, mempool)"
                         }
                     },
                     "required": ["descriptor"]
                 }),
             ),
         ];
+        tools.retain(|tool| self.config.allows_tool(&tool.name));
+        tools
+    }
 
-        Ok(ListToolsResult {
-            tools,
-            next_cursor: None,
-            meta: None,
-        })
+    /// Run a tool call through the read-only check, the rate limits and the audit
+    /// log. Shared by the MCP transports and the HTTP API.
+    pub async fn invoke(
+        &self,
+        tool: &str,
+        arguments: Option<serde_json::Map<String, serde_json::Value>>,
+    ) -> (AuditStatus, Result<CallToolResult, McpError>) {
+        let started = Instant::now();
+        let args_sha256 = hash_arguments(arguments.as_ref());
+
+        let (status, result) = if !self.config.allows_tool(tool) {
+            let error = McpError::invalid_request(
+                format!("Tool {tool} is disabled in read-only mode"),
+                None,
+            );
+            (AuditStatus::Rejected, Err(error))
+        } else if let Err(retry_after) = self.check_rate_limit(tool, started).await {
+            let error = McpError::invalid_request(
+                format!(
+                    "Rate limit exceeded for tool {tool}, retry in {seconds}s",
+                    seconds = retry_after.as_secs().max(1)
+                ),
+                None,
+            );
+            (AuditStatus::RateLimited, Err(error))
+        } else {
+            let result = self.dispatch_tool(tool, arguments).await;
+            let status = match &result {
+                Ok(result) if result.is_error != Some(true) => AuditStatus::Success,
+                Ok(_) => AuditStatus::Error,
+                Err(_) => AuditStatus::Rejected,
+            };
+            (status, result)
+        };
+
+        self.audit(AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            tool,
+            args_sha256,
+            status,
+            duration_ms: started.elapsed().as_millis(),
+            error: result.as_ref().err().map(|e| e.message.to_string()),
+        });
+        (status, result)
     }
 
-    async fn call_tool(
+    async fn check_rate_limit(&self, tool: &str, now: Instant) -> Result<(), Duration> {
+        self.state
+            .lock()
+            .await
+            .check_rate_limit(tool, &self.config.rate_limits, now)
+    }
+
+    /// Append `record` to the audit log, if one is configured
+    fn audit(&self, record: AuditRecord) {
+        let Some(path) = &self.config.audit_log else {
+            return;
+        };
+        let write = || -> Result<()> {
+            let mut line = serde_json::to_vec(&record)?;
+            line.push(b'\n');
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&line)?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!(
+                "Failed to write the MCP audit log {path}: {e}",
+                path = path.display()
+            );
+        }
+    }
+
+    async fn dispatch_tool(
         &self,
-        request: CallToolRequestParam,
-        _context: RequestContext<RoleServer>,
+        tool: &str,
+        arguments: Option<serde_json::Map<String, serde_json::Value>>,
     ) -> Result<CallToolResult, McpError> {
-        let args = request.arguments.unwrap_or_default();
+        let mut args = arguments.unwrap_or_default();
+        self.config.backend_defaults.apply(&mut args);
 
-        match request.name.as_ref() {
+        match tool {
             "decode_invoice" => {
                 let invoice = args
                     .get("invoice")
@@ -1449,6 +1898,24 @@ impl ServerHandler for CyberkrillMcpServer {
                     })
                     .await)
             }
+            "verify_payment" => {
+                let invoice = args
+                    .get("invoice")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| McpError::invalid_request("invoice parameter required", None))?;
+                let preimage = args
+                    .get("preimage")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| {
+                        McpError::invalid_request("preimage parameter required", None)
+                    })?;
+                Ok(self
+                    .verify_payment(VerifyPaymentRequest {
+                        invoice: invoice.to_string(),
+                        preimage: preimage.to_string(),
+                    })
+                    .await)
+            }
             "generate_invoice" => {
                 let address = args
                     .get("address")
@@ -1681,6 +2148,10 @@ impl ServerHandler for CyberkrillMcpServer {
                     .get("max_amount")
                     .and_then(|v| v.as_str())
                     .map(|s| s.to_string());
+                let select = args
+                    .get("select")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string());
                 let descriptor = args
                     .get("descriptor")
                     .and_then(|v| v.as_str())
@@ -1708,6 +2179,7 @@ impl ServerHandler for CyberkrillMcpServer {
                         fee_rate,
                         fee,
                         max_amount,
+                        select,
                         descriptor,
                         network,
                         backend,
@@ -1739,8 +2211,8 @@ impl ServerHandler for CyberkrillMcpServer {
                     .get("bitcoin_dir")
                     .and_then(|v| v.as_str())
                     .map(|s| s.to_string());
-                let cache_dir = args
-                    .get("cache_dir")
+                let price_source = args
+                    .get("price_source")
                     .and_then(|v| v.as_str())
                     .map(|s| s.to_string());
                 Ok(self
@@ -1750,14 +2222,179 @@ impl ServerHandler for CyberkrillMcpServer {
                         backend,
                         backend_url,
                         bitcoin_dir,
-                        cache_dir,
+                        price_source,
                     })
                     .await)
             }
             _ => Err(McpError::invalid_request(
-                format!("Tool '{}' not found", request.name),
+                format!("Tool '{tool}' not found"),
                 None,
             )),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_only_only_allows_decode_and_list_tools() {
+        let mut config = McpServerConfig::default();
+        assert!(config.allows_tool("create_psbt"));
+        assert!(config.allows_tool("generate_invoice"));
+        config.read_only = true;
+        for tool in [
+            "create_psbt",
+            "create_funded_psbt",
+            "move_utxos",
+            "generate_invoice",
+            "encode_fedimint_invite",
+            "dca_report",
+            "some_future_tool",
+        ] {
+            assert!(!config.allows_tool(tool), "{tool} is allowed");
+        }
+        for tool in READ_ONLY_TOOLS {
+            assert!(config.allows_tool(tool));
+        }
+    }
+
+    #[test]
+    fn test_parse_tool_rate_limit() -> Result<()> {
+        let limit: ToolRateLimit = "create_psbt=5/min".parse()?;
+        assert_eq!(
+            limit,
+            ToolRateLimit {
+                tool: "create_psbt".to_string(),
+                max_calls: 5,
+                period: Duration::from_secs(60),
+            }
+        );
+        assert_eq!("*=100/hour".parse::<ToolRateLimit>()?.tool, "*");
+        assert!("create_psbt=5".parse::<ToolRateLimit>().is_err());
+        assert!("create_psbt=5/week".parse::<ToolRateLimit>().is_err());
+        assert!("=5/min".parse::<ToolRateLimit>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limit_sliding_window() -> Result<()> {
+        let limits = vec![
+            "move_utxos=2/min".parse::<ToolRateLimit>()?,
+            "*=3/sec".parse::<ToolRateLimit>()?,
+        ];
+        let mut state = ServerState::default();
+        let start = Instant::now();
+        assert!(state.check_rate_limit("move_utxos", &limits, start).is_ok());
+        assert!(state.check_rate_limit("move_utxos", &limits, start).is_ok());
+        let retry = state.check_rate_limit("move_utxos", &limits, start + Duration::from_secs(10));
+        assert_eq!(retry, Err(Duration::from_secs(50)));
+        // The window slides: a minute after the first calls they are allowed again
+        let later = start + Duration::from_secs(60);
+        assert!(state.check_rate_limit("move_utxos", &limits, later).is_ok());
+
+        // Other tools fall back to the `*` limit, counted per tool
+        for _ in 0..3 {
+            assert!(
+                state
+                    .check_rate_limit("decode_psbt", &limits, start)
+                    .is_ok()
+            );
+        }
+        assert!(
+            state
+                .check_rate_limit("decode_psbt", &limits, start)
+                .is_err()
+        );
+        assert!(state.check_rate_limit("list_utxos", &limits, start).is_ok());
+
+        // Without limits nothing is tracked
+        assert!(state.check_rate_limit("anything", &[], start).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_record_hashes_arguments() -> Result<()> {
+        let arguments = serde_json::json!({"descriptor": "wpkh(xpub...)"});
+        let hash = hash_arguments(arguments.as_object());
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_arguments(arguments.as_object()));
+        assert_ne!(hash, hash_arguments(None));
+
+        let record = AuditRecord {
+            timestamp: "2024-01-01T00:00:00+00:00".to_string(),
+            tool: "list_utxos",
+            args_sha256: hash,
+            status: AuditStatus::RateLimited,
+            duration_ms: 3,
+            error: None,
+        };
+        let line = serde_json::to_value(&record)?;
+        assert_eq!(line["status"], "rate_limited");
+        assert!(line.get("error").is_none());
+        assert!(!line.to_string().contains("xpub"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_backend_defaults_fill_missing_arguments() {
+        let defaults = BackendDefaults {
+            network: Some("signet".to_string()),
+            backend: Some("esplora".to_string()),
+            backend_url: Some("https://mutinynet.com/api".to_string()),
+            bitcoin_dir: None,
+            rpc_url: None,
+            rpc_user: None,
+            rpc_password: None,
+        };
+        let mut args =
+            serde_json::json!({"descriptor": "wpkh(...)", "network": "regtest", "backend": null})
+                .as_object()
+                .cloned()
+                .unwrap_or_default();
+        defaults.apply(&mut args);
+        assert_eq!(args["network"], "regtest");
+        assert_eq!(args["backend"], "esplora");
+        assert_eq!(args["backend_url"], "https://mutinynet.com/api");
+        assert!(args.get("bitcoin_dir").is_none());
+        assert_eq!(defaults.rpc_url(), DEFAULT_RPC_URL);
+    }
+
+    #[test]
+    fn test_authorize_bearer_token() {
+        let mut config = McpServerConfig::default();
+        assert!(config.authorize(None));
+        config.auth_token = Some("s3cret".to_string());
+        assert!(config.authorize(Some("Bearer s3cret")));
+        assert!(!config.authorize(Some("Bearer s3cre")));
+        assert!(!config.authorize(Some("Basic s3cret")));
+        assert!(!config.authorize(None));
+    }
+
+    #[test]
+    fn test_tool_error_carries_error_class() {
+        let error = anyhow::Error::new(cyberkrill_core::RpcError {
+            code: cyberkrill_core::RpcError::IN_WARMUP,
+            message: "Loading block index...".to_string(),
+        })
+        .context("Failed to list UTXOs");
+        let result = tool_error(error);
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            result.content[0].as_text().map(|text| text.text.as_str()),
+            Some("Error: Failed to list UTXOs")
+        );
+        assert_eq!(
+            result.structured_content,
+            Some(serde_json::json!({
+                "error": {
+                    "kind": "backend_unavailable",
+                    "message": "Failed to list UTXOs",
+                    "retryable": true,
+                    "rpc_code": -28,
+                }
+            }))
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Rendering of command results in the format picked with `--output-format`
+//!
+//! Results are serialized once and rendered from their JSON value. For the table
+//! and CSV formats a result is laid out as rows: a list of objects gives one row
+//! per object, an object holding such a list (a UTXO listing, a report) gives one
+//! row per list entry, and any other object is shown as field/value pairs. Nested
+//! objects become dotted columns; nested lists stay compact JSON in their cell.
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::sync::OnceLock;
+
+static ACTIVE_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Pretty-printed JSON
+    #[default]
+    Json,
+    Yaml,
+    /// Aligned columns for reading in a terminal
+    Table,
+    Csv,
+}
+
+impl OutputFormat {
+    /// Make `format` the one used by [`write`] for the rest of the process
+    pub fn install(format: OutputFormat) -> Result<()> {
+        ACTIVE_FORMAT
+            .set(format)
+            .map_err(|_| anyhow::anyhow!("An output format is already installed"))
+    }
+
+    pub fn active() -> OutputFormat {
+        ACTIVE_FORMAT.get().copied().unwrap_or_default()
+    }
+}
+
+/// Write `value` in the active output format, without a trailing newline
+pub fn write<W: Write, T: Serialize + ?Sized>(writer: W, value: &T) -> Result<()> {
+    render(writer, value, OutputFormat::active())
+}
+
+pub fn render<W: Write, T: Serialize + ?Sized>(
+    mut writer: W,
+    value: &T,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, value)?,
+        OutputFormat::Yaml => {
+            let yaml = serde_yaml::to_string(value)?;
+            write!(writer, "{yaml}", yaml = yaml.trim_end())?;
+        }
+        OutputFormat::Table => {
+            let value = serde_json::to_value(value)?;
+            let (rows, summary) = to_rows(&value);
+            let mut sections = Vec::new();
+            if !rows.headers.is_empty() {
+                sections.push(rows.to_table());
+            }
+            if let Some(summary) = summary {
+                sections.push(summary.to_table());
+            }
+            write!(writer, "{tables}", tables = sections.join("\n\n"))?;
+        }
+        OutputFormat::Csv => {
+            let value = serde_json::to_value(value)?;
+            let (rows, _) = to_rows(&value);
+            write!(writer, "{csv}", csv = rows.to_csv())?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Rows {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Rows {
+    fn from_records<'a>(records: impl IntoIterator<Item = &'a Value>) -> Self {
+        let flattened: Vec<Vec<(String, String)>> = records
+            .into_iter()
+            .map(|record| {
+                let mut cells = Vec::new();
+                flatten("", record, &mut cells);
+                cells
+            })
+            .collect();
+        let mut headers: Vec<String> = Vec::new();
+        for cells in &flattened {
+            for (column, _) in cells {
+                if !headers.contains(column) {
+                    headers.push(column.clone());
+                }
+            }
+        }
+        let rows = flattened
+            .into_iter()
+            .map(|cells| {
+                headers
+                    .iter()
+                    .map(|header| {
+                        cells
+                            .iter()
+                            .find(|(column, _)| column == header)
+                            .map(|(_, cell)| cell.clone())
+                            .unwrap_or_default()
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { headers, rows }
+    }
+
+    /// Field/value pairs of an object
+    fn from_fields(fields: &[(String, String)]) -> Self {
+        Self {
+            headers: vec!["field".to_string(), "value".to_string()],
+            rows: fields
+                .iter()
+                .map(|(field, value)| vec![field.clone(), value.clone()])
+                .collect(),
+        }
+    }
+
+    fn to_table(&self) -> String {
+        let widths: Vec<usize> = self
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| {
+                self.rows
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([header.chars().count()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let numeric: Vec<bool> = (0..self.headers.len())
+            .map(|i| {
+                !self.rows.is_empty()
+                    && self
+                        .rows
+                        .iter()
+                        .all(|row| row[i].is_empty() || row[i].parse::<f64>().is_ok())
+            })
+            .collect();
+        let line = |cells: &[String], align_numbers: bool| {
+            cells
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    let width = widths[i];
+                    if align_numbers && numeric[i] {
+                        format!("{cell:>width$}")
+                    } else {
+                        format!("{cell:<width$}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        let separator: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+        let mut lines = vec![line(&self.headers, false), line(&separator, false)];
+        lines.extend(self.rows.iter().map(|row| line(row, true)));
+        lines.join("\n")
+    }
+
+    fn to_csv(&self) -> String {
+        std::iter::once(&self.headers)
+            .chain(&self.rows)
+            .map(|cells| {
+                cells
+                    .iter()
+                    .map(|cell| csv_field(cell))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Lay out `value` as rows, plus the remaining scalar fields of an object whose
+/// list became the rows
+fn to_rows(value: &Value) -> (Rows, Option<Rows>) {
+    match value {
+        Value::Array(items) if items.iter().all(Value::is_object) && !items.is_empty() => {
+            (Rows::from_records(items), None)
+        }
+        Value::Array(items) => (
+            Rows::from_records(
+                &items
+                    .iter()
+                    .map(|item| serde_json::json!({ "value": item }))
+                    .collect::<Vec<_>>(),
+            ),
+            None,
+        ),
+        Value::Object(fields) => match record_list(fields) {
+            Some((name, records)) => {
+                let mut summary = Vec::new();
+                for (field, value) in fields {
+                    if field != name {
+                        flatten(field, value, &mut summary);
+                    }
+                }
+                let summary = (!summary.is_empty()).then(|| Rows::from_fields(&summary));
+                (Rows::from_records(records), summary)
+            }
+            None => {
+                let mut cells = Vec::new();
+                flatten("", value, &mut cells);
+                (Rows::from_fields(&cells), None)
+            }
+        },
+        scalar => (
+            Rows::from_fields(&[("value".to_string(), cell(scalar))]),
+            None,
+        ),
+    }
+}
+
+/// The first non-empty list of objects among an object's fields
+fn record_list(fields: &Map<String, Value>) -> Option<(&String, &Vec<Value>)> {
+    fields.iter().find_map(|(name, value)| match value {
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object) => {
+            Some((name, items))
+        }
+        _ => None,
+    })
+}
+
+fn flatten(prefix: &str, value: &Value, cells: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for (field, value) in fields {
+                let column = if prefix.is_empty() {
+                    field.clone()
+                } else {
+                    format!("{prefix}.{field}")
+                };
+                flatten(&column, value, cells);
+            }
+        }
+        value => cells.push((prefix.to_string(), cell(value))),
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{escaped}\"", escaped = field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rendered(value: &Value, format: OutputFormat) -> Result<String> {
+        let mut buffer = Vec::new();
+        render(&mut buffer, value, format)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    #[test]
+    fn test_list_of_records_as_table_and_csv() -> Result<()> {
+        let utxos = json!({
+            "utxos": [
+                {"txid": "aa", "vout": 0, "amount_sats": 150000, "address": "bc1q..."},
+                {"txid": "bb", "vout": 12, "amount_sats": 2000, "label": "cold, \"savings\""}
+            ],
+            "total_amount_sats": 152000,
+            "backend": {"name": "electrum"}
+        });
+
+        assert_eq!(
+            rendered(&utxos, OutputFormat::Csv)?,
+            "txid,vout,amount_sats,address,label\n\
+             aa,0,150000,bc1q...,\n\
+             bb,12,2000,,\"cold, \"\"savings\"\"\""
+        );
+        assert_eq!(
+            rendered(&utxos, OutputFormat::Table)?,
+            "txid  vout  amount_sats  address  label\n\
+             ----  ----  -----------  -------  ---------------\n\
+             aa       0       150000  bc1q...\n\
+             bb      12         2000           cold, \"savings\"\n\
+             \n\
+             field              value\n\
+             -----------------  --------\n\
+             total_amount_sats  152000\n\
+             backend.name       electrum"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_object_as_fields() -> Result<()> {
+        let invoice = json!({
+            "network": "bitcoin",
+            "amount_msats": 1000,
+            "route_hints": [],
+            "features": {"var_onion_optin": "required"}
+        });
+        assert_eq!(
+            rendered(&invoice, OutputFormat::Csv)?,
+            "field,value\n\
+             network,bitcoin\n\
+             amount_msats,1000\n\
+             route_hints,[]\n\
+             features.var_onion_optin,required"
+        );
+        assert_eq!(
+            rendered(&invoice, OutputFormat::Yaml)?,
+            "network: bitcoin\namount_msats: 1000\nroute_hints: []\nfeatures:\n  var_onion_optin: required"
+        );
+        assert_eq!(rendered(&json!([1, 2]), OutputFormat::Csv)?, "value\n1\n2");
+        Ok(())
+    }
+}
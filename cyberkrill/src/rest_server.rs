@@ -0,0 +1,226 @@
+//! JSON HTTP API over the MCP tools (`cyberkrill serve`)
+//!
+//! Every tool the MCP server exposes is reachable as `POST /v1/<tool>` with the
+//! tool arguments as the JSON body (`list-utxos` and `list_utxos` both work).
+//! Calls go through [`CyberkrillMcpServer::invoke`], so the read-only mode, rate
+//! limits, audit log and backend defaults behave exactly as over MCP.
+//! A failed tool call answers 422, or 503 when the backend is unavailable and
+//! the call may be retried, with the error class in `kind`.
+
+use crate::mcp_server::{AuditStatus, CyberkrillMcpServer};
+use anyhow::{Context, Result};
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use rmcp::model::CallToolResult;
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+type Server = Arc<CyberkrillMcpServer>;
+
+/// Serve the API on `listen` until the process is stopped
+pub async fn serve(server: CyberkrillMcpServer, listen: SocketAddr) -> Result<()> {
+    anyhow::ensure!(
+        server.config().auth_token.is_some(),
+        "The HTTP API requires a bearer token (--auth-token or CYBERKRILL_API_TOKEN)"
+    );
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {listen}"))?;
+    eprintln!("Serving the cyberkrill API on http://{listen}");
+    axum::serve(listener, router(Arc::new(server)))
+        .await
+        .context("HTTP server failed")
+}
+
+fn router(server: Server) -> Router {
+    Router::new()
+        .route("/v1/tools", get(list_tools))
+        .route("/v1/{tool}", post(call_tool))
+        .route_layer(middleware::from_fn_with_state(server.clone(), authenticate))
+        .route("/health", get(|| async { "ok" }))
+        .with_state(server)
+}
+
+async fn authenticate(State(server): State<Server>, request: Request, next: Next) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if server.config().authorize(authorization) {
+        next.run(request).await
+    } else {
+        error(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token")
+    }
+}
+
+async fn list_tools(State(server): State<Server>) -> Response {
+    let tools: Vec<Value> = server
+        .tools()
+        .into_iter()
+        .map(|tool| {
+            json!({
+                "name": tool.name,
+                "description": tool.description,
+                "input_schema": tool.input_schema,
+            })
+        })
+        .collect();
+    axum::Json(json!({ "tools": tools })).into_response()
+}
+
+async fn call_tool(
+    State(server): State<Server>,
+    Path(tool): Path<String>,
+    body: Bytes,
+) -> Response {
+    let tool = tool.replace('-', "_");
+    let arguments = if body.iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(Value::Object(arguments)) => Some(arguments),
+            Ok(_) => {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    "Request body must be a JSON object",
+                );
+            }
+            Err(e) => {
+                return error(StatusCode::BAD_REQUEST, &format!("Invalid JSON body: {e}"));
+            }
+        }
+    };
+
+    match server.invoke(&tool, arguments).await {
+        (_, Ok(result)) => tool_response(result),
+        (AuditStatus::RateLimited, Err(e)) => error(StatusCode::TOO_MANY_REQUESTS, &e.message),
+        (_, Err(e)) if !server.config().allows_tool(&tool) => {
+            error(StatusCode::FORBIDDEN, &e.message)
+        }
+        (_, Err(e)) if !server.tools().iter().any(|known| known.name == tool) => {
+            error(StatusCode::NOT_FOUND, &e.message)
+        }
+        (_, Err(e)) => error(StatusCode::BAD_REQUEST, &e.message),
+    }
+}
+
+/// The tools answer with JSON text; pass it through as the response body
+fn tool_response(result: CallToolResult) -> Response {
+    let text: String = result
+        .content
+        .iter()
+        .filter_map(|content| content.as_text())
+        .map(|content| content.text.as_str())
+        .collect();
+    if result.is_error == Some(true) {
+        let message = text.strip_prefix("Error: ").unwrap_or(&text);
+        let Some(class) = result
+            .structured_content
+            .as_ref()
+            .and_then(|content| content.get("error"))
+        else {
+            return error(StatusCode::UNPROCESSABLE_ENTITY, message);
+        };
+        // A backend that may come back is a 503, anything else a 422
+        let retryable = class["retryable"].as_bool().unwrap_or(false);
+        let status = if retryable {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::UNPROCESSABLE_ENTITY
+        };
+        let body = json!({
+            "error": message,
+            "kind": class["kind"],
+            "retryable": retryable,
+        });
+        return (status, axum::Json(body)).into_response();
+    }
+    let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+    axum::Json(body).into_response()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, axum::Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp_server::{BackendDefaults, McpServerConfig, Transport};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    fn server(read_only: bool) -> Server {
+        Arc::new(CyberkrillMcpServer::new(McpServerConfig {
+            transport: Transport::Stdio,
+            host: "127.0.0.1".to_string(),
+            port: 9090,
+            read_only,
+            auth_token: Some("secret".to_string()),
+            audit_log: None,
+            rate_limits: Vec::new(),
+            backend_defaults: BackendDefaults::default(),
+            client_options: cyberkrill_core::ClientOptions::default(),
+        }))
+    }
+
+    async fn send(
+        server: Server,
+        uri: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> Result<(StatusCode, Value)> {
+        let mut request = Request::post(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let response = router(server)
+            .oneshot(request.body(Body::from(body.to_string()))?)
+            .await?;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        Ok((status, serde_json::from_slice(&body)?))
+    }
+
+    #[tokio::test]
+    async fn test_rest_api_auth_and_status_codes() -> Result<()> {
+        let invite = json!({ "invite_code": "not-an-invite" }).to_string();
+
+        let (status, _) = send(server(false), "/v1/decode-fedimint-invite", None, &invite).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            server(false),
+            "/v1/decode-fedimint-invite",
+            Some("wrong"),
+            &invite,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send(
+            server(false),
+            "/v1/decode-fedimint-invite",
+            Some("secret"),
+            &invite,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"].is_string());
+        assert!(body["kind"].is_string());
+        assert_eq!(body["retryable"], false);
+
+        let (status, _) = send(server(false), "/v1/no_such_tool", Some("secret"), "").await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(server(false), "/v1/decode_psbt", Some("secret"), "[1]").await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(server(true), "/v1/create_psbt", Some("secret"), "{}").await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
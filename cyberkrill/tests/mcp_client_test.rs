@@ -8,10 +8,18 @@ use serde_json::json;
 
 /// Helper function to start the MCP server and connect as a client
 async fn connect_to_server() -> Result<rmcp::service::RunningService<rmcp::RoleClient, ()>> {
+    connect_with_args(&[]).await
+}
+
+/// Start the MCP server with extra `mcp-server` arguments and connect to it
+async fn connect_with_args(
+    extra_args: &[&str],
+) -> Result<rmcp::service::RunningService<rmcp::RoleClient, ()>> {
     // Start our MCP server as a subprocess and connect to it using rmcp client
     let transport =
         TokioChildProcess::new(tokio::process::Command::new("cargo").configure(|cmd| {
             cmd.args(["run", "--quiet", "--", "mcp-server", "-t", "stdio"])
+                .args(extra_args)
                 .env("RUST_LOG", "error"); // Suppress logs for cleaner test output
         }))?;
 
@@ -37,6 +45,7 @@ async fn test_list_all_tools() -> Result<()> {
     let expected_tools = vec![
         "decode_invoice",
         "decode_lnurl",
+        "verify_payment",
         "generate_invoice",
         "decode_fedimint_invite",
         "encode_fedimint_invite",
@@ -59,11 +68,11 @@ async fn test_list_all_tools() -> Result<()> {
         );
     }
 
-    // Verify we have exactly 11 tools
+    // Verify we have exactly 12 tools
     assert_eq!(
         tools.len(),
-        11,
-        "Expected 11 tools, found {}. Tools: {:?}",
+        12,
+        "Expected 12 tools, found {}. Tools: {:?}",
         tools.len(),
         tools.iter().map(|t| &t.name).collect::<Vec<_>>()
     );
@@ -71,6 +80,50 @@ async fn test_list_all_tools() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_read_only_mode_only_lists_decode_and_list_tools() -> Result<()> {
+    let client = connect_with_args(&["--read-only"]).await?;
+
+    let tools = client.list_all_tools().await?;
+    let names: Vec<_> = tools.iter().map(|t| t.name.as_ref()).collect();
+    for hidden_tool in [
+        "create_psbt",
+        "create_funded_psbt",
+        "move_utxos",
+        "generate_invoice",
+        "encode_fedimint_invite",
+        "dca_report",
+    ] {
+        assert!(
+            !names.contains(&hidden_tool),
+            "Tool '{hidden_tool}' should be hidden in read-only mode: {names:?}"
+        );
+    }
+    assert!(names.contains(&"decode_psbt"));
+    assert!(names.contains(&"list_utxos"));
+    assert_eq!(tools.len(), 6, "Unexpected tools: {names:?}");
+
+    // Calling a hidden tool directly is refused as well
+    let result = client
+        .call_tool(CallToolRequestParam {
+            name: "create_psbt".into(),
+            arguments: Some(
+                json!({
+                    "inputs": ["txid:0"],
+                    "outputs": "bc1qtest:0.001",
+                    "fee_rate": 10.0
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        })
+        .await;
+    assert!(result.is_err(), "create_psbt should be refused");
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_decode_invoice_tool() -> Result<()> {
     let client = connect_to_server().await?;
@@ -32,6 +32,7 @@ use tracing::{debug, warn};
 pub use crate::{
     decode_fedimint_invite as decode_invite, encode_fedimint_invite as encode_invite,
     fetch_fedimint_config as fetch_config,
+    fetch_fedimint_config_with_client as fetch_config_with_client,
 };
 
 // Re-export types with simpler names
@@ -451,11 +452,18 @@ pub struct GuardianConfigInfo {
 }
 
 pub async fn fetch_fedimint_config(invite_code: &str) -> Result<FederationConfigOutput> {
+    fetch_fedimint_config_with_client(invite_code, &reqwest::Client::new()).await
+}
+
+/// Fetch the federation configuration using a caller-configured HTTP client
+/// (e.g. one that routes through a SOCKS5 proxy)
+pub async fn fetch_fedimint_config_with_client(
+    invite_code: &str,
+    client: &reqwest::Client,
+) -> Result<FederationConfigOutput> {
     // First decode the invite code to get guardian endpoints and federation ID
     let invite = decode_fedimint_invite(invite_code)?;
 
-    let client = reqwest::Client::new();
-
     // Try each guardian until we get a successful response
     let mut last_error = None;
 
@@ -468,7 +476,7 @@ pub async fn fetch_fedimint_config(invite_code: &str) -> Result<FederationConfig
         let base_url = http_url.trim_end_matches('/');
         let config_url = format!("{base_url}/config");
 
-        match fetch_config_from_guardian(&client, &config_url).await {
+        match fetch_config_from_guardian(client, &config_url).await {
             Ok(config) => {
                 // Validate that the config matches the expected federation ID
                 validate_federation_id(&config, &invite.federation_id)?;
@@ -484,11 +492,11 @@ pub async fn fetch_fedimint_config(invite_code: &str) -> Result<FederationConfig
         }
     }
 
-    // If we get here, all guardians failed
-    anyhow::bail!(
-        "Failed to fetch config from any guardian. Last error: {:?}",
-        last_error
-    );
+    // If we get here, all guardians failed; keep the last error as the source
+    match last_error {
+        Some(e) => Err(e.context("Failed to fetch config from any guardian")),
+        None => anyhow::bail!("Invite code lists no guardians"),
+    }
 }
 
 async fn fetch_config_from_guardian(
@@ -34,6 +34,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("\n=== Extended Public Key ===");
     println!("Path: {path}");
     println!("xpub: {xpub}");
+    println!("Master fingerprint: {}", xpub.master_fingerprint);
+    println!("Descriptor key: {}", xpub.descriptor_key());
 
     // Demonstrate getting multiple xpubs
     println!("\n=== Additional xpubs ===");
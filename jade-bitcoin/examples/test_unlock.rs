@@ -48,7 +48,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("\nTesting xpub retrieval...");
     match client.get_xpub("m/84'/0'/0'").await {
         Ok(xpub) => {
-            println!("✓ Got xpub: {}", &xpub[..20]);
+            println!("✓ Got xpub: {}", &xpub.to_string()[..20]);
         }
         Err(e) => {
             println!("✗ Failed to get xpub: {e}");
@@ -0,0 +1,134 @@
+//! Host side of the anti-exfil (sign-to-contract) protocol
+//!
+//! A signer that picks its own nonces can leak key material through them. With
+//! anti-exfil the host commits to random entropy, the device commits to its
+//! nonce point `R0`, and only then is the entropy revealed. The final nonce is
+//! `R0` tweaked by a hash of both, which the host checks against the signature.
+
+use bitcoin::hashes::{Hash, HashEngine, sha256};
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, Verification, ecdsa};
+
+const DATA_TAG: &str = "s2c/ecdsa/data";
+const POINT_TAG: &str = "s2c/ecdsa/point";
+
+/// Commitment to `host_entropy`, sent to the device before the entropy itself
+pub fn host_commitment(host_entropy: &[u8; 32]) -> [u8; 32] {
+    tagged_hash(DATA_TAG, &[host_entropy])
+}
+
+/// Whether `signature` uses the nonce the device committed to, tweaked by
+/// `host_entropy`. This does not check the signature against a message.
+pub fn verify_signer_commitment<C: Verification>(
+    secp: &Secp256k1<C>,
+    signature: &ecdsa::Signature,
+    host_entropy: &[u8; 32],
+    signer_commitment: &PublicKey,
+) -> bool {
+    let tweak = tagged_hash(POINT_TAG, &[&signer_commitment.serialize(), host_entropy]);
+    let Ok(tweak) = Scalar::from_be_bytes(tweak) else {
+        return false;
+    };
+    let Ok(nonce_point) = signer_commitment.add_exp_tweak(secp, &tweak) else {
+        return false;
+    };
+    // r is x(R) mod n; an x coordinate above n is astronomically unlikely and
+    // simply fails the comparison
+    nonce_point.x_only_public_key().0.serialize()[..] == signature.serialize_compact()[..32]
+}
+
+/// BIP340-style tagged hash: SHA256(SHA256(tag) || SHA256(tag) || data)
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_byte_array());
+    engine.input(tag_hash.as_byte_array());
+    for chunk in data {
+        engine.input(chunk);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::FromHex;
+    use bitcoin::secp256k1::Message;
+    use std::str::FromStr;
+
+    // Generated with an independent reference signer: private key 0x01 * 32,
+    // device nonce 0x02 * 32, host entropy 0x03 * 32, message SHA256("anti-exfil")
+    const PUBKEY: &str = "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f";
+    const MESSAGE: &str = "460ce187b91a3ca473804db4b74359a2f3885edd8302b2c03d4b01a6a3169056";
+    const HOST_ENTROPY: [u8; 32] = [0x03; 32];
+    const HOST_COMMITMENT: &str =
+        "dff63fc6bd8848be75a4c4011fc6191525617b8f7931c1ae7907d598b26fb6bc";
+    const SIGNER_COMMITMENT: &str =
+        "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766";
+    /// Signed with the nonce tweaked by the host entropy
+    const SIGNATURE: &str = "ca2083d675b6a7e2d16c98d10daf2a8acd755322cfd630f91aaded0167f2a188446d2ea63516a1a8235557ea8f510e6d312960029e3f792ae82e7b2bf1a9b37e";
+    /// Signed with the committed nonce itself, ignoring the host entropy
+    const UNTWEAKED_SIGNATURE: &str = "4d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d076609a50e81ecea8c9e1d032d2216f0f8c6e8959e8250369c9b31d8c5ca2e777902";
+
+    fn signature(hex: &str) -> anyhow::Result<ecdsa::Signature> {
+        Ok(ecdsa::Signature::from_compact(&Vec::<u8>::from_hex(hex)?)?)
+    }
+
+    #[test]
+    fn test_host_commitment() -> anyhow::Result<()> {
+        assert_eq!(
+            host_commitment(&HOST_ENTROPY),
+            <[u8; 32]>::from_hex(HOST_COMMITMENT)?
+        );
+        assert_ne!(host_commitment(&[0x04; 32]), host_commitment(&HOST_ENTROPY));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_signer_commitment() -> anyhow::Result<()> {
+        let secp = Secp256k1::verification_only();
+        let signer_commitment = PublicKey::from_str(SIGNER_COMMITMENT)?;
+        let signature = signature(SIGNATURE)?;
+
+        // A real signature of the message, not just a matching r
+        secp.verify_ecdsa(
+            &Message::from_digest(<[u8; 32]>::from_hex(MESSAGE)?),
+            &signature,
+            &PublicKey::from_str(PUBKEY)?,
+        )?;
+        assert!(verify_signer_commitment(
+            &secp,
+            &signature,
+            &HOST_ENTROPY,
+            &signer_commitment
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_signer_commitment_rejects_wrong_entropy() -> anyhow::Result<()> {
+        let secp = Secp256k1::verification_only();
+        let signer_commitment = PublicKey::from_str(SIGNER_COMMITMENT)?;
+
+        assert!(!verify_signer_commitment(
+            &secp,
+            &signature(SIGNATURE)?,
+            &[0x04; 32],
+            &signer_commitment
+        ));
+        // The device used the nonce it committed to without mixing in the entropy
+        assert!(!verify_signer_commitment(
+            &secp,
+            &signature(UNTWEAKED_SIGNATURE)?,
+            &HOST_ENTROPY,
+            &signer_commitment
+        ));
+        // A commitment to another nonce
+        assert!(!verify_signer_commitment(
+            &secp,
+            &signature(SIGNATURE)?,
+            &HOST_ENTROPY,
+            &PublicKey::from_str(PUBKEY)?
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! Bluetooth LE communication for Jade
+//!
+//! Jade exposes a Nordic-UART-style GATT service: requests are written to one
+//! characteristic and responses arrive as notifications on another, split into
+//! MTU-sized pieces. Pairing (with the passkey shown on the Jade) is handled by
+//! the operating system the first time the device is connected.
+
+use crate::error::{Error, Result};
+use crate::transport::take_frame;
+use crate::types::{BLE_SCAN_TIMEOUT_MS, JadeBleDevice};
+use btleplug::api::{
+    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, ValueNotification,
+    WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
+use tokio::time::{Duration, sleep, timeout};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+const SERVICE_UUID: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+/// Characteristic the host writes requests to
+const WRITE_UUID: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+/// Characteristic the device notifies responses on
+const NOTIFY_UUID: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+/// Largest write that fits the common 247-byte ATT MTU
+const WRITE_CHUNK_SIZE: usize = 244;
+
+/// Async Bluetooth LE connection to Jade device
+pub struct BleConnection {
+    peripheral: Peripheral,
+    write_char: Characteristic,
+    notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+    read_buffer: Vec<u8>,
+}
+
+impl BleConnection {
+    /// Connect to the Jade named or addressed `device`, or to the first one
+    /// found when `None`
+    pub async fn connect(device: Option<&str>) -> Result<Self> {
+        let adapter = adapter().await?;
+        let mut candidates = scan(&adapter).await?;
+        if let Some(device) = device {
+            candidates.retain(|(info, _)| {
+                info.name.eq_ignore_ascii_case(device) || info.address.eq_ignore_ascii_case(device)
+            });
+        }
+
+        if candidates.len() > 1 {
+            warn!(
+                "Found {count} Jade devices over Bluetooth, using {name}; select one explicitly to avoid ambiguity",
+                count = candidates.len(),
+                name = candidates[0].0.name
+            );
+        }
+        let (info, peripheral) = candidates.into_iter().next().ok_or(Error::DeviceNotFound)?;
+
+        debug!(
+            "Connecting to {name} ({address})",
+            name = info.name,
+            address = info.address
+        );
+        peripheral.connect().await?;
+        peripheral.discover_services().await?;
+
+        let characteristics = peripheral.characteristics();
+        let find = |uuid: Uuid| {
+            characteristics
+                .iter()
+                .find(|c| c.service_uuid == SERVICE_UUID && c.uuid == uuid)
+                .cloned()
+                .ok_or_else(|| {
+                    Error::Other(format!("{name} has no Jade BLE service", name = info.name))
+                })
+        };
+        let write_char = find(WRITE_UUID)?;
+        let notify_char = find(NOTIFY_UUID)?;
+
+        peripheral.subscribe(&notify_char).await?;
+        let notifications = peripheral.notifications().await?;
+
+        Ok(Self {
+            peripheral,
+            write_char,
+            notifications,
+            read_buffer: Vec::with_capacity(65536),
+        })
+    }
+
+    /// List Jade devices advertising over Bluetooth, sorted by name
+    pub async fn list_devices() -> Result<Vec<JadeBleDevice>> {
+        let adapter = adapter().await?;
+        Ok(scan(&adapter)
+            .await?
+            .into_iter()
+            .map(|(info, _)| info)
+            .collect())
+    }
+
+    /// Write an encoded message, split into MTU-sized writes
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        for chunk in bytes.chunks(WRITE_CHUNK_SIZE) {
+            self.peripheral
+                .write(&self.write_char, chunk, WriteType::WithResponse)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Read the bytes of the next complete CBOR message, failing with
+    /// [`Error::Timeout`] when no notification arrives for `wait`
+    pub async fn read_frame(&mut self, wait: Duration) -> Result<Vec<u8>> {
+        loop {
+            if let Some(frame) = take_frame(&mut self.read_buffer)? {
+                return Ok(frame);
+            }
+
+            let notification = timeout(wait, self.notifications.next())
+                .await
+                .map_err(|_| Error::Timeout)?
+                .ok_or_else(|| Error::Other("Jade closed the Bluetooth connection".to_string()))?;
+            if notification.uuid == NOTIFY_UUID {
+                self.read_buffer.extend_from_slice(&notification.value);
+            }
+        }
+    }
+}
+
+impl Drop for BleConnection {
+    fn drop(&mut self) {
+        // Disconnecting is async; leave it to a background task when a runtime is around
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let peripheral = self.peripheral.clone();
+            handle.spawn(async move {
+                let _ = peripheral.disconnect().await;
+            });
+        }
+    }
+}
+
+/// First Bluetooth adapter of the host
+async fn adapter() -> Result<Adapter> {
+    let manager = Manager::new().await?;
+    manager
+        .adapters()
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::Other("No Bluetooth adapter found".to_string()))
+}
+
+/// Scan for peripherals advertising a Jade name, sorted by name
+async fn scan(adapter: &Adapter) -> Result<Vec<(JadeBleDevice, Peripheral)>> {
+    debug!("Scanning for Jade devices over Bluetooth");
+    // Jade advertises its name but not always its service, so filter by name
+    adapter.start_scan(ScanFilter::default()).await?;
+    sleep(Duration::from_millis(BLE_SCAN_TIMEOUT_MS)).await;
+    let peripherals = adapter.peripherals().await?;
+    adapter.stop_scan().await?;
+
+    let mut devices = Vec::new();
+    for peripheral in peripherals {
+        let Some(properties) = peripheral.properties().await? else {
+            continue;
+        };
+        let Some(name) = properties.local_name else {
+            continue;
+        };
+        if name.starts_with("Jade") {
+            let address = properties.address.to_string();
+            devices.push((JadeBleDevice { name, address }, peripheral));
+        }
+    }
+    devices.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    Ok(devices)
+}
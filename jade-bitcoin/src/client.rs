@@ -1,17 +1,40 @@
 //! High-level Jade client API
 
+use crate::anti_exfil;
+#[cfg(feature = "ble")]
+use crate::ble::BleConnection;
 use crate::error::{Error, Result};
+use crate::messages::{
+    Bytes, ChangeOutput, OtaParams, SignTxParams, TxInputParams, UpdatePinserverParams,
+};
 use crate::protocol::JadeProtocol;
 use crate::serial::SerialConnection;
-use crate::types::{Network, VersionInfo};
-use bitcoin::bip32::DerivationPath;
-use log::{debug, info};
+use crate::transport::Connection;
+#[cfg(feature = "ble")]
+use crate::types::JadeBleDevice;
+#[cfg(feature = "pinserver")]
+use crate::types::PinServerConfig;
+use crate::types::{
+    JadeDevice, JadeEvent, JadeOptions, MultisigDescriptor, Network, PinServerDetails, VersionInfo,
+    XpubInfo,
+};
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{Hash, sha256};
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1};
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::{Amount, ScriptBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{debug, info};
 
 /// High-level client for Jade hardware wallet
 pub struct JadeClient {
     protocol: JadeProtocol,
     current_network: Option<Network>,
+    /// Cached after the first lookup; cleared on logout
+    master_fingerprint: Option<Fingerprint>,
 }
 
 impl JadeClient {
@@ -19,31 +42,61 @@ impl JadeClient {
     pub async fn connect() -> Result<Self> {
         info!("Searching for Jade device...");
         let connection = SerialConnection::connect().await?;
-        let protocol = JadeProtocol::new(connection);
+        Ok(Self::with_connection(connection.into()))
+    }
 
-        Ok(Self {
-            protocol,
-            current_network: None,
-        })
+    /// Connect to Jade device on a specific serial port
+    pub async fn connect_to(port: &str) -> Result<Self> {
+        info!("Connecting to Jade on {port}");
+        let connection = SerialConnection::connect_to(port).await?;
+        Ok(Self::with_connection(connection.into()))
     }
 
-    /// Connect to Jade device on specific port
-    pub async fn connect_path(path: &str) -> Result<Self> {
-        info!("Connecting to Jade on {path}");
-        let connection = SerialConnection::connect_path(path).await?;
-        let protocol = JadeProtocol::new(connection);
+    /// Connect to Jade over Bluetooth LE: the device with this name or address,
+    /// or the first one found when `None`
+    #[cfg(feature = "ble")]
+    pub async fn connect_ble(device: Option<&str>) -> Result<Self> {
+        info!("Searching for Jade over Bluetooth...");
+        let connection = BleConnection::connect(device).await?;
+        Ok(Self::with_connection(connection.into()))
+    }
 
-        Ok(Self {
-            protocol,
+    fn with_connection(connection: Connection) -> Self {
+        Self {
+            protocol: JadeProtocol::new(connection),
             current_network: None,
-        })
+            master_fingerprint: None,
+        }
+    }
+
+    /// Set request timeouts and retries
+    pub fn set_options(&mut self, options: JadeOptions) {
+        self.protocol.set_options(options);
+    }
+
+    /// Call `callback` when the device starts or stops waiting for the user
+    /// (PIN entry, confirmations) and when a request is retried
+    pub fn on_event(&mut self, callback: impl Fn(&JadeEvent) + Send + Sync + 'static) {
+        self.protocol.set_event_callback(Arc::new(callback));
     }
 
-    /// List all available Jade devices
-    pub fn list_devices() -> Vec<String> {
+    /// Choose the PIN server the host contacts while unlocking, or go offline
+    #[cfg(feature = "pinserver")]
+    pub fn set_pinserver_config(&mut self, config: PinServerConfig) {
+        self.protocol.set_pinserver_config(config);
+    }
+
+    /// List candidate Jade serial ports, filtered by USB VID/PID and sorted by port name
+    pub fn list_devices() -> Vec<JadeDevice> {
         SerialConnection::list_devices()
     }
 
+    /// Scan for Jade devices advertising over Bluetooth LE, sorted by name
+    #[cfg(feature = "ble")]
+    pub async fn list_ble_devices() -> Result<Vec<JadeBleDevice>> {
+        BleConnection::list_devices().await
+    }
+
     /// Get device version information
     pub async fn get_version_info(&mut self) -> Result<VersionInfo> {
         debug!("Getting version info");
@@ -119,21 +172,46 @@ impl JadeClient {
         info!("Logging out from Jade");
         self.protocol.logout().await?;
         self.current_network = None;
+        self.master_fingerprint = None;
         Ok(())
     }
 
-    /// Get extended public key at derivation path
-    pub async fn get_xpub(&mut self, path: &str) -> Result<String> {
+    /// Get the extended public key at derivation path, with its key origin
+    pub async fn get_xpub(&mut self, path: &str) -> Result<XpubInfo> {
         debug!("Getting xpub for path: {path}");
 
-        // Check if device is unlocked
-        if self.current_network.is_none() {
-            return Err(Error::DeviceLocked);
+        let network = self.current_network.ok_or(Error::DeviceLocked)?;
+        let origin =
+            DerivationPath::from_str(path).map_err(|_| Error::InvalidPath(path.to_string()))?;
+        let master_fingerprint = self.get_master_fingerprint().await?;
+        let xpub = self.fetch_xpub(&origin, network).await?;
+
+        Ok(XpubInfo {
+            depth: xpub.depth,
+            xpub,
+            master_fingerprint,
+            origin,
+        })
+    }
+
+    /// Fingerprint of the master key, as used in PSBT and descriptor key origins
+    pub async fn get_master_fingerprint(&mut self) -> Result<Fingerprint> {
+        if let Some(fingerprint) = self.master_fingerprint {
+            return Ok(fingerprint);
         }
+        let network = self.current_network.ok_or(Error::DeviceLocked)?;
+        let fingerprint = self
+            .fetch_xpub(&DerivationPath::master(), network)
+            .await?
+            .fingerprint();
+        self.master_fingerprint = Some(fingerprint);
+        Ok(fingerprint)
+    }
 
-        let path_array = parse_derivation_path(path)?;
-        let network = self.current_network.unwrap();
-        self.protocol.get_xpub(&path_array, network).await
+    async fn fetch_xpub(&mut self, path: &DerivationPath, network: Network) -> Result<Xpub> {
+        let path_array = to_path_array(path);
+        let xpub = self.protocol.get_xpub(&path_array, network).await?;
+        Ok(Xpub::from_str(&xpub)?)
     }
 
     /// Get Bitcoin address at derivation path
@@ -162,6 +240,139 @@ impl JadeClient {
             .await
     }
 
+    /// Register a multisig wallet so its addresses can be displayed and its PSBTs signed
+    pub async fn register_multisig(
+        &mut self,
+        name: &str,
+        descriptor: &MultisigDescriptor,
+        network: Network,
+    ) -> Result<()> {
+        debug!("Registering multisig {name} on {network:?}");
+
+        self.check_network(network)?;
+
+        self.protocol
+            .register_multisig(network, name, descriptor)
+            .await
+    }
+
+    /// Make the device use another PIN server after confirmation on its screen.
+    /// The PIN set against the previous server no longer unlocks the device.
+    pub async fn set_pinserver(&mut self, details: &PinServerDetails) -> Result<()> {
+        info!("Setting PIN server to {}", details.url);
+        let params = UpdatePinserverParams {
+            reset_details: false,
+            reset_certificate: details.certificate.is_none(),
+            url_a: Some(details.url.clone()),
+            url_b: Some(details.onion_url.clone().unwrap_or_default()),
+            pubkey: Some(Bytes(details.pubkey.to_vec())),
+            certificate: details.certificate.clone(),
+        };
+        self.protocol.update_pinserver(params).await
+    }
+
+    /// Go back to Blockstream's PIN server
+    pub async fn reset_pinserver(&mut self) -> Result<()> {
+        info!("Resetting PIN server");
+        let params = UpdatePinserverParams {
+            reset_details: true,
+            reset_certificate: true,
+            ..Default::default()
+        };
+        self.protocol.update_pinserver(params).await
+    }
+
+    /// Upload firmware over OTA after confirmation on the device, which then
+    /// reboots into it
+    ///
+    /// `compressed` is the zlib-compressed image as published by Blockstream,
+    /// `firmware_size` its uncompressed size and `firmware_hash` the SHA-256 of
+    /// the uncompressed image. The device checks both hashes before switching
+    /// to the new firmware. `progress` gets the bytes sent so far and the total.
+    pub async fn update_firmware(
+        &mut self,
+        compressed: &[u8],
+        firmware_size: usize,
+        firmware_hash: Option<[u8; 32]>,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<()> {
+        let chunk_size = self.get_version_info().await?.ota_chunk_size();
+        info!(
+            "Updating firmware: {len} compressed bytes in chunks of {chunk_size}",
+            len = compressed.len()
+        );
+
+        let params = OtaParams {
+            fwsize: firmware_size,
+            cmpsize: compressed.len(),
+            cmphash: Bytes(sha256::Hash::hash(compressed).to_byte_array().to_vec()),
+            fwhash: firmware_hash.map(|hash| Bytes(hash.to_vec())),
+        };
+        self.protocol.ota_start(params).await?;
+
+        let mut sent = 0;
+        for chunk in compressed.chunks(chunk_size) {
+            self.protocol.ota_data(chunk).await?;
+            sent += chunk.len();
+            progress(sent, compressed.len());
+        }
+        self.protocol.ota_complete().await?;
+
+        // The device reboots, so any unlocked session is gone
+        self.current_network = None;
+        self.master_fingerprint = None;
+        Ok(())
+    }
+
+    /// Names of the multisig wallets registered on the device
+    pub async fn get_registered_multisigs(&mut self) -> Result<Vec<String>> {
+        debug!("Getting registered multisigs");
+
+        if self.current_network.is_none() {
+            return Err(Error::DeviceLocked);
+        }
+
+        let result = self.protocol.get_registered_multisigs().await?;
+        let wallets = result.as_object().ok_or(Error::InvalidResponse)?;
+
+        Ok(wallets.keys().cloned().collect())
+    }
+
+    /// Get address of a registered multisig wallet
+    ///
+    /// `paths` holds the path below each signer's xpub, in registration order.
+    pub async fn get_multisig_address(
+        &mut self,
+        name: &str,
+        paths: &[&str],
+        network: Network,
+    ) -> Result<String> {
+        debug!("Getting address for multisig {name} on {network:?}");
+
+        self.check_network(network)?;
+
+        let path_arrays = paths
+            .iter()
+            .map(|path| parse_derivation_path(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.protocol
+            .get_multisig_receive_address(network, name, &path_arrays)
+            .await
+    }
+
+    /// Fail unless the device is unlocked for `network`
+    fn check_network(&self, network: Network) -> Result<()> {
+        match self.current_network {
+            Some(current) if current != network => Err(Error::NetworkMismatch {
+                device: format!("{current:?}"),
+                requested: format!("{network:?}"),
+            }),
+            Some(_) => Ok(()),
+            None => Err(Error::DeviceLocked),
+        }
+    }
+
     /// Sign a PSBT (Partially Signed Bitcoin Transaction)
     pub async fn sign_psbt(&mut self, psbt: &[u8], network: Network) -> Result<Vec<u8>> {
         debug!("Signing PSBT for {network:?}");
@@ -190,6 +401,114 @@ impl JadeClient {
         }
     }
 
+    /// Sign a PSBT with the anti-exfil protocol, checking every signature on the host
+    ///
+    /// Inputs with a BIP32 derivation from this device's master key are signed;
+    /// each signature must commit to the host's fresh entropy, so a compromised
+    /// device cannot choose nonces that leak its keys. Taproot inputs are not
+    /// supported by the protocol.
+    pub async fn sign_psbt_anti_exfil(&mut self, psbt: &[u8], network: Network) -> Result<Vec<u8>> {
+        debug!("Signing PSBT with anti-exfil for {network:?}");
+
+        self.check_network(network)?;
+
+        let mut psbt = Psbt::deserialize(psbt).map_err(|_| Error::InvalidPsbt)?;
+        let master_fingerprint = self.get_master_fingerprint().await?;
+
+        let mut inputs = Vec::with_capacity(psbt.inputs.len());
+        let mut host_entropy = Vec::with_capacity(psbt.inputs.len());
+        let mut expected = Vec::with_capacity(psbt.inputs.len());
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            let described = describe_input(&psbt, index)?;
+            let mut params = described.params;
+            let key = input
+                .bip32_derivation
+                .iter()
+                .find(|(_, (fingerprint, _))| *fingerprint == master_fingerprint);
+            let Some((pubkey, (_, path))) = key else {
+                inputs.push(params);
+                host_entropy.push(None);
+                expected.push(None);
+                continue;
+            };
+
+            let sighash_type = input
+                .sighash_type
+                .map(|sighash_type| sighash_type.ecdsa_hash_ty())
+                .transpose()
+                .map_err(|_| {
+                    Error::Other(format!("Input {index} has a non-standard sighash type"))
+                })?
+                .unwrap_or(EcdsaSighashType::All);
+            let sighash = match described.segwit_value {
+                Some(value) => cache
+                    .p2wsh_signature_hash(index, &described.script_code, value, sighash_type)
+                    .map(|sighash| sighash.to_byte_array()),
+                None => cache
+                    .legacy_signature_hash(index, &described.script_code, sighash_type.to_u32())
+                    .map(|sighash| sighash.to_byte_array()),
+            }
+            .map_err(|_| Error::InvalidPsbt)?;
+
+            let entropy: [u8; 32] = rand::random();
+            params.path = Some(to_path_array(path));
+            params.sighash = (sighash_type != EcdsaSighashType::All).then(|| sighash_type.to_u32());
+            params.ae_host_commitment = Some(Bytes(anti_exfil::host_commitment(&entropy).to_vec()));
+            inputs.push(params);
+            host_entropy.push(Some(entropy));
+            expected.push(Some((*pubkey, Message::from_digest(sighash))));
+        }
+
+        let sign_params = SignTxParams {
+            network: network.as_jade_str(),
+            txn: Bytes(serialize(&psbt.unsigned_tx)),
+            num_inputs: u32::try_from(psbt.inputs.len()).map_err(|_| Error::InvalidPsbt)?,
+            use_ae_signatures: true,
+            change: change_outputs(&psbt, master_fingerprint),
+        };
+        let replies = self
+            .protocol
+            .sign_tx_anti_exfil(sign_params, inputs, host_entropy.clone())
+            .await?;
+        if replies.len() != psbt.inputs.len() {
+            return Err(Error::InvalidResponse);
+        }
+
+        let secp = Secp256k1::verification_only();
+        for (index, (signer_commitment, signature)) in replies.into_iter().enumerate() {
+            let (Some((pubkey, sighash)), Some(entropy)) = (expected[index], host_entropy[index])
+            else {
+                continue;
+            };
+            if signature.is_empty() {
+                return Err(Error::Other(format!("Jade did not sign input {index}")));
+            }
+            let signature = bitcoin::ecdsa::Signature::from_slice(&signature)
+                .map_err(|_| Error::InvalidResponse)?;
+            let signer_commitment =
+                PublicKey::from_slice(&signer_commitment).map_err(|_| Error::InvalidResponse)?;
+
+            let valid = secp
+                .verify_ecdsa(&sighash, &signature.signature, &pubkey)
+                .is_ok()
+                && anti_exfil::verify_signer_commitment(
+                    &secp,
+                    &signature.signature,
+                    &entropy,
+                    &signer_commitment,
+                );
+            if !valid {
+                return Err(Error::AntiExfilVerification { input: index });
+            }
+            psbt.inputs[index]
+                .partial_sigs
+                .insert(bitcoin::PublicKey::new(pubkey), signature);
+        }
+
+        Ok(psbt.serialize())
+    }
+
     /// Sign a message with a specific derivation path
     pub async fn sign_message(&mut self, message: &str, path: &str) -> Result<String> {
         debug!("Signing message with path: {path}");
@@ -210,9 +529,12 @@ fn parse_derivation_path(path: &str) -> Result<Vec<u32>> {
     // Parse using bitcoin crate's DerivationPath
     let derivation =
         DerivationPath::from_str(path).map_err(|_| Error::InvalidPath(path.to_string()))?;
+    Ok(to_path_array(&derivation))
+}
 
-    // Convert to u32 array for Jade
-    let path_array: Vec<u32> = derivation
+/// Convert a derivation path to the u32 array Jade expects
+fn to_path_array(derivation: &DerivationPath) -> Vec<u32> {
+    derivation
         .into_iter()
         .map(|child| {
             let index = u32::from(*child);
@@ -223,9 +545,113 @@ fn parse_derivation_path(path: &str) -> Result<Vec<u32>> {
                 index
             }
         })
-        .collect();
+        .collect()
+}
 
-    Ok(path_array)
+/// `tx_input` params of a PSBT input, with the script code and (for segwit)
+/// the amount its sighash commits to
+struct DescribedInput {
+    params: TxInputParams,
+    script_code: ScriptBuf,
+    segwit_value: Option<Amount>,
+}
+
+fn describe_input(psbt: &Psbt, index: usize) -> Result<DescribedInput> {
+    let input = &psbt.inputs[index];
+    let vout = psbt.unsigned_tx.input[index].previous_output.vout;
+    let prevout = match (&input.witness_utxo, &input.non_witness_utxo) {
+        (Some(txout), _) => txout.clone(),
+        (None, Some(tx)) => usize::try_from(vout)
+            .ok()
+            .and_then(|vout| tx.output.get(vout))
+            .cloned()
+            .ok_or(Error::InvalidPsbt)?,
+        (None, None) => {
+            return Err(Error::Other(format!(
+                "Input {index} has no UTXO information"
+            )));
+        }
+    };
+
+    // The script that is executed: the redeem script for P2SH
+    let script = if prevout.script_pubkey.is_p2sh() {
+        input
+            .redeem_script
+            .clone()
+            .ok_or_else(|| Error::Other(format!("Input {index} is missing its redeem script")))?
+    } else {
+        prevout.script_pubkey.clone()
+    };
+    if script.is_p2tr() {
+        return Err(Error::Other(
+            "Anti-exfil signing does not support taproot inputs".to_string(),
+        ));
+    }
+    let (script_code, segwit_value) = if script.is_p2wpkh() {
+        let script_code = script.p2wpkh_script_code().ok_or(Error::InvalidPsbt)?;
+        (script_code, Some(prevout.value))
+    } else if script.is_p2wsh() {
+        let witness_script = input
+            .witness_script
+            .clone()
+            .ok_or_else(|| Error::Other(format!("Input {index} is missing its witness script")))?;
+        (witness_script, Some(prevout.value))
+    } else if input.non_witness_utxo.is_some() {
+        (script, None)
+    } else {
+        return Err(Error::Other(format!(
+            "Legacy input {index} needs its previous transaction"
+        )));
+    };
+
+    Ok(DescribedInput {
+        params: TxInputParams {
+            is_witness: segwit_value.is_some(),
+            input_tx: input
+                .non_witness_utxo
+                .as_ref()
+                .map(|tx| Bytes(serialize(tx))),
+            satoshi: segwit_value.map(Amount::to_sat),
+            script: Some(Bytes(script_code.to_bytes())),
+            ..Default::default()
+        },
+        script_code,
+        segwit_value,
+    })
+}
+
+/// Single-key outputs paying back to this device, so it can verify them as change
+fn change_outputs(psbt: &Psbt, master_fingerprint: Fingerprint) -> Vec<Option<ChangeOutput>> {
+    psbt.outputs
+        .iter()
+        .zip(&psbt.unsigned_tx.output)
+        .map(|(output, txout)| {
+            let mut origins = output.bip32_derivation.values();
+            let (fingerprint, path) = origins.next()?;
+            if origins.next().is_some() || *fingerprint != master_fingerprint {
+                return None;
+            }
+            let script_pubkey = &txout.script_pubkey;
+            let variant = if script_pubkey.is_p2wpkh() {
+                "wpkh(k)"
+            } else if script_pubkey.is_p2sh()
+                && output
+                    .redeem_script
+                    .as_ref()
+                    .is_some_and(|script| script.is_p2wpkh())
+            {
+                "sh(wpkh(k))"
+            } else if script_pubkey.is_p2pkh() {
+                "pkh(k)"
+            } else {
+                return None;
+            };
+            Some(ChangeOutput {
+                path: to_path_array(path),
+                variant,
+            })
+        })
+        .collect()
 }
 
 /// Determine address variant based on derivation path
@@ -7,6 +7,10 @@ pub enum Error {
     #[error("Serial port error: {0}")]
     SerialPort(#[from] tokio_serial::Error),
 
+    #[cfg(feature = "ble")]
+    #[error("Bluetooth error: {0}")]
+    Ble(#[from] btleplug::Error),
+
     #[error("CBOR encoding error: {0}")]
     CborEncode(#[from] serde_cbor::Error),
 
@@ -43,6 +47,14 @@ pub enum Error {
     #[error("Bitcoin error: {0}")]
     Bitcoin(#[from] bitcoin::address::ParseError),
 
+    #[error("Invalid extended public key: {0}")]
+    Bip32(#[from] bitcoin::bip32::Error),
+
+    #[error(
+        "Anti-exfil verification failed for input {input}: the signature does not commit to the host entropy"
+    )]
+    AntiExfilVerification { input: usize },
+
     #[error("Invalid PSBT")]
     InvalidPsbt,
 
@@ -1,8 +1,9 @@
 //! Bitcoin-focused Rust client for Blockstream Jade hardware wallet
 //!
 //! This crate provides a clean, Bitcoin-only interface for interacting with
-//! Jade hardware wallets. It handles serial communication, CBOR protocol,
-//! and provides simple methods for common Bitcoin operations.
+//! Jade hardware wallets. It handles serial communication (or Bluetooth LE
+//! with the `ble` feature), CBOR protocol, and provides simple methods for
+//! common Bitcoin operations.
 //!
 //! # Examples
 //!
@@ -20,23 +21,34 @@
 //! let address = jade.get_address("m/84'/0'/0'/0/0", Network::Bitcoin).await?;
 //! println!("Address: {}", address);
 //!
-//! // Get extended public key
+//! // Get extended public key with its key origin
 //! let xpub = jade.get_xpub("m/84'/0'/0'").await?;
-//! println!("xpub: {}", xpub);
+//! println!("xpub: {}", xpub.descriptor_key());
 //! # Ok(())
 //! # }
 //! ```
 
+mod anti_exfil;
+#[cfg(feature = "ble")]
+mod ble;
 mod client;
 mod error;
 mod messages;
 mod protocol;
 mod serial;
+mod transport;
 mod types;
 
 pub use client::JadeClient;
 pub use error::{Error, Result};
-pub use types::{Network, VersionInfo};
+#[cfg(feature = "ble")]
+pub use types::JadeBleDevice;
+#[cfg(feature = "pinserver")]
+pub use types::PinServerConfig;
+pub use types::{
+    EventCallback, FirmwareVersion, JadeDevice, JadeEvent, JadeOptions, MultisigDescriptor,
+    MultisigSigner, Network, PinServerDetails, VersionInfo, XpubInfo,
+};
 
 // Re-export commonly used types
 pub use bitcoin::psbt::Psbt;
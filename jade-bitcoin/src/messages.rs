@@ -1,18 +1,21 @@
 //! CBOR message structures for Jade protocol
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 
 /// Request message to Jade
+///
+/// Params default to a JSON value; typed params are used where the request
+/// needs CBOR byte strings, which `serde_json::Value` cannot represent.
 #[derive(Debug, Serialize)]
-pub struct Request {
+pub struct Request<P = Value> {
     pub id: String,
     pub method: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub params: Option<Value>,
+    pub params: Option<P>,
 }
 
-impl Request {
+impl<P> Request<P> {
     pub fn new(id: impl Into<String>, method: impl Into<String>) -> Self {
         Self {
             id: id.into(),
@@ -21,7 +24,7 @@ impl Request {
         }
     }
 
-    pub fn with_params(id: impl Into<String>, method: impl Into<String>, params: Value) -> Self {
+    pub fn with_params(id: impl Into<String>, method: impl Into<String>, params: P) -> Self {
         Self {
             id: id.into(),
             method: method.into(),
@@ -31,17 +34,20 @@ impl Request {
 }
 
 /// Response message from Jade
+///
+/// Results default to a JSON value; replies carrying byte strings (anti-exfil
+/// commitments and signatures) are read as `serde_cbor::Value`.
 #[derive(Debug, Deserialize)]
-pub struct Response {
+pub struct Response<R = Value> {
     pub id: String,
     #[serde(flatten)]
-    pub body: ResponseBody,
+    pub body: ResponseBody<R>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
-pub enum ResponseBody {
-    Result { result: Value },
+pub enum ResponseBody<R = Value> {
+    Result { result: R },
     Error { error: ErrorResponse },
 }
 
@@ -54,6 +60,94 @@ pub struct ErrorResponse {
     pub data: Option<Value>,
 }
 
+/// Byte string param, sent as a CBOR byte string rather than an array of integers
+#[derive(Debug, Clone)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+/// Params of a `sign_tx` request
+#[derive(Debug, Serialize)]
+pub struct SignTxParams<'a> {
+    pub network: &'a str,
+    /// Unsigned transaction, consensus encoded
+    pub txn: Bytes,
+    pub num_inputs: u32,
+    pub use_ae_signatures: bool,
+    /// One entry per output; `None` for outputs that are not change
+    pub change: Vec<Option<ChangeOutput>>,
+}
+
+/// Change output the device should verify and hide from the confirmation screen
+#[derive(Debug, Serialize)]
+pub struct ChangeOutput {
+    pub path: Vec<u32>,
+    pub variant: &'static str,
+}
+
+/// Params of a `tx_input` message following `sign_tx`
+#[derive(Debug, Default, Serialize)]
+pub struct TxInputParams {
+    pub is_witness: bool,
+    /// Previous transaction, required for legacy inputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_tx: Option<Bytes>,
+    /// Amount of a segwit input
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub satoshi: Option<u64>,
+    /// Script code to sign (script pubkey, redeem script or witness script)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<Bytes>,
+    /// Signing key path; inputs without one are not signed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sighash: Option<u32>,
+    /// Commitment to the host entropy, revealed only after the device commits to its nonce
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ae_host_commitment: Option<Bytes>,
+}
+
+/// Params of a `get_signature` message, revealing the host entropy
+#[derive(Debug, Serialize)]
+pub struct GetSignatureParams {
+    pub ae_host_entropy: Option<Bytes>,
+}
+
+/// Params of an `update_pinserver` request
+#[derive(Debug, Default, Serialize)]
+pub struct UpdatePinserverParams {
+    /// Go back to the default PIN server
+    pub reset_details: bool,
+    pub reset_certificate: bool,
+    #[serde(rename = "urlA", skip_serializing_if = "Option::is_none")]
+    pub url_a: Option<String>,
+    #[serde(rename = "urlB", skip_serializing_if = "Option::is_none")]
+    pub url_b: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<Bytes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+}
+
+/// Params of an `ota` request starting a firmware update
+#[derive(Debug, Serialize)]
+pub struct OtaParams {
+    /// Size of the uncompressed firmware
+    pub fwsize: usize,
+    /// Size of the compressed firmware that is uploaded
+    pub cmpsize: usize,
+    /// SHA-256 of the compressed firmware
+    pub cmphash: Bytes,
+    /// SHA-256 of the uncompressed firmware, checked after decompression
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fwhash: Option<Bytes>,
+}
+
 /// Methods supported by Jade
 #[allow(dead_code)]
 pub mod methods {
@@ -63,12 +157,20 @@ pub mod methods {
     pub const GET_XPUB: &str = "get_xpub";
     pub const GET_RECEIVE_ADDRESS: &str = "get_receive_address";
     pub const SIGN_PSBT: &str = "sign_psbt";
+    pub const SIGN_TX: &str = "sign_tx";
+    pub const TX_INPUT: &str = "tx_input";
     pub const SIGN_MESSAGE: &str = "sign_message";
     pub const GET_MASTER_BLINDING_KEY: &str = "get_master_blinding_key";
     pub const GET_SHARED_NONCE: &str = "get_shared_nonce";
     pub const GET_COMMITMENTS: &str = "get_commitments";
     pub const GET_SIGNATURE: &str = "get_signature";
     pub const HTTP_REQUEST: &str = "http_request";
+    pub const UPDATE_PINSERVER: &str = "update_pinserver";
+    pub const REGISTER_MULTISIG: &str = "register_multisig";
+    pub const GET_REGISTERED_MULTISIGS: &str = "get_registered_multisigs";
+    pub const OTA: &str = "ota";
+    pub const OTA_DATA: &str = "ota_data";
+    pub const OTA_COMPLETE: &str = "ota_complete";
 }
 
 /// Error codes from Jade
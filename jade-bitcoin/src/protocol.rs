@@ -1,24 +1,68 @@
 //! Jade protocol implementation
 
 use crate::error::{Error, Result};
-use crate::messages::{Request, ResponseBody, error_codes, methods};
-use crate::serial::SerialConnection;
-use crate::types::Network;
-use log::{debug, info};
+use crate::messages::{
+    Bytes, GetSignatureParams, OtaParams, Request, Response, ResponseBody, SignTxParams,
+    TxInputParams, UpdatePinserverParams, error_codes, methods,
+};
+use crate::transport::Connection;
+#[cfg(feature = "pinserver")]
+use crate::types::PinServerConfig;
+use crate::types::{EventCallback, JadeEvent, JadeOptions, MultisigDescriptor, Network};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use serde_json::{Value, json};
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Params of a `register_multisig` request
+#[derive(Debug, Serialize)]
+struct RegisterMultisigParams<'a> {
+    network: &'a str,
+    multisig_name: &'a str,
+    descriptor: &'a MultisigDescriptor,
+}
 
 /// Low-level protocol handler for Jade communication
 pub struct JadeProtocol {
-    connection: SerialConnection,
+    connection: Connection,
     message_counter: u32,
+    options: JadeOptions,
+    on_event: Option<EventCallback>,
+    #[cfg(feature = "pinserver")]
+    pinserver: PinServerConfig,
 }
 
 impl JadeProtocol {
     /// Create new protocol handler with connection
-    pub fn new(connection: SerialConnection) -> Self {
+    pub fn new(connection: Connection) -> Self {
         Self {
             connection,
             message_counter: 0,
+            options: JadeOptions::default(),
+            on_event: None,
+            #[cfg(feature = "pinserver")]
+            pinserver: PinServerConfig::default(),
+        }
+    }
+
+    #[cfg(feature = "pinserver")]
+    pub fn set_pinserver_config(&mut self, config: PinServerConfig) {
+        self.pinserver = config;
+    }
+
+    pub fn set_options(&mut self, options: JadeOptions) {
+        self.options = options;
+    }
+
+    pub fn set_event_callback(&mut self, callback: EventCallback) {
+        self.on_event = Some(callback);
+    }
+
+    fn emit(&self, event: &JadeEvent) {
+        if let Some(callback) = &self.on_event {
+            callback(event);
         }
     }
 
@@ -30,22 +74,69 @@ impl JadeProtocol {
 
     /// Send request and get response
     pub async fn call(&mut self, method: &str, params: Option<Value>) -> Result<Value> {
-        let id = self.next_id();
-        let request = if let Some(params) = params {
-            Request::with_params(id.clone(), method, params)
+        self.call_with(method, params).await
+    }
+
+    /// Send request with typed params and get response
+    pub async fn call_with<P: Serialize + Debug>(
+        &mut self,
+        method: &str,
+        params: Option<P>,
+    ) -> Result<Value> {
+        self.call_typed(method, params).await
+    }
+
+    /// Send request with typed params and decode the result as `R`
+    ///
+    /// Requests that need the user wait for the interaction timeout and are
+    /// announced through the event callback; read-only requests that time out
+    /// are retried.
+    async fn call_typed<P: Serialize + Debug, R: DeserializeOwned + Debug>(
+        &mut self,
+        method: &str,
+        params: Option<P>,
+    ) -> Result<R> {
+        let interaction = interaction(method);
+        // During signing the device may stop for the fee confirmation on any
+        // input message, after the transaction itself was announced
+        let waits_for_user =
+            interaction.is_some() || matches!(method, methods::TX_INPUT | methods::GET_SIGNATURE);
+        let (wait, attempts) = if waits_for_user {
+            (self.options.interaction_timeout, 1)
+        } else if is_read_only(method) {
+            (self.options.request_timeout, self.options.retries + 1)
         } else {
-            Request::new(id.clone(), method)
+            (self.options.request_timeout, 1)
         };
 
-        let response = self.connection.request(&request).await?;
-
-        // Verify response ID matches request
-        if response.id != id {
-            return Err(Error::InvalidResponse);
+        if let Some(event) = &interaction {
+            self.emit(event);
+        }
+        let mut attempt = 1;
+        let response = loop {
+            let id = self.next_id();
+            let request = match &params {
+                Some(params) => Request::with_params(id, method, params),
+                None => Request::new(id, method),
+            };
+            match self.exchange(&request, wait).await {
+                Err(Error::Timeout) if attempt < attempts => {
+                    debug!("{method} timed out, retrying");
+                    self.emit(&JadeEvent::Retrying {
+                        method: method.to_string(),
+                        attempt,
+                    });
+                    attempt += 1;
+                }
+                response => break response,
+            }
+        };
+        if interaction.is_some() {
+            self.emit(&JadeEvent::InteractionComplete);
         }
 
         // Handle response
-        match response.body {
+        match response?.body {
             ResponseBody::Result { result } => Ok(result),
             ResponseBody::Error { error } => {
                 // Handle specific error codes
@@ -63,6 +154,53 @@ impl JadeProtocol {
         }
     }
 
+    /// Send `request` and read its response, skipping late responses to
+    /// earlier attempts (message ids only grow)
+    ///
+    /// Each exchange is traced with its method, sizes and duration.
+    async fn exchange<P: Serialize + Debug, R: DeserializeOwned + Debug>(
+        &mut self,
+        request: &Request<P>,
+        wait: Duration,
+    ) -> Result<Response<R>> {
+        let started = Instant::now();
+        let request_bytes = self.connection.send_request(request).await?;
+        loop {
+            let (response, response_bytes) = match self.connection.receive_response(wait).await {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!(
+                        method = %request.method,
+                        id = %request.id,
+                        request_bytes,
+                        elapsed_ms = started.elapsed().as_millis() as u64,
+                        error = %e,
+                        "Jade request failed"
+                    );
+                    return Err(e);
+                }
+            };
+            if response.id == request.id {
+                debug!(
+                    method = %request.method,
+                    id = %request.id,
+                    request_bytes,
+                    response_bytes,
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    jade_error = matches!(response.body, ResponseBody::Error { .. }),
+                    "Jade request completed"
+                );
+                return Ok(response);
+            }
+            match (response.id.parse::<u32>(), request.id.parse::<u32>()) {
+                (Ok(stale), Ok(current)) if stale < current => {
+                    debug!("Discarding stale response with id {stale}");
+                }
+                _ => return Err(Error::InvalidResponse),
+            }
+        }
+    }
+
     /// Get device version information
     pub async fn get_version_info(&mut self) -> Result<Value> {
         self.call(methods::GET_VERSION_INFO, None).await
@@ -70,6 +208,13 @@ impl JadeProtocol {
 
     /// Authenticate user with network
     pub async fn auth_user(&mut self, network: Network) -> Result<()> {
+        self.emit(&JadeEvent::AwaitingPin);
+        let result = self.authenticate(network).await;
+        self.emit(&JadeEvent::InteractionComplete);
+        result
+    }
+
+    async fn authenticate(&mut self, network: Network) -> Result<()> {
         info!("Starting auth_user for network: {network:?}");
 
         let params = json!({
@@ -80,7 +225,9 @@ impl JadeProtocol {
         let id = self.next_id();
         let request = Request::with_params(id.clone(), methods::AUTH_USER, params);
         debug!("Sending auth_user request with id: {id}");
-        let response = self.connection.request(&request).await?;
+        let response: Response = self
+            .exchange(&request, self.options.interaction_timeout)
+            .await?;
 
         match response.body {
             ResponseBody::Result { result } => {
@@ -166,6 +313,57 @@ impl JadeProtocol {
             .ok_or(Error::InvalidResponse)
     }
 
+    /// Get address of a registered multisig wallet, one path per signer
+    pub async fn get_multisig_receive_address(
+        &mut self,
+        network: Network,
+        multisig_name: &str,
+        paths: &[Vec<u32>],
+    ) -> Result<String> {
+        let params = json!({
+            "network": network.as_jade_str(),
+            "multisig_name": multisig_name,
+            "paths": paths
+        });
+
+        let result = self
+            .call(methods::GET_RECEIVE_ADDRESS, Some(params))
+            .await?;
+
+        result
+            .as_str()
+            .map(String::from)
+            .ok_or(Error::InvalidResponse)
+    }
+
+    /// Register a multisig wallet under `multisig_name`
+    pub async fn register_multisig(
+        &mut self,
+        network: Network,
+        multisig_name: &str,
+        descriptor: &MultisigDescriptor,
+    ) -> Result<()> {
+        let params = RegisterMultisigParams {
+            network: network.as_jade_str(),
+            multisig_name,
+            descriptor,
+        };
+
+        let result = self
+            .call_with(methods::REGISTER_MULTISIG, Some(params))
+            .await?;
+
+        match result.as_bool() {
+            Some(true) => Ok(()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    /// Get the multisig wallets registered on the device, keyed by name
+    pub async fn get_registered_multisigs(&mut self) -> Result<Value> {
+        self.call(methods::GET_REGISTERED_MULTISIGS, None).await
+    }
+
     /// Sign a PSBT
     pub async fn sign_psbt(&mut self, network: Network, psbt_bytes: &[u8]) -> Result<Value> {
         // Encode PSBT as base64 for transmission
@@ -180,6 +378,88 @@ impl JadeProtocol {
         self.call(methods::SIGN_PSBT, Some(params)).await
     }
 
+    /// Sign a transaction with the anti-exfil protocol
+    ///
+    /// Every input is sent with the host's entropy commitment and answered with
+    /// the device's nonce commitment; only then is the entropy revealed, input by
+    /// input, in exchange for the signatures. Returns the (signer commitment,
+    /// signature) pair of each input, both empty for inputs the device did not sign.
+    pub async fn sign_tx_anti_exfil(
+        &mut self,
+        params: SignTxParams<'_>,
+        inputs: Vec<TxInputParams>,
+        host_entropy: Vec<Option<[u8; 32]>>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let result = self.call_with(methods::SIGN_TX, Some(params)).await?;
+        if result.as_bool() != Some(true) {
+            return Err(Error::InvalidResponse);
+        }
+
+        let mut commitments = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let reply = self.call_typed(methods::TX_INPUT, Some(input)).await?;
+            commitments.push(byte_string(reply)?);
+        }
+
+        let mut signatures = Vec::with_capacity(host_entropy.len());
+        for entropy in host_entropy {
+            let params = GetSignatureParams {
+                ae_host_entropy: entropy.map(|entropy| Bytes(entropy.to_vec())),
+            };
+            let reply = self
+                .call_typed(methods::GET_SIGNATURE, Some(params))
+                .await?;
+            signatures.push(byte_string(reply)?);
+        }
+
+        Ok(commitments.into_iter().zip(signatures).collect())
+    }
+
+    /// Point the device at another PIN server, or back to the default one
+    pub async fn update_pinserver(&mut self, params: UpdatePinserverParams) -> Result<()> {
+        let result = self
+            .call_with(methods::UPDATE_PINSERVER, Some(params))
+            .await?;
+
+        match result.as_bool() {
+            Some(true) => Ok(()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    /// Start a firmware update; the device asks the user to confirm it
+    pub async fn ota_start(&mut self, params: OtaParams) -> Result<()> {
+        let result = self.call_with(methods::OTA, Some(params)).await?;
+
+        match result.as_bool() {
+            Some(true) => Ok(()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    /// Upload the next chunk of compressed firmware
+    pub async fn ota_data(&mut self, chunk: &[u8]) -> Result<()> {
+        let result = self
+            .call_with(methods::OTA_DATA, Some(Bytes(chunk.to_vec())))
+            .await?;
+
+        match result.as_bool() {
+            Some(true) => Ok(()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    /// Finish a firmware update once every chunk was uploaded; the device
+    /// checks the hashes, then reboots into the new firmware
+    pub async fn ota_complete(&mut self) -> Result<()> {
+        let result = self.call(methods::OTA_COMPLETE, None).await?;
+
+        match result.as_bool() {
+            Some(true) => Ok(()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
     /// Sign a message
     pub async fn sign_message(
         &mut self,
@@ -208,14 +488,17 @@ impl JadeProtocol {
         initial_result: Value,
         auth_id: &str,
     ) -> Result<()> {
-        use reqwest::Client;
+        if self.pinserver.offline {
+            return Err(Error::Other(
+                "Jade asked for its PIN server in offline mode; only a SeedQR temporary signer unlocks without one".to_string(),
+            ));
+        }
 
         info!("Starting PIN server authentication");
-        let client = Client::new();
 
         // Process the initial HTTP request from the auth_user response
         if let Some(http_req) = initial_result.get("http_request") {
-            self.process_http_request(&client, http_req).await?;
+            self.process_http_request(http_req).await?;
         } else {
             return Err(Error::Other(
                 "Expected http_request in auth response".to_string(),
@@ -223,19 +506,18 @@ impl JadeProtocol {
         }
 
         // Continue processing any additional HTTP requests
-        self.handle_pinserver_auth_loop(&client, auth_id).await
+        self.handle_pinserver_auth_loop(auth_id).await
     }
 
     #[cfg(feature = "pinserver")]
-    async fn handle_pinserver_auth_loop(
-        &mut self,
-        client: &reqwest::Client,
-        auth_id: &str,
-    ) -> Result<()> {
+    async fn handle_pinserver_auth_loop(&mut self, auth_id: &str) -> Result<()> {
         loop {
             info!("Waiting for next message from Jade in PIN auth loop...");
             // Read next message from Jade
-            let response = self.connection.receive_response().await?;
+            let (response, _) = self
+                .connection
+                .receive_response::<Value>(self.options.interaction_timeout)
+                .await?;
 
             info!(
                 "Received response with id: {} (looking for: {})",
@@ -247,7 +529,7 @@ impl JadeProtocol {
                     // Check if this is another HTTP request
                     if let Some(http_req) = result.get("http_request") {
                         info!("Received another HTTP request from Jade");
-                        self.process_http_request(client, http_req).await?;
+                        self.process_http_request(http_req).await?;
                         continue;
                     }
 
@@ -281,11 +563,7 @@ impl JadeProtocol {
     }
 
     #[cfg(feature = "pinserver")]
-    async fn process_http_request(
-        &mut self,
-        client: &reqwest::Client,
-        http_req: &Value,
-    ) -> Result<()> {
+    async fn process_http_request(&mut self, http_req: &Value) -> Result<()> {
         // Extract the HTTP request parameters
         let params = http_req
             .get("params")
@@ -295,10 +573,9 @@ impl JadeProtocol {
             .as_array()
             .ok_or_else(|| Error::Other("Missing urls in http_request".to_string()))?;
 
-        let url = urls
-            .first()
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| Error::Other("No URL provided".to_string()))?;
+        let url = self.pinserver_url(urls)?;
+        let url = url.as_str();
+        let client = self.pinserver_client(params)?;
 
         let method = params["method"].as_str().unwrap_or("POST");
         let data = params.get("data");
@@ -356,3 +633,89 @@ impl JadeProtocol {
         Ok(())
     }
 }
+
+#[cfg(feature = "pinserver")]
+impl JadeProtocol {
+    /// URL to contact for a device request listing `urls`: clearnet first,
+    /// then onion. A configured server replaces the scheme, host and port.
+    fn pinserver_url(&self, urls: &[Value]) -> Result<String> {
+        let mut urls = urls
+            .iter()
+            .filter_map(Value::as_str)
+            .filter(|u| !u.is_empty());
+        let clearnet = urls.next();
+        let onion = urls.next();
+        let url = match (self.pinserver.prefer_onion, onion) {
+            (true, Some(onion)) => onion,
+            _ => clearnet.ok_or_else(|| Error::Other("No URL provided".to_string()))?,
+        };
+
+        let Some(base) = &self.pinserver.url else {
+            return Ok(url.to_string());
+        };
+        let requested = reqwest::Url::parse(url)
+            .map_err(|e| Error::Other(format!("Invalid PIN server URL {url}: {e}")))?;
+        let mut target = reqwest::Url::parse(base)
+            .map_err(|e| Error::Other(format!("Invalid PIN server URL {base}: {e}")))?;
+        target.set_path(requested.path());
+        Ok(target.to_string())
+    }
+
+    /// Client for a device request: the configured one, or one trusting only
+    /// the root certificates the device sent along
+    fn pinserver_client(&self, params: &Value) -> Result<reqwest::Client> {
+        if let Some(client) = &self.pinserver.http_client {
+            return Ok(client.clone());
+        }
+
+        let certificates: Vec<&str> = params
+            .get("root_certificates")
+            .and_then(Value::as_array)
+            .map(|certs| certs.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        if certificates.is_empty() {
+            return Ok(reqwest::Client::new());
+        }
+
+        let mut builder = reqwest::Client::builder().tls_built_in_root_certs(false);
+        for pem in certificates {
+            let certificate = reqwest::Certificate::from_pem(pem.as_bytes())
+                .map_err(|e| Error::Other(format!("Invalid PIN server certificate: {e}")))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        builder
+            .build()
+            .map_err(|e| Error::Other(format!("Failed to build HTTP client: {e}")))
+    }
+}
+
+/// What the user has to do on the device before it answers `method`
+fn interaction(method: &str) -> Option<JadeEvent> {
+    let action = match method {
+        methods::SIGN_PSBT | methods::SIGN_TX => "sign the transaction",
+        methods::SIGN_MESSAGE => "sign the message",
+        methods::GET_RECEIVE_ADDRESS => "verify the address",
+        methods::REGISTER_MULTISIG => "register the multisig wallet",
+        methods::UPDATE_PINSERVER => "change the PIN server",
+        methods::OTA => "accept the firmware update",
+        _ => return None,
+    };
+    Some(JadeEvent::AwaitingConfirmation { action })
+}
+
+/// Requests that can be sent again without side effects
+fn is_read_only(method: &str) -> bool {
+    matches!(
+        method,
+        methods::GET_VERSION_INFO | methods::GET_XPUB | methods::GET_REGISTERED_MULTISIGS
+    )
+}
+
+/// Bytes of a byte string reply; `null` and empty replies stand for "not signed"
+fn byte_string(value: serde_cbor::Value) -> Result<Vec<u8>> {
+    match value {
+        serde_cbor::Value::Bytes(bytes) => Ok(bytes),
+        serde_cbor::Value::Null => Ok(Vec::new()),
+        _ => Err(Error::InvalidResponse),
+    }
+}
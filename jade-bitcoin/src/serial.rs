@@ -1,12 +1,12 @@
 //! Serial port communication for Jade (async implementation)
 
 use crate::error::{Error, Result};
-use crate::messages::{Request, Response};
-use crate::types::{JADE_USB_IDS, SERIAL_BAUD_RATE, SERIAL_TIMEOUT_MS};
-use log::debug;
+use crate::transport::take_frame;
+use crate::types::{JADE_USB_IDS, JadeDevice, SERIAL_BAUD_RATE};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time::{Duration, sleep, timeout};
 use tokio_serial::{SerialPortBuilderExt, SerialStream};
+use tracing::{debug, warn};
 
 /// Async serial connection to Jade device
 pub struct SerialConnection {
@@ -16,24 +16,33 @@ pub struct SerialConnection {
 
 impl SerialConnection {
     /// Connect to Jade on any available port
+    ///
+    /// Candidates are tried in port name order, so the same device is picked
+    /// every time. Use [`SerialConnection::connect_to`] to choose one explicitly.
     pub async fn connect() -> Result<Self> {
-        let ports = Self::find_jade_ports();
-        if ports.is_empty() {
+        let devices = Self::list_devices();
+        if devices.is_empty() {
             debug!("No Jade devices found");
             return Err(Error::DeviceNotFound);
         }
 
-        debug!("Found {} potential Jade device(s)", ports.len());
+        debug!("Found {} potential Jade device(s)", devices.len());
+        if devices.len() > 1 {
+            warn!(
+                "Found {count} candidate Jade ports, trying them in order; select one explicitly to avoid ambiguity",
+                count = devices.len()
+            );
+        }
         // Try each port until one works
-        for port_info in ports {
-            debug!("Attempting to connect to Jade on {}", port_info.port_name);
-            match Self::connect_path(&port_info.port_name).await {
+        for device in devices {
+            debug!("Attempting to connect to Jade on {}", device.port_name);
+            match Self::connect_to(&device.port_name).await {
                 Ok(conn) => {
-                    debug!("Successfully connected to Jade on {}", port_info.port_name);
+                    debug!("Successfully connected to Jade on {}", device.port_name);
                     return Ok(conn);
                 }
                 Err(e) => {
-                    debug!("Failed to connect to {}: {}", port_info.port_name, e);
+                    debug!("Failed to connect to {}: {}", device.port_name, e);
                 }
             }
         }
@@ -41,11 +50,11 @@ impl SerialConnection {
         Err(Error::DeviceNotFound)
     }
 
-    /// Connect to Jade on specific port
-    pub async fn connect_path(path: &str) -> Result<Self> {
-        debug!("Opening serial port: {path}");
+    /// Connect to Jade on a specific serial port, whatever its USB ids
+    pub async fn connect_to(port: &str) -> Result<Self> {
+        debug!("Opening serial port: {port}");
 
-        let port = tokio_serial::new(path, SERIAL_BAUD_RATE)
+        let port = tokio_serial::new(port, SERIAL_BAUD_RATE)
             .data_bits(tokio_serial::DataBits::Eight)
             .stop_bits(tokio_serial::StopBits::One)
             .parity(tokio_serial::Parity::None)
@@ -61,146 +70,72 @@ impl SerialConnection {
         })
     }
 
-    /// Find all potential Jade serial ports
-    pub fn find_jade_ports() -> Vec<tokio_serial::SerialPortInfo> {
+    /// List serial ports whose USB bridge matches one used by Jade, sorted by port name
+    pub fn list_devices() -> Vec<JadeDevice> {
         let ports = tokio_serial::available_ports().unwrap_or_default();
 
-        ports
+        let mut devices: Vec<JadeDevice> = ports
             .into_iter()
-            .filter(|port| {
-                if let tokio_serial::SerialPortType::UsbPort(info) = &port.port_type {
-                    JADE_USB_IDS
+            .filter_map(|port| match port.port_type {
+                tokio_serial::SerialPortType::UsbPort(info)
+                    if JADE_USB_IDS
                         .iter()
-                        .any(|(vid, pid)| info.vid == *vid && info.pid == *pid)
-                } else {
-                    false
+                        .any(|(vid, pid)| info.vid == *vid && info.pid == *pid) =>
+                {
+                    Some(JadeDevice {
+                        port_name: port.port_name,
+                        vid: info.vid,
+                        pid: info.pid,
+                        serial_number: info.serial_number,
+                        manufacturer: info.manufacturer,
+                        product: info.product,
+                    })
                 }
+                _ => None,
             })
-            .collect()
+            .collect();
+        devices.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+        devices
     }
 
-    /// List all available Jade devices
-    pub fn list_devices() -> Vec<String> {
-        Self::find_jade_ports()
-            .into_iter()
-            .map(|p| p.port_name)
-            .collect()
-    }
-
-    /// Send a request and receive response
-    pub async fn request(&mut self, request: &Request) -> Result<Response> {
-        self.send_request(request).await?;
-        self.receive_response().await
-    }
-
-    /// Send a CBOR-encoded request
-    pub async fn send_request(&mut self, request: &Request) -> Result<()> {
-        let cbor = serde_cbor::to_vec(request)?;
-        debug!("Sending request: {request:?}");
-        debug!("CBOR hex: {}", hex::encode(&cbor));
-
-        self.port.write_all(&cbor).await?;
+    /// Write an encoded message
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.port.write_all(bytes).await?;
         self.port.flush().await?;
-
         Ok(())
     }
 
-    /// Receive and decode a CBOR response
-    pub async fn receive_response(&mut self) -> Result<Response> {
-        // Read CBOR message
-        // Jade sends complete CBOR messages, so we need to read until we have a complete one
-
-        debug!("Starting to receive response from Jade...");
-        self.read_buffer.clear();
+    /// Read the bytes of the next complete CBOR message, failing with
+    /// [`Error::Timeout`] when no data arrives for `wait`
+    pub async fn read_frame(&mut self, wait: Duration) -> Result<Vec<u8>> {
         let mut temp_buffer = [0u8; 4096];
         let mut consecutive_empty_reads = 0;
 
-        // Read data until we have a complete CBOR message
         loop {
-            // Use timeout for read operations
-            let read_result = timeout(
-                Duration::from_millis(SERIAL_TIMEOUT_MS),
-                self.port.read(&mut temp_buffer),
-            )
-            .await;
+            if let Some(frame) = take_frame(&mut self.read_buffer)? {
+                return Ok(frame);
+            }
 
-            match read_result {
+            match timeout(wait, self.port.read(&mut temp_buffer)).await {
                 Ok(Ok(0)) => {
                     // No data available right now
                     consecutive_empty_reads += 1;
-
-                    // If we've had several empty reads and have data, try to parse it
-                    if consecutive_empty_reads > 3 && !self.read_buffer.is_empty() {
-                        match serde_cbor::from_slice::<Response>(&self.read_buffer) {
-                            Ok(response) => return Ok(response),
-                            Err(e) => {
-                                debug!(
-                                    "Failed to decode {} bytes after empty reads: {}",
-                                    self.read_buffer.len(),
-                                    e
-                                );
-                                // Give it more time
-                                sleep(Duration::from_millis(100)).await;
-                            }
-                        }
-                    }
-
                     if consecutive_empty_reads > 10 {
                         return Err(Error::Timeout);
                     }
-
-                    // Small delay before retry
                     sleep(Duration::from_millis(10)).await;
                 }
                 Ok(Ok(n)) => {
-                    // Got data
                     consecutive_empty_reads = 0;
                     self.read_buffer.extend_from_slice(&temp_buffer[..n]);
-
-                    // Try to decode CBOR
-                    match serde_cbor::from_slice::<Response>(&self.read_buffer) {
-                        Ok(response) => {
-                            debug!("Received response: {response:?}");
-                            debug!("Response hex: {}", hex::encode(&self.read_buffer));
-                            return Ok(response);
-                        }
-                        Err(e) => {
-                            // Check if this looks like a complete but invalid message
-                            if self.read_buffer.len() > 1000 {
-                                debug!(
-                                    "Failed to decode CBOR after {} bytes: {}",
-                                    self.read_buffer.len(),
-                                    e
-                                );
-                                debug!(
-                                    "Raw hex (first 200 bytes): {}",
-                                    hex::encode(
-                                        &self.read_buffer[..200.min(self.read_buffer.len())]
-                                    )
-                                );
-                                return Err(Error::InvalidResponse);
-                            }
-                            // Not enough data yet, continue reading
-                            continue;
-                        }
-                    }
-                }
-                Ok(Err(e)) => {
-                    return Err(Error::Io(e));
                 }
+                Ok(Err(e)) => return Err(Error::Io(e)),
                 Err(_) => {
-                    // Timeout
                     if !self.read_buffer.is_empty() {
-                        match serde_cbor::from_slice::<Response>(&self.read_buffer) {
-                            Ok(response) => return Ok(response),
-                            Err(decode_err) => {
-                                debug!(
-                                    "Timeout with {} bytes, decode error: {}",
-                                    self.read_buffer.len(),
-                                    decode_err
-                                );
-                            }
-                        }
+                        debug!(
+                            "Timeout with {len} bytes of an incomplete message",
+                            len = self.read_buffer.len()
+                        );
                     }
                     return Err(Error::Timeout);
                 }
@@ -0,0 +1,115 @@
+//! Transports carrying CBOR messages between the host and Jade
+//!
+//! Every message sent and received is traced under the `jade_bitcoin::cbor`
+//! target with its size and raw hex, so `RUST_LOG=jade_bitcoin=trace` dumps
+//! the wire traffic.
+
+#[cfg(feature = "ble")]
+use crate::ble::BleConnection;
+use crate::error::{Error, Result};
+use crate::messages::{Request, Response};
+use crate::serial::SerialConnection;
+use serde::Serialize;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use std::fmt::Debug;
+use std::time::Duration;
+use tracing::{debug, trace};
+
+/// Target of the raw CBOR dumps
+const CBOR_TARGET: &str = "jade_bitcoin::cbor";
+
+/// Connection to a Jade over USB serial or Bluetooth LE
+pub enum Connection {
+    Serial(SerialConnection),
+    #[cfg(feature = "ble")]
+    Ble(BleConnection),
+}
+
+impl From<SerialConnection> for Connection {
+    fn from(connection: SerialConnection) -> Self {
+        Self::Serial(connection)
+    }
+}
+
+#[cfg(feature = "ble")]
+impl From<BleConnection> for Connection {
+    fn from(connection: BleConnection) -> Self {
+        Self::Ble(connection)
+    }
+}
+
+impl Connection {
+    /// Send a CBOR-encoded request, returning its size in bytes
+    pub async fn send_request<P: Serialize + Debug>(
+        &mut self,
+        request: &Request<P>,
+    ) -> Result<usize> {
+        let cbor = serde_cbor::to_vec(request)?;
+        trace!(
+            target: CBOR_TARGET,
+            id = %request.id,
+            method = %request.method,
+            bytes = cbor.len(),
+            hex = %hex::encode(&cbor),
+            "send"
+        );
+
+        match self {
+            Self::Serial(connection) => connection.write(&cbor).await?,
+            #[cfg(feature = "ble")]
+            Self::Ble(connection) => connection.write(&cbor).await?,
+        }
+        Ok(cbor.len())
+    }
+
+    /// Receive and decode a CBOR response with its size in bytes, failing with
+    /// [`Error::Timeout`] when nothing arrives for `wait`
+    pub async fn receive_response<R: DeserializeOwned + Debug>(
+        &mut self,
+        wait: Duration,
+    ) -> Result<(Response<R>, usize)> {
+        let frame = match self {
+            Self::Serial(connection) => connection.read_frame(wait).await?,
+            #[cfg(feature = "ble")]
+            Self::Ble(connection) => connection.read_frame(wait).await?,
+        };
+        trace!(
+            target: CBOR_TARGET,
+            bytes = frame.len(),
+            hex = %hex::encode(&frame),
+            "receive"
+        );
+
+        let response = serde_cbor::from_slice(&frame).map_err(|e| {
+            debug!("Unexpected response shape: {e}");
+            Error::InvalidResponse
+        })?;
+        Ok((response, frame.len()))
+    }
+}
+
+/// Remove and return the first complete CBOR message in `buffer`, or `None`
+/// while it is still incomplete. A read may end one message and start the
+/// next, so only the bytes of the first one are consumed.
+pub(crate) fn take_frame(buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+    let mut items = serde_cbor::Deserializer::from_slice(buffer).into_iter::<IgnoredAny>();
+    match items.next() {
+        Some(Ok(_)) => {
+            let len = items.byte_offset();
+            Ok(Some(buffer.drain(..len).collect()))
+        }
+        Some(Err(e)) if e.is_eof() => Ok(None),
+        Some(Err(e)) => {
+            debug!(
+                "Discarding {len} bytes that are not CBOR: {e}",
+                len = buffer.len()
+            );
+            buffer.clear();
+            Err(Error::InvalidResponse)
+        }
+        None => Ok(None),
+    }
+}
@@ -1,6 +1,12 @@
 //! Common types used throughout jade-bitcoin
 
-use serde::{Deserialize, Serialize};
+use crate::error::Error;
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Bitcoin network type
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -67,6 +73,150 @@ pub struct VersionInfo {
     pub jade_has_pin: bool,
 }
 
+impl VersionInfo {
+    /// Parsed `JADE_VERSION`
+    pub fn version(&self) -> crate::Result<FirmwareVersion> {
+        self.jade_version.parse()
+    }
+
+    /// Whether the device only boots firmware signed by Blockstream
+    pub fn has_secure_boot(&self) -> bool {
+        self.jade_features.split_whitespace().any(|f| f == "SB")
+    }
+
+    /// Whether the firmware is built with Bluetooth ("BLE" rather than "NORADIO")
+    pub fn has_ble(&self) -> bool {
+        self.jade_config.eq_ignore_ascii_case("BLE")
+    }
+
+    /// Whether the device has a wallet that must be unlocked with its PIN
+    pub fn is_locked(&self) -> bool {
+        self.jade_state == "LOCKED"
+    }
+
+    /// Largest firmware chunk the device accepts per `ota_data` message
+    pub fn ota_chunk_size(&self) -> usize {
+        self.jade_ota_max_chunk
+            .map_or(OTA_DEFAULT_CHUNK, |size| size as usize)
+    }
+}
+
+/// Firmware version, e.g. "1.0.31"; pre-release suffixes ("-beta2") are ignored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FirmwareVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl FromStr for FirmwareVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        let release = s.trim().split(['-', '+']).next().unwrap_or_default();
+        let parts: Vec<u32> = release
+            .split('.')
+            .map(str::parse)
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| Error::Other(format!("Invalid firmware version: {s}")))?;
+        match parts[..] {
+            [major, minor, patch] => Ok(Self {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(Error::Other(format!("Invalid firmware version: {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Signer of a multisig wallet registered on the device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MultisigSigner {
+    /// Master key fingerprint, sent as a CBOR byte string
+    #[serde(serialize_with = "serialize_fingerprint")]
+    pub fingerprint: [u8; 4],
+    /// Origin path from the master key to `xpub`
+    pub derivation: Vec<u32>,
+    pub xpub: String,
+    /// Fixed path below `xpub` shared by every address (usually empty)
+    pub path: Vec<u32>,
+}
+
+/// Multisig wallet descriptor in the shape Jade's `register_multisig` expects
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MultisigDescriptor {
+    /// Script variant, e.g. `wsh(multi(k))`
+    pub variant: String,
+    /// Whether keys are sorted (`sortedmulti`)
+    pub sorted: bool,
+    pub threshold: u32,
+    pub signers: Vec<MultisigSigner>,
+}
+
+fn serialize_fingerprint<S: Serializer>(
+    fingerprint: &[u8; 4],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(fingerprint)
+}
+
+/// Extended public key together with its key origin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XpubInfo {
+    pub xpub: Xpub,
+    /// Fingerprint of the device's master key
+    pub master_fingerprint: Fingerprint,
+    /// Number of derivation steps from the master key
+    pub depth: u8,
+    /// Full path from the master key to `xpub`
+    pub origin: DerivationPath,
+}
+
+impl XpubInfo {
+    /// Key with its origin as used in output descriptors, e.g. `[73c5da0a/84'/0'/0']xpub...`
+    pub fn descriptor_key(&self) -> String {
+        if self.origin.is_master() {
+            format!(
+                "[{fingerprint}]{xpub}",
+                fingerprint = self.master_fingerprint,
+                xpub = self.xpub
+            )
+        } else {
+            format!(
+                "[{fingerprint}/{origin}]{xpub}",
+                fingerprint = self.master_fingerprint,
+                origin = self.origin,
+                xpub = self.xpub
+            )
+        }
+    }
+}
+
+/// Displays the bare xpub
+impl fmt::Display for XpubInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.xpub, f)
+    }
+}
+
+/// Serial port that may have a Jade attached, identified by its USB bridge chip
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JadeDevice {
+    pub port_name: String,
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
 /// Device identifiers for auto-detection
 pub const JADE_USB_IDS: &[(u16, u16)] = &[
     (0x10c4, 0xea60), // CP210x UART Bridge
@@ -75,5 +225,88 @@ pub const JADE_USB_IDS: &[(u16, u16)] = &[
 ];
 
 /// Default serial port settings
+/// A Jade advertising over Bluetooth LE
+#[cfg(feature = "ble")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JadeBleDevice {
+    /// Advertised name, e.g. "Jade 1A2B3C"
+    pub name: String,
+    /// Bluetooth address; platform-specific identifier on macOS
+    pub address: String,
+}
+
 pub const SERIAL_BAUD_RATE: u32 = 115200;
 pub const SERIAL_TIMEOUT_MS: u64 = 120000; // 120 seconds for PIN server auth
+/// Default wait for requests that need no user interaction
+pub const REQUEST_TIMEOUT_MS: u64 = 30000;
+/// OTA chunk size for firmware that does not report `JADE_OTA_MAX_CHUNK`
+pub const OTA_DEFAULT_CHUNK: usize = 4096;
+/// How long to listen for Bluetooth advertisements
+#[cfg(feature = "ble")]
+pub const BLE_SCAN_TIMEOUT_MS: u64 = 5000;
+
+/// How the host relays the PIN server requests of the unlock flow
+#[cfg(feature = "pinserver")]
+#[derive(Debug, Clone, Default)]
+pub struct PinServerConfig {
+    /// Send requests to this server (scheme, host and port) instead of the
+    /// URL the device asks for, keeping the request path
+    pub url: Option<String>,
+    /// Use the device's onion URL when it has one, e.g. behind a Tor proxy
+    pub prefer_onion: bool,
+    /// Never contact a PIN server; only a device that unlocks without one,
+    /// such as a SeedQR temporary signer, can be used
+    pub offline: bool,
+    /// Client for the requests, e.g. with a proxy or a pinned certificate.
+    /// Without one, root certificates sent by the device are the only ones trusted.
+    pub http_client: Option<reqwest::Client>,
+}
+
+/// PIN server the device itself uses, replacing Blockstream's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinServerDetails {
+    pub url: String,
+    /// Onion URL of the same server
+    pub onion_url: Option<String>,
+    /// Public key of the PIN server, compressed
+    pub pubkey: [u8; 33],
+    /// PEM root certificate the host should trust for `url`
+    pub certificate: Option<String>,
+}
+
+/// Timeouts and retries for device requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JadeOptions {
+    /// How long to wait for an answer that needs no user interaction
+    pub request_timeout: Duration,
+    /// How long to wait while the user confirms on the device or enters the PIN
+    pub interaction_timeout: Duration,
+    /// Extra attempts for read-only requests (version, xpubs) that time out
+    pub retries: u32,
+}
+
+impl Default for JadeOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_millis(REQUEST_TIMEOUT_MS),
+            interaction_timeout: Duration::from_millis(SERIAL_TIMEOUT_MS),
+            retries: 1,
+        }
+    }
+}
+
+/// Progress of a request, for telling the user what the device is waiting for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JadeEvent {
+    /// Waiting for the PIN on the device (a no-op when already unlocked)
+    AwaitingPin,
+    /// Waiting for the user to review and confirm `action` on the device
+    AwaitingConfirmation { action: &'static str },
+    /// The device answered a request that needed user interaction
+    InteractionComplete,
+    /// `method` timed out and is being sent again
+    Retrying { method: String, attempt: u32 },
+}
+
+/// Receiver of [`JadeEvent`]s
+pub type EventCallback = Arc<dyn Fn(&JadeEvent) + Send + Sync>;
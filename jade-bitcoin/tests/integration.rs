@@ -79,7 +79,12 @@ mod integration_tests {
             .expect("Failed to get xpub");
 
         // Testnet xpubs start with "tpub"
-        assert!(xpub.starts_with("tpub") || xpub.starts_with("vpub"));
+        assert!(xpub.to_string().starts_with("tpub"));
+        assert_eq!(xpub.depth, 3);
+        assert!(
+            xpub.descriptor_key()
+                .starts_with(&format!("[{}/84'/1'/0']", xpub.master_fingerprint))
+        );
         println!("xpub: {xpub}");
 
         jade.logout().await.expect("Failed to logout");